@@ -50,6 +50,9 @@ pub mod watchd {
 pub mod routed {
     include!("src/routed/opts.rs");
 }
+pub mod gatewayd {
+    include!("src/gatewayd/opts.rs");
+}
 
 fn main() -> Result<(), configure_me_codegen::Error> {
     let outdir = "./shell";
@@ -61,6 +64,7 @@ fn main() -> Result<(), configure_me_codegen::Error> {
         watchd::Opts::into_app(),
         routed::Opts::into_app(),
         signd::Opts::into_app(),
+        gatewayd::Opts::into_app(),
         cli::Opts::into_app(),
     ]
     .iter_mut()
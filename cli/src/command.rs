@@ -13,13 +13,34 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fs, thread};
 
-use internet2::{NodeAddr, RemoteSocketAddr, ToNodeAddr, ToRemoteNodeAddr};
+use bitcoin::{secp256k1, Txid};
+use internet2::{NodeAddr, RemoteSocketAddr, ToNodeAddr};
+use lnp::channel::bolt::Lifecycle;
 use lnp::p2p::legacy::{ChannelId, LNP2P_LEGACY_PORT};
-use lnp_rpc::{self, Client, CreateChannel, Error, PayInvoice, RpcMsg, ServiceId};
+use amplify::Wrapper;
+use lnp_rpc::{
+    self, BanInfo, BanPeer, ChannelEvent, ChannelFilter, ChannelList, ChannelSummary, Client,
+    CloseChannel, ClosingFeeRange, ConnectPeer, CreateChannel, DisconnectPeer, Error, ErrorCode,
+    EventCategory, EventSubscriber, FeePolicy, FeePolicyList, FundingPreview, NodeEvent,
+    Pagination, PayInvoice, PeerFilter, PeerInfo, PeerList, PendingPsbt, PendingTx, PolicyScope,
+    ProvideFunding, RpcError, RpcMsg, ServiceId, SetFeePolicy, SetFees, TxDepth, VerifyMessage,
+    Withdraw,
+};
 use microservices::shell::Exec;
 
-use crate::opts::Command;
+use crate::opts::{
+    BanCommand, ChainCommand, ChannelCommand, Command, GraphCommand, PeerCommand, WaitCondition,
+    WalletCommand,
+};
+
+/// Interval between the requests for the transaction status made by `wait tx-confirmed`
+const TX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Period after which `wait` re-checks the awaited condition if no relevant event has happened
+const WAIT_RECHECK_PERIOD: Duration = Duration::from_secs(60);
 
 impl Exec for Command {
     type Client = Client;
@@ -42,10 +63,86 @@ impl Exec for Command {
                 } else {
                     runtime.request(ServiceId::LnpBroker, RpcMsg::GetInfo)?;
                 }
-                match runtime.response()? {
-                    RpcMsg::NodeInfo(info) => println!("{}", info),
-                    RpcMsg::PeerInfo(info) => println!("{}", info),
-                    RpcMsg::ChannelInfo(info) => println!("{}", info),
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::NodeInfo(_)
+                    | reply @ RpcMsg::PeerInfo(_)
+                    | reply @ RpcMsg::ChannelInfo(_) => runtime.print_reply(&reply)?,
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::ChainStatus => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::GetChainStatus)?;
+                runtime.report_response()?;
+            }
+
+            Command::Chain { command: ChainCommand::Pending } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListPendingTxs)?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::PendingTxs(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::PendingTxs(pending) => print_pending_txs(pending.as_inner()),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Peers { node, since, until, listener, offset, limit, reset } => {
+                let filter = PeerFilter { remote_node: node, since, until, listener };
+                let page = Pagination { offset, limit };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListPeers(filter, page, reset))?;
+                let mut peers = match runtime.report_failure()? {
+                    RpcMsg::PeerList(peers) => peers,
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                };
+                peers.reconnecting.sort_by_key(|peer| peer.next_attempt);
+                match node {
+                    Some(node) => {
+                        let status =
+                            match peers.reconnecting.iter().find(|peer| peer.node_id == node) {
+                                Some(_) => "; the node is reconnecting it",
+                                None => "",
+                            };
+                        // The listing is already filtered by the node id
+                        let peer = peers.connected.into_iter().next().ok_or_else(|| {
+                            Error::Other(format!("Peer {} is not connected{}", node, status))
+                        })?;
+                        runtime.print_reply(&RpcMsg::PeerInfo(peer))?;
+                    }
+                    None if runtime.json_output() => runtime.print_reply(&RpcMsg::PeerList(peers))?,
+                    None => print_peers(&peers),
+                }
+            }
+
+            Command::Peer { command: PeerCommand::Pin { node_id } } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::PinPeer(node_id))?;
+                runtime.report_response()?;
+            }
+
+            Command::Peer { command: PeerCommand::Unpin { node_id } } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::UnpinPeer(node_id))?;
+                runtime.report_response()?;
+            }
+
+            Command::Ban { command: BanCommand::List } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListBans)?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::Bans(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::Bans(bans) => print_bans(bans.as_inner()),
                     _ => {
                         return Err(Error::Other(
                             "Server returned unrecognizable response".to_string(),
@@ -54,36 +151,178 @@ impl Exec for Command {
                 }
             }
 
-            Command::Peers => {
-                runtime.request(ServiceId::LnpBroker, RpcMsg::ListPeers)?;
+            Command::Ban { command: BanCommand::Add { node_id, duration } } => {
+                let ban_peer = BanPeer { node_id, duration };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::BanPeer(ban_peer))?;
                 runtime.report_response()?;
             }
 
-            Command::Channels => {
-                runtime.request(ServiceId::LnpBroker, RpcMsg::ListChannels)?;
+            Command::Ban { command: BanCommand::Remove { node_id } } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::UnbanPeer(node_id))?;
                 runtime.report_response()?;
             }
 
+            Command::Wallet { command: WalletCommand::Pending } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListPendingPsbts)?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::PendingPsbts(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::PendingPsbts(pending) => print_pending_psbts(pending.as_inner()),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Wallet { command: WalletCommand::SubmitPsbt { file } } => {
+                let data = fs::read(&file).map_err(|err| Error::Other(err.to_string()))?;
+                runtime.request(ServiceId::LnpBroker, RpcMsg::SubmitPsbt(data))?;
+                runtime.report_response()?;
+            }
+
+            Command::Channels { peer, stage, offset, limit } => {
+                let filter = ChannelFilter { remote_node: peer, lifecycle: stage };
+                let page = Pagination { offset, limit };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListChannels(filter, page))?;
+                let channels = match runtime.report_failure()? {
+                    RpcMsg::ChannelList(channels) => channels,
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                };
+                if runtime.json_output() {
+                    runtime.print_reply(&RpcMsg::ChannelList(channels))?;
+                } else {
+                    print_channels(&channels);
+                }
+            }
+
             Command::Funds => {
                 runtime.request(ServiceId::LnpBroker, RpcMsg::ListFunds)?;
                 runtime.report_response()?;
             }
 
+            Command::Events { events_socket, filter } => {
+                let mut subscriber = EventSubscriber::with(&events_socket, &filter)?;
+                loop {
+                    let event = subscriber.recv()?;
+                    if runtime.json_output() {
+                        let json = serde_json::to_string(&event)
+                            .map_err(|err| Error::Other(err.to_string()))?;
+                        println!("{}", json);
+                    } else {
+                        println!("{}", event);
+                    }
+                }
+            }
+
+            Command::Wait { events_socket, timeout, condition } => {
+                let deadline = timeout.map(|timeout| Instant::now() + Duration::from_secs(timeout));
+                let reply = match condition {
+                    WaitCondition::ChannelActive { channel } => RpcMsg::ChannelSummary(
+                        wait_channel_active(runtime, &events_socket, channel, deadline)?,
+                    ),
+                    WaitCondition::PeerConnected { node_id } => RpcMsg::PeerInfo(
+                        wait_peer_connected(runtime, &events_socket, node_id, deadline)?,
+                    ),
+                    WaitCondition::TxConfirmed { txid, depth } => {
+                        RpcMsg::TxDepth(wait_tx_confirmed(runtime, txid, depth, deadline)?)
+                    }
+                };
+                runtime.print_reply(&reply)?;
+            }
+
+            Command::Address { address_type } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::GetNewAddress(address_type))?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::NewAddress(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::NewAddress(new_address) => {
+                        println!("{}", new_address.address);
+                        if let Some(warning) = new_address.warning {
+                            eprintln!("Warning: {}", warning);
+                        }
+                    }
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Withdraw { address, amount, all: _, fee_rate, dry_run, confirm } => {
+                runtime.request(
+                    ServiceId::LnpBroker,
+                    RpcMsg::Withdraw(Withdraw {
+                        address,
+                        amount_sat: amount,
+                        // 1 vbyte is 4 weight units
+                        feerate_per_kw: fee_rate.map(|sat_per_vbyte| sat_per_vbyte * 250),
+                        dry_run,
+                        confirmation: confirm,
+                    }),
+                )?;
+                // Signing with hardware wallet is reported before the withdrawal completes
+                loop {
+                    match runtime.report_failure()? {
+                        progress @ RpcMsg::Progress(_) if runtime.json_output() => {
+                            runtime.print_reply(&progress)?
+                        }
+                        RpcMsg::Progress(info) => println!("{}", info),
+                        reply @ RpcMsg::Withdrawal(_) => break runtime.print_reply(&reply)?,
+                        _ => {
+                            return Err(Error::Other(
+                                "Server returned unrecognizable response".to_string(),
+                            ))
+                        }
+                    }
+                }
+            }
+
+            Command::BakeToken { permissions } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::BakeToken(permissions))?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::Token(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::Token(token) => println!("{}", token),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
             Command::Listen { ip_addr, port, overlay } => {
                 let socket = RemoteSocketAddr::with_ip_addr(overlay, ip_addr, port);
                 runtime.request(ServiceId::LnpBroker, RpcMsg::Listen(socket))?;
                 runtime.report_progress()?;
             }
 
-            Command::Connect { peer: node_locator } => {
-                let peer = node_locator
-                    .to_remote_node_addr(LNP2P_LEGACY_PORT)
-                    .expect("Provided node address is invalid");
-
-                runtime.request(ServiceId::LnpBroker, RpcMsg::ConnectPeer(peer))?;
+            Command::Connect { peer, timeout } => {
+                let connect_peer = ConnectPeer {
+                    node_id: peer.node_id,
+                    remote_addr: peer.remote_socket(),
+                    timeout: Some(timeout),
+                };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ConnectPeer(connect_peer))?;
                 runtime.report_progress()?;
             }
 
+            Command::Disconnect { node_id, permanent } => {
+                let disconnect_peer = DisconnectPeer { node_id, permanent };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::DisconnectPeer(disconnect_peer))?;
+                runtime.report_response()?;
+            }
+
             Command::Ping { peer } => {
                 let node_addr =
                     peer.to_node_addr(LNP2P_LEGACY_PORT).expect("node address is invalid");
@@ -97,6 +336,7 @@ impl Exec for Command {
                 push_msat,
                 fee_rate,
                 announce_channel,
+                private,
                 channel_type,
                 dust_limit,
                 to_self_delay,
@@ -104,6 +344,12 @@ impl Exec for Command {
                 htlc_min_value,
                 htlc_max_total_value,
                 channel_reserve,
+                shutdown_address,
+                max_to_self_delay,
+                zero_conf,
+                external_funding,
+                dry_run,
+                quiet,
             } => {
                 let node_addr =
                     peer.to_node_addr(LNP2P_LEGACY_PORT).expect("node address is invalid");
@@ -114,7 +360,7 @@ impl Exec for Command {
                         funding_sat,
                         push_msat: push_msat.unwrap_or_default(),
                         fee_rate,
-                        announce_channel,
+                        announce_channel: if private { Some(false) } else { announce_channel },
                         channel_type,
                         dust_limit,
                         to_self_delay,
@@ -124,10 +370,230 @@ impl Exec for Command {
                         remote_peer: node_addr,
                         report_to: Some(runtime.identity()),
                         channel_reserve,
+                        shutdown_script: shutdown_address
+                            .map(|address| address.script_pubkey().into()),
+                        zero_conf,
+                        max_to_self_delay,
+                        external_funding,
+                        dry_run,
+                    }),
+                )?;
+                if dry_run {
+                    report_funding_preview(runtime, quiet)?;
+                } else if quiet {
+                    runtime.report_outcome()?;
+                } else {
+                    runtime.report_progress()?;
+                }
+            }
+
+            Command::OpenBatch { channels, fee_rate, announce_channel, channel_type, quiet } => {
+                let requests = channels
+                    .into_iter()
+                    .map(|channel| CreateChannel {
+                        funding_sat: channel.funding_sat,
+                        push_msat: 0,
+                        fee_rate,
+                        announce_channel,
+                        channel_type,
+                        dust_limit: None,
+                        to_self_delay: None,
+                        htlc_max_count: None,
+                        htlc_min_value: None,
+                        htlc_max_total_value: None,
+                        remote_peer: channel
+                            .peer
+                            .to_node_addr(LNP2P_LEGACY_PORT)
+                            .expect("node address is invalid"),
+                        report_to: Some(runtime.identity()),
+                        channel_reserve: None,
+                        shutdown_script: None,
+                        zero_conf: false,
+                        max_to_self_delay: None,
+                        external_funding: false,
+                        dry_run: false,
+                    })
+                    .collect();
+                runtime.request(ServiceId::LnpBroker, RpcMsg::OpenChannels(requests))?;
+                if quiet {
+                    runtime.report_outcome()?;
+                } else {
+                    runtime.report_progress()?;
+                }
+            }
+
+            Command::Abort { channel: channel_id } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::AbortChannel(channel_id))?;
+                runtime.report_progress()?;
+            }
+
+            Command::Close { channel: channel_id, force, fee_rate, address } => {
+                runtime.request(
+                    ServiceId::LnpBroker,
+                    RpcMsg::CloseChannel(CloseChannel {
+                        channel_id,
+                        force,
+                        fee_range: fee_rate.map(ClosingFeeRange::with_feerate),
+                        dest_address: address,
                     }),
                 )?;
                 runtime.report_progress()?;
             }
+
+            Command::Channel { command: ChannelCommand::Export { channel: channel_id, file } } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ExportChannel(channel_id))?;
+                match runtime.report_failure()? {
+                    RpcMsg::ChannelExport(data) => {
+                        fs::write(&file, data).map_err(|err| Error::Other(err.to_string()))?;
+                        let file = file.display();
+                        let msg = format!("Channel {} state is exported to {}", channel_id, file);
+                        if runtime.json_output() {
+                            runtime.print_reply(&RpcMsg::Success(msg.into()))?;
+                        } else {
+                            println!("{}", msg);
+                        }
+                    }
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Channel { command: ChannelCommand::Import { file } } => {
+                let data = fs::read(&file).map_err(|err| Error::Other(err.to_string()))?;
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ImportChannel(data))?;
+                runtime.report_progress()?;
+            }
+
+            Command::Channel { command: ChannelCommand::Fund { channel: channel_id, file } } => {
+                let psbt = fs::read(&file).map_err(|err| Error::Other(err.to_string()))?;
+                runtime.request(
+                    ServiceId::LnpBroker,
+                    RpcMsg::ProvideFunding(ProvideFunding { channel_id, psbt }),
+                )?;
+                runtime.report_response()?;
+            }
+
+            Command::Channel { command: ChannelCommand::History { channel: channel_id } } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ChannelHistory(channel_id))?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::ChannelEvents(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::ChannelEvents(events) => print_history(events.as_inner()),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Channel {
+                command: ChannelCommand::DumpCommitment { channel: channel_id, confirmed },
+            } => {
+                if !confirmed {
+                    return Err(Error::Other(
+                        "Dumped commitment transaction may lead to the loss of all channel funds; \
+                         use `--i-know-what-i-am-doing` flag if you understand the risks"
+                            .to_string(),
+                    ));
+                }
+                runtime.request(ServiceId::LnpBroker, RpcMsg::DumpCommitment(channel_id))?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::CommitmentDump(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::CommitmentDump(dump) => {
+                        println!("{}", dump.commitment_tx);
+                        for htlc_tx in dump.htlc_txs {
+                            println!("{}", htlc_tx);
+                        }
+                    }
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::Channel {
+                command: ChannelCommand::ExportSecrets { channel: channel_id, file },
+            } => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ExportChannelSecrets(channel_id))?;
+                let secrets = match runtime.report_failure()? {
+                    RpcMsg::ChannelSecrets(secrets) => secrets,
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                };
+                let json = serde_json::to_string_pretty(&secrets)
+                    .map_err(|err| Error::Other(err.to_string()))?;
+                match file {
+                    Some(file) => {
+                        fs::write(&file, json).map_err(|err| Error::Other(err.to_string()))?;
+                        let msg = format!(
+                            "Channel {} secrets are exported to {}",
+                            channel_id,
+                            file.display()
+                        );
+                        if runtime.json_output() {
+                            runtime.print_reply(&RpcMsg::Success(msg.into()))?;
+                        } else {
+                            println!("{}", msg);
+                        }
+                    }
+                    None => println!("{}", json),
+                }
+            }
+
+            Command::Feerates => {
+                runtime.request(ServiceId::LnpBroker, RpcMsg::ListFeePolicies)?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::FeePolicies(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::FeePolicies(fee_policies) => print_fee_policies(&fee_policies),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::SetFeePolicy {
+                scope,
+                base_msat,
+                ppm,
+                cltv_delta,
+                htlc_min_msat,
+                htlc_max_msat,
+            } => {
+                let policy = FeePolicy {
+                    base_msat,
+                    proportional_millionths: ppm,
+                    cltv_delta,
+                    htlc_min_msat,
+                    htlc_max_msat,
+                };
+                let set_fee_policy = SetFeePolicy { scope, policy };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::SetFeePolicy(set_fee_policy))?;
+                runtime.report_response()?;
+            }
+
+            Command::SetFees { floor, ceiling, target } => {
+                let set_fees =
+                    SetFees { floor_per_kw: floor, ceiling_per_kw: ceiling, target_blocks: target };
+                runtime.request(ServiceId::LnpBroker, RpcMsg::SetFees(set_fees))?;
+                runtime.report_response()?;
+            }
+
             Command::Invoice { .. } => todo!("Implement invoice generation"),
 
             Command::Pay { invoice, channel: channel_id, amount_msat } => {
@@ -137,7 +603,438 @@ impl Exec for Command {
                 )?;
                 runtime.report_progress()?;
             }
+
+            Command::Graph { command: GraphCommand::Describe } => {
+                runtime.request(ServiceId::Router, RpcMsg::DescribeGraph)?;
+                runtime.report_response()?;
+            }
+
+            Command::Graph { command: GraphCommand::Node { node_id } } => {
+                runtime.request(ServiceId::Router, RpcMsg::GetNodeInfo(node_id))?;
+                runtime.report_response()?;
+            }
+
+            Command::Graph { command: GraphCommand::Channel { short_channel_id } } => {
+                runtime.request(ServiceId::Router, RpcMsg::GetChannelInfo(short_channel_id))?;
+                runtime.report_response()?;
+            }
+
+            Command::SignMessage { message } => {
+                runtime.request(ServiceId::Signer, RpcMsg::SignMessage(message))?;
+                match runtime.report_failure()? {
+                    reply @ RpcMsg::MessageSignature(_) if runtime.json_output() => {
+                        runtime.print_reply(&reply)?
+                    }
+                    RpcMsg::MessageSignature(signature) => println!("{}", signature),
+                    _ => {
+                        return Err(Error::Other(
+                            "Server returned unrecognizable response".to_string(),
+                        ))
+                    }
+                }
+            }
+
+            Command::VerifyMessage { message, signature, pubkey } => {
+                let verify_message = VerifyMessage { message, signature, pubkey };
+                runtime.request(ServiceId::Router, RpcMsg::VerifyMessage(verify_message))?;
+                runtime.report_response()?;
+            }
+
+            Command::Unlock { passphrase_file } => {
+                let passphrase = match passphrase_file {
+                    Some(path) => fs::read_to_string(path)
+                        .map_err(|err| Error::Other(err.to_string()))?
+                        .trim_end_matches(&['\r', '\n'][..])
+                        .to_owned(),
+                    None => rpassword::read_password_from_tty(Some("Passphrase: "))
+                        .map_err(|err| Error::Other(err.to_string()))?,
+                };
+                runtime.request(ServiceId::Signer, RpcMsg::Unlock(passphrase))?;
+                runtime.report_response()?;
+            }
+
+            Command::Recover { scb } => {
+                let data = fs::read(&scb).map_err(|err| Error::Other(err.to_string()))?;
+                runtime.request(ServiceId::LnpBroker, RpcMsg::RecoverChannels(data))?;
+                runtime.report_response()?;
+            }
         }
         Ok(())
     }
 }
+
+/// Waits until `check` reports the awaited state. The state is checked first right away, and
+/// then again on each node event for which `relevant` returns `true`; without the event
+/// subscription it is polled every [`TX_POLL_INTERVAL`]. Fails with [`ErrorCode::Timeout`] once
+/// the deadline passes.
+fn wait_until<T>(
+    runtime: &mut Client,
+    mut subscriber: Option<EventSubscriber>,
+    deadline: Option<Instant>,
+    awaited: &str,
+    mut check: impl FnMut(&mut Client) -> Result<Option<T>, Error>,
+    relevant: impl Fn(&NodeEvent) -> bool,
+) -> Result<T, Error> {
+    loop {
+        if let Some(state) = check(runtime)? {
+            return Ok(state);
+        }
+        loop {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::from_secs(0)) {
+                let message = format!("Timed out waiting for {}", awaited);
+                return Err(Error::Failure(RpcError::new(ErrorCode::Timeout, message)));
+            }
+            match subscriber {
+                Some(ref mut subscriber) => {
+                    let period = remaining.unwrap_or(WAIT_RECHECK_PERIOD).min(WAIT_RECHECK_PERIOD);
+                    match subscriber.recv_timeout(period)? {
+                        Some(event) if !relevant(&event) => continue,
+                        _ => break,
+                    }
+                }
+                None => {
+                    thread::sleep(remaining.unwrap_or(TX_POLL_INTERVAL).min(TX_POLL_INTERVAL));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn wait_channel_active(
+    runtime: &mut Client,
+    events_socket: &str,
+    channel_id: ChannelId,
+    deadline: Option<Instant>,
+) -> Result<ChannelSummary, Error> {
+    // Subscribing before the first check, so the transition happening in between is not missed
+    let subscriber = EventSubscriber::with(events_socket, &[EventCategory::Channel])?;
+    let awaited = format!("channel {} to become active", channel_id);
+    let check = |runtime: &mut Client| {
+        runtime.request(ServiceId::LnpBroker, RpcMsg::GetChannel(channel_id))?;
+        let summary = match runtime.report_failure()? {
+            RpcMsg::ChannelSummary(summary) => summary,
+            _ => return Err(Error::Other("Server returned unrecognizable response".to_string())),
+        };
+        if summary.lifecycle == Lifecycle::Active.to_string() {
+            Ok(Some(summary))
+        } else if summary.lifecycle == Lifecycle::Closed.to_string() {
+            let message = format!("Channel {} is closed and will never become active", channel_id);
+            Err(Error::Failure(RpcError::new(ErrorCode::ChannelState, message)))
+        } else {
+            Ok(None)
+        }
+    };
+    // The channel may get its permanent id while waiting, so any lifecycle change triggers the
+    // check
+    let relevant = |event: &NodeEvent| matches!(event, NodeEvent::ChannelLifecycle { .. });
+    wait_until(runtime, Some(subscriber), deadline, &awaited, check, relevant)
+}
+
+fn wait_peer_connected(
+    runtime: &mut Client,
+    events_socket: &str,
+    node_id: secp256k1::PublicKey,
+    deadline: Option<Instant>,
+) -> Result<PeerInfo, Error> {
+    // Subscribing before the first check, so the connection established in between is not
+    // missed
+    let subscriber = EventSubscriber::with(events_socket, &[EventCategory::Peer])?;
+    let awaited = format!("peer {} to get connected", node_id);
+    let check = |runtime: &mut Client| {
+        let filter = PeerFilter { remote_node: Some(node_id), ..PeerFilter::default() };
+        let request = RpcMsg::ListPeers(filter, Pagination::default(), false);
+        runtime.request(ServiceId::LnpBroker, request)?;
+        match runtime.report_failure()? {
+            RpcMsg::PeerList(peers) => Ok(peers.connected.into_iter().find(|peer| peer.connected)),
+            _ => Err(Error::Other("Server returned unrecognizable response".to_string())),
+        }
+    };
+    let relevant = |event: &NodeEvent| match event {
+        NodeEvent::PeerConnected { remote_peer: NodeAddr::Remote(remote_addr) } => {
+            remote_addr.node_id == node_id
+        }
+        _ => false,
+    };
+    wait_until(runtime, Some(subscriber), deadline, &awaited, check, relevant)
+}
+
+fn wait_tx_confirmed(
+    runtime: &mut Client,
+    txid: Txid,
+    depth: u32,
+    deadline: Option<Instant>,
+) -> Result<TxDepth, Error> {
+    let awaited = format!("transaction {} to get {} confirmations", txid, depth);
+    let check = |runtime: &mut Client| {
+        runtime.request(ServiceId::LnpBroker, RpcMsg::GetTxDepth(txid))?;
+        match runtime.report_failure()? {
+            RpcMsg::TxDepth(tx_depth) if tx_depth.depth.unwrap_or_default() >= depth => {
+                Ok(Some(tx_depth))
+            }
+            RpcMsg::TxDepth(_) => Ok(None),
+            _ => Err(Error::Other("Server returned unrecognizable response".to_string())),
+        }
+    };
+    wait_until(runtime, None, deadline, &awaited, check, |_| false)
+}
+
+/// Awaits for the funding transaction composed for the channel opened as a dry run
+fn report_funding_preview(runtime: &mut Client, quiet: bool) -> Result<(), Error> {
+    loop {
+        match runtime.report_failure()? {
+            RpcMsg::Progress(_) if quiet => {}
+            progress @ RpcMsg::Progress(_) if runtime.json_output() => {
+                runtime.print_reply(&progress)?
+            }
+            RpcMsg::Progress(info) => println!("{}", info),
+            reply @ RpcMsg::FundingPreview(_) if runtime.json_output() => {
+                return runtime.print_reply(&reply)
+            }
+            RpcMsg::FundingPreview(preview) => {
+                print_funding_preview(&preview);
+                return Ok(());
+            }
+            _ => return Err(Error::Other("Server returned unrecognizable response".to_string())),
+        }
+    }
+}
+
+fn print_funding_preview(preview: &FundingPreview) {
+    println!("Inputs:");
+    for outpoint in &preview.inputs {
+        println!("  {}", outpoint);
+    }
+    println!("Input total:   {} sat", preview.input_sat);
+    println!("Funding:       {} sat", preview.funding_sat);
+    match preview.change_sat {
+        Some(change_sat) => println!("Change:        {} sat", change_sat),
+        None => println!("Change:        none"),
+    }
+    println!("Fee:           {} sat", preview.fee_sat);
+    println!(
+        "Feerate:       {:.2} sat/vB ({} weight units)",
+        preview.feerate_sat_vb(),
+        preview.weight
+    );
+    println!();
+    println!("Funding output pays to a placeholder script; the actual one is known only once the");
+    println!("remote peer accepts the channel");
+    println!("{}", preview.psbt);
+}
+
+fn print_peers(peers: &PeerList) {
+    println!(
+        "{:<66} {:<24} {:<3} {:>8} {:>8} {:>8} {:>10} {:>10} {:>6} {}",
+        "NODE",
+        "SOCKET",
+        "DIR",
+        "UPTIME_S",
+        "MSG_OUT",
+        "MSG_IN",
+        "BYTES_OUT",
+        "BYTES_IN",
+        "RTT_MS",
+        "CHANNELS"
+    );
+    for peer in &peers.connected {
+        let node = peer.remote_id.first().map(|node| node.to_string()).unwrap_or_else(|| s!("-"));
+        let socket = peer
+            .remote_socket
+            .first()
+            .map(|socket| socket.to_string())
+            .unwrap_or_else(|| s!("-"));
+        let rtt = peer.ping_rtt_ms.map(|rtt| rtt.to_string()).unwrap_or_else(|| s!("-"));
+        let channels =
+            peer.channels.iter().map(|channel_id| channel_id.to_string()).collect::<Vec<_>>();
+        println!(
+            "{:<66} {:<24} {:<3} {:>8} {:>8} {:>8} {:>10} {:>10} {:>6} {}",
+            node,
+            socket,
+            peer.direction,
+            peer.uptime.as_secs(),
+            peer.messages_sent,
+            peer.messages_received,
+            peer.bytes_sent,
+            peer.bytes_received,
+            rtt,
+            channels.join(",")
+        );
+    }
+    print_page_status(peers.connected.len(), peers.total_count, "connections");
+
+    if peers.reconnecting.is_empty() {
+        return;
+    }
+    println!();
+    println!(
+        "{:<66} {:<24} {:<6} {:>8} {:>8} {}",
+        "RECONNECTING", "SOCKET", "PINNED", "ATTEMPTS", "NEXT_S", "LAST_CONNECTED"
+    );
+    for peer in &peers.reconnecting {
+        let socket =
+            peer.remote_socket.as_ref().map(|socket| socket.to_string()).unwrap_or_else(|| s!("-"));
+        let last_connected =
+            peer.last_connected.map(|timestamp| timestamp.to_string()).unwrap_or_else(|| s!("-"));
+        println!(
+            "{:<66} {:<24} {:<6} {:>8} {:>8} {}",
+            peer.node_id,
+            socket,
+            peer.pinned,
+            peer.attempts,
+            peer.next_attempt.as_secs(),
+            last_connected
+        );
+    }
+}
+
+fn print_channels(list: &ChannelList) {
+    println!(
+        "{:<64} {:<66} {:<16} {:>12} {:>15} {:>15} {:>5} {:<69} {}",
+        "CHANNEL",
+        "PEER",
+        "LIFECYCLE",
+        "CAPACITY_SAT",
+        "LOCAL_MSAT",
+        "REMOTE_MSAT",
+        "HTLCS",
+        "FUNDING",
+        "CONF"
+    );
+    for channel in &list.channels {
+        let peer = channel.remote_node.map(|node| node.to_string()).unwrap_or_else(|| s!("-"));
+        let funding = channel
+            .funding_outpoint
+            .map(|outpoint| outpoint.to_string())
+            .unwrap_or_else(|| s!("-"));
+        let confirmations = channel
+            .confirmations
+            .map(|depth| depth.to_string())
+            .unwrap_or_else(|| s!("-"));
+        println!(
+            "{:<64} {:<66} {:<16} {:>12} {:>15} {:>15} {:>5} {:<69} {}/{}",
+            channel.channel_id,
+            peer,
+            channel.lifecycle,
+            channel.capacity_sat,
+            channel.local_balance_msat,
+            channel.remote_balance_msat,
+            channel.pending_htlcs,
+            funding,
+            confirmations,
+            channel.minimum_depth
+        );
+    }
+    print_page_status(list.channels.len(), list.total_count, "channels");
+}
+
+/// Reports the number of listed items if the listing page does not contain all of them
+fn print_page_status(shown: usize, total_count: u32, items: &str) {
+    if shown < total_count as usize {
+        println!();
+        println!("Showing {} of {} {}", shown, total_count, items);
+    }
+}
+
+fn print_fee_policies(fee_policies: &FeePolicyList) {
+    println!("Default policy: {}", fee_policies.default);
+    for (node_id, policy) in &fee_policies.peers {
+        println!("Policy of peer {}: {}", node_id, policy);
+    }
+    println!();
+    println!(
+        "{:<64} {:<66} {:<7} {:>9} {:>8} {:>5} {:>13} {:>13} {:>10} {}",
+        "CHANNEL",
+        "PEER",
+        "SCOPE",
+        "BASE_MSAT",
+        "PPM",
+        "CLTV",
+        "HTLC_MIN_MSAT",
+        "HTLC_MAX_MSAT",
+        "ANNOUNCED",
+        "PENDING"
+    );
+    for channel in &fee_policies.channels {
+        let scope = match channel.scope {
+            PolicyScope::Channel(_) => "channel",
+            PolicyScope::Peer(_) => "peer",
+            PolicyScope::All => "all",
+        };
+        let policy = channel.policy;
+        let htlc_max =
+            policy.htlc_max_msat.map(|amount| amount.to_string()).unwrap_or_else(|| s!("-"));
+        let announced =
+            channel.announced.map(|timestamp| timestamp.to_string()).unwrap_or_else(|| s!("-"));
+        println!(
+            "{:<64} {:<66} {:<7} {:>9} {:>8} {:>5} {:>13} {:>13} {:>10} {}",
+            channel.channel_id,
+            channel.remote_node,
+            scope,
+            policy.base_msat,
+            policy.proportional_millionths,
+            policy.cltv_delta,
+            policy.htlc_min_msat,
+            htlc_max,
+            announced,
+            channel.pending
+        );
+    }
+}
+
+fn print_bans(bans: &[BanInfo]) {
+    println!("{:<66} {:<40} {:>10} {:>11} {}", "NODE", "ADDRESS", "UNTIL", "REMAINING_S", "REASON");
+    for ban in bans {
+        let address = ban.address.map(|address| address.to_string()).unwrap_or_else(|| s!("-"));
+        println!(
+            "{:<66} {:<40} {:>10} {:>11} {}",
+            ban.node_id,
+            address,
+            ban.until,
+            ban.remaining.as_secs(),
+            ban.reason
+        );
+    }
+}
+
+fn print_pending_psbts(pending: &[PendingPsbt]) {
+    for item in pending {
+        println!("{} {}", item.txid, item.purpose);
+        println!("{}\n", item.psbt);
+    }
+}
+
+fn print_pending_txs(pending: &[PendingTx]) {
+    println!(
+        "{:<64} {:>8} {:>10} {:>12} {:<7} {}",
+        "TXID", "AGE_S", "SAT_PER_KW", "REBROADCASTS", "EVICTED", "OWNER"
+    );
+    for tx in pending {
+        let feerate =
+            tx.feerate_per_kw.map(|feerate| feerate.to_string()).unwrap_or_else(|| s!("-"));
+        println!(
+            "{:<64} {:>8} {:>10} {:>12} {:<7} {}",
+            tx.txid, tx.age, feerate, tx.rebroadcasts, tx.evicted, tx.owner
+        );
+    }
+}
+
+fn print_history(events: &[ChannelEvent]) {
+    println!(
+        "{:<12} {:<16} {:<3} {:<28} {:<24} {}",
+        "TIMESTAMP", "LIFECYCLE", "DIR", "MESSAGE", "SERVICE", "OUTCOME"
+    );
+    for event in events {
+        println!(
+            "{:<12} {:<16} {:<3} {:<28} {:<24} {}",
+            event.timestamp,
+            event.lifecycle,
+            event.direction,
+            event.message,
+            event.service,
+            event.outcome
+        );
+    }
+}
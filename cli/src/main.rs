@@ -36,22 +36,56 @@ extern crate clap;
 mod command;
 mod opts;
 
+use std::path::Path;
+use std::str::FromStr;
+use std::{fs, process};
+
 use clap::Parser;
-use lnp_rpc::Client;
+use lnp_rpc::{AuthToken, Client, RpcError, ToRpcError};
 use microservices::shell::{Exec, LogLevel};
 
 pub use crate::opts::{Command, Opts};
 
 fn main() {
-    println!("lnp-cli: command-line tool for working with LNP node");
-
     let opts = Opts::parse();
+    if !opts.json {
+        println!("lnp-cli: command-line tool for working with LNP node");
+    }
     LogLevel::from_verbosity_flag_count(opts.verbose).apply();
 
     trace!("Command-line arguments: {:?}", opts);
 
     let mut client = Client::with(&opts.connect).expect("Error initializing client");
+    client.set_json_output(opts.json);
+    let token = match opts.token_file {
+        Some(ref path) => Some(read_token(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read RPC token from '{}': {}", path.display(), err);
+            process::exit(1);
+        })),
+        None => opts.token.clone(),
+    };
+    client.set_token(token);
 
     trace!("Executing command: {:?}", opts.command);
-    opts.command.exec(&mut client).unwrap_or_else(|err| eprintln!("{}", err));
+    if let Err(err) = opts.command.exec(&mut client) {
+        let failure = err.to_rpc_error();
+        if opts.json {
+            print_json_error(&failure);
+        } else {
+            eprintln!("{}", err);
+        }
+        process::exit(failure.class().exit_status());
+    }
+}
+
+fn read_token(path: &Path) -> Result<AuthToken, String> {
+    let data = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    AuthToken::from_str(data.trim()).map_err(|err| err.to_string())
+}
+
+/// Prints error as `{"error": {"code": <code>, "message": <description>, "details": {...}}}`
+/// JSON object, keeping the failure code and details reported by the node
+fn print_json_error(failure: &RpcError) {
+    let json = serde_json::json!({ "error": failure });
+    println!("{}", serde_json::to_string_pretty(&json).expect("JSON value is always serializable"));
 }
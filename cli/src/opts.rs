@@ -12,13 +12,20 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::net::IpAddr;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use internet2::{FramingProtocol, PartialNodeAddr};
+use bitcoin::{secp256k1, Address, Txid};
+use internet2::addr::InetSocketAddr;
+use internet2::{FramingProtocol, PartialNodeAddr, RemoteSocketAddr};
 use lightning_invoice::Invoice;
-use lnp::p2p::legacy::{ChannelId, ChannelType};
-use lnp_rpc::LNP_NODE_RPC_SOCKET;
+use lnp::p2p::legacy::{ChannelId, ChannelType, ShortChannelId, LNP2P_LEGACY_PORT};
+use lnp_rpc::{
+    AddressType, AuthToken, EventCategory, Permissions, PolicyScope, LNP_NODE_EVENTS_SOCKET,
+    LNP_NODE_RPC_SOCKET,
+};
 
 /// Command-line tool for working with LNP node
 #[derive(Parser, Clone, PartialEq, Eq, Debug)]
@@ -27,7 +34,8 @@ pub struct Opts {
     /// ZMQ socket for connecting daemon RPC interface.
     ///
     /// Socket can be either TCP address in form of `<ipv4 | ipv6>:<port>` – or a path
-    /// to an IPC file.
+    /// to an IPC file. Remote RPC listener of the node is connected over encrypted connection
+    /// with `lnpr://<node_id>@<host>:<port>`.
     ///
     /// Defaults to `127.0.0.1:62962`.
     #[clap(
@@ -45,6 +53,24 @@ pub struct Opts {
     #[clap(short, long, global = true, parse(from_occurrences))]
     pub verbose: u8,
 
+    /// Print output in JSON format.
+    ///
+    /// Each reply of the node is printed as JSON object with a single key naming the reply type.
+    /// Errors are printed as `{"error": {"code": <code>, "info": <description>}}`.
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// RPC token authenticating requests to the node.
+    ///
+    /// The node saves the admin token to `admin.token` file in its data directory at the first
+    /// start; tokens with reduced permissions are issued with `bake-token` command.
+    #[clap(long, global = true, env = "LNP_RPC_TOKEN", hide_env_values = true)]
+    pub token: Option<AuthToken>,
+
+    /// File to read the RPC token from, like `admin.token` file in the node data directory.
+    #[clap(long, global = true, conflicts_with = "token")]
+    pub token_file: Option<PathBuf>,
+
     /// Command to execute
     #[clap(subcommand)]
     pub command: Command,
@@ -71,9 +97,26 @@ pub enum Command {
 
     /// Connect to the remote lightning network peer
     Connect {
-        /// Address of the remote node, in
-        /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]' format
-        peer: PartialNodeAddr,
+        /// Address of the remote node, in '<public_key>[@<ipv4>|<ipv6>|<onionv3>[:<port>]]'
+        /// format. If only the public key is given, the address announced by the node in the
+        /// gossip or the address it was last connected at is used. With `ws://` prefix the node
+        /// is connected over WebSocket
+        peer: PeerLocator,
+
+        /// Number of seconds to wait for the connection to be established
+        #[clap(long, default_value = "30")]
+        timeout: u16,
+    },
+
+    /// Disconnect the remote lightning network peer. Channels with the peer stop offering new
+    /// HTLCs, and channels which funding is not yet signed are abandoned
+    Disconnect {
+        /// Public key of the remote node
+        node_id: secp256k1::PublicKey,
+
+        /// Do not reconnect the peer automatically until it is connected with `connect` command
+        #[clap(long)]
+        permanent: bool,
     },
 
     /// Ping remote peer (must be already connected)
@@ -90,16 +133,179 @@ pub enum Command {
         subject: Option<String>,
     },
 
+    /// Status of the blockchain backend used by the on-chain tracking service
+    ChainStatus,
+
+    /// On-chain tracking service operations
+    Chain {
+        #[clap(subcommand)]
+        command: ChainCommand,
+    },
+
+    /// Subscribes to the node events and prints them as they happen
+    Events {
+        /// ZMQ socket the node publishes events to.
+        ///
+        /// Socket can be either TCP address in form of `<ipv4 | ipv6>:<port>` – or a path
+        /// to an IPC file.
+        #[clap(
+            long = "socket",
+            default_value = LNP_NODE_EVENTS_SOCKET,
+            env = "LNP_NODE_EVENTS_SOCKET"
+        )]
+        events_socket: String,
+
+        /// Print only events of this category: `peer`, `channel`, `payment` or `wallet`. Can be
+        /// used multiple times; if absent, all events are printed.
+        #[clap(long)]
+        filter: Vec<EventCategory>,
+    },
+
+    /// Waits until the channel becomes active, the remote peer gets connected or the
+    /// transaction gets confirmed.
+    ///
+    /// Exits with status 9 if the condition is not met before the timeout.
+    Wait {
+        /// ZMQ socket the node publishes events to.
+        ///
+        /// Socket can be either TCP address in form of `<ipv4 | ipv6>:<port>` – or a path
+        /// to an IPC file.
+        #[clap(
+            long = "socket",
+            global = true,
+            default_value = LNP_NODE_EVENTS_SOCKET,
+            env = "LNP_NODE_EVENTS_SOCKET"
+        )]
+        events_socket: String,
+
+        /// Number of seconds to wait; waits indefinitely if absent
+        #[clap(long, global = true)]
+        timeout: Option<u64>,
+
+        #[clap(subcommand)]
+        condition: WaitCondition,
+    },
+
     /// Lists all funds available for channel creation with the list of assets
     /// and provides information about funding points (bitcoin address or UTXO
     /// for RGB assets)
     Funds,
 
-    /// Lists existing peer connections
-    Peers,
+    /// Issues a new funding wallet address for deposits
+    Address {
+        /// Type of the address: `bech32` or `taproot`
+        #[clap(long = "type", default_value = "bech32")]
+        address_type: AddressType,
+    },
+
+    /// Sends funds from the funding wallet to an external address
+    Withdraw {
+        /// Address receiving the funds
+        address: Address,
+
+        /// Amount to send, in satoshis
+        #[clap(required_unless_present = "all")]
+        amount: Option<u64>,
+
+        /// Send all funds which are not reserved for the channel funding, deducting the fee from
+        /// the sent amount
+        #[clap(long, conflicts_with = "amount")]
+        all: bool,
+
+        /// Fee rate of the transaction, in satoshi per vbyte. If absent, the fee rate is
+        /// estimated by the node.
+        #[clap(long)]
+        fee_rate: Option<u32>,
+
+        /// Print the unsigned transaction instead of signing and publishing it
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Token confirming the withdrawal exceeding the withdrawal limit, read from the
+        /// `withdrawal.token` file of the node data directory
+        #[clap(long)]
+        confirm: Option<String>,
+    },
+
+    /// Issues a new RPC token granting the given permissions. Requires admin token.
+    BakeToken {
+        /// Comma-separated list of permissions granted by the token: `read` for reading node,
+        /// peer and channel information, `invoice` for receiving funds and `admin` for all
+        /// operations
+        #[clap(long, default_value = "read")]
+        permissions: Permissions,
+    },
+
+    /// Lists existing peer connections and the remote peers being reconnected
+    Peers {
+        /// Show only connection with the remote peer having this node id
+        #[clap(long)]
+        node: Option<secp256k1::PublicKey>,
+
+        /// List only connections established at or after this UNIX timestamp
+        #[clap(long)]
+        since: Option<u64>,
+
+        /// List only connections established at or before this UNIX timestamp
+        #[clap(long)]
+        until: Option<u64>,
+
+        /// List only incoming connections accepted at this listening socket
+        #[clap(long)]
+        listener: Option<InetSocketAddr>,
+
+        /// Number of connections to skip
+        #[clap(long, default_value = "0")]
+        offset: u32,
+
+        /// Maximal number of connections to list
+        #[clap(long)]
+        limit: Option<u32>,
+
+        /// Reset the traffic and connection statistics once they are reported
+        ///
+        /// Unless `--node` is given, statistics of all peers are reset, including the ones
+        /// filtered out by the other options.
+        #[clap(long)]
+        reset: bool,
+    },
+
+    /// Peer address book operations
+    Peer {
+        #[clap(subcommand)]
+        command: PeerCommand,
+    },
+
+    /// Ban list operations
+    Ban {
+        #[clap(subcommand)]
+        command: BanCommand,
+    },
+
+    /// Watch-only funding wallet operations
+    Wallet {
+        #[clap(subcommand)]
+        command: WalletCommand,
+    },
 
     /// Lists existing channels
-    Channels,
+    Channels {
+        /// List only channels with the remote peer having this node id
+        #[clap(long)]
+        peer: Option<secp256k1::PublicKey>,
+
+        /// List only channels at this lifecycle stage
+        #[clap(long)]
+        stage: Option<String>,
+
+        /// Number of channels to skip
+        #[clap(long, default_value = "0")]
+        offset: u32,
+
+        /// Maximal number of channels to list
+        #[clap(long)]
+        limit: Option<u32>,
+    },
 
     /// Opens a new channel with a remote peer, which must be already
     /// connected.
@@ -139,6 +345,13 @@ pub enum Command {
         #[clap(long)]
         announce_channel: Option<bool>,
 
+        /// Keep the channel private.
+        ///
+        /// The channel is not announced to the lightning network and the remote peer is asked
+        /// not to announce it either. Same as `--announce-channel false`.
+        #[clap(long, conflicts_with = "announce_channel")]
+        private: bool,
+
         /// Channel type as defined in BOLT-2.
         ///
         /// If used, overrides default node settings.
@@ -192,6 +405,175 @@ pub enum Command {
         /// If used, overrides default node settings.
         #[clap(long)]
         channel_reserve: Option<u64>,
+
+        /// Address which must receive our funds when the channel is cooperatively closed.
+        ///
+        /// The address is committed to the remote peer at the channel opening
+        /// (`option_upfront_shutdown_script`) and can't be changed later. Must be of P2PKH, P2SH,
+        /// P2WPKH or P2WSH type.
+        #[clap(long)]
+        shutdown_address: Option<Address>,
+
+        /// Maximal number of blocks the remote peer may require our funds to be timelocked for
+        /// after a unilateral channel close.
+        ///
+        /// If used, overrides default node settings.
+        #[clap(long)]
+        max_to_self_delay: Option<u16>,
+
+        /// Start using the channel without waiting for the funding transaction confirmation.
+        ///
+        /// Works only if the remote peer trusts us and agrees to accept the channel with zero
+        /// confirmations.
+        #[clap(long)]
+        zero_conf: bool,
+
+        /// Fund the channel from an external wallet instead of the node funding wallet.
+        ///
+        /// The node reports the channel funding address and the exact amount to pay. The funding
+        /// transaction must be provided back with `channel fund` command and published only after
+        /// the remote peer has signed the refund transaction.
+        #[clap(long)]
+        external_funding: bool,
+
+        /// Only compose the funding transaction, without proposing the channel to the remote
+        /// peer.
+        ///
+        /// Prints the unsigned funding PSBT together with the inputs, change, fee and effective
+        /// feerate. No funds are reserved and the remote peer is not contacted. The funding output
+        /// pays to a placeholder script, since the actual one depends on the key of the remote
+        /// peer.
+        #[clap(long, conflicts_with = "external_funding")]
+        dry_run: bool,
+
+        /// Print only the final result of the channel opening instead of reporting each stage
+        /// of the channel negotiation and funding.
+        #[clap(long)]
+        quiet: bool,
+    },
+
+    /// Opens multiple channels with remote peers, which must be already connected, funding all
+    /// of them with a single transaction.
+    ///
+    /// If any of the remote peers rejects its channel before the funding transaction is
+    /// committed to, opening of all channels is abandoned and the reserved funds are released.
+    /// Channels opened in a batch are always funded by the node funding wallet.
+    OpenBatch {
+        /// Channels to open, each in '<node_addr>=<funding_sat>' format, where node address is
+        /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]'
+        #[clap(required = true)]
+        channels: Vec<ChannelFunding>,
+
+        /// Sets fee rate for the transactions of all channels, in satoshi per 1000-weight.
+        ///
+        /// If used, overrides default node settings.
+        #[clap(long)]
+        fee_rate: Option<u32>,
+
+        /// Make all channels public and route payments.
+        ///
+        /// If used, overrides default node settings.
+        #[clap(long)]
+        announce_channel: Option<bool>,
+
+        /// Channel type as defined in BOLT-2 for all channels.
+        ///
+        /// If used, overrides default node settings.
+        #[clap(long)]
+        channel_type: Option<ChannelType>,
+
+        /// Print only the final result of the channel opening instead of reporting each stage
+        /// of the channel negotiation and funding.
+        #[clap(long)]
+        quiet: bool,
+    },
+
+    /// Aborts opening of a channel, which funding transaction is not signed yet.
+    ///
+    /// Funds reserved for the channel funding are released. Channels which funding transaction
+    /// is already signed can be closed only with a transaction spending the funding output.
+    Abort {
+        /// Temporary channel id
+        channel: ChannelId,
+    },
+
+    /// Closes an active channel.
+    ///
+    /// By default the closing transaction is negotiated with the remote peer, which must be
+    /// connected, and the channel can be closed only once all pending HTLCs are resolved.
+    Close {
+        /// Channel id
+        channel: ChannelId,
+
+        /// Close the channel unilaterally by publishing the latest commitment transaction. Our
+        /// funds get timelocked for the delay required by the remote peer.
+        #[clap(long)]
+        force: bool,
+
+        /// Fee rate of the cooperative closing transaction, in satoshi per vbyte. The closing
+        /// fails if the remote peer does not agree with it.
+        #[clap(long, conflicts_with = "force")]
+        fee_rate: Option<u64>,
+
+        /// Address receiving our funds from the cooperative closing transaction. Can't be used
+        /// if the channel has committed upfront to another address.
+        #[clap(long, conflicts_with = "force")]
+        address: Option<Address>,
+    },
+
+    /// Channel state operations
+    Channel {
+        #[clap(subcommand)]
+        command: ChannelCommand,
+    },
+
+    /// Lists routing fee policies set for the channels and the policies in effect for the active
+    /// channels
+    Feerates,
+
+    /// Sets routing fee policy announced for the channels to the network
+    SetFeePolicy {
+        /// Channels to set the policy for: `all`, node id of the remote peer or channel id.
+        /// Policy set for all channels or for a remote peer replaces policies previously set for
+        /// the individual channels.
+        scope: PolicyScope,
+
+        /// Fee charged for each forwarded HTLC, in millisatoshis
+        #[clap(long)]
+        base_msat: u32,
+
+        /// Fee charged for each million of forwarded millisatoshis
+        #[clap(long)]
+        ppm: u32,
+
+        /// Number of blocks the incoming HTLC timelock must exceed the forwarded HTLC timelock by
+        #[clap(long, default_value = "40")]
+        cltv_delta: u16,
+
+        /// Minimal amount of the forwarded HTLC, in millisatoshis
+        #[clap(long, default_value = "1000")]
+        htlc_min_msat: u64,
+
+        /// Maximal amount of the forwarded HTLC, in millisatoshis. Defaults to the channel
+        /// capacity.
+        #[clap(long)]
+        htlc_max_msat: Option<u64>,
+    },
+
+    /// Updates limits and confirmation target of the fee estimation used for the funding, sweep
+    /// and commitment transactions. Settings which are not given are kept.
+    SetFees {
+        /// Minimal estimated feerate, in satoshi per 1000-weight
+        #[clap(long)]
+        floor: Option<u32>,
+
+        /// Maximal estimated feerate, in satoshi per 1000-weight
+        #[clap(long)]
+        ceiling: Option<u32>,
+
+        /// Number of blocks within which the node transactions should get mined
+        #[clap(long)]
+        target: Option<u16>,
     },
 
     /// Create an invoice
@@ -217,6 +599,249 @@ pub enum Command {
         /// amount. Overrides amount provided by the invoice.
         amount_msat: Option<u64>,
     },
+
+    /// Network graph known to the node from the gossip
+    Graph {
+        #[clap(subcommand)]
+        command: GraphCommand,
+    },
+
+    /// Signs the message with the node key. The signature is compatible with `signmessage`
+    /// command of LND and c-lightning.
+    SignMessage {
+        /// Message to sign
+        message: String,
+    },
+
+    /// Verifies the message signature and prints the node id of the signer. Without the public
+    /// key the signature is valid only if the signer is known from the gossip.
+    VerifyMessage {
+        /// Signed message
+        message: String,
+
+        /// zbase32-encoded signature of the message
+        signature: String,
+
+        /// Public key which is expected to sign the message
+        #[clap(long)]
+        pubkey: Option<secp256k1::PublicKey>,
+    },
+
+    /// Unlocks the signer, decrypting the node master key with the passphrase. Until then the
+    /// node keeps its peer connections, but can't open, update or close channels.
+    Unlock {
+        /// Reads the passphrase from the file instead of prompting for it
+        #[clap(long)]
+        passphrase_file: Option<PathBuf>,
+    },
+
+    /// Recovers channel funds from the static channel backup after the loss of the node data.
+    ///
+    /// The node must be restored from the same master key. Remote peers of the backed up channels
+    /// are reconnected and asked to close the channels unilaterally; our funds are swept to the
+    /// funding wallet once the closing transactions are mined. Funds in pending HTLCs are lost.
+    Recover {
+        /// File with the static channel backup
+        #[clap(long)]
+        scb: PathBuf,
+    },
+}
+
+/// On-chain tracking service commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum ChainCommand {
+    /// Lists unconfirmed transactions published by the node, which are rebroadcast until they get
+    /// mined
+    Pending,
+}
+
+/// Watch-only funding wallet commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum WalletCommand {
+    /// Lists transactions of the funding wallet awaiting signature of the node operator, with
+    /// their PSBTs in base64 encoding
+    Pending,
+
+    /// Submits PSBT of the pending transaction signed by the external wallet. The node finalizes
+    /// the transaction and publishes it.
+    SubmitPsbt {
+        /// File with the signed PSBT in binary form
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+}
+
+/// Network graph commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum GraphCommand {
+    /// Lists all nodes and public channels of the network graph
+    Describe,
+
+    /// Prints a node of the network graph with its announced addresses and channels
+    Node {
+        /// Node id
+        node_id: secp256k1::PublicKey,
+    },
+
+    /// Prints a public channel with the routing policies announced for it
+    Channel {
+        /// Short channel id, in `<block height>x<tx index>x<output index>` form
+        short_channel_id: ShortChannelId,
+    },
+}
+
+/// Peer address book commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum PeerCommand {
+    /// Makes the node reconnect the remote peer whenever the connection with it is lost, even if
+    /// the peer has no channels with the node
+    Pin {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+    },
+
+    /// Cancels reconnection of the pinned remote peer. Peers having channels with the node are
+    /// reconnected anyway.
+    Unpin {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+    },
+}
+
+/// Ban list commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum BanCommand {
+    /// Lists the remote peers banned for violating the protocol or by the node operator
+    List,
+
+    /// Bans the remote peer, closing the connections with it and refusing the new ones until the
+    /// ban expires. Unlike the bans for the protocol violations, peers having channels with the
+    /// node can be banned this way.
+    Add {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+
+        /// Duration of the ban, in seconds. Defaults to the ban duration configured for the node.
+        #[clap(long)]
+        duration: Option<u64>,
+    },
+
+    /// Lifts the ban of the remote peer
+    Remove {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+    },
+}
+
+/// Conditions awaited by `wait` command:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum WaitCondition {
+    /// Waits until the channel becomes active. Returns immediately if the channel is already
+    /// active and fails if it is closed.
+    ChannelActive {
+        /// Channel id
+        channel: ChannelId,
+    },
+
+    /// Waits until connection with the remote peer is established. Returns immediately if the
+    /// peer is already connected.
+    PeerConnected {
+        /// Node id of the remote peer
+        node_id: secp256k1::PublicKey,
+    },
+
+    /// Waits until the transaction gets the given number of confirmations, polling the node
+    /// on-chain tracking service
+    TxConfirmed {
+        /// Transaction id
+        txid: Txid,
+
+        /// Number of confirmations to wait for
+        #[clap(long, default_value = "1")]
+        depth: u32,
+    },
+}
+
+/// Channel state commands:
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum ChannelCommand {
+    /// Exports channel state into a file, from which it can be imported by another node.
+    ///
+    /// Used for migrating the node to a new hardware without closing channels. The node
+    /// importing the state must use the same seed. Once the state is imported, the old node must
+    /// never be started again, since using an outdated channel state leads to the loss of all
+    /// channel funds.
+    Export {
+        /// Channel id
+        channel: ChannelId,
+
+        /// File to save the channel state to
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+
+    /// Imports channel state exported by another node.
+    ///
+    /// The node must not be connected to the remote peer of the channel. The state older than
+    /// the one already known to the node is refused.
+    Import {
+        /// File with the exported channel state
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+
+    /// Provides channel with the funding transaction constructed by an external wallet.
+    ///
+    /// The transaction must pay exactly the reported amount to the channel funding address and
+    /// must not be published before the node reports that the refund transaction is signed.
+    Fund {
+        /// Temporary channel id
+        channel: ChannelId,
+
+        /// File with the funding transaction in binary PSBT format
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+
+    /// Prints history of the messages processed and sent by the channel daemon.
+    ///
+    /// The history is available also for the channels which daemons are not running anymore,
+    /// including failed channel negotiations, which are addressed by their temporary channel id.
+    History {
+        /// Channel id
+        channel: ChannelId,
+    },
+
+    /// Prints the latest local commitment transaction of the channel and its second-stage HTLC
+    /// transactions, fully signed, as a last resort for the disaster recovery.
+    ///
+    /// DANGEROUS: publishing the commitment transaction force-closes the channel. Once the
+    /// channel state is updated, the dumped commitment transaction gets revoked, and publishing
+    /// it leads to the loss of all channel funds. Use only if the node is unable to close the
+    /// channel by itself.
+    DumpCommitment {
+        /// Channel id
+        channel: ChannelId,
+
+        /// Confirms that the risks of using the dumped transactions are understood
+        #[clap(long = "i-know-what-i-am-doing")]
+        confirmed: bool,
+    },
+
+    /// Exports the channel basepoints and the revocation secrets received from the remote peer
+    /// in JSON format, for the audit and recovery tools.
+    ///
+    /// Requires the admin token and the node started with `--unsafe-export`. Revocation secrets
+    /// allow to claim all channel funds if the remote peer publishes a revoked commitment
+    /// transaction, so the export must be kept secret.
+    ExportSecrets {
+        /// Channel id
+        channel: ChannelId,
+
+        /// File to save the export to instead of printing it
+        #[clap(short, long)]
+        file: Option<PathBuf>,
+    },
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error, From)]
@@ -271,3 +896,176 @@ impl FromStr for AmountOfAsset {
         Ok(AmountOfAsset { asset, amount })
     }
 }
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ChannelFundingParseError {
+    /// The provided value can't be parsed as a channel funding; use
+    /// '<node_addr>=<funding_sat>' form
+    NeedsValuePair,
+
+    /// The provided node address is invalid; use
+    /// '<public_key>@<ipv4>|<ipv6>|<onionv2>|<onionv3>[:<port>]' form
+    InvalidNodeAddr,
+
+    /// The provided funding amount can't be interpreted; please use unsigned integer
+    #[from(std::num::ParseIntError)]
+    InvalidAmount,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display("{peer}={funding_sat}")]
+pub struct ChannelFunding {
+    /// Remote peer of the channel
+    pub peer: PartialNodeAddr,
+
+    /// Amount of satoshis to allocate to the channel
+    pub funding_sat: u64,
+}
+
+impl FromStr for ChannelFunding {
+    type Err = ChannelFundingParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split('=');
+        let (peer, funding_sat) = match (split.next(), split.next(), split.next()) {
+            (Some(peer), Some(funding_sat), None) => (peer, funding_sat),
+            _ => return Err(ChannelFundingParseError::NeedsValuePair),
+        };
+        let peer = PartialNodeAddr::from_str(peer)
+            .map_err(|_| ChannelFundingParseError::InvalidNodeAddr)?;
+        let funding_sat = u64::from_str(funding_sat)?;
+        Ok(ChannelFunding { peer, funding_sat })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PeerLocatorError {
+    /// The provided node id is not a valid public key; use
+    /// '<public_key>[@<ipv4>|<ipv6>|<onionv3>[:<port>]]' form
+    InvalidNodeId,
+
+    /// The node address after '@' is empty
+    NoAddress,
+
+    /// The provided port can't be interpreted; please use unsigned 16-bit integer
+    #[from(std::num::ParseIntError)]
+    InvalidPort,
+
+    /// '{0}' is neither IP nor onion v3 address; DNS names are not supported to avoid leaking
+    /// information about the node to DNS resolvers
+    UnsupportedHost(String),
+
+    /// '{0}' is not a valid onion v3 address
+    InvalidOnion(String),
+
+    /// Onion addresses require lnp-cli compiled with `tor` feature
+    TorUnsupported,
+
+    /// WebSocket connections over TLS are not supported; use `ws://` address of a reverse proxy
+    /// terminating TLS
+    TlsUnsupported,
+}
+
+/// Prefix of the address of the remote peer connected over WebSocket
+pub const WEBSOCKET_SCHEME: &str = "ws://";
+
+/// Remote peer given by its node id, optionally with the node address
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PeerLocator {
+    pub node_id: secp256k1::PublicKey,
+
+    /// Node address. If absent, the node must be known to the node from the gossip or the past
+    /// connections
+    pub remote_addr: Option<InetSocketAddr>,
+
+    /// Whether the node is connected over WebSocket
+    pub websocket: bool,
+}
+
+impl PeerLocator {
+    /// Peer socket address for the connection request
+    pub fn remote_socket(&self) -> Option<RemoteSocketAddr> {
+        self.remote_addr.map(|inet_addr| {
+            if self.websocket {
+                RemoteSocketAddr::Websocket(inet_addr)
+            } else {
+                RemoteSocketAddr::Ftcp(inet_addr)
+            }
+        })
+    }
+}
+
+impl Display for PeerLocator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.websocket {
+            f.write_str(WEBSOCKET_SCHEME)?;
+        }
+        match self.remote_addr {
+            Some(remote_addr) => write!(f, "{}@{}", self.node_id, remote_addr),
+            None => Display::fmt(&self.node_id, f),
+        }
+    }
+}
+
+impl FromStr for PeerLocator {
+    type Err = PeerLocatorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("wss://") {
+            return Err(PeerLocatorError::TlsUnsupported);
+        }
+        let (s, websocket) = match s.strip_prefix(WEBSOCKET_SCHEME) {
+            Some(s) => (s, true),
+            None => (s, false),
+        };
+        let mut split = s.splitn(2, '@');
+        let node_id = split.next().unwrap_or_default();
+        let node_id = secp256k1::PublicKey::from_str(node_id)
+            .map_err(|_| PeerLocatorError::InvalidNodeId)?;
+        let remote_addr = match split.next() {
+            Some(addr) => Some(parse_peer_addr(addr)?),
+            None if websocket => return Err(PeerLocatorError::NoAddress),
+            None => None,
+        };
+        Ok(PeerLocator { node_id, remote_addr, websocket })
+    }
+}
+
+/// Parses IPv4, IPv6 or onion v3 address of the remote peer, with an optional port
+#[cfg_attr(not(feature = "tor"), allow(unused_variables))]
+fn parse_peer_addr(addr: &str) -> Result<InetSocketAddr, PeerLocatorError> {
+    if addr.is_empty() {
+        return Err(PeerLocatorError::NoAddress);
+    }
+    if let Ok(socket_addr) = SocketAddr::from_str(addr) {
+        return Ok(socket_addr.into());
+    }
+    if let Ok(ip_addr) = IpAddr::from_str(addr) {
+        return Ok(InetSocketAddr { address: ip_addr.into(), port: LNP2P_LEGACY_PORT });
+    }
+
+    let mut split = addr.rsplitn(2, ':');
+    let (host, port) = match (split.next(), split.next()) {
+        (Some(port), Some(host)) => (host, u16::from_str(port)?),
+        _ => (addr, LNP2P_LEGACY_PORT),
+    };
+    let onion = match host.strip_suffix(".onion") {
+        Some(onion) => onion,
+        None => return Err(PeerLocatorError::UnsupportedHost(host.to_owned())),
+    };
+    // Onion v3 address is a base32-encoded 35-byte string; v2 addresses are deprecated
+    if onion.len() != 56 {
+        return Err(PeerLocatorError::InvalidOnion(host.to_owned()));
+    }
+    #[cfg(feature = "tor")]
+    {
+        let address = internet2::addr::InetAddr::from_str(host)
+            .map_err(|_| PeerLocatorError::InvalidOnion(host.to_owned()))?;
+        Ok(InetSocketAddr { address, port })
+    }
+    #[cfg(not(feature = "tor"))]
+    Err(PeerLocatorError::TorUnsupported)
+}
@@ -0,0 +1,235 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Authentication of the client requests to the node RPC interface.
+//!
+//! Each client request is sent within [`RpcRequest`] envelope carrying an [`AuthToken`]. The
+//! token grants a set of [`Permissions`] and is authenticated with HMAC-SHA256 tag made with
+//! the node root key, such that the permissions can't be extended by the token holder. Node
+//! generates the root key and the admin token at the first start; tokens with reduced
+//! permissions are minted by the admin with [`RpcMsg::BakeToken`] request.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::Slice32;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::{ErrorCode, RpcError, RpcMsg, ToRpcError};
+
+/// Permission for a class of the RPC requests
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum Permission {
+    /// Reading node, peer and channel information
+    #[display("read")]
+    Read,
+
+    /// Receiving funds
+    #[display("invoice")]
+    Invoice,
+
+    /// All operations, including moving funds and managing channels and tokens
+    #[display("admin")]
+    Admin,
+}
+
+/// Set of permissions granted by an RPC token
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(NetworkEncode, NetworkDecode)]
+pub struct Permissions {
+    pub read: bool,
+    pub invoice: bool,
+    pub admin: bool,
+}
+
+impl Permissions {
+    /// Permissions of the admin token generated by the node
+    pub fn admin() -> Permissions { Permissions { read: true, invoice: true, admin: true } }
+
+    /// Checks whether the permissions allow requests of the given class. Admin permission
+    /// allows all requests.
+    pub fn allow(&self, permission: Permission) -> bool {
+        self.admin
+            || match permission {
+                Permission::Read => self.read,
+                Permission::Invoice => self.invoice,
+                Permission::Admin => false,
+            }
+    }
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let names = [(self.read, "read"), (self.invoice, "invoice"), (self.admin, "admin")]
+            .iter()
+            .filter(|(granted, _)| *granted)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        f.write_str(&names.join(","))
+    }
+}
+
+/// Error parsing RPC token permissions
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("unknown permission '{0}'; supported permissions are read, invoice and admin")]
+pub struct UnknownPermission(String);
+
+impl FromStr for Permissions {
+    type Err = UnknownPermission;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut permissions = Permissions::default();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name.to_lowercase().as_str() {
+                "read" => permissions.read = true,
+                "invoice" => permissions.invoice = true,
+                "admin" => permissions.admin = true,
+                _ => return Err(UnknownPermission(name.to_owned())),
+            }
+        }
+        Ok(permissions)
+    }
+}
+
+/// Token authenticating client requests to the node RPC interface
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(NetworkEncode, NetworkDecode)]
+pub struct AuthToken {
+    /// Permissions granted by the token
+    pub permissions: Permissions,
+
+    /// Random nonce distinguishing tokens with the same permissions
+    pub nonce: u64,
+
+    /// HMAC-SHA256 tag of the permissions and nonce made with the node root key
+    pub mac: Slice32,
+}
+
+impl AuthToken {
+    /// Data authenticated by the token tag
+    pub fn signed_data(permissions: Permissions, nonce: u64) -> Vec<u8> {
+        let mut data = vec![];
+        // Encoding of plain data into a vector can't fail
+        permissions.strict_encode(&mut data).expect("in-memory encoding");
+        nonce.strict_encode(&mut data).expect("in-memory encoding");
+        data
+    }
+}
+
+impl Display for AuthToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let data = self.strict_serialize().map_err(|_| fmt::Error)?;
+        f.write_str(&data.to_hex())
+    }
+}
+
+/// Errors parsing RPC token
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum TokenError {
+    /// RPC token must be a hex string. Details: {0}
+    #[from]
+    Hex(bitcoin::hashes::hex::Error),
+
+    /// RPC token is malformed. Details: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+impl FromStr for AuthToken {
+    type Err = TokenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = Vec::<u8>::from_hex(s.trim())?;
+        Ok(AuthToken::strict_deserialize(data)?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AuthToken {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Envelope of the client request carrying RPC token
+#[derive(Clone, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{msg}")]
+pub struct RpcRequest {
+    /// Token authenticating the request; requests without token are rejected
+    pub token: Option<AuthToken>,
+
+    /// The request itself
+    pub msg: RpcMsg,
+}
+
+/// Errors authenticating client requests
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AuthError {
+    /// request is not authenticated; please provide RPC token with `--token` or `--token-file`
+    /// option
+    Unauthenticated,
+
+    /// RPC token is not issued by this node
+    InvalidToken,
+
+    /// RPC token does not grant {0} permission required for {1}
+    PermissionDenied(Permission, String),
+}
+
+impl ToRpcError for AuthError {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            AuthError::Unauthenticated => ErrorCode::Unauthenticated,
+            AuthError::InvalidToken => ErrorCode::InvalidToken,
+            AuthError::PermissionDenied(..) => ErrorCode::PermissionDenied,
+        }
+    }
+}
+
+impl From<AuthError> for RpcError {
+    fn from(err: AuthError) -> Self { err.to_rpc_error() }
+}
+
+impl RpcMsg {
+    /// Permission required for the client to perform the request
+    pub fn required_permission(&self) -> Permission {
+        match self {
+            RpcMsg::GetInfo
+            | RpcMsg::ListPeers(..)
+            | RpcMsg::ListChannels(..)
+            | RpcMsg::GetChannel(_)
+            | RpcMsg::GetTxDepth(_)
+            | RpcMsg::GetChainStatus
+            | RpcMsg::ListPendingTxs
+            | RpcMsg::ListFunds
+            | RpcMsg::ListPendingPsbts
+            | RpcMsg::ListFeePolicies
+            | RpcMsg::ListBans
+            | RpcMsg::ChannelHistory(_)
+            | RpcMsg::DescribeGraph
+            | RpcMsg::GetNodeInfo(_)
+            | RpcMsg::GetChannelInfo(_)
+            | RpcMsg::VerifyMessage(_) => Permission::Read,
+            RpcMsg::GetNewAddress(_) => Permission::Invoice,
+            _ => Permission::Admin,
+        }
+    }
+}
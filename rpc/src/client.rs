@@ -12,81 +12,58 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::net::SocketAddr;
 use std::str::FromStr;
-use std::thread::sleep;
-use std::time::Duration;
 
 use colored::Colorize;
-use internet2::ZmqType;
-use microservices::esb;
-use microservices::esb::BusId;
 
-use crate::{BusMsg, ClientId, Error, OptionDetails, RpcMsg, ServiceId};
-
-// We have just a single service bus (RPC), so we can use any id
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default, Display)]
-#[display("LNPRPC")]
-struct RpcBus;
-
-impl BusId for RpcBus {
-    type Address = ServiceId;
-}
-
-type Bus = esb::EndpointList<RpcBus>;
+use crate::transport::Transport;
+use crate::{AuthToken, ClientId, Error, OptionDetails, RpcEndpoint, RpcMsg, RpcRequest, ServiceId};
 
 #[repr(C)]
 pub struct Client {
     identity: ClientId,
     response_queue: Vec<RpcMsg>,
-    esb: esb::Controller<RpcBus, BusMsg, Handler>,
+    /// Whether replies are printed as JSON instead of the human-readable text
+    json_output: bool,
+    /// Token authenticating requests to the node
+    token: Option<AuthToken>,
+    transport: Transport,
 }
 
 impl Client {
+    /// Connects the node RPC interface, which is given either as a ZMQ socket of the RPC bus or
+    /// as a remote RPC endpoint `lnpr://<node_id>@<host>:<port>`
     pub fn with(connect: &str) -> Result<Self, Error> {
         use bitcoin::secp256k1::rand;
 
         debug!("RPC socket {}", connect);
 
         debug!("Setting up RPC client...");
-        let identity = rand::random();
-        let rpc_endpoint = match SocketAddr::from_str(connect) {
-            Ok(_) => format!("tcp://{}", connect),
-            Err(_) => format!("ipc://{}", connect),
-        };
-        let bus_config = esb::BusConfig::with_locator(
-            rpc_endpoint.parse().expect("Only ZMQ RPC is currently supported"),
-            Some(ServiceId::router()),
-        );
-        let esb = esb::Controller::with(
-            map! {
-                RpcBus => bus_config
-            },
-            Handler { identity: ServiceId::Client(identity) },
-            ZmqType::RouterConnect,
-        )?;
-
-        // We have to sleep in order for ZMQ to bootstrap
-        sleep(Duration::from_secs_f32(0.1));
-
-        Ok(Self { identity, response_queue: empty!(), esb })
+        let endpoint = RpcEndpoint::from_str(connect)?;
+        let (transport, identity) = Transport::connect(&endpoint, rand::random())?;
+
+        Ok(Self { identity, response_queue: empty!(), json_output: false, token: None, transport })
     }
 
     pub fn identity(&self) -> ClientId { self.identity }
 
+    /// Switches printing of the replies to JSON format without any text styling
+    pub fn set_json_output(&mut self, json_output: bool) { self.json_output = json_output; }
+
+    pub fn json_output(&self) -> bool { self.json_output }
+
+    /// Sets token authenticating all further requests to the node
+    pub fn set_token(&mut self, token: Option<AuthToken>) { self.token = token; }
+
     pub fn request(&mut self, daemon: ServiceId, req: RpcMsg) -> Result<(), Error> {
         debug!("Executing {}", req);
-        self.esb.send_to(RpcBus, daemon, BusMsg::Rpc(req))?;
-        Ok(())
+        let request = RpcRequest { token: self.token.clone(), msg: req };
+        self.transport.send(daemon, request)
     }
 
     pub fn response(&mut self) -> Result<RpcMsg, Error> {
-        if self.response_queue.is_empty() {
-            for (_, _, rep) in self.esb.recv_poll()? {
-                match rep {
-                    BusMsg::Rpc(msg) => self.response_queue.push(msg),
-                }
-            }
+        while self.response_queue.is_empty() {
+            self.response_queue.extend(self.transport.recv()?);
         }
         Ok(self.response_queue.pop().expect("We always have at least one element"))
     }
@@ -94,8 +71,11 @@ impl Client {
     pub fn report_failure(&mut self) -> Result<RpcMsg, Error> {
         match self.response()? {
             RpcMsg::Failure(fail) => {
-                eprintln!("{}: {}", "Request failure".bright_red(), fail.to_string().red());
-                Err(Error::Rpc(fail.into_microservice_failure().into()))
+                // Failures are reported in JSON by the caller handling the returned error
+                if !self.json_output {
+                    eprintln!("{}: {}", "Request failure".bright_red(), fail.to_string().red());
+                }
+                Err(Error::Failure(fail))
             }
             resp => Ok(resp),
         }
@@ -103,11 +83,27 @@ impl Client {
 
     pub fn report_response(&mut self) -> Result<(), Error> {
         let resp = self.report_failure()?;
-        println!("{:#}", resp);
+        self.print_reply(&resp)
+    }
+
+    /// Prints reply either in the human-readable form or as JSON object, depending on the output
+    /// format of the client
+    pub fn print_reply(&self, reply: &RpcMsg) -> Result<(), Error> {
+        if self.json_output {
+            print_json(reply)?;
+        } else {
+            println!("{:#}", reply);
+        }
         Ok(())
     }
 
-    pub fn report_progress(&mut self) -> Result<usize, Error> {
+    pub fn report_progress(&mut self) -> Result<usize, Error> { self.await_outcome(true) }
+
+    /// Waits for the final result of the request, printing only the result and skipping progress
+    /// reports
+    pub fn report_outcome(&mut self) -> Result<usize, Error> { self.await_outcome(false) }
+
+    fn await_outcome(&mut self, print_progress: bool) -> Result<usize, Error> {
         let mut counter = 0;
         let mut finished = false;
         while !finished {
@@ -116,15 +112,23 @@ impl Client {
             match self.report_failure()? {
                 // Failure is already covered by `report_response()`
                 RpcMsg::Progress(info) => {
-                    println!("{}", info);
+                    if print_progress && self.json_output {
+                        print_json(&RpcMsg::Progress(info))?;
+                    } else if print_progress {
+                        println!("{}", info);
+                    }
                     finished = false;
                 }
+                success @ RpcMsg::Success(_) if self.json_output => print_json(&success)?,
                 RpcMsg::Success(OptionDetails(Some(info))) => {
                     println!("{}{}", "Success: ".bright_green(), info);
                 }
                 RpcMsg::Success(OptionDetails(None)) => {
                     println!("{}", "Success".bright_green());
                 }
+                other if self.json_output => {
+                    return Err(Error::Other(format!("Unexpected server response: {}", other)));
+                }
                 other => {
                     eprintln!(
                         "{}: {}",
@@ -139,29 +143,14 @@ impl Client {
     }
 }
 
-pub struct Handler {
-    identity: ServiceId,
+#[cfg(feature = "serde")]
+fn print_json(reply: &RpcMsg) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(reply).map_err(|err| Error::Other(err.to_string()))?;
+    println!("{}", json);
+    Ok(())
 }
 
-impl esb::Handler<RpcBus> for Handler {
-    type Request = BusMsg;
-    type Error = Error;
-
-    fn identity(&self) -> ServiceId { self.identity.clone() }
-
-    fn handle(
-        &mut self,
-        _: &mut Bus,
-        _: RpcBus,
-        _: ServiceId,
-        _: BusMsg,
-    ) -> Result<(), Self::Error> {
-        // Cli does not receive replies for now
-        Ok(())
-    }
-
-    fn handle_err(&mut self, _: &mut Bus, err: esb::Error<ServiceId>) -> Result<(), Self::Error> {
-        // We simply propagate the error since it already has been reported
-        Err(err.into())
-    }
+#[cfg(not(feature = "serde"))]
+fn print_json(_: &RpcMsg) -> Result<(), Error> {
+    Err(Error::Other(s!("JSON output requires LNP RPC library compiled with `serde` feature")))
 }
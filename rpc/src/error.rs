@@ -12,9 +12,10 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use amplify::IoError;
 use microservices::{esb, rpc};
 
-use crate::ServiceId;
+use crate::{ErrorCode, RpcError, ServiceId, ToRpcError};
 
 #[derive(Debug, Display, From, Error)]
 #[display(doc_comments)]
@@ -28,6 +29,22 @@ pub enum Error {
     #[from]
     Rpc(rpc::Error),
 
+    /// failure reported by the node
+    #[display(inner)]
+    #[from]
+    Failure(RpcError),
+
+    /// I/O error: {0}
+    #[from(std::io::Error)]
+    Io(IoError),
+
+    /// data encoding error: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// remote RPC connection error: {0}
+    Remote(String),
+
     /// other error type with string explanation
     #[display(inner)]
     #[from(internet2::addr::NoOnionSupportError)]
@@ -47,7 +64,31 @@ impl From<Error> for rpc::Error {
     fn from(err: Error) -> Self {
         match err {
             Error::Rpc(err) => err,
-            err => rpc::Error::ServerFailure(rpc::Failure { code: 2000, info: err.to_string() }),
+            err => rpc::Error::ServerFailure(err.to_rpc_error().into_microservice_failure()),
+        }
+    }
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Esb(_) => ErrorCode::Bus,
+            Error::Rpc(rpc::Error::ServerFailure(failure)) => {
+                ErrorCode::with(failure.code).unwrap_or(ErrorCode::Internal)
+            }
+            Error::Rpc(_) => ErrorCode::Bus,
+            Error::Failure(failure) => failure.error_code().unwrap_or(ErrorCode::Internal),
+            Error::Io(_) | Error::Remote(_) => ErrorCode::Bus,
+            Error::Encoding(_) => ErrorCode::InvalidRequest,
+            Error::Other(_) => ErrorCode::Internal,
+        }
+    }
+
+    fn to_rpc_error(&self) -> RpcError {
+        match self {
+            Error::Failure(failure) => failure.clone(),
+            Error::Rpc(rpc::Error::ServerFailure(failure)) => RpcError::from(failure.clone()),
+            err => RpcError::new(err.error_code(), err),
         }
     }
 }
@@ -0,0 +1,317 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Node events published by lnpd to the subscribed clients.
+//!
+//! Events are published over ZMQ PUB socket, which is bound to [`LNP_NODE_EVENTS_SOCKET`] by
+//! default. Each event is a two-frame message: the topic followed by the event payload. Topic
+//! has the form `<encoding>.<category>.<kind>`, where
+//! - `encoding` is `strict` for the strict-encoded payload or `json` for the JSON payload;
+//! - `category` is one of `peer`, `channel`, `payment` or `wallet` (see [`EventCategory`]);
+//! - `kind` names the event within its category (see [`NodeEvent::kind`]).
+//!
+//! Each event is published in both encodings, and ZMQ subscriptions match the topic by prefix.
+//! Thus, subscribing to `json.channel.` delivers all channel events in JSON, while subscribing
+//! to `strict.` delivers all events strict-encoded.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use amplify::Slice32;
+use bitcoin::Txid;
+use internet2::{NodeAddr, ZMQ_CONTEXT};
+use lnp::p2p::legacy::ChannelId;
+#[cfg(feature = "serde")]
+use serde_with::DisplayFromStr;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::Error;
+
+/// Default ZMQ socket lnpd publishes node events to
+pub const LNP_NODE_EVENTS_SOCKET: &str = "127.0.0.1:62963";
+
+/// Encoding of the published event payload
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum EventEncoding {
+    #[display("strict")]
+    Strict,
+
+    #[display("json")]
+    Json,
+}
+
+/// Category of node events, allowing clients to subscribe to the related events only
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum EventCategory {
+    /// Connections with remote peers
+    #[display("peer")]
+    Peer,
+
+    /// Channel lifecycle, funding and closing
+    #[display("channel")]
+    Channel,
+
+    /// Settlement of the HTLCs offered to remote peers
+    #[display("payment")]
+    Payment,
+
+    /// Transactions of the funding wallet
+    #[display("wallet")]
+    Wallet,
+}
+
+/// Error parsing event category
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(
+    "unknown event category '{0}'; supported categories are peer, channel, payment and wallet"
+)]
+pub struct UnknownEventCategory(String);
+
+impl FromStr for EventCategory {
+    type Err = UnknownEventCategory;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "peer" => Ok(EventCategory::Peer),
+            "channel" => Ok(EventCategory::Channel),
+            "payment" => Ok(EventCategory::Payment),
+            "wallet" => Ok(EventCategory::Wallet),
+            _ => Err(UnknownEventCategory(s.to_owned())),
+        }
+    }
+}
+
+/// Event happened to the node
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(crate = "serde_crate", rename_all = "snake_case")
+)]
+pub enum NodeEvent {
+    /// Connection with the remote peer is established and initialized
+    #[display("peer_connected({remote_peer})")]
+    PeerConnected {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        remote_peer: NodeAddr,
+    },
+
+    /// Connection with the remote peer is lost
+    #[display("peer_disconnected({remote_peer})")]
+    PeerDisconnected {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        remote_peer: NodeAddr,
+    },
+
+    /// Remote peer has sent a warning, which does not close the connection or the channel
+    #[display("peer_warning({remote_peer}, {message})")]
+    PeerWarning {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        remote_peer: NodeAddr,
+        /// Channel the warning refers to, unless it relates to all the channels with the peer
+        #[cfg_attr(feature = "serde", serde_as(as = "Option<DisplayFromStr>"))]
+        channel_id: Option<ChannelId>,
+        message: String,
+    },
+
+    /// Channel has moved to another lifecycle stage
+    #[display("channel_lifecycle({channel_id}, {previous} -> {lifecycle})")]
+    ChannelLifecycle {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+        previous: String,
+        lifecycle: String,
+    },
+
+    /// Channel funding transaction got its first confirmation. Reported once per channel
+    /// daemon run, so it may be repeated after the node restart.
+    #[display("funding_confirmed({channel_id}, {txid})")]
+    FundingConfirmed {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        txid: Txid,
+        depth: u32,
+    },
+
+    /// Remote peer has published its commitment transaction, force-closing the channel
+    #[display("force_close_detected({channel_id}, {txid})")]
+    ForceCloseDetected {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        txid: Txid,
+        /// Whether the published commitment transaction is revoked, such that the channel
+        /// funds are claimed with a penalty transaction
+        revoked: bool,
+    },
+
+    /// Static channel backup file is updated after a channel was opened or closed. Contains the
+    /// whole encrypted backup, such that subscribers may store it off the node.
+    #[display("channel_backup({channels})")]
+    ChannelBackup {
+        /// Number of channels in the backup
+        channels: u32,
+        /// Content of the backup file in hex encoding
+        data: String,
+    },
+
+    /// Channel basepoints and revocation secrets were exported on the client request
+    #[display("secrets_exported({channel_id})")]
+    SecretsExported {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+    },
+
+    /// HTLC offered to the remote peer is fulfilled with the payment preimage
+    #[display("htlc_settled({channel_id}, {htlc_id})")]
+    HtlcSettled {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+        htlc_id: u64,
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        payment_hash: Slice32,
+    },
+
+    /// HTLC offered to the remote peer is failed
+    #[display("htlc_failed({channel_id}, {htlc_id})")]
+    HtlcFailed {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        channel_id: ChannelId,
+        htlc_id: u64,
+    },
+
+    /// Transaction of the watch-only funding wallet awaits signature by the operator, who has to
+    /// sign the PSBT and submit it back to the node
+    #[display("psbt_pending({txid}, {purpose})")]
+    PsbtPending {
+        #[cfg_attr(feature = "serde", serde_as(as = "DisplayFromStr"))]
+        txid: Txid,
+        /// What the transaction does: `funding` of a channel or `withdrawal`
+        purpose: String,
+        /// Unsigned PSBT in base64 encoding
+        psbt: String,
+    },
+}
+
+impl NodeEvent {
+    pub fn category(&self) -> EventCategory {
+        match self {
+            NodeEvent::PeerConnected { .. }
+            | NodeEvent::PeerDisconnected { .. }
+            | NodeEvent::PeerWarning { .. } => EventCategory::Peer,
+            NodeEvent::ChannelLifecycle { .. }
+            | NodeEvent::FundingConfirmed { .. }
+            | NodeEvent::ForceCloseDetected { .. }
+            | NodeEvent::ChannelBackup { .. }
+            | NodeEvent::SecretsExported { .. } => EventCategory::Channel,
+            NodeEvent::HtlcSettled { .. } | NodeEvent::HtlcFailed { .. } => EventCategory::Payment,
+            NodeEvent::PsbtPending { .. } => EventCategory::Wallet,
+        }
+    }
+
+    /// Name of the event within its category, used as the last component of the event topic
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NodeEvent::PeerConnected { .. } => "connected",
+            NodeEvent::PeerDisconnected { .. } => "disconnected",
+            NodeEvent::PeerWarning { .. } => "warning",
+            NodeEvent::ChannelLifecycle { .. } => "lifecycle",
+            NodeEvent::FundingConfirmed { .. } => "funding_confirmed",
+            NodeEvent::ForceCloseDetected { .. } => "force_close",
+            NodeEvent::ChannelBackup { .. } => "backup",
+            NodeEvent::SecretsExported { .. } => "secrets_exported",
+            NodeEvent::HtlcSettled { .. } => "htlc_settled",
+            NodeEvent::HtlcFailed { .. } => "htlc_failed",
+            NodeEvent::PsbtPending { .. } => "psbt_pending",
+        }
+    }
+
+    /// Topic the event is published under with the given payload encoding
+    pub fn topic(&self, encoding: EventEncoding) -> String {
+        format!("{}.{}.{}", encoding, self.category(), self.kind())
+    }
+
+    /// Serializes event payload with the given encoding
+    pub fn serialize(&self, encoding: EventEncoding) -> Result<Vec<u8>, Error> {
+        match encoding {
+            EventEncoding::Strict => {
+                self.strict_serialize().map_err(|err| Error::Other(err.to_string()))
+            }
+            #[cfg(feature = "serde")]
+            EventEncoding::Json => {
+                serde_json::to_vec(self).map_err(|err| Error::Other(err.to_string()))
+            }
+            #[cfg(not(feature = "serde"))]
+            EventEncoding::Json => Err(Error::Other(s!(
+                "JSON encoding requires LNP RPC library compiled with `serde` feature"
+            ))),
+        }
+    }
+}
+
+/// Client receiving strict-encoded node events published by lnpd
+pub struct EventSubscriber {
+    socket: zmq::Socket,
+}
+
+impl EventSubscriber {
+    /// Connects to the node event socket, subscribing to the events of the given categories. If
+    /// no categories are given, subscribes to all events.
+    pub fn with(connect: &str, categories: &[EventCategory]) -> Result<Self, Error> {
+        let events_endpoint = match SocketAddr::from_str(connect) {
+            Ok(_) => format!("tcp://{}", connect),
+            Err(_) => format!("ipc://{}", connect),
+        };
+        debug!("Subscribing to node events at {}", events_endpoint);
+        let zmq_err = |err: zmq::Error| Error::Other(err.to_string());
+        let socket = ZMQ_CONTEXT.socket(zmq::SUB).map_err(zmq_err)?;
+        socket.connect(&events_endpoint).map_err(zmq_err)?;
+        if categories.is_empty() {
+            let prefix = format!("{}.", EventEncoding::Strict);
+            socket.set_subscribe(prefix.as_bytes()).map_err(zmq_err)?;
+        }
+        for category in categories {
+            let prefix = format!("{}.{}.", EventEncoding::Strict, category);
+            socket.set_subscribe(prefix.as_bytes()).map_err(zmq_err)?;
+        }
+        Ok(EventSubscriber { socket })
+    }
+
+    /// Blocks until the next event is published or the timeout passes, returning `None` in the
+    /// latter case
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<NodeEvent>, Error> {
+        let timeout_ms = timeout.as_millis().min(i64::MAX as u128) as i64;
+        match self.socket.poll(zmq::POLLIN, timeout_ms) {
+            Ok(0) => Ok(None),
+            Ok(_) => self.recv().map(Some),
+            Err(err) => Err(Error::Other(err.to_string())),
+        }
+    }
+
+    /// Blocks until the next event is published
+    pub fn recv(&mut self) -> Result<NodeEvent, Error> {
+        let frames = self.socket.recv_multipart(0).map_err(|err| Error::Other(err.to_string()))?;
+        match frames.as_slice() {
+            [_, payload] => NodeEvent::strict_deserialize(payload)
+                .map_err(|err| Error::Other(format!("malformed node event: {}", err))),
+            _ => Err(Error::Other(format!(
+                "malformed node event: expected 2 message frames, got {}",
+                frames.len()
+            ))),
+        }
+    }
+}
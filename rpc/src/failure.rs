@@ -0,0 +1,308 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Structured failures reported to the RPC clients.
+//!
+//! Each failure carries a code from the stable [`ErrorCode`] registry, which clients may rely on
+//! for handling the failure programmatically, and optional JSON details. Details contain the
+//! originating daemon under `source` key and, for the errors of the channel workflows, the
+//! internal error number under `errno` key. Codes are grouped into [`ErrorClass`]es, which are
+//! reported by `lnp-cli` with distinct exit statuses.
+
+use std::io;
+
+use serde_json::{Map, Value};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::ServiceId;
+
+/// Stable registry of the failure codes reported to the RPC clients. Codes are never reused for
+/// a different kind of failure.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[display(doc_comments)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// internal node error
+    Internal = 1000,
+
+    /// communication between node daemons has failed
+    Bus = 1001,
+
+    /// node data storage has failed
+    Storage = 1002,
+
+    /// blockchain backend has not synced yet
+    ChainSyncing = 1003,
+
+    /// request is malformed or has invalid parameters
+    InvalidRequest = 2000,
+
+    /// request is not supported by the node or by the daemon it was sent to
+    NotSupported = 2001,
+
+    /// object referenced by the request is not known to the node
+    NotFound = 2002,
+
+    /// operation has timed out
+    Timeout = 3000,
+
+    /// remote peer can't be reached
+    PeerUnreachable = 4000,
+
+    /// remote peer has rejected the operation or violated the protocol
+    PeerRejected = 4001,
+
+    /// channel is not known to the node
+    ChannelNotFound = 5000,
+
+    /// operation is not possible at the current channel state
+    ChannelState = 5001,
+
+    /// operation violates the policy of the channel or the node
+    PolicyViolation = 5002,
+
+    /// not enough funds for the operation
+    InsufficientFunds = 6000,
+
+    /// channel funding has failed
+    Funding = 6001,
+
+    /// signer is not available or has failed to sign
+    SignerUnavailable = 7000,
+
+    /// signature is invalid
+    InvalidSignature = 7001,
+
+    /// signer is locked and must be unlocked with the passphrase
+    SignerLocked = 7002,
+
+    /// signer refuses to sign the transaction violating its signing policy
+    SignerPolicy = 7003,
+
+    /// request is not authenticated
+    Unauthenticated = 8001,
+
+    /// RPC token is not issued by this node
+    InvalidToken = 8002,
+
+    /// RPC token does not grant the permission required for the request
+    PermissionDenied = 8003,
+}
+
+impl ErrorCode {
+    /// Finds registered code by its number
+    pub fn with(code: u16) -> Option<ErrorCode> {
+        Some(match code {
+            1000 => ErrorCode::Internal,
+            1001 => ErrorCode::Bus,
+            1002 => ErrorCode::Storage,
+            1003 => ErrorCode::ChainSyncing,
+            2000 => ErrorCode::InvalidRequest,
+            2001 => ErrorCode::NotSupported,
+            2002 => ErrorCode::NotFound,
+            3000 => ErrorCode::Timeout,
+            4000 => ErrorCode::PeerUnreachable,
+            4001 => ErrorCode::PeerRejected,
+            5000 => ErrorCode::ChannelNotFound,
+            5001 => ErrorCode::ChannelState,
+            5002 => ErrorCode::PolicyViolation,
+            6000 => ErrorCode::InsufficientFunds,
+            6001 => ErrorCode::Funding,
+            7000 => ErrorCode::SignerUnavailable,
+            7001 => ErrorCode::InvalidSignature,
+            7002 => ErrorCode::SignerLocked,
+            7003 => ErrorCode::SignerPolicy,
+            8001 => ErrorCode::Unauthenticated,
+            8002 => ErrorCode::InvalidToken,
+            8003 => ErrorCode::PermissionDenied,
+            _ => return None,
+        })
+    }
+
+    pub fn class(self) -> ErrorClass {
+        match self {
+            ErrorCode::Internal
+            | ErrorCode::Bus
+            | ErrorCode::Storage
+            | ErrorCode::ChainSyncing => ErrorClass::Node,
+            ErrorCode::InvalidRequest | ErrorCode::NotSupported | ErrorCode::NotFound => {
+                ErrorClass::Request
+            }
+            ErrorCode::Timeout => ErrorClass::Timeout,
+            ErrorCode::PeerUnreachable | ErrorCode::PeerRejected => ErrorClass::Peer,
+            ErrorCode::ChannelNotFound
+            | ErrorCode::ChannelState
+            | ErrorCode::PolicyViolation => ErrorClass::Channel,
+            ErrorCode::InsufficientFunds | ErrorCode::Funding => ErrorClass::Funds,
+            ErrorCode::SignerUnavailable
+            | ErrorCode::InvalidSignature
+            | ErrorCode::SignerLocked
+            | ErrorCode::SignerPolicy => ErrorClass::Signer,
+            ErrorCode::Unauthenticated
+            | ErrorCode::InvalidToken
+            | ErrorCode::PermissionDenied => ErrorClass::Auth,
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self { code as u16 }
+}
+
+/// Classes of the failures, which are reported by `lnp-cli` with distinct exit statuses
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ErrorClass {
+    /// Failure of the node itself or of the connection to the node
+    Node,
+
+    /// Invalid or unsupported request
+    Request,
+
+    /// Failure to authenticate or authorize the request
+    Auth,
+
+    /// Failure related to the remote peer
+    Peer,
+
+    /// Failure related to the channel state or policy
+    Channel,
+
+    /// Failure of the channel funding
+    Funds,
+
+    /// Failure of the signer
+    Signer,
+
+    /// Operation timeout
+    Timeout,
+}
+
+impl ErrorClass {
+    /// Process exit status used by `lnp-cli` for the failures of this class. Status 2 is skipped,
+    /// since it is used for the command-line argument errors.
+    pub fn exit_status(self) -> i32 {
+        match self {
+            ErrorClass::Node => 1,
+            ErrorClass::Request => 3,
+            ErrorClass::Auth => 4,
+            ErrorClass::Peer => 5,
+            ErrorClass::Channel => 6,
+            ErrorClass::Funds => 7,
+            ErrorClass::Signer => 8,
+            ErrorClass::Timeout => 9,
+        }
+    }
+}
+
+/// Information about server-side failure returned through RPC API
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display("{message}", alt = "Server returned failure #{code}: {message}")]
+pub struct RpcError {
+    /// Failure code from [`ErrorCode`] registry
+    pub code: u16,
+
+    /// Human-readable description of the failure
+    pub message: String,
+
+    /// Additional information about the failure, which is always a JSON object
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none", default))]
+    pub details: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: ErrorCode, message: impl ToString) -> RpcError {
+        RpcError { code: code.into(), message: message.to_string(), details: None }
+    }
+
+    /// Adds detail to the failure, replacing the previous value under the same key
+    pub fn with_detail(mut self, key: &str, value: impl Into<Value>) -> RpcError {
+        let mut details = match self.details.take() {
+            Some(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        details.insert(key.to_owned(), value.into());
+        self.details = Some(Value::Object(details));
+        self
+    }
+
+    /// Daemon which has originated the failure
+    pub fn source(&self) -> Option<&str> {
+        self.details.as_ref().and_then(|details| details.get("source")).and_then(Value::as_str)
+    }
+
+    /// Records the daemon which has originated the failure, unless the failure already has one,
+    /// such that failures relayed by lnpd keep the daemon which has reported them
+    pub fn with_source(self, source: &ServiceId) -> RpcError {
+        if self.source().is_some() {
+            return self;
+        }
+        self.with_detail("source", source.to_string())
+    }
+
+    /// Registered code of the failure, if any
+    pub fn error_code(&self) -> Option<ErrorCode> { ErrorCode::with(self.code) }
+
+    /// Class of the failure; failures with unregistered codes are treated as node failures
+    pub fn class(&self) -> ErrorClass {
+        self.error_code().map(ErrorCode::class).unwrap_or(ErrorClass::Node)
+    }
+
+    pub fn into_microservice_failure(self) -> microservices::rpc::Failure {
+        microservices::rpc::Failure { code: self.code, info: self.message }
+    }
+}
+
+impl From<microservices::rpc::Failure> for RpcError {
+    fn from(failure: microservices::rpc::Failure) -> Self {
+        RpcError { code: failure.code, message: failure.info, details: None }
+    }
+}
+
+/// Errors which are reported to the RPC clients with a code from [`ErrorCode`] registry
+pub trait ToRpcError: std::error::Error {
+    fn error_code(&self) -> ErrorCode;
+
+    fn to_rpc_error(&self) -> RpcError { RpcError::new(self.error_code(), self) }
+}
+
+impl<E: ToRpcError> From<&E> for RpcError {
+    fn from(err: &E) -> Self { err.to_rpc_error() }
+}
+
+// Details are transferred between the daemons as JSON text
+impl StrictEncode for RpcError {
+    fn strict_encode<E: io::Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let details = self.details.as_ref().map(Value::to_string);
+        Ok(strict_encode_list!(e; self.code, self.message, details))
+    }
+}
+
+impl StrictDecode for RpcError {
+    fn strict_decode<D: io::Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        Ok(RpcError {
+            code: StrictDecode::strict_decode(&mut d)?,
+            message: StrictDecode::strict_decode(&mut d)?,
+            details: Option::<String>::strict_decode(&mut d)?
+                .map(|details| serde_json::from_str(&details))
+                .transpose()
+                .map_err(|err| {
+                    strict_encoding::Error::DataIntegrityError(format!(
+                        "invalid JSON failure details: {}",
+                        err
+                    ))
+                })?,
+        })
+    }
+}
@@ -40,14 +40,30 @@ extern crate serde_crate as serde;
 #[macro_use]
 extern crate serde_with;
 
+mod auth;
 mod client;
 mod error;
+mod events;
+mod failure;
 mod messages;
 mod service_id;
+mod transport;
 
+pub use auth::{
+    AuthError, AuthToken, Permission, Permissions, RpcRequest, TokenError, UnknownPermission,
+};
 pub use client::Client;
 pub use error::Error;
+pub use events::{
+    EventCategory, EventEncoding, EventSubscriber, NodeEvent, UnknownEventCategory,
+    LNP_NODE_EVENTS_SOCKET,
+};
+pub use failure::{ErrorClass, ErrorCode, RpcError, ToRpcError};
 pub use messages::*;
 pub use service_id::{ClientId, ClientName, ServiceId};
+pub use transport::{
+    RemoteListener, RemoteRequest, RemoteSession, RpcEndpoint, LNP_NODE_REMOTE_RPC_PORT,
+    LNP_NODE_REMOTE_RPC_SCHEME,
+};
 
 pub const LNP_NODE_RPC_SOCKET: &str = "127.0.0.1:62962";
@@ -20,20 +20,21 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use amplify::{Slice32, ToYamlString, Wrapper};
-use bitcoin::{secp256k1, Address};
-use internet2::addr::InetSocketAddr;
-use internet2::{NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
+use bitcoin::{secp256k1, Address, OutPoint, Txid};
+use internet2::addr::{InetAddr, InetSocketAddr};
+use internet2::{NodeAddr, RemoteSocketAddr};
 use lightning_invoice::Invoice;
 use lnp::channel::bolt::{AssetsBalance, ChannelState, CommonParams, PeerParams};
-use lnp::p2p::legacy::{ChannelId, ChannelType};
+use lnp::p2p::legacy::{ChannelId, ChannelType, ShortChannelId};
 use lnpbp::chain::AssetId;
 use microservices::rpc_connection;
 #[cfg(feature = "serde")]
 use serde_with::{DisplayFromStr, DurationSeconds, Same};
 use strict_encoding::{StrictDecode, StrictEncode};
 use wallet::address::AddressCompat;
+use wallet::scripts::PubkeyScript;
 
-use crate::{ClientId, ServiceId};
+use crate::{AuthToken, ClientId, Permissions, RpcError, RpcRequest, ServiceId};
 
 /// We need this wrapper type to be compatible with LNP Node having multiple message buses
 #[derive(Clone, Debug, Display, From, Api)]
@@ -44,52 +45,265 @@ pub(crate) enum BusMsg {
     #[display(inner)]
     #[from]
     Rpc(RpcMsg),
+
+    #[api(type = 5)]
+    #[display(inner)]
+    #[from]
+    Request(RpcRequest),
 }
 
 impl rpc_connection::Request for BusMsg {}
 
 /// RPC API requests between LNP Node daemons and clients.
+///
+/// Replies are serialized as JSON objects with a single key naming the reply type, like
+/// `{"channel_info": {...}}`; requests are not serializable.
 #[derive(Clone, Debug, Display, From)]
 #[derive(NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(crate = "serde_crate", rename_all = "snake_case")
+)]
 #[non_exhaustive]
 pub enum RpcMsg {
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("get_info()")]
     GetInfo,
 
-    #[display("list_peers()")]
-    ListPeers,
+    /// Requests lnpd for the peer connections, resetting their traffic statistics once they are
+    /// reported if the flag is set
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_peers({0}, {1}, {2})")]
+    ListPeers(PeerFilter, Pagination, bool),
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_channels({0}, {1})")]
+    ListChannels(ChannelFilter, Pagination),
 
-    #[display("list_channels()")]
-    ListChannels,
+    /// Requests lnpd for the current state of a single channel, including its lifecycle stage
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_channel({0})")]
+    GetChannel(ChannelId),
 
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("list_funds()")]
     ListFunds,
 
+    /// Requests routing fee policies set for the channels and the policies in effect for the
+    /// active channels
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_fee_policies()")]
+    ListFeePolicies,
+
+    /// Requests the funding wallet to issue a new address for deposits
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_new_address({0})")]
+    GetNewAddress(AddressType),
+
+    /// Requests to send funds from the funding wallet to an external address
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("withdraw({0})")]
+    Withdraw(Withdraw),
+
+    /// Requests transactions of the watch-only funding wallet which await signature by the
+    /// operator
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_pending_psbts()")]
+    ListPendingPsbts,
+
+    /// Provides consensus-serialized PSBT of a pending transaction signed by the operator with
+    /// the wallet holding the funding wallet keys
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("submit_psbt(...)")]
+    SubmitPsbt(Vec<u8>),
+
+    /// Requests number of confirmations of a transaction from the on-chain tracking service
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_tx_depth({0})")]
+    GetTxDepth(Txid),
+
+    /// Requests status of the blockchain backend used by the on-chain tracking service
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_chain_status()")]
+    GetChainStatus,
+
+    /// Requests unconfirmed transactions published by the node, which are rebroadcast by the
+    /// on-chain tracking service until they get mined
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_pending_txs()")]
+    ListPendingTxs,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("listen({0})")]
     Listen(RemoteSocketAddr),
 
+    /// Requests a new RPC token granting the given permissions
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("bake_token({0})")]
+    BakeToken(Permissions),
+
     // Node connectivity API
     // ---------------------
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("connect({0})")]
-    ConnectPeer(RemoteNodeAddr),
+    ConnectPeer(ConnectPeer),
+
+    /// Requests lnpd to close connections with the remote peer, freezing its channels. Channels
+    /// which funding is not yet signed are abandoned.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("disconnect({0})")]
+    DisconnectPeer(DisconnectPeer),
 
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("ping_peer()")]
     PingPeer,
 
+    /// Requests lnpd to reconnect the remote peer whenever the connection with it is lost, even
+    /// if the peer has no channels with the node
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("pin_peer({0})")]
+    PinPeer(secp256k1::PublicKey),
+
+    /// Cancels reconnection of the pinned remote peer, unless it has channels with the node
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("unpin_peer({0})")]
+    UnpinPeer(secp256k1::PublicKey),
+
+    /// Requests the remote peers banned by the node
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("list_bans()")]
+    ListBans,
+
+    /// Requests lnpd to ban the remote peer, disconnecting it and refusing its connections for
+    /// the ban duration. Unlike the automatic bans, peers with channels are banned as well.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("ban_peer({0})")]
+    BanPeer(BanPeer),
+
+    /// Lifts the ban of the remote peer
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("unban_peer({0})")]
+    UnbanPeer(secp256k1::PublicKey),
+
     // Channel API
     // -----------
     /// Requests creation of a new outbound channel by a client.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("create_channel({0})")]
     CreateChannel(CreateChannel),
 
+    /// Requests creation of multiple outbound channels funded by a single transaction, which
+    /// pays to the funding outputs of all channels.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("open_channels(...)")]
+    OpenChannels(Vec<CreateChannel>),
+
+    /// Requests closing of an active channel, either cooperatively or unilaterally.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("close_channel({0})")]
+    CloseChannel(CloseChannel),
+
+    /// Requests to abandon opening of a channel, which funding transaction is not signed yet.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("abort_channel({0})")]
+    AbortChannel(ChannelId),
+
+    /// Requests export of the channel state for the migration to another node.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("export_channel({0})")]
+    ExportChannel(ChannelId),
+
+    /// Provides funding transaction constructed by an external wallet for the channel created
+    /// with external funding.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("provide_funding({0})")]
+    ProvideFunding(ProvideFunding),
+
+    /// Requests import of the channel state exported from another node.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("import_channel(...)")]
+    ImportChannel(Vec<u8>),
+
+    /// Requests recovery of the channel funds from the static channel backup created by this
+    /// node.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("recover_channels(...)")]
+    RecoverChannels(Vec<u8>),
+
+    /// Requests history of the events processed by the channel daemon.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("channel_history({0})")]
+    ChannelHistory(ChannelId),
+
+    /// Requests the latest local commitment transaction of the channel and its second-stage HTLC
+    /// transactions, fully signed but not published, for the disaster recovery.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("dump_commitment({0})")]
+    DumpCommitment(ChannelId),
+
+    /// Requests the channel basepoints and the revocation secrets received from the remote peer,
+    /// for the audit and recovery tools. Refused unless the node is started with
+    /// `--unsafe-export`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("export_channel_secrets({0})")]
+    ExportChannelSecrets(ChannelId),
+
+    /// Sets routing fee policy announced for the channels in `channel_update` gossip messages
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("set_fee_policy({0})")]
+    SetFeePolicy(SetFeePolicy),
+
+    /// Updates limits and confirmation target of the fee estimation made by the node for its
+    /// funding, sweep and commitment transactions
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("set_fees({0})")]
+    SetFees(SetFees),
+
     // Can be issued from a `cli` to `routed`
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("send({0})")]
     Send(Send),
 
     // Can be issued from a `cli` to `routed`
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     #[display("pay_invoice({0})")]
     PayInvoice(PayInvoice),
 
+    /// Requests all nodes and channels of the network graph known to routed from the gossip
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("describe_graph()")]
+    DescribeGraph,
+
+    /// Requests a node of the network graph with its announced addresses and channels
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_node_info({0})")]
+    GetNodeInfo(secp256k1::PublicKey),
+
+    /// Requests a channel of the network graph with the routing policies announced for it
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("get_channel_info({0})")]
+    GetChannelInfo(ShortChannelId),
+
+    // Message signing API
+    // -------------------
+    /// Requests signd to sign the message with the node key, producing zbase32-encoded
+    /// recoverable signature compatible with `signmessage` of LND and c-lightning
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("sign_message(...)")]
+    SignMessage(String),
+
+    /// Requests signd to decrypt the master key with the passphrase, unlocking the signer
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("unlock(...)")]
+    Unlock(String),
+
+    /// Requests routed to recover the node id from the message signature and to check it against
+    /// the network graph or the public key provided with the request
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[display("verify_message({0})")]
+    VerifyMessage(VerifyMessage),
+
     // Responses to CLI
     // ----------------
     #[display("progress(\"{0}\")")]
@@ -101,7 +315,7 @@ pub enum RpcMsg {
 
     #[display("failure({0:#})")]
     #[from]
-    Failure(Failure),
+    Failure(RpcError),
 
     #[display("node_info({0})", alt = "{0:#}")]
     #[from]
@@ -117,15 +331,94 @@ pub enum RpcMsg {
 
     #[display("peer_list({0})", alt = "{0:#}")]
     #[from]
-    PeerList(List<NodeAddr>),
+    PeerList(PeerList),
 
     #[display("channel_list({0})", alt = "{0:#}")]
     #[from]
-    ChannelList(List<ChannelId>),
+    ChannelList(ChannelList),
+
+    #[display("channel_summary({0})", alt = "{0:#}")]
+    #[from]
+    ChannelSummary(ChannelSummary),
 
     #[display("funds_info({0})", alt = "{0:#}")]
     #[from]
     FundsInfo(FundsInfo),
+
+    #[display("fee_policies({0})", alt = "{0:#}")]
+    #[from]
+    FeePolicies(FeePolicyList),
+
+    #[display("new_address({0})", alt = "{0:#}")]
+    #[from]
+    NewAddress(NewAddress),
+
+    #[display("withdrawal({0})", alt = "{0:#}")]
+    #[from]
+    Withdrawal(Withdrawal),
+
+    #[display("pending_psbts({0})", alt = "{0:#}")]
+    #[from]
+    PendingPsbts(List<PendingPsbt>),
+
+    #[display("tx_depth({0})", alt = "{0:#}")]
+    #[from]
+    TxDepth(TxDepth),
+
+    #[display("chain_status({0})", alt = "{0:#}")]
+    #[from]
+    ChainStatus(ChainStatus),
+
+    #[display("pending_txs({0})", alt = "{0:#}")]
+    #[from]
+    PendingTxs(List<PendingTx>),
+
+    #[display("funding_preview({0})", alt = "{0:#}")]
+    #[from]
+    FundingPreview(FundingPreview),
+
+    #[display("channel_export(...)")]
+    ChannelExport(Vec<u8>),
+
+    #[display("channel_events({0})", alt = "{0:#}")]
+    #[from]
+    ChannelEvents(List<ChannelEvent>),
+
+    #[display("bans({0})", alt = "{0:#}")]
+    #[from]
+    Bans(List<BanInfo>),
+
+    #[display("commitment_dump({0})", alt = "{0:#}")]
+    #[from]
+    CommitmentDump(CommitmentDump),
+
+    // Secrets are never displayed, so they don't get into the logs
+    #[display("channel_secrets({0}, ...)")]
+    #[from]
+    ChannelSecrets(ChannelSecrets),
+
+    #[display("graph({0})", alt = "{0:#}")]
+    #[from]
+    Graph(GraphInfo),
+
+    #[display("graph_node({0})", alt = "{0:#}")]
+    #[from]
+    GraphNode(GraphNode),
+
+    #[display("graph_channel({0})", alt = "{0:#}")]
+    #[from]
+    GraphChannel(GraphChannel),
+
+    #[display("message_signature({0})")]
+    MessageSignature(String),
+
+    #[display("message_verification({0})", alt = "{0:#}")]
+    #[from]
+    MessageVerification(MessageVerification),
+
+    #[display("token({0})", alt = "{0}")]
+    #[from]
+    Token(AuthToken),
 }
 
 /// Request to create channel originating from a client
@@ -178,6 +471,27 @@ pub struct CreateChannel {
     /// The minimum value unencumbered by HTLCs for the counterparty to keep in
     /// the channel, in satoshis.
     pub channel_reserve: Option<u64>,
+
+    /// Script which must receive our funds during cooperative channel closing, committed upfront
+    /// with `option_upfront_shutdown_script`.
+    pub shutdown_script: Option<PubkeyScript>,
+
+    /// Start using the channel without waiting for the funding transaction confirmation, if the
+    /// remote peer agrees on that.
+    pub zero_conf: bool,
+
+    /// Maximal number of blocks the remote peer may require our funds to be timelocked for after
+    /// a unilateral channel close.
+    pub max_to_self_delay: Option<u16>,
+
+    /// Fund the channel from an external wallet instead of the node funding wallet. The channel
+    /// funding address and amount are reported to the client, which must provide the funding
+    /// transaction with `ProvideFunding` request.
+    pub external_funding: bool,
+
+    /// Return the prospective funding transaction instead of opening the channel. Neither the
+    /// remote peer is contacted nor the funding wallet outputs are reserved.
+    pub dry_run: bool,
 }
 
 impl CreateChannel {
@@ -213,6 +527,374 @@ impl CreateChannel {
     }
 }
 
+/// Weight of the cooperative closing transaction spending 2-of-2 funding output into two P2WPKH
+/// outputs, in weight units
+pub const CLOSING_TX_WEIGHT: u64 = 672;
+
+/// Request to close a channel originating from a client
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{channel_id}, force={force}, ...")]
+pub struct CloseChannel {
+    /// Permanent id of the channel to close
+    pub channel_id: ChannelId,
+
+    /// Close the channel unilaterally by publishing the latest local commitment transaction
+    /// instead of negotiating cooperative closing with the remote peer
+    pub force: bool,
+
+    /// Range of fees acceptable for the cooperative closing transaction. If absent, the range is
+    /// derived from the channel fee rate.
+    pub fee_range: Option<ClosingFeeRange>,
+
+    /// Address receiving our funds from the cooperative closing transaction. If absent, the
+    /// address committed upfront during the channel opening is used.
+    pub dest_address: Option<Address>,
+}
+
+/// Range of fees acceptable for a cooperative channel closing transaction
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{min_fee_sat}..{max_fee_sat} sat")]
+pub struct ClosingFeeRange {
+    /// Minimal fee we agree to pay/receive for the closing transaction, in satoshis
+    pub min_fee_sat: u64,
+
+    /// Maximal fee we agree to pay/receive for the closing transaction, in satoshis
+    pub max_fee_sat: u64,
+}
+
+impl ClosingFeeRange {
+    /// Constructs range containing only the fee of cooperative closing transaction paying the
+    /// given fee rate, in satoshis per virtual byte
+    pub fn with_feerate(sat_per_vbyte: u64) -> ClosingFeeRange {
+        let fee_sat = sat_per_vbyte * CLOSING_TX_WEIGHT / 4;
+        ClosingFeeRange { min_fee_sat: fee_sat, max_fee_sat: fee_sat }
+    }
+}
+
+/// Funding transaction for the channel funded from an external wallet, provided by a client
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{channel_id}, ...")]
+pub struct ProvideFunding {
+    /// Temporary id of the channel, as it was reported to the client
+    pub channel_id: ChannelId,
+
+    /// Consensus-serialized PSBT of the funding transaction, which must contain output paying
+    /// the exact funding amount to the channel funding address
+    pub psbt: Vec<u8>,
+}
+
+/// Request to connect to the remote peer
+#[derive(Clone, PartialEq, Eq, Debug, NetworkEncode, NetworkDecode)]
+pub struct ConnectPeer {
+    /// Node id of the remote peer
+    pub node_id: secp256k1::PublicKey,
+
+    /// Address of the remote peer. If absent, the address announced by the node in the gossip
+    /// or the address it was last connected at is used.
+    pub remote_addr: Option<RemoteSocketAddr>,
+
+    /// Number of seconds to wait for the connection to be established. If absent, the operating
+    /// system TCP connection timeout applies.
+    pub timeout: Option<u16>,
+}
+
+impl Display for ConnectPeer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.remote_addr {
+            Some(ref remote_addr) => write!(f, "{}@{}", self.node_id, remote_addr),
+            None => Display::fmt(&self.node_id, f),
+        }
+    }
+}
+
+/// Request to disconnect the remote peer
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{node_id}, permanent: {permanent}")]
+pub struct DisconnectPeer {
+    /// Node id of the remote peer
+    pub node_id: secp256k1::PublicKey,
+
+    /// Do not reconnect the peer automatically until it is connected on the client request
+    pub permanent: bool,
+}
+
+/// Request to ban the remote peer
+#[derive(Clone, PartialEq, Eq, Debug, NetworkEncode, NetworkDecode)]
+pub struct BanPeer {
+    /// Node id of the remote peer
+    pub node_id: secp256k1::PublicKey,
+
+    /// Number of seconds the peer is banned for; the node default is used if absent
+    pub duration: Option<u64>,
+}
+
+impl Display for BanPeer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.duration {
+            Some(duration) => write!(f, "{}, {} s", self.node_id, duration),
+            None => Display::fmt(&self.node_id, f),
+        }
+    }
+}
+
+/// Request to verify the message signature
+#[derive(Clone, PartialEq, Eq, Debug, NetworkEncode, NetworkDecode)]
+pub struct VerifyMessage {
+    /// Signed message
+    pub message: String,
+
+    /// zbase32-encoded recoverable signature of the message
+    pub signature: String,
+
+    /// Public key expected to sign the message. If absent, the signature is valid only if its
+    /// signer is a node known from the gossip.
+    pub pubkey: Option<secp256k1::PublicKey>,
+}
+
+impl Display for VerifyMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.pubkey {
+            Some(pubkey) => write!(f, "{}, signed by {}", self.signature, pubkey),
+            None => Display::fmt(&self.signature, f),
+        }
+    }
+}
+
+/// Page of the items requested by a listing request. Items are sorted by lnpd, such that
+/// subsequent requests with increasing offsets page through the whole listing.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default, NetworkEncode, NetworkDecode)]
+pub struct Pagination {
+    /// Number of the items to skip
+    pub offset: u32,
+
+    /// Maximal number of the items to return; all remaining items are returned if absent
+    pub limit: Option<u32>,
+}
+
+impl Display for Pagination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Some(limit) => write!(f, "offset {}, limit {}", self.offset, limit),
+            None => write!(f, "offset {}, no limit", self.offset),
+        }
+    }
+}
+
+impl Pagination {
+    /// Takes the requested page out of the listing items. The page is shorter than the limit,
+    /// or empty, if the listing has not enough items past the offset.
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        let limit = self.limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+        items.into_iter().skip(self.offset as usize).take(limit).collect()
+    }
+}
+
+/// Filter applied by lnpd to the peer listing
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default, NetworkEncode, NetworkDecode)]
+pub struct PeerFilter {
+    /// List only the connection with the remote peer having this node id
+    pub remote_node: Option<secp256k1::PublicKey>,
+
+    /// List only the connections established at or after this UNIX timestamp
+    pub since: Option<u64>,
+
+    /// List only the connections established at or before this UNIX timestamp
+    pub until: Option<u64>,
+
+    /// List only the incoming connections accepted at this listening socket
+    pub listener: Option<InetSocketAddr>,
+}
+
+impl Display for PeerFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let timestamp = |t: Option<u64>| t.map(|t| t.to_string()).unwrap_or_else(|| s!("-"));
+        match self.remote_node {
+            Some(remote_node) => write!(f, "peer {}, ", remote_node)?,
+            None => f.write_str("all peers, ")?,
+        }
+        write!(f, "since {} until {}", timestamp(self.since), timestamp(self.until))?;
+        if let Some(listener) = self.listener {
+            write!(f, " at listener {}", listener)?;
+        }
+        Ok(())
+    }
+}
+
+impl PeerFilter {
+    /// Checks whether the connection passes the filter
+    pub fn matches(&self, peer: &PeerInfo) -> bool {
+        self.remote_node.map_or(true, |node_id| peer.remote_id.contains(&node_id))
+            && self.since.map_or(true, |since| peer.since >= since)
+            && self.until.map_or(true, |until| peer.since <= until)
+            && self.listener.map_or(true, |listener| {
+                peer.direction == ConnectionDirection::Inbound
+                    && peer.local_socket == Some(listener)
+            })
+    }
+
+    /// Checks whether the peer being reconnected passes the filter. Reconnecting peers are
+    /// filtered by node id only, since they have no established connection; they are never
+    /// listed when filtering by listener, since the node reconnects them itself.
+    pub fn matches_reconnecting(&self, peer: &ReconnectInfo) -> bool {
+        self.remote_node.map_or(true, |node_id| peer.node_id == node_id)
+            && self.listener.is_none()
+    }
+}
+
+/// Filter applied by lnpd to the channel listing
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, NetworkEncode, NetworkDecode)]
+pub struct ChannelFilter {
+    /// List only the channels with the remote peer having this node id
+    pub remote_node: Option<secp256k1::PublicKey>,
+
+    /// List only the channels at this lifecycle stage, matched case-insensitively
+    pub lifecycle: Option<String>,
+}
+
+impl Display for ChannelFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.remote_node {
+            Some(remote_node) => write!(f, "peer {}, ", remote_node)?,
+            None => f.write_str("all peers, ")?,
+        }
+        match self.lifecycle {
+            Some(ref lifecycle) => write!(f, "stage {}", lifecycle),
+            None => f.write_str("all stages"),
+        }
+    }
+}
+
+impl ChannelFilter {
+    /// Checks whether the channel passes the filter
+    pub fn matches(&self, channel: &ChannelSummary) -> bool {
+        self.remote_node.map_or(true, |node_id| channel.remote_node == Some(node_id))
+            && self.lifecycle.as_ref().map_or(true, |lifecycle| {
+                channel.lifecycle.eq_ignore_ascii_case(lifecycle)
+            })
+    }
+}
+
+/// Routing fee policy of a channel, announced to the network in `channel_update` gossip
+/// messages
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{base_msat} msat + {proportional_millionths} ppm, cltv delta {cltv_delta}")]
+pub struct FeePolicy {
+    /// Fee charged for each forwarded HTLC, in millisatoshis
+    pub base_msat: u32,
+
+    /// Fee charged for each million of forwarded millisatoshis
+    pub proportional_millionths: u32,
+
+    /// Number of blocks the incoming HTLC timelock must exceed the forwarded HTLC timelock by
+    pub cltv_delta: u16,
+
+    /// Minimal amount of the forwarded HTLC, in millisatoshis
+    pub htlc_min_msat: u64,
+
+    /// Maximal amount of the forwarded HTLC, in millisatoshis. If absent, the channel capacity
+    /// is announced.
+    pub htlc_max_msat: Option<u64>,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        FeePolicy {
+            base_msat: 1000,
+            proportional_millionths: 1,
+            cltv_delta: 40,
+            htlc_min_msat: 1000,
+            htlc_max_msat: None,
+        }
+    }
+}
+
+/// Channels which a routing fee policy is set for
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+pub enum PolicyScope {
+    /// Single channel
+    #[display("{0}")]
+    Channel(ChannelId),
+
+    /// All channels with the remote peer
+    #[display("{0}")]
+    Peer(secp256k1::PublicKey),
+
+    /// All channels of the node
+    #[display("all")]
+    All,
+}
+
+/// Error parsing policy scope
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(
+    "invalid policy scope '{0}'; the scope must be `all`, node id of the remote peer or channel id"
+)]
+pub struct UnknownPolicyScope(String);
+
+impl FromStr for PolicyScope {
+    type Err = UnknownPolicyScope;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(PolicyScope::All);
+        }
+        secp256k1::PublicKey::from_str(s)
+            .map(PolicyScope::Peer)
+            .or_else(|_| ChannelId::from_str(s).map(PolicyScope::Channel))
+            .map_err(|_| UnknownPolicyScope(s.to_owned()))
+    }
+}
+
+/// Request to set routing fee policy for the channels
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{scope}, {policy}")]
+pub struct SetFeePolicy {
+    /// Channels the policy is set for. Policy set for a single channel takes precedence over
+    /// the policy of its remote peer, which in turn takes precedence over the policy set for
+    /// all channels.
+    pub scope: PolicyScope,
+
+    pub policy: FeePolicy,
+}
+
+/// Request to update the fee estimation settings; the settings which are not given are kept
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{floor_per_kw:?}, {ceiling_per_kw:?}, {target_blocks:?}")]
+pub struct SetFees {
+    /// Minimal estimated feerate, in satoshis per kilo-weight unit
+    pub floor_per_kw: Option<u32>,
+
+    /// Maximal estimated feerate, in satoshis per kilo-weight unit
+    pub ceiling_per_kw: Option<u32>,
+
+    /// Number of blocks within which the node transactions should get mined
+    pub target_blocks: Option<u16>,
+}
+
+/// Request to send funds from the funding wallet to an external address
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{address}, ...")]
+pub struct Withdraw {
+    /// Address receiving the funds
+    pub address: Address,
+
+    /// Amount to send, in satoshis. If absent, all funds which are not reserved for the channel
+    /// funding are sent, with the fee deducted from the sent amount.
+    pub amount_sat: Option<u64>,
+
+    /// Fee rate in satoshi per 1000-weight. If absent, the fee rate estimated by the node is
+    /// used.
+    pub feerate_per_kw: Option<u32>,
+
+    /// Return the unsigned transaction instead of signing and publishing it
+    pub dry_run: bool,
+
+    /// Token confirming the withdrawal of the amount exceeding the withdrawal limit of the
+    /// signer
+    pub confirmation: Option<String>,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Display)]
 #[display("{invoice}, {channel_id}")]
 pub struct PayInvoice {
@@ -256,7 +938,13 @@ pub struct Send {
 #[display(NodeInfo::to_yaml_string)]
 pub struct NodeInfo {
     pub node_id: secp256k1::PublicKey,
+    /// Version of the node software
+    pub version: String,
+    /// Features advertised by the node to the remote peers
+    pub features: Vec<String>,
     pub listens: Vec<RemoteSocketAddr>,
+    /// Onion address of the node, once its onion service is created through Tor control port
+    pub onion_address: Option<String>,
     #[serde_as(as = "DurationSeconds")]
     pub uptime: Duration,
     pub since: u64,
@@ -264,6 +952,49 @@ pub struct NodeInfo {
     pub peers: Vec<NodeAddr>,
     #[serde_as(as = "Vec<DisplayFromStr>")]
     pub channels: Vec<ChannelId>,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub reaped_channels: Vec<ChannelId>,
+    pub rejected_channels: u64,
+    /// Number of incoming connections served by the peer listeners
+    pub inbound_peers: u32,
+    /// Number of incoming connections refused by the peer listeners for exceeding the rate of
+    /// their IP address or the limit of concurrent connections, or since their IP address is
+    /// banned
+    pub rejected_connections: u64,
+    /// Number of remote peers without channels disconnected to make room for new incoming
+    /// connections
+    pub evicted_peers: u64,
+    /// Number of incoming connections closed for not completing BOLT-8 handshake in time
+    pub handshake_timeouts: u64,
+    /// Number of incoming connections closed for not sending `init` message in time after the
+    /// handshake
+    pub init_timeouts: u64,
+    /// Number of incoming connections closed for sending a message exceeding the maximal BOLT-8
+    /// message length
+    pub oversized_messages: u64,
+    /// Number of peer daemons which have reported live connection with their remote peers
+    pub connected_peers: u32,
+    /// Number of channels at each lifecycle stage, as reported by the channel daemons
+    pub channel_stages: BTreeMap<String, u32>,
+    /// Type of the blockchain backend used by watchd
+    pub chain_backend: Option<String>,
+    /// Blockchain height known to watchd, if it has already synced with the backend
+    pub sync_height: Option<u32>,
+    /// Error of the last watchd request to the blockchain backend, if it has failed, which
+    /// indicates that the backend is unreachable
+    pub chain_error: Option<String>,
+    /// Progress of the compact block filter synchronization, if watchd uses such backend
+    pub chain_sync: Option<SyncProgress>,
+    /// Funding wallet balance in mined outputs, if the wallet was able to scan the blockchain
+    pub confirmed_balance_sat: Option<u64>,
+    /// Funding wallet balance in outputs which are not mined yet
+    pub unconfirmed_balance_sat: Option<u64>,
+    /// Whether the signer is locked and awaits for the passphrase to sign transactions, if
+    /// signd has reported its status
+    pub signer_locked: Option<bool>,
+    /// Problems gathering the information, like daemons which have not replied in time, in
+    /// which case the information is partial
+    pub warnings: Vec<String>,
 }
 
 #[cfg_attr(feature = "serde", serde_as)]
@@ -277,15 +1008,126 @@ pub struct PeerInfo {
     pub local_socket: Option<InetSocketAddr>,
     #[serde_as(as = "Vec<DisplayFromStr>")]
     pub remote_socket: Vec<InetSocketAddr>,
+    /// Whether the connection was initiated by the remote peer or by the local node
+    pub direction: ConnectionDirection,
     #[serde_as(as = "DurationSeconds")]
     pub uptime: Duration,
     pub since: u64,
+    /// Features supported by both the local node and the remote peer, once the remote peer has
+    /// sent its `init` message
+    pub features: Vec<String>,
     pub messages_sent: usize,
     pub messages_received: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Round-trip time of the last ping answered by the remote peer, in milliseconds
+    pub ping_rtt_ms: Option<u64>,
     #[serde_as(as = "HashSet<DisplayFromStr>")]
     pub channels: HashSet<Slice32>,
     pub connected: bool,
     pub awaits_pong: bool,
+    /// Number of the messages awaiting to be written to the remote peer
+    pub queued_messages: usize,
+    /// Number of the gossip messages dropped since the remote peer did not read them in time
+    pub dropped_gossip: u64,
+    /// Traffic with the remote peer by the message type, counted since the connection was
+    /// established or since the statistics were last reset
+    pub message_stats: BTreeMap<String, MessageStats>,
+    /// UNIX timestamp of the last message written to the remote peer
+    pub last_sent: Option<u64>,
+    /// UNIX timestamp of the last message received from the remote peer
+    pub last_received: Option<u64>,
+    /// Number of the connections with the remote peer which have completed the handshake since
+    /// lnpd was started or since the statistics were last reset
+    pub handshakes: u32,
+    /// Number of the lost connections with the remote peer by the reason of the disconnection
+    pub disconnects: BTreeMap<String, u64>,
+}
+
+/// Number and total size of the messages of a single type exchanged with the remote peer
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct MessageStats {
+    pub sent: u64,
+    pub received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Connections of the node with the remote peers
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(PeerList::to_yaml_string)]
+pub struct PeerList {
+    /// Established connections
+    pub connected: Vec<PeerInfo>,
+    /// Remote peers which lnpd is reconnecting after the connection loss
+    pub reconnecting: Vec<ReconnectInfo>,
+    /// Number of the established connections passing the listing filter, including the ones
+    /// outside of the requested page
+    pub total_count: u32,
+}
+
+/// Remote peer which lnpd is reconnecting after the connection loss
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{node_id}: {attempts} attempts")]
+pub struct ReconnectInfo {
+    pub node_id: secp256k1::PublicKey,
+    /// Address used for the reconnection attempts, unless it is unknown
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub remote_socket: Option<InetSocketAddr>,
+    /// Whether the peer is reconnected even if it has no channels with the node
+    pub pinned: bool,
+    /// Number of the reconnection attempts made so far
+    pub attempts: u32,
+    /// Time until the next reconnection attempt
+    #[serde_as(as = "DurationSeconds")]
+    pub next_attempt: Duration,
+    /// UNIX timestamp of the last successful connection with the peer
+    pub last_connected: Option<u64>,
+    /// Number of the connections with the peer which have completed the handshake since lnpd
+    /// was started or since the statistics were last reset
+    pub handshakes: u32,
+    /// Number of the lost connections with the peer by the reason of the disconnection
+    pub disconnects: BTreeMap<String, u64>,
+}
+
+/// Remote peer banned by the node
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{node_id}: {reason}")]
+pub struct BanInfo {
+    pub node_id: secp256k1::PublicKey,
+    /// Address which incoming connections are refused from, if the peer was banned on a
+    /// connection. IPv6 addresses are banned with their /64 network.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub address: Option<InetAddr>,
+    /// UNIX timestamp after which the ban is lifted
+    pub until: u64,
+    /// Time remaining until the ban is lifted
+    #[serde_as(as = "DurationSeconds")]
+    pub remaining: Duration,
+    pub reason: String,
+}
+
+/// Direction in which a peer connection was established
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum ConnectionDirection {
+    /// Connection initiated by the remote peer
+    #[display("in")]
+    Inbound,
+
+    /// Connection initiated by the local node
+    #[display("out")]
+    Outbound,
 }
 
 pub type RemotePeerMap<T> = BTreeMap<NodeAddr, T>;
@@ -297,6 +1139,169 @@ pub type RemotePeerMap<T> = BTreeMap<NodeAddr, T>;
 pub struct ChannelInfo {
     pub state: ChannelState,
     pub remote_peer: Option<NodeAddr>,
+    /// Whether the channel is being cooperatively closed and does not accept new payments
+    pub closing: bool,
+    /// Number of funding transaction confirmations required before the channel can be used
+    pub minimum_depth: u32,
+    /// Number of revoked channel states which justice data are not yet accepted by all
+    /// watchtowers
+    pub tower_pending: u32,
+    /// The last error uploading justice data to a watchtower, if some uploads are still retried
+    pub tower_error: Option<String>,
+    /// Routing fee policy announced for the channel, once the channel is active
+    pub fee_policy: Option<FeePolicy>,
+}
+
+/// Brief information about a channel, reported in the channel listing
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(ChannelSummary::to_yaml_string)]
+pub struct ChannelSummary {
+    /// Permanent channel id, or temporary id if the channel has not reached the funding stage
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel_id: ChannelId,
+    /// Node id of the remote peer
+    pub remote_node: Option<secp256k1::PublicKey>,
+    /// Lifecycle stage of the channel
+    pub lifecycle: String,
+    pub capacity_sat: u64,
+    pub local_balance_msat: u64,
+    pub remote_balance_msat: u64,
+    /// Number of HTLCs offered and received over the channel which are not yet resolved
+    pub pending_htlcs: u32,
+    /// Funding transaction output, once the channel has permanent id
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub funding_outpoint: Option<OutPoint>,
+    /// Number of funding transaction confirmations reported by watchd since the channel daemon
+    /// has started. Confirmations are not tracked past the minimum depth.
+    pub confirmations: Option<u32>,
+    /// Number of funding transaction confirmations required before the channel can be used
+    pub minimum_depth: u32,
+}
+
+/// Page of the channel listing
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(ChannelList::to_yaml_string)]
+pub struct ChannelList {
+    pub channels: Vec<ChannelSummary>,
+    /// Number of the channels passing the listing filter, including the ones outside of the
+    /// requested page
+    pub total_count: u32,
+}
+
+/// Latest local commitment transaction of a channel with its second-stage HTLC transactions,
+/// fully signed and ready to be published
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(CommitmentDump::to_yaml_string)]
+pub struct CommitmentDump {
+    /// Raw commitment transaction in hex encoding
+    pub commitment_tx: String,
+    /// Raw HTLC-timeout and HTLC-success transactions spending HTLC outputs of the commitment
+    /// transaction, in hex encoding. HTLC-timeout transactions can be published only once the
+    /// HTLC expires.
+    pub htlc_txs: Vec<String>,
+}
+
+/// Channel keys and revocation secrets exported for the audit and recovery tools. Contains no
+/// private keys of the node, but the revocation secrets allow to claim all channel funds if the
+/// remote peer publishes a revoked commitment transaction.
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{channel_id}")]
+pub struct ChannelSecrets {
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel_id: ChannelId,
+    /// Number of the current commitment transaction
+    pub commitment_number: u64,
+    /// Our basepoints, confirmed by the signer to be derived from the node master key
+    pub local_basepoints: ChannelBasepoints,
+    /// Basepoints of the remote peer
+    pub remote_basepoints: ChannelBasepoints,
+    /// Per-commitment point of the current remote commitment transaction
+    #[serde_as(as = "DisplayFromStr")]
+    pub remote_per_commitment_point: secp256k1::PublicKey,
+    /// Per-commitment secrets revealed by the remote peer for its revoked commitment
+    /// transactions
+    pub revocation_secrets: Vec<RevocationSecret>,
+}
+
+/// Public keys from which the keys of the channel transactions of one of the peers are derived
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{funding_pubkey}, ...")]
+pub struct ChannelBasepoints {
+    #[serde_as(as = "DisplayFromStr")]
+    pub funding_pubkey: secp256k1::PublicKey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub revocation_basepoint: secp256k1::PublicKey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub payment_basepoint: secp256k1::PublicKey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub delayed_payment_basepoint: secp256k1::PublicKey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub htlc_basepoint: secp256k1::PublicKey,
+}
+
+/// Per-commitment secret revealed by the remote peer with `revoke_and_ack` message
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{commitment_txid}")]
+pub struct RevocationSecret {
+    /// Id of the revoked remote commitment transaction
+    #[serde_as(as = "DisplayFromStr")]
+    pub commitment_txid: Txid,
+    /// Per-commitment secret of the revoked commitment transaction in hex encoding
+    pub per_commitment_secret: String,
+}
+
+/// Record of a message processed or sent by a channel daemon, persisted in the channel history
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+#[display("{timestamp} {lifecycle} {direction} {message} {service}: {outcome}")]
+pub struct ChannelEvent {
+    /// UNIX timestamp of the event, in seconds
+    pub timestamp: u64,
+
+    /// Lifecycle stage of the channel at the moment of the event
+    pub lifecycle: String,
+
+    /// Type of the message
+    pub message: String,
+
+    /// Whether the message was received or sent by the channel daemon
+    pub direction: EventDirection,
+
+    /// Daemon or remote peer which has sent the message or to which the message was sent
+    #[serde_as(as = "DisplayFromStr")]
+    pub service: ServiceId,
+
+    /// State of the channel workflow after the message was processed, or an error which has
+    /// prevented processing or sending the message
+    pub outcome: String,
+}
+
+/// Direction of a message recorded in the channel history
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum EventDirection {
+    /// Message received by the channel daemon
+    #[display("in")]
+    Inbound,
+
+    /// Message sent by the channel daemon to the remote peer
+    #[display("out")]
+    Outbound,
 }
 
 #[cfg_attr(feature = "serde", serde_as)]
@@ -308,6 +1313,337 @@ pub struct FundsInfo {
     pub bitcoin_funds: BTreeMap<AddressCompat, u64>,
     pub asset_funds: AssetsBalance,
     pub next_address: Address,
+    /// All outputs managed by the funding wallet, including the reserved ones
+    pub utxos: Vec<UtxoInfo>,
+    /// Balance of the mined outputs available for channel funding
+    pub confirmed_sat: u64,
+    /// Balance of the outputs available for channel funding which are not mined yet
+    pub unconfirmed_sat: u64,
+    /// Balance of the outputs reserved for the funding transactions of the channels being
+    /// opened
+    pub reserved_sat: u64,
+    /// Off-chain balances of the channels reported by the channel daemons
+    pub channel_balances: Vec<ChannelBalance>,
+}
+
+/// Routing fee policies of the node
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(FeePolicyList::to_yaml_string)]
+pub struct FeePolicyList {
+    /// Policy of the channels which have no policy set for them or for their remote peer
+    pub default: FeePolicy,
+    /// Policies set for all channels with the remote peers
+    #[serde_as(as = "BTreeMap<DisplayFromStr, Same>")]
+    pub peers: BTreeMap<secp256k1::PublicKey, FeePolicy>,
+    /// Policies in effect for the active channels
+    pub channels: Vec<ChannelFeePolicy>,
+}
+
+/// Routing fee policy in effect for an active channel
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{channel_id}: {policy}")]
+pub struct ChannelFeePolicy {
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel_id: ChannelId,
+    pub remote_node: secp256k1::PublicKey,
+    /// Scope of the policy applied to the channel
+    #[serde_as(as = "DisplayFromStr")]
+    pub scope: PolicyScope,
+    pub policy: FeePolicy,
+    /// UNIX timestamp of the last `channel_update` message announcing the channel policy
+    pub announced: Option<u64>,
+    /// Whether announcement of the changed policy is postponed by the gossip rate limit
+    pub pending: bool,
+}
+
+/// Type of the address issued by the funding wallet
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum AddressType {
+    /// Native segwit v0 address
+    #[display("bech32")]
+    Bech32,
+
+    /// Segwit v1 address
+    #[display("taproot")]
+    Taproot,
+}
+
+/// Error parsing address type
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("unknown address type '{0}'; supported types are bech32 and taproot")]
+pub struct UnknownAddressType(String);
+
+impl FromStr for AddressType {
+    type Err = UnknownAddressType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bech32" => Ok(AddressType::Bech32),
+            "taproot" => Ok(AddressType::Taproot),
+            _ => Err(UnknownAddressType(s.to_owned())),
+        }
+    }
+}
+
+/// Address issued by the funding wallet for deposits
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(NewAddress::to_yaml_string)]
+pub struct NewAddress {
+    pub address: Address,
+    /// Derivation path of the address key relative to the wallet account
+    pub derivation: String,
+    /// Number of the issued addresses which have not received funds yet, including this one
+    pub unused_issued: u32,
+    /// Warning about too many issued addresses which have not received funds
+    pub warning: Option<String>,
+}
+
+/// Transaction sending funds from the funding wallet to an external address
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(Withdrawal::to_yaml_string)]
+pub struct Withdrawal {
+    #[serde_as(as = "DisplayFromStr")]
+    pub txid: Txid,
+    pub amount_sat: u64,
+    pub fee_sat: u64,
+    /// Consensus-serialized unsigned PSBT in hex encoding, provided for a dry run only
+    pub psbt: Option<String>,
+}
+
+/// Transaction of the watch-only funding wallet awaiting signature by the operator
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{txid}: {purpose}")]
+pub struct PendingPsbt {
+    #[serde_as(as = "DisplayFromStr")]
+    pub txid: Txid,
+    /// What the transaction does: `funding` of a channel or `withdrawal`
+    pub purpose: String,
+    /// Unsigned PSBT in base64 encoding
+    pub psbt: String,
+}
+
+/// Unconfirmed transaction published by the node, which is rebroadcast until it gets mined
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+#[display("{txid}: {owner}")]
+pub struct PendingTx {
+    #[serde_as(as = "DisplayFromStr")]
+    pub txid: Txid,
+
+    /// Daemon which has published the transaction and is notified about its eviction
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: ServiceId,
+
+    /// Time since the transaction was published for the first time, in seconds
+    pub age: u64,
+
+    /// Feerate of the transaction, if known, in satoshi per kilo-weight unit
+    pub feerate_per_kw: Option<u32>,
+
+    /// Number of times the transaction was rebroadcast
+    pub rebroadcasts: u32,
+
+    /// Whether the transaction was found missing from the mempool of the blockchain backend
+    pub evicted: bool,
+}
+
+/// Prospective funding transaction of a channel opened as a dry run
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(FundingPreview::to_yaml_string)]
+pub struct FundingPreview {
+    /// Unsigned PSBT in base64 encoding. Its funding output pays to a placeholder script, since
+    /// the channel funding script is known only once the remote peer accepts the channel.
+    pub psbt: String,
+    pub funding_sat: u64,
+    /// Funding wallet outputs spent by the transaction
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub inputs: Vec<OutPoint>,
+    /// Total amount of the spent outputs
+    pub input_sat: u64,
+    /// Amount returned to the funding wallet, if the transaction has change output
+    pub change_sat: Option<u64>,
+    pub fee_sat: u64,
+    /// Transaction weight, including the estimated weight of the input witnesses
+    pub weight: u64,
+}
+
+impl FundingPreview {
+    /// Effective feerate paid by the transaction, in satoshi per virtual byte
+    pub fn feerate_sat_vb(&self) -> f64 { self.fee_sat as f64 * 4.0 / self.weight.max(1) as f64 }
+}
+
+/// Mining status of a transaction reported by the on-chain tracking service
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(TxDepth::to_yaml_string)]
+pub struct TxDepth {
+    #[serde_as(as = "DisplayFromStr")]
+    pub txid: Txid,
+    /// Number of confirmations, or none if the transaction is not mined
+    pub depth: Option<u32>,
+}
+
+/// Status of the blockchain backend used by the on-chain tracking service
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(ChainStatus::to_yaml_string)]
+pub struct ChainStatus {
+    /// Type of the blockchain backend
+    pub backend: String,
+    /// Server the backend is connected to, or none if the connection is lost
+    pub server: Option<String>,
+    /// Blockchain height received from the backend during the last poll
+    pub height: Option<u32>,
+    /// Number of the funding outputs watched with subscriptions to the backend
+    pub subscriptions: u32,
+    /// Number of the transactions which mining status is tracked
+    pub tracked_txs: u32,
+    /// Number of the outpoints which spending by a mined transaction is watched
+    pub watched_outpoints: u32,
+    /// Number of the reconnections to the backend since watchd start
+    pub reconnects: u64,
+    /// Error of the last request to the backend, if it has failed
+    pub error: Option<String>,
+}
+
+/// Progress of the compact block filter backend synchronization with the bitcoin peers
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("headers {header_height}, filters {filter_height}, birthday {birthday}")]
+pub struct SyncProgress {
+    /// Height of the best block header received from the peers
+    pub header_height: u32,
+    /// Height up to which compact block filters are matched against the watched scripts
+    pub filter_height: u32,
+    /// Wallet birthday height, below which the filters are not matched
+    pub birthday: u32,
+    /// Whether the headers and the filters have caught up with the peers
+    pub synced: bool,
+}
+
+/// Network graph known to routed from the gossip messages
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(GraphInfo::to_yaml_string)]
+pub struct GraphInfo {
+    pub nodes: Vec<GraphNode>,
+    pub channels: Vec<GraphChannel>,
+}
+
+/// Node of the network graph
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(GraphNode::to_yaml_string)]
+pub struct GraphNode {
+    pub node_id: secp256k1::PublicKey,
+    /// Timestamp of the latest `node_announcement` message, if the node has announced itself
+    pub announced: Option<u32>,
+    /// Addresses announced by the node, except the Tor ones
+    pub addresses: Vec<RemoteSocketAddr>,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub channels: Vec<ShortChannelId>,
+    /// Total capacity of the node channels
+    pub capacity_sat: u64,
+}
+
+/// Public channel of the network graph
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(GraphChannel::to_yaml_string)]
+pub struct GraphChannel {
+    #[serde_as(as = "DisplayFromStr")]
+    pub short_channel_id: ShortChannelId,
+    /// Channel node with the lesser node id
+    pub node_1: secp256k1::PublicKey,
+    /// Channel node with the greater node id
+    pub node_2: secp256k1::PublicKey,
+    /// Channel capacity, equal to the amount of the funding output
+    pub capacity_sat: u64,
+    /// Policy of routing payments from the first node to the second one, if announced
+    pub policy_1: Option<GraphPolicy>,
+    /// Policy of routing payments from the second node to the first one, if announced
+    pub policy_2: Option<GraphPolicy>,
+}
+
+/// Routing policy announced for a channel direction with `channel_update` message
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{fee_base_msat} msat + {fee_proportional_millionths} ppm")]
+pub struct GraphPolicy {
+    /// Timestamp of the `channel_update` message
+    pub timestamp: u32,
+    pub disabled: bool,
+    pub cltv_expiry_delta: u16,
+    pub htlc_minimum_msat: u64,
+    pub htlc_maximum_msat: Option<u64>,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+}
+
+/// Result of the message signature verification
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display(MessageVerification::to_yaml_string)]
+pub struct MessageVerification {
+    /// Whether the message is signed by the expected public key or by a node known from the
+    /// gossip
+    pub valid: bool,
+    /// Node id recovered from the signature
+    pub pubkey: secp256k1::PublicKey,
+}
+
+/// Output managed by the funding wallet
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{outpoint}: {amount_sat} sat")]
+pub struct UtxoInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub outpoint: OutPoint,
+    pub amount_sat: u64,
+    /// Number of confirmations, which is zero for the outputs which are not mined yet
+    pub confirmations: u32,
+    /// Derivation path of the output key relative to the wallet account
+    pub derivation: String,
+    /// Channel which funding transaction spends the output. Reserved output is not available
+    /// for funding other channels until the channel is either funded or abandoned.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub reserved_for: Option<ChannelId>,
+}
+
+/// Off-chain balance of a channel
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+#[display("{channel_id}: {local_balance_msat} msat")]
+pub struct ChannelBalance {
+    #[serde_as(as = "DisplayFromStr")]
+    pub channel_id: ChannelId,
+    /// Lifecycle stage of the channel
+    pub lifecycle: String,
+    pub local_balance_msat: u64,
+    pub remote_balance_msat: u64,
 }
 
 #[cfg(feature = "serde")]
@@ -315,9 +1651,37 @@ impl ToYamlString for NodeInfo {}
 #[cfg(feature = "serde")]
 impl ToYamlString for PeerInfo {}
 #[cfg(feature = "serde")]
+impl ToYamlString for PeerList {}
+#[cfg(feature = "serde")]
 impl ToYamlString for ChannelInfo {}
 #[cfg(feature = "serde")]
+impl ToYamlString for ChannelSummary {}
+#[cfg(feature = "serde")]
+impl ToYamlString for ChannelList {}
+#[cfg(feature = "serde")]
 impl ToYamlString for FundsInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for FeePolicyList {}
+#[cfg(feature = "serde")]
+impl ToYamlString for NewAddress {}
+#[cfg(feature = "serde")]
+impl ToYamlString for Withdrawal {}
+#[cfg(feature = "serde")]
+impl ToYamlString for FundingPreview {}
+#[cfg(feature = "serde")]
+impl ToYamlString for TxDepth {}
+#[cfg(feature = "serde")]
+impl ToYamlString for ChainStatus {}
+#[cfg(feature = "serde")]
+impl ToYamlString for CommitmentDump {}
+#[cfg(feature = "serde")]
+impl ToYamlString for GraphInfo {}
+#[cfg(feature = "serde")]
+impl ToYamlString for GraphNode {}
+#[cfg(feature = "serde")]
+impl ToYamlString for GraphChannel {}
+#[cfg(feature = "serde")]
+impl ToYamlString for MessageVerification {}
 
 #[derive(Wrapper, Clone, PartialEq, Eq, Debug, From, NetworkEncode, NetworkDecode)]
 #[wrapper(IndexRange)]
@@ -360,27 +1724,9 @@ where
     }
 }
 
-/// Information about server-side failure returned through RPC API
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
-#[derive(NetworkEncode, NetworkDecode)]
-#[display("{info}", alt = "Server returned failure #{code}: {info}")]
-pub struct Failure {
-    /// Failure code
-    pub code: u16,
-
-    /// Detailed information about the failure
-    pub info: String,
-}
-
-impl Failure {
-    pub fn into_microservice_failure(self) -> microservices::rpc::Failure {
-        microservices::rpc::Failure { code: self.code, info: self.info }
-    }
-}
-
 #[derive(Wrapper, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, From, Default)]
 #[derive(NetworkEncode, NetworkDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
 pub struct OptionDetails(pub Option<String>);
 
 impl Display for OptionDetails {
@@ -407,18 +1753,366 @@ impl From<&str> for OptionDetails {
 }
 
 impl From<crate::Error> for RpcMsg {
-    fn from(err: crate::Error) -> Self { RpcMsg::Failure(Failure::from(&err)) }
+    fn from(err: crate::Error) -> Self { RpcMsg::Failure(RpcError::from(&err)) }
 }
 
 impl From<&str> for RpcMsg {
     fn from(s: &str) -> Self { RpcMsg::Progress(s.to_owned()) }
 }
 
-impl<E: std::error::Error> From<&E> for Failure {
-    fn from(err: &E) -> Self {
-        Failure {
-            code: 9000, // TODO: do code types
-            info: err.to_string(),
-        }
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::ErrorCode;
+
+    const NODE_ID: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn to_json(reply: RpcMsg) -> Value { serde_json::to_value(reply).unwrap() }
+
+    fn channel_id() -> ChannelId { ChannelId::from_inner(Slice32::from_inner([0x42; 32])) }
+
+    #[test]
+    fn json_requests() {
+        assert!(serde_json::to_value(RpcMsg::GetInfo).is_err());
+        assert!(serde_json::to_value(RpcMsg::ListFunds).is_err());
+    }
+
+    #[test]
+    fn json_open_channel() {
+        let progress = RpcMsg::Progress(s!("Channel proposed"));
+        assert_eq!(to_json(progress), json!({ "progress": "Channel proposed" }));
+        let success = RpcMsg::Success(OptionDetails(Some(s!("Channel is active"))));
+        assert_eq!(to_json(success), json!({ "success": "Channel is active" }));
+        assert_eq!(to_json(RpcMsg::Success(OptionDetails(None))), json!({ "success": null }));
+    }
+
+    #[test]
+    fn json_failure() {
+        let failure = RpcError::new(ErrorCode::ChannelNotFound, "unknown channel");
+        assert_eq!(
+            to_json(RpcMsg::Failure(failure.clone())),
+            json!({ "failure": { "code": 5000, "message": "unknown channel" } })
+        );
+        let failure = failure.with_detail("source", "lnpd");
+        assert_eq!(
+            to_json(RpcMsg::Failure(failure)),
+            json!({
+                "failure": {
+                    "code": 5000,
+                    "message": "unknown channel",
+                    "details": { "source": "lnpd" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn json_node_info() {
+        let info = NodeInfo {
+            node_id: secp256k1::PublicKey::from_str(NODE_ID).unwrap(),
+            version: s!("0.6.0"),
+            features: vec![s!("var_onion_optin")],
+            listens: vec![],
+            onion_address: None,
+            uptime: Duration::from_secs(3600),
+            since: 1_600_000_000,
+            peers: vec![],
+            channels: vec![channel_id()],
+            reaped_channels: vec![],
+            rejected_channels: 1,
+            inbound_peers: 2,
+            rejected_connections: 3,
+            evicted_peers: 4,
+            handshake_timeouts: 5,
+            init_timeouts: 6,
+            oversized_messages: 7,
+            connected_peers: 8,
+            channel_stages: bmap! { s!("active") => 1 },
+            chain_backend: Some(s!("electrum")),
+            sync_height: Some(700_000),
+            chain_error: None,
+            chain_sync: None,
+            confirmed_balance_sat: Some(100_000),
+            unconfirmed_balance_sat: Some(0),
+            signer_locked: Some(false),
+            warnings: vec![],
+        };
+        assert_eq!(
+            to_json(RpcMsg::NodeInfo(info)),
+            json!({
+                "node_info": {
+                    "node_id": NODE_ID,
+                    "version": "0.6.0",
+                    "features": ["var_onion_optin"],
+                    "listens": [],
+                    "onion_address": null,
+                    "uptime": 3600,
+                    "since": 1_600_000_000,
+                    "peers": [],
+                    "channels": [channel_id().to_string()],
+                    "reaped_channels": [],
+                    "rejected_channels": 1,
+                    "inbound_peers": 2,
+                    "rejected_connections": 3,
+                    "evicted_peers": 4,
+                    "handshake_timeouts": 5,
+                    "init_timeouts": 6,
+                    "oversized_messages": 7,
+                    "connected_peers": 8,
+                    "channel_stages": { "active": 1 },
+                    "chain_backend": "electrum",
+                    "sync_height": 700_000,
+                    "chain_error": null,
+                    "chain_sync": null,
+                    "confirmed_balance_sat": 100_000,
+                    "unconfirmed_balance_sat": 0,
+                    "signer_locked": false,
+                    "warnings": []
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn json_peer_list() {
+        let list = PeerList {
+            connected: vec![],
+            reconnecting: vec![ReconnectInfo {
+                node_id: secp256k1::PublicKey::from_str(NODE_ID).unwrap(),
+                remote_socket: None,
+                pinned: true,
+                attempts: 3,
+                next_attempt: Duration::from_secs(30),
+                last_connected: Some(1_600_000_000),
+                handshakes: 2,
+                disconnects: bmap! { s!("timeout") => 1 },
+            }],
+            total_count: 0,
+        };
+        assert_eq!(
+            to_json(RpcMsg::PeerList(list)),
+            json!({
+                "peer_list": {
+                    "connected": [],
+                    "reconnecting": [{
+                        "node_id": NODE_ID,
+                        "remote_socket": null,
+                        "pinned": true,
+                        "attempts": 3,
+                        "next_attempt": 30,
+                        "last_connected": 1_600_000_000,
+                        "handshakes": 2,
+                        "disconnects": { "timeout": 1 }
+                    }],
+                    "total_count": 0
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn json_channel_list() {
+        let outpoint = OutPoint::new(Txid::default(), 1);
+        let list = ChannelList {
+            channels: vec![ChannelSummary {
+                channel_id: channel_id(),
+                remote_node: None,
+                lifecycle: s!("active"),
+                capacity_sat: 100_000,
+                local_balance_msat: 60_000_000,
+                remote_balance_msat: 40_000_000,
+                pending_htlcs: 0,
+                funding_outpoint: Some(outpoint),
+                confirmations: Some(3),
+                minimum_depth: 3,
+            }],
+            total_count: 3120,
+        };
+        assert_eq!(
+            to_json(RpcMsg::ChannelList(list)),
+            json!({
+                "channel_list": {
+                    "channels": [{
+                        "channel_id": channel_id().to_string(),
+                        "remote_node": null,
+                        "lifecycle": "active",
+                        "capacity_sat": 100_000,
+                        "local_balance_msat": 60_000_000,
+                        "remote_balance_msat": 40_000_000,
+                        "pending_htlcs": 0,
+                        "funding_outpoint": outpoint.to_string(),
+                        "confirmations": 3,
+                        "minimum_depth": 3
+                    }],
+                    "total_count": 3120
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn json_funds_info() {
+        let address = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let outpoint = OutPoint::new(Txid::default(), 0);
+        let funds = FundsInfo {
+            bitcoin_funds: empty!(),
+            asset_funds: empty!(),
+            next_address: Address::from_str(address).unwrap(),
+            utxos: vec![UtxoInfo {
+                outpoint,
+                amount_sat: 150_000,
+                confirmations: 6,
+                derivation: s!("0/3"),
+                reserved_for: Some(channel_id()),
+            }],
+            confirmed_sat: 150_000,
+            unconfirmed_sat: 0,
+            reserved_sat: 150_000,
+            channel_balances: vec![ChannelBalance {
+                channel_id: channel_id(),
+                lifecycle: s!("active"),
+                local_balance_msat: 60_000_000,
+                remote_balance_msat: 40_000_000,
+            }],
+        };
+        assert_eq!(
+            to_json(RpcMsg::FundsInfo(funds)),
+            json!({
+                "funds_info": {
+                    "bitcoin_funds": {},
+                    "asset_funds": {},
+                    "next_address": address,
+                    "utxos": [{
+                        "outpoint": outpoint.to_string(),
+                        "amount_sat": 150_000,
+                        "confirmations": 6,
+                        "derivation": "0/3",
+                        "reserved_for": channel_id().to_string()
+                    }],
+                    "confirmed_sat": 150_000,
+                    "unconfirmed_sat": 0,
+                    "reserved_sat": 150_000,
+                    "channel_balances": [{
+                        "channel_id": channel_id().to_string(),
+                        "lifecycle": "active",
+                        "local_balance_msat": 60_000_000,
+                        "remote_balance_msat": 40_000_000
+                    }]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn pagination() {
+        let items = (0..10).collect::<Vec<_>>();
+        let page = |offset, limit| Pagination { offset, limit }.apply(items.clone());
+        assert_eq!(page(0, None), items);
+        assert_eq!(page(0, Some(3)), vec![0, 1, 2]);
+        assert_eq!(page(7, Some(3)), vec![7, 8, 9]);
+        // Limit exceeding the remaining items
+        assert_eq!(page(8, Some(3)), vec![8, 9]);
+        assert_eq!(page(0, Some(50)), items);
+        assert_eq!(page(9, None), vec![9]);
+        // Offset at and past the end of the listing
+        assert_eq!(page(10, Some(3)), Vec::<i32>::new());
+        assert_eq!(page(11, None), Vec::<i32>::new());
+        assert_eq!(page(3, Some(0)), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn channel_filter() {
+        let node_id = secp256k1::PublicKey::from_str(NODE_ID).unwrap();
+        let channel = ChannelSummary {
+            channel_id: channel_id(),
+            remote_node: Some(node_id),
+            lifecycle: s!("Active"),
+            capacity_sat: 100_000,
+            local_balance_msat: 100_000_000,
+            remote_balance_msat: 0,
+            pending_htlcs: 0,
+            funding_outpoint: None,
+            confirmations: None,
+            minimum_depth: 3,
+        };
+        let filter = |remote_node, lifecycle: Option<&str>| ChannelFilter {
+            remote_node,
+            lifecycle: lifecycle.map(str::to_owned),
+        };
+        assert!(filter(None, None).matches(&channel));
+        assert!(filter(Some(node_id), None).matches(&channel));
+        assert!(filter(None, Some("active")).matches(&channel));
+        assert!(filter(Some(node_id), Some("ACTIVE")).matches(&channel));
+        assert!(!filter(None, Some("closing")).matches(&channel));
+        let other = secp256k1::PublicKey::from_str(
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+        )
+        .unwrap();
+        assert!(!filter(Some(other), Some("active")).matches(&channel));
+    }
+
+    #[test]
+    fn reconnecting_peer_filter() {
+        let node_id = secp256k1::PublicKey::from_str(NODE_ID).unwrap();
+        let peer = ReconnectInfo {
+            node_id,
+            remote_socket: None,
+            pinned: false,
+            attempts: 1,
+            next_attempt: Duration::from_secs(1),
+            last_connected: None,
+            handshakes: 0,
+            disconnects: empty!(),
+        };
+        assert!(PeerFilter::default().matches_reconnecting(&peer));
+        let filter = PeerFilter { remote_node: Some(node_id), since: Some(1), ..none!() };
+        assert!(filter.matches_reconnecting(&peer));
+        let listener = InetSocketAddr::from_str("127.0.0.1:9735").unwrap();
+        let filter = PeerFilter { listener: Some(listener), ..none!() };
+        assert!(!filter.matches_reconnecting(&peer));
+    }
+
+    #[test]
+    fn peer_filter() {
+        let node_id = secp256k1::PublicKey::from_str(NODE_ID).unwrap();
+        let listener = InetSocketAddr::from_str("127.0.0.1:9735").unwrap();
+        let peer = PeerInfo {
+            local_id: node_id,
+            remote_id: vec![node_id],
+            local_socket: Some(listener),
+            remote_socket: vec![],
+            direction: ConnectionDirection::Inbound,
+            uptime: Duration::from_secs(60),
+            since: 1_600_000_000,
+            features: vec![],
+            messages_sent: 0,
+            messages_received: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            ping_rtt_ms: None,
+            channels: empty!(),
+            connected: true,
+            awaits_pong: false,
+            queued_messages: 0,
+            dropped_gossip: 0,
+            message_stats: empty!(),
+            last_sent: None,
+            last_received: None,
+            handshakes: 1,
+            disconnects: empty!(),
+        };
+        let range = |since, until| PeerFilter { since, until, ..none!() };
+        assert!(range(None, None).matches(&peer));
+        assert!(range(Some(1_600_000_000), Some(1_600_000_000)).matches(&peer));
+        assert!(range(Some(1_500_000_000), None).matches(&peer));
+        assert!(range(None, Some(1_700_000_000)).matches(&peer));
+        assert!(!range(Some(1_600_000_001), None).matches(&peer));
+        assert!(!range(None, Some(1_599_999_999)).matches(&peer));
+        assert!(PeerFilter { remote_node: Some(node_id), ..none!() }.matches(&peer));
+        assert!(PeerFilter { listener: Some(listener), ..none!() }.matches(&peer));
+        let outbound = PeerInfo { direction: ConnectionDirection::Outbound, ..peer };
+        assert!(!PeerFilter { listener: Some(listener), ..none!() }.matches(&outbound));
     }
 }
@@ -0,0 +1,325 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Transports delivering client requests to the node.
+//!
+//! Local clients connect the RPC bus of the node directly over ZMQ. Remote clients connect the
+//! remote RPC listener of lnpd, given as `lnpr://<node_id>@<host>:<port>`, over TCP connection
+//! encrypted and authenticated with Noise_XK handshake, as used by BOLT-8 lightning peer
+//! connections. The client is authenticated by lnpd with the RPC token carried inside each
+//! request, just like the local clients; the Noise handshake authenticates the node to the
+//! client with the node key (or a dedicated RPC key). Connection keys are rotated each 1000
+//! messages in each direction as defined by BOLT-8.
+//!
+//! Once the handshake is completed, lnpd sends a frame with the client id under which the
+//! requests are relayed to the RPC bus. Further frames sent by the client are [`RemoteRequest`]s;
+//! each frame sent by lnpd is a single reply.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
+
+use bitcoin::secp256k1::{self, rand, PublicKey};
+use internet2::addr::InetSocketAddr;
+use internet2::session::noise::HandshakeState;
+use internet2::session::{self, ftcp};
+use internet2::{LocalNode, RemoteNodeAddr, RemoteSocketAddr, ZmqType};
+use microservices::esb;
+use microservices::esb::BusId;
+use microservices::peer::{PeerConnection, RecvMessage, SendMessage};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::{BusMsg, ClientId, Error, RpcMsg, RpcRequest, ServiceId};
+
+/// URL scheme of the remote RPC endpoints
+pub const LNP_NODE_REMOTE_RPC_SCHEME: &str = "lnpr";
+
+/// Default port of the remote RPC listener
+pub const LNP_NODE_REMOTE_RPC_PORT: u16 = 62964;
+
+/// Length of BOLT-8 handshake act one and two messages
+const ACT_ONE_TWO_LEN: usize = 50;
+
+/// Length of BOLT-8 handshake act three message
+const ACT_THREE_LEN: usize = 66;
+
+/// Endpoint of the node RPC interface
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum RpcEndpoint {
+    /// ZMQ socket of the node RPC bus: either TCP or IPC one
+    #[display(inner)]
+    Bus(String),
+
+    /// Remote RPC listener of lnpd, reached over encrypted TCP connection
+    #[display("lnpr://{0}")]
+    Remote(RemoteNodeAddr),
+}
+
+impl FromStr for RpcEndpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let remote = match s.split_once("://") {
+            Some((LNP_NODE_REMOTE_RPC_SCHEME, remote)) => remote,
+            Some(("tcp", _)) | Some(("ipc", _)) | Some(("inproc", _)) => {
+                return Ok(RpcEndpoint::Bus(s.to_owned()))
+            }
+            Some((scheme, _)) => {
+                return Err(Error::Other(format!("unsupported RPC endpoint scheme `{}`", scheme)))
+            }
+            None if SocketAddr::from_str(s).is_ok() => {
+                return Ok(RpcEndpoint::Bus(format!("tcp://{}", s)))
+            }
+            None => return Ok(RpcEndpoint::Bus(format!("ipc://{}", s))),
+        };
+
+        let (node_id, addr) = remote.split_once('@').ok_or_else(|| {
+            Error::Other(s!("remote RPC endpoint must have form of lnpr://<node_id>@<host>:<port>"))
+        })?;
+        let node_id = PublicKey::from_str(node_id)
+            .map_err(|err| Error::Other(format!("invalid remote node id: {}", err)))?;
+        let socket_addr = match addr.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            Err(_) => (addr, LNP_NODE_REMOTE_RPC_PORT).to_socket_addrs()?.next(),
+        }
+        .ok_or_else(|| Error::Other(format!("unable to resolve remote RPC address {}", addr)))?;
+        Ok(RpcEndpoint::Remote(RemoteNodeAddr {
+            node_id,
+            remote_addr: RemoteSocketAddr::Ftcp(InetSocketAddr::from(socket_addr)),
+        }))
+    }
+}
+
+/// Request sent by a remote client to the node daemon
+#[derive(Clone, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{daemon} <- {request}")]
+pub struct RemoteRequest {
+    /// Daemon serving the request
+    pub daemon: ServiceId,
+
+    pub request: RpcRequest,
+}
+
+// We have just a single service bus (RPC), so we can use any id
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default, Display)]
+#[display("LNPRPC")]
+pub(crate) struct RpcBus;
+
+impl BusId for RpcBus {
+    type Address = ServiceId;
+}
+
+type Bus = esb::EndpointList<RpcBus>;
+
+/// Transport used by the client
+pub(crate) enum Transport {
+    Bus(esb::Controller<RpcBus, BusMsg, Handler>),
+    Remote(PeerConnection),
+}
+
+impl Transport {
+    /// Connects the node. Returns the transport together with the client identity, which is
+    /// assigned by lnpd for the remote connections.
+    pub fn connect(endpoint: &RpcEndpoint, identity: ClientId) -> Result<(Self, ClientId), Error> {
+        match endpoint {
+            RpcEndpoint::Bus(rpc_endpoint) => {
+                let bus_config = esb::BusConfig::with_locator(
+                    rpc_endpoint.parse().map_err(|_| {
+                        Error::Other(format!("invalid RPC socket {}", rpc_endpoint))
+                    })?,
+                    Some(ServiceId::router()),
+                );
+                let esb = esb::Controller::with(
+                    map! {
+                        RpcBus => bus_config
+                    },
+                    Handler { identity: ServiceId::Client(identity) },
+                    ZmqType::RouterConnect,
+                )?;
+
+                // We have to sleep in order for ZMQ to bootstrap
+                sleep(Duration::from_secs_f32(0.1));
+
+                Ok((Transport::Bus(esb), identity))
+            }
+            RpcEndpoint::Remote(remote_node) => {
+                // Client is authenticated with its RPC token, so it uses a one-time key
+                let secp = secp256k1::Secp256k1::signing_only();
+                let seckey = secp256k1::SecretKey::new(&mut rand::thread_rng());
+                let local_id = PublicKey::from_secret_key(&secp, &seckey);
+                let local_node = LocalNode::with(seckey, local_id);
+                let mut connection = PeerConnection::connect(remote_node.clone(), &local_node)
+                    .map_err(|err| remote_err(remote_node, err))?;
+                let hello =
+                    connection.recv_raw_message().map_err(|err| remote_err(remote_node, err))?;
+                let identity = ClientId::strict_deserialize(&hello)?;
+                Ok((Transport::Remote(connection), identity))
+            }
+        }
+    }
+
+    pub fn send(&mut self, daemon: ServiceId, request: RpcRequest) -> Result<(), Error> {
+        match self {
+            Transport::Bus(esb) => esb.send_to(RpcBus, daemon, BusMsg::Request(request))?,
+            Transport::Remote(connection) => {
+                let frame = RemoteRequest { daemon, request }.strict_serialize()?;
+                connection.send_raw_message(&frame).map_err(|err| Error::Remote(err.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Receives replies, blocking until at least one is received
+    pub fn recv(&mut self) -> Result<Vec<RpcMsg>, Error> {
+        match self {
+            Transport::Bus(esb) => Ok(esb
+                .recv_poll()?
+                .into_iter()
+                .filter_map(|(_, _, rep)| match rep {
+                    BusMsg::Rpc(msg) => Some(msg),
+                    BusMsg::Request(request) => {
+                        warn!("Unexpected request {} received instead of a reply", request);
+                        None
+                    }
+                })
+                .collect()),
+            Transport::Remote(connection) => {
+                let frame =
+                    connection.recv_raw_message().map_err(|err| Error::Remote(err.to_string()))?;
+                Ok(vec![RpcMsg::strict_deserialize(&frame)?])
+            }
+        }
+    }
+}
+
+fn remote_err(remote_node: &RemoteNodeAddr, err: impl ToString) -> Error {
+    Error::Remote(format!("unable to connect lnpr://{}: {}", remote_node, err.to_string()))
+}
+
+/// Remote RPC listener accepting encrypted connections of the remote clients
+pub struct RemoteListener {
+    listener: TcpListener,
+    local_node: LocalNode,
+}
+
+impl RemoteListener {
+    /// Binds listener to the address. The key authenticates the node to the clients.
+    pub fn bind(addr: SocketAddr, local_node: LocalNode) -> Result<RemoteListener, Error> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(RemoteListener { listener, local_node })
+    }
+
+    /// Endpoint to be given to the remote clients
+    pub fn endpoint(&self) -> Result<RpcEndpoint, Error> {
+        Ok(RpcEndpoint::Remote(RemoteNodeAddr {
+            node_id: self.local_node.node_id(),
+            remote_addr: RemoteSocketAddr::Ftcp(self.listener.local_addr()?.into()),
+        }))
+    }
+
+    /// Awaits for the next client TCP connection. The handshake has to be completed with
+    /// [`RemoteSession::accept`], which may be done from a separate thread.
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddr), Error> {
+        Ok(self.listener.accept()?)
+    }
+
+    pub fn local_node(&self) -> &LocalNode { &self.local_node }
+}
+
+/// Connection with a remote client, once Noise_XK handshake is completed
+pub struct RemoteSession {
+    connection: PeerConnection,
+    remote_key: PublicKey,
+}
+
+impl RemoteSession {
+    /// Completes Noise_XK handshake as a responder, authenticating the node with its key
+    pub fn accept(
+        mut stream: TcpStream,
+        remote_addr: SocketAddr,
+        local_node: &LocalNode,
+    ) -> Result<RemoteSession, Error> {
+        let ephemeral_key = secp256k1::SecretKey::new(&mut rand::thread_rng());
+        let handshake = HandshakeState::new_responder(&local_node.private_key(), &ephemeral_key);
+
+        let mut act_one = [0u8; ACT_ONE_TWO_LEN];
+        stream.read_exact(&mut act_one)?;
+        let (act_two, handshake) = handshake.next(&act_one).map_err(Error::Remote)?;
+        stream.write_all(&act_two.unwrap_or_default())?;
+
+        let mut act_three = [0u8; ACT_THREE_LEN];
+        stream.read_exact(&mut act_three)?;
+        let (transcoder, remote_key) = match handshake.next(&act_three).map_err(Error::Remote)? {
+            (None, HandshakeState::Complete(Some(complete))) => complete,
+            _ => return Err(Error::Remote(s!("unexpected state of Noise handshake"))),
+        };
+
+        let connection = ftcp::Connection::with(stream, InetSocketAddr::from(remote_addr));
+        let session = session::Raw::with(transcoder, connection);
+        Ok(RemoteSession { connection: PeerConnection::with(session), remote_key })
+    }
+
+    /// Key used by the client for the connection. It does not authenticate the client, which
+    /// is done with the RPC token of each request.
+    pub fn remote_key(&self) -> PublicKey { self.remote_key }
+
+    /// Tells client its identity on the RPC bus
+    pub fn send_identity(&mut self, identity: ClientId) -> Result<(), Error> {
+        let frame = identity.strict_serialize()?;
+        self.connection.send_raw_message(&frame).map_err(|err| Error::Remote(err.to_string()))?;
+        Ok(())
+    }
+
+    pub fn recv_request(&mut self) -> Result<RemoteRequest, Error> {
+        let frame =
+            self.connection.recv_raw_message().map_err(|err| Error::Remote(err.to_string()))?;
+        Ok(RemoteRequest::strict_deserialize(&frame)?)
+    }
+
+    pub fn send_reply(&mut self, reply: &RpcMsg) -> Result<(), Error> {
+        let frame = reply.strict_serialize()?;
+        self.connection.send_raw_message(&frame).map_err(|err| Error::Remote(err.to_string()))?;
+        Ok(())
+    }
+}
+
+pub(crate) struct Handler {
+    identity: ServiceId,
+}
+
+impl esb::Handler<RpcBus> for Handler {
+    type Request = BusMsg;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId { self.identity.clone() }
+
+    fn handle(
+        &mut self,
+        _: &mut Bus,
+        _: RpcBus,
+        _: ServiceId,
+        _: BusMsg,
+    ) -> Result<(), Self::Error> {
+        // Cli does not receive replies for now
+        Ok(())
+    }
+
+    fn handle_err(&mut self, _: &mut Bus, err: esb::Error<ServiceId>) -> Result<(), Self::Error> {
+        // We simply propagate the error since it already has been reported
+        Err(err.into())
+    }
+}
@@ -28,16 +28,40 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--version', 'version', [CompletionResultType]::ParameterName, 'Print version information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             [CompletionResult]::new('listen', 'listen', [CompletionResultType]::ParameterValue, 'Bind to a socket and start listening for incoming LN peer connections')
             [CompletionResult]::new('connect', 'connect', [CompletionResultType]::ParameterValue, 'Connect to the remote lightning network peer')
+            [CompletionResult]::new('disconnect', 'disconnect', [CompletionResultType]::ParameterValue, 'Disconnect the remote lightning network peer. Channels with the peer stop offering new HTLCs, and channels which funding is not yet signed are abandoned')
             [CompletionResult]::new('ping', 'ping', [CompletionResultType]::ParameterValue, 'Ping remote peer (must be already connected)')
             [CompletionResult]::new('info', 'info', [CompletionResultType]::ParameterValue, 'General information about the running node')
+            [CompletionResult]::new('chain-status', 'chain-status', [CompletionResultType]::ParameterValue, 'Status of the blockchain backend used by the on-chain tracking service')
+            [CompletionResult]::new('chain', 'chain', [CompletionResultType]::ParameterValue, 'On-chain tracking service operations')
+            [CompletionResult]::new('events', 'events', [CompletionResultType]::ParameterValue, 'Subscribes to the node events and prints them as they happen')
+            [CompletionResult]::new('wait', 'wait', [CompletionResultType]::ParameterValue, 'Waits until the channel becomes active, the remote peer gets connected or the transaction gets confirmed')
             [CompletionResult]::new('funds', 'funds', [CompletionResultType]::ParameterValue, 'Lists all funds available for channel creation with the list of assets and provides information about funding points (bitcoin address or UTXO for RGB assets)')
-            [CompletionResult]::new('peers', 'peers', [CompletionResultType]::ParameterValue, 'Lists existing peer connections')
+            [CompletionResult]::new('address', 'address', [CompletionResultType]::ParameterValue, 'Issues a new funding wallet address for deposits')
+            [CompletionResult]::new('withdraw', 'withdraw', [CompletionResultType]::ParameterValue, 'Sends funds from the funding wallet to an external address')
+            [CompletionResult]::new('bake-token', 'bake-token', [CompletionResultType]::ParameterValue, 'Issues a new RPC token granting the given permissions. Requires admin token')
+            [CompletionResult]::new('peers', 'peers', [CompletionResultType]::ParameterValue, 'Lists existing peer connections and the remote peers being reconnected')
+            [CompletionResult]::new('peer', 'peer', [CompletionResultType]::ParameterValue, 'Peer address book operations')
+            [CompletionResult]::new('ban', 'ban', [CompletionResultType]::ParameterValue, 'Ban list operations')
+            [CompletionResult]::new('wallet', 'wallet', [CompletionResultType]::ParameterValue, 'Watch-only funding wallet operations')
             [CompletionResult]::new('channels', 'channels', [CompletionResultType]::ParameterValue, 'Lists existing channels')
             [CompletionResult]::new('open', 'open', [CompletionResultType]::ParameterValue, 'Opens a new channel with a remote peer, which must be already connected')
+            [CompletionResult]::new('open-batch', 'open-batch', [CompletionResultType]::ParameterValue, 'Opens multiple channels with remote peers, which must be already connected, funding all of them with a single transaction')
+            [CompletionResult]::new('abort', 'abort', [CompletionResultType]::ParameterValue, 'Aborts opening of a channel, which funding transaction is not signed yet')
+            [CompletionResult]::new('close', 'close', [CompletionResultType]::ParameterValue, 'Closes an active channel')
+            [CompletionResult]::new('channel', 'channel', [CompletionResultType]::ParameterValue, 'Channel state operations')
+            [CompletionResult]::new('feerates', 'feerates', [CompletionResultType]::ParameterValue, 'Lists routing fee policies set for the channels and the policies in effect for the active channels')
+            [CompletionResult]::new('set-fee-policy', 'set-fee-policy', [CompletionResultType]::ParameterValue, 'Sets routing fee policy announced for the channels to the network')
+            [CompletionResult]::new('set-fees', 'set-fees', [CompletionResultType]::ParameterValue, 'Updates limits and confirmation target of the fee estimation used for the funding, sweep and commitment transactions. Settings which are not given are kept')
             [CompletionResult]::new('invoice', 'invoice', [CompletionResultType]::ParameterValue, 'Create an invoice')
             [CompletionResult]::new('pay', 'pay', [CompletionResultType]::ParameterValue, 'Pay the invoice')
+            [CompletionResult]::new('graph', 'graph', [CompletionResultType]::ParameterValue, 'Network graph known to the node from the gossip')
+            [CompletionResult]::new('sign-message', 'sign-message', [CompletionResultType]::ParameterValue, 'Signs the message with the node key. The signature is compatible with `signmessage` command of LND and c-lightning')
+            [CompletionResult]::new('verify-message', 'verify-message', [CompletionResultType]::ParameterValue, 'Verifies the message signature and prints the node id of the signer. Without the public key the signature is valid only if the signer is known from the gossip')
+            [CompletionResult]::new('unlock', 'unlock', [CompletionResultType]::ParameterValue, 'Unlocks the signer, decrypting the node master key with the passphrase. Until then the node keeps its peer connections, but can''t open, update or close channels')
+            [CompletionResult]::new('recover', 'recover', [CompletionResultType]::ParameterValue, 'Recovers channel funds from the static channel backup after the loss of the node data')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
         }
@@ -54,15 +78,29 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;connect' {
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait for the connection to be established')
             [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;disconnect' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--permanent', 'permanent', [CompletionResultType]::ParameterName, 'Do not reconnect the peer automatically until it is connected with `connect` command')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;ping' {
@@ -72,6 +110,7 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;info' {
@@ -81,6 +120,122 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;chain-status' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;chain' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('pending', 'pending', [CompletionResultType]::ParameterValue, 'Lists unconfirmed transactions published by the node, which are rebroadcast until they get mined')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;chain;pending' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;chain;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;events' {
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--filter', 'filter', [CompletionResultType]::ParameterName, 'Print only events of this category: `peer`, `channel`, `payment` or `wallet`. Can be used multiple times; if absent, all events are printed')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wait' {
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait; waits indefinitely if absent')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('channel-active', 'channel-active', [CompletionResultType]::ParameterValue, 'Waits until the channel becomes active. Returns immediately if the channel is already active and fails if it is closed')
+            [CompletionResult]::new('peer-connected', 'peer-connected', [CompletionResultType]::ParameterValue, 'Waits until connection with the remote peer is established. Returns immediately if the peer is already connected')
+            [CompletionResult]::new('tx-confirmed', 'tx-confirmed', [CompletionResultType]::ParameterValue, 'Waits until the transaction gets the given number of confirmations, polling the node on-chain tracking service')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;wait;channel-active' {
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait; waits indefinitely if absent')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wait;peer-connected' {
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait; waits indefinitely if absent')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wait;tx-confirmed' {
+            [CompletionResult]::new('--depth', 'depth', [CompletionResultType]::ParameterName, 'Number of confirmations to wait for')
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait; waits indefinitely if absent')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wait;help' {
+            [CompletionResult]::new('--socket', 'socket', [CompletionResultType]::ParameterName, 'ZMQ socket the node publishes events to')
+            [CompletionResult]::new('--timeout', 'timeout', [CompletionResultType]::ParameterName, 'Number of seconds to wait; waits indefinitely if absent')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;funds' {
@@ -90,24 +245,211 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;address' {
+            [CompletionResult]::new('--type', 'type', [CompletionResultType]::ParameterName, 'Type of the address: `bech32` or `taproot`')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;withdraw' {
+            [CompletionResult]::new('--fee-rate', 'fee-rate', [CompletionResultType]::ParameterName, 'Fee rate of the transaction, in satoshi per vbyte')
+            [CompletionResult]::new('--confirm', 'confirm', [CompletionResultType]::ParameterName, 'Token confirming the withdrawal exceeding the withdrawal limit, read from the `withdrawal.token` file of the node data directory')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--all', 'all', [CompletionResultType]::ParameterName, 'Send all funds which are not reserved for the channel funding, deducting the fee from the sent amount')
+            [CompletionResult]::new('--dry-run', 'dry-run', [CompletionResultType]::ParameterName, 'Print the unsigned transaction instead of signing and publishing it')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;bake-token' {
+            [CompletionResult]::new('--permissions', 'permissions', [CompletionResultType]::ParameterName, 'Comma-separated list of permissions granted by the token: `read` for reading node, peer and channel information, `invoice` for receiving funds and `admin` for all operations')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;peers' {
+            [CompletionResult]::new('--node', 'node', [CompletionResultType]::ParameterName, 'Show only connection with the remote peer having this node id')
+            [CompletionResult]::new('--since', 'since', [CompletionResultType]::ParameterName, 'List only connections established at or after this UNIX timestamp')
+            [CompletionResult]::new('--until', 'until', [CompletionResultType]::ParameterName, 'List only connections established at or before this UNIX timestamp')
+            [CompletionResult]::new('--listener', 'listener', [CompletionResultType]::ParameterName, 'List only incoming connections accepted at this listening socket')
+            [CompletionResult]::new('--offset', 'offset', [CompletionResultType]::ParameterName, 'Number of connections to skip')
+            [CompletionResult]::new('--limit', 'limit', [CompletionResultType]::ParameterName, 'Maximal number of connections to list')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--reset', 'reset', [CompletionResultType]::ParameterName, 'Reset the traffic and connection statistics once they are reported')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;peer' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('pin', 'pin', [CompletionResultType]::ParameterValue, 'Makes the node reconnect the remote peer whenever the connection with it is lost, even if the peer has no channels with the node')
+            [CompletionResult]::new('unpin', 'unpin', [CompletionResultType]::ParameterValue, 'Cancels reconnection of the pinned remote peer. Peers having channels with the node are reconnected anyway')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;peer;pin' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;peer;unpin' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;peer;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;ban' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('list', 'list', [CompletionResultType]::ParameterValue, 'Lists the remote peers banned for violating the protocol or by the node operator')
+            [CompletionResult]::new('add', 'add', [CompletionResultType]::ParameterValue, 'Bans the remote peer, closing the connections with it and refusing the new ones until the ban expires. Unlike the bans for the protocol violations, peers having channels with the node can be banned this way')
+            [CompletionResult]::new('remove', 'remove', [CompletionResultType]::ParameterValue, 'Lifts the ban of the remote peer')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;ban;list' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;ban;add' {
+            [CompletionResult]::new('--duration', 'duration', [CompletionResultType]::ParameterName, 'Duration of the ban, in seconds. Defaults to the ban duration configured for the node')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;ban;remove' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;ban;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wallet' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('pending', 'pending', [CompletionResultType]::ParameterValue, 'Lists transactions of the funding wallet awaiting signature of the node operator, with their PSBTs in base64 encoding')
+            [CompletionResult]::new('submit-psbt', 'submit-psbt', [CompletionResultType]::ParameterValue, 'Submits PSBT of the pending transaction signed by the external wallet. The node finalizes the transaction and publishes it')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;wallet;pending' {
             [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wallet;submit-psbt' {
+            [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'File with the signed PSBT in binary form')
+            [CompletionResult]::new('--file', 'file', [CompletionResultType]::ParameterName, 'File with the signed PSBT in binary form')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;wallet;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;channels' {
+            [CompletionResult]::new('--peer', 'peer', [CompletionResultType]::ParameterName, 'List only channels with the remote peer having this node id')
+            [CompletionResult]::new('--stage', 'stage', [CompletionResultType]::ParameterName, 'List only channels at this lifecycle stage')
+            [CompletionResult]::new('--offset', 'offset', [CompletionResultType]::ParameterName, 'Number of channels to skip')
+            [CompletionResult]::new('--limit', 'limit', [CompletionResultType]::ParameterName, 'Maximal number of channels to list')
             [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;open' {
@@ -121,12 +463,189 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--htlc-min-value', 'htlc-min-value', [CompletionResultType]::ParameterName, 'Indicates the smallest value of an HTLC this node will accept, in milli-satoshi')
             [CompletionResult]::new('--htlc-max-total-value', 'htlc-max-total-value', [CompletionResultType]::ParameterName, 'The maximum inbound HTLC value in flight towards this node, in milli-satoshi')
             [CompletionResult]::new('--channel-reserve', 'channel-reserve', [CompletionResultType]::ParameterName, 'The minimum value unencumbered by HTLCs for the counterparty to keep in the channel, in satoshis')
+            [CompletionResult]::new('--shutdown-address', 'shutdown-address', [CompletionResultType]::ParameterName, 'Address which must receive our funds when the channel is cooperatively closed')
+            [CompletionResult]::new('--max-to-self-delay', 'max-to-self-delay', [CompletionResultType]::ParameterName, 'Maximal number of blocks the remote peer may require our funds to be timelocked for after a unilateral channel close')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--private', 'private', [CompletionResultType]::ParameterName, 'Keep the channel private')
+            [CompletionResult]::new('--zero-conf', 'zero-conf', [CompletionResultType]::ParameterName, 'Start using the channel without waiting for the funding transaction confirmation')
+            [CompletionResult]::new('--external-funding', 'external-funding', [CompletionResultType]::ParameterName, 'Fund the channel from an external wallet instead of the node funding wallet')
+            [CompletionResult]::new('--dry-run', 'dry-run', [CompletionResultType]::ParameterName, 'Only compose the funding transaction, without proposing the channel to the remote peer')
+            [CompletionResult]::new('--quiet', 'quiet', [CompletionResultType]::ParameterName, 'Print only the final result of the channel opening instead of reporting each stage of the channel negotiation and funding')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;open-batch' {
+            [CompletionResult]::new('--fee-rate', 'fee-rate', [CompletionResultType]::ParameterName, 'Sets fee rate for the transactions of all channels, in satoshi per 1000-weight')
+            [CompletionResult]::new('--announce-channel', 'announce-channel', [CompletionResultType]::ParameterName, 'Make all channels public and route payments')
+            [CompletionResult]::new('--channel-type', 'channel-type', [CompletionResultType]::ParameterName, 'Channel type as defined in BOLT-2 for all channels')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--quiet', 'quiet', [CompletionResultType]::ParameterName, 'Print only the final result of the channel opening instead of reporting each stage of the channel negotiation and funding')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;abort' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;close' {
+            [CompletionResult]::new('--fee-rate', 'fee-rate', [CompletionResultType]::ParameterName, 'Fee rate of the cooperative closing transaction, in satoshi per vbyte')
+            [CompletionResult]::new('--address', 'address', [CompletionResultType]::ParameterName, 'Address receiving our funds from the cooperative closing transaction')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--force', 'force', [CompletionResultType]::ParameterName, 'Close the channel unilaterally by publishing the latest commitment transaction')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('export', 'export', [CompletionResultType]::ParameterValue, 'Exports channel state into a file, from which it can be imported by another node')
+            [CompletionResult]::new('import', 'import', [CompletionResultType]::ParameterValue, 'Imports channel state exported by another node')
+            [CompletionResult]::new('fund', 'fund', [CompletionResultType]::ParameterValue, 'Provides channel with the funding transaction constructed by an external wallet')
+            [CompletionResult]::new('history', 'history', [CompletionResultType]::ParameterValue, 'Prints history of the messages processed and sent by the channel daemon')
+            [CompletionResult]::new('dump-commitment', 'dump-commitment', [CompletionResultType]::ParameterValue, 'Prints the latest local commitment transaction of the channel and its second-stage HTLC transactions, fully signed, as a last resort for the disaster recovery')
+            [CompletionResult]::new('export-secrets', 'export-secrets', [CompletionResultType]::ParameterValue, 'Exports the channel basepoints and the revocation secrets received from the remote peer in JSON format, for the audit and recovery tools')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;channel;export' {
+            [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'File to save the channel state to')
+            [CompletionResult]::new('--file', 'file', [CompletionResultType]::ParameterName, 'File to save the channel state to')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;import' {
+            [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'File with the exported channel state')
+            [CompletionResult]::new('--file', 'file', [CompletionResultType]::ParameterName, 'File with the exported channel state')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;fund' {
+            [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'File with the funding transaction in binary PSBT format')
+            [CompletionResult]::new('--file', 'file', [CompletionResultType]::ParameterName, 'File with the funding transaction in binary PSBT format')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;history' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;dump-commitment' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--i-know-what-i-am-doing', 'i-know-what-i-am-doing', [CompletionResultType]::ParameterName, 'Confirms that the risks of using the dumped transactions are understood')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;export-secrets' {
+            [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'File to save the export to instead of printing it')
+            [CompletionResult]::new('--file', 'file', [CompletionResultType]::ParameterName, 'File to save the export to instead of printing it')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;channel;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;feerates' {
             [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;set-fee-policy' {
+            [CompletionResult]::new('--base-msat', 'base-msat', [CompletionResultType]::ParameterName, 'Fee charged for each forwarded HTLC, in millisatoshis')
+            [CompletionResult]::new('--ppm', 'ppm', [CompletionResultType]::ParameterName, 'Fee charged for each million of forwarded millisatoshis')
+            [CompletionResult]::new('--cltv-delta', 'cltv-delta', [CompletionResultType]::ParameterName, 'Number of blocks the incoming HTLC timelock must exceed the forwarded HTLC timelock by')
+            [CompletionResult]::new('--htlc-min-msat', 'htlc-min-msat', [CompletionResultType]::ParameterName, 'Minimal amount of the forwarded HTLC, in millisatoshis')
+            [CompletionResult]::new('--htlc-max-msat', 'htlc-max-msat', [CompletionResultType]::ParameterName, 'Maximal amount of the forwarded HTLC, in millisatoshis. Defaults to the channel capacity')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;set-fees' {
+            [CompletionResult]::new('--floor', 'floor', [CompletionResultType]::ParameterName, 'Minimal estimated feerate, in satoshi per 1000-weight')
+            [CompletionResult]::new('--ceiling', 'ceiling', [CompletionResultType]::ParameterName, 'Maximal estimated feerate, in satoshi per 1000-weight')
+            [CompletionResult]::new('--target', 'target', [CompletionResultType]::ParameterName, 'Number of blocks within which the node transactions should get mined')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;invoice' {
@@ -136,6 +655,7 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;pay' {
@@ -145,6 +665,102 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;graph' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            [CompletionResult]::new('describe', 'describe', [CompletionResultType]::ParameterValue, 'Lists all nodes and public channels of the network graph')
+            [CompletionResult]::new('node', 'node', [CompletionResultType]::ParameterValue, 'Prints a node of the network graph with its announced addresses and channels')
+            [CompletionResult]::new('channel', 'channel', [CompletionResultType]::ParameterValue, 'Prints a public channel with the routing policies announced for it')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'lnp-cli;graph;describe' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;graph;node' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;graph;channel' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;graph;help' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;sign-message' {
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;verify-message' {
+            [CompletionResult]::new('--pubkey', 'pubkey', [CompletionResultType]::ParameterName, 'Public key which is expected to sign the message')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;unlock' {
+            [CompletionResult]::new('--passphrase-file', 'passphrase-file', [CompletionResultType]::ParameterName, 'Reads the passphrase from the file instead of prompting for it')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
+            break
+        }
+        'lnp-cli;recover' {
+            [CompletionResult]::new('--scb', 'scb', [CompletionResultType]::ParameterName, 'File with the static channel backup')
+            [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
+            [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
+            [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
         'lnp-cli;help' {
@@ -152,6 +768,7 @@ Register-ArgumentCompleter -Native -CommandName 'lnp-cli' -ScriptBlock {
             [CompletionResult]::new('--connect', 'connect', [CompletionResultType]::ParameterName, 'ZMQ socket for connecting daemon RPC interface')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print output in JSON format')
             break
         }
     })
@@ -40,6 +40,18 @@ Register-ArgumentCompleter -Native -CommandName 'lnpd' -ScriptBlock {
             [CompletionResult]::new('--listen', 'listen', [CompletionResultType]::ParameterName, 'Start daemon in listening mode binding the provided local address')
             [CompletionResult]::new('-p', 'p', [CompletionResultType]::ParameterName, 'Customize port used by lightning peer network')
             [CompletionResult]::new('--port', 'port', [CompletionResultType]::ParameterName, 'Customize port used by lightning peer network')
+            [CompletionResult]::new('--listen-addr', 'listen-addr', [CompletionResultType]::ParameterName, 'Listen for incoming peer connections also at the provided socket address')
+            [CompletionResult]::new('--listen-ws', 'listen-ws', [CompletionResultType]::ParameterName, 'Accept incoming peer connections over WebSocket at the provided socket address')
+            [CompletionResult]::new('--remote-rpc', 'remote-rpc', [CompletionResultType]::ParameterName, 'Start remote RPC listener on the given port, accepting encrypted connections of the clients from other machines')
+            [CompletionResult]::new('--rpc-bind', 'rpc-bind', [CompletionResultType]::ParameterName, 'Interface to bind the remote RPC listener to')
+            [CompletionResult]::new('--remote-rpc-key', 'remote-rpc-key', [CompletionResultType]::ParameterName, 'Path to the file with a dedicated key authenticating the node to the remote RPC clients, which is generated if the file does not exist')
+            [CompletionResult]::new('--tor-control', 'tor-control', [CompletionResultType]::ParameterName, 'Expose the peer listener as Tor onion service, created through the Tor control port at the given address')
+            [CompletionResult]::new('--tor-control-password', 'tor-control-password', [CompletionResultType]::ParameterName, 'Password for the Tor control port')
+            [CompletionResult]::new('--alias', 'alias', [CompletionResultType]::ParameterName, 'Node alias announced to the network, up to 32 bytes long')
+            [CompletionResult]::new('--rgb-color', 'rgb-color', [CompletionResultType]::ParameterName, 'Node color announced to the network, in `RRGGBB` hex form')
+            [CompletionResult]::new('--announce-addr', 'announce-addr', [CompletionResultType]::ParameterName, 'Address the node is reachable at, which is announced to the network')
+            [CompletionResult]::new('--bootstrap-seed', 'bootstrap-seed', [CompletionResultType]::ParameterName, 'Domain of the DNS seed queried for the first peers of the node. May be repeated')
+            [CompletionResult]::new('--bootstrap-dns', 'bootstrap-dns', [CompletionResultType]::ParameterName, 'Name server resolving the DNS seeds')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-V', 'V', [CompletionResultType]::ParameterName, 'Print version information')
@@ -47,6 +59,7 @@ Register-ArgumentCompleter -Native -CommandName 'lnpd' -ScriptBlock {
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--verbose', 'verbose', [CompletionResultType]::ParameterName, 'Set verbosity level')
             [CompletionResult]::new('--threaded-daemons', 'threaded-daemons', [CompletionResultType]::ParameterName, 'Spawn daemons as threads and not processes')
+            [CompletionResult]::new('--no-bootstrap', 'no-bootstrap', [CompletionResultType]::ParameterName, 'Do not look for the first peers of the node in DNS seeds')
             [CompletionResult]::new('init', 'init', [CompletionResultType]::ParameterValue, 'Initialize data directory')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
@@ -66,6 +79,7 @@ Register-ArgumentCompleter -Native -CommandName 'lnpd' -ScriptBlock {
             [CompletionResult]::new('--chain', 'chain', [CompletionResultType]::ParameterName, 'Blockchain to use')
             [CompletionResult]::new('--electrum-server', 'electrum-server', [CompletionResultType]::ParameterName, 'Electrum server to use')
             [CompletionResult]::new('--electrum-port', 'electrum-port', [CompletionResultType]::ParameterName, 'Customize Electrum server port number. By default the wallet will use port matching the selected network')
+            [CompletionResult]::new('--funding-descriptor', 'funding-descriptor', [CompletionResultType]::ParameterName, 'Descriptor of the funding wallet to use instead of the one derived from the master key')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help information')
             [CompletionResult]::new('-v', 'v', [CompletionResultType]::ParameterName, 'Set verbosity level')
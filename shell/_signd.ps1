@@ -20,6 +20,10 @@ Register-ArgumentCompleter -Native -CommandName 'signd' -ScriptBlock {
 
     $completions = @(switch ($command) {
         'signd' {
+            [CompletionResult]::new('-k', 'k', [CompletionResultType]::ParameterName, 'Node key file')
+            [CompletionResult]::new('--key-file', 'key-file', [CompletionResultType]::ParameterName, 'Node key file')
+            [CompletionResult]::new('--serve', 'serve', [CompletionResultType]::ParameterName, 'Run as a remote signer serving the signing requests of the nodes at the given address, instead of serving the local node daemons')
+            [CompletionResult]::new('--allow-client', 'allow-client', [CompletionResultType]::ParameterName, 'Node id of a node allowed to use the remote signer')
             [CompletionResult]::new('-d', 'd', [CompletionResultType]::ParameterName, '<[_]<[_]>::into_vec(box [$($x),+]).into_iter().flatten() are located')
             [CompletionResult]::new('--data-dir', 'data-dir', [CompletionResultType]::ParameterName, '<[_]<[_]>::into_vec(box [$($x),+]).into_iter().flatten() are located')
             [CompletionResult]::new('-c', 'c', [CompletionResultType]::ParameterName, 'Path for the configuration file')
@@ -0,0 +1,194 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Node root key authenticating client RPC tokens.
+//!
+//! The root key is generated by lnpd at the first start and stored in [`LNP_NODE_RPC_KEY_FILE`]
+//! inside the data directory, together with the admin token saved to
+//! [`LNP_NODE_ADMIN_TOKEN_FILE`]. All daemons serving client requests read the same key.
+
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::rand::{self, RngCore};
+
+use crate::opts::{LNP_NODE_ADMIN_TOKEN_FILE, LNP_NODE_RPC_KEY_FILE};
+use crate::rpc::{AuthError, AuthToken, Permissions, RpcMsg, RpcRequest};
+use crate::{Error, LogStyle};
+
+/// Root key authenticating client RPC tokens
+pub struct RpcAuth {
+    root_key: [u8; 32],
+}
+
+impl RpcAuth {
+    /// Reads the root key from the data directory, generating it together with the admin token
+    /// if the node is started for the first time. The admin token is generated anew if it is
+    /// missing, such that a start interrupted after saving the key does not leave the node
+    /// without the admin token. Used by lnpd.
+    pub fn init(data_dir: &Path) -> Result<RpcAuth, Error> {
+        let key_path = data_dir.join(LNP_NODE_RPC_KEY_FILE);
+        let auth = if key_path.exists() {
+            RpcAuth::load(data_dir)?
+        } else {
+            let mut root_key = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut root_key);
+            write_secret(&key_path, &root_key)?;
+            RpcAuth { root_key }
+        };
+
+        let token_path = data_dir.join(LNP_NODE_ADMIN_TOKEN_FILE);
+        if !token_path.exists() {
+            let admin_token = auth.bake(Permissions::admin());
+            write_secret(&token_path, admin_token.to_string().as_bytes())?;
+            info!("{} admin RPC token to '{}'", "Saved".ended(), token_path.display());
+        }
+        Ok(auth)
+    }
+
+    /// Reads the root key generated by lnpd from the data directory
+    pub fn load(data_dir: &Path) -> Result<RpcAuth, Error> {
+        let key_path = data_dir.join(LNP_NODE_RPC_KEY_FILE);
+        let data = fs::read(&key_path)?;
+        if data.len() != 32 {
+            return Err(Error::Other(format!(
+                "RPC key file '{}' is corrupted; remove it and restart the node to generate a new \
+                 key",
+                key_path.display()
+            )));
+        }
+        let mut root_key = [0u8; 32];
+        root_key.copy_from_slice(&data);
+        Ok(RpcAuth { root_key })
+    }
+
+    /// Mints a new token granting the given permissions
+    pub fn bake(&self, permissions: Permissions) -> AuthToken {
+        let nonce = rand::thread_rng().next_u64();
+        AuthToken { permissions, nonce, mac: self.mac(permissions, nonce) }
+    }
+
+    /// Checks that the request is made with a token issued by this node, granting permission
+    /// required for the request
+    pub fn authorize(&self, request: RpcRequest) -> Result<RpcMsg, AuthError> {
+        let token = request.token.ok_or(AuthError::Unauthenticated)?;
+        let mac = self.mac(token.permissions, token.nonce);
+        // Comparing in constant time, such that the tag can't be guessed byte by byte
+        let diff = mac
+            .as_inner()
+            .iter()
+            .zip(token.mac.as_inner())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Err(AuthError::InvalidToken);
+        }
+        let permission = request.msg.required_permission();
+        if !token.permissions.allow(permission) {
+            return Err(AuthError::PermissionDenied(permission, request.msg.to_string()));
+        }
+        Ok(request.msg)
+    }
+
+    fn mac(&self, permissions: Permissions, nonce: u64) -> Slice32 {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.root_key);
+        engine.input(&AuthToken::signed_data(permissions, nonce));
+        Slice32::from_inner(Hmac::from_engine(engine).into_inner())
+    }
+}
+
+/// Writes secret data to a file readable only by the node user, replacing the existing file. The
+/// data are written to a temporary file first, which is then renamed, so the file is never left
+/// partially written.
+pub(crate) fn write_secret(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    // Leftover of an interrupted write may have been created with other permissions
+    let _ = fs::remove_file(&tmp_path);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn data_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("lnp-node-auth-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn init_once() {
+        let dir = data_dir("init");
+        let auth = RpcAuth::init(&dir).unwrap();
+        let token = fs::read(dir.join(LNP_NODE_ADMIN_TOKEN_FILE)).unwrap();
+
+        let again = RpcAuth::init(&dir).unwrap();
+        assert_eq!(again.root_key, auth.root_key);
+        assert_eq!(fs::read(dir.join(LNP_NODE_ADMIN_TOKEN_FILE)).unwrap(), token);
+        assert_eq!(RpcAuth::load(&dir).unwrap().root_key, auth.root_key);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn init_missing_token() {
+        // Start interrupted after the key was saved
+        let dir = data_dir("token");
+        let auth = RpcAuth::init(&dir).unwrap();
+        fs::remove_file(dir.join(LNP_NODE_ADMIN_TOKEN_FILE)).unwrap();
+
+        let again = RpcAuth::init(&dir).unwrap();
+        assert_eq!(again.root_key, auth.root_key);
+        assert!(dir.join(LNP_NODE_ADMIN_TOKEN_FILE).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_secret_atomic() {
+        let dir = data_dir("secret");
+        let path = dir.join("secret.key");
+        // Leftover of an interrupted write
+        fs::write(dir.join("secret.key.tmp"), b"partial").unwrap();
+
+        write_secret(&path, b"secret").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+        assert!(!dir.join("secret.key.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+#![recursion_limit = "256"]
+// Coding conventions
+#![deny(
+    non_upper_case_globals,
+    non_camel_case_types,
+    non_snake_case,
+    unused_mut,
+    unused_imports,
+    dead_code,
+    missing_docs
+)]
+
+//! Main executable for gatewayd: JSON-RPC over HTTP gateway to the LNP Node
+//! RPC interface.
+
+#[macro_use]
+extern crate log;
+
+use clap::Parser;
+use lnp_node::gatewayd::{self, Opts};
+
+fn main() {
+    println!("gatewayd: JSON-RPC gateway to LNP node");
+
+    let mut opts = Opts::parse();
+    trace!("Command-line arguments: {:?}", &opts);
+    opts.process();
+    trace!("Processed arguments: {:?}", &opts);
+
+    debug!("HTTP JSON-RPC socket {}", &opts.http_socket);
+    debug!("Node RPC socket {}", &opts.shared.rpc_socket);
+
+    debug!("Starting runtime ...");
+    gatewayd::run(opts.http_socket, opts.shared.rpc_socket)
+        .expect("Error running gatewayd runtime");
+
+    unreachable!()
+}
@@ -34,9 +34,17 @@ use std::path::{Path, PathBuf};
 
 use bitcoin::secp256k1::PublicKey;
 use clap::Parser;
-use internet2::LocalNode;
+use internet2::addr::InetSocketAddr;
+use internet2::{LocalNode, RemoteSocketAddr};
+use lnp_node::chain;
+use lnp_node::lnpd::announcement::AnnouncementConfig;
+use lnp_node::lnpd::bootstrap::{self, BootstrapConfig};
+use lnp_node::lnpd::onion_service::OnionServiceConfig;
+use lnp_node::lnpd::remote_rpc::RemoteRpcConfig;
 use lnp_node::lnpd::{self, Command, Opts};
 use lnp_node::peerd::supervisor::read_node_key_file;
+use lnp_node::opts::LNP_NODE_ONION_KEY;
+use lnp_node::rpc::LNP_NODE_REMOTE_RPC_PORT;
 use lnp_node::{Config, Error, LogStyle};
 use strict_encoding::StrictEncode;
 
@@ -64,24 +72,83 @@ fn main() -> Result<(), Error> {
 
     let key_file = PathBuf::from(opts.key_opts.key_file);
     let bind_port = opts.port;
-    let bind_socket = opts.listen.map(|maybe_ip: Option<IpAddr>| {
-        let ip = maybe_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
-        SocketAddr::new(ip, bind_port)
+    let mut bind_sockets = opts
+        .listen
+        .map(|maybe_ip: Option<IpAddr>| {
+            let ip = maybe_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            SocketAddr::new(ip, bind_port)
+        })
+        .into_iter()
+        .collect::<Vec<_>>();
+    for addr in &opts.listen_addrs {
+        if !bind_sockets.contains(addr) {
+            bind_sockets.push(*addr);
+        }
+    }
+
+    let remote_rpc = opts.remote_rpc.map(|maybe_port: Option<u16>| RemoteRpcConfig {
+        bind: SocketAddr::new(
+            opts.rpc_bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            maybe_port.unwrap_or(LNP_NODE_REMOTE_RPC_PORT),
+        ),
+        explicit_bind: opts.rpc_bind.is_some(),
+        key_file: opts.remote_rpc_key.clone().map(PathBuf::from),
+        rpc_socket: opts.shared.rpc_socket.clone(),
     });
 
+    if opts.tor_control.is_some() && bind_sockets.is_empty() {
+        warn!("Onion service is not created since the node does not listen for connections");
+    }
+    let onion_service =
+        opts.tor_control.zip(bind_sockets.first().copied()).map(|(control, listen)| {
+            OnionServiceConfig {
+                control,
+                password: opts.tor_control_password.clone(),
+                key_file: config.data_dir.join(LNP_NODE_ONION_KEY),
+                listen,
+            }
+        });
+
+    let announcement = AnnouncementConfig {
+        alias: opts.alias.clone(),
+        color: opts.rgb_color,
+        addresses: opts.announce_addrs.clone(),
+    };
+
+    let bootstrap = if opts.no_bootstrap {
+        None
+    } else {
+        let mut seeds = opts.bootstrap_seeds.clone();
+        if seeds.is_empty() {
+            seeds = bootstrap::default_seeds(&config.chain);
+        }
+        Some(BootstrapConfig { seeds, nameserver: opts.bootstrap_dns })
+    };
+
     if let Some(command) = opts.command {
         match command {
-            Command::Init => init(&config, &key_file)?,
+            Command::Init { funding_descriptor } => {
+                init(&config, &key_file, funding_descriptor.as_deref())?
+            }
         }
     }
 
+    let mut listens = bind_sockets
+        .into_iter()
+        .map(|addr| RemoteSocketAddr::Ftcp(InetSocketAddr::from(addr)))
+        .collect::<Vec<_>>();
+    for addr in &opts.listen_ws {
+        listens.push(RemoteSocketAddr::Websocket(InetSocketAddr::from(*addr)));
+    }
+
     debug!("Starting runtime ...");
-    lnpd::run(config, key_file, bind_socket).expect("running lnpd runtime");
+    lnpd::run(config, key_file, listens, remote_rpc, onion_service, announcement, bootstrap)
+        .expect("running lnpd runtime");
 
     unreachable!()
 }
 
-fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
+fn init(config: &Config, key_path: &Path, funding_descriptor: Option<&str>) -> Result<(), Error> {
     use std::fs;
     use std::process::exit;
     use std::str::FromStr;
@@ -91,6 +158,7 @@ fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
     use bitcoin_hd::{TerminalStep, TrackingAccount};
     use lnp_node::lnpd::funding::FundingWallet;
     use lnp_node::opts::{LNP_NODE_FUNDING_WALLET, LNP_NODE_MASTER_KEY_FILE};
+    use lnp_node::signd::keystore;
     use miniscript::descriptor::{Descriptor, Wpkh};
     use psbt::sign::MemorySigningAccount;
 
@@ -117,12 +185,20 @@ fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
         let fingerprint = xpriv.identifier(&secp);
         let signing_account =
             MemorySigningAccount::with(&secp, fingerprint, derivation, xpriv_account);
-        let file = fs::File::create(wallet_path)?;
-        signing_account.write(file)?;
+        let passphrase = read_new_passphrase()?;
+        keystore::write_signing_account(&signing_account, &wallet_path, &passphrase)?;
         signing_account
-    } else {
+    } else if keystore::is_encrypted(&fs::read(&wallet_path)?) {
         println!("Signing account '{}' ... {}", LNP_NODE_MASTER_KEY_FILE, "found".progress());
-        MemorySigningAccount::read(&secp, fs::File::open(wallet_path)?)?
+        let passphrase = rpassword::read_password_from_tty(Some("Master key passphrase: "))?;
+        keystore::read_signing_account(&secp, &wallet_path, Some(&passphrase))?
+    } else {
+        // Master key files of the previous versions are stored in plaintext
+        println!("Signing account '{}' ... {}", LNP_NODE_MASTER_KEY_FILE, "encrypting".action());
+        let signing_account = keystore::read_signing_account(&secp, &wallet_path, None)?;
+        let passphrase = read_new_passphrase()?;
+        keystore::write_signing_account(&signing_account, &wallet_path, &passphrase)?;
+        signing_account
     };
     println!(
         "Signing account: {}",
@@ -137,7 +213,15 @@ fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
 
     let mut wallet_path = config.data_dir.clone();
     wallet_path.push(LNP_NODE_FUNDING_WALLET);
-    let funding_wallet = if !wallet_path.exists() {
+    let backend = chain::connect(config)?;
+    let funding_wallet = if wallet_path.exists() {
+        println!("Funding wallet '{}' ... {}", LNP_NODE_FUNDING_WALLET, "found".progress());
+        FundingWallet::with(&config.chain, wallet_path, &config.electrum_url, backend)?
+    } else if let Some(descriptor) = funding_descriptor {
+        println!("Funding wallet '{}' ... {}", LNP_NODE_FUNDING_WALLET, "importing".action());
+        let descriptor = Descriptor::<TrackingAccount>::from_str(descriptor)?;
+        FundingWallet::new(&config.chain, wallet_path, descriptor, &config.electrum_url, backend)?
+    } else {
         println!("Funding wallet '{}' ... {}", LNP_NODE_FUNDING_WALLET, "creating".action());
         let account_path = &[chain_index, 2][..];
         let node_xpriv = signing_account.account_xpriv();
@@ -159,10 +243,7 @@ fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
             vec![TerminalStep::range(0u16, 1u16), TerminalStep::Wildcard],
         );
         let descriptor = Descriptor::Wpkh(Wpkh::new(account)?);
-        FundingWallet::new(&config.chain, wallet_path, descriptor, &config.electrum_url)?
-    } else {
-        println!("Funding wallet '{}' ... {}", LNP_NODE_FUNDING_WALLET, "found".progress());
-        FundingWallet::with(&config.chain, wallet_path, &config.electrum_url)?
+        FundingWallet::new(&config.chain, wallet_path, descriptor, &config.electrum_url, backend)?
     };
     println!("Funding wallet: {}", funding_wallet.descriptor().promo());
 
@@ -192,3 +273,16 @@ fn init(config: &Config, key_path: &Path) -> Result<(), Error> {
 
     exit(0);
 }
+
+/// Asks for a new passphrase encrypting the master key, requiring to repeat it
+fn read_new_passphrase() -> Result<String, Error> {
+    let passphrase = rpassword::read_password_from_tty(Some("New master key passphrase: "))?;
+    if passphrase.is_empty() {
+        return Err(Error::Other("Master key passphrase must not be empty".to_owned()));
+    }
+    let repeated = rpassword::read_password_from_tty(Some("Repeat the passphrase: "))?;
+    if passphrase != repeated {
+        return Err(Error::Other("Passphrases do not match".to_owned()));
+    }
+    Ok(passphrase)
+}
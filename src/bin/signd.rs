@@ -30,6 +30,8 @@
 #[macro_use]
 extern crate log;
 
+use std::path::PathBuf;
+
 use clap::Parser;
 use lnp_node::signd::{self, Opts};
 use lnp_node::Config;
@@ -42,6 +44,7 @@ fn main() {
     opts.process();
     trace!("Processed arguments: {:?}", &opts);
 
+    let key_file = PathBuf::from(opts.key_opts.key_file.clone());
     let config: Config = opts.shared.clone().into();
     trace!("Daemon configuration: {:?}", &config);
     debug!("MSG RPC socket {}", &config.msg_endpoint);
@@ -56,8 +59,15 @@ fn main() {
         .unwrap_or_exit();
      */
 
+    if let Some(listen) = opts.serve {
+        debug!("Starting remote signer ...");
+        signd::serve(config, &key_file, listen, opts.allow_clients)
+            .expect("Error running remote signer");
+        unreachable!()
+    }
+
     debug!("Starting runtime ...");
-    signd::run(config).expect("Error running signd runtime");
+    signd::run(config, &key_file).expect("Error running signd runtime");
 
     unreachable!()
 }
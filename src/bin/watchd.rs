@@ -30,6 +30,8 @@
 #[macro_use]
 extern crate log;
 
+use std::path::PathBuf;
+
 use clap::Parser;
 use lnp_node::watchd::{self, Opts};
 use lnp_node::Config;
@@ -42,6 +44,7 @@ fn main() {
     opts.process();
     trace!("Processed arguments: {:?}", &opts);
 
+    let key_file = PathBuf::from(opts.key_opts.key_file.clone());
     let config: Config = opts.shared.clone().into();
     trace!("Daemon configuration: {:?}", &config);
     debug!("MSG RPC socket {}", &config.msg_endpoint);
@@ -57,7 +60,7 @@ fn main() {
      */
 
     debug!("Starting runtime ...");
-    watchd::run(config).expect("Error running watchd runtime");
+    watchd::run(config, &key_file).expect("Error running watchd runtime");
 
     unreachable!()
 }
@@ -14,19 +14,31 @@
 
 use amplify::num::u24;
 use amplify::Slice32;
-use bitcoin::Txid;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoin::{BlockHash, OutPoint, Transaction, TxOut, Txid};
+use internet2::addr::InetSocketAddr;
 use internet2::presentation::sphinx::Hop;
 use internet2::NodeAddr;
-use lnp::channel::bolt::{CommonParams, LocalKeyset, PeerParams, Policy};
-use lnp::p2p::legacy::{ChannelId, OpenChannel, PaymentOnion};
+use lnp::channel::bolt::{CommonParams, LocalKeyset, LocalPubkey, PeerParams, Policy};
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::{
+    ActiveChannelId, ChannelAnnouncement, ChannelId, OpenChannel, PaymentOnion, ShortChannelId,
+    TempChannelId,
+};
 use lnp::router::gossip::LocalChannelInfo;
-use lnp_rpc::{ChannelInfo, Failure, OptionDetails, PeerInfo};
+use lnp_rpc::{
+    ChainStatus, ChannelInfo, ChannelSummary, ClosingFeeRange, FeePolicy, NodeEvent, OptionDetails,
+    PeerInfo, PendingTx, RpcError, SyncProgress, TxDepth,
+};
 use psbt::Psbt;
 use strict_encoding::{NetworkDecode, NetworkEncode};
 use wallet::hlc::HashLock;
 use wallet::scripts::PubkeyScript;
 
+use crate::channeld::ChannelBackup;
 use crate::rpc::{ClientId, ServiceId};
+use crate::signd::PolicyError;
+use crate::{AcceptPolicy, FeeSettings};
 
 /// RPC API requests over CTL message bus between LNP Node daemons and from/to clients.
 #[derive(Clone, Debug, Display, From)]
@@ -36,15 +48,78 @@ pub enum CtlMsg {
     #[display("hello()")]
     Hello,
 
+    /// Asks channel daemon to park its state before the node shutdown. Sent from lnpd to
+    /// channeld.
+    #[display("shutdown()")]
+    Shutdown,
+
+    /// Confirms that the channel state is parked and the daemon is ready for the node shutdown.
+    /// Sent from channeld to lnpd.
+    #[display("shutdown_ack()")]
+    ShutdownAck,
+
     // Node connectivity API
     // ---------------------
-    // Sent from lnpd to peerd
+    // Sent from lnpd to peerd, channeld, watchd and signd
     #[display("get_info()")]
     GetInfo,
 
+    /// Asks peer daemon to report its status, as [`CtlMsg::GetInfo`] does, resetting the traffic
+    /// statistics once they are reported
+    #[display("get_info_and_reset()")]
+    GetInfoAndReset,
+
+    /// Asks peer daemon to ping the remote peer if it is silent for the configured interval and
+    /// to close the connection if the peer does not reply to pings. Sent by lnpd periodically.
     #[display("ping_peer()")]
     PingPeer,
 
+    /// Notifies about connection with the remote peer being (re)established, such that all
+    /// channels with the peer can be reestablished. Provides the features negotiated with the
+    /// remote peer in `init` messages, defining which channel types and funding amounts are
+    /// allowed. Sent by peerd to lnpd, which forwards it to the channel daemons and to routed
+    /// relaying the gossip to the connected peers.
+    #[display("peer_reconnected({0}, ...)")]
+    PeerReconnected(NodeAddr, InitFeatures),
+
+    /// Notifies about connection with the remote peer being lost, giving the short name of the
+    /// disconnection cause. Sent by peerd to lnpd, which forwards it to the channel daemons and
+    /// routed, and by lnpd to the same daemons once the peer is disconnected on the client
+    /// request.
+    #[display("peer_disconnected({0}, {1})")]
+    PeerDisconnected(NodeAddr, String),
+
+    /// Orders peer daemon to close the connection. If sent from lnpd, the remote peer gets a
+    /// warning with the given reason first. Channel daemons send it after warning the remote peer
+    /// about the channel, in which case the remote peer is reconnected.
+    #[display("disconnect(\"{0}\")")]
+    Disconnect(String),
+
+    /// Notifies that messages for the remote peer pile up in the outbound queue of the peer
+    /// daemon, so no new channel updates may be started until the peer gets writable. Sent by
+    /// peerd to the channel daemons sending messages to the busy peer.
+    #[display("peer_busy({0})")]
+    PeerBusy(NodeAddr),
+
+    /// Notifies that the outbound queue of the peer daemon has drained after the peer was busy.
+    /// Sent by peerd to the channel daemons notified with `peer_busy`, and by the writer thread
+    /// of peerd to its runtime.
+    #[display("peer_writable({0})")]
+    PeerWritable(NodeAddr),
+
+    /// Reports the remote peer violating the protocol. Sent by peerd to lnpd, which adds the
+    /// penalty to the misbehaviour score of the remote node and bans the node once the score
+    /// crosses the threshold. The listener thread of peerd sends it to its runtime, which
+    /// completes the channel status of the connection.
+    #[display("peer_misbehaved({0})")]
+    PeerMisbehaved(Misbehaviour),
+
+    /// Orders peer daemon to close the connection with the remote peer banned by lnpd, warning
+    /// the peer with the given reason first. The daemon reports the connection as lost with
+    /// `banned` cause.
+    #[display("banned(\"{0}\")")]
+    Banned(String),
+
     // Channel creation API
     // --------------------
     /// Initiates creation of a new channel by a local node. Sent from lnpd to a newly instantiated
@@ -61,19 +136,214 @@ pub enum CtlMsg {
     #[display("construct_funding({0})")]
     ConstructFunding(FundChannel),
 
+    /// Asks lnpd to construct funding PSBT for a channel opened as a dry run, which is returned to
+    /// the client without reserving the funding wallet outputs. Sent from channeld to lnpd
+    /// instead of proposing the channel to the remote peer.
+    #[display("construct_funding_dry_run({0})")]
+    ConstructFundingDryRun(FundChannel),
+
     /// Provides channeld with the information about funding transaction output used to fund the
     /// newly created channel. Sent from lnpd to channeld.
     #[display("funding_constructed(...)")]
     FundingConstructed(Psbt),
 
+    /// Provides channeld with the funding transaction constructed by the user for the channel
+    /// funded from an external wallet. Sent from lnpd to channeld on behalf of a client.
+    #[display("funding_provided(...)")]
+    FundingProvided(Psbt),
+
     /// Signs previously prepared funding transaction and publishes it to bitcoin network. Sent
     /// from channeld to lnpd upon receival of `funding_signed` message from a remote peer.
     #[display("publish_funding({0})")]
     PublishFunding,
 
+    /// Asks lnpd to publish fully signed transaction which does not belong to the funding wallet
+    /// (like cooperative channel closing transaction). Sent from channeld to lnpd.
+    #[display("publish_tx(...)")]
+    PublishTx(Psbt),
+
+    /// Reports that the transaction requested with [`CtlMsg::PublishTx`] is rejected since it
+    /// conflicts with another transaction in the mempool. Sent from lnpd to the requesting
+    /// service.
+    #[display("tx_conflict({0})")]
+    TxConflict(Txid),
+
+    /// Reports that the transaction requested with [`CtlMsg::PublishTx`] is rejected since its
+    /// fee is too low for the mempool acceptance. Sent from lnpd to the requesting service.
+    #[display("tx_fee_too_low({0})")]
+    TxFeeTooLow(Txid),
+
+    /// Reports that the transaction requested with [`CtlMsg::PublishTx`] is not published for
+    /// other reason, including unreachable blockchain backend. Sent from lnpd to the requesting
+    /// service.
+    #[display("publish_failed({txid}, \"{error}\")")]
+    PublishFailed { txid: Txid, error: String },
+
+    /// Hands over transaction published on behalf of the `owner` service to the on-chain
+    /// tracking service, which rebroadcasts it until it gets mined. Sent from lnpd to watchd.
+    #[display("rebroadcast({owner}, ...)")]
+    Rebroadcast { tx: Transaction, owner: ServiceId, feerate_per_kw: Option<u32> },
+
+    /// Reports that the published transaction is neither mined nor present in the mempool of the
+    /// blockchain backend. watchd keeps rebroadcasting it, while the owning workflow may bump its
+    /// fee. Sent from watchd to the service on which behalf the transaction was published.
+    #[display("tx_evicted({0})")]
+    TxEvicted(Txid),
+
+    /// Asks on-chain tracking service for the transactions it rebroadcasts. Sent from lnpd to
+    /// watchd on a client request.
+    #[display("get_pending_txs()")]
+    GetPendingTxs,
+
+    /// Reply to [`CtlMsg::GetPendingTxs`]
+    #[display("pending_txs(...)")]
+    PendingTxs(Vec<PendingTx>),
+
+    /// Abandons opening of the channel on behalf of the client, which is possible only until the
+    /// funding transaction is signed. Sent from lnpd to channeld.
+    #[display("abort_channel({channel_id}, ...)")]
+    AbortChannel { channel_id: ActiveChannelId, enquirer: ClientId },
+
+    /// Fails the channel funded together with other channels by a single transaction, once
+    /// another channel of the batch has failed and the funding transaction will never be
+    /// published. Sent from lnpd to channeld.
+    #[display("batch_failed(\"{0}\")")]
+    BatchFailed(String),
+
+    /// Requests channel state export for the migration to another node. The exported data are
+    /// sent by channeld directly to the client. Sent from lnpd to channeld.
+    #[display("export_channel_state({channel_id}, ...)")]
+    ExportChannelState { channel_id: ChannelId, enquirer: ClientId },
+
+    /// Replaces channel state with the state exported from another node, unless it is older
+    /// than the current one. Sent from lnpd to channeld.
+    #[display("import_channel_state({channel_id}, ...)")]
+    ImportChannelState { channel_id: ChannelId, data: Vec<u8>, enquirer: ClientId },
+
+    /// Requests the latest local commitment transaction and its second-stage HTLC transactions
+    /// signed but not published, for the disaster recovery. The transactions are sent by
+    /// channeld directly to the client. Sent from lnpd to channeld.
+    #[display("dump_commitment({channel_id}, ...)")]
+    DumpCommitment { channel_id: ChannelId, enquirer: ClientId },
+
+    /// Requests the channel basepoints and the revocation secrets received from the remote peer.
+    /// channeld has its local basepoints confirmed by signd, and sends the export directly to
+    /// the client. Sent from lnpd to channeld.
+    #[display("export_channel_secrets({channel_id}, ...)")]
+    ExportChannelSecrets { channel_id: ChannelId, enquirer: ClientId },
+
+    /// Accelerates mining of the published channel funding transaction by spending its change
+    /// output with a child transaction paying for the whole package at the given feerate (in
+    /// satoshi per kw). Sent from lnpd to channeld of the channel funder.
+    #[display("bump_funding({channel_id}, {feerate_per_kw})")]
+    BumpFunding { channel_id: ChannelId, feerate_per_kw: u32 },
+
+    /// Asks funding wallet to construct child transaction spending the change output of the
+    /// funding transaction, such that the funding transaction and its child together pay the
+    /// required feerate. Sent from channeld to lnpd, which replies with
+    /// [`CtlMsg::CpfpConstructed`].
+    #[display("construct_funding_cpfp({txid}, {feerate_per_kw})")]
+    ConstructFundingCpfp { txid: Txid, feerate_per_kw: u32 },
+
+    /// Notifies lnpd that the channel negotiation was abandoned, such that the funding UTXOs
+    /// reserved for the channel can be released. Sent from channeld to lnpd.
+    #[display("funding_released({0})")]
+    FundingReleased(TempChannelId),
+
+    /// Notifies lnpd that the channel daemon has changed its temporary channel id to the
+    /// permanent one, under which it now connects. Sent from channeld to lnpd.
+    #[display("channel_renamed({0})")]
+    ChannelRenamed(TempChannelId),
+
+    /// Reports that the channel daemon is alive, together with the time of the last channel
+    /// activity (as UNIX timestamp) and whether the channel has reached the funding stage. Used
+    /// by lnpd to reap daemons of stale channel negotiations. Sent periodically from channeld to
+    /// lnpd.
+    #[display("channel_heartbeat({channel_id}, {last_activity}, {funding_started})")]
+    ChannelHeartbeat { channel_id: ActiveChannelId, last_activity: u64, funding_started: bool },
+
+    /// Reports that the channel proposed by the remote peer was rejected, such that the
+    /// rejection is accounted in the node statistics. Sent from channeld to lnpd.
+    #[display("channel_rejected({channel_id}, {reason})")]
+    ChannelRejected { channel_id: TempChannelId, reason: String },
+
+    /// Reports event happened to the channel, which is published by lnpd to the event
+    /// subscribers. Sent from channeld to lnpd.
+    #[display("node_event({0})")]
+    NodeEvent(NodeEvent),
+
+    /// Provides static backup of the channel, which has to be added to the channel backup file.
+    /// Sent from channeld to lnpd once the funding outpoint is known and each time the channel
+    /// gets active.
+    #[display("backup_channel({0})")]
+    BackupChannel(ChannelBackup),
+
+    /// Replaces policy for accepting channels proposed by remote peers. Sent to lnpd on behalf
+    /// of the node operator.
+    #[display("set_channel_policy(...)")]
+    SetChannelPolicy(AcceptPolicy),
+
+    // Channel closing API
+    // -------------------
+    /// Initiates closing of the channel on a client request. Sent from lnpd to channeld.
+    #[display("close_channel({channel_id}, force={force}, ...)")]
+    CloseChannel {
+        channel_id: ChannelId,
+        force: bool,
+        fee_range: Option<ClosingFeeRange>,
+        /// Script receiving our funds from the cooperative closing transaction
+        shutdown_script: Option<PubkeyScript>,
+        enquirer: ClientId,
+    },
+
+    /// Closes the channel unilaterally by publishing the latest local commitment transaction.
+    /// Sent from lnpd to channeld.
+    #[display("force_close({0})")]
+    ForceClose(ChannelId),
+
+    /// Accelerates mining of our commitment transaction published during unilateral channel
+    /// close by spending its anchor output with a child transaction paying for the whole package
+    /// at the given feerate (in satoshi per kw). Sent from lnpd to channeld.
+    #[display("bump_commitment({0}, {1})")]
+    BumpCommitment(ChannelId, u32),
+
+    /// Asks funding wallet to add inputs and change output to the child transaction spending
+    /// the anchor output, such that the parent transaction and its child together pay the
+    /// required feerate. Sent from channeld to lnpd, which replies with
+    /// [`CtlMsg::CpfpConstructed`].
+    #[display("construct_cpfp({feerate_per_kw}, ...)")]
+    ConstructCpfp { psbt: Psbt, feerate_per_kw: u32, parent_weight: u64, parent_fee: u64 },
+
+    /// Child transaction spending the anchor output funded by the funding wallet, which is not
+    /// signed yet. Sent from lnpd to channeld.
+    #[display("cpfp_constructed(...)")]
+    CpfpConstructed(Psbt),
+
+    /// Asks funding wallet to add inputs and change output to the zero-fee second-stage HTLC
+    /// transaction of the channel with anchor outputs, such that it pays the required feerate.
+    /// The HTLC input, which witness weight is provided, and the HTLC output are kept first in
+    /// the transaction. Sent from channeld to lnpd, which replies with
+    /// [`CtlMsg::HtlcTxFunded`].
+    #[display("fund_htlc_tx({feerate_per_kw}, ...)")]
+    FundHtlcTx { psbt: Psbt, feerate_per_kw: u32, witness_weight: u64 },
+
+    /// Second-stage HTLC transaction funded by the funding wallet, which is not signed yet.
+    /// Sent from lnpd to channeld.
+    #[display("htlc_tx_funded(...)")]
+    HtlcTxFunded(Psbt),
+
+    // Channel operations API
+    // ----------------------
+    /// Requests channel funder to update feerate of the channel commitment transactions. Sent to
+    /// channeld by a fee estimator service or by lnpd on behalf of the node operator.
+    #[display("set_channel_feerate({channel_id}, {feerate_per_kw})")]
+    SetChannelFeerate { channel_id: ChannelId, feerate_per_kw: u32 },
+
     // On-chain tracking API
     // ---------------------
-    /// Asks on-chain tracking service to send updates on the transaction mining status
+    /// Asks on-chain tracking service to send updates on the transaction mining status. The
+    /// service is notified about each new confirmation until the transaction reaches the given
+    /// depth, and about the transaction getting reorged out of its block at any depth.
     #[display("track({txid}, {depth})")]
     Track { txid: Txid, depth: u32 },
 
@@ -81,10 +351,129 @@ pub enum CtlMsg {
     #[display("untrack({0})")]
     Untrack(Txid),
 
-    /// Reports changes in the mining status for previously requested transaction tracked by an
-    /// on-chain service
-    #[display("tx_found({0})")]
-    TxFound(TxStatus),
+    /// Reports new confirmation of the transaction tracked by an on-chain service, until the
+    /// depth requested with [`CtlMsg::Track`] is reached
+    #[display("tx_confirmed({0})")]
+    TxConfirmed(TxStatus),
+
+    /// Reports that the transaction previously reported as mined by an on-chain tracking
+    /// service got reorged out of its block. The requested depth is re-armed: once the
+    /// transaction is mined again, its confirmations are reported anew until the depth is reached.
+    #[display("tx_reorged({0})")]
+    TxReorged(Txid),
+
+    /// Asks on-chain tracking service for the current number of transaction confirmations,
+    /// without tracking the transaction. Sent from lnpd to watchd on a client request.
+    #[display("get_tx_depth({0})")]
+    GetTxDepth(Txid),
+
+    /// Reply to [`CtlMsg::GetTxDepth`]
+    #[display("tx_depth({0})")]
+    TxDepth(TxDepth),
+
+    /// Asks on-chain tracking service for the status of its blockchain backend. Sent from lnpd
+    /// to watchd on a client request.
+    #[display("get_chain_status()")]
+    GetChainStatus,
+
+    /// Reply to [`CtlMsg::GetChainStatus`]
+    #[display("chain_status(...)")]
+    ChainStatus(ChainStatus),
+
+    /// Reports progress of the compact block filter backend synchronization whenever it
+    /// changes. Sent from watchd to lnpd, which refuses channel operations until the backend is
+    /// synced.
+    #[display("chain_sync({0})")]
+    ChainSync(SyncProgress),
+
+    /// Asks on-chain tracking service for the feerate of the transactions to be mined within the
+    /// given number of blocks, or within the node confirmation target if none is given. Sent to
+    /// watchd by lnpd and channeld.
+    #[display("estimate_fee({confirmation_target:?})")]
+    EstimateFee { confirmation_target: Option<u16> },
+
+    /// Feerate estimation in satoshi per kilo-weight unit, smoothed and limited by the node fee
+    /// settings. Sent by watchd in reply to [`CtlMsg::EstimateFee`], and to lnpd each time the
+    /// estimation for the node confirmation target changes.
+    #[display("fee_estimate({confirmation_target}, {feerate_per_kw})")]
+    FeeEstimate { confirmation_target: u16, feerate_per_kw: u32 },
+
+    /// Updates the fee estimation settings. Sent from lnpd to watchd on a client request.
+    #[display("set_fees({0})")]
+    SetFees(FeeSettings),
+
+    /// Asks on-chain tracking service to watch inputs of the published funding transaction for
+    /// double-spends until the funding transaction is mined. Sent from lnpd to watchd.
+    #[display("track_outpoints(...)")]
+    TrackOutpoints(Vec<OutPoint>),
+
+    /// Reports that inputs of the funding transaction are spent by another mined transaction, so
+    /// the funding transaction will never be mined. Sent from watchd to channeld.
+    #[display("funding_conflict({0})")]
+    FundingConflict(Txid),
+
+    /// Asks on-chain tracking service to report the mined transaction spending the outpoint with
+    /// [`CtlMsg::OutpointSpent`]. If `mempool` is set, the unconfirmed transactions spending the
+    /// outpoint are reported as well, as long as the blockchain backend has access to the
+    /// mempool. Sent from channeld to watchd for the funding output once the channel funding is
+    /// locked, and from lnpd to watchd during channel recovery from the static backup.
+    #[display("watch_spending({outpoint})")]
+    WatchSpending { outpoint: OutPoint, mempool: bool },
+
+    /// Reports the transaction spending the outpoint requested with [`CtlMsg::WatchSpending`].
+    /// The outpoint stays watched until the spending transaction is mined; each unconfirmed
+    /// spending transaction is reported once. Sent from watchd to the requesting channeld or
+    /// lnpd.
+    #[display("outpoint_spent({outpoint}, {spending_txid}, ...)")]
+    OutpointSpent { outpoint: OutPoint, spending_txid: Txid, mined: bool, tx: Transaction },
+
+    /// Asks watchtower client to upload justice data for the revoked remote commitment
+    /// transaction `breach_txid` to the watchtowers. The client has the penalty transaction
+    /// signed by signd and uploads it in encrypted form. Sent from channeld to watchd after
+    /// each `revoke_and_ack` from the remote peer.
+    #[display("register_with_tower({breach_txid}, ...)")]
+    RegisterWithTower { breach_txid: Txid, penalty_psbt: Psbt, per_commitment_secret: SecretKey },
+
+    /// Reports that justice data for the revoked commitment transaction are accepted by all
+    /// watchtowers. Sent from watchd to channeld.
+    #[display("tower_registered({0})")]
+    TowerRegistered(Txid),
+
+    /// Reports failure to upload justice data for the revoked commitment transaction to some of
+    /// the watchtowers; the upload is retried later. Sent from watchd to channeld.
+    #[display("tower_failed({breach_txid}, {error})")]
+    TowerFailed { breach_txid: Txid, error: String },
+
+    /// Asks on-chain tracking service to notify once the blockchain reaches a given height
+    #[display("track_height({0})")]
+    TrackHeight(u32),
+
+    /// Notifies that the blockchain has reached the height previously requested with
+    /// [`CtlMsg::TrackHeight`]; contains the current blockchain height
+    #[display("height_reached({0})")]
+    HeightReached(u32),
+
+    /// Announces new blockchain tip to the services awaiting some blockchain height with
+    /// [`CtlMsg::TrackHeight`]. Sent from watchd once per new block, including blocks replacing
+    /// the reorged ones at the same height.
+    #[display("new_block({height}, {block_hash})")]
+    NewBlock { height: u32, block_hash: BlockHash },
+
+    /// Asks on-chain tracking service for the funding output of the channel announced in the
+    /// gossip. Once the output is found, the service watches it and reports its spending with
+    /// [`CtlMsg::FundingSpent`]. Sent from routed to watchd.
+    #[display("get_funding_output({0})")]
+    GetFundingOutput(ShortChannelId),
+
+    /// Reply to [`CtlMsg::GetFundingOutput`]; the output is absent if it does not exist or is
+    /// already spent
+    #[display("funding_output({short_channel_id}, ...)")]
+    FundingOutput { short_channel_id: ShortChannelId, txout: Option<TxOut> },
+
+    /// Reports that the funding output of the announced channel is spent, so the channel is
+    /// closed. Sent from watchd to routed.
+    #[display("funding_spent({0})")]
+    FundingSpent(ShortChannelId),
 
     // Routing & payments
     /// Request to channel daemon to perform payment using provided route
@@ -103,6 +492,36 @@ pub enum CtlMsg {
     #[display("channel_balance_update({channel_id}, {local_amount_msat}+{remote_amount_msat})")]
     ChannelBalanceUpdate { channel_id: ChannelId, local_amount_msat: u64, remote_amount_msat: u64 },
 
+    /// Notifies lnpd that the channel is operational, such that its routing fee policy has to
+    /// be announced to the network
+    #[display("channel_active({channel_info}, public: {public})")]
+    ChannelActive { channel_info: LocalChannelInfo, public: bool },
+
+    /// Informs channel daemon about the routing fee policy announced for the channel. Sent by
+    /// lnpd once the channel gets active or the policy changes.
+    #[display("set_fee_policy({0})")]
+    SetFeePolicy(FeePolicy),
+
+    /// Asks lnpd to compose `channel_announcement` message for the public channel and to sign it
+    /// with the node key. Sent from channeld to lnpd, which replies with
+    /// [`CtlMsg::AnnouncementSigned`].
+    #[display("compose_announcement({channel_info}, ...)")]
+    ComposeAnnouncement {
+        channel_info: LocalChannelInfo,
+        local_funding_key: PublicKey,
+        remote_funding_key: PublicKey,
+    },
+
+    /// `channel_announcement` message with a local signature added: the node signature, if sent
+    /// by lnpd, or the bitcoin signature, if sent by signd. Sent to channeld.
+    #[display("announcement_signed(...)")]
+    AnnouncementSigned(ChannelAnnouncement),
+
+    /// Reports `channel_announcement` message signed by both channel nodes, which has to be
+    /// broadcast to the network. Sent from channeld to lnpd.
+    #[display("channel_announced(...)")]
+    ChannelAnnounced(ChannelAnnouncement),
+
     // Key-related tasks
     // -----------------
     #[display("sign(...)")]
@@ -111,20 +530,113 @@ pub enum CtlMsg {
     #[display("signed(...)")]
     Signed(Psbt),
 
-    // lnpd -> signd
-    #[display("derive_keyset({0})")]
-    DeriveKeyset(Slice32),
+    /// Signing of the transaction sent with [`CtlMsg::Sign`] has failed since the hardware
+    /// wallet signing the funding wallet inputs has not signed it, for instance because the user
+    /// has not confirmed the transaction on the device in time. Sent by signd to the service
+    /// which has requested the signature.
+    #[display("sign_failed({txid}, \"{error}\")")]
+    SignFailed { txid: Txid, error: String },
+
+    /// Signs withdrawal transaction sending funds from the funding wallet to an external
+    /// address. Withdrawals exceeding the withdrawal limit must be confirmed with the token. Sent
+    /// by lnpd to signd, which replies with [`CtlMsg::Signed`].
+    #[display("sign_withdrawal(...)")]
+    SignWithdrawal { psbt: Psbt, confirmation: Option<String> },
+
+    /// Registers the funding output of a channel with signd, which signs only the funding
+    /// transactions paying to the registered outputs and the channel transactions spending them.
+    /// Sent by channeld to signd once the funding output is known and each time channeld is
+    /// restarted.
+    #[display("expect_funding({script_pubkey}, {amount})")]
+    ExpectFunding { script_pubkey: PubkeyScript, amount: u64 },
+
+    /// Signing of the transaction is refused since it violates the signing policy. Sent by signd
+    /// instead of [`CtlMsg::Signed`] to the service which has requested the signature.
+    #[display("sign_refused({txid}, {error})")]
+    SignRefused { txid: Txid, error: PolicyError },
+
+    /// Signs penalty transaction spending outputs of a revoked remote commitment transaction
+    /// with the revocation private key derived from the per-commitment secret. Sent by channeld
+    /// to signd, which replies with [`CtlMsg::Signed`] containing finalized transaction.
+    #[display("sign_penalty(...)")]
+    SignPenalty { psbt: Psbt, per_commitment_secret: SecretKey },
+
+    /// Signs transaction spending time-locked outputs of our own commitment transaction with the
+    /// key derived from the per-commitment point of that commitment. Sent by channeld to signd,
+    /// which replies with [`CtlMsg::Signed`] containing finalized transaction.
+    #[display("sign_delayed(...)")]
+    SignDelayed { psbt: Psbt, per_commitment_point: PublicKey },
+
+    /// Signs second-stage HTLC transactions spending HTLC outputs of our own commitment
+    /// transaction with the HTLC key derived from the per-commitment point of that commitment.
+    /// Sent by channeld to signd, which replies with [`CtlMsg::Signed`] containing partial
+    /// signatures, since the final witness requires remote peer signature known to channeld.
+    #[display("sign_htlc(...)")]
+    SignHtlc { psbt: Psbt, per_commitment_point: PublicKey },
+
+    /// Signs transaction sweeping our output of the remote commitment transaction of the channel
+    /// recovered from the static backup. The signing key is the payment basepoint, tweaked with
+    /// the remote per-commitment point unless the channel uses static remote key. Sent by lnpd
+    /// to signd, which replies with [`CtlMsg::Signed`] containing partial signatures.
+    #[display("sign_to_remote(...)")]
+    SignToRemote { psbt: Psbt, per_commitment_point: Option<PublicKey> },
+
+    /// Signs `channel_announcement` message with the local funding key of the channel. Sent by
+    /// channeld to signd, which replies with [`CtlMsg::AnnouncementSigned`].
+    #[display("sign_announcement(...)")]
+    SignAnnouncement { announcement: ChannelAnnouncement, funding_key: LocalPubkey },
+
+    // channeld -> lnpd
+    /// Requests a new address from the funding wallet to sweep channel funds to
+    #[display("get_sweep_address()")]
+    GetSweepAddress,
+
+    // lnpd -> channeld
+    #[display("sweep_address({0})")]
+    SweepAddress(PubkeyScript),
+
+    /// Asks signd to derive keyset with the given index for the channel with the given
+    /// temporary id. Sent from lnpd to signd.
+    #[display("derive_channel_keys({channel_id}, {index})")]
+    DeriveChannelKeys { channel_id: Slice32, index: u32 },
 
     // signd -> lnpd
     #[display("keyset({0}, ...)")]
     Keyset(ServiceId, LocalKeyset),
 
+    /// Asks signd for the per-commitment point of our commitment transaction with the given
+    /// number, derived from the per-commitment seed of the channel keyset with the given index.
+    /// Sent from channeld to signd, which replies with [`CtlMsg::CommitmentPoint`].
+    #[display("get_commitment_point({index}, {commitment_number})")]
+    GetCommitmentPoint { index: u32, commitment_number: u64 },
+
+    // signd -> channeld
+    #[display("commitment_point({commitment_number}, {point})")]
+    CommitmentPoint { commitment_number: u64, point: PublicKey },
+
+    /// Asks signd to reveal the per-commitment secret of our revoked commitment transaction with
+    /// the given number, for the channel keyset with the given index. Sent from channeld to
+    /// signd, which replies with [`CtlMsg::CommitmentSecret`].
+    #[display("reveal_commitment_secret({index}, {commitment_number})")]
+    RevealCommitmentSecret { index: u32, commitment_number: u64 },
+
+    /// Per-commitment secret of the revoked commitment transaction, together with the
+    /// per-commitment point of the commitment transaction following the next one, as required
+    /// by `revoke_and_ack` message. Sent from signd to channeld.
+    #[display("commitment_secret({commitment_number}, ...)")]
+    CommitmentSecret { commitment_number: u64, secret: SecretKey, next_point: PublicKey },
+
     // Responses
     // ---------
     #[display("progress(\"{0}\")")]
     #[from]
     Report(Report),
 
+    /// Periodic timer event sent by the daemon timer thread to its runtime over the bridge. Also
+    /// wakes up signd runtime once the hardware wallet completes signing.
+    #[display("timeout()")]
+    Timeout,
+
     /// Error returned back by response-reply type of daemons (like signed) in case if the
     /// operation has failed.
     #[display("error({destination}, \"{error}\")")]
@@ -139,6 +651,27 @@ pub enum CtlMsg {
 
     #[display("channel_info({0})", alt = "{0:#}")]
     ChannelInfo(ChannelInfo),
+
+    /// Reply of watchd to [`CtlMsg::GetInfo`] request made by lnpd: type of the blockchain
+    /// backend, the blockchain height, unless watchd has not yet synced with the backend, the
+    /// error of the last request to the backend, if it has failed, and the progress of the
+    /// compact block filter synchronization, if the backend uses them
+    #[display("chain_info({backend}, {height:?}, {error:?})")]
+    ChainInfo {
+        backend: String,
+        height: Option<u32>,
+        error: Option<String>,
+        sync: Option<SyncProgress>,
+    },
+
+    /// Reply of signd to [`CtlMsg::GetInfo`] request made by lnpd: whether the signer is locked
+    /// and awaits for the passphrase
+    #[display("signer_info({locked})")]
+    SignerInfo { locked: bool },
+
+    /// Reply of channeld to [`CtlMsg::GetInfo`] request made by lnpd for the channel listing
+    #[display("channel_summary({0})", alt = "{0:#}")]
+    ChannelSummary(ChannelSummary),
 }
 
 impl CtlMsg {
@@ -182,6 +715,51 @@ pub struct OpenChannelWith {
 
     /// Channel local keyset
     pub local_keys: LocalKeyset,
+
+    /// Script receiving our funds during cooperative channel closing, which is committed to the
+    /// remote peer upfront
+    pub shutdown_script: Option<PubkeyScript>,
+
+    /// Features negotiated with the remote peer, defining whether channels with funding above
+    /// 2^24-1 satoshis (`option_support_large_channel`) are allowed
+    pub features: InitFeatures,
+
+    /// Start using the channel without waiting for the funding transaction confirmation, if the
+    /// remote peer agrees on that
+    pub zero_conf: bool,
+
+    /// Whether the channel is announced to the network. Sets the `announce_channel` bit of
+    /// `channel_flags` in `open_channel` message, taking precedence over the common parameters.
+    pub announce_channel: bool,
+
+    /// Maximal `to_self_delay` the remote peer may require from us, overriding the node
+    /// configuration
+    pub max_to_self_delay: Option<u16>,
+
+    /// Wallet providing funds for the channel funding transaction
+    pub funding: FundingSource,
+
+    /// Only construct the funding transaction without proposing the channel to the remote peer
+    pub dry_run: bool,
+}
+
+/// Wallet providing funds for the channel funding transaction
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+pub enum FundingSource {
+    /// Funding transaction is constructed, signed and published by lnpd from the node funding
+    /// wallet
+    #[display("internal")]
+    Internal,
+
+    /// Funding transaction is constructed by the user with an external wallet: the channel
+    /// funding address and amount are reported to the client, which provides the funding
+    /// transaction back and publishes it once the remote peer has signed the refund transaction
+    #[display("external")]
+    External,
+}
+
+impl Default for FundingSource {
+    fn default() -> Self { FundingSource::Internal }
 }
 
 /// Request configuring newly launched channeld instance
@@ -208,6 +786,41 @@ pub struct AcceptChannelFrom {
 
     /// Channel local keyset
     pub local_keys: LocalKeyset,
+
+    /// Features negotiated with the remote peer, defining which channel types and whether
+    /// channels with funding above 2^24-1 satoshis (`option_support_large_channel`) are allowed
+    pub features: InitFeatures,
+
+    /// Indicates that the remote peer is trusted, such that the channel can be used without
+    /// waiting for the funding transaction confirmation
+    pub zero_conf: bool,
+
+    /// Policy for accepting channels proposed by remote peers
+    pub accept_policy: AcceptPolicy,
+
+    /// Number of channels the node already has with the remote peer
+    pub peer_channels: u16,
+}
+
+/// Protocol violation by the remote peer of a connection
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{remote_id}@{remote_socket}, {offence}, {penalty}")]
+pub struct Misbehaviour {
+    /// Node id of the remote peer
+    pub remote_id: PublicKey,
+
+    /// Address of the remote peer connection
+    pub remote_socket: InetSocketAddr,
+
+    /// Short name of the violation
+    pub offence: String,
+
+    /// Score added to the misbehaviour score of the remote peer
+    pub penalty: u32,
+
+    /// Whether the connection carries channels with the remote peer, which protects the peer
+    /// from being banned
+    pub has_channels: bool,
 }
 
 /// Request information about constructing funding transaction
@@ -220,7 +833,8 @@ pub struct FundChannel {
     /// Amount of funds to be sent to the funding address
     pub amount: u64,
 
-    /// Fee rate to use for the funding transaction, per kilo-weight unit
+    /// Fee rate to use for the funding transaction, per kilo-weight unit. If absent, lnpd uses
+    /// the latest fee estimation reported by watchd for the node confirmation target.
     pub feerate_per_kw: Option<u32>,
 }
 
@@ -242,14 +856,14 @@ pub struct TxStatus {
     pub pos: u24,
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, NetworkEncode, NetworkDecode)]
 #[display("{client}, {status}")]
 pub struct Report {
     pub client: ClientId,
     pub status: Status,
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, From)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, From)]
 #[derive(NetworkEncode, NetworkDecode)]
 pub enum Status {
     #[display("progress = \"{0}\"")]
@@ -261,5 +875,5 @@ pub enum Status {
 
     #[display("failure = {0}")]
     #[from]
-    Failure(Failure),
+    Failure(RpcError),
 }
@@ -17,7 +17,7 @@ mod reports;
 
 pub use ctl::*;
 use lnp::p2p;
-use lnp_rpc::RpcMsg;
+use lnp_rpc::{RpcMsg, RpcRequest};
 use microservices::esb::BusId;
 use microservices::rpc_connection;
 pub use reports::{IntoSuccessOrFalure, ToProgressOrFalure};
@@ -70,6 +70,12 @@ pub enum BusMsg {
     #[display(inner)]
     #[from]
     Rpc(RpcMsg),
+
+    /// Client requests carrying RPC token, which are authorized before being processed
+    #[api(type = 5)]
+    #[display(inner)]
+    #[from]
+    Request(RpcRequest),
 }
 
 impl rpc_connection::Request for BusMsg {}
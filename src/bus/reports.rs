@@ -12,7 +12,7 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use lnp_rpc::{Failure, OptionDetails, RpcMsg};
+use lnp_rpc::{OptionDetails, RpcError, RpcMsg, ToRpcError};
 
 pub trait ToProgressOrFalure {
     fn to_progress_or_failure(&self) -> RpcMsg;
@@ -23,12 +23,12 @@ pub trait IntoSuccessOrFalure {
 
 impl<E> ToProgressOrFalure for Result<String, E>
 where
-    E: std::error::Error,
+    E: ToRpcError,
 {
     fn to_progress_or_failure(&self) -> RpcMsg {
         match self {
             Ok(val) => RpcMsg::Progress(val.clone()),
-            Err(err) => RpcMsg::Failure(Failure::from(err)),
+            Err(err) => RpcMsg::Failure(RpcError::from(err)),
         }
     }
 }
@@ -37,7 +37,7 @@ impl IntoSuccessOrFalure for Result<String, crate::Error> {
     fn into_success_or_failure(self) -> RpcMsg {
         match self {
             Ok(val) => RpcMsg::Success(OptionDetails::with(val)),
-            Err(err) => RpcMsg::from(Failure::from(&err)),
+            Err(err) => RpcMsg::from(RpcError::from(&err)),
         }
     }
 }
@@ -46,7 +46,7 @@ impl IntoSuccessOrFalure for Result<(), crate::Error> {
     fn into_success_or_failure(self) -> RpcMsg {
         match self {
             Ok(_) => RpcMsg::Success(OptionDetails::new()),
-            Err(err) => RpcMsg::from(Failure::from(&err)),
+            Err(err) => RpcMsg::from(RpcError::from(&err)),
         }
     }
 }
@@ -0,0 +1,389 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Bitcoin Core backend. Transactions are looked up with `getrawtransaction` and `gettxout` RPC
+//! calls; if the node is configured with ZMQ notifications, the blocks and transactions received
+//! from `rawblock` and `rawtx` topics allow to locate mined transactions without `txindex` and
+//! to detect spending of the watched outputs without polling.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use amplify::num::u24;
+use bitcoin::consensus::deserialize;
+use bitcoin::{Block, BlockHash, OutPoint, Transaction, TxOut, Txid};
+use bitcoincore_rpc::{jsonrpc, Auth, Client, RpcApi};
+use internet2::ZMQ_CONTEXT;
+use lnp::p2p::legacy::ShortChannelId;
+
+use super::{BackendStatus, BroadcastError, ChainApi, ChainError};
+use crate::bus::TxStatus;
+use crate::{BitcoindAuth, BitcoindConfig};
+
+/// Number of the recent blocks received over ZMQ which transaction ids are kept for locating
+/// mined transactions
+const RECENT_BLOCKS: usize = 144;
+
+/// Number of the blocks below the blockchain tip searched for a transaction spending an outpoint
+/// when the outpoint is checked for the first time
+const SPENDING_SEARCH_DEPTH: u32 = 144;
+
+/// Bitcoin Core node backend
+pub struct BitcoindBackend {
+    rpc: Client,
+
+    /// JSON-RPC address
+    rpc_url: String,
+
+    /// Socket subscribed to `rawblock` and `rawtx` ZMQ notifications, if configured
+    zmq: Option<zmq::Socket>,
+
+    /// Hashes and transaction ids of the recent blocks received over ZMQ
+    recent_blocks: RefCell<VecDeque<(BlockHash, Vec<Txid>)>>,
+
+    /// Block hashes and positions of the transactions which mining status was requested
+    mined: RefCell<HashMap<Txid, (BlockHash, u32)>>,
+
+    /// Watched outputs, with flags indicating whether a transaction spending the output was
+    /// received over ZMQ since the last check
+    watched: RefCell<HashMap<OutPoint, bool>>,
+
+    /// Height of the last block searched for a transaction spending an outpoint
+    scanned: RefCell<HashMap<OutPoint, u32>>,
+}
+
+impl BitcoindBackend {
+    /// Connects Bitcoin Core RPC interface and subscribes to its ZMQ notifications
+    pub fn connect(config: &BitcoindConfig) -> Result<BitcoindBackend, ChainError> {
+        info!("Connecting Bitcoin Core RPC at {}", config.rpc_url);
+        let auth = match &config.rpc_auth {
+            BitcoindAuth::None => Auth::None,
+            BitcoindAuth::Cookie(path) => Auth::CookieFile(path.clone()),
+            BitcoindAuth::UserPass(user, pass) => Auth::UserPass(user.clone(), pass.clone()),
+        };
+        let rpc = Client::new(&config.rpc_url, auth)?;
+        // Fails early if the node is unreachable or the credentials are wrong
+        let height = rpc.get_block_count()?;
+        debug!("Bitcoin Core node is at height {}", height);
+
+        let zmq = match &config.zmq_endpoint {
+            Some(endpoint) => {
+                info!("Subscribing to Bitcoin Core ZMQ notifications at {}", endpoint);
+                let socket = ZMQ_CONTEXT.socket(zmq::SUB)?;
+                socket.connect(endpoint)?;
+                socket.set_subscribe(b"rawblock")?;
+                socket.set_subscribe(b"rawtx")?;
+                Some(socket)
+            }
+            None => {
+                warn!(
+                    "Bitcoin Core ZMQ notifications are not configured; mined transactions with \
+                     the first output spent can be located only if bitcoind runs with `txindex`"
+                );
+                None
+            }
+        };
+
+        Ok(BitcoindBackend {
+            rpc,
+            rpc_url: config.rpc_url.clone(),
+            zmq,
+            recent_blocks: empty!(),
+            mined: empty!(),
+            watched: empty!(),
+            scanned: empty!(),
+        })
+    }
+
+    /// Processes all ZMQ notifications received since the last call, without blocking
+    fn receive_notifications(&self) -> Result<(), ChainError> {
+        let socket = match &self.zmq {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+        loop {
+            let multipart = match socket.recv_multipart(zmq::DONTWAIT) {
+                Ok(multipart) => multipart,
+                Err(zmq::Error::EAGAIN) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            // Bitcoin Core sends topic, message body and sequence number
+            match (multipart.get(0).map(Vec::as_slice), multipart.get(1)) {
+                (Some(b"rawblock"), Some(body)) => match deserialize::<Block>(body) {
+                    Ok(block) => self.process_block(block),
+                    Err(err) => warn!("Bitcoin Core has sent invalid block over ZMQ: {}", err),
+                },
+                (Some(b"rawtx"), Some(body)) => match deserialize::<Transaction>(body) {
+                    Ok(tx) => self.process_tx(&tx),
+                    Err(err) => warn!("Bitcoin Core has sent invalid tx over ZMQ: {}", err),
+                },
+                _ => debug!("Ignoring unknown ZMQ notification from Bitcoin Core"),
+            }
+        }
+    }
+
+    fn process_block(&self, block: Block) {
+        let block_hash = block.block_hash();
+        trace!("Received block {} over ZMQ", block_hash);
+        for tx in &block.txdata {
+            self.process_tx(tx);
+        }
+        let txids = block.txdata.iter().map(Transaction::txid).collect();
+        let mut recent_blocks = self.recent_blocks.borrow_mut();
+        recent_blocks.push_back((block_hash, txids));
+        while recent_blocks.len() > RECENT_BLOCKS {
+            recent_blocks.pop_front();
+        }
+    }
+
+    fn process_tx(&self, tx: &Transaction) {
+        let mut watched = self.watched.borrow_mut();
+        for txin in &tx.input {
+            if let Some(changed) = watched.get_mut(&txin.previous_output) {
+                *changed = true;
+            }
+        }
+    }
+
+    /// Finds the block and the position of a transaction among the recent blocks received over
+    /// ZMQ
+    fn find_recent(&self, txid: Txid) -> Option<(BlockHash, u32)> {
+        self.recent_blocks.borrow().iter().rev().find_map(|(block_hash, txids)| {
+            let pos = txids.iter().position(|id| *id == txid)?;
+            Some((*block_hash, pos as u32))
+        })
+    }
+
+    /// Finds the block and the position of a mined transaction, returning `None` if the
+    /// transaction is not mined or can't be located
+    fn locate(&self, txid: Txid) -> Result<Option<(BlockHash, u32)>, ChainError> {
+        if let Some(location) = self.mined.borrow().get(&txid) {
+            return Ok(Some(*location));
+        }
+        if let Some(location) = self.find_recent(txid) {
+            return Ok(Some(location));
+        }
+        match self.rpc.get_raw_transaction_info(&txid, None).map(|info| info.blockhash) {
+            Ok(Some(block_hash)) => {
+                return Ok(self.position(block_hash, txid)?.map(|pos| (block_hash, pos)));
+            }
+            // Transaction is in the mempool
+            Ok(None) => return Ok(None),
+            // Without `txindex` bitcoind knows only the transactions in the mempool
+            Err(err) => trace!("Transaction {} is not found by getrawtransaction: {}", txid, err),
+        }
+        // Fallback for the transactions which first output is not spent yet
+        let txout = match self.rpc.get_tx_out(&txid, 0, Some(false))? {
+            Some(txout) if txout.confirmations > 0 => txout,
+            _ => return Ok(None),
+        };
+        let best_height = self.rpc.get_block_header_info(&txout.bestblock)?.height as u32;
+        let height = best_height.saturating_sub(txout.confirmations) + 1;
+        let block_hash = self.rpc.get_block_hash(height as u64)?;
+        Ok(self.position(block_hash, txid)?.map(|pos| (block_hash, pos)))
+    }
+
+    /// Position of the transaction within the block
+    fn position(&self, block_hash: BlockHash, txid: Txid) -> Result<Option<u32>, ChainError> {
+        let block = self.rpc.get_block(&block_hash)?;
+        Ok(block.txdata.iter().position(|tx| tx.txid() == txid).map(|pos| pos as u32))
+    }
+}
+
+impl ChainApi for BitcoindBackend {
+    fn name(&self) -> &'static str { "bitcoind" }
+
+    fn status(&self) -> BackendStatus {
+        // JSON-RPC requests are made over separate HTTP connections, so there is no connection
+        // to lose
+        BackendStatus {
+            server: Some(self.rpc_url.clone()),
+            subscriptions: self.watched.borrow().len(),
+            reconnects: 0,
+            sync: None,
+        }
+    }
+
+    fn height(&self) -> Result<u32, ChainError> {
+        self.receive_notifications()?;
+        Ok(self.rpc.get_block_count()? as u32)
+    }
+
+    fn block_hash(&self, height: u32) -> Result<BlockHash, ChainError> {
+        Ok(self.rpc.get_block_hash(height as u64)?)
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ChainError> {
+        let err = match self.rpc.get_raw_transaction(&txid, None) {
+            Ok(tx) => return Ok(tx),
+            Err(err) => err,
+        };
+        // Without `txindex` mined transactions are returned only if their block is given
+        let location = self.mined.borrow().get(&txid).copied();
+        match location.or_else(|| self.find_recent(txid)) {
+            Some((block_hash, _)) => Ok(self.rpc.get_raw_transaction(&txid, Some(&block_hash))?),
+            None => Err(err.into()),
+        }
+    }
+
+    fn tx_status(&self, txid: Txid, tip: u32) -> Result<Option<TxStatus>, ChainError> {
+        self.receive_notifications()?;
+        let (block_hash, pos) = match self.locate(txid)? {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let header = self.rpc.get_block_header_info(&block_hash)?;
+        // Blocks which are not in the main chain have negative number of confirmations
+        if header.confirmations < 0 {
+            self.mined.borrow_mut().remove(&txid);
+            return Ok(None);
+        }
+        self.mined.borrow_mut().insert(txid, (block_hash, pos));
+        let height = header.height as u32;
+        let depth = tip.saturating_sub(height) + 1;
+        Ok(Some(TxStatus {
+            txid,
+            depth: u24::try_from(depth).unwrap_or(u24::MAX),
+            height: u24::try_from(height).unwrap_or(u24::MAX),
+            pos: u24::try_from(pos).unwrap_or(u24::MAX),
+        }))
+    }
+
+    fn funding_output(
+        &self,
+        short_channel_id: ShortChannelId,
+    ) -> Result<Option<(OutPoint, TxOut)>, ChainError> {
+        let block_height = u32::from(short_channel_id.block_height) as u64;
+        let block_hash = self.rpc.get_block_hash(block_height)?;
+        let block = self.rpc.get_block(&block_hash)?;
+        let tx = match block.txdata.get(u32::from(short_channel_id.tx_index) as usize) {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let vout = short_channel_id.output_index as u32;
+        let txout = match tx.output.get(vout as usize) {
+            Some(txout) => txout.clone(),
+            None => return Ok(None),
+        };
+        let outpoint = OutPoint::new(tx.txid(), vout);
+        Ok(if self.is_unspent(outpoint, &txout)? { Some((outpoint, txout)) } else { None })
+    }
+
+    fn is_unspent(&self, outpoint: OutPoint, _txout: &TxOut) -> Result<bool, ChainError> {
+        Ok(self.rpc.get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?.is_some())
+    }
+
+    fn watch_output(&self, outpoint: OutPoint, _txout: &TxOut) -> Result<(), ChainError> {
+        self.watched.borrow_mut().insert(outpoint, false);
+        Ok(())
+    }
+
+    fn output_changed(&self, outpoint: OutPoint, _txout: &TxOut) -> Result<bool, ChainError> {
+        // Without ZMQ notifications each watched output is checked on every poll
+        if self.zmq.is_none() {
+            return Ok(true);
+        }
+        self.receive_notifications()?;
+        Ok(self
+            .watched
+            .borrow_mut()
+            .get_mut(&outpoint)
+            .map(|changed| std::mem::replace(changed, false))
+            .unwrap_or_default())
+    }
+
+    fn unwatch_output(&self, outpoint: OutPoint, _txout: &TxOut) -> Result<(), ChainError> {
+        self.watched.borrow_mut().remove(&outpoint);
+        Ok(())
+    }
+
+    fn find_spending(&self, outpoint: OutPoint) -> Result<Option<Transaction>, ChainError> {
+        if self.rpc.get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?.is_some() {
+            self.scanned.borrow_mut().remove(&outpoint);
+            return Ok(None);
+        }
+        // Bitcoin Core does not index spending transactions, so we search them in the blocks
+        // mined since the last check
+        let tip = self.rpc.get_block_count()? as u32;
+        let from = match self.scanned.borrow().get(&outpoint) {
+            Some(height) => height + 1,
+            None => tip.saturating_sub(SPENDING_SEARCH_DEPTH),
+        };
+        for height in from..=tip {
+            let block_hash = self.rpc.get_block_hash(height as u64)?;
+            let block = self.rpc.get_block(&block_hash)?;
+            let spending = block
+                .txdata
+                .into_iter()
+                .find(|tx| tx.input.iter().any(|txin| txin.previous_output == outpoint));
+            if let Some(tx) = spending {
+                self.scanned.borrow_mut().remove(&outpoint);
+                return Ok(Some(tx));
+            }
+        }
+        self.scanned.borrow_mut().insert(outpoint, tip);
+        Ok(None)
+    }
+
+    fn find_unconfirmed_spending(
+        &self,
+        outpoint: OutPoint,
+    ) -> Result<Option<Transaction>, ChainError> {
+        let prevouts =
+            serde_json::json!([{ "txid": outpoint.txid.to_string(), "vout": outpoint.vout }]);
+        let spending: serde_json::Value = match self.rpc.call("gettxspendingprevout", &[prevouts]) {
+            Ok(spending) => spending,
+            // Bitcoin Core before v24 does not provide spending of the outputs by mempool
+            // transactions
+            Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(err)))
+                if err.code == -32601 =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match spending[0]["spendingtxid"].as_str().map(Txid::from_str) {
+            Some(Ok(txid)) => Ok(Some(self.rpc.get_raw_transaction(&txid, None)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn in_mempool(&self, tx: &Transaction) -> Result<bool, ChainError> {
+        match self.rpc.get_mempool_entry(&tx.txid()) {
+            Ok(_) => Ok(true),
+            // Transactions missing from the mempool are reported with RPC_INVALID_ADDRESS_OR_KEY
+            Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(err))) if err.code == -5 => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        match self.rpc.send_raw_transaction(tx) {
+            Ok(_) => Ok(()),
+            Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(err))) => {
+                Err(BroadcastError::with_reason(err.message))
+            }
+            Err(err) => Err(BroadcastError::Unreachable(err.to_string())),
+        }
+    }
+
+    fn estimate_fee(&self, target_blocks: u16) -> Result<Option<f64>, ChainError> {
+        let estimation = self.rpc.estimate_smart_fee(target_blocks, None)?;
+        Ok(estimation.fee_rate.map(|fee_rate| fee_rate.as_btc()))
+    }
+}
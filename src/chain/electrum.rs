@@ -0,0 +1,409 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use amplify::num::u24;
+use bitcoin::{BlockHash, OutPoint, Script, Transaction, TxOut, Txid};
+use electrum_client::{Batch, Client as ElectrumClient, ElectrumApi, Param};
+use lnp::p2p::legacy::ShortChannelId;
+
+use super::{
+    BackendStatus, BroadcastError, ChainApi, ChainError, RECONNECT_DELAY_MAX, RECONNECT_DELAY_MIN,
+};
+use crate::bus::TxStatus;
+
+/// Electrum server backend.
+///
+/// The backend keeps the list of the servers and switches to the next one in the list once the
+/// connection with the current server is lost; when none of them is reachable the attempts are
+/// repeated with exponentially growing delay. All output scripts watched for the changes are
+/// re-subscribed after reconnection. Electrum protocol has no subscriptions for transaction ids:
+/// tracked transactions are re-checked on each poll and need no re-subscription.
+pub struct ElectrumBackend {
+    /// Electrum servers in the order of preference
+    servers: Vec<String>,
+
+    /// Connected client together with the index of its server in [`ElectrumBackend::servers`]
+    client: RefCell<Option<(usize, ElectrumClient)>>,
+
+    /// Number of the successful connections to the servers
+    connections: Cell<u64>,
+
+    /// Delay before the next reconnection attempt, if it fails
+    reconnect_delay: Cell<Duration>,
+
+    /// Time before which no reconnection attempts are made
+    next_attempt: Cell<Option<Instant>>,
+
+    /// Output scripts of the watched outputs
+    watched: RefCell<HashMap<OutPoint, Script>>,
+
+    /// Watched output scripts which are to be subscribed with the next batch request
+    pending: RefCell<HashSet<Script>>,
+
+    /// Watched outputs which are to be reported as changed, since their notifications might be
+    /// lost together with the connection
+    changed: RefCell<HashSet<OutPoint>>,
+}
+
+impl ElectrumBackend {
+    /// Connects the first reachable Electrum server from the list. If none of the servers is
+    /// reachable, the backend is created disconnected and retries to connect on further requests.
+    pub fn connect(servers: Vec<String>) -> ElectrumBackend {
+        let backend = ElectrumBackend {
+            servers,
+            client: none!(),
+            connections: none!(),
+            reconnect_delay: Cell::new(RECONNECT_DELAY_MIN),
+            next_attempt: none!(),
+            watched: none!(),
+            pending: none!(),
+            changed: none!(),
+        };
+        if let Err(err) = backend.ensure_connected() {
+            warn!("Unable to connect Electrum server: {}", err);
+        }
+        backend
+    }
+
+    /// Connects the first reachable server from the list unless the backend is already connected
+    /// or the delay before the next reconnection attempt has not passed yet
+    fn ensure_connected(&self) -> Result<(), ChainError> {
+        if self.client.borrow().is_some() {
+            return Ok(());
+        }
+        if matches!(self.next_attempt.get(), Some(time) if Instant::now() < time) {
+            return Err(ChainError::Disconnected);
+        }
+        for (index, server) in self.servers.iter().enumerate() {
+            info!("Connecting Electrum server at {}", server);
+            let client = match ElectrumClient::new(server) {
+                Ok(client) => client,
+                Err(err) => {
+                    warn!("Electrum server {} is unreachable: {}", server, err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.resubscribe(&client) {
+                warn!("Unable to subscribe to Electrum server {}: {}", server, err);
+                continue;
+            }
+            *self.client.borrow_mut() = Some((index, client));
+            self.connections.set(self.connections.get() + 1);
+            self.reconnect_delay.set(RECONNECT_DELAY_MIN);
+            self.next_attempt.set(None);
+            return Ok(());
+        }
+        let delay = self.reconnect_delay.get();
+        warn!("None of Electrum servers is reachable, retrying in {} seconds", delay.as_secs());
+        self.next_attempt.set(Some(Instant::now() + delay));
+        self.reconnect_delay.set((delay * 2).min(RECONNECT_DELAY_MAX));
+        Err(ChainError::Disconnected)
+    }
+
+    /// Subscribes newly connected client to all watched output scripts with a single batch
+    /// request. The watched outputs are reported as changed, since the notifications sent by the
+    /// previous server might have been lost.
+    fn resubscribe(&self, client: &ElectrumClient) -> Result<(), electrum_client::Error> {
+        let watched = self.watched.borrow();
+        if watched.is_empty() {
+            return Ok(());
+        }
+        let scripts = watched.values().collect::<HashSet<_>>();
+        debug!("Subscribing to {} output scripts", scripts.len());
+        client.batch_script_subscribe(scripts)?;
+        self.pending.borrow_mut().clear();
+        self.changed.borrow_mut().extend(watched.keys());
+        Ok(())
+    }
+
+    /// Subscribes to the output scripts of the outputs which started to be watched since the last
+    /// call, using a single batch request
+    fn subscribe_pending(&self) -> Result<(), ChainError> {
+        // Reconnection subscribes all pending scripts by itself
+        self.ensure_connected()?;
+        let scripts = self.pending.borrow().iter().cloned().collect::<Vec<_>>();
+        if scripts.is_empty() {
+            return Ok(());
+        }
+        debug!("Subscribing to {} output scripts", scripts.len());
+        self.with_client(|client| client.batch_script_subscribe(&scripts))?;
+        self.pending.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Performs request to the connected server, reconnecting it if necessary. Connection
+    /// failures drop the client, such that the next request switches to another server.
+    fn with_client<T>(
+        &self,
+        request: impl FnOnce(&ElectrumClient) -> Result<T, electrum_client::Error>,
+    ) -> Result<T, ChainError> {
+        self.ensure_connected()?;
+        let result = match &*self.client.borrow() {
+            Some((_, client)) => request(client),
+            None => return Err(ChainError::Disconnected),
+        };
+        result.map_err(|err| {
+            if is_connection_error(&err) {
+                self.disconnect(&err);
+            }
+            err.into()
+        })
+    }
+
+    fn disconnect(&self, err: &electrum_client::Error) {
+        if let Some((index, _)) = self.client.borrow_mut().take() {
+            warn!("Connection with Electrum server {} is lost: {}", self.servers[index], err);
+        }
+    }
+}
+
+/// Detects errors caused by the loss of the connection with the server, as opposed to the errors
+/// reported by the server itself
+fn is_connection_error(err: &electrum_client::Error) -> bool {
+    matches!(
+        err,
+        electrum_client::Error::IOError(_)
+            | electrum_client::Error::SharedIOError(_)
+            | electrum_client::Error::AllAttemptsErrored(_)
+    )
+}
+
+impl ChainApi for ElectrumBackend {
+    fn name(&self) -> &'static str { "electrum" }
+
+    fn status(&self) -> BackendStatus {
+        BackendStatus {
+            server: self.client.borrow().as_ref().map(|(index, _)| self.servers[*index].clone()),
+            subscriptions: self.watched.borrow().len(),
+            reconnects: self.connections.get().saturating_sub(1),
+            sync: None,
+        }
+    }
+
+    fn height(&self) -> Result<u32, ChainError> {
+        // Output scripts started to be watched since the previous poll are subscribed in a batch
+        self.subscribe_pending()?;
+        self.with_client(|client| Ok(client.block_headers_subscribe()?.height as u32))
+    }
+
+    fn block_hash(&self, height: u32) -> Result<BlockHash, ChainError> {
+        self.with_client(|client| Ok(client.block_header(height as usize)?.block_hash()))
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ChainError> {
+        self.with_client(|client| client.transaction_get(&txid))
+    }
+
+    fn tx_status(&self, txid: Txid, tip: u32) -> Result<Option<TxStatus>, ChainError> {
+        // Electrum protocol does not provide transaction status by its id, so we look it up in
+        // the history of its first output script
+        self.with_client(|client| {
+            let tx = client.transaction_get(&txid)?;
+            let script_pubkey = match tx.output.first() {
+                Some(txout) => &txout.script_pubkey,
+                None => return Ok(None),
+            };
+            let height = match client
+                .script_get_history(script_pubkey)?
+                .into_iter()
+                .find(|entry| entry.tx_hash == txid)
+            {
+                // Zero and negative heights are used for the transactions in mempool
+                Some(entry) if entry.height > 0 => entry.height as u32,
+                _ => return Ok(None),
+            };
+            let merkle = client.transaction_get_merkle(&txid, height as usize)?;
+            let depth = tip.saturating_sub(height) + 1;
+            Ok(Some(TxStatus {
+                txid,
+                depth: u24::try_from(depth).unwrap_or(u24::MAX),
+                height: u24::try_from(height).unwrap_or(u24::MAX),
+                pos: u24::try_from(merkle.pos as u32).unwrap_or(u24::MAX),
+            }))
+        })
+    }
+
+    fn funding_output(
+        &self,
+        short_channel_id: ShortChannelId,
+    ) -> Result<Option<(OutPoint, TxOut)>, ChainError> {
+        // Electrum client does not wrap the method returning transaction id by its position in
+        // the blockchain, so we call it directly
+        let mut batch = Batch::default();
+        batch.raw(s!("blockchain.transaction.id_from_pos"), vec![
+            Param::Usize(u32::from(short_channel_id.block_height) as usize),
+            Param::Usize(u32::from(short_channel_id.tx_index) as usize),
+        ]);
+        let reply = self.with_client(|client| client.batch_call(&batch))?;
+        let txid = match reply.first().and_then(|txid| txid.as_str()).map(Txid::from_str) {
+            Some(Ok(txid)) => txid,
+            _ => return Ok(None),
+        };
+        let vout = short_channel_id.output_index as u32;
+        let tx = self.transaction(txid)?;
+        let txout = match tx.output.get(vout as usize) {
+            Some(txout) => txout.clone(),
+            None => return Ok(None),
+        };
+        let outpoint = OutPoint::new(txid, vout);
+        Ok(if self.is_unspent(outpoint, &txout)? { Some((outpoint, txout)) } else { None })
+    }
+
+    fn is_unspent(&self, outpoint: OutPoint, txout: &TxOut) -> Result<bool, ChainError> {
+        let utxos = self.with_client(|client| client.script_list_unspent(&txout.script_pubkey))?;
+        Ok(utxos
+            .iter()
+            .any(|utxo| utxo.tx_hash == outpoint.txid && utxo.tx_pos == outpoint.vout as usize))
+    }
+
+    fn watch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError> {
+        // Subscription to the changes in the output script history is postponed till the next
+        // poll, such that outputs watched in a row are subscribed with a single batch request
+        let mut watched = self.watched.borrow_mut();
+        if !watched.values().any(|script| *script == txout.script_pubkey) {
+            self.pending.borrow_mut().insert(txout.script_pubkey.clone());
+        }
+        watched.insert(outpoint, txout.script_pubkey.clone());
+        Ok(())
+    }
+
+    fn output_changed(&self, outpoint: OutPoint, txout: &TxOut) -> Result<bool, ChainError> {
+        if self.changed.borrow_mut().remove(&outpoint) {
+            return Ok(true);
+        }
+        self.subscribe_pending()?;
+        if self.pending.borrow().contains(&txout.script_pubkey) {
+            return Ok(false);
+        }
+        let changed = self.with_client(|client| client.script_pop(&txout.script_pubkey))?.is_some();
+        if changed {
+            // Notification is popped once, so other outputs with the same script are marked here
+            self.changed.borrow_mut().extend(
+                self.watched
+                    .borrow()
+                    .iter()
+                    .filter(|(other, script)| {
+                        **other != outpoint && **script == txout.script_pubkey
+                    })
+                    .map(|(other, _)| *other),
+            );
+        }
+        Ok(changed)
+    }
+
+    fn unwatch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError> {
+        self.changed.borrow_mut().remove(&outpoint);
+        {
+            let mut watched = self.watched.borrow_mut();
+            watched.remove(&outpoint);
+            // The script remains subscribed while other watched outputs use it
+            if watched.values().any(|script| *script == txout.script_pubkey) {
+                return Ok(());
+            }
+        }
+        // Scripts awaiting subscription are not subscribed at the server yet
+        let pending = self.pending.borrow_mut().remove(&txout.script_pubkey);
+        if pending || self.client.borrow().is_none() {
+            return Ok(());
+        }
+        self.with_client(|client| client.script_unsubscribe(&txout.script_pubkey))?;
+        Ok(())
+    }
+
+    fn find_spending(&self, outpoint: OutPoint) -> Result<Option<Transaction>, ChainError> {
+        // Electrum protocol does not provide spending transaction for an outpoint, so we look it
+        // up in the history of the spent output script
+        self.with_client(|client| {
+            let prev_tx = client.transaction_get(&outpoint.txid)?;
+            let script_pubkey = match prev_tx.output.get(outpoint.vout as usize) {
+                Some(txout) => &txout.script_pubkey,
+                None => return Ok(None),
+            };
+            for entry in client.script_get_history(script_pubkey)? {
+                // Zero and negative heights are used for the transactions in mempool
+                if entry.height <= 0 || entry.tx_hash == outpoint.txid {
+                    continue;
+                }
+                let tx = client.transaction_get(&entry.tx_hash)?;
+                if tx.input.iter().any(|txin| txin.previous_output == outpoint) {
+                    return Ok(Some(tx));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn find_unconfirmed_spending(
+        &self,
+        outpoint: OutPoint,
+    ) -> Result<Option<Transaction>, ChainError> {
+        self.with_client(|client| {
+            let prev_tx = client.transaction_get(&outpoint.txid)?;
+            let script_pubkey = match prev_tx.output.get(outpoint.vout as usize) {
+                Some(txout) => &txout.script_pubkey,
+                None => return Ok(None),
+            };
+            for entry in client.script_get_history(script_pubkey)? {
+                // Zero and negative heights are used for the transactions in mempool
+                if entry.height > 0 || entry.tx_hash == outpoint.txid {
+                    continue;
+                }
+                let tx = client.transaction_get(&entry.tx_hash)?;
+                if tx.input.iter().any(|txin| txin.previous_output == outpoint) {
+                    return Ok(Some(tx));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn in_mempool(&self, tx: &Transaction) -> Result<bool, ChainError> {
+        let txid = tx.txid();
+        let script_pubkey = match tx.output.first() {
+            Some(txout) => &txout.script_pubkey,
+            None => return Ok(false),
+        };
+        // Zero and negative heights are used for the transactions in mempool
+        self.with_client(|client| {
+            Ok(client
+                .script_get_history(script_pubkey)?
+                .into_iter()
+                .any(|entry| entry.tx_hash == txid && entry.height <= 0))
+        })
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        match self.with_client(|client| client.transaction_broadcast(tx)) {
+            Ok(_) => Ok(()),
+            // Electrum server relays bitcoind rejection reason within the error message
+            Err(ChainError::Electrum(electrum_client::Error::Protocol(reason))) => {
+                Err(BroadcastError::with_reason(reason.to_string()))
+            }
+            Err(err) => Err(BroadcastError::Unreachable(err.to_string())),
+        }
+    }
+
+    fn estimate_fee(&self, target_blocks: u16) -> Result<Option<f64>, ChainError> {
+        let fee_estimate =
+            self.with_client(|client| client.estimate_fee(target_blocks as usize))?;
+        // Electrum servers report -1 if bitcoind has no estimation
+        Ok(if fee_estimate < 0.0 { None } else { Some(fee_estimate) })
+    }
+}
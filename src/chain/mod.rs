@@ -0,0 +1,210 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Blockchain backends used by watchd for tracking transactions and by lnpd for broadcasting
+//! transactions and fee estimation.
+//!
+//! Backend is selected with [`ChainBackend`] node configuration: Electrum server, Bitcoin Core
+//! node accessed through its JSON-RPC interface or bitcoin peers serving BIP 157/158 compact
+//! block filters. The funding wallet scans its funds with Electrum server regardless of the
+//! selected backend.
+
+mod bitcoind;
+mod electrum;
+mod neutrino;
+
+use std::convert::TryInto;
+use std::io;
+use std::time::Duration;
+
+use bitcoin::{BlockHash, OutPoint, Transaction, TxOut, Txid};
+use lnp::p2p::legacy::ShortChannelId;
+use lnpbp::chain::ConversionImpossibleError;
+
+pub use self::bitcoind::BitcoindBackend;
+pub use self::electrum::ElectrumBackend;
+pub use self::neutrino::NeutrinoBackend;
+use crate::bus::TxStatus;
+use crate::rpc::SyncProgress;
+use crate::{ChainBackend, Config};
+
+/// Delay before the first attempt to reconnect the backend after the connection is lost
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+
+/// Maximal delay between the attempts to reconnect the backend
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(300);
+
+/// Errors requesting the blockchain backend
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum ChainError {
+    /// Electrum server error. Details: {0}
+    #[from]
+    Electrum(electrum_client::Error),
+
+    /// Bitcoin Core RPC error. Details: {0}
+    #[from]
+    Bitcoind(bitcoincore_rpc::Error),
+
+    /// Bitcoin Core ZMQ notifications error. Details: {0}
+    #[from]
+    Zmq(zmq::Error),
+
+    /// I/O error communicating bitcoin peers or storing block headers. Details: {0}
+    #[from]
+    Io(io::Error),
+
+    /// bitcoin peer has violated the protocol: {0}
+    PeerProtocol(String),
+
+    /// bitcoin peers have served inconsistent compact block filters: {0}
+    InconsistentFilters(String),
+
+    /// transaction {0} is not known to the compact block filter backend
+    UnknownTx(Txid),
+
+    /// block at height {0} is not yet synced by the compact block filter backend
+    UnknownBlock(u32),
+
+    /// chain is not supported by the compact block filter backend
+    #[from(ConversionImpossibleError)]
+    ChainNotSupported,
+
+    /// none of the backend servers is reachable; reconnection is scheduled
+    Disconnected,
+}
+
+/// Errors broadcasting transaction. Rejections are classified by the reasons reported by
+/// bitcoind, which Electrum servers relay as is.
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BroadcastError {
+    /// transaction conflicts with another transaction in the mempool
+    MempoolConflict,
+
+    /// transaction fee is insufficient for the mempool acceptance: {0}
+    InsufficientFee(String),
+
+    /// transaction is rejected by the blockchain backend: {0}
+    Rejected(String),
+
+    /// blockchain backend is unreachable: {0}
+    Unreachable(String),
+}
+
+impl BroadcastError {
+    /// Classifies transaction rejection by the reason reported by bitcoind
+    pub fn with_reason(reason: String) -> BroadcastError {
+        const FEE_REASONS: [&str; 3] =
+            ["insufficient fee", "min relay fee not met", "mempool min fee not met"];
+        if reason.contains("txn-mempool-conflict") {
+            BroadcastError::MempoolConflict
+        } else if FEE_REASONS.iter().any(|fee_reason| reason.contains(fee_reason)) {
+            BroadcastError::InsufficientFee(reason)
+        } else {
+            BroadcastError::Rejected(reason)
+        }
+    }
+}
+
+/// Status of the connection with the blockchain backend
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BackendStatus {
+    /// Server the backend is connected to, or `None` if the connection is lost
+    pub server: Option<String>,
+
+    /// Number of the outputs watched with [`ChainApi::watch_output`]
+    pub subscriptions: usize,
+
+    /// Number of the reconnections to the backend since it was connected first
+    pub reconnects: u64,
+
+    /// Progress of the compact block filter synchronization, for the backends using them
+    pub sync: Option<SyncProgress>,
+}
+
+/// Blockchain backend
+pub trait ChainApi: Send {
+    /// Name of the backend reported in the node info
+    fn name(&self) -> &'static str;
+
+    /// Status of the connection with the backend
+    fn status(&self) -> BackendStatus;
+
+    /// Requests the current blockchain height
+    fn height(&self) -> Result<u32, ChainError>;
+
+    /// Requests hash of the block at the given height of the main chain
+    fn block_hash(&self, height: u32) -> Result<BlockHash, ChainError>;
+
+    /// Requests transaction by its id
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ChainError>;
+
+    /// Requests mining status of a transaction. Returns `None` if the transaction is not mined.
+    fn tx_status(&self, txid: Txid, tip: u32) -> Result<Option<TxStatus>, ChainError>;
+
+    /// Finds unspent funding output of the channel with the given short channel id
+    fn funding_output(
+        &self,
+        short_channel_id: ShortChannelId,
+    ) -> Result<Option<(OutPoint, TxOut)>, ChainError>;
+
+    /// Checks whether the output is not spent by a mined transaction
+    fn is_unspent(&self, outpoint: OutPoint, txout: &TxOut) -> Result<bool, ChainError>;
+
+    /// Starts watching changes to the output, reported by [`ChainApi::output_changed`]
+    fn watch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError>;
+
+    /// Detects whether the watched output may have been spent since the last check
+    fn output_changed(&self, outpoint: OutPoint, txout: &TxOut) -> Result<bool, ChainError>;
+
+    /// Stops watching changes to the output
+    fn unwatch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError>;
+
+    /// Finds mined transaction spending the outpoint
+    fn find_spending(&self, outpoint: OutPoint) -> Result<Option<Transaction>, ChainError>;
+
+    /// Finds unconfirmed transaction spending the outpoint in the mempool of the backend. Returns
+    /// `None` if the backend has no access to the mempool.
+    fn find_unconfirmed_spending(
+        &self,
+        outpoint: OutPoint,
+    ) -> Result<Option<Transaction>, ChainError>;
+
+    /// Checks whether the unconfirmed transaction is present in the mempool of the backend
+    fn in_mempool(&self, tx: &Transaction) -> Result<bool, ChainError>;
+
+    /// Broadcasts transaction to the bitcoin network
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError>;
+
+    /// Estimates fee rate for the transaction to be mined within the given number of blocks, in
+    /// BTC per kilo-vbyte. Returns `None` if the backend has no estimation.
+    fn estimate_fee(&self, target_blocks: u16) -> Result<Option<f64>, ChainError>;
+}
+
+/// Connects blockchain backend selected by the node configuration
+pub fn connect(config: &Config) -> Result<Box<dyn ChainApi>, ChainError> {
+    Ok(match &config.chain_backend {
+        ChainBackend::Electrum => {
+            let mut servers = vec![config.electrum_url.clone()];
+            servers.extend(config.electrum_fallback_urls.iter().cloned());
+            Box::new(ElectrumBackend::connect(servers))
+        }
+        ChainBackend::Bitcoind(bitcoind) => Box::new(BitcoindBackend::connect(bitcoind)?),
+        ChainBackend::Neutrino(neutrino) => {
+            let network = (&config.chain).try_into()?;
+            Box::new(NeutrinoBackend::new(network, neutrino, &config.data_dir))
+        }
+    })
+}
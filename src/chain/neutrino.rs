@@ -0,0 +1,1309 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Compact block filter backend. Block headers and BIP 158 basic filters are downloaded from the
+//! bitcoin peers serving them according to BIP 157; full blocks are requested only when their
+//! filters match the scripts watched by the node.
+//!
+//! Filters are checked against the filter headers served by the same peer. Once more than one
+//! peer is configured, the filter headers are also compared with the ones served by another
+//! peer, and both peers are banned if they disagree, since it is not known which of them is
+//! honest.
+//!
+//! Scripts of the watched outputs are matched against the filters of the new blocks only, while
+//! the scripts of the tracked transactions and of the outpoints which spending is looked for are
+//! also matched against the filters of all blocks since the wallet birthday. Transactions which
+//! are not mined yet are requested from the peer mempool by their ids, so a transaction mined
+//! before the node has seen it can be located only through the scripts rescanned this way.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use amplify::num::u24;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::encode::{self, serialize, Decodable, Encodable};
+use bitcoin::consensus::params::Params;
+use bitcoin::hash_types::{FilterHash, FilterHeader};
+use bitcoin::hashes::Hash;
+use bitcoin::network::constants::ServiceFlags;
+use bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::network::message_blockdata::{GetHeadersMessage, Inventory};
+use bitcoin::network::message_filter::{GetCFHeaders, GetCFilters};
+use bitcoin::network::message_network::VersionMessage;
+use bitcoin::network::Address;
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::util::bip158::BlockFilter;
+use bitcoin::util::uint::Uint256;
+use bitcoin::{Block, BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxOut, Txid};
+use lnp::p2p::legacy::ShortChannelId;
+
+use super::{
+    BackendStatus, BroadcastError, ChainApi, ChainError, RECONNECT_DELAY_MAX, RECONNECT_DELAY_MIN,
+};
+use crate::bus::TxStatus;
+use crate::rpc::SyncProgress;
+use crate::NeutrinoConfig;
+
+/// File in the node data directory keeping the block headers received from the peers
+const HEADERS_FILE: &str = "neutrino_headers.dat";
+
+/// Size of the consensus-encoded block header
+const HEADER_SIZE: u64 = 80;
+
+/// Number of the recent block headers kept for handling blockchain reorganizations
+const REORG_DEPTH: usize = 144;
+
+/// Maximal number of the headers sent by a peer in reply to a single request
+const MAX_HEADERS: usize = 2000;
+
+/// Maximal number of the compact block filters requested at once, as limited by BIP 157
+const MAX_FILTERS: u32 = 1000;
+
+/// Maximal number of the header and filter batches requested during a single poll, such that
+/// watchd stays responsive while the initial sync spans multiple polls
+const SYNC_BATCHES: usize = 10;
+
+/// Timeout for connecting a peer and for waiting for its reply
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time for which the peers having served inconsistent compact block filters are not connected
+const BAN_DURATION: Duration = Duration::from_secs(24 * 3600);
+
+/// Basic filter type defined by BIP 158
+const BASIC_FILTER: u8 = 0;
+
+/// User agent reported to the peers
+const USER_AGENT: &str = concat!("/lnp-node:", env!("CARGO_PKG_VERSION"), "/");
+
+/// Connection with a bitcoin peer serving compact block filters
+struct Peer {
+    writer: TcpStream,
+
+    reader: BufReader<TcpStream>,
+
+    /// Network magic number
+    magic: u32,
+
+    /// Blockchain height reported by the peer on connection
+    height: u32,
+}
+
+impl Peer {
+    /// Connects the peer and performs the version handshake. Fails if the peer does not serve
+    /// compact block filters and witness data.
+    fn connect(address: &str, network: Network) -> Result<Peer, ChainError> {
+        let socket_addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("unable to resolve {}", address))
+        })?;
+        let stream = TcpStream::connect_timeout(&socket_addr, PEER_TIMEOUT)?;
+        stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+        let mut peer = Peer {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            magic: network.magic(),
+            height: 0,
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() as i64)
+            .unwrap_or_default();
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+        // Version message does not ask the peer to relay its transactions: they are requested by
+        // their ids when needed
+        let version = VersionMessage::new(
+            ServiceFlags::NONE,
+            timestamp,
+            Address::new(&socket_addr, ServiceFlags::NONE),
+            Address::new(&unspecified, ServiceFlags::NONE),
+            rand::thread_rng().next_u64(),
+            USER_AGENT.to_owned(),
+            0,
+        );
+        peer.send(NetworkMessage::Version(version))?;
+
+        let (mut version_received, mut verack_received) = (false, false);
+        while !version_received || !verack_received {
+            match peer.receive()? {
+                NetworkMessage::Version(version) => {
+                    let services = ServiceFlags::COMPACT_FILTERS | ServiceFlags::WITNESS;
+                    if !version.services.has(services) {
+                        return Err(ChainError::PeerProtocol(s!(
+                            "peer does not serve compact block filters"
+                        )));
+                    }
+                    peer.height = version.start_height.max(0) as u32;
+                    peer.send(NetworkMessage::Verack)?;
+                    version_received = true;
+                }
+                NetworkMessage::Verack => verack_received = true,
+                message => trace!("Ignoring {} message received during handshake", message.cmd()),
+            }
+        }
+        Ok(peer)
+    }
+
+    fn send(&mut self, payload: NetworkMessage) -> Result<(), ChainError> {
+        let message = RawNetworkMessage { magic: self.magic, payload };
+        self.writer.write_all(&serialize(&message))?;
+        Ok(())
+    }
+
+    /// Receives the next message from the peer, answering pings on the way
+    fn receive(&mut self) -> Result<NetworkMessage, ChainError> {
+        loop {
+            let message =
+                RawNetworkMessage::consensus_decode(&mut self.reader).map_err(decode_error)?;
+            if message.magic != self.magic {
+                return Err(ChainError::PeerProtocol(s!("peer uses different bitcoin network")));
+            }
+            match message.payload {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(nonce))?,
+                payload => return Ok(payload),
+            }
+        }
+    }
+
+    /// Receives messages until `reply` accepts one of them; other messages are ignored
+    fn receive_reply<T>(
+        &mut self,
+        mut reply: impl FnMut(NetworkMessage) -> Result<T, NetworkMessage>,
+    ) -> Result<T, ChainError> {
+        loop {
+            match reply(self.receive()?) {
+                Ok(value) => return Ok(value),
+                Err(message) => trace!("Ignoring unsolicited {} message", message.cmd()),
+            }
+        }
+    }
+
+    /// Makes sure the peer has processed all previously sent messages
+    fn ping(&mut self) -> Result<(), ChainError> {
+        let nonce = rand::thread_rng().next_u64();
+        self.send(NetworkMessage::Ping(nonce))?;
+        self.receive_reply(|message| match message {
+            NetworkMessage::Pong(pong) if pong == nonce => Ok(()),
+            message => Err(message),
+        })
+    }
+
+    fn fetch_block(&mut self, block_hash: BlockHash) -> Result<Block, ChainError> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(block_hash)]))?;
+        let block = self.receive_reply(|message| match message {
+            NetworkMessage::Block(block) if block.block_hash() == block_hash => Ok(Some(block)),
+            NetworkMessage::NotFound(_) => Ok(None),
+            message => Err(message),
+        })?;
+        match block {
+            Some(block) => check_block(block),
+            None => {
+                Err(ChainError::PeerProtocol(format!("peer has not served block {}", block_hash)))
+            }
+        }
+    }
+
+    /// Requests filter headers of the blocks starting from `start_height` up to the block with
+    /// `stop_hash`, which are `count` blocks. Returns the filter header preceding them together
+    /// with the filter hashes, from which the following filter headers are computed.
+    fn fetch_filter_hashes(
+        &mut self,
+        start_height: u32,
+        stop_hash: BlockHash,
+        count: usize,
+    ) -> Result<(FilterHeader, Vec<FilterHash>), ChainError> {
+        self.send(NetworkMessage::GetCFHeaders(GetCFHeaders {
+            filter_type: BASIC_FILTER,
+            start_height,
+            stop_hash,
+        }))?;
+        let cfheaders = self.receive_reply(|message| match message {
+            NetworkMessage::CFHeaders(cfheaders) if cfheaders.stop_hash == stop_hash => {
+                Ok(cfheaders)
+            }
+            message => Err(message),
+        })?;
+        if cfheaders.filter_type != BASIC_FILTER || cfheaders.filter_hashes.len() != count {
+            return Err(ChainError::PeerProtocol(format!(
+                "peer has sent wrong filter headers for blocks up to {}",
+                stop_hash
+            )));
+        }
+        Ok((cfheaders.previous_filter_header, cfheaders.filter_hashes))
+    }
+
+    /// Requests transaction from the peer mempool. Returns `None` if the peer does not have it.
+    fn fetch_tx(&mut self, txid: Txid) -> Result<Option<Transaction>, ChainError> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessTransaction(txid)]))?;
+        self.receive_reply(|message| match message {
+            NetworkMessage::Tx(tx) if tx.txid() == txid => Ok(Some(tx)),
+            NetworkMessage::NotFound(_) => Ok(None),
+            message => Err(message),
+        })
+    }
+}
+
+/// Checks the transactions of the block received from a peer. Block hash commits only to the
+/// header, so the transactions are checked against the merkle root and their witnesses against
+/// the coinbase commitment.
+fn check_block(block: Block) -> Result<Block, ChainError> {
+    if !block.check_merkle_root() {
+        return Err(ChainError::PeerProtocol(format!(
+            "block {} does not match its merkle root",
+            block.block_hash()
+        )));
+    }
+    if !block.check_witness_commitment() {
+        return Err(ChainError::PeerProtocol(format!(
+            "block {} does not match its witness commitment",
+            block.block_hash()
+        )));
+    }
+    Ok(block)
+}
+
+fn decode_error(err: encode::Error) -> ChainError {
+    match err {
+        encode::Error::Io(err) => ChainError::Io(err),
+        err => ChainError::PeerProtocol(err.to_string()),
+    }
+}
+
+/// Best chain of the block headers received from the peers, kept in a file within the node data
+/// directory. Headers are checked to connect to each other, to have the difficulty required by
+/// the network rules and to satisfy the proof of work for it.
+struct HeaderChain {
+    path: PathBuf,
+
+    network: Network,
+
+    /// Consensus parameters of the network, defining difficulty adjustments
+    params: Params,
+
+    /// Block hashes indexed by their height; empty until the chain is loaded
+    hashes: Vec<BlockHash>,
+
+    /// Timestamps and compact targets of the blocks indexed by their height, for computing
+    /// difficulty of the following blocks
+    timing: Vec<(u32, u32)>,
+
+    /// Headers of the most recent blocks, for comparing the work of the competing branches
+    recent: VecDeque<BlockHeader>,
+}
+
+impl HeaderChain {
+    fn new(path: PathBuf, network: Network) -> HeaderChain {
+        HeaderChain {
+            path,
+            network,
+            params: Params::new(network),
+            hashes: empty!(),
+            timing: empty!(),
+            recent: empty!(),
+        }
+    }
+
+    /// Loads the headers from the file, unless they are already loaded. Headers which do not
+    /// connect to the previous ones or do not have the required difficulty are removed from the
+    /// file together with the following ones.
+    fn load(&mut self) -> Result<(), ChainError> {
+        if !self.hashes.is_empty() {
+            return Ok(());
+        }
+        let genesis = genesis_block(self.network).header;
+        let stored = match File::open(&self.path) {
+            Ok(file) => {
+                let count = file.metadata()?.len() / HEADER_SIZE;
+                let mut reader = BufReader::new(file);
+                for _ in 0..count {
+                    let header =
+                        BlockHeader::consensus_decode(&mut reader).map_err(decode_error)?;
+                    let height = self.hashes.len() as u32;
+                    let valid = match self.hashes.last() {
+                        Some(tip) => {
+                            let bits = required_bits(&self.params, height, header.time, |height| {
+                                self.timing[height as usize]
+                            });
+                            header.prev_blockhash == *tip && header.bits == bits
+                        }
+                        None => header.block_hash() == genesis.block_hash(),
+                    };
+                    if !valid {
+                        warn!(
+                            "Block headers in {} are inconsistent above height {}; dropping them",
+                            self.path.display(),
+                            self.hashes.len()
+                        );
+                        break;
+                    }
+                    self.push(header);
+                }
+                count as usize
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        if self.hashes.is_empty() {
+            self.truncate_file(0)?;
+            self.append_file(&[genesis])?;
+            self.push(genesis);
+        } else if self.hashes.len() < stored {
+            self.truncate_file(self.hashes.len())?;
+        }
+        debug!("Loaded block headers up to height {}", self.height());
+        Ok(())
+    }
+
+    fn push(&mut self, header: BlockHeader) {
+        self.hashes.push(header.block_hash());
+        self.timing.push((header.time, header.bits));
+        self.recent.push_back(header);
+        if self.recent.len() > REORG_DEPTH {
+            self.recent.pop_front();
+        }
+    }
+
+    fn height(&self) -> u32 { self.hashes.len().saturating_sub(1) as u32 }
+
+    fn hash_at(&self, height: u32) -> Option<BlockHash> { self.hashes.get(height as usize).copied() }
+
+    /// Height of the block among the recent ones
+    fn recent_height(&self, block_hash: BlockHash) -> Option<u32> {
+        let tip = self.height();
+        (0..self.recent.len() as u32)
+            .map(|depth| tip - depth)
+            .find(|height| self.hashes[*height as usize] == block_hash)
+    }
+
+    /// Block locator for `getheaders` request: hashes of the recent blocks, followed by the
+    /// hashes of the blocks with exponentially growing distance, down to the genesis block
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut height = self.height();
+        let mut step = 1;
+        loop {
+            locator.push(self.hashes[height as usize]);
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Connects the headers received from a peer to the chain. Returns height of the fork point
+    /// if the headers replace some of the recent blocks.
+    fn connect(&mut self, headers: Vec<BlockHeader>) -> Result<Option<u32>, ChainError> {
+        let first = match headers.first() {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        // Peers send the headers following the last block from the locator on their best chain
+        let fork_height = self.recent_height(first.prev_blockhash).ok_or_else(|| {
+            ChainError::PeerProtocol(format!(
+                "headers starting from {} do not connect to the recent blocks",
+                first.block_hash()
+            ))
+        })?;
+        let mut prev_hash = first.prev_blockhash;
+        for (height, header) in (fork_height + 1..).zip(&headers) {
+            if header.prev_blockhash != prev_hash {
+                return Err(ChainError::PeerProtocol(s!("headers do not connect to each other")));
+            }
+            // Difficulty is computed over the known blocks up to the fork point, followed by the
+            // new ones
+            let bits = required_bits(&self.params, height, header.time, |height| {
+                match height.checked_sub(fork_height + 1) {
+                    Some(index) => (headers[index as usize].time, headers[index as usize].bits),
+                    None => self.timing[height as usize],
+                }
+            });
+            if header.bits != bits {
+                return Err(ChainError::PeerProtocol(format!(
+                    "header {} has target {:#010x} instead of the required {:#010x}",
+                    header.block_hash(),
+                    header.bits,
+                    bits
+                )));
+            }
+            header.validate_pow(&header.target()).map_err(|err| {
+                ChainError::PeerProtocol(format!("header {} is invalid: {}", prev_hash, err))
+            })?;
+            prev_hash = header.block_hash();
+        }
+
+        let replaced = (self.height() - fork_height) as usize;
+        if replaced > 0 {
+            let known_work = chain_work(self.recent.iter().rev().take(replaced));
+            if chain_work(headers.iter()) <= known_work {
+                debug!("Ignoring headers of a branch with less work than the known one");
+                return Ok(None);
+            }
+            warn!(
+                "Blockchain reorganization replaces {} blocks above height {}",
+                replaced, fork_height
+            );
+            self.hashes.truncate(fork_height as usize + 1);
+            self.timing.truncate(fork_height as usize + 1);
+            self.recent.truncate(self.recent.len() - replaced);
+            self.truncate_file(self.hashes.len())?;
+        }
+        self.append_file(&headers)?;
+        for header in headers {
+            self.push(header);
+        }
+        Ok(if replaced > 0 { Some(fork_height) } else { None })
+    }
+
+    fn truncate_file(&self, count: usize) -> Result<(), ChainError> {
+        let file = OpenOptions::new().write(true).create(true).open(&self.path)?;
+        file.set_len(count as u64 * HEADER_SIZE)?;
+        Ok(())
+    }
+
+    fn append_file(&self, headers: &[BlockHeader]) -> Result<(), ChainError> {
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        let mut data = Vec::with_capacity(headers.len() * HEADER_SIZE as usize);
+        for header in headers {
+            header.consensus_encode(&mut data).map_err(decode_error)?;
+        }
+        file.write_all(&data)?;
+        Ok(())
+    }
+}
+
+/// Computes compact target required from the block at the given height and with the given
+/// timestamp by the difficulty adjustment rules of the network. Timestamps and compact targets of
+/// the preceding blocks are provided by `timing` for their heights.
+fn required_bits(
+    params: &Params,
+    height: u32,
+    time: u32,
+    timing: impl Fn(u32) -> (u32, u32),
+) -> u32 {
+    let pow_limit = BlockHeader::compact_target_from_u256(&params.pow_limit);
+    let interval = (params.pow_target_timespan / params.pow_target_spacing) as u32;
+    let (last_time, last_bits) = timing(height - 1);
+
+    if height % interval != 0 {
+        if !params.allow_min_difficulty_blocks {
+            return last_bits;
+        }
+        // Test networks allow blocks with the minimal difficulty once no block was mined for
+        // twice the target spacing; other blocks keep the difficulty of the last block which is
+        // not such one
+        if time as u64 > last_time as u64 + params.pow_target_spacing * 2 {
+            return pow_limit;
+        }
+        let (mut prev_height, mut bits) = (height - 1, last_bits);
+        while prev_height % interval != 0 && bits == pow_limit {
+            prev_height -= 1;
+            bits = timing(prev_height).1;
+        }
+        return bits;
+    }
+    if params.no_pow_retargeting {
+        return last_bits;
+    }
+
+    let (first_time, _) = timing(height - interval);
+    let timespan = params.pow_target_timespan as i64;
+    let actual = (last_time as i64 - first_time as i64).max(timespan / 4).min(timespan * 4);
+    let target = BlockHeader::u256_from_compact_target(last_bits)
+        * Uint256::from_u64(actual as u64).expect("u64 fits into Uint256")
+        / Uint256::from_u64(timespan as u64).expect("u64 fits into Uint256");
+    BlockHeader::compact_target_from_u256(&target.min(params.pow_limit))
+}
+
+/// Computes filter headers following the given one from the filter hashes, as defined by BIP 157
+fn filter_headers(previous: FilterHeader, filter_hashes: &[FilterHash]) -> Vec<FilterHeader> {
+    filter_hashes
+        .iter()
+        .scan(previous, |header, filter_hash| {
+            let mut data = filter_hash[..].to_vec();
+            data.extend_from_slice(&header[..]);
+            *header = FilterHeader::hash(&data);
+            Some(*header)
+        })
+        .collect()
+}
+
+fn chain_work<'a>(headers: impl Iterator<Item = &'a BlockHeader>) -> Uint256 {
+    headers.fold(Uint256([0; 4]), |work, header| work + header.work())
+}
+
+/// Bitcoin peers serving BIP 157/158 compact block filters
+pub struct NeutrinoBackend {
+    network: Network,
+
+    /// Peer addresses in the order of preference
+    peers: Vec<String>,
+
+    /// Height below which the filters are not matched
+    birthday: u32,
+
+    /// Connected peer together with the index of its address in [`NeutrinoBackend::peers`]
+    peer: RefCell<Option<(usize, Peer)>>,
+
+    /// Another connected peer, which filter headers are compared with the ones of
+    /// [`NeutrinoBackend::peer`], together with the index of its address
+    witness: RefCell<Option<(usize, Peer)>>,
+
+    /// Indexes of the peer addresses which are banned, with the time their bans expire
+    banned: RefCell<HashMap<usize, Instant>>,
+
+    /// Highest blockchain height reported by the connected peers
+    peer_height: Cell<u32>,
+
+    /// Number of the successful connections to the peers
+    connections: Cell<u64>,
+
+    /// Delay before the next reconnection attempt, if it fails
+    reconnect_delay: Cell<Duration>,
+
+    /// Time before which no reconnection attempts are made
+    next_attempt: Cell<Option<Instant>>,
+
+    headers: RefCell<HeaderChain>,
+
+    /// Height of the last block which filter is matched against [`NeutrinoBackend::scripts`]
+    filter_height: Cell<u32>,
+
+    /// Scripts matched against the filters of the new blocks
+    scripts: RefCell<HashSet<Script>>,
+
+    /// Scripts which were matched against the filters of all blocks since the wallet birthday
+    rescanned: RefCell<HashSet<Script>>,
+
+    /// Scripts awaiting to be matched against the filters of all blocks since the wallet
+    /// birthday before they join [`NeutrinoBackend::scripts`]
+    rescan_scripts: RefCell<HashSet<Script>>,
+
+    /// Height of the next block which filter is matched against
+    /// [`NeutrinoBackend::rescan_scripts`], if the rescan is in progress
+    rescan_height: Cell<Option<u32>>,
+
+    /// Transactions paying to the matched scripts or spending the outputs of interest, together
+    /// with the transactions received from the peer mempool
+    txs: RefCell<HashMap<Txid, Transaction>>,
+
+    /// Heights and positions of the mined transactions from [`NeutrinoBackend::txs`]
+    mined: RefCell<HashMap<Txid, (u32, u32)>>,
+
+    /// Outputs which spending is detected, with their scripts
+    outputs: RefCell<HashMap<OutPoint, Script>>,
+
+    /// Mined transactions spending the outputs, with their heights
+    spent: RefCell<HashMap<OutPoint, (Txid, u32)>>,
+
+    /// Watched outputs, with flags indicating whether they were spent since the last check
+    watched: RefCell<HashMap<OutPoint, bool>>,
+}
+
+impl NeutrinoBackend {
+    /// Creates backend keeping block headers in the given data directory. Peers are connected
+    /// and the headers are loaded on the first request which needs them.
+    pub fn new(network: Network, config: &NeutrinoConfig, data_dir: &Path) -> NeutrinoBackend {
+        if config.peers.len() < 2 {
+            warn!("Compact block filters are not cross-checked, since a single peer is configured");
+        }
+        NeutrinoBackend {
+            network,
+            peers: config.peers.clone(),
+            birthday: config.birthday,
+            peer: none!(),
+            witness: none!(),
+            banned: empty!(),
+            peer_height: none!(),
+            connections: none!(),
+            reconnect_delay: Cell::new(RECONNECT_DELAY_MIN),
+            next_attempt: none!(),
+            headers: RefCell::new(HeaderChain::new(data_dir.join(HEADERS_FILE), network)),
+            filter_height: Cell::new(config.birthday.saturating_sub(1)),
+            scripts: empty!(),
+            rescanned: empty!(),
+            rescan_scripts: empty!(),
+            rescan_height: none!(),
+            txs: empty!(),
+            mined: empty!(),
+            outputs: empty!(),
+            spent: empty!(),
+            watched: empty!(),
+        }
+    }
+
+    /// Connects the first reachable peer from the list unless the backend is already connected
+    /// or the delay before the next reconnection attempt has not passed yet
+    fn ensure_connected(&self) -> Result<(), ChainError> {
+        if self.peer.borrow().is_some() {
+            return Ok(());
+        }
+        if matches!(self.next_attempt.get(), Some(time) if Instant::now() < time) {
+            return Err(ChainError::Disconnected);
+        }
+        let witness = self.witness.borrow().as_ref().map(|(index, _)| *index);
+        for (index, address) in self.peers.iter().enumerate() {
+            if Some(index) == witness || self.is_banned(index) {
+                continue;
+            }
+            info!("Connecting bitcoin peer at {}", address);
+            match Peer::connect(address, self.network) {
+                Ok(peer) => {
+                    debug!("Bitcoin peer {} is at height {}", address, peer.height);
+                    self.peer_height.set(self.peer_height.get().max(peer.height));
+                    *self.peer.borrow_mut() = Some((index, peer));
+                    self.connections.set(self.connections.get() + 1);
+                    self.reconnect_delay.set(RECONNECT_DELAY_MIN);
+                    self.next_attempt.set(None);
+                    return Ok(());
+                }
+                Err(err) => warn!("Bitcoin peer {} is unavailable: {}", address, err),
+            }
+        }
+        let delay = self.reconnect_delay.get();
+        warn!("None of bitcoin peers is reachable, retrying in {} seconds", delay.as_secs());
+        self.next_attempt.set(Some(Instant::now() + delay));
+        self.reconnect_delay.set((delay * 2).min(RECONNECT_DELAY_MAX));
+        Err(ChainError::Disconnected)
+    }
+
+    /// Connects another peer for cross-checking the filter headers, unless a single peer is
+    /// configured or the other peer is already connected
+    fn ensure_witness(&self) -> Result<(), ChainError> {
+        if self.peers.len() < 2 || self.witness.borrow().is_some() {
+            return Ok(());
+        }
+        let primary = self.peer.borrow().as_ref().map(|(index, _)| *index);
+        for (index, address) in self.peers.iter().enumerate() {
+            if Some(index) == primary || self.is_banned(index) {
+                continue;
+            }
+            info!("Connecting bitcoin peer at {} for cross-checking filter headers", address);
+            match Peer::connect(address, self.network) {
+                Ok(peer) => {
+                    self.peer_height.set(self.peer_height.get().max(peer.height));
+                    *self.witness.borrow_mut() = Some((index, peer));
+                    return Ok(());
+                }
+                Err(err) => warn!("Bitcoin peer {} is unavailable: {}", address, err),
+            }
+        }
+        warn!("None of other bitcoin peers is reachable for cross-checking filter headers");
+        Err(ChainError::Disconnected)
+    }
+
+    fn is_banned(&self, index: usize) -> bool {
+        matches!(self.banned.borrow().get(&index), Some(until) if Instant::now() < *until)
+    }
+
+    fn ban(&self, index: usize) {
+        warn!(
+            "Banning bitcoin peer {} for {} hours",
+            self.peers[index],
+            BAN_DURATION.as_secs() / 3600
+        );
+        self.banned.borrow_mut().insert(index, Instant::now() + BAN_DURATION);
+    }
+
+    /// Performs request to the connected peer, reconnecting it if necessary. Connection failures
+    /// and protocol violations drop the peer, such that the next request switches to another
+    /// one; peers having served inconsistent filters are also banned.
+    fn with_peer<T>(
+        &self,
+        request: impl FnOnce(&mut Peer) -> Result<T, ChainError>,
+    ) -> Result<T, ChainError> {
+        self.ensure_connected()?;
+        let result = match &mut *self.peer.borrow_mut() {
+            Some((_, peer)) => request(peer),
+            None => return Err(ChainError::Disconnected),
+        };
+        result.map_err(|err| {
+            if matches!(
+                err,
+                ChainError::Io(_)
+                    | ChainError::PeerProtocol(_)
+                    | ChainError::InconsistentFilters(_)
+            ) {
+                if let Some((index, _)) = self.peer.borrow_mut().take() {
+                    warn!("Disconnecting bitcoin peer {}: {}", self.peers[index], err);
+                    if let ChainError::InconsistentFilters(_) = err {
+                        self.ban(index);
+                    }
+                }
+            }
+            err
+        })
+    }
+
+    /// Compares filter headers served by the connected peer with the ones served by another
+    /// peer. Both peers are banned if the headers differ; failure of the other peer only drops
+    /// it.
+    fn cross_check(
+        &self,
+        start_height: u32,
+        stop_hash: BlockHash,
+        headers: &[FilterHeader],
+    ) -> Result<(), ChainError> {
+        if self.peers.len() < 2 {
+            return Ok(());
+        }
+        let mut witness = self.witness.borrow_mut();
+        let (index, peer) = witness.as_mut().ok_or(ChainError::Disconnected)?;
+        let index = *index;
+        let result = peer
+            .fetch_filter_hashes(start_height, stop_hash, headers.len())
+            .map(|(previous, filter_hashes)| filter_headers(previous, &filter_hashes));
+        match result {
+            Ok(witness_headers) if witness_headers == headers => Ok(()),
+            Ok(_) => {
+                *witness = None;
+                self.ban(index);
+                Err(ChainError::InconsistentFilters(format!(
+                    "peer {} has sent filter headers for blocks up to {} which differ from the \
+                     ones of the connected peer",
+                    self.peers[index], stop_hash
+                )))
+            }
+            Err(err) => {
+                warn!("Disconnecting bitcoin peer {}: {}", self.peers[index], err);
+                *witness = None;
+                Err(ChainError::Disconnected)
+            }
+        }
+    }
+
+    /// Downloads new block headers and matches compact filters of the new blocks against the
+    /// watched scripts, fetching the matching blocks. The work done at once is limited by
+    /// [`SYNC_BATCHES`].
+    fn sync(&self) -> Result<(), ChainError> {
+        self.headers.borrow_mut().load()?;
+        self.ensure_connected()?;
+        // Without the other peer the filters are not matched, while the headers are still synced
+        let _ = self.ensure_witness();
+        self.with_peer(|peer| {
+            for _ in 0..SYNC_BATCHES {
+                let locator = self.headers.borrow().locator();
+                let request = GetHeadersMessage::new(locator, BlockHash::default());
+                peer.send(NetworkMessage::GetHeaders(request))?;
+                let headers = peer.receive_reply(|message| match message {
+                    NetworkMessage::Headers(headers) => Ok(headers),
+                    message => Err(message),
+                })?;
+                let count = headers.len();
+                if let Some(fork_height) = self.headers.borrow_mut().connect(headers)? {
+                    self.disconnect_blocks(fork_height);
+                }
+                if count < MAX_HEADERS {
+                    break;
+                }
+            }
+
+            let tip = self.headers.borrow().height();
+            let mut batches = SYNC_BATCHES;
+            // Rescan goes first, such that the rescanned scripts join the regular ones without a
+            // gap in the matched filters
+            while let Some(from) = self.rescan_height.get() {
+                let to = self.filter_height.get();
+                if from > to {
+                    let scripts = mem::take(&mut *self.rescan_scripts.borrow_mut());
+                    debug!("Rescan of {} scripts is complete", scripts.len());
+                    self.scripts.borrow_mut().extend(scripts.iter().cloned());
+                    self.rescanned.borrow_mut().extend(scripts);
+                    self.rescan_height.set(None);
+                    break;
+                }
+                if batches == 0 {
+                    return Ok(());
+                }
+                batches -= 1;
+                let scripts = self.rescan_scripts.borrow().clone();
+                let scanned = self.scan_filters(peer, from, to, &scripts)?;
+                self.rescan_height.set(Some(scanned + 1));
+            }
+            while self.filter_height.get() < tip && batches > 0 {
+                batches -= 1;
+                if self.scripts.borrow().is_empty() {
+                    self.filter_height.set(tip);
+                    break;
+                }
+                let from = self.filter_height.get() + 1;
+                let scripts = self.scripts.borrow().clone();
+                let scanned = self.scan_filters(peer, from, tip, &scripts)?;
+                self.filter_height.set(scanned);
+            }
+            Ok(())
+        })
+    }
+
+    /// Matches compact filters of the blocks starting from `from` height against the scripts,
+    /// processing the matching blocks. Filters are checked against the filter headers, which are
+    /// cross-checked with another peer. Returns height of the last matched filter, which does not
+    /// exceed `to`.
+    fn scan_filters(
+        &self,
+        peer: &mut Peer,
+        from: u32,
+        to: u32,
+        scripts: &HashSet<Script>,
+    ) -> Result<u32, ChainError> {
+        let to = to.min(from + MAX_FILTERS - 1).min(self.headers.borrow().height());
+        let hashes = self.headers.borrow().hashes[from as usize..=to as usize].to_vec();
+        let stop_hash = hashes[hashes.len() - 1];
+        trace!("Matching compact filters of blocks {}..={}", from, to);
+        let (previous, filter_hashes) = peer.fetch_filter_hashes(from, stop_hash, hashes.len())?;
+        self.cross_check(from, stop_hash, &filter_headers(previous, &filter_hashes))?;
+        peer.send(NetworkMessage::GetCFilters(GetCFilters {
+            filter_type: BASIC_FILTER,
+            start_height: from,
+            stop_hash,
+        }))?;
+        let mut matched = vec![];
+        for ((height, block_hash), filter_hash) in (from..=to).zip(hashes).zip(filter_hashes) {
+            let cfilter = peer.receive_reply(|message| match message {
+                NetworkMessage::CFilter(cfilter) => Ok(cfilter),
+                message => Err(message),
+            })?;
+            if cfilter.filter_type != BASIC_FILTER || cfilter.block_hash != block_hash {
+                return Err(ChainError::PeerProtocol(format!(
+                    "peer has sent filter for block {} instead of {}",
+                    cfilter.block_hash, block_hash
+                )));
+            }
+            if FilterHash::hash(&cfilter.filter) != filter_hash {
+                return Err(ChainError::InconsistentFilters(format!(
+                    "filter of block {} does not match its filter header",
+                    block_hash
+                )));
+            }
+            let filter = BlockFilter::new(&cfilter.filter);
+            let found = filter
+                .match_any(&block_hash, &mut scripts.iter().map(Script::as_bytes))
+                .map_err(|err| {
+                    ChainError::PeerProtocol(format!("filter of block {}: {}", block_hash, err))
+                })?;
+            if found {
+                matched.push((height, block_hash));
+            }
+        }
+        for (height, block_hash) in matched {
+            trace!("Compact filter of block {} matches watched scripts", block_hash);
+            let block = peer.fetch_block(block_hash)?;
+            self.process_block(height, &block);
+        }
+        Ok(to)
+    }
+
+    /// Registers transactions of the block paying to the matched scripts or spending the outputs
+    /// of interest
+    fn process_block(&self, height: u32, block: &Block) {
+        let scripts = self.scripts.borrow();
+        let rescan_scripts = self.rescan_scripts.borrow();
+        let mut outputs = self.outputs.borrow_mut();
+        let mut txs = self.txs.borrow_mut();
+        for (pos, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.txid();
+            let mut relevant = txs.contains_key(&txid);
+            for txin in &tx.input {
+                if !outputs.contains_key(&txin.previous_output) {
+                    continue;
+                }
+                relevant = true;
+                self.spent.borrow_mut().insert(txin.previous_output, (txid, height));
+                if let Some(changed) = self.watched.borrow_mut().get_mut(&txin.previous_output) {
+                    *changed = true;
+                }
+            }
+            for (vout, txout) in tx.output.iter().enumerate() {
+                let script = &txout.script_pubkey;
+                if scripts.contains(script) || rescan_scripts.contains(script) {
+                    relevant = true;
+                    outputs.insert(OutPoint::new(txid, vout as u32), script.clone());
+                }
+            }
+            if relevant {
+                trace!("Transaction {} is mined at height {}", txid, height);
+                self.mined.borrow_mut().insert(txid, (height, pos as u32));
+                txs.insert(txid, tx.clone());
+            }
+        }
+    }
+
+    /// Forgets transactions mined above the fork point of the blockchain reorganization, such
+    /// that the filters of the new blocks are matched again
+    fn disconnect_blocks(&self, fork_height: u32) {
+        self.mined.borrow_mut().retain(|_, (height, _)| *height <= fork_height);
+        self.spent.borrow_mut().retain(|_, (_, height)| *height <= fork_height);
+        self.filter_height.set(self.filter_height.get().min(fork_height));
+        if let Some(height) = self.rescan_height.get() {
+            self.rescan_height.set(Some(height.min(fork_height + 1)));
+        }
+    }
+
+    /// Schedules matching the script against the filters of all blocks since the wallet
+    /// birthday. Rescan in progress is restarted to include the script.
+    fn rescan(&self, script: Script) {
+        if self.rescanned.borrow().contains(&script)
+            || !self.rescan_scripts.borrow_mut().insert(script)
+        {
+            return;
+        }
+        self.rescan_height.set(Some(self.birthday));
+    }
+
+    fn progress(&self) -> SyncProgress {
+        let header_height = self.headers.borrow().height();
+        let filter_height = self.filter_height.get();
+        SyncProgress {
+            header_height,
+            filter_height: filter_height.min(header_height),
+            birthday: self.birthday,
+            synced: self.connections.get() > 0
+                && header_height >= self.peer_height.get()
+                && filter_height >= header_height
+                && self.rescan_height.get().is_none(),
+        }
+    }
+}
+
+impl ChainApi for NeutrinoBackend {
+    fn name(&self) -> &'static str { "neutrino" }
+
+    fn status(&self) -> BackendStatus {
+        BackendStatus {
+            server: self.peer.borrow().as_ref().map(|(index, _)| self.peers[*index].clone()),
+            subscriptions: self.watched.borrow().len(),
+            reconnects: self.connections.get().saturating_sub(1),
+            sync: Some(self.progress()),
+        }
+    }
+
+    fn height(&self) -> Result<u32, ChainError> {
+        self.sync()?;
+        Ok(self.headers.borrow().height())
+    }
+
+    fn block_hash(&self, height: u32) -> Result<BlockHash, ChainError> {
+        self.headers.borrow().hash_at(height).ok_or(ChainError::UnknownBlock(height))
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ChainError> {
+        if let Some(tx) = self.txs.borrow().get(&txid) {
+            return Ok(tx.clone());
+        }
+        // Peers serve only the transactions from their mempools
+        match self.with_peer(|peer| peer.fetch_tx(txid))? {
+            Some(tx) => {
+                self.txs.borrow_mut().insert(txid, tx.clone());
+                Ok(tx)
+            }
+            None => Err(ChainError::UnknownTx(txid)),
+        }
+    }
+
+    fn tx_status(&self, txid: Txid, tip: u32) -> Result<Option<TxStatus>, ChainError> {
+        let location = self.mined.borrow().get(&txid).copied();
+        if let Some((height, pos)) = location {
+            let depth = tip.saturating_sub(height) + 1;
+            return Ok(Some(TxStatus {
+                txid,
+                depth: u24::try_from(depth).unwrap_or(u24::MAX),
+                height: u24::try_from(height).unwrap_or(u24::MAX),
+                pos: u24::try_from(pos).unwrap_or(u24::MAX),
+            }));
+        }
+        // Transaction gets located by the filters matching its first output script
+        let tx = match self.transaction(txid) {
+            Ok(tx) => tx,
+            Err(ChainError::UnknownTx(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if let Some(txout) = tx.output.first() {
+            self.rescan(txout.script_pubkey.clone());
+        }
+        Ok(None)
+    }
+
+    fn funding_output(
+        &self,
+        short_channel_id: ShortChannelId,
+    ) -> Result<Option<(OutPoint, TxOut)>, ChainError> {
+        self.headers.borrow_mut().load()?;
+        let block_height = u32::from(short_channel_id.block_height);
+        let block_hash = match self.headers.borrow().hash_at(block_height) {
+            Some(block_hash) => block_hash,
+            None => return Ok(None),
+        };
+        let block = self.with_peer(|peer| peer.fetch_block(block_hash))?;
+        let tx = match block.txdata.get(u32::from(short_channel_id.tx_index) as usize) {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let vout = short_channel_id.output_index as u32;
+        let txout = match tx.output.get(vout as usize) {
+            Some(txout) => txout.clone(),
+            None => return Ok(None),
+        };
+        let outpoint = OutPoint::new(tx.txid(), vout);
+        Ok(if self.is_unspent(outpoint, &txout)? { Some((outpoint, txout)) } else { None })
+    }
+
+    fn is_unspent(&self, outpoint: OutPoint, txout: &TxOut) -> Result<bool, ChainError> {
+        // Spending is detected only with the filters of the blocks matched after the output has
+        // been registered here, so the outputs spent before are reported unspent
+        self.outputs.borrow_mut().insert(outpoint, txout.script_pubkey.clone());
+        self.scripts.borrow_mut().insert(txout.script_pubkey.clone());
+        Ok(!self.spent.borrow().contains_key(&outpoint))
+    }
+
+    fn watch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError> {
+        self.outputs.borrow_mut().insert(outpoint, txout.script_pubkey.clone());
+        self.scripts.borrow_mut().insert(txout.script_pubkey.clone());
+        self.watched.borrow_mut().insert(outpoint, false);
+        Ok(())
+    }
+
+    fn output_changed(&self, outpoint: OutPoint, _txout: &TxOut) -> Result<bool, ChainError> {
+        Ok(self
+            .watched
+            .borrow_mut()
+            .get_mut(&outpoint)
+            .map(|changed| mem::replace(changed, false))
+            .unwrap_or_default())
+    }
+
+    fn unwatch_output(&self, outpoint: OutPoint, txout: &TxOut) -> Result<(), ChainError> {
+        self.watched.borrow_mut().remove(&outpoint);
+        let mut outputs = self.outputs.borrow_mut();
+        outputs.remove(&outpoint);
+        let script = &txout.script_pubkey;
+        // Rescanned scripts belong to the transactions of the node and stay matched
+        if !outputs.values().any(|other| other == script)
+            && !self.rescanned.borrow().contains(script)
+        {
+            self.scripts.borrow_mut().remove(script);
+        }
+        Ok(())
+    }
+
+    fn find_spending(&self, outpoint: OutPoint) -> Result<Option<Transaction>, ChainError> {
+        let spending = self.spent.borrow().get(&outpoint).map(|(txid, _)| *txid);
+        if let Some(txid) = spending {
+            return self.transaction(txid).map(Some);
+        }
+        if !self.outputs.borrow().contains_key(&outpoint) {
+            // Spending transaction gets located by the filters matching the spent output script
+            let prev_tx = match self.transaction(outpoint.txid) {
+                Ok(tx) => tx,
+                Err(ChainError::UnknownTx(_)) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let script = match prev_tx.output.get(outpoint.vout as usize) {
+                Some(txout) => txout.script_pubkey.clone(),
+                None => return Ok(None),
+            };
+            self.outputs.borrow_mut().insert(outpoint, script.clone());
+            self.rescan(script);
+        }
+        Ok(None)
+    }
+
+    fn find_unconfirmed_spending(
+        &self,
+        _outpoint: OutPoint,
+    ) -> Result<Option<Transaction>, ChainError> {
+        // Peers serve transactions from their mempools only by the transaction id
+        Ok(None)
+    }
+
+    fn in_mempool(&self, tx: &Transaction) -> Result<bool, ChainError> {
+        // Peers serve only the transactions from their mempools
+        Ok(self.with_peer(|peer| peer.fetch_tx(tx.txid()))?.is_some())
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        // Peers do not report rejected transactions, so only their delivery is confirmed
+        let result = self.with_peer(|peer| {
+            peer.send(NetworkMessage::Tx(tx.clone()))?;
+            peer.ping()
+        });
+        match result {
+            Ok(()) => {
+                self.txs.borrow_mut().insert(tx.txid(), tx.clone());
+                Ok(())
+            }
+            Err(err) => Err(BroadcastError::Unreachable(err.to_string())),
+        }
+    }
+
+    fn estimate_fee(&self, _target_blocks: u16) -> Result<Option<f64>, ChainError> {
+        // Bitcoin peers provide no fee estimation
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    fn headers_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("lnp-node-neutrino-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Mines header following the given one with the given compact target
+    fn mine(prev: &BlockHeader, bits: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash: prev.block_hash(),
+            merkle_root: prev.merkle_root,
+            time: prev.time + 600,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    #[test]
+    fn mainnet_retarget() {
+        // First difficulty adjustment of the bitcoin mainnet
+        let params = Params::new(Network::Bitcoin);
+        let timing = |height: u32| match height {
+            30240 => (1261130161, 0x1d00ffff),
+            32255 => (1262152739, 0x1d00ffff),
+            _ => unreachable!(),
+        };
+        assert_eq!(required_bits(&params, 32256, 1262153464, timing), 0x1d00d86a);
+
+        // Blocks within the adjustment period keep the difficulty
+        let timing = |_: u32| (1262153464, 0x1d00d86a);
+        assert_eq!(required_bits(&params, 32257, 1262154000, timing), 0x1d00d86a);
+    }
+
+    /// Timing of the first adjustment period with the given timestamps of its first and last
+    /// blocks, which all have the given compact target
+    fn period(first_time: u32, last_time: u32, bits: u32) -> impl Fn(u32) -> (u32, u32) {
+        move |height| if height == 0 { (first_time, bits) } else { (last_time, bits) }
+    }
+
+    #[test]
+    fn retarget_limits() {
+        let params = Params::new(Network::Bitcoin);
+        // Adjustment is limited to four times in each direction
+        let timing = period(0, 100_000_000, 0x1c00ffff);
+        assert_eq!(required_bits(&params, 2016, 100_000_600, timing), 0x1c03fffc);
+        assert_eq!(required_bits(&params, 2016, 600, period(0, 1, 0x1d00ffff)), 0x1c3fffc0);
+
+        // Target never exceeds the proof of work limit
+        let timing = period(0, 100_000_000, 0x1d00ffff);
+        assert_eq!(required_bits(&params, 2016, 100_000_600, timing), 0x1d00ffff);
+    }
+
+    #[test]
+    fn testnet_min_difficulty() {
+        let params = Params::new(Network::Testnet);
+        let timing = |height: u32| match height {
+            2016 => (1000, 0x1c0ffff0),
+            2017..=2019 => (1000 + (height - 2016) * 1300, 0x1d00ffff),
+            _ => unreachable!(),
+        };
+        // Block mined more than 20 minutes after the previous one may have the minimal difficulty
+        assert_eq!(required_bits(&params, 2020, 4900 + 1201, timing), 0x1d00ffff);
+        // Otherwise it has the difficulty of the last block which does not have the minimal one
+        assert_eq!(required_bits(&params, 2020, 4900 + 600, timing), 0x1c0ffff0);
+    }
+
+    #[test]
+    fn easier_target() {
+        let path = headers_file("easier");
+        let mut chain = HeaderChain::new(path.clone(), Network::Bitcoin);
+        chain.load().unwrap();
+        let header = mine(&genesis_block(Network::Bitcoin).header, 0x207fffff);
+        assert!(matches!(chain.connect(vec![header]), Err(ChainError::PeerProtocol(_))));
+        assert_eq!(chain.height(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn regtest_headers() {
+        let path = headers_file("regtest");
+        let mut chain = HeaderChain::new(path.clone(), Network::Regtest);
+        chain.load().unwrap();
+        let first = mine(&genesis_block(Network::Regtest).header, 0x207fffff);
+        let second = mine(&first, 0x207fffff);
+        assert_eq!(chain.connect(vec![first, second]).unwrap(), None);
+        assert_eq!(chain.height(), 2);
+
+        // Headers stored in the file pass the same checks once loaded
+        let mut chain = HeaderChain::new(path.clone(), Network::Regtest);
+        chain.load().unwrap();
+        assert_eq!(chain.hash_at(2), Some(second.block_hash()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn filter_header_chain() {
+        // Basic filter of the testnet genesis block from BIP 158 test vectors
+        let filter_hash = FilterHash::hash(&Vec::<u8>::from_hex("019dfca8").unwrap());
+        let previous = FilterHeader::from_inner([0u8; 32]);
+        let expected = FilterHeader::from_hex(
+            "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+        )
+        .unwrap();
+        assert_eq!(filter_headers(previous, &[filter_hash]), vec![expected]);
+
+        // Each header commits to the previous one
+        let headers = filter_headers(previous, &[filter_hash, filter_hash]);
+        assert_eq!(headers[0], expected);
+        assert_eq!(headers[1..], filter_headers(expected, &[filter_hash])[..]);
+        assert!(filter_headers(previous, &[]).is_empty());
+    }
+
+    #[test]
+    fn block_commitments() {
+        let genesis = genesis_block(Network::Bitcoin);
+        assert!(check_block(genesis.clone()).is_ok());
+
+        // Transaction which is not committed to by the merkle root
+        let mut block = genesis.clone();
+        block.txdata[0].lock_time = 1;
+        assert!(matches!(check_block(block), Err(ChainError::PeerProtocol(_))));
+
+        // Witness which is not committed to by the coinbase transaction
+        let mut block = genesis;
+        let mut tx = block.txdata[0].clone();
+        tx.lock_time = 1;
+        tx.input[0].witness = vec![vec![1]];
+        block.txdata.push(tx);
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        assert!(block.check_merkle_root());
+        assert!(matches!(check_block(block), Err(ChainError::PeerProtocol(_))));
+    }
+}
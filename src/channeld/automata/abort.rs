@@ -0,0 +1,677 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use lnp::channel::bolt::Lifecycle;
+use lnp::p2p::legacy::ActiveChannelId;
+use lnp::Extension;
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use super::bolt3::{
+    anchor_script, derive_pubkey, derive_revocation_pubkey, has_anchors, to_local_script,
+};
+use super::htlc::{self, HtlcResolution};
+use super::Error;
+use crate::automata::{Event, StateMachine};
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::service::LogStyle;
+use crate::{Endpoints, Responder};
+
+/// Weight of the transaction sweeping to-local output of our commitment transaction into a
+/// single P2WPKH output, in weight units
+const SWEEP_TX_WEIGHT: u64 = 484;
+
+/// Weight of the witness spending 2-of-2 multisig funding output by the commitment transaction,
+/// in weight units
+const COMMITMENT_WITNESS_WEIGHT: u64 = 222;
+
+/// Number of blocks after which unconfirmed sweep transaction gets fee-bumped
+const SWEEP_BUMP_BLOCKS: u32 = 6;
+
+/// Unilateral channel closing workflow
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+pub enum ChannelAbort {
+    /// signing latest local commitment transaction
+    #[display("SIGNING")]
+    Signing,
+
+    /// commitment transaction is published, awaiting for it to be mined
+    #[display("PUBLISHED")]
+    Published,
+
+    /// commitment transaction is mined, awaiting for the to-local output timelock to expire
+    #[display("MATURING")]
+    Maturing,
+
+    /// signing transaction sweeping to-local output
+    #[display("SWEEPING")]
+    Sweeping,
+
+    /// sweep transaction is published, awaiting for it to be mined
+    #[display("SWEPT")]
+    Swept,
+
+    /// to-local output is swept, awaiting for the pending HTLCs to be resolved on-chain
+    #[display("RESOLVING")]
+    Resolving,
+}
+
+/// Data for sweeping to-local output of our commitment transaction published during unilateral
+/// channel close
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct SweepSession {
+    /// To-local output of the published commitment transaction
+    pub outpoint: OutPoint,
+
+    /// Value and script pubkey of the to-local output
+    pub prevout: TxOut,
+
+    /// Witness script of the to-local output
+    pub witness_script: Script,
+
+    /// Our per-commitment point for the published commitment transaction, required to derive
+    /// the key spending to-local output
+    pub per_commitment_point: PublicKey,
+
+    /// Blockchain height at which to-local output becomes spendable, known once the commitment
+    /// transaction is mined
+    pub spendable_height: Option<u32>,
+
+    /// Current blockchain height, as it was last reported by the on-chain tracking service
+    pub height: Option<u32>,
+
+    /// Script receiving swept funds, provided by the funding wallet
+    pub sweep_script: Option<PubkeyScript>,
+
+    /// Feerate for the sweep transaction, which is increased with each fee bump
+    pub feerate_per_kw: u32,
+
+    /// Blockchain height at which the latest version of the sweep transaction was published
+    pub published_height: Option<u32>,
+
+    /// Ids of all versions of the sweep transaction published so far
+    pub sweep_txids: Vec<Txid>,
+}
+
+impl StateMachine<BusMsg, Runtime> for ChannelAbort {
+    type Error = Error;
+
+    fn next(
+        self,
+        event: Event<BusMsg>,
+        runtime: &mut Runtime,
+    ) -> Result<Option<Self>, Self::Error> {
+        let channel_id = runtime.state.channel.active_channel_id();
+        debug!("ChannelAbort {:#} received {} event", channel_id, event.message);
+        let state = match self {
+            ChannelAbort::Signing => finish_signing(event, runtime).map(Some),
+            ChannelAbort::Published => finish_published(event, runtime),
+            ChannelAbort::Maturing | ChannelAbort::Sweeping | ChannelAbort::Swept => {
+                // HTLC outputs are resolved independently from sweeping to-local output
+                if htlc::process(runtime, event.endpoints, &event.message)? {
+                    return Ok(Some(self));
+                }
+                finish_sweeping(event, runtime, self)
+            }
+            ChannelAbort::Resolving => finish_resolving(event, runtime),
+        }?;
+        let state = match state {
+            Some(state) => state,
+            None => {
+                info!("ChannelAbort {:#} has completed its work", channel_id);
+                return Ok(None);
+            }
+        };
+        info!("ChannelAbort {:#} switched to {} state", channel_id, state);
+        Ok(Some(state))
+    }
+}
+
+impl ChannelAbort {
+    /// Computes channel lifecycle stage for the current channel aborting workflow stage
+    pub fn lifecycle(&self) -> Lifecycle { Lifecycle::Aborting }
+}
+
+// State transitions:
+
+impl ChannelAbort {
+    /// Starts unilateral channel closing by signing the latest local commitment transaction
+    pub fn with(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<ChannelAbort, Error> {
+        let commitment_psbt = compose_local_commitment(runtime)?;
+        let txid = commitment_psbt.global.unsigned_tx.txid();
+        trace!("Local commitment transaction: {:#?}", commitment_psbt);
+        debug!("Local commitment transaction id is {}", txid);
+
+        runtime.send_ctl(endpoints, ServiceId::Signer, CtlMsg::Sign(commitment_psbt))?;
+        Ok(ChannelAbort::Signing)
+    }
+
+    /// Re-issues outstanding requests of the to-local output sweeping and HTLC resolution stages
+    /// after the channel daemon restart
+    pub fn resume_sweeping(
+        self,
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+    ) -> Result<(), Error> {
+        if matches!(self, ChannelAbort::Signing | ChannelAbort::Published) {
+            return Ok(());
+        }
+        htlc::resume(runtime, endpoints)?;
+        let session = match runtime.state.sweep.clone() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        match self {
+            ChannelAbort::Maturing => {
+                if let Some(spendable_height) = session.spendable_height {
+                    let message = CtlMsg::TrackHeight(spendable_height);
+                    runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+                }
+                if session.sweep_script.is_none() {
+                    runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+                }
+            }
+            ChannelAbort::Sweeping => {
+                sign_sweep(endpoints, runtime)?;
+            }
+            ChannelAbort::Swept => {
+                for txid in &session.sweep_txids {
+                    let message = CtlMsg::Track { txid: *txid, depth: 1 };
+                    runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+                }
+                let height = session.published_height.or(session.height).unwrap_or_default();
+                let message = CtlMsg::TrackHeight(height + SWEEP_BUMP_BLOCKS);
+                runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+            }
+            // HTLC resolution requests are already re-issued above
+            ChannelAbort::Signing | ChannelAbort::Published | ChannelAbort::Resolving => {}
+        }
+        Ok(())
+    }
+
+    /// Construct information message for error and client reporting
+    pub fn info_message(&self, channel_id: ActiveChannelId) -> String {
+        match self {
+            ChannelAbort::Signing => format!(
+                "{} commitment transaction to force-close channel {:#}",
+                "Signing".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelAbort::Published => format!(
+                "{} commitment transaction for channel {:#} to be mined",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelAbort::Maturing => format!(
+                "{} commitment transaction for channel {:#} to mature",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelAbort::Sweeping => format!(
+                "{} transaction sweeping to-local output of channel {:#}",
+                "Signing".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelAbort::Swept => format!(
+                "{} sweep transaction for channel {:#} to be mined",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelAbort::Resolving => format!(
+                "{} HTLCs of channel {:#} to be resolved on-chain",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+        }
+    }
+}
+
+/// Constructs the latest local commitment transaction carrying the remote peer signature, which
+/// becomes fully signed once signd adds our signature
+pub(super) fn compose_local_commitment(runtime: &mut Runtime) -> Result<Psbt, Error> {
+    let remote_sig = runtime.state.remote_commitment_sig.ok_or(Error::InvalidState {
+        operation: "sign commitment transaction without remote commitment signature",
+        current_state: runtime.state.state_machine.lifecycle(),
+    })?;
+
+    let channel = &mut runtime.state.channel;
+    let mut commitment_psbt = channel.commitment_tx(false)?;
+    let remote_pubkey = channel.constructor().remote_keys().funding_pubkey;
+    let mut sig = remote_sig.serialize_der().to_vec();
+    sig.push(bitcoin::SigHashType::All.as_u32() as u8);
+    commitment_psbt
+        .inputs
+        .get_mut(0)
+        .expect("BOLT commitment always has a single input")
+        .partial_sigs
+        .insert(bitcoin::PublicKey::new(remote_pubkey), sig);
+    Ok(commitment_psbt)
+}
+
+fn finish_signing(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelAbort, Error> {
+    let commitment_psbt = match event.message {
+        BusMsg::Ctl(CtlMsg::Signed(psbt)) => psbt,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Aborting, event.source))
+        }
+    };
+
+    runtime.state.sweep = sweep_session(runtime, &commitment_psbt)?;
+    runtime.state.htlcs = HtlcResolution::with(runtime, &commitment_psbt)?;
+
+    let txid = commitment_psbt.global.unsigned_tx.txid();
+    info!("{} commitment transaction {}", "Publishing".promo(), txid.promoter());
+    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::PublishTx(commitment_psbt))?;
+
+    runtime.state.commitment_txid = Some(txid);
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+
+    Ok(ChannelAbort::Published)
+}
+
+fn finish_published(
+    event: Event<BusMsg>,
+    runtime: &mut Runtime,
+) -> Result<Option<ChannelAbort>, Error> {
+    let tx_status = match event.message {
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status))
+            if Some(tx_status.txid) == runtime.state.commitment_txid =>
+        {
+            tx_status
+        }
+        BusMsg::Ctl(CtlMsg::BumpCommitment(_, feerate_per_kw)) => {
+            bump_commitment(event.endpoints, runtime, feerate_per_kw)?;
+            return Ok(Some(ChannelAbort::Published));
+        }
+        BusMsg::Ctl(CtlMsg::CpfpConstructed(cpfp_psbt)) => {
+            debug!("Signing CPFP transaction {}", cpfp_psbt.global.unsigned_tx.txid());
+            runtime.send_ctl(event.endpoints, ServiceId::Signer, CtlMsg::Sign(cpfp_psbt))?;
+            return Ok(Some(ChannelAbort::Published));
+        }
+        BusMsg::Ctl(CtlMsg::Signed(cpfp_psbt)) => {
+            let txid = cpfp_psbt.global.unsigned_tx.txid();
+            info!("{} CPFP transaction {}", "Publishing".promo(), txid.promoter());
+            let message = CtlMsg::PublishTx(cpfp_psbt);
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!("Commitment transaction fee is bumped with CPFP transaction {}", txid),
+            );
+            return Ok(Some(ChannelAbort::Published));
+        }
+        BusMsg::Ctl(CtlMsg::SignFailed { txid, error }) => {
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!("CPFP transaction {} is not signed: {}", txid, error),
+            );
+            return Ok(Some(ChannelAbort::Published));
+        }
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Aborting, event.source))
+        }
+    };
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Untrack(tx_status.txid))?;
+
+    // Our to-local output becomes spendable once the commitment transaction reaches the depth
+    // of `to_self_delay` requested by the remote peer
+    let to_self_delay = runtime.state.channel_snapshot().remote_params.to_self_delay as u32;
+    let spendable_height = u32::from(tx_status.height) + to_self_delay;
+    htlc::start(runtime, event.endpoints)?;
+    match runtime.state.sweep {
+        Some(ref mut session) => session.spendable_height = Some(spendable_height),
+        None if runtime.state.htlcs.is_some() => {
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!(
+                    "Commitment transaction {} is mined; resolving pending HTLCs",
+                    tx_status.txid
+                ),
+            );
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+            return Ok(Some(ChannelAbort::Resolving));
+        }
+        None => {
+            let message = format!(
+                "Channel is force-closed with commitment transaction {}, which has no outputs \
+                 to sweep",
+                tx_status.txid
+            );
+            complete_closing(event.endpoints, runtime, message);
+            return Ok(None);
+        }
+    }
+
+    let _ = runtime.report_progress(
+        event.endpoints,
+        format!(
+            "Commitment transaction {} is mined; to-local output will be spendable at height {}",
+            tx_status.txid, spendable_height
+        ),
+    );
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::TrackHeight(spendable_height))?;
+    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+    Ok(Some(ChannelAbort::Maturing))
+}
+
+fn finish_sweeping(
+    event: Event<BusMsg>,
+    runtime: &mut Runtime,
+    current_state: ChannelAbort,
+) -> Result<Option<ChannelAbort>, Error> {
+    let mut session = runtime.state.sweep.clone().ok_or(Error::InvalidState {
+        operation: "sweep to-local output without sweep data",
+        current_state: Lifecycle::Aborting,
+    })?;
+
+    let next_state = match event.message {
+        BusMsg::Ctl(CtlMsg::SweepAddress(sweep_script)) => {
+            session.sweep_script = Some(sweep_script);
+            current_state
+        }
+        BusMsg::Ctl(CtlMsg::HeightReached(height))
+        | BusMsg::Ctl(CtlMsg::NewBlock { height, .. }) => {
+            session.height = Some(height);
+            let bump_height = session.published_height.map(|h| h + SWEEP_BUMP_BLOCKS);
+            let bump_due = matches!(bump_height, Some(bump_height) if height >= bump_height);
+            if current_state == ChannelAbort::Swept && bump_due {
+                // Sweep transaction was not mined in time, so we bump its fee
+                session.feerate_per_kw = session.feerate_per_kw.saturating_mul(2);
+                warn!(
+                    "Sweep transaction is not mined within {} blocks; bumping its fee to {} sat/kw",
+                    SWEEP_BUMP_BLOCKS, session.feerate_per_kw
+                );
+                runtime.state.sweep = Some(session);
+                return sign_sweep(event.endpoints, runtime).map(Some);
+            }
+            current_state
+        }
+        BusMsg::Ctl(CtlMsg::Signed(sweep_psbt)) if current_state == ChannelAbort::Sweeping => {
+            let txid = sweep_psbt.global.unsigned_tx.txid();
+            let height = session.height.unwrap_or_default();
+            session.published_height = Some(height);
+            session.sweep_txids.push(txid);
+            info!("{} sweep transaction {}", "Publishing".promo(), txid.promoter());
+            let message = CtlMsg::PublishTx(sweep_psbt);
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+            let message = CtlMsg::Track { txid, depth: 1 };
+            runtime.send_ctl(event.endpoints, ServiceId::Watch, message)?;
+            let message = CtlMsg::TrackHeight(height + SWEEP_BUMP_BLOCKS);
+            runtime.send_ctl(event.endpoints, ServiceId::Watch, message)?;
+            ChannelAbort::Swept
+        }
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status))
+            if session.sweep_txids.contains(&tx_status.txid) =>
+        {
+            for txid in &session.sweep_txids {
+                runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Untrack(*txid))?;
+            }
+            if !htlc::is_resolved(runtime) {
+                let _ = runtime.report_progress(
+                    event.endpoints,
+                    format!("To-local output is swept with {}", tx_status.txid),
+                );
+                return Ok(Some(ChannelAbort::Resolving));
+            }
+            let message = format!(
+                "Channel is force-closed; to-local output is swept with {}",
+                tx_status.txid
+            );
+            complete_closing(event.endpoints, runtime, message);
+            return Ok(None);
+        }
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Aborting, event.source))
+        }
+    };
+
+    let is_spendable = matches!(
+        (session.height, session.spendable_height),
+        (Some(height), Some(spendable_height)) if height >= spendable_height
+    );
+    let is_ready =
+        next_state == ChannelAbort::Maturing && session.sweep_script.is_some() && is_spendable;
+    runtime.state.sweep = Some(session);
+    if is_ready {
+        return sign_sweep(event.endpoints, runtime).map(Some);
+    }
+    Ok(Some(next_state))
+}
+
+fn finish_resolving(
+    event: Event<BusMsg>,
+    runtime: &mut Runtime,
+) -> Result<Option<ChannelAbort>, Error> {
+    let consumed = htlc::process(runtime, event.endpoints, &event.message)?;
+    match event.message {
+        _ if consumed => {}
+        // These may be useful only for the to-local output, which is already swept
+        BusMsg::Ctl(CtlMsg::SweepAddress(_))
+        | BusMsg::Ctl(CtlMsg::HeightReached(_))
+        | BusMsg::Ctl(CtlMsg::NewBlock { .. }) => {}
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Aborting, event.source))
+        }
+    }
+
+    if !htlc::is_resolved(runtime) {
+        return Ok(Some(ChannelAbort::Resolving));
+    }
+    complete_closing(
+        event.endpoints,
+        runtime,
+        s!("Channel is force-closed; all its outputs are swept"),
+    );
+    Ok(None)
+}
+
+/// Notifies router about the channel closing and reports completion of the force close workflow
+/// to the client
+fn complete_closing(endpoints: &mut Endpoints, runtime: &mut Runtime, message: String) {
+    if let Some(channel_id) = runtime.state.channel.active_channel_id().channel_id() {
+        // We swallow error since we do not want to fail the channel if we can't update the router
+        let message = CtlMsg::ChannelClosed(channel_id);
+        let _ = runtime.send_ctl(endpoints, ServiceId::Router, message);
+    }
+    runtime.complete_workflow(endpoints, message);
+}
+
+/// Starts fee bump of our published commitment transaction by spending its anchor output with a
+/// child transaction, which inputs and change output are provided by the funding wallet
+fn bump_commitment(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    feerate_per_kw: u32,
+) -> Result<(), Error> {
+    let channel_type = runtime.state.channel_snapshot().common_params.channel_type;
+    if !has_anchors(channel_type) {
+        return Err(Error::InvalidState {
+            operation: "bump commitment transaction fee for channel without anchor outputs",
+            current_state: Lifecycle::Aborting,
+        });
+    }
+
+    let channel = &mut runtime.state.channel;
+    let commitment_psbt = channel.commitment_tx(false)?;
+    let commitment_tx = &commitment_psbt.global.unsigned_tx;
+    let funding = channel.funding();
+    let parent_fee =
+        funding.amount().saturating_sub(commitment_tx.output.iter().map(|txout| txout.value).sum());
+    let parent_weight = commitment_tx.get_weight() as u64 + COMMITMENT_WITNESS_WEIGHT;
+
+    let funding_pubkey = &channel.constructor().local_keys().funding_pubkey;
+    let witness_script = anchor_script(funding_pubkey.key);
+    let script_pubkey = witness_script.to_v0_p2wsh();
+    let (vout, prevout) = commitment_tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, txout)| txout.script_pubkey == script_pubkey)
+        .ok_or(Error::AnchorOutputNotFound(commitment_tx.txid()))?;
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(commitment_tx.txid(), vout as u32),
+            script_sig: Script::new(),
+            // Signals RBF, such that the child transaction can be replaced by a bigger bump
+            sequence: 0xFFFFFFFD,
+            witness: vec![],
+        }],
+        output: vec![],
+    };
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("anchor spending transaction is constructed unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(prevout.clone());
+    input.witness_script = Some(witness_script);
+    input
+        .bip32_derivation
+        .insert(bitcoin::PublicKey::new(funding_pubkey.key), funding_pubkey.source.clone());
+
+    info!(
+        "{} commitment transaction {} to {} sat/kw with anchor output",
+        "Bumping".promo(),
+        commitment_tx.txid().promoter(),
+        feerate_per_kw
+    );
+    let message =
+        CtlMsg::ConstructCpfp { psbt: Psbt::from(psbt), feerate_per_kw, parent_weight, parent_fee };
+    runtime.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+    Ok(())
+}
+
+/// Constructs transaction sweeping to-local output with the current sweep feerate and sends it
+/// to signd
+fn sign_sweep(endpoints: &mut Endpoints, runtime: &mut Runtime) -> Result<ChannelAbort, Error> {
+    let sweep_psbt = compose_sweep_psbt(runtime)?;
+    let per_commitment_point = runtime
+        .state
+        .sweep
+        .as_ref()
+        .expect("sweep session must be present at sweeping stage")
+        .per_commitment_point;
+    trace!("Sweep transaction: {:#?}", sweep_psbt);
+    debug!("Sweep transaction id is {}", sweep_psbt.global.unsigned_tx.txid());
+    let message = CtlMsg::SignDelayed { psbt: sweep_psbt, per_commitment_point };
+    runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+    Ok(ChannelAbort::Sweeping)
+}
+
+/// Detects to-local output in our commitment transaction and constructs data required to sweep
+/// it. Returns `None` if the commitment transaction has no to-local output.
+fn sweep_session(runtime: &Runtime, commitment_psbt: &Psbt) -> Result<Option<SweepSession>, Error> {
+    let secp = Secp256k1::verification_only();
+    let channel = &runtime.state.channel;
+    let snapshot = runtime.state.channel_snapshot();
+    let revocation_basepoint = channel.constructor().remote_keys().revocation_basepoint;
+    let delayed_basepoint = channel.constructor().local_keys().delayed_payment_basepoint.key;
+    let per_commitment_point = snapshot.local_per_commitment_point;
+
+    let revocation_pubkey =
+        derive_revocation_pubkey(&secp, revocation_basepoint, per_commitment_point)?;
+    let delayed_pubkey = derive_pubkey(&secp, delayed_basepoint, per_commitment_point)?;
+    let witness_script =
+        to_local_script(revocation_pubkey, delayed_pubkey, snapshot.remote_params.to_self_delay);
+    let script_pubkey = witness_script.to_v0_p2wsh();
+
+    let tx = &commitment_psbt.global.unsigned_tx;
+    let output =
+        tx.output.iter().enumerate().find(|(_, txout)| txout.script_pubkey == script_pubkey);
+    Ok(output.map(|(vout, prevout)| SweepSession {
+        outpoint: OutPoint::new(tx.txid(), vout as u32),
+        prevout: prevout.clone(),
+        witness_script,
+        per_commitment_point,
+        spendable_height: None,
+        height: None,
+        sweep_script: None,
+        feerate_per_kw: snapshot.common_params.feerate_per_kw,
+        published_height: None,
+        sweep_txids: empty!(),
+    }))
+}
+
+/// Constructs transaction spending to-local output of our commitment transaction with the
+/// current sweep feerate
+fn compose_sweep_psbt(runtime: &Runtime) -> Result<Psbt, Error> {
+    let session = runtime.state.sweep.as_ref().ok_or(Error::InvalidState {
+        operation: "sweep to-local output without sweep data",
+        current_state: Lifecycle::Aborting,
+    })?;
+    let sweep_script = session.sweep_script.clone().ok_or(Error::InvalidState {
+        operation: "sweep to-local output without sweep address",
+        current_state: Lifecycle::Aborting,
+    })?;
+    compose_delayed_sweep(
+        runtime,
+        session.outpoint,
+        session.prevout.clone(),
+        session.witness_script.clone(),
+        sweep_script,
+        session.feerate_per_kw,
+    )
+}
+
+/// Constructs transaction spending output locked with our delayed payment key and
+/// `to_self_delay` relative timelock, like the to-local output of our commitment transaction
+/// or outputs of the second-stage HTLC transactions
+pub(super) fn compose_delayed_sweep(
+    runtime: &Runtime,
+    outpoint: OutPoint,
+    prevout: TxOut,
+    witness_script: Script,
+    sweep_script: PubkeyScript,
+    feerate_per_kw: u32,
+) -> Result<Psbt, Error> {
+    let snapshot = runtime.state.channel_snapshot();
+
+    let fee = feerate_per_kw as u64 * SWEEP_TX_WEIGHT / 1000;
+    let value = prevout.value;
+    if value <= fee + snapshot.local_params.dust_limit_satoshis {
+        return Err(Error::SweepOutputDust { value, fee });
+    }
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: Script::new(),
+            // CSV-encumbered output requires relative timelock; it also signals RBF
+            sequence: snapshot.remote_params.to_self_delay as u32,
+            witness: vec![],
+        }],
+        output: vec![TxOut { value: value - fee, script_pubkey: sweep_script.into() }],
+    };
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("sweep transaction is constructed unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(prevout);
+    input.witness_script = Some(witness_script);
+    // signd uses delayed payment basepoint derivation to produce the spending key
+    let delayed_basepoint =
+        &runtime.state.channel.constructor().local_keys().delayed_payment_basepoint;
+    input
+        .bip32_derivation
+        .insert(bitcoin::PublicKey::new(delayed_basepoint.key), delayed_basepoint.source.clone());
+    Ok(Psbt::from(psbt))
+}
@@ -12,22 +12,38 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use amplify::Wrapper;
 use lnp::channel::bolt::Lifecycle;
-use lnp::p2p::legacy::{ActiveChannelId, Messages};
+use internet2::NodeAddr;
+use lnp::p2p::legacy::{
+    ActiveChannelId, ChannelId, Error as PeerError, FundingSigned, Messages as LnMsg, OpenChannel,
+};
 use lnp::Extension;
+use microservices::esb::Handler;
 
-use super::Error;
+use super::close::upfront_shutdown_script;
+use super::propose::{
+    activate_channel, confirm_funding, funding_input_signature, lock_unconfirmed_funding,
+    postpone_funding_locked,
+};
+use super::{
+    validate_channel_type, validate_funding_amount, validate_push_amount, validate_to_self_delay,
+    Error,
+};
 use crate::automata::{Event, StateMachine};
-use crate::bus::{AcceptChannelFrom, BusMsg};
+use crate::bus::{AcceptChannelFrom, BusMsg, CtlMsg};
 use crate::channeld::runtime::Runtime;
+use crate::channeld::ChannelState;
+use crate::rpc::ServiceId;
 use crate::service::LogStyle;
-use crate::Endpoints;
+use crate::{AcceptPolicy, Endpoints, Responder};
 
-/// Channel proposal workflow
+/// Channel acceptance workflow
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[derive(StrictEncode, StrictDecode)]
 pub enum ChannelAccept {
-    /// remote peer proposed a new channel to accept
+    /// remote peer proposed a new channel, which we have accepted; awaiting funding transaction
+    /// details from the remote peer
     #[display("ACCEPTED")]
     Accepted,
 
@@ -35,13 +51,17 @@ pub enum ChannelAccept {
     #[display("SIGNED")]
     Signed,
 
-    /// awaiting funding transaction to be mined
+    /// funding transaction is mined, awaiting for it to reach the required depth
     #[display("FUNDED")]
     Funded,
 
     /// funding transaction is mined, awaiting for the other peer confirmation of this fact
     #[display("LOCKED")]
     Locked,
+
+    /// signing commitment transaction of the remote peer
+    #[display("SIGNING")]
+    Signing,
 }
 
 impl StateMachine<BusMsg, Runtime> for ChannelAccept {
@@ -56,11 +76,18 @@ impl StateMachine<BusMsg, Runtime> for ChannelAccept {
         debug!("ChannelAccept {:#} received {} event", channel_id, event.message);
         let state = match self {
             ChannelAccept::Accepted => finish_accepted(event, runtime),
-            ChannelAccept::Signed => finish_signed(event, runtime),
-            ChannelAccept::Funded => finish_funded(event, runtime),
+            ChannelAccept::Signing => finish_signing(event, runtime),
+            ChannelAccept::Signed | ChannelAccept::Funded => {
+                if let Some(next) = finish_signed(event, runtime, self)? {
+                    Ok(next)
+                } else {
+                    info!("ChannelAccept {:#} has completed its work", channel_id);
+                    return Ok(None);
+                }
+            }
             ChannelAccept::Locked => {
                 finish_locked(event, runtime)?;
-                info!("ChannelPropose {} has completed its work", channel_id);
+                info!("ChannelAccept {:#} has completed its work", channel_id);
                 return Ok(None);
             }
         }?;
@@ -70,10 +97,11 @@ impl StateMachine<BusMsg, Runtime> for ChannelAccept {
 }
 
 impl ChannelAccept {
-    /// Computes channel lifecycle stage for the current channel proposal workflow stage
+    /// Computes channel lifecycle stage for the current channel acceptance workflow stage
     pub fn lifecycle(&self) -> Lifecycle {
         match self {
             ChannelAccept::Accepted => Lifecycle::Accepted,
+            ChannelAccept::Signing => Lifecycle::Signing,
             ChannelAccept::Signed => Lifecycle::Signed,
             ChannelAccept::Funded => Lifecycle::Funded,
             ChannelAccept::Locked => Lifecycle::Locked,
@@ -90,10 +118,83 @@ impl ChannelAccept {
         accept_channel_from: AcceptChannelFrom,
         runtime: &mut Runtime,
     ) -> Result<ChannelAccept, Error> {
-        let open_channel = Messages::OpenChannel(accept_channel_from.channel_req);
-        runtime.state.channel.update_from_peer(&open_channel)?;
+        let AcceptChannelFrom {
+            remote_peer,
+            channel_req,
+            policy,
+            common_params,
+            local_params,
+            local_keys,
+            features,
+            zero_conf,
+            accept_policy,
+            peer_channels,
+            ..
+        } = accept_channel_from;
+        let temp_channel_id = channel_req.temporary_channel_id;
+        let funding_sat = channel_req.funding_satoshis;
+        let max_delay = runtime.config().peer_bounds.max_to_self_delay;
+
+        let validation = validate_accept_policy(
+            &accept_policy,
+            &remote_peer,
+            peer_channels,
+            &channel_req,
+        )
+        .and_then(|_| {
+            policy
+                .validate_inbound(&channel_req)
+                .map_err(|err| Error::Channel(lnp::channel::bolt::Error::Policy(err)))
+        })
+        .and_then(|_| validate_channel_type(channel_req.channel_type, &features))
+        .and_then(|_| validate_funding_amount(channel_req.funding_satoshis, &features))
+        // BOLT-2 does not require the funder to keep the channel reserve right after the opening
+        .and_then(|_| validate_push_amount(channel_req.push_msat, funding_sat, 0))
+        .and_then(|_| validate_to_self_delay(channel_req.to_self_delay, max_delay, true))
+        .and_then(|_| validate_to_self_delay(local_params.to_self_delay, max_delay, false))
+        .and_then(|_| upfront_shutdown_script(channel_req.shutdown_scriptpubkey.as_ref()));
+        let remote_shutdown_script = match validation {
+            Ok(script) => script,
+            Err(err) => {
+                warn!("Rejecting channel {} proposed by the remote peer: {}", temp_channel_id, err);
+                let error = PeerError {
+                    channel_id: ChannelId::from_inner(temp_channel_id.into_inner()),
+                    data: err.to_string().into_bytes(),
+                };
+                runtime.send_p2p(endpoints, LnMsg::Error(error))?;
+                let reason = err.to_string();
+                let message = CtlMsg::ChannelRejected { channel_id: temp_channel_id, reason };
+                runtime.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+                return Err(err);
+            }
+        };
+
+        let chain = runtime.config().chain.clone();
+        runtime.state.channel = ChannelState::channel_with(
+            temp_channel_id,
+            &chain,
+            policy,
+            common_params,
+            local_params,
+            local_keys,
+        );
+        runtime.state.channel.update_from_peer(&LnMsg::OpenChannel(channel_req))?;
+        runtime.state.remote_shutdown_script = remote_shutdown_script;
 
-        runtime.send_p2p(endpoints, open_channel)?;
+        let mut accept_channel = runtime.state.channel.compose_accept_channel()?;
+        if zero_conf {
+            debug!("Remote peer is trusted; accepting channel {} as zero-conf", temp_channel_id);
+            accept_channel.minimum_depth = 0;
+        } else if let Some(minimum_depth) = accept_policy.minimum_depth(funding_sat) {
+            debug!(
+                "Requiring {} confirmations for channel {} funded with {} sat",
+                minimum_depth, temp_channel_id, funding_sat
+            );
+            accept_channel.minimum_depth = minimum_depth;
+        }
+        runtime.state.zero_conf = zero_conf;
+        runtime.state.minimum_depth = accept_channel.minimum_depth;
+        runtime.send_p2p(endpoints, LnMsg::AcceptChannel(accept_channel))?;
 
         Ok(ChannelAccept::Accepted)
     }
@@ -106,21 +207,206 @@ impl ChannelAccept {
                 "Accepted".ended(),
                 channel_id.ender(),
             ),
-            _ => todo!(),
+            ChannelAccept::Signing => format!(
+                "{} commitment transaction for channel {:#}",
+                "Signing".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelAccept::Signed => format!(
+                "{} for funding transaction of channel {:#} to be mined",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelAccept::Funded => format!(
+                "{} for funding transaction of channel {:#} to get enough confirmations",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelAccept::Locked => {
+                format!("{} channel {:#}", "Activating".promo(), channel_id.promoter())
+            }
         }
     }
 }
 
-fn finish_accepted(_event: Event<BusMsg>, _runtime: &mut Runtime) -> Result<ChannelAccept, Error> {
-    todo!()
+/// Checks channel proposed by the remote peer against the node policy for accepting channels
+fn validate_accept_policy(
+    accept_policy: &AcceptPolicy,
+    remote_peer: &NodeAddr,
+    peer_channels: u16,
+    channel_req: &OpenChannel,
+) -> Result<(), Error> {
+    let allowed = match remote_peer {
+        NodeAddr::Remote(remote_addr) => accept_policy.is_peer_allowed(&remote_addr.node_id),
+        // Peers without node id can't be listed in the allowlist
+        NodeAddr::Local(_) => accept_policy.allowlist.is_empty(),
+    };
+    if !allowed {
+        return Err(Error::PeerNotAllowed(remote_peer.clone()));
+    }
+    match accept_policy.max_channels_per_peer {
+        Some(max) if peer_channels >= max => {
+            return Err(Error::TooManyChannels { count: peer_channels, max });
+        }
+        _ => {}
+    }
+
+    let funding_sat = channel_req.funding_satoshis;
+    let max_funding_sat = accept_policy.max_funding_sat.unwrap_or(u64::MAX);
+    if funding_sat < accept_policy.min_funding_sat || funding_sat > max_funding_sat {
+        let allowed = match accept_policy.max_funding_sat {
+            Some(max) => format!("{}..={}", accept_policy.min_funding_sat, max),
+            None => format!("{}..", accept_policy.min_funding_sat),
+        };
+        return Err(Error::PolicyViolation {
+            field: "funding_satoshis",
+            value: funding_sat,
+            allowed,
+        });
+    }
+    match accept_policy.max_push_msat {
+        Some(max) if channel_req.push_msat > max => {
+            return Err(Error::PolicyViolation {
+                field: "push_msat",
+                value: channel_req.push_msat,
+                allowed: format!("0..={}", max),
+            });
+        }
+        _ => {}
+    }
+    match accept_policy.max_push_percent {
+        Some(percent) if channel_req.push_msat > funding_sat * 10 * percent as u64 => {
+            Err(Error::PolicyViolation {
+                field: "push_msat",
+                value: channel_req.push_msat,
+                allowed: format!("up to {}% of the funding", percent),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn finish_accepted(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelAccept, Error> {
+    let funding_created = match event.message {
+        BusMsg::Ln(LnMsg::FundingCreated(funding_created)) => funding_created,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Accepted, event.source))
+        }
+    };
+
+    debug!(
+        "Remote peer funds the channel with {}:{}",
+        funding_created.funding_txid, funding_created.funding_output_index
+    );
+    // Save funding outpoint and remote signature
+    runtime.state.remote_commitment_sig = Some(funding_created.signature);
+    let channel = &mut runtime.state.channel;
+    channel.update_from_peer(&LnMsg::FundingCreated(funding_created))?;
+
+    let commitment_psbt = channel.commitment_tx(true)?;
+    trace!("Remote commitment transaction: {:#?}", commitment_psbt);
+    debug!("Remote commitment transaction id is {}", commitment_psbt.global.unsigned_tx.txid());
+
+    runtime.verify_to_remote(&commitment_psbt)?;
+    runtime.expect_funding(event.endpoints)?;
+    runtime.send_ctl(event.endpoints, ServiceId::Signer, CtlMsg::Sign(commitment_psbt))?;
+    Ok(ChannelAccept::Signing)
 }
 
-fn finish_signed(_event: Event<BusMsg>, _runtime: &mut Runtime) -> Result<ChannelAccept, Error> {
-    todo!()
+fn finish_signing(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelAccept, Error> {
+    let commitment_psbt = match event.message {
+        BusMsg::Ctl(CtlMsg::Signed(psbt)) => psbt,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Signing, event.source))
+        }
+    };
+
+    let signature = funding_input_signature(runtime, &commitment_psbt)?;
+
+    let funding = runtime.state.channel.funding();
+    let (funding_txid, funding_output_index) = (funding.txid(), funding.output());
+    let channel_id = ChannelId::with(funding_txid, funding_output_index);
+    let temp_channel_id = runtime
+        .state
+        .channel
+        .temp_channel_id()
+        .expect("channel at funding stage must have temporary channel id");
+    debug!("Changing channel id from {} to {}", runtime.identity(), channel_id);
+    runtime.set_identity(event.endpoints, channel_id).expect("unrecoverable ZMQ failure");
+    // needed to update ESB routing map
+    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::Hello)?;
+    // lnpd learns permanent ids of the channels we fund while constructing funding transaction,
+    // but has to be told about the channels we accept
+    let message = CtlMsg::ChannelRenamed(temp_channel_id);
+    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+    runtime.register_remote_commitment(event.endpoints, commitment_psbt)?;
+    runtime.backup_channel(event.endpoints);
+
+    let funding_signed = FundingSigned { channel_id, signature };
+    runtime.send_p2p(event.endpoints, LnMsg::FundingSigned(funding_signed))?;
+
+    if runtime.state.minimum_depth == 0 {
+        lock_unconfirmed_funding(event.endpoints, runtime)?;
+        return Ok(ChannelAccept::Locked);
+    }
+
+    let depth = runtime.state.minimum_depth;
+    debug!("Waiting for funding transaction {} to be mined at depth {}", funding_txid, depth);
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Track {
+        txid: funding_txid,
+        depth,
+    })?;
+
+    Ok(ChannelAccept::Signed)
 }
 
-fn finish_funded(_event: Event<BusMsg>, _runtime: &mut Runtime) -> Result<ChannelAccept, Error> {
-    todo!()
+fn finish_signed(
+    event: Event<BusMsg>,
+    runtime: &mut Runtime,
+    current_state: ChannelAccept,
+) -> Result<Option<ChannelAccept>, Error> {
+    let tx_status = match event.message {
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status)) => tx_status,
+        BusMsg::Ln(LnMsg::FundingLocked(funding_locked)) => {
+            postpone_funding_locked(runtime, funding_locked)?;
+            return Ok(Some(current_state));
+        }
+        BusMsg::Ctl(CtlMsg::PeerReconnected(..)) if current_state == ChannelAccept::Signed => {
+            // The remote peer may have not received `funding_signed` before the disconnection,
+            // in which case it would never publish the funding transaction
+            if let Some(message @ LnMsg::FundingSigned(_)) = runtime.state.last_p2p_message.clone()
+            {
+                debug!("Retransmitting `funding_signed` to the reconnected remote peer");
+                runtime.send_p2p(event.endpoints, message)?;
+            }
+            return Ok(Some(current_state));
+        }
+        wrong_msg => {
+            let lifecycle = current_state.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+
+    if !confirm_funding(event.endpoints, runtime, tx_status)? {
+        return Ok(Some(ChannelAccept::Funded));
+    }
+
+    if let Some(remote_funding_locked) = runtime.state.remote_funding_locked.take() {
+        debug!("Applying `funding_locked` previously received from the remote peer");
+        activate_channel(event.endpoints, runtime, remote_funding_locked)?;
+        return Ok(None);
+    }
+
+    Ok(Some(ChannelAccept::Locked))
 }
 
-fn finish_locked(_event: Event<BusMsg>, _runtime: &mut Runtime) -> Result<(), Error> { todo!() }
+fn finish_locked(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<(), Error> {
+    let funding_locked = match event.message {
+        BusMsg::Ln(LnMsg::FundingLocked(funding_locked)) => funding_locked,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Locked, event.source))
+        }
+    };
+
+    activate_channel(event.endpoints, runtime, funding_locked)
+}
@@ -0,0 +1,270 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Announcement of the public channel to the network with `channel_announcement` message.
+//!
+//! Once the funding transaction has [`ANNOUNCEMENT_DEPTH`] confirmations, the announcement is
+//! composed and signed with the node key by lnpd, which holds the key, and with the local funding
+//! key by signd. The local signatures are exchanged for the remote ones with
+//! `announcement_signatures` message, and the announcement signed by both channel nodes is passed
+//! to lnpd, which broadcasts it through routed.
+//!
+//! The exchange does not persist: it is repeated each time the channel is reestablished, since
+//! the remote peer has to respond to our signatures with its own ones after the reconnection.
+
+use amplify::Wrapper;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Signature};
+use lightning_encoding::LightningEncode;
+use lnp::p2p::legacy::{AnnouncementSignatures, ChannelAnnouncement, ChannelId, Messages as LnMsg};
+
+use super::{ChannelStateMachine, Error};
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::{Endpoints, Responder};
+
+/// Number of the funding transaction confirmations required before the channel is announced
+pub const ANNOUNCEMENT_DEPTH: u32 = 6;
+
+/// Stage of the channel announcement
+#[derive(Clone, Debug)]
+enum Stage {
+    /// Awaiting for the blockchain to reach the given height
+    AwaitingDepth(u32),
+
+    /// Announcement is being signed by lnpd with the node key
+    NodeSigning,
+
+    /// Announcement is being signed by signd with the local funding key
+    FundingSigning,
+
+    /// Announcement is signed with the local keys and awaits for the remote signatures
+    Signed(ChannelAnnouncement),
+
+    /// Announcement signed by both channel nodes is passed to lnpd
+    Announced(ChannelAnnouncement),
+}
+
+/// Channel announcement in progress; does not persist
+#[derive(Clone, Debug, Default)]
+pub struct AnnounceSession {
+    /// Stage of the announcement, unless it is not yet started
+    stage: Option<Stage>,
+
+    /// Signatures received from the remote peer, which are kept until the local signatures are
+    /// ready
+    remote_signatures: Option<AnnouncementSignatures>,
+}
+
+/// Starts announcing the public channel once its funding transaction gets deep enough, or resends
+/// the local signatures to the reconnected remote peer if they are already known
+pub fn start(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<(), Error> {
+    if !runtime.state.channel_snapshot().common_params.announce_channel {
+        return Ok(());
+    }
+    // Short channel id of the unconfirmed funding transaction is not known yet
+    let funding_height = match runtime.state.funding_height {
+        Some(height)
+            if runtime.state.zero_conf_deadline.is_none()
+                && runtime.state.reorg_deadline.is_none() =>
+        {
+            height
+        }
+        _ => return Ok(()),
+    };
+
+    match runtime.announcement.stage {
+        Some(Stage::Signed(ref announcement)) | Some(Stage::Announced(ref announcement)) => {
+            debug!("Sending announcement signatures to the reconnected remote peer");
+            let signatures = local_signatures(runtime, announcement);
+            runtime.send_p2p(endpoints, LnMsg::AnnouncementSignatures(signatures))?;
+        }
+        // Signing requests which were not answered are repeated
+        _ => {
+            let height = funding_height + ANNOUNCEMENT_DEPTH - 1;
+            debug!("Channel will be announced once the blockchain reaches height {}", height);
+            runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::TrackHeight(height))?;
+            runtime.announcement.stage = Some(Stage::AwaitingDepth(height));
+        }
+    }
+    Ok(())
+}
+
+/// Abandons the announcement, which has to be started over once the funding transaction
+/// reorged out of the blockchain is mined again
+pub fn reset(runtime: &mut Runtime) { runtime.announcement = AnnounceSession::default(); }
+
+/// Processes messages related to the channel announcement. Returns `true` if the message was
+/// consumed and should not be processed further.
+pub fn process(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    source: &ServiceId,
+    message: &BusMsg,
+) -> Result<bool, Error> {
+    let consumed = match (message, runtime.announcement.stage.clone()) {
+        // Height notifications are also used by the other workflows, so they are not consumed
+        (
+            BusMsg::Ctl(CtlMsg::HeightReached(height))
+            | BusMsg::Ctl(CtlMsg::NewBlock { height, .. }),
+            Some(Stage::AwaitingDepth(target)),
+        ) => {
+            if *height >= target
+                && matches!(runtime.state.state_machine, ChannelStateMachine::Active)
+            {
+                compose(runtime, endpoints)?;
+            }
+            false
+        }
+        (BusMsg::Ctl(CtlMsg::AnnouncementSigned(announcement)), Some(Stage::NodeSigning))
+            if *source == ServiceId::LnpBroker =>
+        {
+            let local_keys = runtime.state.channel.constructor().local_keys();
+            let funding_key = local_keys.funding_pubkey.clone();
+            let announcement = announcement.clone();
+            let message = CtlMsg::SignAnnouncement { announcement, funding_key };
+            runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+            runtime.announcement.stage = Some(Stage::FundingSigning);
+            true
+        }
+        (BusMsg::Ctl(CtlMsg::AnnouncementSigned(announcement)), Some(Stage::FundingSigning))
+            if *source == ServiceId::Signer =>
+        {
+            debug!("Sending announcement signatures to the remote peer");
+            let signatures = local_signatures(runtime, announcement);
+            runtime.send_p2p(endpoints, LnMsg::AnnouncementSignatures(signatures))?;
+            runtime.announcement.stage = Some(Stage::Signed(announcement.clone()));
+            complete(runtime, endpoints)?;
+            true
+        }
+        (BusMsg::Ctl(CtlMsg::AnnouncementSigned(_)), _) => {
+            debug!("Ignoring signed announcement from {} which is not awaited", source);
+            true
+        }
+        (BusMsg::Ln(LnMsg::AnnouncementSignatures(signatures)), _) => {
+            if signatures.channel_id != channel_id(runtime) {
+                warn!(
+                    "Ignoring announcement signatures for foreign channel {}",
+                    signatures.channel_id
+                );
+                return Ok(true);
+            }
+            // Channel marked private by the funder is never announced
+            if !runtime.state.channel_snapshot().common_params.announce_channel {
+                warn!("Ignoring announcement signatures for the private channel");
+                return Ok(true);
+            }
+            runtime.announcement.remote_signatures = Some(signatures.clone());
+            complete(runtime, endpoints)?;
+            true
+        }
+        _ => false,
+    };
+    Ok(consumed)
+}
+
+/// Asks lnpd to compose the channel announcement
+fn compose(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<(), Error> {
+    let channel = &runtime.state.channel;
+    let channel_info = channel.channel_info(runtime.state.remote_id());
+    debug!(
+        "Funding transaction has {} confirmations; announcing channel {}",
+        ANNOUNCEMENT_DEPTH, channel_info.short_channel_id
+    );
+    let message = CtlMsg::ComposeAnnouncement {
+        channel_info,
+        local_funding_key: channel.funding_pubkey(),
+        remote_funding_key: channel.constructor().remote_keys().funding_pubkey,
+    };
+    runtime.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+    runtime.announcement.stage = Some(Stage::NodeSigning);
+    Ok(())
+}
+
+/// Completes the announcement with the remote signatures, once both local and remote signatures
+/// are known, and passes it to lnpd. Invalid remote signatures are discarded.
+fn complete(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<(), Error> {
+    let mut announcement = match runtime.announcement.stage {
+        Some(Stage::Signed(ref announcement)) => announcement.clone(),
+        _ => return Ok(()),
+    };
+    let signatures = match runtime.announcement.remote_signatures.take() {
+        Some(signatures) => signatures,
+        None => return Ok(()),
+    };
+    if signatures.short_channel_id != announcement.short_channel_id {
+        warn!(
+            "Remote peer has signed announcement of channel {} instead of {}",
+            signatures.short_channel_id, announcement.short_channel_id
+        );
+        return Ok(());
+    }
+
+    let remote_first = announcement.node_id_1 == runtime.state.remote_id();
+    let (node_id, bitcoin_key) = if remote_first {
+        announcement.node_signature_1 = signatures.node_signature;
+        announcement.bitcoin_signature_1 = signatures.bitcoin_signature;
+        (announcement.node_id_1, announcement.bitcoin_key_1)
+    } else {
+        announcement.node_signature_2 = signatures.node_signature;
+        announcement.bitcoin_signature_2 = signatures.bitcoin_signature;
+        (announcement.node_id_2, announcement.bitcoin_key_2)
+    };
+    // All four signatures commit to the double SHA256 hash of the message data following them
+    let data = announcement.lightning_serialize().expect("in-memory encoding");
+    let digest = sha256d::Hash::hash(&data[256..]);
+    let message = Message::from_slice(&digest[..]).expect("hash is 32 bytes");
+    let is_valid = |signature: &Signature, key: &PublicKey| {
+        Secp256k1::verification_only().verify(&message, signature, key).is_ok()
+    };
+    if !is_valid(&signatures.node_signature, &node_id)
+        || !is_valid(&signatures.bitcoin_signature, &bitcoin_key)
+    {
+        warn!("Remote peer has sent invalid signatures for the channel announcement");
+        return Ok(());
+    }
+
+    info!("Channel {} is announced to the network", announcement.short_channel_id);
+    runtime.send_ctl(
+        endpoints,
+        ServiceId::LnpBroker,
+        CtlMsg::ChannelAnnounced(announcement.clone()),
+    )?;
+    runtime.announcement.stage = Some(Stage::Announced(announcement));
+    Ok(())
+}
+
+/// Composes `announcement_signatures` message with the local signatures of the announcement
+fn local_signatures(
+    runtime: &Runtime,
+    announcement: &ChannelAnnouncement,
+) -> AnnouncementSignatures {
+    let remote_first = announcement.node_id_1 == runtime.state.remote_id();
+    let (node_signature, bitcoin_signature) = if remote_first {
+        (announcement.node_signature_2, announcement.bitcoin_signature_2)
+    } else {
+        (announcement.node_signature_1, announcement.bitcoin_signature_1)
+    };
+    AnnouncementSignatures {
+        channel_id: channel_id(runtime),
+        short_channel_id: announcement.short_channel_id,
+        node_signature,
+        bitcoin_signature,
+    }
+}
+
+fn channel_id(runtime: &Runtime) -> ChannelId {
+    ChannelId::from_inner(runtime.state.channel.active_channel_id().as_slice32())
+}
@@ -0,0 +1,354 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-3 key derivation and commitment transaction output scripts
+
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CLTV, OP_CSV, OP_DROP, OP_DUP, OP_ELSE,
+    OP_ENDIF, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160, OP_IF, OP_IFDUP, OP_NOTIF, OP_PUSHNUM_1,
+    OP_PUSHNUM_16, OP_PUSHNUM_2, OP_SIZE, OP_SWAP,
+};
+use bitcoin::blockdata::script;
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1};
+use bitcoin::{Script, Transaction};
+use lnp::p2p::legacy::ChannelType;
+use wallet::hlc::HashLock;
+
+use super::Error;
+
+/// Detects whether commitment transactions of the channel type have anchor outputs
+pub fn has_anchors(channel_type: ChannelType) -> bool {
+    matches!(
+        channel_type,
+        ChannelType::AnchoredOutputsStaticRemotekey | ChannelType::AnchoredZeroFeeHtlc
+    )
+}
+
+/// Detects whether second-stage HTLC transactions of the channel type pay zero fee, such that
+/// the fee is added later by attaching extra inputs
+pub fn has_zero_fee_htlc(channel_type: ChannelType) -> bool {
+    channel_type == ChannelType::AnchoredZeroFeeHtlc
+}
+
+/// Detects whether the to-remote output of commitment transactions of the channel type pays to
+/// the static payment basepoint of the remote peer instead of a key rotated with each commitment
+pub fn has_static_remotekey(channel_type: ChannelType) -> bool {
+    channel_type != ChannelType::Basic
+}
+
+/// Weight added to the commitment transaction by each untrimmed HTLC output according to BOLT-3,
+/// in weight units
+pub const HTLC_OUTPUT_WEIGHT: u64 = 172;
+
+/// Weight of the HTLC-timeout transaction according to BOLT-3, in weight units
+const HTLC_TIMEOUT_WEIGHT: u64 = 663;
+
+/// Weight of the HTLC-success transaction according to BOLT-3, in weight units
+const HTLC_SUCCESS_WEIGHT: u64 = 703;
+
+/// Computes fee of the second-stage HTLC transaction spending offered (HTLC-timeout) or received
+/// (HTLC-success) HTLC output according to BOLT-3, in satoshis
+pub fn htlc_tx_fee(offered: bool, feerate_per_kw: u32, channel_type: ChannelType) -> u64 {
+    let weight = match (has_zero_fee_htlc(channel_type), offered) {
+        (true, _) => 0,
+        (false, true) => HTLC_TIMEOUT_WEIGHT,
+        (false, false) => HTLC_SUCCESS_WEIGHT,
+    };
+    // Anchor channels add a single-block CSV to the HTLC transaction inputs
+    let weight = if weight > 0 && has_anchors(channel_type) { weight + 3 } else { weight };
+    feerate_per_kw as u64 * weight / 1000
+}
+
+/// Detects whether HTLC output is trimmed from the commitment transaction according to BOLT-3,
+/// i.e. whether its amount does not cover the dust limit of the commitment owner together with
+/// the fee of the second-stage HTLC transaction. The amount of trimmed HTLCs goes to the
+/// commitment transaction fee.
+pub fn is_trimmed_htlc(
+    amount_msat: u64,
+    offered: bool,
+    dust_limit_sat: u64,
+    feerate_per_kw: u32,
+    channel_type: ChannelType,
+) -> bool {
+    amount_msat / 1000 < dust_limit_sat + htlc_tx_fee(offered, feerate_per_kw, channel_type)
+}
+
+/// Constructs script pubkey for the to-remote output of a commitment transaction according to
+/// BOLT-3. Without anchor outputs this is a plain P2WPKH output; with anchors the output is
+/// additionally delayed by a single block (CPFP carve-out).
+pub fn to_remote_script_pubkey(payment_pubkey: PublicKey, anchors: bool) -> Script {
+    let payment_pubkey = bitcoin::PublicKey::new(payment_pubkey);
+    if !anchors {
+        return Script::new_v0_wpkh(
+            &payment_pubkey.wpubkey_hash().expect("secp256k1 public keys are always compressed"),
+        );
+    }
+    to_remote_script(payment_pubkey.key).to_v0_p2wsh()
+}
+
+/// Constructs witness script for the to-remote output of a commitment transaction with anchor
+/// outputs according to BOLT-3
+pub fn to_remote_script(payment_pubkey: PublicKey) -> Script {
+    script::Builder::new()
+        .push_key(&bitcoin::PublicKey::new(payment_pubkey))
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_opcode(OP_PUSHNUM_1)
+        .push_opcode(OP_CSV)
+        .into_script()
+}
+
+/// Constructs witness script for the to-local output of a commitment transaction according to
+/// BOLT-3
+pub fn to_local_script(
+    revocation_pubkey: PublicKey,
+    delayed_pubkey: PublicKey,
+    delay: u16,
+) -> Script {
+    script::Builder::new()
+        .push_opcode(OP_IF)
+        .push_key(&bitcoin::PublicKey::new(revocation_pubkey))
+        .push_opcode(OP_ELSE)
+        .push_int(delay as i64)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_key(&bitcoin::PublicKey::new(delayed_pubkey))
+        .push_opcode(OP_ENDIF)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Constructs witness script for the anchor output of a commitment transaction according to
+/// BOLT-3. The anchor can be spent by the funding key owner at any time, or by anyone after 16
+/// blocks, once the commitment transaction is mined.
+pub fn anchor_script(funding_pubkey: PublicKey) -> Script {
+    script::Builder::new()
+        .push_key(&bitcoin::PublicKey::new(funding_pubkey))
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_IFDUP)
+        .push_opcode(OP_NOTIF)
+        .push_opcode(OP_PUSHNUM_16)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_ENDIF)
+        .into_script()
+}
+
+/// Appends the end of the HTLC output witness script. For channels with anchor outputs, the
+/// non-revocation spending paths are additionally delayed by a single block (CPFP carve-out)
+fn finish_htlc_script(builder: script::Builder, anchors: bool) -> Script {
+    let builder = builder.push_opcode(OP_ENDIF);
+    let builder = if anchors {
+        builder.push_opcode(OP_PUSHNUM_1).push_opcode(OP_CSV).push_opcode(OP_DROP)
+    } else {
+        builder
+    };
+    builder.push_opcode(OP_ENDIF).into_script()
+}
+
+/// Constructs witness script for the HTLC output offered by the commitment transaction owner
+/// according to BOLT-3
+pub fn offered_htlc_script(
+    revocation_pubkey: PublicKey,
+    local_htlc_pubkey: PublicKey,
+    remote_htlc_pubkey: PublicKey,
+    hash_lock: HashLock,
+    anchors: bool,
+) -> Script {
+    let builder = script::Builder::new()
+        .push_opcode(OP_DUP)
+        .push_opcode(OP_HASH160)
+        .push_slice(&hash160::Hash::hash(&revocation_pubkey.serialize())[..])
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ELSE)
+        .push_key(&bitcoin::PublicKey::new(remote_htlc_pubkey))
+        .push_opcode(OP_SWAP)
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_NOTIF)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_SWAP)
+        .push_key(&bitcoin::PublicKey::new(local_htlc_pubkey))
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .push_opcode(OP_ELSE)
+        .push_opcode(OP_HASH160)
+        .push_slice(&ripemd160::Hash::hash(hash_lock.as_inner().as_inner())[..])
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(OP_CHECKSIG);
+    finish_htlc_script(builder, anchors)
+}
+
+/// Constructs witness script for the HTLC output received by the commitment transaction owner
+/// according to BOLT-3
+pub fn received_htlc_script(
+    revocation_pubkey: PublicKey,
+    local_htlc_pubkey: PublicKey,
+    remote_htlc_pubkey: PublicKey,
+    hash_lock: HashLock,
+    cltv_expiry: u32,
+    anchors: bool,
+) -> Script {
+    let builder = script::Builder::new()
+        .push_opcode(OP_DUP)
+        .push_opcode(OP_HASH160)
+        .push_slice(&hash160::Hash::hash(&revocation_pubkey.serialize())[..])
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ELSE)
+        .push_key(&bitcoin::PublicKey::new(remote_htlc_pubkey))
+        .push_opcode(OP_SWAP)
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_HASH160)
+        .push_slice(&ripemd160::Hash::hash(hash_lock.as_inner().as_inner())[..])
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_SWAP)
+        .push_key(&bitcoin::PublicKey::new(local_htlc_pubkey))
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .push_opcode(OP_ELSE)
+        .push_opcode(OP_DROP)
+        .push_int(cltv_expiry as i64)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_CHECKSIG);
+    finish_htlc_script(builder, anchors)
+}
+
+/// Derives per-commitment public key from the basepoint according to BOLT-3:
+/// `pubkey = basepoint + SHA256(per_commitment_point || basepoint) * G`
+pub fn derive_pubkey<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    basepoint: PublicKey,
+    per_commitment_point: PublicKey,
+) -> Result<PublicKey, Error> {
+    let mut pubkey = basepoint;
+    pubkey
+        .add_exp_assign(secp, &tweak(&per_commitment_point, &basepoint)[..])
+        .map_err(Error::KeyDerivation)?;
+    Ok(pubkey)
+}
+
+/// Derives revocation public key according to BOLT-3:
+/// `revocationpubkey = revocation_basepoint * SHA256(revocation_basepoint || per_commitment_point)
+///     + per_commitment_point * SHA256(per_commitment_point || revocation_basepoint)`
+pub fn derive_revocation_pubkey<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    revocation_basepoint: PublicKey,
+    per_commitment_point: PublicKey,
+) -> Result<PublicKey, Error> {
+    let mut basepoint_part = revocation_basepoint;
+    basepoint_part
+        .mul_assign(secp, &tweak(&revocation_basepoint, &per_commitment_point)[..])
+        .map_err(Error::KeyDerivation)?;
+    let mut commitment_part = per_commitment_point;
+    commitment_part
+        .mul_assign(secp, &tweak(&per_commitment_point, &revocation_basepoint)[..])
+        .map_err(Error::KeyDerivation)?;
+    basepoint_part.combine(&commitment_part).map_err(Error::KeyDerivation)
+}
+
+fn tweak(first: &PublicKey, second: &PublicKey) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&first.serialize());
+    engine.input(&second.serialize());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Computes the factor obscuring commitment numbers in the commitment transactions according to
+/// BOLT-3: the lower 48 bits of `SHA256(funder_payment_basepoint || fundee_payment_basepoint)`
+pub fn obscuring_factor(
+    funder_payment_basepoint: PublicKey,
+    fundee_payment_basepoint: PublicKey,
+) -> u64 {
+    let hash = tweak(&funder_payment_basepoint, &fundee_payment_basepoint);
+    let mut factor = [0u8; 8];
+    factor[2..].copy_from_slice(&hash[26..]);
+    u64::from_be_bytes(factor)
+}
+
+/// Extracts commitment number from the commitment transaction, which keeps the lower 24 bits of
+/// the obscured commitment number in its locktime and the upper 24 bits in its input sequence
+pub fn commitment_number(tx: &Transaction, obscuring_factor: u64) -> Option<u64> {
+    let sequence = tx.input.first()?.sequence;
+    let obscured =
+        (u64::from(sequence & 0x00FF_FFFF) << 24) | u64::from(tx.lock_time & 0x00FF_FFFF);
+    Some(obscured ^ obscuring_factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::ToHex;
+
+    use super::*;
+
+    /// `remotepubkey` of BOLT-3 test vectors
+    const REMOTE_PUBKEY: &str =
+        "0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b";
+
+    #[test]
+    fn static_remotekey_channel_types() {
+        assert!(!has_static_remotekey(ChannelType::Basic));
+        assert!(has_static_remotekey(ChannelType::StaticRemotekey));
+        assert!(has_static_remotekey(ChannelType::AnchoredOutputsStaticRemotekey));
+        assert!(has_static_remotekey(ChannelType::AnchoredZeroFeeHtlc));
+    }
+
+    #[test]
+    fn to_remote_p2wpkh() {
+        let pubkey = PublicKey::from_str(REMOTE_PUBKEY).unwrap();
+        // to-remote output of BOLT-3 commitment transaction test vectors
+        let expected = "0014ccf1af2f2aabee14bb40fa3851ab2301de843110";
+        assert_eq!(to_remote_script_pubkey(pubkey, false).to_hex(), expected);
+    }
+
+    #[test]
+    fn to_remote_anchors() {
+        let pubkey = PublicKey::from_str(REMOTE_PUBKEY).unwrap();
+        // P2WSH of `<remotepubkey> OP_CHECKSIGVERIFY 1 OP_CHECKSEQUENCEVERIFY`
+        let expected = "0020ed256e373a5928b5db092f489ac04472cd58ec13cbbceb31d349e489aab7d353";
+        assert_eq!(to_remote_script_pubkey(pubkey, true).to_hex(), expected);
+        assert_eq!(to_remote_script(pubkey).to_v0_p2wsh().to_hex(), expected);
+    }
+
+    #[test]
+    fn derive_pubkey_vector() {
+        // BOLT-3 key derivation test vector
+        let secp = Secp256k1::verification_only();
+        let basepoint = PublicKey::from_str(
+            "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2",
+        )
+        .unwrap();
+        let per_commitment_point = PublicKey::from_str(
+            "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486",
+        )
+        .unwrap();
+        let expected = PublicKey::from_str(
+            "0235f2dbfaa89b57ec7b055afe29849ef7ddfeb1cefdb9ebdc43f5494984db29e5",
+        )
+        .unwrap();
+        assert_eq!(derive_pubkey(&secp, basepoint, per_commitment_point).unwrap(), expected);
+    }
+}
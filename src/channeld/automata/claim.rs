@@ -0,0 +1,220 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Claiming our to-remote output of the latest remote commitment transaction published by the
+//! remote peer. The claim does not persist: once the channel daemon restarts, watchd reports the
+//! funding output spending again and the claim is started over.
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use super::bolt3::{
+    derive_pubkey, has_anchors, has_static_remotekey, to_remote_script, to_remote_script_pubkey,
+};
+use super::Error;
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::service::LogStyle;
+use crate::Endpoints;
+
+/// Weight of the transaction sweeping to-remote output of the remote commitment transaction into
+/// a single P2WPKH output, in weight units. Uses the larger weight of spending P2WSH to-remote
+/// output of the channels with anchor outputs.
+const CLAIM_TX_WEIGHT: u64 = 500;
+
+/// Our to-remote output of the remote commitment transaction being claimed
+pub struct ClaimSession {
+    /// To-remote output of the remote commitment transaction
+    outpoint: OutPoint,
+
+    /// Value and script pubkey of the to-remote output
+    prevout: TxOut,
+
+    /// Key controlling the to-remote output
+    payment_pubkey: PublicKey,
+
+    /// Remote per-commitment point tweaking the payment basepoint, or `None` if the channel
+    /// uses static remote key
+    per_commitment_point: Option<PublicKey>,
+
+    /// Id of the claiming transaction, once it is sent to signd
+    claim_txid: Option<Txid>,
+}
+
+/// Starts claiming our output of the remote commitment transaction by requesting address from
+/// the funding wallet. Does nothing if the commitment transaction has no output paying to us.
+pub fn start(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    commitment_tx: &Transaction,
+) -> Result<(), Error> {
+    let secp = Secp256k1::verification_only();
+    let snapshot = runtime.state.channel_snapshot();
+    let channel_type = snapshot.common_params.channel_type;
+    let payment_basepoint = runtime.state.channel.constructor().local_keys().payment_basepoint.key;
+    let per_commitment_point = if has_static_remotekey(channel_type) {
+        None
+    } else {
+        Some(snapshot.remote_per_commitment_point)
+    };
+    let payment_pubkey = match per_commitment_point {
+        Some(point) => derive_pubkey(&secp, payment_basepoint, point)?,
+        None => payment_basepoint,
+    };
+    let script_pubkey = to_remote_script_pubkey(payment_pubkey, has_anchors(channel_type));
+
+    let txid = commitment_tx.txid();
+    let (vout, prevout) = match commitment_tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, txout)| txout.script_pubkey == script_pubkey)
+    {
+        Some((vout, txout)) => (vout, txout.clone()),
+        None => {
+            info!("Remote commitment transaction {} has no output paying to us", txid);
+            return Ok(());
+        }
+    };
+
+    debug!("Claiming our output {}:{} of the remote commitment transaction", txid, vout);
+    runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+    runtime.claim = Some(ClaimSession {
+        outpoint: OutPoint::new(txid, vout as u32),
+        prevout,
+        payment_pubkey,
+        per_commitment_point,
+        claim_txid: None,
+    });
+    Ok(())
+}
+
+/// Processes messages related to the claim of our output of the remote commitment transaction.
+/// Returns `true` if the message belongs to the claim and should not be processed further.
+pub fn process(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    message: &BusMsg,
+) -> Result<bool, Error> {
+    let mut session = match runtime.claim.take() {
+        Some(session) => session,
+        None => return Ok(false),
+    };
+
+    match message {
+        BusMsg::Ctl(CtlMsg::SweepAddress(sweep_script)) if session.claim_txid.is_none() => {
+            let psbt = compose_claim_psbt(runtime, &session, sweep_script.clone())?;
+            let txid = psbt.global.unsigned_tx.txid();
+            debug!("Claiming transaction id is {}", txid);
+            session.claim_txid = Some(txid);
+            let per_commitment_point = session.per_commitment_point;
+            let message = CtlMsg::SignToRemote { psbt, per_commitment_point };
+            runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+        }
+        BusMsg::Ctl(CtlMsg::Signed(psbt))
+            if Some(psbt.global.unsigned_tx.txid()) == session.claim_txid =>
+        {
+            let psbt = finalize_claim(psbt.clone(), runtime)?;
+            let txid = psbt.global.unsigned_tx.txid();
+            info!("{} transaction {} claiming our output", "Publishing".promo(), txid.promoter());
+            runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::PublishTx(psbt))?;
+            runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+        }
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status))
+            if Some(tx_status.txid) == session.claim_txid =>
+        {
+            runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Untrack(tx_status.txid))?;
+            info!(
+                "Our output of the remote commitment transaction {} is claimed with {}",
+                session.outpoint.txid,
+                tx_status.txid.ended()
+            );
+            return Ok(true);
+        }
+        _ => {
+            runtime.claim = Some(session);
+            return Ok(false);
+        }
+    }
+    runtime.claim = Some(session);
+    Ok(true)
+}
+
+/// Constructs transaction spending our to-remote output of the remote commitment transaction
+fn compose_claim_psbt(
+    runtime: &Runtime,
+    session: &ClaimSession,
+    sweep_script: PubkeyScript,
+) -> Result<Psbt, Error> {
+    let snapshot = runtime.state.channel_snapshot();
+    let anchors = has_anchors(snapshot.common_params.channel_type);
+    let fee = snapshot.common_params.feerate_per_kw as u64 * CLAIM_TX_WEIGHT / 1000;
+    let value = session.prevout.value;
+    if value <= fee + snapshot.local_params.dust_limit_satoshis {
+        return Err(Error::SweepOutputDust { value, fee });
+    }
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: session.outpoint,
+            script_sig: Script::new(),
+            // With anchor outputs the to-remote output is delayed by a single block
+            sequence: if anchors { 1 } else { 0xFFFF_FFFD },
+            witness: vec![],
+        }],
+        output: vec![TxOut { value: value - fee, script_pubkey: sweep_script.into() }],
+    };
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("claiming transaction is constructed unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(session.prevout.clone());
+    // Signature of P2WPKH output commits to the P2PKH script of the key
+    input.witness_script = Some(if anchors {
+        to_remote_script(session.payment_pubkey)
+    } else {
+        Script::new_p2pkh(&bitcoin::PublicKey::new(session.payment_pubkey).pubkey_hash())
+    });
+    // signd uses payment basepoint derivation to produce the spending key
+    let payment_basepoint = &runtime.state.channel.constructor().local_keys().payment_basepoint;
+    input
+        .bip32_derivation
+        .insert(bitcoin::PublicKey::new(payment_basepoint.key), payment_basepoint.source.clone());
+    Ok(Psbt::from(psbt))
+}
+
+/// Constructs witness of the claiming transaction signed by signd
+fn finalize_claim(mut psbt: Psbt, runtime: &Runtime) -> Result<Psbt, Error> {
+    let anchors = has_anchors(runtime.state.channel_snapshot().common_params.channel_type);
+    let input = psbt.inputs.get_mut(0).expect("claiming transaction always has a single input");
+    let (pubkey, sig) = input.partial_sigs.iter().next().ok_or(Error::InvalidState {
+        operation: "publish claiming transaction not signed by signd",
+        current_state: runtime.state.state_machine.lifecycle(),
+    })?;
+    let witness_script = input.witness_script.take().expect("witness script is set on creation");
+    let witness = if anchors {
+        vec![sig.clone(), witness_script.to_bytes()]
+    } else {
+        vec![sig.clone(), pubkey.to_bytes()]
+    };
+    input.final_script_witness = Some(witness);
+    input.partial_sigs.clear();
+    Ok(psbt)
+}
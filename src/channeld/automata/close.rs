@@ -0,0 +1,667 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2};
+use bitcoin::blockdata::script;
+use bitcoin::secp256k1::{PublicKey, Signature};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+use lnp::channel::bolt::Lifecycle;
+use lnp::p2p::legacy::{ActiveChannelId, ChannelId, ClosingSigned, Messages as LnMsg, Shutdown};
+use lnp::Extension;
+use microservices::esb::Handler;
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use super::propose::funding_input_signature;
+use super::Error;
+use crate::automata::{Event, StateMachine};
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::{ClosingFeeRange, ServiceId, CLOSING_TX_WEIGHT};
+use crate::service::LogStyle;
+use crate::{Endpoints, Responder};
+
+/// Maximal number of `closing_signed` messages we accept from the remote peer during a single
+/// closing fee negotiation. Each round halves the gap between the proposed fees, so the fees
+/// within the satoshi precision converge much earlier.
+pub const MAX_CLOSING_ROUNDS: u16 = 64;
+
+/// Cooperative channel closing workflow
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+pub enum ChannelClose {
+    /// sent `shutdown` to the remote peer, awaiting for its `shutdown` reply
+    #[display("SHUTDOWN")]
+    Shutdown,
+
+    /// remote peer has requested to close the channel; awaiting for the pending HTLCs to be
+    /// resolved before replying with our `shutdown`
+    #[display("DRAINING")]
+    Draining,
+
+    /// negotiating closing transaction fee with the remote peer
+    #[display("NEGOTIATING")]
+    Negotiating,
+
+    /// signing closing transaction with the fee proposed to the remote peer
+    #[display("SIGNING")]
+    Signing,
+
+    /// closing transaction is published, awaiting for it to be mined
+    #[display("PUBLISHED")]
+    Published,
+
+    /// awaiting for the funding wallet to provide address receiving our part of the channel
+    /// funds
+    #[display("ADDRESSING")]
+    Addressing,
+}
+
+/// Persistable data for cooperative channel closing negotiations, which can't be a part of the
+/// [`ChannelClose`] state machine
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct ClosingSession {
+    /// Script which will receive our part of the channel funds
+    pub local_script: PubkeyScript,
+
+    /// Script which will receive remote peer part of the channel funds, known once `shutdown`
+    /// message from the remote peer is received
+    pub remote_script: Option<PubkeyScript>,
+
+    /// Range of the closing transaction fees we agree with
+    pub fee_range: ClosingFeeRange,
+
+    /// Fee which we have proposed (or going to propose) to the remote peer
+    pub local_fee: Option<u64>,
+
+    /// Our signature for the closing transaction with `local_fee`
+    pub local_sig: Option<Signature>,
+
+    /// Fee last proposed by the remote peer
+    pub remote_fee: Option<u64>,
+
+    /// Remote peer signature for the closing transaction with `remote_fee`
+    pub remote_sig: Option<Signature>,
+}
+
+impl StateMachine<BusMsg, Runtime> for ChannelClose {
+    type Error = Error;
+
+    fn next(
+        self,
+        event: Event<BusMsg>,
+        runtime: &mut Runtime,
+    ) -> Result<Option<Self>, Self::Error> {
+        let channel_id = runtime.state.channel.active_channel_id();
+        debug!("ChannelClose {:#} received {} event", channel_id, event.message);
+        let state = match self {
+            ChannelClose::Shutdown => finish_shutdown(event, runtime),
+            ChannelClose::Draining => finish_draining(event, runtime),
+            ChannelClose::Negotiating => finish_negotiating(event, runtime),
+            ChannelClose::Signing => finish_signing(event, runtime),
+            ChannelClose::Addressing => finish_addressing(event, runtime),
+            ChannelClose::Published => {
+                finish_published(event, runtime)?;
+                info!("ChannelClose {:#} has completed its work", channel_id);
+                return Ok(None);
+            }
+        }?;
+        info!("ChannelClose {:#} switched to {} state", channel_id, state);
+        Ok(Some(state))
+    }
+}
+
+impl ChannelClose {
+    /// Computes channel lifecycle stage for the current channel closing workflow stage
+    pub fn lifecycle(&self) -> Lifecycle {
+        // TODO: Count negotiation rounds
+        Lifecycle::Closing { round: 0 }
+    }
+}
+
+// State transitions:
+
+impl ChannelClose {
+    /// Starts cooperative channel closing on a request from a local node. Our funds are sent to
+    /// the given shutdown script, which can be provided only if the channel has not committed
+    /// upfront to another one. Otherwise, the funds are sent to the committed script or to a new
+    /// address of the funding wallet.
+    pub fn with(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+        fee_range: Option<ClosingFeeRange>,
+        shutdown_script: Option<PubkeyScript>,
+    ) -> Result<ChannelClose, Error> {
+        ensure_no_htlcs(runtime)?;
+        if let Some(ref script) = shutdown_script {
+            if !is_standard_shutdown_script(script) {
+                return Err(Error::NonStandardShutdownScript(script.clone()));
+            }
+            match runtime.state.local_shutdown_script {
+                Some(ref committed) if committed != script => {
+                    return Err(Error::ShutdownScriptCommitted {
+                        committed: committed.clone(),
+                        requested: script.clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        let mut session = ClosingSession::with(runtime, fee_range);
+        if let Some(script) = shutdown_script {
+            session.local_script = script;
+        }
+        let addressed = session.is_addressed();
+        runtime.state.closing = Some(session);
+        if !addressed {
+            return request_address(runtime, endpoints);
+        }
+        send_shutdown(runtime, endpoints)
+    }
+
+    /// Starts cooperative channel closing in response to `shutdown` message from the remote peer
+    pub fn with_remote(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+        remote_shutdown: Shutdown,
+    ) -> Result<ChannelClose, Error> {
+        debug!("Remote peer will receive its funds to {}", remote_shutdown.scriptpubkey);
+        let mut session = ClosingSession::with(runtime, None);
+        session.remote_script = Some(remote_shutdown.scriptpubkey);
+        let addressed = session.is_addressed();
+        runtime.state.closing = Some(session);
+        if !addressed {
+            return request_address(runtime, endpoints);
+        }
+        await_draining(runtime, endpoints)
+    }
+
+    /// Re-requests address for our part of the channel funds from the funding wallet once the
+    /// channel is restored in [`ChannelClose::Addressing`] state, since the wallet reply is not
+    /// persisted
+    pub fn resume_addressing(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+    ) -> Result<(), Error> {
+        request_address(runtime, endpoints)?;
+        Ok(())
+    }
+
+    /// Construct information message for error and client reporting
+    pub fn info_message(&self, channel_id: ActiveChannelId) -> String {
+        match self {
+            ChannelClose::Shutdown => format!(
+                "{} remote peer to shut down channel {:#}",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelClose::Draining => format!(
+                "{} pending HTLCs of channel {:#} to resolve before closing it",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelClose::Negotiating => format!(
+                "{} closing fee for channel {:#} with the remote peer",
+                "Negotiating".promo(),
+                channel_id.promoter()
+            ),
+            ChannelClose::Signing => format!(
+                "{} closing transaction for channel {:#}",
+                "Signing".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelClose::Published => format!(
+                "{} closing transaction for channel {:#} to be mined",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelClose::Addressing => format!(
+                "{} funding wallet address to close channel {:#}",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+        }
+    }
+}
+
+impl ClosingSession {
+    fn with(runtime: &Runtime, fee_range: Option<ClosingFeeRange>) -> ClosingSession {
+        let base_fee = runtime.state.channel_snapshot().common_params.feerate_per_kw as u64
+            * CLOSING_TX_WEIGHT
+            / 1000;
+        let fee_range = fee_range
+            .unwrap_or(ClosingFeeRange { min_fee_sat: base_fee / 2, max_fee_sat: base_fee * 2 });
+        // Script committed upfront can't be changed; otherwise the script is left empty until the
+        // funding wallet provides a new address
+        let local_script =
+            runtime.state.local_shutdown_script.clone().unwrap_or_else(|| Script::new().into());
+        ClosingSession {
+            local_script,
+            remote_script: None,
+            fee_range,
+            local_fee: Some(base_fee.max(fee_range.min_fee_sat).min(fee_range.max_fee_sat)),
+            local_sig: None,
+            remote_fee: None,
+            remote_sig: None,
+        }
+    }
+
+    /// Detects whether the script receiving our part of the channel funds is known
+    #[inline]
+    fn is_addressed(&self) -> bool { !self.local_script.as_inner().is_empty() }
+}
+
+/// Checks that the script is one of the standard forms allowed by BOLT-2 for `shutdown`
+/// messages: P2PKH, P2SH, P2WPKH or P2WSH
+pub(super) fn is_standard_shutdown_script(script: &PubkeyScript) -> bool {
+    let script = script.as_inner();
+    script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wpkh() || script.is_v0_p2wsh()
+}
+
+/// Extracts shutdown script committed upfront from `open_channel` or `accept_channel` message
+/// field. Zero-length script means that the peer has opted out of the commitment.
+pub(super) fn upfront_shutdown_script(
+    scriptpubkey: Option<&PubkeyScript>,
+) -> Result<Option<PubkeyScript>, Error> {
+    match scriptpubkey {
+        None => Ok(None),
+        Some(script) if script.as_inner().is_empty() => Ok(None),
+        Some(script) if is_standard_shutdown_script(script) => Ok(Some(script.clone())),
+        Some(script) => Err(Error::NonStandardShutdownScript(script.clone())),
+    }
+}
+
+/// Checks that the `shutdown` message from the remote peer uses a standard script, which must
+/// be exactly the one the peer has committed to upfront, if any
+pub(super) fn validate_remote_shutdown(
+    runtime: &Runtime,
+    shutdown: &Shutdown,
+) -> Result<(), Error> {
+    if !is_standard_shutdown_script(&shutdown.scriptpubkey) {
+        return Err(Error::NonStandardShutdownScript(shutdown.scriptpubkey.clone()));
+    }
+    match runtime.state.remote_shutdown_script {
+        Some(ref committed) if committed != &shutdown.scriptpubkey => {
+            Err(Error::ShutdownScriptMismatch {
+                committed: committed.clone(),
+                received: shutdown.scriptpubkey.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn finish_shutdown(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelClose, Error> {
+    let remote_shutdown = match event.message {
+        BusMsg::Ln(LnMsg::Shutdown(shutdown)) => shutdown,
+        wrong_msg => {
+            let lifecycle = ChannelClose::Shutdown.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+    complete_shutdown(runtime, event.endpoints, remote_shutdown)
+}
+
+fn finish_addressing(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelClose, Error> {
+    let sweep_script = match event.message {
+        BusMsg::Ctl(CtlMsg::SweepAddress(sweep_script)) => sweep_script,
+        // Remote peer which has sent us `shutdown` continues resolving pending HTLCs
+        message if closing_session(runtime)?.remote_script.is_some() => {
+            drain_htlcs(runtime, event.endpoints, message, event.source)?;
+            return Ok(ChannelClose::Addressing);
+        }
+        wrong_msg => {
+            let lifecycle = ChannelClose::Addressing.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+
+    debug!("Our funds will be sent to the funding wallet script {}", sweep_script);
+    let session = closing_session_mut(runtime)?;
+    session.local_script = sweep_script;
+    if session.remote_script.is_some() {
+        return await_draining(runtime, event.endpoints);
+    }
+    send_shutdown(runtime, event.endpoints)
+}
+
+fn finish_draining(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelClose, Error> {
+    drain_htlcs(runtime, event.endpoints, event.message, event.source)?;
+    if ensure_no_htlcs(runtime).is_err() {
+        return Ok(ChannelClose::Draining);
+    }
+    debug!("All pending HTLCs are resolved, replying remote peer with `shutdown`");
+    reply_shutdown(runtime, event.endpoints)
+}
+
+/// Processes messages resolving HTLCs pending once the remote peer has sent us `shutdown`
+fn drain_htlcs(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    message: BusMsg,
+    source: ServiceId,
+) -> Result<(), Error> {
+    match message {
+        BusMsg::Ln(
+            message @ LnMsg::UpdateFulfillHtlc(_)
+            | message @ LnMsg::UpdateFailHtlc(_)
+            | message @ LnMsg::UpdateFailMalformedHtlc(_),
+        ) => {
+            runtime.state.channel.update_from_peer(&message)?;
+            runtime.publish_htlc_resolution(endpoints, &message);
+        }
+        BusMsg::Ln(LnMsg::CommitmentSigned(commitment_signed)) => {
+            runtime.accept_commitment(endpoints, commitment_signed)?;
+        }
+        BusMsg::Ctl(CtlMsg::CommitmentSecret { commitment_number, secret, next_point }) => {
+            runtime.revoke_commitment(endpoints, commitment_number, secret, next_point)?;
+        }
+        BusMsg::Ln(LnMsg::RevokeAndAck(revoke_and_ack)) => {
+            runtime.complete_revocation(endpoints, revoke_and_ack)?;
+        }
+        wrong_msg => {
+            let lifecycle = runtime.state.state_machine.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, source));
+        }
+    }
+    Ok(())
+}
+
+/// Awaits for the pending HTLCs to resolve before replying to the `shutdown` received from the
+/// remote peer
+fn await_draining(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<ChannelClose, Error> {
+    if let Err(Error::HtlcsPending(count)) = ensure_no_htlcs(runtime) {
+        info!(
+            "Remote peer requested to close channel {}; {} for {} pending HTLCs to resolve",
+            static_channel_id(runtime)?.promoter(),
+            "awaiting".promo(),
+            count
+        );
+        return Ok(ChannelClose::Draining);
+    }
+    reply_shutdown(runtime, endpoints)
+}
+
+/// Requests a new address from the funding wallet to receive our part of the channel funds
+fn request_address(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+) -> Result<ChannelClose, Error> {
+    runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+    Ok(ChannelClose::Addressing)
+}
+
+/// Sends `shutdown` to the remote peer on a cooperative closing initiated by us
+fn send_shutdown(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<ChannelClose, Error> {
+    let shutdown = Shutdown {
+        channel_id: static_channel_id(runtime)?,
+        scriptpubkey: closing_session(runtime)?.local_script.clone(),
+    };
+    runtime.send_p2p(endpoints, LnMsg::Shutdown(shutdown))?;
+    Ok(ChannelClose::Shutdown)
+}
+
+/// Sends our `shutdown` in reply to the one received from the remote peer and proceeds to the
+/// closing fee negotiations
+fn reply_shutdown(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<ChannelClose, Error> {
+    let shutdown = Shutdown {
+        channel_id: static_channel_id(runtime)?,
+        scriptpubkey: closing_session(runtime)?.local_script.clone(),
+    };
+    runtime.send_p2p(endpoints, LnMsg::Shutdown(shutdown))?;
+    start_negotiating(runtime, endpoints)
+}
+
+fn complete_shutdown(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    remote_shutdown: Shutdown,
+) -> Result<ChannelClose, Error> {
+    debug!("Remote peer will receive its funds to {}", remote_shutdown.scriptpubkey);
+    closing_session_mut(runtime)?.remote_script = Some(remote_shutdown.scriptpubkey);
+    start_negotiating(runtime, endpoints)
+}
+
+fn start_negotiating(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+) -> Result<ChannelClose, Error> {
+    runtime.closing_rounds = 0;
+    // Funding node is the one who starts fee negotiations
+    if !runtime.state.is_funder {
+        return Ok(ChannelClose::Negotiating);
+    }
+    sign_closing(runtime, endpoints)
+}
+
+fn finish_negotiating(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelClose, Error> {
+    let closing_signed = match event.message {
+        BusMsg::Ln(LnMsg::ClosingSigned(closing_signed)) => closing_signed,
+        wrong_msg => {
+            let lifecycle = runtime.state.state_machine.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+
+    let remote_fee = closing_signed.fee_satoshis;
+    let session = closing_session_mut(runtime)?;
+    session.remote_fee = Some(remote_fee);
+    session.remote_sig = Some(closing_signed.signature);
+    let ClosingFeeRange { min_fee_sat, max_fee_sat } = session.fee_range;
+
+    if session.local_fee == Some(remote_fee) && session.local_sig.is_some() {
+        debug!("Remote peer agreed on the closing fee of {} sat", remote_fee);
+        return publish_closing(runtime, event.endpoints);
+    }
+
+    let acceptable_fee = remote_fee.max(min_fee_sat).min(max_fee_sat);
+    let local_fee = match session.local_fee {
+        _ if acceptable_fee == remote_fee => remote_fee,
+        // Per BOLT-2 we have to propose a value strictly between our previous proposal and the
+        // fee proposed by the remote peer
+        Some(prev_fee) if session.local_sig.is_some() => {
+            let next_fee = (prev_fee + acceptable_fee) / 2;
+            if next_fee == prev_fee {
+                return Err(Error::ClosingFeeDisagreement { local: prev_fee, remote: remote_fee });
+            }
+            next_fee
+        }
+        // We have not proposed anything yet, so we start with the closest acceptable fee
+        _ => acceptable_fee,
+    };
+    debug!("Proposing closing fee of {} sat to the remote peer", local_fee);
+    session.local_fee = Some(local_fee);
+    session.local_sig = None;
+    sign_closing(runtime, event.endpoints)
+}
+
+fn finish_signing(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelClose, Error> {
+    let closing_psbt = match event.message {
+        BusMsg::Ctl(CtlMsg::Signed(psbt)) => psbt,
+        wrong_msg => {
+            let lifecycle = runtime.state.state_machine.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+
+    let signature = funding_input_signature(runtime, &closing_psbt)?;
+    let channel_id = static_channel_id(runtime)?;
+    let session = closing_session_mut(runtime)?;
+    session.local_sig = Some(signature);
+    let fee_satoshis = session.local_fee.expect("closing fee must be known at signing stage");
+    let agreed = session.remote_fee == Some(fee_satoshis);
+
+    let closing_signed = ClosingSigned { channel_id, fee_satoshis, signature };
+    runtime.send_p2p(event.endpoints, LnMsg::ClosingSigned(closing_signed))?;
+
+    if agreed {
+        debug!("Closing fee of {} sat is agreed with the remote peer", fee_satoshis);
+        return publish_closing(runtime, event.endpoints);
+    }
+    Ok(ChannelClose::Negotiating)
+}
+
+fn finish_published(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<(), Error> {
+    let tx_status = match event.message {
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status)) => tx_status,
+        wrong_msg => {
+            let lifecycle = runtime.state.state_machine.lifecycle();
+            return Err(Error::UnexpectedMessage(wrong_msg, lifecycle, event.source));
+        }
+    };
+
+    let channel_id = static_channel_id(runtime)?;
+    let message = CtlMsg::ChannelClosed(channel_id);
+    // We swallow error since we do not want to fail the channel if we can't update the router
+    let _ = runtime.send_ctl(event.endpoints, ServiceId::Router, message);
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Untrack(tx_status.txid))?;
+    runtime.state.closing = None;
+    runtime.complete_workflow(event.endpoints, format!("Channel {} is closed", channel_id.ended()));
+    Ok(())
+}
+
+fn sign_closing(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<ChannelClose, Error> {
+    let fee = closing_session(runtime)?.local_fee.expect("closing fee is always initialized");
+    let closing_psbt = compose_closing_psbt(runtime, fee)?;
+    debug!("Closing transaction id is {}", closing_psbt.global.unsigned_tx.txid());
+    runtime.send_ctl(endpoints, ServiceId::Signer, CtlMsg::Sign(closing_psbt))?;
+    Ok(ChannelClose::Signing)
+}
+
+fn publish_closing(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+) -> Result<ChannelClose, Error> {
+    let session = closing_session(runtime)?;
+    let fee = session.local_fee.expect("closing fee is known at publishing stage");
+    let local_sig = session.local_sig.expect("local signature is known at publishing stage");
+    let remote_sig = session.remote_sig.expect("remote signature is known at publishing stage");
+
+    let mut closing_psbt = compose_closing_psbt(runtime, fee)?;
+    let channel = &runtime.state.channel;
+    let local_pubkey = channel.funding_pubkey();
+    let remote_pubkey = channel.constructor().remote_keys().funding_pubkey;
+    let input = &mut closing_psbt.inputs[0];
+    for (pubkey, sig) in vec![(local_pubkey, local_sig), (remote_pubkey, remote_sig)] {
+        let mut sig = sig.serialize_der().to_vec();
+        sig.push(bitcoin::SigHashType::All.as_u32() as u8);
+        input.partial_sigs.insert(bitcoin::PublicKey::new(pubkey), sig);
+    }
+
+    let txid = closing_psbt.global.unsigned_tx.txid();
+    info!("{} closing transaction {}", "Publishing".promo(), txid.promoter());
+    runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::PublishTx(closing_psbt))?;
+    runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+    Ok(ChannelClose::Published)
+}
+
+/// Constructs closing transaction spending channel funding output with the given fee paid by the
+/// channel funder
+fn compose_closing_psbt(runtime: &Runtime, fee: u64) -> Result<Psbt, Error> {
+    let session = closing_session(runtime)?;
+    let remote_script = session.remote_script.clone().ok_or(Error::InvalidState {
+        operation: "construct closing transaction",
+        current_state: runtime.state.state_machine.lifecycle(),
+    })?;
+    let channel = &runtime.state.channel;
+    let snapshot = runtime.state.channel_snapshot();
+    let funding = channel.funding();
+
+    let mut to_local = snapshot.local_amount_msat / 1000;
+    let mut to_remote = snapshot.remote_amount_msat / 1000;
+    match runtime.state.is_funder {
+        true => to_local = to_local.saturating_sub(fee),
+        false => to_remote = to_remote.saturating_sub(fee),
+    }
+
+    let dust_limit = snapshot.local_params.dust_limit_satoshis;
+    let mut output = vec![(to_local, session.local_script.clone()), (to_remote, remote_script)]
+        .into_iter()
+        .filter(|(value, _)| *value >= dust_limit)
+        .map(|(value, script)| TxOut { value, script_pubkey: script.into() })
+        .collect::<Vec<_>>();
+    // BIP-69 output ordering required by BOLT-3
+    output.sort_by(|a, b| (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey)));
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(funding.txid(), funding.output() as u32),
+            script_sig: Script::new(),
+            sequence: 0xFFFFFFFF,
+            witness: vec![],
+        }],
+        output,
+    };
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("closing transaction is constructed unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(TxOut {
+        value: funding.amount(),
+        script_pubkey: channel.funding_script_pubkey().into(),
+    });
+    input.witness_script = Some(funding_witness_script(
+        channel.funding_pubkey(),
+        channel.constructor().remote_keys().funding_pubkey,
+    ));
+    Ok(Psbt::from(psbt))
+}
+
+/// Constructs 2-of-2 multisig witness script for the channel funding output according to BOLT-3
+fn funding_witness_script(local_pubkey: PublicKey, remote_pubkey: PublicKey) -> Script {
+    let mut pubkeys = [local_pubkey.serialize(), remote_pubkey.serialize()];
+    pubkeys.sort();
+    script::Builder::new()
+        .push_opcode(OP_PUSHNUM_2)
+        .push_slice(&pubkeys[0])
+        .push_slice(&pubkeys[1])
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+fn ensure_no_htlcs(runtime: &Runtime) -> Result<(), Error> {
+    let snapshot = runtime.state.channel_snapshot();
+    match snapshot.offered_htlcs.len() + snapshot.received_htlcs.len() {
+        0 => Ok(()),
+        count => Err(Error::HtlcsPending(count)),
+    }
+}
+
+fn static_channel_id(runtime: &Runtime) -> Result<ChannelId, Error> {
+    runtime.state.channel.active_channel_id().channel_id().ok_or(Error::InvalidState {
+        operation: "close channel",
+        current_state: runtime.state.state_machine.lifecycle(),
+    })
+}
+
+fn closing_session(runtime: &Runtime) -> Result<&ClosingSession, Error> {
+    runtime.state.closing.as_ref().ok_or(Error::InvalidState {
+        operation: "negotiate channel closing",
+        current_state: runtime.state.state_machine.lifecycle(),
+    })
+}
+
+fn closing_session_mut(runtime: &mut Runtime) -> Result<&mut ClosingSession, Error> {
+    let current_state = runtime.state.state_machine.lifecycle();
+    runtime
+        .state
+        .closing
+        .as_mut()
+        .ok_or(Error::InvalidState { operation: "negotiate channel closing", current_state })
+}
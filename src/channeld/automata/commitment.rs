@@ -0,0 +1,253 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Signing of the updated remote commitment transaction together with its second-stage HTLC
+//! transactions, which signatures are sent to the remote peer with `commitment_signed` message.
+
+use bitcoin::secp256k1::{Secp256k1, Signature};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut, Txid};
+use lnp::channel::bolt::Lifecycle;
+use psbt::Psbt;
+
+use super::bolt3::{
+    derive_pubkey, derive_revocation_pubkey, has_anchors, htlc_tx_fee, offered_htlc_script,
+    received_htlc_script, to_local_script,
+};
+use super::Error;
+use crate::bus::CtlMsg;
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::Endpoints;
+
+/// Remote commitment transaction and its second-stage HTLC transactions being signed by signd
+pub struct SigningSession {
+    /// Id of the remote commitment transaction
+    commitment_txid: Txid,
+
+    /// Remote commitment transaction, once it is signed
+    commitment_psbt: Option<Psbt>,
+
+    /// Ids of the second-stage HTLC transactions, ordered by the index of the HTLC output they
+    /// spend, together with our signatures once they are known
+    htlc_txs: Vec<(Txid, Option<Signature>)>,
+}
+
+/// Starts signing of the remote commitment transaction by sending it and its second-stage HTLC
+/// transactions to signd
+pub fn start(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    commitment_psbt: Psbt,
+) -> Result<(), Error> {
+    if runtime.commitment_signing.is_some() {
+        return Err(Error::InvalidState {
+            operation: "sign remote commitment while the previous one is being signed",
+            current_state: runtime.state.state_machine.lifecycle(),
+        });
+    }
+
+    let mut commitment_psbt = commitment_psbt;
+    let commitment_txid = commitment_psbt.global.unsigned_tx.txid();
+    let htlc_psbts = compose_remote_htlc_txs(runtime, &mut commitment_psbt)?;
+    let per_commitment_point = runtime.state.channel_snapshot().remote_per_commitment_point;
+    let mut htlc_txs = Vec::with_capacity(htlc_psbts.len());
+    for psbt in htlc_psbts {
+        htlc_txs.push((psbt.global.unsigned_tx.txid(), None));
+        let message = CtlMsg::SignHtlc { psbt, per_commitment_point };
+        runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+    }
+
+    debug!(
+        "Signing remote commitment transaction {} with {} HTLC transactions",
+        commitment_txid,
+        htlc_txs.len()
+    );
+    runtime.send_ctl(endpoints, ServiceId::Signer, CtlMsg::Sign(commitment_psbt))?;
+    runtime.commitment_signing =
+        Some(SigningSession { commitment_txid, commitment_psbt: None, htlc_txs });
+    Ok(())
+}
+
+/// Processes transaction signed by signd for the remote commitment, sending `commitment_signed`
+/// to the remote peer once all transactions are signed. Returns `true` if the transaction belongs
+/// to the signing session and should not be processed further.
+pub fn process_signed(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    psbt: &Psbt,
+) -> Result<bool, Error> {
+    let mut session = match runtime.commitment_signing.take() {
+        Some(session) => session,
+        None => return Ok(false),
+    };
+
+    let txid = psbt.global.unsigned_tx.txid();
+    if txid == session.commitment_txid {
+        session.commitment_psbt = Some(psbt.clone());
+    } else if let Some(pos) = session.htlc_txs.iter().position(|(htlc_txid, _)| *htlc_txid == txid)
+    {
+        let signature = match htlc_signature(psbt) {
+            Ok(signature) => signature,
+            Err(err) => {
+                warn!("Remote commitment {} is not signed: {}", session.commitment_txid, err);
+                return Err(err);
+            }
+        };
+        session.htlc_txs[pos].1 = Some(signature);
+    } else {
+        runtime.commitment_signing = Some(session);
+        return Ok(false);
+    }
+
+    let htlc_signatures =
+        session.htlc_txs.iter().filter_map(|(_, signature)| *signature).collect::<Vec<_>>();
+    let commitment_psbt = match session.commitment_psbt {
+        Some(ref psbt) if htlc_signatures.len() == session.htlc_txs.len() => psbt.clone(),
+        _ => {
+            runtime.commitment_signing = Some(session);
+            return Ok(true);
+        }
+    };
+    runtime.complete_commitment_signing(endpoints, commitment_psbt, htlc_signatures)?;
+    Ok(true)
+}
+
+/// Extracts our signature from the second-stage HTLC transaction signed by signd
+fn htlc_signature(psbt: &Psbt) -> Result<Signature, Error> {
+    let input = psbt.inputs.get(0).expect("HTLC transaction always has a single input");
+    let signature = input.partial_sigs.values().next().ok_or(Error::InvalidState {
+        operation: "send HTLC transaction not signed by signd",
+        current_state: Lifecycle::Active,
+    })?;
+    // TODO: Use BitcoinSignature type for parsing signature once bitcoin 0.27 is released
+    Signature::from_der(&signature[..signature.len() - 1]).map_err(Error::InvalidSig)
+}
+
+/// Constructs second-stage HTLC transactions spending each HTLC output of the remote commitment
+/// transaction, ordered by the output index. HTLCs trimmed from the commitment transaction have
+/// no outputs and do not require signatures. Witness scripts of the HTLC outputs are kept in the
+/// commitment PSBT, such that the outputs can be claimed if the commitment gets revoked.
+fn compose_remote_htlc_txs(
+    runtime: &Runtime,
+    commitment_psbt: &mut Psbt,
+) -> Result<Vec<Psbt>, Error> {
+    let commitment_tx = commitment_psbt.global.unsigned_tx.clone();
+    let secp = Secp256k1::verification_only();
+    let channel = &runtime.state.channel;
+    let snapshot = runtime.state.channel_snapshot();
+    let local_keys = channel.constructor().local_keys();
+    let remote_keys = channel.constructor().remote_keys();
+    let per_commitment_point = snapshot.remote_per_commitment_point;
+    let channel_type = snapshot.common_params.channel_type;
+    let feerate_per_kw = snapshot.common_params.feerate_per_kw;
+    let anchors = has_anchors(channel_type);
+
+    // Keys are taken from the remote peer perspective: the remote commitment transaction is
+    // revocable with our revocation basepoint
+    let revocation_pubkey =
+        derive_revocation_pubkey(&secp, local_keys.revocation_basepoint.key, per_commitment_point)?;
+    let local_htlc_pubkey =
+        derive_pubkey(&secp, local_keys.htlc_basepoint.key, per_commitment_point)?;
+    let remote_htlc_pubkey =
+        derive_pubkey(&secp, remote_keys.htlc_basepoint, per_commitment_point)?;
+    let remote_delayed_pubkey =
+        derive_pubkey(&secp, remote_keys.delayed_payment_basepoint, per_commitment_point)?;
+    let delayed_script = to_local_script(
+        revocation_pubkey,
+        remote_delayed_pubkey,
+        snapshot.local_params.to_self_delay,
+    );
+
+    // HTLCs offered by us are received by the remote peer and vice versa
+    let htlcs = snapshot
+        .received_htlcs
+        .iter()
+        .map(|htlc| {
+            let witness_script = offered_htlc_script(
+                revocation_pubkey,
+                remote_htlc_pubkey,
+                local_htlc_pubkey,
+                htlc.hashlock,
+                anchors,
+            );
+            (witness_script, true, htlc.cltv_expiry)
+        })
+        .chain(snapshot.offered_htlcs.iter().map(|htlc| {
+            let witness_script = received_htlc_script(
+                revocation_pubkey,
+                remote_htlc_pubkey,
+                local_htlc_pubkey,
+                htlc.hashlock,
+                htlc.cltv_expiry,
+                anchors,
+            );
+            (witness_script, false, 0)
+        }));
+
+    let txid = commitment_tx.txid();
+    let mut matched = vec![];
+    let mut htlc_psbts = vec![];
+    for (witness_script, offered, lock_time) in htlcs {
+        let script_pubkey = witness_script.to_v0_p2wsh();
+        // HTLCs with the same payment hash, amount and expiry have equal outputs
+        let output = commitment_tx.output.iter().enumerate().find(|(vout, txout)| {
+            txout.script_pubkey == script_pubkey && !matched.contains(vout)
+        });
+        let (vout, prevout) = match output {
+            Some((vout, txout)) => (vout, txout.clone()),
+            None => continue,
+        };
+        matched.push(vout);
+        let fee = htlc_tx_fee(offered, feerate_per_kw, channel_type);
+
+        let tx = Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(txid, vout as u32),
+                script_sig: Script::new(),
+                // Anchor outputs require HTLC outputs to be spent with one block relative timelock
+                sequence: anchors as u32,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: prevout.value.saturating_sub(fee),
+                script_pubkey: delayed_script.to_v0_p2wsh(),
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+            .expect("HTLC transaction is constructed unsigned");
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(prevout);
+        input.witness_script = Some(witness_script.clone());
+        // With anchor outputs we sign second-stage transactions in a way allowing the remote peer
+        // to attach more inputs and outputs paying the fee
+        input.sighash_type =
+            Some(if anchors { SigHashType::SinglePlusAnyoneCanPay } else { SigHashType::All });
+        // signd uses HTLC basepoint derivation to produce our HTLC key
+        let htlc_basepoint = &local_keys.htlc_basepoint;
+        input
+            .bip32_derivation
+            .insert(bitcoin::PublicKey::new(htlc_basepoint.key), htlc_basepoint.source.clone());
+        htlc_psbts.push((vout, Psbt::from(psbt)));
+        if let Some(output) = commitment_psbt.outputs.get_mut(vout) {
+            output.witness_script = Some(witness_script);
+        }
+    }
+
+    // Remote peer expects signatures in the order of the HTLC outputs in the commitment
+    htlc_psbts.sort_by_key(|(vout, _)| *vout);
+    Ok(htlc_psbts.into_iter().map(|(_, psbt)| psbt).collect())
+}
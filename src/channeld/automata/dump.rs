@@ -0,0 +1,207 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Dump of the latest local commitment transaction and its second-stage HTLC transactions for
+//! the disaster recovery. The transactions are signed, but never published by the node: the
+//! operator publishes them manually if the node is unable to close the channel by itself.
+
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Transaction, Txid};
+use lnp::channel::bolt::Lifecycle;
+use lnp_rpc::{CommitmentDump, EventDirection, RpcMsg};
+use psbt::Psbt;
+
+use super::abort::compose_local_commitment;
+use super::htlc::{
+    finalize_second_stage, sign_second_stage, HtlcClaim, HtlcDirection, HtlcResolution,
+};
+use super::reestablish::ChannelReestablishing;
+use super::{ChannelStateMachine, Error};
+use crate::bus::CtlMsg;
+use crate::channeld::runtime::Runtime;
+use crate::rpc::{ClientId, RpcError, ServiceId};
+use crate::{Endpoints, Responder};
+
+/// Commitment dump awaiting for the transactions to be signed by signd
+pub struct DumpSession {
+    /// Client which has requested the dump
+    enquirer: ClientId,
+
+    /// Id of the dumped commitment transaction
+    commitment_txid: Txid,
+
+    /// Commitment transaction, once it is signed
+    commitment_tx: Option<Transaction>,
+
+    /// HTLC outputs of the commitment transaction which second-stage transactions are not yet
+    /// signed
+    claims: Vec<HtlcClaim>,
+
+    /// Signed second-stage HTLC transactions
+    htlc_txs: Vec<Transaction>,
+}
+
+/// Starts dumping the latest local commitment transaction by sending it and its second-stage
+/// HTLC transactions to signd
+pub fn start(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    enquirer: ClientId,
+) -> Result<(), Error> {
+    match runtime.state.state_machine {
+        // Our commitment transaction is outdated, so publishing it would lead to funds loss
+        ChannelStateMachine::Reestablishing(ChannelReestablishing::Frozen) => {
+            return Err(Error::InvalidState {
+                operation: "dump outdated commitment transaction",
+                current_state: Lifecycle::Reestablishing,
+            })
+        }
+        ChannelStateMachine::Active
+        | ChannelStateMachine::Reestablishing(_)
+        | ChannelStateMachine::Closing(_) => {}
+        state_machine => {
+            return Err(Error::InvalidState {
+                operation: "dump commitment transaction",
+                current_state: state_machine.lifecycle(),
+            })
+        }
+    }
+    if runtime.dump.is_some() {
+        return Err(Error::InvalidState {
+            operation: "dump commitment transaction while the previous dump is in progress",
+            current_state: runtime.state.state_machine.lifecycle(),
+        });
+    }
+
+    let commitment_psbt = compose_local_commitment(runtime)?;
+    let commitment_txid = commitment_psbt.global.unsigned_tx.txid();
+
+    let mut claims = vec![];
+    if let Some(resolution) = HtlcResolution::with(runtime, &commitment_psbt)? {
+        for mut claim in resolution.claims {
+            let preimage_known = runtime.state.htlc_preimages.contains_key(&claim.hash_lock);
+            if claim.direction == HtlcDirection::Received && !preimage_known {
+                warn!(
+                    "Preimage for received HTLC {} is not known; its HTLC-success transaction is \
+                     not dumped",
+                    claim.hash_lock
+                );
+                continue;
+            }
+            sign_second_stage(
+                runtime,
+                endpoints,
+                resolution.per_commitment_point,
+                &resolution.delayed_script,
+                &mut claim,
+            )?;
+            // HTLCs which are not worth claiming are not signed
+            if claim.second_stage_txid.is_some() {
+                claims.push(claim);
+            }
+        }
+    }
+
+    debug!("Signing commitment transaction {} for the dump", commitment_txid);
+    runtime.send_ctl(endpoints, ServiceId::Signer, CtlMsg::Sign(commitment_psbt))?;
+    runtime.dump = Some(DumpSession {
+        enquirer,
+        commitment_txid,
+        commitment_tx: None,
+        claims,
+        htlc_txs: vec![],
+    });
+    Ok(())
+}
+
+/// Processes transaction signed by signd for the commitment dump, sending the dump to the
+/// client once all transactions are signed. Returns `true` if the transaction belongs to the
+/// dump and should not be processed further.
+pub fn process_signed(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    psbt: &Psbt,
+) -> Result<bool, Error> {
+    let mut session = match runtime.dump.take() {
+        Some(session) => session,
+        None => return Ok(false),
+    };
+
+    let txid = psbt.global.unsigned_tx.txid();
+    if txid == session.commitment_txid {
+        let mut psbt = psbt.clone();
+        let secp = Secp256k1::verification_only();
+        if let Err(err) = miniscript::psbt::finalize(&mut psbt, &secp) {
+            let err = Error::Finalization(txid, err.to_string());
+            return fail(runtime, endpoints, session.enquirer, err);
+        }
+        session.commitment_tx = Some(psbt.extract_tx());
+    } else if let Some(pos) =
+        session.claims.iter().position(|claim| claim.second_stage_txid == Some(txid))
+    {
+        let claim = session.claims.remove(pos);
+        let preimage = runtime.state.htlc_preimages.get(&claim.hash_lock).cloned();
+        let channel_type = runtime.state.channel_snapshot().common_params.channel_type;
+        match finalize_second_stage(psbt.clone(), &claim, preimage, channel_type) {
+            Ok(psbt) => session.htlc_txs.push(psbt.extract_tx()),
+            Err(err) => return fail(runtime, endpoints, session.enquirer, err),
+        }
+    } else {
+        runtime.dump = Some(session);
+        return Ok(false);
+    }
+
+    let commitment_tx = match session.commitment_tx {
+        Some(ref tx) if session.claims.is_empty() => tx,
+        _ => {
+            runtime.dump = Some(session);
+            return Ok(true);
+        }
+    };
+    let dump = CommitmentDump {
+        commitment_tx: serialize_hex(commitment_tx),
+        htlc_txs: session.htlc_txs.iter().map(serialize_hex).collect(),
+    };
+    warn!(
+        "Commitment transaction {} is dumped with {} HTLC transactions; publishing it will \
+         force-close the channel",
+        session.commitment_txid,
+        session.htlc_txs.len()
+    );
+    let outcome = format!(
+        "commitment transaction {} with {} HTLC transactions is dumped",
+        session.commitment_txid,
+        session.htlc_txs.len()
+    );
+    let lifecycle = runtime.state.state_machine.lifecycle();
+    let client = ServiceId::Client(session.enquirer);
+    let message = s!("commitment_dump");
+    runtime.record_event(lifecycle, EventDirection::Outbound, message, client, outcome);
+    runtime.send_rpc(endpoints, session.enquirer, RpcMsg::CommitmentDump(dump))?;
+    Ok(true)
+}
+
+/// Reports failure of the commitment dump to the client, abandoning the dump
+fn fail(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    enquirer: ClientId,
+    err: Error,
+) -> Result<bool, Error> {
+    warn!("Commitment dump has failed: {}", err);
+    let failure = RpcError::from(&err);
+    runtime.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+    Ok(true)
+}
@@ -0,0 +1,634 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! On-chain resolution of HTLCs pending at the moment of the unilateral channel close with
+//! second-stage HTLC-timeout and HTLC-success transactions
+
+use amplify::Wrapper;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, Signature};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, SigHashType, Transaction, TxIn, TxOut, Txid};
+use lnp::channel::bolt::Lifecycle;
+use lnp::p2p::legacy::ChannelType;
+use lnp::Extension;
+use psbt::Psbt;
+use wallet::hlc::{HashLock, HashPreimage};
+use wallet::scripts::PubkeyScript;
+
+use super::abort::compose_delayed_sweep;
+use super::bolt3::{
+    derive_pubkey, derive_revocation_pubkey, has_anchors, has_zero_fee_htlc, offered_htlc_script,
+    received_htlc_script, to_local_script,
+};
+use super::Error;
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::service::LogStyle;
+use crate::{Endpoints, Responder};
+
+/// Weight of HTLC-timeout transaction according to BOLT-3, in weight units
+const HTLC_TIMEOUT_WEIGHT: u64 = 663;
+
+/// Weight of HTLC-success transaction according to BOLT-3, in weight units
+const HTLC_SUCCESS_WEIGHT: u64 = 703;
+
+/// Weight of the HTLC-timeout input witness of the channels with anchor outputs, in weight units
+const HTLC_TIMEOUT_WITNESS_WEIGHT: u64 = 288;
+
+/// Weight of the HTLC-success input witness of the channels with anchor outputs, in weight units
+const HTLC_SUCCESS_WITNESS_WEIGHT: u64 = 327;
+
+/// Direction of HTLC in our commitment transaction
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, StrictEncode, StrictDecode)]
+pub enum HtlcDirection {
+    /// HTLC offered by us, which we claim back with HTLC-timeout transaction after its expiry
+    #[display("offered")]
+    Offered,
+
+    /// HTLC received by us, which we claim with HTLC-success transaction using the known
+    /// payment preimage
+    #[display("received")]
+    Received,
+}
+
+/// HTLC output of our published commitment transaction and the progress of its on-chain
+/// resolution
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct HtlcClaim {
+    /// Direction of the HTLC
+    pub direction: HtlcDirection,
+
+    /// Payment hash locking the HTLC
+    pub hash_lock: HashLock,
+
+    /// Absolute block height after which the HTLC expires
+    pub cltv_expiry: u32,
+
+    /// HTLC output of the commitment transaction
+    pub outpoint: OutPoint,
+
+    /// Value and script pubkey of the HTLC output
+    pub prevout: TxOut,
+
+    /// Witness script of the HTLC output
+    pub witness_script: Script,
+
+    /// Remote peer signature for the second-stage transaction, received with the
+    /// `commitment_signed` message
+    pub remote_sig: Option<Signature>,
+
+    /// Id of the second-stage transaction, once it is constructed
+    pub second_stage_txid: Option<Txid>,
+
+    /// Output of the second-stage transaction
+    pub second_stage_output: Option<TxOut>,
+
+    /// Height at which the output of the second-stage transaction becomes spendable, known once
+    /// the second-stage transaction is mined
+    pub mature_height: Option<u32>,
+
+    /// Id of the transaction sweeping the output of the second-stage transaction
+    pub sweep_txid: Option<Txid>,
+
+    /// Indicates that no further actions are required for the HTLC
+    pub resolved: bool,
+}
+
+/// Data on the on-chain resolution of the HTLCs pending in our published commitment transaction
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct HtlcResolution {
+    /// Our per-commitment point for the published commitment transaction
+    pub per_commitment_point: PublicKey,
+
+    /// Witness script of the second-stage transaction outputs, which are delayed in the same way
+    /// as the to-local output of the commitment transaction
+    pub delayed_script: Script,
+
+    /// Script receiving swept funds, provided by the funding wallet
+    pub sweep_script: Option<PubkeyScript>,
+
+    /// Current blockchain height, as it was last reported by the on-chain tracking service
+    pub height: Option<u32>,
+
+    /// HTLC outputs of the published commitment transaction, ordered by their output index
+    pub claims: Vec<HtlcClaim>,
+}
+
+impl HtlcResolution {
+    /// Detects HTLC outputs in our commitment transaction. Returns `None` if the commitment
+    /// transaction has no HTLC outputs.
+    pub fn with(runtime: &Runtime, commitment_psbt: &Psbt) -> Result<Option<Self>, Error> {
+        let secp = Secp256k1::verification_only();
+        let channel = &runtime.state.channel;
+        let snapshot = runtime.state.channel_snapshot();
+        let local_keys = channel.constructor().local_keys();
+        let remote_keys = channel.constructor().remote_keys();
+        let per_commitment_point = snapshot.local_per_commitment_point;
+        let anchors = has_anchors(snapshot.common_params.channel_type);
+
+        let revocation_pubkey = derive_revocation_pubkey(
+            &secp,
+            remote_keys.revocation_basepoint,
+            per_commitment_point,
+        )?;
+        let local_htlc_pubkey =
+            derive_pubkey(&secp, local_keys.htlc_basepoint.key, per_commitment_point)?;
+        let remote_htlc_pubkey =
+            derive_pubkey(&secp, remote_keys.htlc_basepoint, per_commitment_point)?;
+        let delayed_pubkey =
+            derive_pubkey(&secp, local_keys.delayed_payment_basepoint.key, per_commitment_point)?;
+
+        let htlcs = snapshot
+            .offered_htlcs
+            .iter()
+            .map(|htlc| (HtlcDirection::Offered, htlc.hashlock, htlc.cltv_expiry))
+            .chain(
+                snapshot
+                    .received_htlcs
+                    .iter()
+                    .map(|htlc| (HtlcDirection::Received, htlc.hashlock, htlc.cltv_expiry)),
+            );
+
+        let tx = &commitment_psbt.global.unsigned_tx;
+        let mut claims = vec![];
+        for (direction, hash_lock, cltv_expiry) in htlcs {
+            let witness_script = match direction {
+                HtlcDirection::Offered => offered_htlc_script(
+                    revocation_pubkey,
+                    local_htlc_pubkey,
+                    remote_htlc_pubkey,
+                    hash_lock,
+                    anchors,
+                ),
+                HtlcDirection::Received => received_htlc_script(
+                    revocation_pubkey,
+                    local_htlc_pubkey,
+                    remote_htlc_pubkey,
+                    hash_lock,
+                    cltv_expiry,
+                    anchors,
+                ),
+            };
+            let script_pubkey = witness_script.to_v0_p2wsh();
+            // HTLCs below dust limit are trimmed and do not have commitment outputs
+            if let Some((vout, prevout)) =
+                tx.output.iter().enumerate().find(|(_, txout)| txout.script_pubkey == script_pubkey)
+            {
+                claims.push(HtlcClaim {
+                    direction,
+                    hash_lock,
+                    cltv_expiry,
+                    outpoint: OutPoint::new(tx.txid(), vout as u32),
+                    prevout: prevout.clone(),
+                    witness_script,
+                    remote_sig: None,
+                    second_stage_txid: None,
+                    second_stage_output: None,
+                    mature_height: None,
+                    sweep_txid: None,
+                    resolved: false,
+                });
+            }
+        }
+        if claims.is_empty() {
+            return Ok(None);
+        }
+
+        // Remote peer provides signatures for the second-stage transactions in the order of the
+        // HTLC outputs in the commitment transaction
+        claims.sort_by_key(|claim| claim.outpoint.vout);
+        let remote_sigs = &runtime.state.remote_htlc_sigs;
+        if remote_sigs.len() != claims.len() {
+            warn!(
+                "Commitment transaction has {} HTLC outputs, while remote peer has provided {} \
+                 HTLC signatures",
+                claims.len(),
+                remote_sigs.len()
+            );
+        }
+        for (claim, sig) in claims.iter_mut().zip(remote_sigs) {
+            claim.remote_sig = Some(*sig);
+        }
+
+        Ok(Some(HtlcResolution {
+            per_commitment_point,
+            delayed_script: to_local_script(
+                revocation_pubkey,
+                delayed_pubkey,
+                snapshot.remote_params.to_self_delay,
+            ),
+            sweep_script: None,
+            height: None,
+            claims,
+        }))
+    }
+
+    /// Checks whether all HTLCs are resolved on-chain
+    pub fn is_resolved(&self) -> bool { self.claims.iter().all(|claim| claim.resolved) }
+}
+
+/// Starts on-chain resolution of the pending HTLCs once our commitment transaction is mined:
+/// claims received HTLCs for which we know the preimage and asks on-chain tracking service to
+/// notify us on the expiry of the offered HTLCs
+pub fn start(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<(), Error> {
+    let mut resolution = match runtime.state.htlcs.clone() {
+        Some(resolution) => resolution,
+        None => return Ok(()),
+    };
+
+    let per_commitment_point = resolution.per_commitment_point;
+    for claim in &mut resolution.claims {
+        let preimage_known = runtime.state.htlc_preimages.contains_key(&claim.hash_lock);
+        match claim.direction {
+            HtlcDirection::Offered => {
+                let message = CtlMsg::TrackHeight(claim.cltv_expiry);
+                runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+            }
+            HtlcDirection::Received if preimage_known => {
+                let delayed_script = &resolution.delayed_script;
+                sign_second_stage(runtime, endpoints, per_commitment_point, delayed_script, claim)?;
+            }
+            HtlcDirection::Received => {
+                // The remote peer will claim the HTLC back after its expiry
+                warn!(
+                    "Preimage for received HTLC {} is not known; the HTLC can't be claimed",
+                    claim.hash_lock
+                );
+                claim.resolved = true;
+            }
+        }
+    }
+
+    runtime.state.htlcs = Some(resolution);
+    Ok(())
+}
+
+/// Processes messages related to the on-chain HTLC resolution. Returns `true` if the message
+/// was consumed and should not be processed further.
+pub fn process(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    message: &BusMsg,
+) -> Result<bool, Error> {
+    let mut resolution = match runtime.state.htlcs.clone() {
+        Some(resolution) => resolution,
+        None => return Ok(false),
+    };
+
+    let consumed = match message {
+        // These messages are also used by the to-local output sweeping, so they are not consumed
+        BusMsg::Ctl(CtlMsg::SweepAddress(sweep_script)) => {
+            resolution.sweep_script = Some(sweep_script.clone());
+            advance(runtime, endpoints, &mut resolution)?;
+            false
+        }
+        BusMsg::Ctl(CtlMsg::HeightReached(height))
+        | BusMsg::Ctl(CtlMsg::NewBlock { height, .. }) => {
+            resolution.height = Some(*height);
+            advance(runtime, endpoints, &mut resolution)?;
+            false
+        }
+        BusMsg::Ctl(CtlMsg::HtlcTxFunded(psbt)) => {
+            let outpoint = psbt.global.unsigned_tx.input[0].previous_output;
+            if let Some(claim) = resolution
+                .claims
+                .iter_mut()
+                .find(|claim| claim.outpoint == outpoint && claim.second_stage_txid.is_some())
+            {
+                let txid = psbt.global.unsigned_tx.txid();
+                debug!("HTLC {} is claimed with funded transaction {}", claim.hash_lock, txid);
+                claim.second_stage_txid = Some(txid);
+                let message = CtlMsg::SignHtlc {
+                    psbt: psbt.clone(),
+                    per_commitment_point: resolution.per_commitment_point,
+                };
+                runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+                true
+            } else {
+                false
+            }
+        }
+        BusMsg::Ctl(CtlMsg::Signed(psbt)) => {
+            let txid = psbt.global.unsigned_tx.txid();
+            if let Some(claim) =
+                resolution.claims.iter_mut().find(|claim| claim.second_stage_txid == Some(txid))
+            {
+                let preimage = runtime.state.htlc_preimages.get(&claim.hash_lock).cloned();
+                let channel_type = runtime.state.channel_snapshot().common_params.channel_type;
+                let psbt = finalize_second_stage(psbt.clone(), claim, preimage, channel_type)?;
+                info!(
+                    "{} second-stage transaction {} for {} HTLC {}",
+                    "Publishing".promo(),
+                    txid.promoter(),
+                    claim.direction,
+                    claim.hash_lock
+                );
+                runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::PublishTx(psbt))?;
+                runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+                true
+            } else if resolution.claims.iter().any(|claim| claim.sweep_txid == Some(txid)) {
+                info!("{} HTLC sweep transaction {}", "Publishing".promo(), txid.promoter());
+                let message = CtlMsg::PublishTx(psbt.clone());
+                runtime.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+                runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+                true
+            } else {
+                false
+            }
+        }
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status)) => {
+            let txid = tx_status.txid;
+            let to_self_delay = runtime.state.channel_snapshot().remote_params.to_self_delay;
+            if let Some(claim) =
+                resolution.claims.iter_mut().find(|claim| claim.second_stage_txid == Some(txid))
+            {
+                // Output of the second-stage transaction is delayed in the same way as our
+                // to-local output
+                let mature_height = u32::from(tx_status.height) + to_self_delay as u32;
+                claim.mature_height = Some(mature_height);
+                runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Untrack(txid))?;
+                let message = CtlMsg::TrackHeight(mature_height);
+                runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+                true
+            } else if let Some(claim) =
+                resolution.claims.iter_mut().find(|claim| claim.sweep_txid == Some(txid))
+            {
+                info!("{} HTLC {} is resolved on-chain", "Channel".ended(), claim.hash_lock);
+                claim.resolved = true;
+                runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Untrack(txid))?;
+                true
+            } else {
+                false
+            }
+        }
+        _ => false,
+    };
+
+    runtime.state.htlcs = Some(resolution);
+    Ok(consumed)
+}
+
+/// Claims expired offered HTLCs and sweeps matured outputs of the second-stage transactions
+/// according to the current blockchain height
+fn advance(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    resolution: &mut HtlcResolution,
+) -> Result<(), Error> {
+    let height = match resolution.height {
+        Some(height) => height,
+        None => return Ok(()),
+    };
+    let per_commitment_point = resolution.per_commitment_point;
+    for claim in &mut resolution.claims {
+        if claim.resolved {
+            continue;
+        }
+        let delayed_script = &resolution.delayed_script;
+        match (claim.second_stage_txid, claim.mature_height, claim.sweep_txid) {
+            (None, _, _)
+                if claim.direction == HtlcDirection::Offered && height >= claim.cltv_expiry =>
+            {
+                sign_second_stage(runtime, endpoints, per_commitment_point, delayed_script, claim)?;
+            }
+            (Some(_), Some(mature_height), None) if height >= mature_height => {
+                if let Some(ref sweep_script) = resolution.sweep_script {
+                    let sweep_script = sweep_script.clone();
+                    sign_sweep(runtime, endpoints, delayed_script, sweep_script, claim)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Re-issues outstanding requests for the unresolved HTLCs after the channel daemon restart.
+/// Transactions which might have been signed but not published are signed and published again.
+pub fn resume(runtime: &mut Runtime, endpoints: &mut Endpoints) -> Result<(), Error> {
+    let mut resolution = match runtime.state.htlcs.clone() {
+        Some(resolution) => resolution,
+        None => return Ok(()),
+    };
+
+    if resolution.sweep_script.is_none() {
+        runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+    }
+    let per_commitment_point = resolution.per_commitment_point;
+    for claim in &mut resolution.claims {
+        if claim.resolved {
+            continue;
+        }
+        let delayed_script = &resolution.delayed_script;
+        match (claim.second_stage_txid, claim.mature_height, claim.sweep_txid) {
+            (None, _, _) => {
+                let message = CtlMsg::TrackHeight(claim.cltv_expiry);
+                runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+            }
+            (Some(_), None, _) => {
+                sign_second_stage(runtime, endpoints, per_commitment_point, delayed_script, claim)?;
+            }
+            (Some(_), Some(mature_height), None) => {
+                let message = CtlMsg::TrackHeight(mature_height);
+                runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+            }
+            (Some(_), Some(mature_height), Some(_)) => match resolution.sweep_script {
+                Some(ref sweep_script) => {
+                    let sweep_script = sweep_script.clone();
+                    sign_sweep(runtime, endpoints, delayed_script, sweep_script, claim)?;
+                }
+                None => {
+                    let message = CtlMsg::TrackHeight(mature_height);
+                    runtime.send_ctl(endpoints, ServiceId::Watch, message)?;
+                }
+            },
+        }
+    }
+
+    runtime.state.htlcs = Some(resolution);
+    Ok(())
+}
+
+/// Checks whether all HTLCs pending in our published commitment transaction are resolved
+pub fn is_resolved(runtime: &Runtime) -> bool {
+    runtime.state.htlcs.as_ref().map(HtlcResolution::is_resolved).unwrap_or(true)
+}
+
+/// Constructs second-stage HTLC transaction and sends it to signd for signing with our HTLC key
+pub(super) fn sign_second_stage(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    per_commitment_point: PublicKey,
+    delayed_script: &Script,
+    claim: &mut HtlcClaim,
+) -> Result<(), Error> {
+    if claim.remote_sig.is_none() {
+        warn!("No remote signature for {} HTLC {}", claim.direction, claim.hash_lock);
+        claim.resolved = true;
+        return Ok(());
+    }
+    let common_params = runtime.state.channel_snapshot().common_params;
+    let channel_type = common_params.channel_type;
+    let (weight, witness_weight, lock_time) = match claim.direction {
+        HtlcDirection::Offered => {
+            (HTLC_TIMEOUT_WEIGHT, HTLC_TIMEOUT_WITNESS_WEIGHT, claim.cltv_expiry)
+        }
+        HtlcDirection::Received => (HTLC_SUCCESS_WEIGHT, HTLC_SUCCESS_WITNESS_WEIGHT, 0),
+    };
+    let zero_fee = has_zero_fee_htlc(channel_type);
+    let fee = if zero_fee {
+        // The fee is paid by the funding wallet inputs added to the transaction
+        0
+    } else {
+        common_params.feerate_per_kw as u64 * weight / 1000
+    };
+    if claim.prevout.value <= fee {
+        warn!("HTLC {} of {} sat is not worth claiming", claim.hash_lock, claim.prevout.value);
+        claim.resolved = true;
+        return Ok(());
+    }
+
+    // Second-stage transactions must be constructed exactly as the remote peer has signed them
+    let tx = Transaction {
+        version: 2,
+        lock_time,
+        input: vec![TxIn {
+            previous_output: claim.outpoint,
+            script_sig: Script::new(),
+            // Anchor outputs require HTLC outputs to be spent with one block relative timelock
+            sequence: has_anchors(channel_type) as u32,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: claim.prevout.value - fee,
+            script_pubkey: delayed_script.to_v0_p2wsh(),
+        }],
+    };
+    claim.second_stage_txid = Some(tx.txid());
+    claim.second_stage_output = Some(tx.output[0].clone());
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("HTLC transaction is constructed unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(claim.prevout.clone());
+    input.witness_script = Some(claim.witness_script.clone());
+    // signd uses HTLC basepoint derivation to produce our HTLC key
+    let htlc_basepoint = &runtime.state.channel.constructor().local_keys().htlc_basepoint;
+    input
+        .bip32_derivation
+        .insert(bitcoin::PublicKey::new(htlc_basepoint.key), htlc_basepoint.source.clone());
+
+    // Zero-fee transactions are not relayed by the bitcoin nodes, so the funding wallet adds
+    // inputs paying the fee; the transaction is signed once they are added
+    let message = if zero_fee {
+        let feerate_per_kw = common_params.feerate_per_kw;
+        CtlMsg::FundHtlcTx { psbt: Psbt::from(psbt), feerate_per_kw, witness_weight }
+    } else {
+        CtlMsg::SignHtlc { psbt: Psbt::from(psbt), per_commitment_point }
+    };
+    let service = if zero_fee { ServiceId::LnpBroker } else { ServiceId::Signer };
+    runtime.send_ctl(endpoints, service, message)?;
+    Ok(())
+}
+
+/// Constructs witness for the second-stage HTLC transaction signed by signd. HTLC input is the
+/// first one; the rest are P2WPKH inputs of the funding wallet paying the fee of zero-fee HTLC
+/// transactions.
+pub(super) fn finalize_second_stage(
+    mut psbt: Psbt,
+    claim: &HtlcClaim,
+    preimage: Option<HashPreimage>,
+    channel_type: ChannelType,
+) -> Result<Psbt, Error> {
+    for input in psbt.inputs.iter_mut().skip(1) {
+        let (pubkey, sig) = input.partial_sigs.iter().next().ok_or(Error::InvalidState {
+            operation: "publish HTLC transaction with funding inputs not signed by signd",
+            current_state: Lifecycle::Aborting,
+        })?;
+        input.final_script_witness = Some(vec![sig.clone(), pubkey.to_bytes()]);
+        input.partial_sigs.clear();
+    }
+    let input = psbt.inputs.get_mut(0).expect("HTLC transaction always has HTLC input");
+    let local_sig = input.partial_sigs.values().next().cloned().ok_or(Error::InvalidState {
+        operation: "publish HTLC transaction not signed by signd",
+        current_state: Lifecycle::Aborting,
+    })?;
+    let mut remote_sig = claim
+        .remote_sig
+        .expect("HTLC transaction is signed only when remote signature is known")
+        .serialize_der()
+        .to_vec();
+    // With anchor outputs, remote peer signs second-stage transactions in a way allowing us to
+    // add more inputs and outputs to them
+    let sighash_type = if has_anchors(channel_type) {
+        SigHashType::SinglePlusAnyoneCanPay
+    } else {
+        SigHashType::All
+    };
+    remote_sig.push(sighash_type.as_u32() as u8);
+    let preimage = match (claim.direction, preimage) {
+        (HtlcDirection::Offered, _) => vec![],
+        (HtlcDirection::Received, Some(preimage)) => preimage.as_inner().to_vec(),
+        (HtlcDirection::Received, None) => unreachable!("HTLC-success requires known preimage"),
+    };
+    // Leading empty element is required by the CHECKMULTISIG bug
+    input.final_script_witness =
+        Some(vec![vec![], remote_sig, local_sig, preimage, claim.witness_script.to_bytes()]);
+    input.partial_sigs.clear();
+    Ok(psbt)
+}
+
+/// Constructs transaction sweeping matured output of the second-stage HTLC transaction and
+/// sends it to signd
+fn sign_sweep(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    delayed_script: &Script,
+    sweep_script: PubkeyScript,
+    claim: &mut HtlcClaim,
+) -> Result<(), Error> {
+    let (second_stage_txid, prevout) = match (claim.second_stage_txid, &claim.second_stage_output) {
+        (Some(txid), Some(output)) => (txid, output.clone()),
+        _ => unreachable!("second-stage output is swept only after it is mined"),
+    };
+    let feerate = runtime.state.channel_snapshot().common_params.feerate_per_kw;
+    let psbt = match compose_delayed_sweep(
+        runtime,
+        OutPoint::new(second_stage_txid, 0),
+        prevout,
+        delayed_script.clone(),
+        sweep_script,
+        feerate,
+    ) {
+        Ok(psbt) => psbt,
+        Err(err @ Error::SweepOutputDust { .. }) => {
+            warn!("HTLC {} output is abandoned: {}", claim.hash_lock, err);
+            claim.resolved = true;
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+    claim.sweep_txid = Some(psbt.global.unsigned_tx.txid());
+
+    let per_commitment_point = runtime
+        .state
+        .htlcs
+        .as_ref()
+        .expect("HTLC resolution data must be present")
+        .per_commitment_point;
+    let message = CtlMsg::SignDelayed { psbt, per_commitment_point };
+    runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+    Ok(())
+}
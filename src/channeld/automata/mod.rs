@@ -12,26 +12,83 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+pub mod abort;
 pub mod accept;
+pub mod announce;
+pub mod bolt3;
+pub mod claim;
+pub mod close;
+pub mod commitment;
+pub mod dump;
+pub mod htlc;
+pub mod penalize;
 pub mod propose;
+pub mod reestablish;
+pub mod secrets;
 
+use std::iter;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::Wrapper;
 use bitcoin::secp256k1;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey, Signature};
+use bitcoin::{OutPoint, Transaction, Txid};
+use internet2::NodeAddr;
 use lnp::channel;
 use lnp::channel::bolt::Lifecycle;
-use lnp::p2p::legacy::{ActiveChannelId, ChannelReestablish, Messages as LnMsg};
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::{
+    ActiveChannelId, ChannelId, ChannelReestablish, ChannelType, CommitmentSigned,
+    Error as PeerError, FundingLocked, Messages as LnMsg, RevokeAndAck, UpdateFee,
+};
 use microservices::esb;
 use microservices::esb::Handler;
+use psbt::Psbt;
 use strict_encoding::StrictEncode;
+use wallet::scripts::PubkeyScript;
 
+use self::abort::ChannelAbort;
 use self::accept::ChannelAccept;
+use self::bolt3::{
+    derive_pubkey, has_anchors, has_static_remotekey, is_trimmed_htlc, to_remote_script_pubkey,
+    HTLC_OUTPUT_WEIGHT,
+};
+use self::close::{ChannelClose, MAX_CLOSING_ROUNDS};
+use self::penalize::{compose_penalty_psbt, ChannelPenalize};
 use self::propose::ChannelPropose;
+use self::reestablish::ChannelReestablishing;
 use crate::automata::{Event, StateMachine};
 use crate::bus::{BusMsg, CtlMsg};
 use crate::channeld::runtime::Runtime;
-use crate::rpc::{Failure, ServiceId};
+use crate::channeld::{
+    self, BackupError, ChannelState, ExportError, RevokedCommitment, ShachainError, StateError,
+};
+use crate::rpc::{EventDirection, ErrorCode, NodeEvent, RpcError, ServiceId, ToRpcError};
 use crate::service::LogStyle;
-use crate::{Endpoints, Responder};
+use crate::{Endpoints, ProposeTimeouts, Responder};
+
+/// Weight of the commitment transaction without HTLC outputs according to BOLT-3, in weight
+/// units
+const COMMITMENT_WEIGHT: u64 = 724;
+
+/// Weight of the commitment transaction with anchor outputs and without HTLC outputs according
+/// to BOLT-3, in weight units
+const COMMITMENT_ANCHORS_WEIGHT: u64 = 1124;
+
+/// Value of each of the anchor outputs of the commitment transaction, in satoshis
+const ANCHOR_OUTPUT_VALUE: u64 = 330;
+
+/// Maximal channel funding amount unless both peers support `option_support_large_channel`, in
+/// satoshis
+const MAX_FUNDING_SAT: u64 = (1 << 24) - 1;
+
+/// Minimal feerate the remote channel funder may set with `update_fee`, in percents of our fee
+/// estimation
+const MIN_UPDATE_FEE_PERCENT: u64 = 50;
+
+/// Maximal feerate the remote channel funder may set with `update_fee`, in percents of our fee
+/// estimation
+const MAX_UPDATE_FEE_PERCENT: u64 = 1000;
 
 /// Errors for channel proposal workflow
 #[derive(Clone, Debug, Display, From, Error)]
@@ -55,6 +112,22 @@ pub enum Error {
     /// channel was not persisted on a disk, so unable to reestablish
     NoPersistantData,
 
+    /// invalid channel state export. Details: {0}
+    #[from]
+    Export(ExportError),
+
+    /// invalid static channel backup. Details: {0}
+    #[from]
+    Backup(BackupError),
+
+    /// unable to read persisted channel state. Details: {0}
+    #[from]
+    State(StateError),
+
+    /// imported channel state at commitment {imported} is older than the current channel state
+    /// at commitment {current}; using it would lead to the loss of the channel funds
+    StaleImport { imported: u64, current: u64 },
+
     /// sign daemon was unable to sign funding transaction for our public key {0}
     FundingPsbtUnsigned(PublicKey),
 
@@ -64,6 +137,245 @@ pub enum Error {
     /// failed to save channel state. Details: {0}
     #[from]
     Persistence(strict_encoding::Error),
+
+    /// on-chain tracking service reported transaction {found} instead of the channel funding
+    /// transaction {expected}
+    FundingTxidMismatch { expected: Txid, found: Txid },
+
+    /// remote peer sent `funding_locked` message for a different channel {0}
+    ForeignFundingLocked(ChannelId),
+
+    /// unable to close the channel cooperatively since it has {0} pending HTLCs
+    HtlcsPending(usize),
+
+    /// channel is being closed and does not accept new HTLCs
+    ChannelClosing,
+
+    /// remote peer has offered a new HTLC after it has requested to close the channel
+    HtlcAfterShutdown,
+
+    /// remote peer {0} is not allowed to open channels with this node
+    PeerNotAllowed(NodeAddr),
+
+    /// remote peer already has {count} channels with this node, while at most {max} channels
+    /// per peer are allowed
+    TooManyChannels { count: u16, max: u16 },
+
+    /// remote peer requires our funds to be timelocked for {value} blocks after a unilateral
+    /// channel close, while at most {max} blocks are allowed; the limit may be overridden with
+    /// `--max-to-self-delay` option
+    RemoteToSelfDelay { value: u16, max: u16 },
+
+    /// our funds timelock of {value} blocks required from the remote peer exceeds {max} blocks,
+    /// so the remote peer will likely reject it; the limit may be overridden with
+    /// `--max-to-self-delay` option
+    LocalToSelfDelay { value: u16, max: u16 },
+
+    /// provided funding transaction has no output paying exactly {amount} sat to the channel
+    /// funding address {address}
+    FundingOutputMissing { address: String, amount: u64 },
+
+    /// provided funding transaction has {0} outputs paying to the channel funding address, while
+    /// exactly one is required
+    FundingOutputAmbiguous(usize),
+
+    /// remote peer does not agree on the closing transaction fee: we propose {local} sat, while
+    /// it requires {remote} sat
+    ClosingFeeDisagreement { local: u64, remote: u64 },
+
+    /// remote peer has not agreed on the closing transaction fee within {0} negotiation rounds
+    ClosingRoundsExceeded(u16),
+
+    /// channel negotiation has timed out at {0} stage and is abandoned
+    Timeout(Lifecycle),
+
+    /// remote peer has failed the channel with the error: {0}
+    RemoteError(String),
+
+    /// remote peer requires unacceptable value {value} for `{field}`; allowed values are
+    /// {allowed}
+    PolicyViolation { field: &'static str, value: u64, allowed: String },
+
+    /// remote peer has sent invalid per-commitment secret. Details: {0}
+    #[from]
+    RevocationSecret(ShachainError),
+
+    /// remote peer has lost the channel state: it expects the next commitment to be {remote},
+    /// while the channel is already at commitment {local}
+    PeerBehind { local: u64, remote: u64 },
+
+    /// only the channel funder may update channel feerate
+    NotFunder,
+
+    /// unable to derive revocation keys. Details: {0}
+    KeyDerivation(secp256k1::Error),
+
+    /// channel keyset has no key index, so the signer can't confirm the channel basepoints
+    KeysetUnindexed,
+
+    /// channel basepoints differ from the keyset derived by the signer for the channel key index
+    KeysetMismatch,
+
+    /// revoked commitment transaction {0} does not contain outputs which can be claimed with the
+    /// revocation key
+    PenaltyOutputNotFound(Txid),
+
+    /// revoked outputs of {value} sat are not worth claiming with {fee} sat penalty transaction
+    /// fee
+    PenaltyOutputDust { value: u64, fee: u64 },
+
+    /// our output of {value} sat is not worth sweeping with {fee} sat sweep transaction fee
+    SweepOutputDust { value: u64, fee: u64 },
+
+    /// commitment transaction {0} does not contain our anchor output
+    AnchorOutputNotFound(Txid),
+
+    /// remote commitment transaction {0} does not pay our funds to the to-remote output
+    /// required by the negotiated channel type
+    ToRemoteMismatch(Txid),
+
+    /// shutdown script {0} is not one of the standard P2PKH, P2SH, P2WPKH or P2WSH forms
+    NonStandardShutdownScript(PubkeyScript),
+
+    /// remote peer requested to close the channel to {received}, while it has committed upfront
+    /// to close it to {committed}
+    ShutdownScriptMismatch { committed: PubkeyScript, received: PubkeyScript },
+
+    /// channel has committed upfront to close to {committed}, so it can't be closed to
+    /// {requested}
+    ShutdownScriptCommitted { committed: PubkeyScript, requested: PubkeyScript },
+
+    /// channel funding of {0} sat exceeds the limit of 16777215 sat, and large channels are not
+    /// supported by both peers
+    FundingTooLarge(u64),
+
+    /// channel type {0:?} requires features which are not negotiated with the remote peer
+    ChannelTypeNotNegotiated(ChannelType),
+
+    /// funding transaction {0} of zero-conf channel was not confirmed within the allowed time
+    ZeroConfUnconfirmed(Txid),
+
+    /// funding transaction {0} was reorged out of the blockchain and was not mined again within
+    /// the allowed number of blocks
+    FundingReorged(Txid),
+
+    /// inputs of funding transaction {0} are double-spent by another transaction mined by the
+    /// blockchain, so the funding transaction will never be mined
+    FundingConflict(Txid),
+
+    /// channel opening can't be aborted at {0} stage, since the funding transaction is already
+    /// signed; the funds can be returned only by closing the channel with a transaction spending
+    /// the funding output
+    FundingCommitted(Lifecycle),
+
+    /// {push_msat} msat pushed to the remote peer exceed the channel funding of {funding_sat} sat
+    PushExceedsFunding { push_msat: u64, funding_sat: u64 },
+
+    /// pushing {push_msat} msat to the remote peer leaves the channel funder with {balance_msat}
+    /// msat, which is below the channel reserve of {reserve_sat} sat
+    PushBelowReserve { push_msat: u64, balance_msat: u64, reserve_sat: u64 },
+
+    /// HTLC of {amount_msat} msat is below the minimum of {minimum_msat} msat accepted by the
+    /// receiving party
+    HtlcBelowMinimum { amount_msat: u64, minimum_msat: u64 },
+
+    /// HTLC of {amount_msat} msat exceeds {available_msat} msat available to the offering party,
+    /// which must keep {required_msat} msat for the channel reserve and commitment transaction fee
+    ReserveViolation { amount_msat: u64, available_msat: u64, required_msat: u64 },
+
+    /// commitment transaction {txid} contains output of {value} sat below the dust limit of
+    /// {dust_limit} sat, which must be trimmed into the transaction fee
+    DustOutput { txid: Txid, value: u64, dust_limit: u64 },
+
+    /// signed transaction {0} can't be finalized. Details: {1}
+    Finalization(Txid, String),
+
+    /// channel is funded together with other channels by a single transaction, which will never
+    /// be published: {0}
+    BatchFailed(String),
+
+    /// remote peer is disconnected on the client request
+    PeerDisconnected,
+
+    /// remote peer does not keep up with the messages sent to it; the operation may be retried
+    /// once the peer catches up
+    PeerBusy,
+
+    /// HTLC expiring at block {expiry} is still pending at block {height}, so it has to be
+    /// resolved on-chain
+    HtlcExpiring { expiry: u32, height: u32 },
+}
+
+/// Checks that the channel funding amount does not exceed the limit for the channels without
+/// `option_support_large_channel` negotiated by both peers
+fn validate_funding_amount(funding_sat: u64, features: &InitFeatures) -> Result<(), Error> {
+    if !features.option_support_large_channel && funding_sat > MAX_FUNDING_SAT {
+        return Err(Error::FundingTooLarge(funding_sat));
+    }
+    Ok(())
+}
+
+/// Checks that the channel type explicitly proposed by the remote peer requires only the features
+/// negotiated with it. Channels proposed without explicit type have the type implied by the
+/// negotiated features.
+fn validate_channel_type(
+    channel_type: Option<ChannelType>,
+    features: &InitFeatures,
+) -> Result<(), Error> {
+    let negotiated = match channel_type {
+        None | Some(ChannelType::Basic) => true,
+        Some(ChannelType::StaticRemotekey) => features.option_static_remotekey,
+        Some(ChannelType::AnchoredOutputsStaticRemotekey) => features.option_anchor_outputs,
+        Some(ChannelType::AnchoredZeroFeeHtlc) => features.option_anchors_zero_fee_htlc_tx,
+    };
+    match channel_type {
+        Some(channel_type) if !negotiated => Err(Error::ChannelTypeNotNegotiated(channel_type)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the amount pushed by the channel funder to the other peer fits into the channel
+/// funding and leaves the funder with at least the channel reserve
+fn validate_push_amount(push_msat: u64, funding_sat: u64, reserve_sat: u64) -> Result<(), Error> {
+    let balance_msat = (funding_sat * 1000)
+        .checked_sub(push_msat)
+        .ok_or(Error::PushExceedsFunding { push_msat, funding_sat })?;
+    if balance_msat < reserve_sat * 1000 {
+        return Err(Error::PushBelowReserve { push_msat, balance_msat, reserve_sat });
+    }
+    Ok(())
+}
+
+/// Checks that `to_self_delay` timelocking funds after a unilateral channel close does not
+/// exceed the limit. The check applies both to the delay required by the remote peer from us
+/// (if `remote` is set) and to the delay we require from the remote peer, since the peers
+/// apply the same kind of limits.
+fn validate_to_self_delay(to_self_delay: u16, max: u16, remote: bool) -> Result<(), Error> {
+    match (to_self_delay > max, remote) {
+        (false, _) => Ok(()),
+        (true, true) => Err(Error::RemoteToSelfDelay { value: to_self_delay, max }),
+        (true, false) => Err(Error::LocalToSelfDelay { value: to_self_delay, max }),
+    }
+}
+
+/// Blockchain height at which the channel has to be force-closed, such that the earliest pending
+/// HTLC expiring at `expiry` is resolved on-chain `margin` blocks before it expires. Returns
+/// `None` if no HTLCs are pending or the channel can't be force-closed at its current state,
+/// including the channels with the outdated local state and the channels which are already being
+/// closed unilaterally.
+fn htlc_deadline(
+    state_machine: &ChannelStateMachine,
+    expiry: Option<u32>,
+    margin: u32,
+) -> Option<u32> {
+    match state_machine {
+        ChannelStateMachine::Reestablishing(ChannelReestablishing::Frozen) => return None,
+        ChannelStateMachine::Active
+        | ChannelStateMachine::Reestablishing(_)
+        | ChannelStateMachine::Closing(_) => {}
+        _ => return None,
+    }
+    expiry.map(|expiry| expiry.saturating_sub(margin))
 }
 
 impl Error {
@@ -86,8 +398,126 @@ impl Error {
             Error::InvalidSig(_) => 5002,
             Error::Persistence(_) => 6000,
             Error::NoPersistantData => 6001,
+            Error::Export(_) => 6002,
+            Error::StaleImport { .. } => 6003,
+            Error::State(_) => 6004,
+            Error::Backup(_) => 6005,
+            Error::FundingTxidMismatch { .. } => 7001,
+            Error::ForeignFundingLocked(_) => 7002,
+            Error::HtlcsPending(_) => 7003,
+            Error::ClosingFeeDisagreement { .. } => 7004,
+            Error::Timeout(_) => 7005,
+            Error::RemoteError(_) => 7006,
+            Error::PolicyViolation { .. } => 7007,
+            Error::PeerBehind { .. } => 7008,
+            Error::NotFunder => 7009,
+            Error::KeyDerivation(_) => 5003,
+            Error::KeysetUnindexed => 5004,
+            Error::KeysetMismatch => 5005,
+            Error::PenaltyOutputNotFound(_) => 7010,
+            Error::PenaltyOutputDust { .. } => 7011,
+            Error::SweepOutputDust { .. } => 7012,
+            Error::AnchorOutputNotFound(_) => 7013,
+            Error::ToRemoteMismatch(_) => 7014,
+            Error::NonStandardShutdownScript(_) => 7015,
+            Error::ShutdownScriptMismatch { .. } => 7016,
+            Error::FundingTooLarge(_) => 7017,
+            Error::ZeroConfUnconfirmed(_) => 7018,
+            Error::FundingCommitted(_) => 7019,
+            Error::FundingReorged(_) => 7020,
+            Error::HtlcBelowMinimum { .. } => 7021,
+            Error::ReserveViolation { .. } => 7022,
+            Error::DustOutput { .. } => 7023,
+            Error::ChannelClosing => 7024,
+            Error::HtlcAfterShutdown => 7025,
+            Error::PeerNotAllowed(_) => 7026,
+            Error::TooManyChannels { .. } => 7027,
+            Error::RemoteToSelfDelay { .. } => 7028,
+            Error::LocalToSelfDelay { .. } => 7029,
+            Error::FundingOutputMissing { .. } => 7030,
+            Error::FundingOutputAmbiguous(_) => 7031,
+            Error::FundingConflict(_) => 7032,
+            Error::PushExceedsFunding { .. } => 7033,
+            Error::PushBelowReserve { .. } => 7034,
+            Error::Finalization(..) => 7035,
+            Error::ShutdownScriptCommitted { .. } => 7036,
+            Error::BatchFailed(_) => 7037,
+            Error::PeerDisconnected => 7038,
+            Error::ChannelTypeNotNegotiated(_) => 7039,
+            Error::PeerBusy => 7040,
+            Error::RevocationSecret(_) => 7041,
+            Error::HtlcExpiring { .. } => 7042,
+            Error::ClosingRoundsExceeded(_) => 7043,
+        }
+    }
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Esb(_) => ErrorCode::Bus,
+            Error::Persistence(_) | Error::NoPersistantData | Error::State(_) => ErrorCode::Storage,
+            Error::Export(_) | Error::Backup(_) | Error::StaleImport { .. } => {
+                ErrorCode::InvalidRequest
+            }
+            Error::Timeout(_) | Error::ZeroConfUnconfirmed(_) | Error::HtlcExpiring { .. } => {
+                ErrorCode::Timeout
+            }
+            Error::PeerDisconnected | Error::PeerBusy => ErrorCode::PeerUnreachable,
+            Error::UnexpectedMessage(..)
+            | Error::Channel(channel::bolt::Error::ChannelReestablish(_))
+            | Error::ForeignFundingLocked(_)
+            | Error::HtlcAfterShutdown
+            | Error::RemoteError(_)
+            | Error::PeerBehind { .. }
+            | Error::RevocationSecret(_)
+            | Error::ClosingFeeDisagreement { .. }
+            | Error::ClosingRoundsExceeded(_)
+            | Error::ShutdownScriptMismatch { .. }
+            | Error::ToRemoteMismatch(_) => ErrorCode::PeerRejected,
+            Error::Channel(channel::bolt::Error::Policy(_))
+            | Error::PolicyViolation { .. }
+            | Error::PeerNotAllowed(_)
+            | Error::TooManyChannels { .. }
+            | Error::RemoteToSelfDelay { .. }
+            | Error::LocalToSelfDelay { .. }
+            | Error::NonStandardShutdownScript(_)
+            | Error::FundingTooLarge(_)
+            | Error::ChannelTypeNotNegotiated(_)
+            | Error::HtlcBelowMinimum { .. } => ErrorCode::PolicyViolation,
+            Error::Channel(_)
+            | Error::InvalidState { .. }
+            | Error::HtlcsPending(_)
+            | Error::ChannelClosing
+            | Error::NotFunder
+            | Error::FundingCommitted(_)
+            | Error::ShutdownScriptCommitted { .. } => ErrorCode::ChannelState,
+            Error::ReserveViolation { .. }
+            | Error::PushExceedsFunding { .. }
+            | Error::PushBelowReserve { .. } => ErrorCode::InsufficientFunds,
+            Error::FundingTxidMismatch { .. }
+            | Error::FundingOutputMissing { .. }
+            | Error::FundingOutputAmbiguous(_)
+            | Error::FundingReorged(_)
+            | Error::FundingConflict(_)
+            | Error::Finalization(..)
+            | Error::BatchFailed(_) => ErrorCode::Funding,
+            Error::FundingPsbtUnsigned(_) | Error::InvalidSig(_) | Error::KeyDerivation(_) => {
+                ErrorCode::SignerUnavailable
+            }
+            Error::KeysetUnindexed => ErrorCode::NotSupported,
+            Error::PenaltyOutputNotFound(_)
+            | Error::PenaltyOutputDust { .. }
+            | Error::SweepOutputDust { .. }
+            | Error::AnchorOutputNotFound(_)
+            | Error::DustOutput { .. }
+            | Error::KeysetMismatch => ErrorCode::Internal,
         }
     }
+
+    fn to_rpc_error(&self) -> RpcError {
+        RpcError::new(self.error_code(), self).with_detail("errno", self.errno())
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, From)]
@@ -113,19 +543,27 @@ pub enum ChannelStateMachine {
 
     /// reestablishing channel
     #[display("REESTABLISHING")]
-    Reestablishing,
+    #[from]
+    Reestablishing(ChannelReestablishing),
 
     /// cooperatively closing channel
     #[display("CLOSING")]
-    Closing,
+    #[from]
+    Closing(ChannelClose),
 
     /// uncooperative channel closing initiated by thyself
     #[display("ABORT")]
-    Abort,
+    #[from]
+    Abort(ChannelAbort),
 
-    /// reacting to an uncooperative channel close from remote
+    /// reacting to an uncooperative channel close from remote with revoked commitment
     #[display("PENALIZE")]
-    Penalize,
+    #[from]
+    Penalize(ChannelPenalize),
+
+    /// channel is closed and its closing transaction is mined
+    #[display("CLOSED")]
+    Closed,
 }
 
 // TODO: Replace with method checking persistence data on the disk and initializing state machine
@@ -143,28 +581,100 @@ impl ChannelStateMachine {
             ChannelStateMachine::Propose(state_machine) => state_machine.lifecycle(),
             ChannelStateMachine::Accept(state_machine) => state_machine.lifecycle(),
             ChannelStateMachine::Active => Lifecycle::Active,
-            ChannelStateMachine::Reestablishing => Lifecycle::Reestablishing,
-            // TODO: use state machine
-            ChannelStateMachine::Closing => Lifecycle::Closing { round: 0 },
-            ChannelStateMachine::Abort => Lifecycle::Aborting,
-            ChannelStateMachine::Penalize => Lifecycle::Penalize,
+            ChannelStateMachine::Reestablishing(state_machine) => state_machine.lifecycle(),
+            ChannelStateMachine::Closing(state_machine) => state_machine.lifecycle(),
+            ChannelStateMachine::Closed => Lifecycle::Closed,
+            ChannelStateMachine::Abort(state_machine) => state_machine.lifecycle(),
+            ChannelStateMachine::Penalize(state_machine) => state_machine.lifecycle(),
+        }
+    }
+
+    /// Returns maximal time the state machine may stay in its current state awaiting for a
+    /// response, or `None` if the state does not time out
+    pub fn timeout(&self, timeouts: &ProposeTimeouts) -> Option<Duration> {
+        match self {
+            ChannelStateMachine::Propose(state_machine) => state_machine.timeout(timeouts),
+            _ => None,
         }
     }
 
+    /// Checks whether the channel awaits for its funding transaction to be mined before it can
+    /// be used
+    pub fn is_awaiting_funding(&self) -> bool {
+        matches!(
+            self,
+            ChannelStateMachine::Propose(ChannelPropose::Published)
+                | ChannelStateMachine::Accept(ChannelAccept::Signed)
+                | ChannelStateMachine::Accept(ChannelAccept::Funded)
+        )
+    }
+
+    /// Checks whether the channel funding is locked by both peers and the channel is not being
+    /// closed unilaterally, such that the funding output may be spent by a transaction not known
+    /// to the channel workflow
+    pub fn is_funding_locked(&self) -> bool {
+        matches!(
+            self,
+            ChannelStateMachine::Active
+                | ChannelStateMachine::Reestablishing(_)
+                | ChannelStateMachine::Closing(_)
+        )
+    }
+
+    /// Checks whether the channel has to process reconnection of its remote peer: active
+    /// channels reestablish, and channels in the middle of funding retransmit their last
+    /// message, since otherwise the negotiation deadlocks with each side awaiting the other one
+    pub fn is_reconnectable(&self) -> bool {
+        matches!(
+            self,
+            ChannelStateMachine::Active
+                | ChannelStateMachine::Propose(ChannelPropose::Funding)
+                | ChannelStateMachine::Accept(ChannelAccept::Signed)
+        )
+    }
+
     pub(self) fn info_message(&self, channel_id: ActiveChannelId) -> String {
         match self {
             ChannelStateMachine::Launch => s!("Launching channel daemon"),
             ChannelStateMachine::Propose(state_machine) => state_machine.info_message(channel_id),
             ChannelStateMachine::Accept(state_machine) => state_machine.info_message(channel_id),
             ChannelStateMachine::Active => s!("Channel is active"),
-            ChannelStateMachine::Reestablishing => s!("Reestablishing channel"),
-            ChannelStateMachine::Closing => s!("Closing channel"),
-            ChannelStateMachine::Abort => s!("Unilaterally closing the channel"),
-            ChannelStateMachine::Penalize => s!("Penalizing incorrect channel"),
+            ChannelStateMachine::Reestablishing(state_machine) => {
+                state_machine.info_message(channel_id)
+            }
+            ChannelStateMachine::Closing(state_machine) => state_machine.info_message(channel_id),
+            ChannelStateMachine::Closed => s!("Channel is closed"),
+            ChannelStateMachine::Abort(state_machine) => state_machine.info_message(channel_id),
+            ChannelStateMachine::Penalize(state_machine) => state_machine.info_message(channel_id),
         }
     }
 }
 
+/// Mined transaction spending the channel funding output
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+enum FundingSpending {
+    /// Latest local commitment transaction
+    #[display("local commitment")]
+    LocalCommitment,
+
+    /// Latest remote commitment transaction, which is not revoked
+    #[display("remote commitment")]
+    RemoteCommitment,
+
+    /// Remote commitment transaction revoked by the remote peer
+    #[display("revoked remote commitment")]
+    RevokedCommitment,
+
+    /// Mutual closing transaction negotiated by the peers
+    #[display("cooperative closing")]
+    CooperativeClose,
+
+    /// Commitment transaction unknown to the channel, like an outdated local commitment or a
+    /// revoked remote commitment which was not retained
+    #[display("unknown commitment")]
+    UnknownCommitment,
+}
+
 impl Runtime {
     /// Processes incoming RPC or peer requests updating state - and switching to a new state, if
     /// necessary. Returns bool indicating whether a successful state update happened
@@ -175,31 +685,49 @@ impl Runtime {
         request: BusMsg,
     ) -> Result<bool, Error> {
         if let BusMsg::Ctl(CtlMsg::EsbError { destination, error: _ }) = &request {
-            let (code, info) = match destination {
-                ServiceId::Peer(remote_peer) => (
-                    9001,
+            let failure = match destination {
+                ServiceId::Peer(remote_peer) => RpcError::new(
+                    ErrorCode::PeerUnreachable,
                     format!(
                         "There is no connection with the remote peer {}; you have to `connect` to \
                          it first",
                         remote_peer
                     ),
                 ),
-                _ => (
-                    9000,
+                _ => RpcError::new(
+                    ErrorCode::Bus,
                     format!("Unable to complete: daemon {} is offline or crashed", destination),
                 ),
             };
-            self.report_failure(endpoints, Failure { code, info });
+            self.report_failure(endpoints, failure);
         }
 
-        let event = Event::with(endpoints, self.identity(), source, request);
-        let channel_id = self.state.channel.active_channel_id();
-        let updated_state = match self.process_event(event) {
+        let message_type = channeld::message_type(&request);
+        let lifecycle = self.state.state_machine.lifecycle();
+        let event = Event::with(endpoints, self.identity(), source.clone(), request);
+        let prev_state = self.state.state_machine;
+        let result = self.process_event(event);
+        let outcome = match result {
+            Ok(_) => self.state.state_machine.to_string(),
+            Err(ref err) => err.to_string(),
+        };
+        self.record_event(lifecycle, EventDirection::Inbound, message_type, source, outcome);
+        let updated_state = match result {
             Ok(_) => {
                 // Ignoring possible reporting errors here and after: do not want to
                 // halt the channel just because the client disconnected
-                let _ = self
-                    .report_progress(endpoints, self.state.state_machine.info_message(channel_id));
+                if self.state.state_machine != prev_state {
+                    let _ = self.report_progress(endpoints, self.progress_report());
+                }
+                let new_lifecycle = self.state.state_machine.lifecycle();
+                if new_lifecycle != lifecycle {
+                    let event = NodeEvent::ChannelLifecycle {
+                        channel_id: self.event_channel_id(),
+                        previous: lifecycle.to_string(),
+                        lifecycle: new_lifecycle.to_string(),
+                    };
+                    self.publish_event(endpoints, event);
+                }
                 true
             }
             // We pass ESB errors forward such that they can fail the channel.
@@ -207,23 +735,26 @@ impl Runtime {
             // message later without channel halting.
             Err(err @ Error::Esb(_)) => {
                 error!("{} due to ESB failure: {}", "Failing channel".err(), err.err_details());
-                self.report_failure(endpoints, Failure {
-                    code: err.errno(),
-                    info: err.to_string(),
-                });
+                self.report_failure(endpoints, &err);
                 return Err(err);
             }
             Err(other_err) => {
                 error!("{}: {}", "Channel error".err(), other_err.err_details());
-                self.report_failure(endpoints, Failure {
-                    code: other_err.errno(),
-                    info: other_err.to_string(),
-                });
+                self.report_failure(endpoints, &other_err);
                 false
             }
         };
         if updated_state {
             self.save_state()?;
+            self.track_htlc_deadline(endpoints)?;
+            self.watch_funding_spending(endpoints)?;
+            if self.state.state_machine != prev_state {
+                self.deadline = self
+                    .state
+                    .state_machine
+                    .timeout(&self.config().propose_timeouts)
+                    .map(|timeout| SystemTime::now() + timeout);
+            }
             info!(
                 "ChannelStateMachine {} switched to {} state",
                 self.state.channel.active_channel_id(),
@@ -233,16 +764,344 @@ impl Runtime {
         Ok(updated_state)
     }
 
+    /// Composes report on the channel progress for the client, containing channel lifecycle
+    /// stage, description of the current state and channel identifiers known at this stage
+    fn progress_report(&self) -> String {
+        let channel = &self.state.channel;
+        let state_machine = self.state.state_machine;
+        let mut report = format!(
+            "[{}] {}",
+            state_machine.lifecycle(),
+            state_machine.info_message(channel.active_channel_id())
+        );
+        if let Some(temp_channel_id) = channel.temp_channel_id() {
+            report.push_str(&format!("; temp id {}", temp_channel_id));
+        }
+        // Permanent channel id is assigned once the funding transaction is known
+        if channel.active_channel_id().channel_id().is_some() {
+            report.push_str(&format!("; funding txid {}", channel.funding().txid()));
+        }
+        report
+    }
+
+    /// Resumes operations of a state machine restored from the persistent storage by re-issuing
+    /// its last outstanding request, since the reply to it might have been lost while the daemon
+    /// was offline. Requests to the remote peer are not repeated: the peer re-sends its messages
+    /// itself after channel reestablishment.
+    pub fn restore(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let state_machine = self.state.state_machine;
+        if state_machine == ChannelStateMachine::Launch {
+            return Ok(());
+        }
+
+        info!(
+            "Resuming channel {} at {} state",
+            self.state.channel.active_channel_id(),
+            state_machine
+        );
+        // Timers do not persist, so they are restarted from the scratch
+        self.deadline = state_machine
+            .timeout(&self.config().propose_timeouts)
+            .map(|timeout| SystemTime::now() + timeout);
+        // Funding outputs registered with signd do not persist either
+        if self.state.channel.active_channel_id().channel_id().is_some() {
+            self.expect_funding(endpoints)?;
+        }
+
+        if let Some((service, request)) = resume_request(&mut self.state)? {
+            self.send_ctl(endpoints, service, request)?;
+        }
+        match state_machine {
+            ChannelStateMachine::Abort(ChannelAbort::Signing) => {
+                ChannelAbort::with(self, endpoints)?;
+            }
+            ChannelStateMachine::Abort(ChannelAbort::Published) => {
+                if let Some(txid) = self.state.commitment_txid {
+                    self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+                }
+            }
+            ChannelStateMachine::Abort(channel_abort) => {
+                channel_abort.resume_sweeping(self, endpoints)?;
+            }
+            ChannelStateMachine::Penalize(
+                ChannelPenalize::Addressing | ChannelPenalize::Signing,
+            ) => {
+                if let Some(breach_txid) = self.state.breach_txid {
+                    ChannelPenalize::with(self, endpoints, breach_txid)?;
+                }
+            }
+            ChannelStateMachine::Closing(ChannelClose::Addressing) => {
+                ChannelClose::resume_addressing(self, endpoints)?;
+            }
+            ChannelStateMachine::Penalize(ChannelPenalize::Published) => {
+                if let Some(txid) = self.state.penalty_txid {
+                    self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+                }
+            }
+            _ => {}
+        }
+        // Funding transactions which are awaited by the channel state machine are already tracked
+        // above
+        let funding_unconfirmed =
+            self.state.zero_conf_deadline.is_some() || self.state.reorg_deadline.is_some();
+        if funding_unconfirmed && !state_machine.is_awaiting_funding() {
+            let txid = self.state.channel.funding().txid();
+            self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+        }
+        if let Some(deadline) = self.state.reorg_deadline {
+            self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::TrackHeight(deadline))?;
+        }
+        self.track_htlc_deadline(endpoints)?;
+        self.watch_funding_spending(endpoints)?;
+        Ok(())
+    }
+
+    /// Parks the channel during the node shutdown: cancels the timers and persists the channel
+    /// state, such that it can be resumed with [`Runtime::restore`] once the node is restarted.
+    ///
+    /// Channel negotiations which have not reached the signing stage are abandoned, since the
+    /// remote peer forgets them upon disconnection anyway. Negotiations at the signing and funding
+    /// stages remain resumable.
+    pub(super) fn park(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        self.deadline = None;
+        let state_machine = self.state.state_machine;
+        match state_machine {
+            ChannelStateMachine::Propose(ChannelPropose::Proposed)
+            | ChannelStateMachine::Propose(ChannelPropose::Accepted)
+            | ChannelStateMachine::Propose(ChannelPropose::ExternalFunding)
+            | ChannelStateMachine::Accept(ChannelAccept::Accepted) => {
+                self.abandon_proposal(endpoints, "node is shutting down")?;
+                self.state.state_machine = ChannelStateMachine::Closed;
+            }
+            ChannelStateMachine::Propose(ChannelPropose::Signing)
+            | ChannelStateMachine::Propose(ChannelPropose::Funding)
+            | ChannelStateMachine::Accept(ChannelAccept::Signing) => {
+                // TODO: Notify the remote peer with `warning` message once it is supported by
+                //       lnp-core
+                info!(
+                    "Parking channel {} at {} state until the node restart",
+                    self.state.channel.active_channel_id(),
+                    state_machine
+                );
+            }
+            _ => {}
+        }
+        self.save_state()?;
+        Ok(())
+    }
+
+    /// Checks whether the funding transaction of a zero-conf channel has failed to get
+    /// confirmed within the allowed time
+    pub fn zero_conf_expired(&self) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        matches!(self.state.zero_conf_deadline, Some(deadline) if deadline <= now)
+    }
+
     fn process_event(&mut self, event: Event<BusMsg>) -> Result<(), Error> {
-        // We have to handle channel reestablishment separately, since this is
-        // shared across multiple channel states
+        // Transactions signed for the commitment dump are not related to the channel workflows,
+        // and remote commitment is signed in the same way in different channel states
+        if let BusMsg::Ctl(CtlMsg::Signed(ref psbt)) = event.message {
+            if dump::process_signed(self, event.endpoints, psbt)? {
+                return Ok(());
+            }
+            if commitment::process_signed(self, event.endpoints, psbt)? {
+                return Ok(());
+            }
+        }
+
+        // Wallet addresses are requested for the justice transactions uploaded to watchtowers
+        // before the claim of our output of the remote commitment
+        if let BusMsg::Ctl(CtlMsg::SweepAddress(ref sweep_script)) = event.message {
+            if !self.tower_queue.is_empty() {
+                self.register_with_towers(event.endpoints, sweep_script.clone());
+                return Ok(());
+            }
+        }
+        if claim::process(self, event.endpoints, &event.message)? {
+            return Ok(());
+        }
+
+        // Channel announcement proceeds independently from the channel workflows
+        if announce::process(self, event.endpoints, &event.source, &event.message)? {
+            return Ok(());
+        }
+
+        // We have to handle channel reestablishment requested by the remote peer separately, since
+        // this is shared across multiple channel states
         if let BusMsg::Ln(LnMsg::ChannelReestablish(ref remote_channel_reestablish)) = event.message
         {
-            self.state.state_machine = self.complete_reestalblish(
-                event.endpoints,
-                event.source,
-                remote_channel_reestablish,
-            )?;
+            if !matches!(self.state.state_machine, ChannelStateMachine::Reestablishing(_)) {
+                self.state.state_machine = self.complete_reestalblish(
+                    event.endpoints,
+                    event.source,
+                    remote_channel_reestablish,
+                )?;
+                return Ok(());
+            }
+        }
+
+        if matches!(
+            event.message,
+            BusMsg::Ctl(CtlMsg::ForceClose(_))
+                | BusMsg::Ctl(CtlMsg::CloseChannel { force: true, .. })
+        ) {
+            self.state.state_machine = self.complete_force_close(event)?;
+            return Ok(());
+        }
+
+        // Cooperative closing is negotiated only for active channels, while force-close is
+        // handled above for the other stages
+        if let BusMsg::Ctl(CtlMsg::CloseChannel { force: false, .. }) = event.message {
+            match self.state.state_machine {
+                ChannelStateMachine::Active => {}
+                ChannelStateMachine::Closing(_) => {
+                    return Err(Error::InvalidState {
+                        operation: "close channel which is already being closed",
+                        current_state: self.state.state_machine.lifecycle(),
+                    })
+                }
+                state_machine => {
+                    return Err(Error::InvalidState {
+                        operation: "close channel cooperatively",
+                        current_state: state_machine.lifecycle(),
+                    })
+                }
+            }
+        }
+
+        if let BusMsg::Ctl(CtlMsg::AbortChannel { .. }) = event.message {
+            self.state.state_machine = self.complete_abort_opening(event.endpoints)?;
+            return Ok(());
+        }
+
+        if let BusMsg::Ctl(CtlMsg::PeerDisconnected(..)) = event.message {
+            self.state.state_machine = self.complete_peer_disconnection(event.endpoints)?;
+            return Ok(());
+        }
+
+        if let BusMsg::Ctl(CtlMsg::BatchFailed(ref reason)) = event.message {
+            let err = Error::BatchFailed(reason.clone());
+            self.state.state_machine = self.complete_batch_failure(event.endpoints, err)?;
+            return Ok(());
+        }
+
+        // Remote errors are handled the same way at all stages of the channel proposal workflow
+        if let BusMsg::Ln(LnMsg::Error(ref peer_error)) = event.message {
+            if let ChannelStateMachine::Propose(channel_propose) = self.state.state_machine {
+                let reason = String::from_utf8_lossy(&peer_error.data).to_string();
+                self.state.state_machine =
+                    self.complete_remote_error(event.endpoints, channel_propose, reason)?;
+                return Ok(());
+            }
+        }
+
+        // Remote peer violating its upfront shutdown script commitment fails the channel
+        if let BusMsg::Ln(LnMsg::Shutdown(ref shutdown)) = event.message {
+            if let Err(err) = close::validate_remote_shutdown(self, shutdown) {
+                self.state.state_machine = self.fail_channel(event.endpoints, err)?;
+                return Ok(());
+            }
+        }
+
+        if let BusMsg::Ctl(CtlMsg::TxReorged(txid)) = event.message {
+            if txid == self.state.channel.funding().txid() {
+                self.funding_depth = None;
+                announce::reset(self);
+                self.state.state_machine = self.complete_funding_reorg(event.endpoints)?;
+            } else {
+                warn!("Transaction {} is reorged out of the blockchain", txid);
+            }
+            return Ok(());
+        }
+
+        if let BusMsg::Ctl(CtlMsg::FundingConflict(txid)) = event.message {
+            let funding_unconfirmed = self.state.state_machine.is_awaiting_funding()
+                || self.state.zero_conf_deadline.is_some()
+                || self.state.reorg_deadline.is_some();
+            if txid == self.state.channel.funding().txid() && funding_unconfirmed {
+                let err = Error::FundingConflict(txid);
+                self.state.state_machine = self.fail_unconfirmed_funding(event.endpoints, err)?;
+            } else {
+                warn!("Ignoring double-spend report for transaction {}", txid);
+            }
+            return Ok(());
+        }
+
+        if let BusMsg::Ctl(CtlMsg::HeightReached(height)) = event.message {
+            if matches!(self.state.reorg_deadline, Some(deadline) if deadline <= height) {
+                let err = Error::FundingReorged(self.state.channel.funding().txid());
+                self.state.state_machine = self.fail_unconfirmed_funding(event.endpoints, err)?;
+                return Ok(());
+            }
+        }
+
+        // Pending HTLCs must be resolved on-chain before they expire, whichever workflow the
+        // channel is in
+        if let BusMsg::Ctl(CtlMsg::HeightReached(height))
+        | BusMsg::Ctl(CtlMsg::NewBlock { height, .. }) = event.message
+        {
+            if self.htlc_deadline_reached(height) {
+                self.state.state_machine = self.fail_expiring_htlcs(event.endpoints, height)?;
+                return Ok(());
+            }
+        }
+
+        // New blocks are announced to all services awaiting some height; among the channel
+        // workflows only the on-chain resolution of the force-closed channel follows them
+        if let BusMsg::Ctl(CtlMsg::NewBlock { .. }) = event.message {
+            if !matches!(
+                self.state.state_machine,
+                ChannelStateMachine::Abort(ChannelAbort::Maturing)
+                    | ChannelStateMachine::Abort(ChannelAbort::Sweeping)
+                    | ChannelStateMachine::Abort(ChannelAbort::Swept)
+                    | ChannelStateMachine::Abort(ChannelAbort::Resolving)
+            ) {
+                return Ok(());
+            }
+        }
+
+        if let BusMsg::Ctl(CtlMsg::TxConfirmed(ref tx_status)) = event.message {
+            let txid = tx_status.txid;
+            if txid == self.state.channel.funding().txid() {
+                let depth = u32::from(tx_status.depth);
+                if self.funding_depth.unwrap_or_default() == 0 && depth > 0 {
+                    let channel_id = self.event_channel_id();
+                    let confirmed = NodeEvent::FundingConfirmed { channel_id, txid, depth };
+                    self.publish_event(event.endpoints, confirmed);
+                }
+                self.funding_depth = Some(depth);
+            }
+            // Funding of zero-conf channel and the funding reorged out of the blockchain get
+            // confirmed in the background, which may happen both before and after the channel
+            // activation
+            if txid == self.state.channel.funding().txid()
+                && (self.state.zero_conf_deadline.is_some() || self.state.reorg_deadline.is_some())
+            {
+                info!("Funding transaction {} is confirmed", txid);
+                self.state.zero_conf_deadline = None;
+                self.state.reorg_deadline = None;
+                self.state.funding_height = Some(u32::from(tx_status.height));
+                if matches!(self.state.state_machine, ChannelStateMachine::Active) {
+                    announce::start(self, event.endpoints)?;
+                }
+                if !self.state.state_machine.is_awaiting_funding() {
+                    return Ok(());
+                }
+            }
+        }
+
+        // Funding output may be spent by the remote peer at any channel state
+        if let BusMsg::Ctl(CtlMsg::OutpointSpent { outpoint, ref tx, .. }) = event.message {
+            if outpoint == self.funding_outpoint() {
+                self.state.state_machine = self.complete_funding_spent(event.endpoints, tx)?;
+            } else {
+                warn!("Ignoring spending report for outpoint {}", outpoint);
+            }
+            return Ok(());
+        }
+
+        if let BusMsg::Ctl(CtlMsg::Timeout) = event.message {
+            self.state.state_machine = self.complete_timeout(event)?;
             return Ok(());
         }
 
@@ -254,13 +1113,23 @@ impl Runtime {
             ChannelStateMachine::Accept(channel_accept) => {
                 self.process_accept(event, channel_accept)
             }
-            ChannelStateMachine::Active => Ok(ChannelStateMachine::Active), // TODO
-            // This is when we were launched by lnpd with a aim of re-establishing channel;
-            // the state is valid _before_ we receive channel_reestablish from the peer.
-            ChannelStateMachine::Reestablishing => todo!(),
-            ChannelStateMachine::Closing => todo!(),
-            ChannelStateMachine::Abort => todo!(),
-            ChannelStateMachine::Penalize => todo!(),
+            ChannelStateMachine::Active => self.process_active(event),
+            ChannelStateMachine::Reestablishing(channel_reestablishing) => {
+                self.process_reestablish(event, channel_reestablishing)
+            }
+            ChannelStateMachine::Closing(channel_close) => {
+                self.process_close(event, channel_close)
+            }
+            ChannelStateMachine::Closed => {
+                let Event { source, message, .. } = event;
+                Err(Error::UnexpectedMessage(message, Lifecycle::Closed, source))
+            }
+            ChannelStateMachine::Abort(channel_abort) => {
+                self.process_abort(event, channel_abort)
+            }
+            ChannelStateMachine::Penalize(channel_penalize) => {
+                self.process_penalize(event, channel_penalize)
+            }
         }?;
         Ok(())
     }
@@ -279,47 +1148,1285 @@ impl Runtime {
         self.state.remote_peer = Some(remote_peer);
         self.send_p2p(endpoints, LnMsg::ChannelReestablish(local_channel_reestablish))?;
 
-        // We swallow error since we do not want to fail the channel if we just can't add it to the
-        // router
-        trace!("Notifying remote peer about channel reestablishing");
-        let remote_id = self.state.remote_id();
-        let message = CtlMsg::ChannelCreated(self.state.channel.channel_info(remote_id));
-        let _ = self.send_ctl(endpoints, ServiceId::Router, message);
-
-        Ok(ChannelStateMachine::Active)
+        let result = reestablish::synchronize(endpoints, self, remote_channel_reestablish);
+        self.complete_synchronization(endpoints, result)
     }
 
-    fn complete_launch(&mut self, event: Event<BusMsg>) -> Result<ChannelStateMachine, Error> {
-        let Event { endpoints, service: _, source, message } = event;
-        Ok(match message {
-            BusMsg::Ctl(CtlMsg::OpenChannelWith(open_channel_with)) => {
-                ChannelPropose::with(self, endpoints, open_channel_with)?.into()
-            }
-            wrong_msg => {
-                return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Initial, source))
+    /// Switches channel to the state resulting from the channel state synchronization with the
+    /// remote peer. If the remote peer has lost the channel state, the channel is closed
+    /// unilaterally.
+    fn complete_synchronization(
+        &mut self,
+        endpoints: &mut Endpoints,
+        result: Result<Option<ChannelReestablishing>, Error>,
+    ) -> Result<ChannelStateMachine, Error> {
+        match result {
+            Ok(None) => Ok(ChannelStateMachine::Active),
+            Ok(Some(channel_reestablishing)) => Ok(channel_reestablishing.into()),
+            Err(err @ Error::PeerBehind { .. }) => {
+                warn!("{}; force-closing the channel", err);
+                Ok(ChannelAbort::with(self, endpoints)?.into())
             }
-        })
+            Err(err) => Err(err),
+        }
     }
 
-    fn process_propose(
+    /// Fails the channel due to the remote peer protocol violation: reports the error to the
+    /// remote peer and closes the channel unilaterally
+    fn fail_channel(
         &mut self,
-        event: Event<BusMsg>,
-        channel_propose: ChannelPropose,
+        endpoints: &mut Endpoints,
+        err: Error,
     ) -> Result<ChannelStateMachine, Error> {
-        Ok(match channel_propose.next(event, self)? {
-            None => ChannelStateMachine::Active,
-            Some(channel_propose) => ChannelStateMachine::Propose(channel_propose),
-        })
+        let channel_id = self.static_channel_id()?;
+        warn!("Failing channel {}: {}", channel_id, err.err_details());
+        let error = PeerError { channel_id, data: err.to_string().into_bytes() };
+        self.send_p2p(endpoints, LnMsg::Error(error))?;
+        let _ = self.report_progress(endpoints, format!("{}; force-closing channel", err));
+        Ok(ChannelAbort::with(self, endpoints)?.into())
     }
 
-    fn process_accept(
+    /// Complains to the remote peer about the recoverable policy violation: sends it a warning
+    /// and closes the connection, which does not fail the channel. The updates not committed yet
+    /// are forgotten once the channel is reestablished with the reconnected peer.
+    fn warn_peer(&mut self, endpoints: &mut Endpoints, err: &Error) -> Result<(), Error> {
+        let channel_id = self.static_channel_id()?;
+        warn!("Warning remote peer about channel {}: {}", channel_id, err.err_details());
+        let warning = PeerError { channel_id, data: err.to_string().into_bytes() };
+        self.send_p2p(endpoints, LnMsg::Warning(warning))?;
+        let remote_peer = self.state.remote_peer.clone().expect("unset remote peer in channeld");
+        let message = CtlMsg::Disconnect(err.to_string());
+        self.send_ctl(endpoints, ServiceId::Peer(remote_peer), message)?;
+        Ok(())
+    }
+
+    /// Force-closes the channel which pending HTLCs are about to expire, such that the HTLCs get
+    /// resolved on-chain
+    fn fail_expiring_htlcs(
         &mut self,
-        event: Event<BusMsg>,
-        channel_accept: ChannelAccept,
+        endpoints: &mut Endpoints,
+        height: u32,
     ) -> Result<ChannelStateMachine, Error> {
-        Ok(match channel_accept.next(event, self)? {
-            None => ChannelStateMachine::Active,
-            Some(channel_accept) => ChannelStateMachine::Accept(channel_accept),
-        })
+        let expiry = self.htlc_expiry().unwrap_or(height);
+        let err = Error::HtlcExpiring { expiry, height };
+        if !matches!(self.state.state_machine, ChannelStateMachine::Reestablishing(_)) {
+            return self.fail_channel(endpoints, err);
+        }
+        // Disconnected remote peer can't be notified about the channel failure
+        let channel_id = self.state.channel.active_channel_id();
+        warn!("Failing channel {}: {}", channel_id, err.err_details());
+        let _ = self.report_progress(endpoints, format!("{}; force-closing channel", err));
+        Ok(ChannelAbort::with(self, endpoints)?.into())
+    }
+
+    fn complete_force_close(&mut self, event: Event<BusMsg>) -> Result<ChannelStateMachine, Error> {
+        match self.state.state_machine {
+            // Our commitment transaction is outdated, so publishing it would lead to funds loss
+            ChannelStateMachine::Reestablishing(ChannelReestablishing::Frozen) => {
+                Err(Error::InvalidState {
+                    operation: "force close channel with outdated local state",
+                    current_state: Lifecycle::Reestablishing,
+                })
+            }
+            // Nothing is committed yet, so we just abandon the negotiations
+            ChannelStateMachine::Propose(ChannelPropose::Proposed) => {
+                let temp_channel_id = self.state.channel.active_channel_id();
+                self.abandon_proposal(event.endpoints, "channel proposal is abandoned")?;
+                self.complete_workflow(
+                    event.endpoints,
+                    format!("Proposal for channel {} is abandoned", temp_channel_id.ended()),
+                );
+                Ok(ChannelStateMachine::Closed)
+            }
+            ChannelStateMachine::Active
+            | ChannelStateMachine::Reestablishing(_)
+            | ChannelStateMachine::Closing(_) => {
+                Ok(ChannelAbort::with(self, event.endpoints)?.into())
+            }
+            _ => Err(Error::InvalidState {
+                operation: "force close channel",
+                current_state: self.state.state_machine.lifecycle(),
+            }),
+        }
+    }
+
+    fn complete_timeout(&mut self, event: Event<BusMsg>) -> Result<ChannelStateMachine, Error> {
+        if self.zero_conf_expired() {
+            let err = Error::ZeroConfUnconfirmed(self.state.channel.funding().txid());
+            return self.fail_unconfirmed_funding(event.endpoints, err);
+        }
+
+        let current_state = self.state.state_machine;
+        match current_state {
+            ChannelStateMachine::Propose(ChannelPropose::Proposed)
+            | ChannelStateMachine::Propose(ChannelPropose::Accepted)
+            | ChannelStateMachine::Propose(ChannelPropose::Signing) => {}
+            // The timer may fire right after the state has changed; ignoring it
+            _ => return Ok(current_state),
+        }
+
+        let err = Error::Timeout(current_state.lifecycle());
+        warn!("Channel {} {}", self.state.channel.active_channel_id(), err);
+
+        self.abandon_proposal(event.endpoints, &err.to_string())?;
+        self.fail_workflow(event.endpoints, RpcError::from(&err));
+
+        Ok(ChannelStateMachine::Closed)
+    }
+
+    /// Aborts opening of the channel on the client request, which is possible only until the
+    /// funding transaction is signed
+    fn complete_abort_opening(
+        &mut self,
+        endpoints: &mut Endpoints,
+    ) -> Result<ChannelStateMachine, Error> {
+        match self.state.state_machine {
+            ChannelStateMachine::Propose(ChannelPropose::Proposed)
+            | ChannelStateMachine::Propose(ChannelPropose::Accepted)
+            | ChannelStateMachine::Propose(ChannelPropose::ExternalFunding)
+            | ChannelStateMachine::Propose(ChannelPropose::Signing) => {}
+            ChannelStateMachine::Launch => {
+                return Err(Error::InvalidState {
+                    operation: "abort channel opening",
+                    current_state: Lifecycle::Initial,
+                })
+            }
+            state_machine => return Err(Error::FundingCommitted(state_machine.lifecycle())),
+        }
+
+        let temp_channel_id = self.state.channel.active_channel_id();
+        info!("Aborting opening of channel {} on the client request", temp_channel_id);
+        self.abandon_proposal(endpoints, "channel opening is aborted")?;
+        self.complete_workflow(
+            endpoints,
+            format!("Opening of channel {} is aborted", temp_channel_id.ended()),
+        );
+        Ok(ChannelStateMachine::Closed)
+    }
+
+    /// Abandons the channel proposal once the remote peer is disconnected on the client request,
+    /// unless the funding transaction is already signed. Channels at the other stages are kept
+    /// until the peer reconnects.
+    fn complete_peer_disconnection(
+        &mut self,
+        endpoints: &mut Endpoints,
+    ) -> Result<ChannelStateMachine, Error> {
+        match self.state.state_machine {
+            ChannelStateMachine::Propose(ChannelPropose::Proposed)
+            | ChannelStateMachine::Propose(ChannelPropose::Accepted)
+            | ChannelStateMachine::Propose(ChannelPropose::ExternalFunding)
+            | ChannelStateMachine::Propose(ChannelPropose::Signing) => {
+                let err = Error::PeerDisconnected;
+                warn!("Channel {}: {}", self.state.channel.active_channel_id(), err.err_details());
+                self.abandon_proposal(endpoints, &err.to_string())?;
+                self.fail_workflow(endpoints, RpcError::from(&err));
+                Ok(ChannelStateMachine::Closed)
+            }
+            state_machine => Ok(state_machine),
+        }
+    }
+
+    /// Fails the channel funded together with other channels by a single transaction after
+    /// another channel of the batch has failed. Channels which have not sent `funding_created`
+    /// yet are abandoned; the rest are failed, since their funding will never be published.
+    fn complete_batch_failure(
+        &mut self,
+        endpoints: &mut Endpoints,
+        err: Error,
+    ) -> Result<ChannelStateMachine, Error> {
+        match self.state.state_machine {
+            ChannelStateMachine::Launch => {
+                warn!("Channel {}: {}", self.state.channel.active_channel_id(), err.err_details());
+                Ok(ChannelStateMachine::Closed)
+            }
+            ChannelStateMachine::Propose(ChannelPropose::Proposed)
+            | ChannelStateMachine::Propose(ChannelPropose::Accepted)
+            | ChannelStateMachine::Propose(ChannelPropose::Signing) => {
+                warn!("Channel {}: {}", self.state.channel.active_channel_id(), err.err_details());
+                self.abandon_proposal(endpoints, &err.to_string())?;
+                self.fail_workflow(endpoints, RpcError::from(&err));
+                Ok(ChannelStateMachine::Closed)
+            }
+            ChannelStateMachine::Propose(ChannelPropose::Funding)
+            | ChannelStateMachine::Propose(ChannelPropose::Published) => {
+                self.fail_unconfirmed_funding(endpoints, err)
+            }
+            state_machine => {
+                warn!("Ignoring batch failure at {} stage: {}", state_machine, err);
+                Ok(state_machine)
+            }
+        }
+    }
+
+    /// Abandons the channel proposal before the funding transaction is signed: notifies the
+    /// remote peer with an error message and releases funding UTXOs reserved for the channel
+    fn abandon_proposal(&mut self, endpoints: &mut Endpoints, reason: &str) -> Result<(), Error> {
+        let temp_channel_id = self
+            .state
+            .channel
+            .temp_channel_id()
+            .expect("channel at proposal stage must have temporary channel id");
+        let error = PeerError {
+            channel_id: ChannelId::from_inner(temp_channel_id.into_inner()),
+            data: reason.as_bytes().to_vec(),
+        };
+        self.send_p2p(endpoints, LnMsg::Error(error))?;
+        let message = CtlMsg::FundingReleased(temp_channel_id);
+        self.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+        Ok(())
+    }
+
+    /// Fails the channel which funding transaction was not mined in time (because the channel
+    /// is zero-conf or its funding transaction was reorged out of the blockchain) or got
+    /// double-spent. Since the funding is not mined, there is nothing to close on-chain.
+    fn fail_unconfirmed_funding(
+        &mut self,
+        endpoints: &mut Endpoints,
+        err: Error,
+    ) -> Result<ChannelStateMachine, Error> {
+        self.state.zero_conf_deadline = None;
+        self.state.reorg_deadline = None;
+        let channel_id = self.static_channel_id()?;
+        warn!("Failing channel {}: {}", channel_id, err.err_details());
+
+        let txid = self.state.channel.funding().txid();
+        self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Untrack(txid))?;
+        let error = PeerError { channel_id, data: err.to_string().into_bytes() };
+        self.send_p2p(endpoints, LnMsg::Error(error))?;
+        // We swallow error since we do not want to keep the channel if we just can't remove it
+        // from the router
+        let _ = self.send_ctl(endpoints, ServiceId::Router, CtlMsg::ChannelClosed(channel_id));
+        self.fail_workflow(endpoints, RpcError::from(&err));
+
+        Ok(ChannelStateMachine::Closed)
+    }
+
+    /// Classifies the mined transaction spending the funding output
+    fn classify_funding_spending(&mut self, tx: &Transaction) -> FundingSpending {
+        let txid = tx.txid();
+        if self.state.revoked_commitments.contains_key(&txid) {
+            return FundingSpending::RevokedCommitment;
+        }
+        let remote_commitments = &self.state.remote_commitments;
+        if remote_commitments.iter().any(|psbt| psbt.global.unsigned_tx.txid() == txid) {
+            return FundingSpending::RemoteCommitment;
+        }
+        let local_txid = self.state.commitment_txid.or_else(|| {
+            let psbt = self.state.channel.commitment_tx(false).ok()?;
+            Some(psbt.global.unsigned_tx.txid())
+        });
+        if local_txid == Some(txid) {
+            return FundingSpending::LocalCommitment;
+        }
+        // BOLT-3 commitment transactions carry 0x20 in the upper byte of their lock time, while
+        // the closing transactions have zero lock time
+        if tx.lock_time >> 24 == 0x20 {
+            FundingSpending::UnknownCommitment
+        } else {
+            FundingSpending::CooperativeClose
+        }
+    }
+
+    /// Switches the channel to the workflow resolving the mined transaction spending the funding
+    /// output, unless the channel is already in that workflow
+    fn complete_funding_spent(
+        &mut self,
+        endpoints: &mut Endpoints,
+        tx: &Transaction,
+    ) -> Result<ChannelStateMachine, Error> {
+        let txid = tx.txid();
+        let current_state = self.state.state_machine;
+        let spending = self.classify_funding_spending(tx);
+        info!("Funding output is spent by {} transaction {}", spending, txid);
+
+        let state_machine = match spending {
+            FundingSpending::RevokedCommitment
+                if !matches!(current_state, ChannelStateMachine::Penalize(_)) =>
+            {
+                let channel_id = self.event_channel_id();
+                let force_close = NodeEvent::ForceCloseDetected { channel_id, txid, revoked: true };
+                self.publish_event(endpoints, force_close);
+                ChannelPenalize::with(self, endpoints, txid)?.into()
+            }
+            FundingSpending::RemoteCommitment => {
+                warn!("Remote peer has published its commitment transaction {}", txid);
+                if self.force_close_txid != Some(txid) {
+                    self.force_close_txid = Some(txid);
+                    let channel_id = self.event_channel_id();
+                    let force_close =
+                        NodeEvent::ForceCloseDetected { channel_id, txid, revoked: false };
+                    self.publish_event(endpoints, force_close);
+                    claim::start(self, endpoints, tx)?;
+                }
+                current_state
+            }
+            // Local commitment published outside of the force-close workflow (for instance, from
+            // the commitment dump) is tracked and swept by the workflow
+            FundingSpending::LocalCommitment
+                if !matches!(current_state, ChannelStateMachine::Abort(_)) =>
+            {
+                warn!("Local commitment transaction {} is mined; force-closing channel", txid);
+                ChannelAbort::with(self, endpoints)?.into()
+            }
+            FundingSpending::CooperativeClose
+                if !matches!(current_state, ChannelStateMachine::Closing(_)) =>
+            {
+                let channel_id = self.event_channel_id();
+                let message = CtlMsg::ChannelClosed(channel_id);
+                // We swallow error since we do not want to fail the channel if we can't update
+                // the router
+                let _ = self.send_ctl(endpoints, ServiceId::Router, message);
+                self.state.closing = None;
+                let msg = format!("Channel {} is closed", channel_id.ended());
+                self.complete_workflow(endpoints, msg);
+                ChannelStateMachine::Closed
+            }
+            FundingSpending::UnknownCommitment => {
+                error!(
+                    "Funding output is spent by commitment transaction {} unknown to the channel; \
+                     the channel funds can't be recovered",
+                    txid
+                );
+                current_state
+            }
+            // The current workflow tracks the transaction itself
+            _ => current_state,
+        };
+        Ok(state_machine)
+    }
+
+    /// Asks watchd to report the mined transaction spending the funding output once the funding
+    /// is locked, unless it is already asked. Does nothing for the channels which funding is not
+    /// locked yet.
+    pub(super) fn watch_funding_spending(
+        &mut self,
+        endpoints: &mut Endpoints,
+    ) -> Result<(), Error> {
+        if self.funding_spending_watched || !self.state.state_machine.is_funding_locked() {
+            return Ok(());
+        }
+        self.funding_spending_watched = true;
+        let outpoint = self.funding_outpoint();
+        debug!("Watching spending of funding output {}", outpoint);
+        let message = CtlMsg::WatchSpending { outpoint, mempool: false };
+        self.send_ctl(endpoints, ServiceId::Watch, message)?;
+        Ok(())
+    }
+
+    fn funding_outpoint(&self) -> OutPoint {
+        let funding = self.state.channel.funding();
+        OutPoint::new(funding.txid(), funding.output() as u32)
+    }
+
+    /// Rolls the channel back to awaiting for the funding transaction to be mined after it got
+    /// reorged out of the blockchain. The channel is failed if the funding transaction does not
+    /// get mined again within the configured number of blocks.
+    fn complete_funding_reorg(
+        &mut self,
+        endpoints: &mut Endpoints,
+    ) -> Result<ChannelStateMachine, Error> {
+        let txid = self.state.channel.funding().txid();
+        let current_state = self.state.state_machine;
+        warn!(
+            "Funding transaction {} of channel {} is reorged out of the blockchain",
+            txid,
+            self.state.channel.active_channel_id()
+        );
+
+        let state_machine = match current_state {
+            // Zero-conf channels do not wait for the funding transaction to be mined
+            _ if self.state.zero_conf => current_state,
+            ChannelStateMachine::Propose(ChannelPropose::Locked) => {
+                ChannelPropose::Published.into()
+            }
+            ChannelStateMachine::Accept(ChannelAccept::Funded)
+            | ChannelStateMachine::Accept(ChannelAccept::Locked) => ChannelAccept::Signed.into(),
+            _ => current_state,
+        };
+        if matches!(
+            current_state,
+            ChannelStateMachine::Propose(ChannelPropose::Locked)
+                | ChannelStateMachine::Accept(ChannelAccept::Locked)
+        ) {
+            // `funding_locked` will be re-sent once the funding transaction is mined again
+            // TODO: Notify remote peer with `warning` message once it is supported by lnp-core
+            warn!("Remote peer was already notified about funding transaction {} mining", txid);
+        }
+
+        match self.state.funding_height {
+            Some(height) => {
+                let deadline = height + self.config().funding_reorg_timeout;
+                self.state.reorg_deadline = Some(deadline);
+                self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::TrackHeight(deadline))?;
+            }
+            None => warn!("Funding transaction {} height is unknown; can't set deadline", txid),
+        }
+        let _ = self.report_progress(
+            endpoints,
+            format!("Funding transaction {} is reorged; awaiting for it to be mined again", txid),
+        );
+
+        Ok(state_machine)
+    }
+
+    fn complete_remote_error(
+        &mut self,
+        endpoints: &mut Endpoints,
+        channel_propose: ChannelPropose,
+        reason: String,
+    ) -> Result<ChannelStateMachine, Error> {
+        let err = Error::RemoteError(reason);
+        warn!("Channel {}: {}", self.state.channel.active_channel_id(), err.err_details());
+
+        match channel_propose {
+            // Funding transaction is not published yet, so we can just abandon the channel
+            ChannelPropose::Proposed
+            | ChannelPropose::Accepted
+            | ChannelPropose::ExternalFunding
+            | ChannelPropose::Signing
+            | ChannelPropose::Funding => {
+                let temp_channel_id = self
+                    .state
+                    .channel
+                    .temp_channel_id()
+                    .expect("channel at proposal stage must have temporary channel id");
+                let message = CtlMsg::FundingReleased(temp_channel_id);
+                self.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+                self.fail_workflow(endpoints, RpcError::from(&err));
+                Ok(ChannelStateMachine::Closed)
+            }
+            // Funds are already locked in the channel, so we have to get them back with our
+            // latest commitment transaction
+            ChannelPropose::Published | ChannelPropose::Locked => {
+                let _ = self.report_progress(endpoints, format!("{}; force-closing channel", err));
+                Ok(ChannelAbort::with(self, endpoints)?.into())
+            }
+        }
+    }
+
+    fn complete_launch(&mut self, event: Event<BusMsg>) -> Result<ChannelStateMachine, Error> {
+        let Event { endpoints, service: _, source, message } = event;
+        Ok(match message {
+            BusMsg::Ctl(CtlMsg::OpenChannelWith(request)) if request.dry_run => {
+                ChannelPropose::dry_run(self, endpoints, request)?;
+                ChannelStateMachine::Launch
+            }
+            BusMsg::Ctl(CtlMsg::OpenChannelWith(open_channel_with)) => {
+                ChannelPropose::with(self, endpoints, open_channel_with)?.into()
+            }
+            BusMsg::Ctl(CtlMsg::AcceptChannelFrom(accept_channel_from)) => {
+                ChannelAccept::with(endpoints, accept_channel_from, self)?.into()
+            }
+            wrong_msg => {
+                return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Initial, source))
+            }
+        })
+    }
+
+    fn process_propose(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_propose: ChannelPropose,
+    ) -> Result<ChannelStateMachine, Error> {
+        Ok(match channel_propose.next(event, self)? {
+            None => ChannelStateMachine::Active,
+            Some(channel_propose) => ChannelStateMachine::Propose(channel_propose),
+        })
+    }
+
+    fn process_active(&mut self, event: Event<BusMsg>) -> Result<ChannelStateMachine, Error> {
+        let Event { endpoints, service: _, source, message } = event;
+        Ok(match message {
+            BusMsg::Ctl(CtlMsg::CloseChannel {
+                force: false,
+                fee_range,
+                shutdown_script,
+                ..
+            }) => ChannelClose::with(self, endpoints, fee_range, shutdown_script)?.into(),
+            BusMsg::Ln(LnMsg::Shutdown(shutdown)) => {
+                ChannelClose::with_remote(self, endpoints, shutdown)?.into()
+            }
+            BusMsg::Ctl(CtlMsg::PeerReconnected(remote_peer, _)) => {
+                ChannelReestablishing::with(self, endpoints, remote_peer)?.into()
+            }
+            BusMsg::Ctl(CtlMsg::SetChannelFeerate { feerate_per_kw, .. }) => {
+                self.update_feerate(endpoints, feerate_per_kw)?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ln(LnMsg::UpdateFee(update_fee)) => {
+                self.accept_feerate(endpoints, update_fee)?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ln(LnMsg::RevokeAndAck(revoke_and_ack)) => {
+                self.complete_revocation(endpoints, revoke_and_ack)?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ln(LnMsg::CommitmentSigned(commitment_signed)) => {
+                self.accept_commitment(endpoints, commitment_signed)?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ctl(CtlMsg::CommitmentSecret { commitment_number, secret, next_point }) => {
+                self.revoke_commitment(endpoints, commitment_number, secret, next_point)?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ln(LnMsg::UpdateAddHtlc(update_add_htlc)) => {
+                if let Err(err) = self.validate_htlc(update_add_htlc.amount_msat, false) {
+                    return self.fail_channel(endpoints, err);
+                }
+                self.state.channel.update_from_peer(&LnMsg::UpdateAddHtlc(update_add_htlc))?;
+                ChannelStateMachine::Active
+            }
+            BusMsg::Ln(
+                message @ LnMsg::UpdateFulfillHtlc(_)
+                | message @ LnMsg::UpdateFailHtlc(_)
+                | message @ LnMsg::UpdateFailMalformedHtlc(_),
+            ) => {
+                self.state.channel.update_from_peer(&message)?;
+                self.publish_htlc_resolution(endpoints, &message);
+                ChannelStateMachine::Active
+            }
+            // TODO: Process channel operations
+            _ => ChannelStateMachine::Active,
+        })
+    }
+
+    /// Registers the channel funding output with signd, which otherwise refuses to sign the
+    /// funding transaction and the transactions spending the funding output
+    pub(super) fn expect_funding(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let channel = &self.state.channel;
+        let script_pubkey = channel.funding_script_pubkey();
+        let amount = channel.funding().amount();
+        let message = CtlMsg::ExpectFunding { script_pubkey, amount };
+        self.send_ctl(endpoints, ServiceId::Signer, message)?;
+        Ok(())
+    }
+
+    /// Asks signd for the per-commitment point of our commitment transaction following the
+    /// current one, which is sent to the remote peer with `funding_locked` message
+    pub(super) fn request_next_point(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let index = match self.key_index() {
+            Some(index) => index,
+            None => {
+                warn!("Channel keyset is not indexed; next per-commitment point is not requested");
+                return Ok(());
+            }
+        };
+        let commitment_number = self.state.local_commitment_number + 1;
+        let message = CtlMsg::GetCommitmentPoint { index, commitment_number };
+        self.send_ctl(endpoints, ServiceId::Signer, message)?;
+        Ok(())
+    }
+
+    /// Composes `funding_locked` message with the next per-commitment point derived by signd
+    pub(super) fn compose_funding_locked(&self) -> FundingLocked {
+        let mut funding_locked = self.state.channel.compose_funding_locked();
+        match self.state.next_local_point {
+            Some(point) => funding_locked.next_per_commitment_point = point,
+            None => warn!("Next per-commitment point is not known yet; using the channel one"),
+        }
+        funding_locked
+    }
+
+    /// Updates feerate of the channel commitment transactions and asks signd to sign the updated
+    /// remote commitment
+    fn update_feerate(
+        &mut self,
+        endpoints: &mut Endpoints,
+        feerate_per_kw: u32,
+    ) -> Result<(), Error> {
+        if !self.state.is_funder {
+            return Err(Error::NotFunder);
+        }
+        if self.peer_busy {
+            return Err(Error::PeerBusy);
+        }
+
+        let channel_id = self.static_channel_id()?;
+        let update_fee = LnMsg::UpdateFee(UpdateFee { channel_id, feerate_per_kw });
+        self.state.channel.update_from_local(&update_fee)?;
+        self.send_p2p(endpoints, update_fee)?;
+        info!(
+            "{} channel {} feerate to {} sat/kw",
+            "Updating".promo(),
+            channel_id.promoter(),
+            feerate_per_kw
+        );
+
+        let commitment_psbt = self.state.channel.commitment_tx(true)?;
+        self.verify_dust_outputs(&commitment_psbt)?;
+        commitment::start(self, endpoints, commitment_psbt)
+    }
+
+    /// Checks that HTLC offered by us (if `local` is set) or by the remote peer can be added to
+    /// the channel. The HTLC amount must not be below the minimum accepted by the receiving
+    /// party, and the HTLC must not bring the balance of the offering party below the channel
+    /// reserve required by the receiving party. If the offering party is the channel funder, its
+    /// balance must also cover the commitment transaction fee, which grows with each HTLC output
+    /// not trimmed according to BOLT-3. No new HTLCs are accepted once the channel closing has
+    /// started.
+    pub(super) fn validate_htlc(&self, amount_msat: u64, local: bool) -> Result<(), Error> {
+        if matches!(self.state.state_machine, ChannelStateMachine::Closing(_)) {
+            return Err(Error::ChannelClosing);
+        }
+        if local && self.peer_disconnected {
+            return Err(Error::PeerDisconnected);
+        }
+        if local && self.peer_busy {
+            return Err(Error::PeerBusy);
+        }
+        let snapshot = self.state.channel_snapshot();
+        let (balance_msat, pending_htlcs, params) = if local {
+            (snapshot.local_amount_msat, &snapshot.offered_htlcs, &snapshot.remote_params)
+        } else {
+            (snapshot.remote_amount_msat, &snapshot.received_htlcs, &snapshot.local_params)
+        };
+        if amount_msat < params.htlc_minimum_msat {
+            return Err(Error::HtlcBelowMinimum {
+                amount_msat,
+                minimum_msat: params.htlc_minimum_msat,
+            });
+        }
+
+        let mut required_msat = params.channel_reserve_satoshis * 1000;
+        if local == self.state.is_funder {
+            let channel_type = snapshot.common_params.channel_type;
+            let feerate_per_kw = snapshot.common_params.feerate_per_kw;
+            let anchors = has_anchors(channel_type);
+            // Counting HTLCs trimmed in both commitments as untrimmed is on the safe side
+            let dust_limit = snapshot
+                .local_params
+                .dust_limit_satoshis
+                .min(snapshot.remote_params.dust_limit_satoshis);
+            let htlc_count = snapshot
+                .offered_htlcs
+                .iter()
+                .map(|htlc| (htlc.amount, true))
+                .chain(snapshot.received_htlcs.iter().map(|htlc| (htlc.amount, false)))
+                .chain(iter::once((amount_msat, local)))
+                .filter(|(amount, offered)| {
+                    !is_trimmed_htlc(*amount, *offered, dust_limit, feerate_per_kw, channel_type)
+                })
+                .count() as u64;
+            let weight = if anchors { COMMITMENT_ANCHORS_WEIGHT } else { COMMITMENT_WEIGHT };
+            let mut fee_sat =
+                feerate_per_kw as u64 * (weight + HTLC_OUTPUT_WEIGHT * htlc_count) / 1000;
+            if anchors {
+                fee_sat += 2 * ANCHOR_OUTPUT_VALUE;
+            }
+            required_msat += fee_sat * 1000;
+        }
+
+        let pending_msat: u64 = pending_htlcs.iter().map(|htlc| htlc.amount).sum();
+        let available_msat = balance_msat.saturating_sub(pending_msat);
+        if available_msat < amount_msat + required_msat {
+            return Err(Error::ReserveViolation { amount_msat, available_msat, required_msat });
+        }
+        Ok(())
+    }
+
+    /// Checks that the remote commitment transaction which we are going to sign does not
+    /// contain outputs below the dust limit of the remote peer, i.e. that sub-dust HTLC and
+    /// balance outputs are trimmed into the transaction fee according to BOLT-3. Anchor outputs
+    /// have a fixed value and are not subject to the check.
+    pub(super) fn verify_dust_outputs(&self, commitment_psbt: &Psbt) -> Result<(), Error> {
+        let snapshot = self.state.channel_snapshot();
+        let anchors = has_anchors(snapshot.common_params.channel_type);
+        let dust_limit = snapshot.remote_params.dust_limit_satoshis;
+        let tx = &commitment_psbt.global.unsigned_tx;
+        match tx
+            .output
+            .iter()
+            .filter(|txout| !anchors || txout.value != ANCHOR_OUTPUT_VALUE)
+            .find(|txout| txout.value < dust_limit)
+        {
+            Some(txout) => {
+                Err(Error::DustOutput { txid: tx.txid(), value: txout.value, dust_limit })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends remote peer our signatures for its updated commitment transaction and its
+    /// second-stage HTLC transactions
+    pub(super) fn complete_commitment_signing(
+        &mut self,
+        endpoints: &mut Endpoints,
+        commitment_psbt: Psbt,
+        htlc_signatures: Vec<Signature>,
+    ) -> Result<(), Error> {
+        let commitment_signed = CommitmentSigned {
+            channel_id: self.static_channel_id()?,
+            signature: propose::funding_input_signature(self, &commitment_psbt)?,
+            htlc_signatures,
+        };
+        self.state.last_commitment_signed = Some(commitment_signed.clone());
+        self.register_remote_commitment(endpoints, commitment_psbt)?;
+        self.send_p2p(endpoints, LnMsg::CommitmentSigned(commitment_signed))?;
+        Ok(())
+    }
+
+    /// Checks that the remote commitment transaction which we are going to sign pays our funds to
+    /// the to-remote output constructed according to the negotiated channel type. With
+    /// `option_static_remotekey` the output pays to our static payment basepoint; otherwise the
+    /// key is derived from it using the remote per-commitment point.
+    pub(super) fn verify_to_remote(&self, commitment_psbt: &Psbt) -> Result<(), Error> {
+        let snapshot = self.state.channel_snapshot();
+        let channel_type = snapshot.common_params.channel_type;
+        let anchors = has_anchors(channel_type);
+        let payment_basepoint =
+            self.state.channel.constructor().local_keys().payment_basepoint.key;
+        let payment_pubkey = if has_static_remotekey(channel_type) {
+            payment_basepoint
+        } else {
+            let secp = secp256k1::Secp256k1::verification_only();
+            derive_pubkey(&secp, payment_basepoint, snapshot.remote_per_commitment_point)?
+        };
+        let script_pubkey = to_remote_script_pubkey(payment_pubkey, anchors);
+
+        let tx = &commitment_psbt.global.unsigned_tx;
+        if tx.output.iter().any(|txout| txout.script_pubkey == script_pubkey) {
+            return Ok(());
+        }
+        // Our output may be trimmed if our balance does not cover the dust limit; the funder
+        // additionally pays the commitment transaction fee (and anchors, if any)
+        let mut required_sat = snapshot.remote_params.dust_limit_satoshis;
+        if self.state.is_funder {
+            let weight = if anchors { COMMITMENT_ANCHORS_WEIGHT } else { COMMITMENT_WEIGHT };
+            required_sat += snapshot.common_params.feerate_per_kw as u64 * weight / 1000;
+            if anchors {
+                required_sat += 2 * ANCHOR_OUTPUT_VALUE;
+            }
+        }
+        if snapshot.local_amount_msat / 1000 <= required_sat {
+            return Ok(());
+        }
+        Err(Error::ToRemoteMismatch(tx.txid()))
+    }
+
+    /// Keeps remote commitment transaction signed by us, such that we will be able to recognize
+    /// it if the remote peer publishes it, including after it gets revoked
+    pub(super) fn register_remote_commitment(
+        &mut self,
+        endpoints: &mut Endpoints,
+        commitment_psbt: Psbt,
+    ) -> Result<(), Error> {
+        let txid = commitment_psbt.global.unsigned_tx.txid();
+        debug!("Keeping remote commitment transaction {}", txid);
+        self.state.remote_commitments.push(commitment_psbt);
+        Ok(())
+    }
+
+    /// Processes revocation of the previous remote commitment transaction, retaining its
+    /// per-commitment secret for the penalty enforcement
+    fn complete_revocation(
+        &mut self,
+        endpoints: &mut Endpoints,
+        revoke_and_ack: RevokeAndAck,
+    ) -> Result<(), Error> {
+        let per_commitment_secret = revoke_and_ack.per_commitment_secret;
+        self.state.channel.update_from_peer(&LnMsg::RevokeAndAck(revoke_and_ack))?;
+
+        // The latest remote commitment is never revoked
+        if self.state.remote_commitments.len() < 2 {
+            warn!("Remote peer revoked commitment transaction which was not signed by us");
+            return Ok(());
+        }
+        // Remote commitments are revoked one by one starting from the first one, so the number
+        // of the revoked commitment equals to the number of the secrets received before
+        let commitment_number = self.state.revocation_secrets.received();
+        self.state
+            .revocation_secrets
+            .insert_commitment_secret(commitment_number, &per_commitment_secret)?;
+        let psbt = self.state.remote_commitments.remove(0);
+        let txid = psbt.global.unsigned_tx.txid();
+        debug!("Remote commitment transaction {} is revoked", txid);
+        let revoked = RevokedCommitment { psbt, commitment_number };
+        self.state.revoked_commitments.insert(txid, revoked);
+        self.request_tower_registration(endpoints, txid);
+        Ok(())
+    }
+
+    /// Requests address from the funding wallet for the justice transaction of the revoked
+    /// remote commitment transaction, which is registered with the watchtowers once the address
+    /// is provided
+    fn request_tower_registration(&mut self, endpoints: &mut Endpoints, breach_txid: Txid) {
+        if self.config().towers.is_empty() {
+            return;
+        }
+        match self.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress) {
+            Ok(_) => self.tower_queue.push_back(breach_txid),
+            Err(err) => warn!("Unable to request watchtower registration: {}", err),
+        }
+    }
+
+    /// Asks watchd to upload justice data for the revoked remote commitment transaction, which
+    /// awaits for the address from the funding wallet, to the watchtowers. Registration proceeds
+    /// in background and its failures do not affect channel operations; they are only reported
+    /// in the channel status.
+    fn register_with_towers(&mut self, endpoints: &mut Endpoints, sweep_script: PubkeyScript) {
+        let breach_txid = match self.tower_queue.pop_front() {
+            Some(breach_txid) => breach_txid,
+            None => return,
+        };
+        let revoked = match self.state.revoked_commitments.get(&breach_txid) {
+            Some(revoked) => revoked,
+            None => return,
+        };
+        let per_commitment_secret =
+            match self.state.revocation_secrets.commitment_secret(revoked.commitment_number) {
+                Some(secret) => secret,
+                None => return,
+            };
+        let secp = Secp256k1::signing_only();
+        let per_commitment_point = PublicKey::from_secret_key(&secp, &per_commitment_secret);
+        let penalty_psbt = match compose_penalty_psbt(
+            self,
+            breach_txid,
+            &revoked.psbt,
+            per_commitment_point,
+            sweep_script,
+        ) {
+            Ok(psbt) => psbt,
+            Err(err) => {
+                warn!(
+                    "Revoked commitment {} can't be registered with watchtowers: {}",
+                    breach_txid, err
+                );
+                return;
+            }
+        };
+        let message =
+            CtlMsg::RegisterWithTower { breach_txid, penalty_psbt, per_commitment_secret };
+        match self.send_ctl(endpoints, ServiceId::Watch, message) {
+            Ok(_) => {
+                self.tower_pending.insert(breach_txid);
+            }
+            Err(err) => warn!("Unable to request watchtower registration: {}", err),
+        }
+    }
+
+    /// Keeps remote peer signatures for our updated commitment transaction and its second-stage
+    /// HTLC transactions, which are required to close the channel unilaterally, and asks signd
+    /// to reveal per-commitment secret of our previous commitment transaction to revoke it
+    fn accept_commitment(
+        &mut self,
+        endpoints: &mut Endpoints,
+        commitment_signed: CommitmentSigned,
+    ) -> Result<(), Error> {
+        let signature = commitment_signed.signature;
+        let htlc_signatures = commitment_signed.htlc_signatures.clone();
+        self.state.channel.update_from_peer(&LnMsg::CommitmentSigned(commitment_signed))?;
+        debug!(
+            "Remote peer signed our commitment transaction with {} HTLC signatures",
+            htlc_signatures.len()
+        );
+        self.state.remote_commitment_sig = Some(signature);
+        self.state.remote_htlc_sigs = htlc_signatures;
+
+        let index = self.key_index().ok_or(Error::KeysetUnindexed)?;
+        let commitment_number = self.state.local_commitment_number;
+        let message = CtlMsg::RevealCommitmentSecret { index, commitment_number };
+        self.send_ctl(endpoints, ServiceId::Signer, message)?;
+        Ok(())
+    }
+
+    /// Revokes our previous commitment transaction by replying to the remote peer with
+    /// `revoke_and_ack` message carrying its per-commitment secret revealed by signd
+    fn revoke_commitment(
+        &mut self,
+        endpoints: &mut Endpoints,
+        commitment_number: u64,
+        per_commitment_secret: SecretKey,
+        next_per_commitment_point: PublicKey,
+    ) -> Result<(), Error> {
+        if commitment_number != self.state.local_commitment_number {
+            warn!(
+                "Ignoring per-commitment secret for commitment {} while our commitment is {}",
+                commitment_number, self.state.local_commitment_number
+            );
+            return Ok(());
+        }
+        let revoke_and_ack = RevokeAndAck {
+            channel_id: self.static_channel_id()?,
+            per_commitment_secret,
+            next_per_commitment_point,
+        };
+        self.state.channel.update_from_local(&LnMsg::RevokeAndAck(revoke_and_ack.clone()))?;
+        self.state.local_commitment_number += 1;
+        self.state.next_local_point = Some(next_per_commitment_point);
+        self.state.last_revoke_and_ack = Some(revoke_and_ack.clone());
+        debug!("Our commitment transaction {} is revoked", commitment_number);
+        self.send_p2p(endpoints, LnMsg::RevokeAndAck(revoke_and_ack))?;
+        Ok(())
+    }
+
+    /// Validates and applies feerate update proposed by the remote peer
+    fn accept_feerate(
+        &mut self,
+        endpoints: &mut Endpoints,
+        update_fee: UpdateFee,
+    ) -> Result<(), Error> {
+        let (min_feerate, max_feerate) = self.update_fee_bounds();
+        let feerate_per_kw = update_fee.feerate_per_kw;
+        let err = if self.state.is_funder {
+            Some(Error::NotFunder)
+        } else if feerate_per_kw < min_feerate || feerate_per_kw > max_feerate {
+            Some(Error::PolicyViolation {
+                field: "feerate_per_kw",
+                value: feerate_per_kw as u64,
+                allowed: format!("{}..={}", min_feerate, max_feerate),
+            })
+        } else {
+            None
+        };
+
+        match err {
+            // Feerate disagreement is recoverable, so it does not kill the channel
+            Some(err @ Error::PolicyViolation { .. }) => {
+                warn!("Rejecting feerate update from the remote peer: {}", err);
+                self.warn_peer(endpoints, &err)?;
+                return Err(err);
+            }
+            Some(err) => {
+                warn!("Rejecting feerate update from the remote peer: {}", err);
+                let error = PeerError {
+                    channel_id: update_fee.channel_id,
+                    data: err.to_string().into_bytes(),
+                };
+                self.send_p2p(endpoints, LnMsg::Error(error))?;
+                return Err(err);
+            }
+            None => {}
+        }
+
+        debug!("Remote peer updated channel feerate to {} sat/kw", feerate_per_kw);
+        self.state.channel.update_from_peer(&LnMsg::UpdateFee(update_fee))?;
+        Ok(())
+    }
+
+    /// Feerates the remote channel funder may set with `update_fee`: the configured peer bounds,
+    /// narrowed around our fee estimation once watchd has reported it
+    fn update_fee_bounds(&self) -> (u32, u32) {
+        let bounds = self.config().peer_bounds;
+        let (mut min_feerate, mut max_feerate) =
+            (bounds.min_feerate_per_kw, bounds.max_feerate_per_kw);
+        if let Some(estimate) = self.fee_estimate {
+            let estimate = estimate as u64;
+            max_feerate = (max_feerate as u64).min(estimate * MAX_UPDATE_FEE_PERCENT / 100) as u32;
+            min_feerate = (min_feerate as u64)
+                .max(estimate * MIN_UPDATE_FEE_PERCENT / 100)
+                .min(max_feerate as u64) as u32;
+        }
+        (min_feerate, max_feerate)
+    }
+
+    /// Expiry height of the earliest HTLC pending in the channel
+    fn htlc_expiry(&self) -> Option<u32> {
+        let snapshot = self.state.channel_snapshot();
+        snapshot
+            .offered_htlcs
+            .iter()
+            .map(|htlc| htlc.cltv_expiry)
+            .chain(snapshot.received_htlcs.iter().map(|htlc| htlc.cltv_expiry))
+            .min()
+    }
+
+    /// Blockchain height at which the channel gets force-closed, such that the earliest pending
+    /// HTLC is resolved on-chain before it expires
+    fn htlc_deadline(&self) -> Option<u32> {
+        let margin = self.config().htlc_deadline_margin;
+        htlc_deadline(&self.state.state_machine, self.htlc_expiry(), margin)
+    }
+
+    /// Checks whether the channel has to be force-closed at the given height to resolve its
+    /// pending HTLCs on-chain
+    fn htlc_deadline_reached(&self, height: u32) -> bool {
+        matches!(self.htlc_deadline(), Some(deadline) if deadline <= height)
+    }
+
+    /// Asks watchd to notify once the deadline of the pending HTLCs is reached, unless the same
+    /// deadline is already registered
+    pub(super) fn track_htlc_deadline(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let deadline = self.htlc_deadline();
+        if deadline == self.htlc_deadline_tracked {
+            return Ok(());
+        }
+        self.htlc_deadline_tracked = deadline;
+        if let Some(deadline) = deadline {
+            debug!("Pending HTLCs have to be resolved on-chain at height {}", deadline);
+            self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::TrackHeight(deadline))?;
+        }
+        Ok(())
+    }
+
+    fn static_channel_id(&self) -> Result<ChannelId, Error> {
+        self.state.channel.active_channel_id().channel_id().ok_or(Error::InvalidState {
+            operation: "update channel without permanent channel id",
+            current_state: self.state.state_machine.lifecycle(),
+        })
+    }
+
+    fn process_reestablish(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_reestablishing: ChannelReestablishing,
+    ) -> Result<ChannelStateMachine, Error> {
+        let Event { endpoints, service, source, message } = event;
+        let event = Event::with(&mut *endpoints, service, source, message);
+        let result = channel_reestablishing.next(event, self);
+        self.complete_synchronization(endpoints, result)
+    }
+
+    fn process_abort(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_abort: ChannelAbort,
+    ) -> Result<ChannelStateMachine, Error> {
+        Ok(match channel_abort.next(event, self)? {
+            None => ChannelStateMachine::Closed,
+            Some(channel_abort) => ChannelStateMachine::Abort(channel_abort),
+        })
+    }
+
+    fn process_penalize(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_penalize: ChannelPenalize,
+    ) -> Result<ChannelStateMachine, Error> {
+        Ok(match channel_penalize.next(event, self)? {
+            None => ChannelStateMachine::Closed,
+            Some(channel_penalize) => ChannelStateMachine::Penalize(channel_penalize),
+        })
+    }
+
+    fn process_close(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_close: ChannelClose,
+    ) -> Result<ChannelStateMachine, Error> {
+        // Per BOLT-2 the remote peer must not add HTLCs once it has sent `shutdown`
+        if let BusMsg::Ln(LnMsg::UpdateAddHtlc(_)) = event.message {
+            let draining = match channel_close {
+                ChannelClose::Draining => true,
+                ChannelClose::Addressing => self
+                    .state
+                    .closing
+                    .as_ref()
+                    .map_or(false, |session| session.remote_script.is_some()),
+                _ => false,
+            };
+            if draining {
+                return self.fail_channel(event.endpoints, Error::HtlcAfterShutdown);
+            }
+        }
+        // Fee negotiation which does not converge fails the channel
+        if let BusMsg::Ln(LnMsg::ClosingSigned(_)) = event.message {
+            if channel_close == ChannelClose::Negotiating {
+                self.closing_rounds += 1;
+                if self.closing_rounds > MAX_CLOSING_ROUNDS {
+                    let err = Error::ClosingRoundsExceeded(MAX_CLOSING_ROUNDS);
+                    return self.fail_channel(event.endpoints, err);
+                }
+            }
+        }
+        Ok(match channel_close.next(event, self)? {
+            None => ChannelStateMachine::Closed,
+            Some(channel_close) => ChannelStateMachine::Closing(channel_close),
+        })
+    }
+
+    fn process_accept(
+        &mut self,
+        event: Event<BusMsg>,
+        channel_accept: ChannelAccept,
+    ) -> Result<ChannelStateMachine, Error> {
+        Ok(match channel_accept.next(event, self)? {
+            None => ChannelStateMachine::Active,
+            Some(channel_accept) => ChannelStateMachine::Accept(channel_accept),
+        })
+    }
+}
+
+/// Composes request to the signing or watching daemon which resumes the channel proposal or
+/// acceptance workflow stage once the channel daemon is restarted
+fn resume_request(state: &mut ChannelState) -> Result<Option<(ServiceId, CtlMsg)>, Error> {
+    Ok(match state.state_machine {
+        ChannelStateMachine::Propose(ChannelPropose::Signing)
+        | ChannelStateMachine::Accept(ChannelAccept::Signing) => {
+            let refund_psbt = state.channel.commitment_tx(true)?;
+            Some((ServiceId::Signer, CtlMsg::Sign(refund_psbt)))
+        }
+        ChannelStateMachine::Propose(ChannelPropose::Published)
+        | ChannelStateMachine::Accept(ChannelAccept::Signed)
+        | ChannelStateMachine::Accept(ChannelAccept::Funded) => {
+            let txid = state.channel.funding().txid();
+            Some((ServiceId::Watch, CtlMsg::Track { txid, depth: state.minimum_depth }))
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lnp::p2p::legacy::TempChannelId;
+    use lnpbp::chain::Chain;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    const RESTORED: [ChannelStateMachine; 6] = [
+        ChannelStateMachine::Propose(ChannelPropose::Signing),
+        ChannelStateMachine::Propose(ChannelPropose::Published),
+        ChannelStateMachine::Accept(ChannelAccept::Signing),
+        ChannelStateMachine::Accept(ChannelAccept::Funded),
+        ChannelStateMachine::Abort(ChannelAbort::Signing),
+        ChannelStateMachine::Abort(ChannelAbort::Published),
+    ];
+
+    #[test]
+    fn state_machine_persistence() {
+        for state_machine in RESTORED.iter() {
+            let data = state_machine.strict_serialize().unwrap();
+            assert_eq!(ChannelStateMachine::strict_deserialize(data).unwrap(), *state_machine);
+        }
+    }
+
+    #[test]
+    fn restored_timeouts() {
+        let timeouts = ProposeTimeouts {
+            proposed: Duration::from_secs(1),
+            accepted: Duration::from_secs(2),
+            signing: Duration::from_secs(3),
+        };
+        let signing = ChannelStateMachine::Propose(ChannelPropose::Signing);
+        assert_eq!(signing.timeout(&timeouts), Some(timeouts.signing));
+        for state_machine in RESTORED.iter().skip(1) {
+            assert_eq!(state_machine.timeout(&timeouts), None);
+        }
+    }
+
+    #[test]
+    fn restored_funding_tracking() {
+        let awaiting = RESTORED.iter().filter(|state_machine| state_machine.is_awaiting_funding());
+        assert_eq!(awaiting.copied().collect::<Vec<_>>(), vec![
+            ChannelStateMachine::Propose(ChannelPropose::Published),
+            ChannelStateMachine::Accept(ChannelAccept::Funded),
+        ]);
+    }
+
+    /// Persists channel state at the given stage and loads it back, as the restarted channel
+    /// daemon does
+    fn restored_state(state_machine: ChannelStateMachine) -> ChannelState {
+        let mut state = ChannelState::with(TempChannelId::random(), &Chain::Testnet3);
+        state.state_machine = state_machine;
+        state.minimum_depth = 3;
+        let data = channeld::wrap_state(&state.strict_serialize().unwrap());
+        ChannelState::strict_deserialize(channeld::unwrap_state(&data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn restored_signing() {
+        for state_machine in [
+            ChannelStateMachine::Propose(ChannelPropose::Signing),
+            ChannelStateMachine::Accept(ChannelAccept::Signing),
+        ]
+        .iter()
+        {
+            let mut state = restored_state(*state_machine);
+            let refund_psbt = state.channel.commitment_tx(true).unwrap();
+            match resume_request(&mut state).unwrap() {
+                Some((ServiceId::Signer, CtlMsg::Sign(psbt))) => assert_eq!(psbt, refund_psbt),
+                _ => panic!("channel restored at {} must request signing", state_machine),
+            }
+        }
+    }
+
+    #[test]
+    fn restored_funding_watching() {
+        for state_machine in [
+            ChannelStateMachine::Propose(ChannelPropose::Published),
+            ChannelStateMachine::Accept(ChannelAccept::Signed),
+            ChannelStateMachine::Accept(ChannelAccept::Funded),
+        ]
+        .iter()
+        {
+            let mut state = restored_state(*state_machine);
+            let funding_txid = state.channel.funding().txid();
+            match resume_request(&mut state).unwrap() {
+                Some((ServiceId::Watch, CtlMsg::Track { txid, depth })) => {
+                    assert_eq!(txid, funding_txid);
+                    assert_eq!(depth, 3);
+                }
+                _ => panic!("channel restored at {} must track funding", state_machine),
+            }
+        }
+    }
+
+    #[test]
+    fn restored_without_request() {
+        for state_machine in [
+            ChannelStateMachine::Propose(ChannelPropose::Proposed),
+            ChannelStateMachine::Accept(ChannelAccept::Locked),
+            ChannelStateMachine::Active,
+        ]
+        .iter()
+        {
+            assert!(resume_request(&mut restored_state(*state_machine)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn funding_amount_limit() {
+        let features = InitFeatures::default();
+        assert!(validate_funding_amount(16_777_215, &features).is_ok());
+        assert!(matches!(
+            validate_funding_amount(16_777_216, &features),
+            Err(Error::FundingTooLarge(16_777_216))
+        ));
+    }
+
+    #[test]
+    fn funding_amount_wumbo() {
+        let features = InitFeatures { option_support_large_channel: true, ..none!() };
+        assert!(validate_funding_amount(16_777_215, &features).is_ok());
+        assert!(validate_funding_amount(16_777_216, &features).is_ok());
+    }
+
+    #[test]
+    fn htlc_deadline_margin() {
+        let active = ChannelStateMachine::Active;
+        assert_eq!(htlc_deadline(&active, None, 12), None);
+        assert_eq!(htlc_deadline(&active, Some(500), 12), Some(488));
+        assert_eq!(htlc_deadline(&active, Some(500), 0), Some(500));
+        assert_eq!(htlc_deadline(&active, Some(10), 12), Some(0));
+    }
+
+    #[test]
+    fn htlc_deadline_states() {
+        let force_closable = [
+            ChannelStateMachine::Active,
+            ChannelStateMachine::Reestablishing(ChannelReestablishing::Sent),
+            ChannelStateMachine::Closing(ChannelClose::Shutdown),
+        ];
+        for state_machine in force_closable.iter() {
+            assert_eq!(htlc_deadline(state_machine, Some(500), 12), Some(488));
+        }
+        // Publishing outdated local commitment would lose the channel funds
+        let frozen = ChannelStateMachine::Reestablishing(ChannelReestablishing::Frozen);
+        assert_eq!(htlc_deadline(&frozen, Some(500), 12), None);
+        // Channels being opened have no HTLCs, and the channels being force-closed resolve their
+        // HTLCs on-chain already
+        for state_machine in RESTORED.iter() {
+            assert_eq!(htlc_deadline(state_machine, Some(500), 12), None);
+        }
+    }
+
+    #[test]
+    fn htlc_deadline_blocks() {
+        // Blocks are mined one by one until the deadline is reached
+        let active = ChannelStateMachine::Active;
+        let deadline = htlc_deadline(&active, Some(500), 12).unwrap();
+        let reached = (480..500).find(|height| deadline <= *height);
+        assert_eq!(reached, Some(488));
     }
 }
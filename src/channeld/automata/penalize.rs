@@ -0,0 +1,312 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use lnp::channel::bolt::Lifecycle;
+use lnp::p2p::legacy::ActiveChannelId;
+use lnp::Extension;
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use super::bolt3::{derive_pubkey, derive_revocation_pubkey, to_local_script};
+use super::Error;
+use crate::automata::{Event, StateMachine};
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::service::LogStyle;
+use crate::{Endpoints, Responder};
+
+/// Weight of the penalty transaction spending revoked to-local output into a single P2WPKH
+/// output, in weight units
+const PENALTY_TX_WEIGHT: u64 = 490;
+
+/// Weight added to the penalty transaction by each input spending revoked HTLC output, in weight
+/// units. Uses the larger weight of spending the received HTLC output.
+const PENALTY_HTLC_INPUT_WEIGHT: u64 = 413;
+
+/// Workflow claiming all funds of the revoked remote commitment transaction published by the
+/// remote peer
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+pub enum ChannelPenalize {
+    /// signing penalty transaction with the revocation key
+    #[display("SIGNING")]
+    Signing,
+
+    /// penalty transaction is published, awaiting for it to be mined
+    #[display("PUBLISHED")]
+    Published,
+
+    /// requesting address from the funding wallet to receive the penalty
+    #[display("ADDRESSING")]
+    Addressing,
+}
+
+impl StateMachine<BusMsg, Runtime> for ChannelPenalize {
+    type Error = Error;
+
+    fn next(
+        self,
+        event: Event<BusMsg>,
+        runtime: &mut Runtime,
+    ) -> Result<Option<Self>, Self::Error> {
+        let channel_id = runtime.state.channel.active_channel_id();
+        debug!("ChannelPenalize {:#} received {} event", channel_id, event.message);
+        let state = match self {
+            ChannelPenalize::Addressing | ChannelPenalize::Signing => {
+                finish_signing(event, runtime)
+            }
+            ChannelPenalize::Published => {
+                finish_published(event, runtime)?;
+                info!("ChannelPenalize {:#} has completed its work", channel_id);
+                return Ok(None);
+            }
+        }?;
+        info!("ChannelPenalize {:#} switched to {} state", channel_id, state);
+        Ok(Some(state))
+    }
+}
+
+impl ChannelPenalize {
+    /// Computes channel lifecycle stage for the current channel penalizing workflow stage
+    pub fn lifecycle(&self) -> Lifecycle { Lifecycle::Penalize }
+}
+
+// State transitions:
+
+impl ChannelPenalize {
+    /// Starts penalizing the remote peer which has published revoked commitment transaction
+    /// `breach_txid` by requesting address for the penalty transaction from the funding wallet
+    pub fn with(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+        breach_txid: Txid,
+    ) -> Result<ChannelPenalize, Error> {
+        error!(
+            "{} remote peer has published revoked commitment transaction {} for channel {}",
+            "CHANNEL BREACH:".err(),
+            breach_txid,
+            runtime.state.channel.active_channel_id()
+        );
+
+        if !runtime.state.revoked_commitments.contains_key(&breach_txid) {
+            return Err(Error::InvalidState {
+                operation: "penalize unknown commitment transaction",
+                current_state: runtime.state.state_machine.lifecycle(),
+            });
+        }
+
+        runtime.state.breach_txid = Some(breach_txid);
+        runtime.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::GetSweepAddress)?;
+        Ok(ChannelPenalize::Addressing)
+    }
+
+    /// Construct information message for error and client reporting
+    pub fn info_message(&self, channel_id: ActiveChannelId) -> String {
+        match self {
+            ChannelPenalize::Addressing => format!(
+                "{} address for the penalty of breached channel {:#}",
+                "Requesting".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelPenalize::Signing => format!(
+                "{} penalty transaction for breached channel {:#}",
+                "Signing".promoter(),
+                channel_id.promoter()
+            ),
+            ChannelPenalize::Published => format!(
+                "{} penalty transaction for breached channel {:#} to be mined",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+        }
+    }
+}
+
+fn finish_signing(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<ChannelPenalize, Error> {
+    let penalty_psbt = match event.message {
+        BusMsg::Ctl(CtlMsg::SweepAddress(sweep_script)) => {
+            sign_penalty(runtime, event.endpoints, sweep_script)?;
+            return Ok(ChannelPenalize::Signing);
+        }
+        BusMsg::Ctl(CtlMsg::Signed(psbt)) => psbt,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Penalize, event.source))
+        }
+    };
+
+    let txid = penalty_psbt.global.unsigned_tx.txid();
+    info!("{} penalty transaction {}", "Publishing".promo(), txid.promoter());
+    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::PublishTx(penalty_psbt))?;
+
+    runtime.state.penalty_txid = Some(txid);
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+
+    Ok(ChannelPenalize::Published)
+}
+
+fn finish_published(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<(), Error> {
+    let tx_status = match event.message {
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status))
+            if Some(tx_status.txid) == runtime.state.penalty_txid =>
+        {
+            tx_status
+        }
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Penalize, event.source))
+        }
+    };
+
+    if let Some(channel_id) = runtime.state.channel.active_channel_id().channel_id() {
+        // We swallow error since we do not want to fail the channel if we can't update the router
+        let message = CtlMsg::ChannelClosed(channel_id);
+        let _ = runtime.send_ctl(event.endpoints, ServiceId::Router, message);
+    }
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Untrack(tx_status.txid))?;
+    if let Some(breach_txid) = runtime.state.breach_txid {
+        runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Untrack(breach_txid))?;
+    }
+    runtime.complete_workflow(
+        event.endpoints,
+        format!(
+            "Channel breach is punished; penalty transaction {} is mined",
+            tx_status.txid.ended()
+        ),
+    );
+    Ok(())
+}
+
+/// Constructs penalty transaction paying to the script provided by the funding wallet and sends
+/// it to signd
+fn sign_penalty(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    sweep_script: PubkeyScript,
+) -> Result<(), Error> {
+    let breach_txid = runtime.state.breach_txid.ok_or(Error::InvalidState {
+        operation: "penalize channel without known breach",
+        current_state: Lifecycle::Penalize,
+    })?;
+    let revoked = runtime.state.revoked_commitments.get(&breach_txid).cloned().ok_or(
+        Error::InvalidState {
+            operation: "penalize unknown commitment transaction",
+            current_state: Lifecycle::Penalize,
+        },
+    )?;
+    let per_commitment_secret =
+        runtime.state.revocation_secrets.commitment_secret(revoked.commitment_number).ok_or(
+            Error::InvalidState {
+                operation: "penalize commitment transaction without per-commitment secret",
+                current_state: Lifecycle::Penalize,
+            },
+        )?;
+    let secp = Secp256k1::signing_only();
+    let per_commitment_point = PublicKey::from_secret_key(&secp, &per_commitment_secret);
+    let penalty_psbt = compose_penalty_psbt(
+        runtime,
+        breach_txid,
+        &revoked.psbt,
+        per_commitment_point,
+        sweep_script,
+    )?;
+    let txid = penalty_psbt.global.unsigned_tx.txid();
+    trace!("Penalty transaction: {:#?}", penalty_psbt);
+    debug!("Penalty transaction id is {}", txid);
+
+    let message = CtlMsg::SignPenalty { psbt: penalty_psbt, per_commitment_secret };
+    runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+    Ok(())
+}
+
+/// Constructs penalty transaction spending the to-local and HTLC outputs of the revoked remote
+/// commitment transaction with the revocation key into the given script. HTLC outputs are
+/// detected by their witness scripts, which are kept in the commitment PSBT when we sign it.
+pub(super) fn compose_penalty_psbt(
+    runtime: &Runtime,
+    breach_txid: Txid,
+    revoked_psbt: &Psbt,
+    per_commitment_point: PublicKey,
+    sweep_script: PubkeyScript,
+) -> Result<Psbt, Error> {
+    let secp = Secp256k1::verification_only();
+    let channel = &runtime.state.channel;
+    let snapshot = runtime.state.channel_snapshot();
+    let revocation_basepoint = &channel.constructor().local_keys().revocation_basepoint;
+    let delayed_basepoint = channel.constructor().remote_keys().delayed_payment_basepoint;
+
+    let revocation_pubkey =
+        derive_revocation_pubkey(&secp, revocation_basepoint.key, per_commitment_point)?;
+    let delayed_pubkey = derive_pubkey(&secp, delayed_basepoint, per_commitment_point)?;
+    // Remote peer to-local output is delayed by `to_self_delay` which we have required
+    let witness_script =
+        to_local_script(revocation_pubkey, delayed_pubkey, snapshot.local_params.to_self_delay);
+    let script_pubkey = witness_script.to_v0_p2wsh();
+
+    let revoked_tx = &revoked_psbt.global.unsigned_tx;
+    let mut claims = vec![];
+    for (vout, txout) in revoked_tx.output.iter().enumerate() {
+        if txout.script_pubkey == script_pubkey {
+            claims.push((vout, txout.clone(), witness_script.clone()));
+            continue;
+        }
+        let htlc_script = revoked_psbt.outputs.get(vout).and_then(|output| {
+            output.witness_script.as_ref().filter(|s| s.to_v0_p2wsh() == txout.script_pubkey)
+        });
+        if let Some(htlc_script) = htlc_script {
+            claims.push((vout, txout.clone(), htlc_script.clone()));
+        }
+    }
+    if claims.is_empty() {
+        return Err(Error::PenaltyOutputNotFound(breach_txid));
+    }
+
+    let htlc_count = claims.iter().filter(|(_, _, script)| *script != witness_script).count();
+    let weight = PENALTY_TX_WEIGHT + htlc_count as u64 * PENALTY_HTLC_INPUT_WEIGHT;
+    let fee = snapshot.common_params.feerate_per_kw as u64 * weight / 1000;
+    let total = claims.iter().map(|(_, prevout, _)| prevout.value).sum::<u64>();
+    if total <= fee + snapshot.local_params.dust_limit_satoshis {
+        return Err(Error::PenaltyOutputDust { value: total, fee });
+    }
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: claims
+            .iter()
+            .map(|(vout, ..)| TxIn {
+                previous_output: OutPoint::new(breach_txid, *vout as u32),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            })
+            .collect(),
+        output: vec![TxOut { value: total - fee, script_pubkey: sweep_script.into() }],
+    };
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("penalty transaction is constructed unsigned");
+    for (input, (_, prevout, witness_script)) in psbt.inputs.iter_mut().zip(claims) {
+        input.witness_utxo = Some(prevout);
+        input.witness_script = Some(witness_script);
+        // signd uses revocation basepoint derivation to produce the revocation private key
+        input.bip32_derivation.insert(
+            bitcoin::PublicKey::new(revocation_basepoint.key),
+            revocation_basepoint.source.clone(),
+        );
+    }
+    Ok(Psbt::from(psbt))
+}
@@ -12,21 +12,31 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::Wrapper;
 use bitcoin::secp256k1::Signature;
+use bitcoin::{Script, WScriptHash};
 use lnp::channel::bolt::Lifecycle;
-use lnp::p2p::legacy::{ActiveChannelId, ChannelId, FundingCreated, Messages as LnMsg};
+use lnp::channel::PsbtLnpFunding;
+use lnp::p2p::legacy::{
+    AcceptChannel, ActiveChannelId, ChannelId, Error as PeerError, FundingCreated, FundingLocked,
+    Messages as LnMsg,
+};
 use lnp::Extension;
 use microservices::esb::Handler;
+use psbt::Psbt;
 use wallet::address::AddressCompat;
 
-use super::Error;
+use super::close::upfront_shutdown_script;
+use super::{validate_funding_amount, validate_push_amount, validate_to_self_delay, Error};
 use crate::automata::{Event, StateMachine};
-use crate::bus::{BusMsg, CtlMsg, FundChannel, OpenChannelWith};
+use crate::bus::{BusMsg, CtlMsg, FundChannel, FundingSource, OpenChannelWith, TxStatus};
 use crate::channeld::automata;
 use crate::channeld::runtime::Runtime;
 use crate::rpc::ServiceId;
 use crate::service::LogStyle;
-use crate::{Endpoints, Responder};
+use crate::{Endpoints, PeerBounds, ProposeTimeouts, Responder};
 
 /// Channel proposal workflow
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
@@ -55,6 +65,10 @@ pub enum ChannelPropose {
     /// funding transaction is mined, awaiting for the other peer confirmation of this fact
     #[display("LOCKED")]
     Locked,
+
+    /// awaiting for the user to provide funding transaction constructed with an external wallet
+    #[display("EXTERNAL_FUNDING")]
+    ExternalFunding,
 }
 
 impl StateMachine<BusMsg, Runtime> for ChannelPropose {
@@ -70,6 +84,7 @@ impl StateMachine<BusMsg, Runtime> for ChannelPropose {
         let state = match self {
             ChannelPropose::Proposed => complete_proposed(event, runtime),
             ChannelPropose::Accepted => complete_accepted(event, runtime),
+            ChannelPropose::ExternalFunding => complete_external_funding(event, runtime),
             ChannelPropose::Signing => complete_signing(event, runtime),
             ChannelPropose::Funding => complete_funding(event, runtime),
             ChannelPropose::Published => {
@@ -96,13 +111,28 @@ impl ChannelPropose {
     pub fn lifecycle(&self) -> Lifecycle {
         match self {
             ChannelPropose::Proposed => Lifecycle::Proposed,
-            ChannelPropose::Accepted => Lifecycle::Accepted,
+            ChannelPropose::Accepted | ChannelPropose::ExternalFunding => Lifecycle::Accepted,
             ChannelPropose::Signing => Lifecycle::Signing,
             ChannelPropose::Funding => Lifecycle::Funding,
             ChannelPropose::Published => Lifecycle::Funded,
             ChannelPropose::Locked => Lifecycle::Locked,
         }
     }
+
+    /// Returns maximal time the workflow may stay in its current stage awaiting for a response,
+    /// or `None` if the stage does not time out
+    pub fn timeout(&self, timeouts: &ProposeTimeouts) -> Option<Duration> {
+        match self {
+            ChannelPropose::Proposed => Some(timeouts.proposed),
+            ChannelPropose::Accepted => Some(timeouts.accepted),
+            ChannelPropose::Signing => Some(timeouts.signing),
+            // External wallet may require user interaction, which we can't predict duration of
+            ChannelPropose::ExternalFunding
+            | ChannelPropose::Funding
+            | ChannelPropose::Published
+            | ChannelPropose::Locked => None,
+        }
+    }
 }
 
 // State transitions:
@@ -114,20 +144,83 @@ impl ChannelPropose {
         endpoints: &mut Endpoints,
         request: OpenChannelWith,
     ) -> Result<ChannelPropose, automata::Error> {
-        let open_channel = LnMsg::OpenChannel(runtime.state.channel.compose_open_channel(
+        validate_funding_amount(request.funding_sat, &request.features)?;
+        let max_to_self_delay =
+            request.max_to_self_delay.unwrap_or(runtime.config().peer_bounds.max_to_self_delay);
+        validate_to_self_delay(request.local_params.to_self_delay, max_to_self_delay, false)?;
+        // The remote peer is not known to require its channel reserve from us yet, so we assume
+        // it to be the same as the one we require from it
+        validate_push_amount(
+            request.push_msat,
+            request.funding_sat,
+            request.local_params.channel_reserve_satoshis,
+        )?;
+        let shutdown_script = upfront_shutdown_script(request.shutdown_script.as_ref())?;
+        let mut common_params = request.common_params;
+        common_params.announce_channel = request.announce_channel;
+        let mut open_channel = runtime.state.channel.compose_open_channel(
             request.funding_sat,
             request.push_msat,
             request.policy,
-            request.common_params,
+            common_params,
             request.local_params,
             request.local_keys,
-        )?);
-
-        runtime.send_p2p(endpoints, open_channel)?;
+        )?;
+        open_channel.shutdown_scriptpubkey = shutdown_script.clone();
+        open_channel.channel_flags =
+            (open_channel.channel_flags & !1) | request.announce_channel as u8;
+
+        runtime.state.is_funder = true;
+        runtime.state.zero_conf = request.zero_conf;
+        runtime.state.local_shutdown_script = shutdown_script;
+        runtime.state.max_to_self_delay = request.max_to_self_delay;
+        runtime.state.funding_source = request.funding;
+        runtime.send_p2p(endpoints, LnMsg::OpenChannel(open_channel))?;
 
         Ok(ChannelPropose::Proposed)
     }
 
+    /// Validates channel proposal and asks the broker to compose funding transaction for it,
+    /// without contacting the remote peer. The funding script depends on the remote funding key,
+    /// which is not known before the peer accepts the channel, so the transaction pays to a
+    /// placeholder P2WSH output of the same size.
+    pub fn dry_run(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+        request: OpenChannelWith,
+    ) -> Result<(), automata::Error> {
+        validate_funding_amount(request.funding_sat, &request.features)?;
+        let max_to_self_delay =
+            request.max_to_self_delay.unwrap_or(runtime.config().peer_bounds.max_to_self_delay);
+        validate_to_self_delay(request.local_params.to_self_delay, max_to_self_delay, false)?;
+        validate_push_amount(
+            request.push_msat,
+            request.funding_sat,
+            request.local_params.channel_reserve_satoshis,
+        )?;
+        upfront_shutdown_script(request.shutdown_script.as_ref())?;
+        runtime.state.channel.compose_open_channel(
+            request.funding_sat,
+            request.push_msat,
+            request.policy,
+            request.common_params,
+            request.local_params,
+            request.local_keys,
+        )?;
+
+        let fund_channel = FundChannel {
+            script_pubkey: Script::new_v0_wsh(&WScriptHash::default()).into(),
+            feerate_per_kw: None, // lnpd applies the current fee estimation
+            amount: request.funding_sat,
+        };
+        runtime.send_ctl(
+            endpoints,
+            ServiceId::LnpBroker,
+            CtlMsg::ConstructFundingDryRun(fund_channel),
+        )?;
+        Ok(())
+    }
+
     /// Construct information message for error and client reporting
     pub fn info_message(&self, channel_id: ActiveChannelId) -> String {
         match self {
@@ -141,6 +234,11 @@ impl ChannelPropose {
                 "accepted".promo(),
                 channel_id.promoter()
             ),
+            ChannelPropose::ExternalFunding => format!(
+                "{} funding transaction for channel {:#} from an external wallet",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
             ChannelPropose::Signing => format!(
                 "{} refund transaction locally for channel {:#}",
                 "Signing".promoter(),
@@ -174,20 +272,60 @@ fn complete_proposed(
         }
     };
 
+    let temp_channel_id = accept_channel.temporary_channel_id;
+    let funding_sat = runtime.state.channel.funding().amount();
+    let mut bounds = runtime.config().peer_bounds;
+    if let Some(max_to_self_delay) = runtime.state.max_to_self_delay {
+        bounds.max_to_self_delay = max_to_self_delay;
+    }
+    if let Err(err) = validate_accept_channel(&bounds, &accept_channel, funding_sat) {
+        warn!("Rejecting channel {} accepted by the remote peer: {}", temp_channel_id, err);
+        let error = PeerError {
+            channel_id: ChannelId::from_inner(temp_channel_id.into_inner()),
+            data: err.to_string().into_bytes(),
+        };
+        runtime.send_p2p(event.endpoints, LnMsg::Error(error))?;
+        return Err(err);
+    }
+
+    // We fund the channel ourselves, so we can start using it without confirmations if we were
+    // asked to. Otherwise we do not rely on the remote peer requirements for large channels and
+    // wait for the depth required by our own configuration.
+    runtime.state.minimum_depth = if runtime.state.zero_conf {
+        0
+    } else {
+        let local_depth = runtime.config().accept_policy.minimum_depth(funding_sat).unwrap_or(0);
+        accept_channel.minimum_depth.max(local_depth)
+    };
+    debug!(
+        "Requiring {} confirmations for channel {} funded with {} sat",
+        runtime.state.minimum_depth, temp_channel_id, funding_sat
+    );
+    runtime.state.remote_shutdown_script =
+        upfront_shutdown_script(accept_channel.shutdown_scriptpubkey.as_ref())?;
     let channel = &mut runtime.state.channel;
     channel.update_from_peer(&LnMsg::AcceptChannel(accept_channel))?;
 
     let fund_channel = FundChannel {
         script_pubkey: channel.funding_script_pubkey(),
-        feerate_per_kw: None, // Will use one from the funding wallet
+        feerate_per_kw: None, // lnpd applies the current fee estimation
         amount: channel.funding().amount(),
     };
-
-    if let Some(address) = channel
-        .network()
-        .and_then(|network| AddressCompat::from_script(&fund_channel.script_pubkey, network))
-    {
-        debug!("Channel funding address is {}", address);
+    runtime.expect_funding(event.endpoints)?;
+
+    let address = funding_address(runtime);
+    debug!("Channel funding address is {}", address);
+
+    if runtime.state.funding_source == FundingSource::External {
+        let _ = runtime.report_progress(
+            event.endpoints,
+            format!(
+                "Fund channel {} by sending exactly {} sat to {}, then provide the funding \
+                 transaction with `channel fund` command without publishing it",
+                temp_channel_id, fund_channel.amount, address
+            ),
+        );
+        return Ok(ChannelPropose::ExternalFunding);
     }
 
     runtime.send_ctl(
@@ -198,6 +336,60 @@ fn complete_proposed(
     Ok(ChannelPropose::Accepted)
 }
 
+/// Checks that the channel parameters required by the remote peer are within the bounds we are
+/// ready to agree on
+fn validate_accept_channel(
+    bounds: &PeerBounds,
+    accept_channel: &AcceptChannel,
+    funding_sat: u64,
+) -> Result<(), automata::Error> {
+    let violation = |field, value, allowed| Error::PolicyViolation { field, value, allowed };
+
+    if accept_channel.minimum_depth > bounds.max_minimum_depth {
+        return Err(violation(
+            "minimum_depth",
+            accept_channel.minimum_depth as u64,
+            format!("up to {}", bounds.max_minimum_depth),
+        ));
+    }
+    validate_to_self_delay(accept_channel.to_self_delay, bounds.max_to_self_delay, true)?;
+    let max_reserve_percent = bounds.max_channel_reserve_percent;
+    let max_reserve = funding_sat * max_reserve_percent as u64 / 100;
+    if accept_channel.channel_reserve_satoshis > max_reserve {
+        return Err(violation(
+            "channel_reserve_satoshis",
+            accept_channel.channel_reserve_satoshis,
+            format!("up to {} ({}% of the funding)", max_reserve, max_reserve_percent),
+        ));
+    }
+    if accept_channel.dust_limit_satoshis < bounds.min_dust_limit_satoshis
+        || accept_channel.dust_limit_satoshis > bounds.max_dust_limit_satoshis
+    {
+        return Err(violation(
+            "dust_limit_satoshis",
+            accept_channel.dust_limit_satoshis,
+            format!("{}..={}", bounds.min_dust_limit_satoshis, bounds.max_dust_limit_satoshis),
+        ));
+    }
+    if accept_channel.htlc_minimum_msat > bounds.max_htlc_minimum_msat {
+        return Err(violation(
+            "htlc_minimum_msat",
+            accept_channel.htlc_minimum_msat,
+            format!("up to {}", bounds.max_htlc_minimum_msat),
+        ));
+    }
+    if accept_channel.max_accepted_htlcs < bounds.min_max_accepted_htlcs {
+        return Err(violation(
+            "max_accepted_htlcs",
+            accept_channel.max_accepted_htlcs as u64,
+            format!("at least {}", bounds.min_max_accepted_htlcs),
+        ));
+    }
+    upfront_shutdown_script(accept_channel.shutdown_scriptpubkey.as_ref())?;
+
+    Ok(())
+}
+
 fn complete_accepted(
     event: Event<BusMsg>,
     runtime: &mut Runtime,
@@ -209,6 +401,51 @@ fn complete_accepted(
         }
     };
 
+    sign_refund(event.endpoints, runtime, funding_psbt)
+}
+
+fn complete_external_funding(
+    event: Event<BusMsg>,
+    runtime: &mut Runtime,
+) -> Result<ChannelPropose, automata::Error> {
+    let mut funding_psbt = match event.message {
+        BusMsg::Ctl(CtlMsg::FundingProvided(funding_psbt)) => funding_psbt,
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Accepted, event.source))
+        }
+    };
+
+    let channel = &runtime.state.channel;
+    let script_pubkey = channel.funding_script_pubkey();
+    let amount = channel.funding().amount();
+    let funding_outputs = funding_psbt
+        .global
+        .unsigned_tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, txout)| txout.script_pubkey == *script_pubkey.as_inner())
+        .collect::<Vec<_>>();
+    let vout = match funding_outputs[..] {
+        [(vout, txout)] if txout.value == amount => vout,
+        [_] | [] => {
+            return Err(Error::FundingOutputMissing { address: funding_address(runtime), amount })
+        }
+        _ => return Err(Error::FundingOutputAmbiguous(funding_outputs.len())),
+    };
+    funding_psbt
+        .set_channel_funding_output(vout as u16)
+        .expect("funding output presence is checked above");
+
+    sign_refund(event.endpoints, runtime, funding_psbt)
+}
+
+/// Constructs refund transaction spending channel funding output and sends it to signd
+fn sign_refund(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    funding_psbt: Psbt,
+) -> Result<ChannelPropose, automata::Error> {
     trace!("Funding transaction: {:#?}", funding_psbt);
     debug!("Funding transaction id is {}", funding_psbt.global.unsigned_tx.txid());
 
@@ -220,7 +457,10 @@ fn complete_accepted(
     trace!("Remote keyset: {:#}", channel.constructor().remote_keys());
     debug!("Refund transaction id is {}", refund_psbt.global.unsigned_tx.txid());
 
-    runtime.send_ctl(event.endpoints, ServiceId::Signer, CtlMsg::Sign(refund_psbt))?;
+    // Refund transaction is the first remote commitment, which must pay our funds back to us
+    // according to the negotiated channel type
+    runtime.verify_to_remote(&refund_psbt)?;
+    runtime.send_ctl(endpoints, ServiceId::Signer, CtlMsg::Sign(refund_psbt))?;
     Ok(ChannelPropose::Signing)
 }
 
@@ -235,19 +475,9 @@ fn complete_signing(
         }
     };
 
-    let channel = &runtime.state.channel;
-
-    let funding_pubkey = channel.funding_pubkey();
-    let funding_input =
-        refund_psbt.inputs.get(0).expect("BOLT commitment always has a single input");
-    let signature = funding_input
-        .partial_sigs
-        .get(&bitcoin::PublicKey::new(funding_pubkey))
-        .ok_or(automata::Error::FundingPsbtUnsigned(funding_pubkey))?;
-    // TODO: Use BitcoinSignature type for parsing signature once bitcoin 0.27 is released
-    let signature = Signature::from_der(&signature[..signature.len() - 1])
-        .map_err(automata::Error::InvalidSig)?;
+    let signature = funding_input_signature(runtime, &refund_psbt)?;
 
+    let channel = &runtime.state.channel;
     let funding = channel.funding();
     let (funding_txid, funding_output_index) = (funding.txid(), funding.output());
     let funding_created = FundingCreated {
@@ -259,11 +489,19 @@ fn complete_signing(
         signature,
     };
 
+    let temp_channel_id = funding_created.temporary_channel_id;
     let channel_id = ChannelId::with(funding_txid, funding_output_index);
     debug!("Changing channel id from {} to {}", runtime.identity(), channel_id);
     runtime.set_identity(event.endpoints, channel_id).expect("unrecoverable ZMQ failure");
     // needed to update ESB routing map
     runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::Hello)?;
+    if runtime.state.funding_source == FundingSource::External {
+        // lnpd does not learn about the funding transaction, so we notify it about the rename
+        let message = CtlMsg::ChannelRenamed(temp_channel_id);
+        runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+    }
+    runtime.register_remote_commitment(event.endpoints, refund_psbt)?;
+    runtime.backup_channel(event.endpoints);
 
     runtime.send_p2p(event.endpoints, LnMsg::FundingCreated(funding_created))?;
     Ok(ChannelPropose::Funding)
@@ -275,20 +513,49 @@ fn complete_funding(
 ) -> Result<ChannelPropose, automata::Error> {
     let funding_signed = match event.message {
         BusMsg::Ln(LnMsg::FundingSigned(funding_signed)) => funding_signed,
+        BusMsg::Ctl(CtlMsg::PeerReconnected(..)) => {
+            if let Some(message @ LnMsg::FundingCreated(_)) = runtime.state.last_p2p_message.clone()
+            {
+                debug!("Retransmitting `funding_created` to the reconnected remote peer");
+                runtime.send_p2p(event.endpoints, message)?;
+            }
+            return Ok(ChannelPropose::Funding);
+        }
         wrong_msg => {
             return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Funding, event.source))
         }
     };
 
     debug!("Got remote node signature {}", funding_signed.signature);
+    runtime.state.remote_commitment_sig = Some(funding_signed.signature);
     // Save signature
     runtime.state.channel.update_from_peer(&LnMsg::FundingSigned(funding_signed))?;
-    runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::PublishFunding)?;
+    match runtime.state.funding_source {
+        FundingSource::Internal => {
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, CtlMsg::PublishFunding)?
+        }
+        FundingSource::External => {
+            let txid = runtime.state.channel.funding().txid();
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!(
+                    "Remote peer has signed refund transaction; funding transaction {} can be \
+                     signed and published now",
+                    txid
+                ),
+            );
+        }
+    }
+
+    if runtime.state.minimum_depth == 0 {
+        lock_unconfirmed_funding(event.endpoints, runtime)?;
+        return Ok(ChannelPropose::Locked);
+    }
 
     let txid = runtime.state.channel.funding().txid();
-    debug!("Waiting for funding transaction {} to be mined", txid);
-    // TODO: Uncomment once watching daemon will be running
-    // runtime.send_ctl(&mut event.endpoints, ServiceId::Watch, CtlMsg::Track(txid))?;
+    let depth = runtime.state.minimum_depth;
+    debug!("Waiting for funding transaction {} to be mined at depth {}", txid, depth);
+    runtime.send_ctl(event.endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth })?;
 
     Ok(ChannelPropose::Published)
 }
@@ -297,22 +564,59 @@ fn complete_published(
     event: Event<BusMsg>,
     runtime: &mut Runtime,
 ) -> Result<Option<ChannelPropose>, automata::Error> {
-    if !matches!(
-        event.message,
-        BusMsg::Ctl(CtlMsg::TxFound(_)) | BusMsg::Ln(LnMsg::FundingLocked(_))
-    ) {
-        return Err(Error::UnexpectedMessage(event.message, Lifecycle::Signed, event.source));
-    }
-
-    debug!("Funding transaction mined, notifying remote peer");
-    let funding_locked = runtime.state.channel.compose_funding_locked();
-    runtime.send_p2p(event.endpoints, LnMsg::FundingLocked(funding_locked))?;
-
-    if let BusMsg::Ln(LnMsg::FundingLocked(_)) = event.message {
-        complete_locked(event, runtime)?;
-    }
+    let funding_event = match event.message {
+        BusMsg::Ctl(CtlMsg::TxConfirmed(tx_status)) => {
+            if !confirm_funding(event.endpoints, runtime, tx_status)? {
+                return Ok(Some(ChannelPropose::Published));
+            }
+            FundingEvent::Confirmed
+        }
+        BusMsg::Ln(LnMsg::FundingLocked(funding_locked)) => {
+            FundingEvent::RemoteLocked(funding_locked)
+        }
+        BusMsg::Ctl(CtlMsg::BumpFunding { feerate_per_kw, .. }) => {
+            let txid = runtime.state.channel.funding().txid();
+            info!(
+                "{} funding transaction {} to {} sat/kw",
+                "Bumping".promo(),
+                txid.promoter(),
+                feerate_per_kw
+            );
+            // Funding txid can't change after `funding_created`, so we bump the fee with CPFP
+            // instead of RBF; thus the funding transaction tracking stays intact
+            let message = CtlMsg::ConstructFundingCpfp { txid, feerate_per_kw };
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+            return Ok(Some(ChannelPropose::Published));
+        }
+        BusMsg::Ctl(CtlMsg::CpfpConstructed(cpfp_psbt)) => {
+            debug!("Signing CPFP transaction {}", cpfp_psbt.global.unsigned_tx.txid());
+            runtime.send_ctl(event.endpoints, ServiceId::Signer, CtlMsg::Sign(cpfp_psbt))?;
+            return Ok(Some(ChannelPropose::Published));
+        }
+        BusMsg::Ctl(CtlMsg::Signed(cpfp_psbt)) => {
+            let txid = cpfp_psbt.global.unsigned_tx.txid();
+            info!("{} CPFP transaction {}", "Publishing".promo(), txid.promoter());
+            let message = CtlMsg::PublishTx(cpfp_psbt);
+            runtime.send_ctl(event.endpoints, ServiceId::LnpBroker, message)?;
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!("Funding transaction fee is bumped with CPFP transaction {}", txid),
+            );
+            return Ok(Some(ChannelPropose::Published));
+        }
+        BusMsg::Ctl(CtlMsg::SignFailed { txid, error }) => {
+            let _ = runtime.report_progress(
+                event.endpoints,
+                format!("CPFP transaction {} is not signed: {}", txid, error),
+            );
+            return Ok(Some(ChannelPropose::Published));
+        }
+        wrong_msg => {
+            return Err(Error::UnexpectedMessage(wrong_msg, Lifecycle::Funded, event.source))
+        }
+    };
 
-    Ok(Some(ChannelPropose::Locked))
+    advance_funding(event.endpoints, runtime, ChannelPropose::Published, funding_event)
 }
 
 fn complete_locked(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<(), automata::Error> {
@@ -323,19 +627,322 @@ fn complete_locked(event: Event<BusMsg>, runtime: &mut Runtime) -> Result<(), au
         }
     };
 
+    let funding_event = FundingEvent::RemoteLocked(funding_locked);
+    advance_funding(event.endpoints, runtime, ChannelPropose::Locked, funding_event).map(|_| ())
+}
+
+/// Funding events completing the channel proposal once the funding transaction is published
+#[derive(Debug)]
+enum FundingEvent {
+    /// Funding transaction has reached the depth required by the channel and we have sent
+    /// `funding_locked` to the remote peer
+    Confirmed,
+
+    /// Remote peer has sent `funding_locked`
+    RemoteLocked(FundingLocked),
+}
+
+/// Applies funding event to the channel proposal awaiting the funding to be locked, activating
+/// the channel once both peers have locked the funding. Returns the next workflow stage, or
+/// `None` if the channel is active.
+fn advance_funding(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    channel_propose: ChannelPropose,
+    funding_event: FundingEvent,
+) -> Result<Option<ChannelPropose>, automata::Error> {
+    let channel_id = runtime.state.channel.active_channel_id().channel_id();
+    let (next, funding_locked) = next_funding_stage(
+        channel_propose,
+        funding_event,
+        &mut runtime.state.remote_funding_locked,
+        channel_id,
+    )?;
+    if let Some(funding_locked) = funding_locked {
+        activate_channel(endpoints, runtime, funding_locked)?;
+    }
+    Ok(next)
+}
+
+/// Computes the channel proposal stage following the funding event, together with the remote
+/// `funding_locked` message which activates the channel. Remote `funding_locked` received before
+/// the funding confirmation is postponed until the confirmation arrives.
+fn next_funding_stage(
+    channel_propose: ChannelPropose,
+    funding_event: FundingEvent,
+    postponed: &mut Option<FundingLocked>,
+    channel_id: Option<ChannelId>,
+) -> Result<(Option<ChannelPropose>, Option<FundingLocked>), automata::Error> {
+    Ok(match (channel_propose, funding_event) {
+        (ChannelPropose::Published, FundingEvent::RemoteLocked(funding_locked)) => {
+            keep_funding_locked(postponed, channel_id, funding_locked)?;
+            debug!(
+                "Remote peer reported funding transaction as mined before we got its \
+                 confirmation; postponing channel activation"
+            );
+            (Some(ChannelPropose::Published), None)
+        }
+        (ChannelPropose::Published, FundingEvent::Confirmed) => match postponed.take() {
+            Some(funding_locked) => {
+                debug!("Applying `funding_locked` previously received from the remote peer");
+                (None, Some(funding_locked))
+            }
+            None => (Some(ChannelPropose::Locked), None),
+        },
+        (ChannelPropose::Locked, FundingEvent::RemoteLocked(funding_locked)) => {
+            if channel_id != Some(funding_locked.channel_id) {
+                return Err(Error::ForeignFundingLocked(funding_locked.channel_id));
+            }
+            (None, Some(funding_locked))
+        }
+        (channel_propose, _) => (Some(channel_propose), None),
+    })
+}
+
+/// Formats channel funding address for reporting to the user, falling back to the funding
+/// script for the networks which do not have address format
+fn funding_address(runtime: &Runtime) -> String {
+    let channel = &runtime.state.channel;
+    let script_pubkey = channel.funding_script_pubkey();
+    channel
+        .network()
+        .and_then(|network| AddressCompat::from_script(&script_pubkey, network))
+        .map(|address| address.to_string())
+        .unwrap_or_else(|| script_pubkey.to_string())
+}
+
+/// Extracts our signature for the channel funding output from a transaction spending it, which
+/// was signed by signd
+pub(super) fn funding_input_signature(
+    runtime: &Runtime,
+    commitment_psbt: &Psbt,
+) -> Result<Signature, automata::Error> {
+    let funding_pubkey = runtime.state.channel.funding_pubkey();
+    let funding_input =
+        commitment_psbt.inputs.get(0).expect("BOLT commitment always has a single input");
+    let signature = funding_input
+        .partial_sigs
+        .get(&bitcoin::PublicKey::new(funding_pubkey))
+        .ok_or(automata::Error::FundingPsbtUnsigned(funding_pubkey))?;
+    // TODO: Use BitcoinSignature type for parsing signature once bitcoin 0.27 is released
+    Signature::from_der(&signature[..signature.len() - 1]).map_err(automata::Error::InvalidSig)
+}
+
+/// Checks that the funding transaction has reached the depth required by the channel and, if it
+/// has, notifies remote peer with `funding_locked` message. Returns whether the funding is locked.
+pub(super) fn confirm_funding(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    tx_status: TxStatus,
+) -> Result<bool, automata::Error> {
+    let funding_txid = runtime.state.channel.funding().txid();
+    if tx_status.txid != funding_txid {
+        return Err(Error::FundingTxidMismatch { expected: funding_txid, found: tx_status.txid });
+    }
+    runtime.state.funding_height = Some(u32::from(tx_status.height));
+    let depth = u32::from(tx_status.depth);
+    let minimum_depth = runtime.state.minimum_depth;
+    if depth < minimum_depth {
+        debug!(
+            "Funding transaction {} has {} out of {} required confirmations",
+            funding_txid, depth, minimum_depth
+        );
+        return Ok(false);
+    }
+
+    debug!("Funding transaction mined, notifying remote peer");
+    let funding_locked = runtime.compose_funding_locked();
+    runtime.send_p2p(endpoints, LnMsg::FundingLocked(funding_locked))?;
+    Ok(true)
+}
+
+/// Notifies remote peer with `funding_locked` message without waiting for the funding transaction
+/// to be mined (zero-conf channel). The funding transaction is still tracked in the background,
+/// and the channel is failed if it does not get confirmed within the configured time.
+pub(super) fn lock_unconfirmed_funding(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+) -> Result<(), automata::Error> {
+    let txid = runtime.state.channel.funding().txid();
+    let timeout = runtime.config().zero_conf_timeout;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    runtime.state.zero_conf_deadline = Some((now + timeout).as_secs());
+    debug!(
+        "Using channel with unconfirmed funding transaction {}, which must be mined within {} \
+         seconds",
+        txid,
+        timeout.as_secs()
+    );
+    runtime.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Track { txid, depth: 1 })?;
+
+    // TODO: Provide alias short channel id (`option_scid_alias`) once it is supported by lnp-core
+    let funding_locked = runtime.compose_funding_locked();
+    runtime.send_p2p(endpoints, LnMsg::FundingLocked(funding_locked))?;
+    Ok(())
+}
+
+/// Keeps `funding_locked` message which the remote peer has sent before we got confirmation of
+/// the funding transaction, such that the channel gets activated right after the confirmation
+/// without waiting for the message to be retransmitted
+pub(super) fn postpone_funding_locked(
+    runtime: &mut Runtime,
+    funding_locked: FundingLocked,
+) -> Result<(), automata::Error> {
+    let channel_id = runtime.state.channel.active_channel_id().channel_id();
+    keep_funding_locked(&mut runtime.state.remote_funding_locked, channel_id, funding_locked)?;
+    debug!(
+        "Remote peer reported funding transaction as mined before we got its confirmation; \
+         postponing channel activation"
+    );
+    Ok(())
+}
+
+/// Checks `funding_locked` message against the channel id and keeps it in place of the
+/// previously postponed one
+fn keep_funding_locked(
+    postponed: &mut Option<FundingLocked>,
+    channel_id: Option<ChannelId>,
+    funding_locked: FundingLocked,
+) -> Result<(), automata::Error> {
+    // We check the message right away, so the peer gets an error before the funding is confirmed
+    if channel_id != Some(funding_locked.channel_id) {
+        return Err(Error::ForeignFundingLocked(funding_locked.channel_id));
+    }
+    if postponed.is_some() {
+        warn!("Remote peer has repeated `funding_locked` message; replacing the previous one");
+    }
+    *postponed = Some(funding_locked);
+    Ok(())
+}
+
+/// Applies remote `funding_locked` message, completing channel establishment
+pub(super) fn activate_channel(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    funding_locked: FundingLocked,
+) -> Result<(), automata::Error> {
+    if runtime.state.channel.active_channel_id().channel_id() != Some(funding_locked.channel_id) {
+        return Err(Error::ForeignFundingLocked(funding_locked.channel_id));
+    }
+
+    debug!("Remote peer confirmed that channel funding got mined");
+    // Save next per commitment point
+    runtime.state.channel.update_from_peer(&LnMsg::FundingLocked(funding_locked))?;
+
     // We swallow error since we do not want to fail the channel if we just can't add it to the
     // router
-    trace!("Notifying remote peer about channel creation");
+    trace!("Notifying router about channel creation");
     let _ = runtime.send_ctl(
-        event.endpoints,
+        endpoints,
         ServiceId::Router,
         CtlMsg::ChannelCreated(runtime.state.channel.channel_info(runtime.state.remote_id())),
     );
+    runtime.announce_active(endpoints);
 
-    debug!("Remote peer confirmed that channel funding got mined");
-    // Save next per commitment point
-    runtime.state.channel.update_from_peer(&LnMsg::FundingLocked(funding_locked))?;
-    info!("Channel {} is active", runtime.state.channel.active_channel_id());
+    let channel_id = runtime.state.channel.active_channel_id();
+    runtime.complete_workflow(endpoints, format!("Channel {} is active", channel_id.ended()));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use amplify::Slice32;
+    use bitcoin::hashes::hex::FromHex;
+    use lightning_encoding::LightningDecode;
+
+    use super::*;
+
+    fn channel_id(id: u8) -> ChannelId { ChannelId::from_inner(Slice32::from([id; 32])) }
+
+    fn funding_locked(id: u8, point: &str) -> FundingLocked {
+        let mut data = vec![id; 32];
+        data.extend(Vec::<u8>::from_hex(point).unwrap());
+        FundingLocked::lightning_deserialize(data).unwrap()
+    }
+
+    const POINT_1: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const POINT_2: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn funding_locked_postponed() {
+        let mut postponed = None;
+        keep_funding_locked(&mut postponed, Some(channel_id(1)), funding_locked(1, POINT_1))
+            .unwrap();
+        assert_eq!(postponed, Some(funding_locked(1, POINT_1)));
+
+        // Repeated message replaces the previous one
+        keep_funding_locked(&mut postponed, Some(channel_id(1)), funding_locked(1, POINT_2))
+            .unwrap();
+        assert_eq!(postponed, Some(funding_locked(1, POINT_2)));
+    }
+
+    #[test]
+    fn funding_locked_foreign() {
+        let mut postponed = None;
+        let message = funding_locked(2, POINT_1);
+        let err = keep_funding_locked(&mut postponed, Some(channel_id(1)), message).unwrap_err();
+        assert!(matches!(err, Error::ForeignFundingLocked(id) if id == channel_id(2)));
+        assert_eq!(postponed, None);
+
+        // Before the funding is known the channel has only a temporary id
+        let message = funding_locked(1, POINT_1);
+        assert!(keep_funding_locked(&mut postponed, None, message).is_err());
+        assert_eq!(postponed, None);
+    }
+
+    #[test]
+    fn remote_locked_first() {
+        let mut postponed = None;
+        let id = Some(channel_id(1));
+        let event = FundingEvent::RemoteLocked(funding_locked(1, POINT_1));
+        let (next, activating) =
+            next_funding_stage(ChannelPropose::Published, event, &mut postponed, id).unwrap();
+        assert_eq!(next, Some(ChannelPropose::Published));
+        assert_eq!(activating, None);
+
+        // Funding confirmation activates the channel with the postponed message
+        let event = FundingEvent::Confirmed;
+        let (next, activating) =
+            next_funding_stage(ChannelPropose::Published, event, &mut postponed, id).unwrap();
+        assert_eq!(next, None);
+        assert_eq!(activating, Some(funding_locked(1, POINT_1)));
+        assert_eq!(postponed, None);
+    }
+
+    #[test]
+    fn remote_locked_second() {
+        let mut postponed = None;
+        let id = Some(channel_id(1));
+        let event = FundingEvent::Confirmed;
+        let (next, activating) =
+            next_funding_stage(ChannelPropose::Published, event, &mut postponed, id).unwrap();
+        assert_eq!(next, Some(ChannelPropose::Locked));
+        assert_eq!(activating, None);
+
+        let event = FundingEvent::RemoteLocked(funding_locked(1, POINT_1));
+        let (next, activating) =
+            next_funding_stage(ChannelPropose::Locked, event, &mut postponed, id).unwrap();
+        assert_eq!(next, None);
+        assert_eq!(activating, Some(funding_locked(1, POINT_1)));
+    }
+
+    #[test]
+    fn remote_locked_mismatch() {
+        let mut postponed = None;
+        let id = Some(channel_id(1));
+        for channel_propose in [ChannelPropose::Published, ChannelPropose::Locked].iter() {
+            let event = FundingEvent::RemoteLocked(funding_locked(2, POINT_1));
+            let err = next_funding_stage(*channel_propose, event, &mut postponed, id).unwrap_err();
+            assert!(matches!(err, Error::ForeignFundingLocked(id) if id == channel_id(2)));
+        }
+        assert_eq!(postponed, None);
+
+        // Confirmation without the valid remote message does not activate the channel
+        let event = FundingEvent::Confirmed;
+        let (next, activating) =
+            next_funding_stage(ChannelPropose::Published, event, &mut postponed, id).unwrap();
+        assert_eq!(next, Some(ChannelPropose::Locked));
+        assert_eq!(activating, None);
+    }
+}
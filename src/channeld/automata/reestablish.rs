@@ -0,0 +1,244 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use amplify::{Slice32, Wrapper};
+use internet2::NodeAddr;
+use lnp::channel::bolt::Lifecycle;
+use lnp::p2p::legacy::{
+    ActiveChannelId, ChannelReestablish, Error as PeerError, Messages as LnMsg,
+};
+use lnp::Extension;
+
+use super::Error;
+use crate::automata::{Event, StateMachine};
+use crate::bus::{BusMsg, CtlMsg};
+use crate::channeld::runtime::Runtime;
+use crate::rpc::ServiceId;
+use crate::service::LogStyle;
+use crate::{Endpoints, Responder};
+
+/// Channel re-establishment workflow, initiated by the local node once the connection with the
+/// remote peer is restored
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+pub enum ChannelReestablishing {
+    /// sent `channel_reestablish` to the remote peer, awaiting for its reply
+    #[display("SENT")]
+    Sent,
+
+    /// remote peer has a newer channel state than we do, meaning that we have lost some of the
+    /// channel data. The channel is frozen: publishing our outdated commitment transaction would
+    /// result in the loss of all channel funds.
+    #[display("FROZEN")]
+    Frozen,
+}
+
+impl StateMachine<BusMsg, Runtime> for ChannelReestablishing {
+    type Error = Error;
+
+    fn next(
+        self,
+        event: Event<BusMsg>,
+        runtime: &mut Runtime,
+    ) -> Result<Option<Self>, Self::Error> {
+        let channel_id = runtime.state.channel.active_channel_id();
+        debug!("ChannelReestablishing {:#} received {} event", channel_id, event.message);
+        let state = match self {
+            ChannelReestablishing::Sent => {
+                let remote_channel_reestablish = match event.message {
+                    BusMsg::Ln(LnMsg::ChannelReestablish(channel_reestablish)) => {
+                        channel_reestablish
+                    }
+                    wrong_msg => {
+                        return Err(Error::UnexpectedMessage(
+                            wrong_msg,
+                            Lifecycle::Reestablishing,
+                            event.source,
+                        ))
+                    }
+                };
+                match synchronize(event.endpoints, runtime, &remote_channel_reestablish)? {
+                    None => {
+                        info!("ChannelReestablishing {:#} has completed its work", channel_id);
+                        return Ok(None);
+                    }
+                    Some(state) => state,
+                }
+            }
+            ChannelReestablishing::Frozen => {
+                return Err(Error::UnexpectedMessage(
+                    event.message,
+                    Lifecycle::Reestablishing,
+                    event.source,
+                ))
+            }
+        };
+        info!("ChannelReestablishing {:#} switched to {} state", channel_id, state);
+        Ok(Some(state))
+    }
+}
+
+impl ChannelReestablishing {
+    /// Computes channel lifecycle stage for the current channel re-establishment workflow stage
+    pub fn lifecycle(&self) -> Lifecycle { Lifecycle::Reestablishing }
+}
+
+// State transitions:
+
+impl ChannelReestablishing {
+    /// Starts channel re-establishment by sending our `channel_reestablish` message to the
+    /// reconnected remote peer
+    pub fn with(
+        runtime: &mut Runtime,
+        endpoints: &mut Endpoints,
+        remote_peer: NodeAddr,
+    ) -> Result<ChannelReestablishing, Error> {
+        runtime.state.remote_peer = Some(remote_peer);
+
+        let channel_id =
+            runtime.state.channel.active_channel_id().channel_id().ok_or(Error::InvalidState {
+                operation: "reestablish channel without permanent channel id",
+                current_state: runtime.state.state_machine.lifecycle(),
+            })?;
+        let snapshot = runtime.state.channel_snapshot();
+        let next_revocation_number = snapshot.commitment_number;
+        // Option_data_loss_protect requires the last per-commitment secret received from the
+        // remote peer, which is all zeros until the remote peer revokes its first commitment
+        let your_last_per_commitment_secret = match next_revocation_number.checked_sub(1) {
+            None => Slice32::default(),
+            Some(commitment_number) => {
+                let secret =
+                    runtime.state.revocation_secrets.commitment_secret(commitment_number).ok_or(
+                        Error::InvalidState {
+                            operation: "reestablish channel without the last per-commitment secret",
+                            current_state: runtime.state.state_machine.lifecycle(),
+                        },
+                    )?;
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&secret[..]);
+                Slice32::from_inner(bytes)
+            }
+        };
+        let channel_reestablish = ChannelReestablish {
+            channel_id,
+            next_commitment_number: snapshot.commitment_number + 1,
+            next_revocation_number,
+            your_last_per_commitment_secret,
+            my_current_per_commitment_point: snapshot.local_per_commitment_point,
+        };
+
+        runtime.send_p2p(endpoints, LnMsg::ChannelReestablish(channel_reestablish))?;
+        Ok(ChannelReestablishing::Sent)
+    }
+
+    /// Construct information message for error and client reporting
+    pub fn info_message(&self, channel_id: ActiveChannelId) -> String {
+        match self {
+            ChannelReestablishing::Sent => format!(
+                "{} remote peer to reestablish channel {:#}",
+                "Awaiting".promo(),
+                channel_id.promoter()
+            ),
+            ChannelReestablishing::Frozen => format!(
+                "Channel {:#} is {}: local channel state is outdated",
+                channel_id.promoter(),
+                "frozen".err()
+            ),
+        }
+    }
+}
+
+/// Validates `channel_reestablish` message received from the remote peer against the stored
+/// channel state and retransmits the messages the remote peer has missed.
+///
+/// Returns `None` if the channel state is synchronized with the remote peer and the channel can
+/// be used, or the state of the re-establishment workflow if the local state is outdated. If the
+/// remote peer has lost its state, returns [`Error::PeerBehind`] error, after which the channel
+/// should be closed unilaterally.
+pub(super) fn synchronize(
+    endpoints: &mut Endpoints,
+    runtime: &mut Runtime,
+    remote_channel_reestablish: &ChannelReestablish,
+) -> Result<Option<ChannelReestablishing>, Error> {
+    let channel_id = remote_channel_reestablish.channel_id;
+    let next_commitment_number = remote_channel_reestablish.next_commitment_number;
+    let next_revocation_number = remote_channel_reestablish.next_revocation_number;
+    let commitment_number = runtime.state.channel_snapshot().commitment_number;
+
+    if next_commitment_number > commitment_number + 1 || next_revocation_number > commitment_number
+    {
+        error!(
+            "{} channel {} is at commitment {}, while the remote peer expects the next commitment \
+             to be {}. Local channel state is outdated, probably due to restoring from a backup; \
+             the channel is frozen. {}",
+            "DATA LOSS:".err(),
+            channel_id,
+            commitment_number,
+            next_commitment_number,
+            "Do not publish local commitment transaction: this will lead to the loss of funds"
+                .err_details()
+        );
+        // Asking the remote peer to fail the channel by publishing its commitment transaction
+        let error = PeerError {
+            channel_id,
+            data: b"channel state is lost; please close the channel unilaterally".to_vec(),
+        };
+        runtime.send_p2p(endpoints, LnMsg::Error(error))?;
+        return Ok(Some(ChannelReestablishing::Frozen));
+    }
+
+    if next_commitment_number < commitment_number || next_revocation_number + 1 < commitment_number
+    {
+        let err = Error::PeerBehind { local: commitment_number, remote: next_commitment_number };
+        let error = PeerError { channel_id, data: err.to_string().into_bytes() };
+        runtime.send_p2p(endpoints, LnMsg::Error(error))?;
+        return Err(err);
+    }
+
+    if commitment_number == 0 && next_commitment_number == 1 {
+        debug!("Retransmitting `funding_locked` to the remote peer");
+        let funding_locked = runtime.compose_funding_locked();
+        runtime.send_p2p(endpoints, LnMsg::FundingLocked(funding_locked))?;
+    }
+
+    if next_revocation_number + 1 == commitment_number {
+        match runtime.state.last_revoke_and_ack.clone() {
+            Some(revoke_and_ack) => {
+                debug!("Retransmitting `revoke_and_ack` to the remote peer");
+                runtime.send_p2p(endpoints, LnMsg::RevokeAndAck(revoke_and_ack))?;
+            }
+            None => warn!("Remote peer has missed `revoke_and_ack` which is not known to us"),
+        }
+    }
+
+    if next_commitment_number == commitment_number {
+        match runtime.state.last_commitment_signed.clone() {
+            Some(commitment_signed) => {
+                debug!("Retransmitting `commitment_signed` to the remote peer");
+                runtime.send_p2p(endpoints, LnMsg::CommitmentSigned(commitment_signed))?;
+            }
+            None => warn!("Remote peer has missed `commitment_signed` which is not known to us"),
+        }
+    }
+
+    // We swallow error since we do not want to fail the channel if we just can't add it to the
+    // router
+    trace!("Notifying router about channel reestablishing");
+    let remote_id = runtime.state.remote_id();
+    let message = CtlMsg::ChannelCreated(runtime.state.channel.channel_info(remote_id));
+    let _ = runtime.send_ctl(endpoints, ServiceId::Router, message);
+    runtime.announce_active(endpoints);
+
+    Ok(None)
+}
@@ -0,0 +1,168 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Export of the channel basepoints and the revocation secrets received from the remote peer for
+//! the audit and recovery tools. Before the export signd derives the channel keyset again from
+//! the channel key index, confirming that the channel uses keys of the node master key.
+//!
+//! Exported data are sent to the client only and never logged. Each export is recorded to the
+//! channel history and reported to the node event subscribers.
+
+use amplify::hex::ToHex;
+use amplify::Wrapper;
+use lnp::channel::bolt::LocalKeyset;
+use lnp_rpc::{
+    ChannelBasepoints, ChannelSecrets, EventDirection, NodeEvent, RevocationSecret, RpcMsg,
+};
+
+use super::Error;
+use crate::bus::CtlMsg;
+use crate::channeld::runtime::Runtime;
+use crate::rpc::{ClientId, ErrorCode, RpcError, ServiceId};
+use crate::{Endpoints, Responder};
+
+/// Starts the export by asking signd to derive the channel keyset
+pub fn start(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    enquirer: ClientId,
+) -> Result<(), Error> {
+    let current_state = runtime.state.state_machine.lifecycle();
+    let channel_id =
+        runtime.state.channel.active_channel_id().channel_id().ok_or(Error::InvalidState {
+            operation: "export secrets of channel without permanent channel id",
+            current_state,
+        })?;
+    if runtime.secrets_export.is_some() {
+        return Err(Error::InvalidState {
+            operation: "export channel secrets while the previous export is in progress",
+            current_state,
+        });
+    }
+    let index = runtime.key_index().ok_or(Error::KeysetUnindexed)?;
+
+    debug!("Asking signer to confirm basepoints of channel {}", channel_id);
+    let message = CtlMsg::DeriveChannelKeys { channel_id: channel_id.into_inner(), index };
+    runtime.send_ctl(endpoints, ServiceId::Signer, message)?;
+    runtime.secrets_export = Some(enquirer);
+    Ok(())
+}
+
+/// Completes the export with the keyset derived by signd, sending the channel secrets to the
+/// client if the keyset matches the channel basepoints
+pub fn complete(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    keyset: LocalKeyset,
+) -> Result<(), Error> {
+    let enquirer = match runtime.secrets_export.take() {
+        Some(enquirer) => enquirer,
+        None => {
+            warn!("Signer has sent channel keyset which was not requested");
+            return Ok(());
+        }
+    };
+
+    let channel = &runtime.state.channel;
+    let local_keys = channel.constructor().local_keys();
+    let confirmed = keyset.funding_pubkey.key == local_keys.funding_pubkey.key
+        && keyset.revocation_basepoint.key == local_keys.revocation_basepoint.key
+        && keyset.payment_basepoint.key == local_keys.payment_basepoint.key
+        && keyset.delayed_payment_basepoint.key == local_keys.delayed_payment_basepoint.key
+        && keyset.htlc_basepoint.key == local_keys.htlc_basepoint.key;
+    if !confirmed {
+        return fail(runtime, endpoints, enquirer, RpcError::from(&Error::KeysetMismatch));
+    }
+
+    let remote_keys = channel.constructor().remote_keys();
+    let snapshot = runtime.state.channel_snapshot();
+    let channel_id = channel.active_channel_id().channel_id().expect("checked at export start");
+    let revocation_secrets = &runtime.state.revocation_secrets;
+    let secrets = ChannelSecrets {
+        channel_id,
+        commitment_number: snapshot.commitment_number,
+        local_basepoints: ChannelBasepoints {
+            funding_pubkey: local_keys.funding_pubkey.key,
+            revocation_basepoint: local_keys.revocation_basepoint.key,
+            payment_basepoint: local_keys.payment_basepoint.key,
+            delayed_payment_basepoint: local_keys.delayed_payment_basepoint.key,
+            htlc_basepoint: local_keys.htlc_basepoint.key,
+        },
+        remote_basepoints: ChannelBasepoints {
+            funding_pubkey: remote_keys.funding_pubkey,
+            revocation_basepoint: remote_keys.revocation_basepoint,
+            payment_basepoint: remote_keys.payment_basepoint,
+            delayed_payment_basepoint: remote_keys.delayed_payment_basepoint,
+            htlc_basepoint: remote_keys.htlc_basepoint,
+        },
+        remote_per_commitment_point: snapshot.remote_per_commitment_point,
+        revocation_secrets: runtime
+            .state
+            .revoked_commitments
+            .iter()
+            .filter_map(|(txid, revoked)| {
+                let secret = revocation_secrets.commitment_secret(revoked.commitment_number)?;
+                Some(RevocationSecret {
+                    commitment_txid: *txid,
+                    per_commitment_secret: secret[..].to_hex(),
+                })
+            })
+            .collect(),
+    };
+
+    warn!(
+        "Basepoints and {} revocation secrets of channel {} are exported",
+        secrets.revocation_secrets.len(),
+        channel_id
+    );
+    let outcome = format!("{} revocation secrets are exported", secrets.revocation_secrets.len());
+    let lifecycle = runtime.state.state_machine.lifecycle();
+    let client = ServiceId::Client(enquirer);
+    let message = s!("channel_secrets");
+    runtime.record_event(lifecycle, EventDirection::Outbound, message, client, outcome);
+    runtime.publish_event(endpoints, NodeEvent::SecretsExported { channel_id });
+    runtime.send_rpc(endpoints, enquirer, RpcMsg::ChannelSecrets(secrets))?;
+    Ok(())
+}
+
+/// Fails the export after signd was unable to derive the channel keyset
+pub fn signer_failed(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    error: &str,
+) -> Result<(), Error> {
+    let enquirer = runtime.secrets_export.take().expect("checked by the caller");
+    let failure = RpcError::new(
+        ErrorCode::SignerUnavailable,
+        format!("Signer is unable to confirm the channel basepoints: {}", error),
+    );
+    fail(runtime, endpoints, enquirer, failure)
+}
+
+/// Reports failure of the export to the client, recording it to the channel history
+fn fail(
+    runtime: &mut Runtime,
+    endpoints: &mut Endpoints,
+    enquirer: ClientId,
+    failure: RpcError,
+) -> Result<(), Error> {
+    warn!("Export of channel secrets has failed: {}", failure.message);
+    let lifecycle = runtime.state.state_machine.lifecycle();
+    let client = ServiceId::Client(enquirer);
+    let message = s!("channel_secrets");
+    let outcome = failure.message.clone();
+    runtime.record_event(lifecycle, EventDirection::Outbound, message, client, outcome);
+    runtime.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+    Ok(())
+}
@@ -0,0 +1,236 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Static channel backup format, allowing to recover channel funds after the loss of the node
+//! data directory.
+//!
+//! Backup data start with [`BACKUP_MAGIC`] bytes and two-byte little-endian format version,
+//! followed by the strict-encoded list of the encrypted channel records. Each record is the
+//! strict-encoded [`ChannelBackup`] encrypted with ChaCha20-Poly1305 and prefixed with its random
+//! nonce. The encryption key is derived from the node key, which is derived from the master key;
+//! thus only the node restored from the same master key can read the backup.
+//!
+//! Records contain just enough data to reconnect the remote peer, ask it to force-close the
+//! channel and sweep our output of its commitment transaction. They do not allow to continue
+//! operating the channel.
+
+use std::io;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoin::OutPoint;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use internet2::NodeAddr;
+use lnp::p2p::legacy::{ChannelId, ChannelType};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Magic bytes starting static channel backup data
+pub const BACKUP_MAGIC: [u8; 4] = *b"LNPB";
+
+/// Version of the static channel backup format produced by this node
+pub const BACKUP_VERSION: u16 = 1;
+
+/// Length of the header preceding the encrypted records: magic bytes and format version
+const HEADER_LEN: usize = BACKUP_MAGIC.len() + 2;
+
+const NONCE_LEN: usize = 12;
+
+/// Errors parsing static channel backup data
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum BackupError {
+    /// data are not a static channel backup
+    WrongMagic,
+
+    /// static channel backup version {0} is not supported; the supported version is 1
+    UnsupportedVersion(u16),
+
+    /// static channel backup is created by a node with a different master key or is corrupted
+    Decryption,
+
+    /// static channel backup is malformed. Details: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+/// Static backup of a single channel
+#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[display("{channel_id}")]
+pub struct ChannelBackup {
+    /// Permanent id of the channel
+    pub channel_id: ChannelId,
+
+    /// Remote peer which is a counterparty of the channel, with the address it was connected at
+    pub remote_peer: NodeAddr,
+
+    /// Funding output of the channel, which gets spent by the remote commitment transaction
+    pub funding_outpoint: OutPoint,
+
+    /// Index from which signd derives the channel keyset, including our payment basepoint
+    pub key_index: u32,
+
+    /// Payment basepoint of the remote peer
+    pub remote_payment_point: PublicKey,
+
+    /// Channel type defining the scripts of the commitment transaction outputs
+    pub channel_type: ChannelType,
+}
+
+/// Derives the key encrypting static channel backups from the node secret key
+pub fn backup_key(node_secret: &SecretKey) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"lnp-node:channel-backup");
+    engine.input(&node_secret[..]);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Serializes channel backups, encrypting each of them with the given key
+pub fn serialize_backups<'a>(
+    backups: impl IntoIterator<Item = &'a ChannelBackup>,
+    key: &[u8; 32],
+) -> Result<Vec<u8>, BackupError> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let mut records = vec![];
+    for backup in backups {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), backup.strict_serialize()?.as_slice())
+            .expect("channel backup record is much shorter than the cipher limit");
+        let mut record = nonce.to_vec();
+        record.extend(ciphertext);
+        records.push(record);
+    }
+
+    let mut data = BACKUP_MAGIC.to_vec();
+    data.extend_from_slice(&BACKUP_VERSION.to_le_bytes());
+    records.strict_encode(&mut data)?;
+    Ok(data)
+}
+
+/// Deserializes channel backups, decrypting them with the given key
+pub fn deserialize_backups(
+    data: impl AsRef<[u8]>,
+    key: &[u8; 32],
+) -> Result<Vec<ChannelBackup>, BackupError> {
+    let data = data.as_ref();
+    if data.len() < HEADER_LEN || data[..BACKUP_MAGIC.len()] != BACKUP_MAGIC {
+        return Err(BackupError::WrongMagic);
+    }
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version != BACKUP_VERSION {
+        return Err(BackupError::UnsupportedVersion(version));
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let records = Vec::<Vec<u8>>::strict_decode(io::Cursor::new(&data[HEADER_LEN..]))?;
+    records
+        .into_iter()
+        .map(|record| {
+            if record.len() < NONCE_LEN {
+                return Err(BackupError::Decryption);
+            }
+            let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+            let plaintext = cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| BackupError::Decryption)?;
+            Ok(ChannelBackup::strict_deserialize(plaintext)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use amplify::{Slice32, Wrapper};
+    use bitcoin::Txid;
+    use internet2::addr::InetSocketAddr;
+    use internet2::{RemoteNodeAddr, RemoteSocketAddr};
+
+    use super::*;
+
+    const POINT: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn backup(no: u8, channel_type: ChannelType) -> ChannelBackup {
+        let node_id = PublicKey::from_str(POINT).unwrap();
+        let remote_addr =
+            RemoteSocketAddr::Ftcp(InetSocketAddr::from_str("127.0.0.1:9735").unwrap());
+        ChannelBackup {
+            channel_id: ChannelId::from_inner(Slice32::from_inner([no; 32])),
+            remote_peer: NodeAddr::Remote(RemoteNodeAddr { node_id, remote_addr }),
+            funding_outpoint: OutPoint::new(Txid::from_inner([no; 32]), 1),
+            key_index: no as u32,
+            remote_payment_point: node_id,
+            channel_type,
+        }
+    }
+
+    fn key(secret: u8) -> [u8; 32] { backup_key(&SecretKey::from_slice(&[secret; 32]).unwrap()) }
+
+    #[test]
+    fn round_trip() {
+        let backups =
+            vec![backup(1, ChannelType::Basic), backup(2, ChannelType::AnchoredZeroFeeHtlc)];
+        let data = serialize_backups(&backups, &key(1)).unwrap();
+        assert_eq!(data[..4], BACKUP_MAGIC);
+        assert_eq!(data[4..6], BACKUP_VERSION.to_le_bytes());
+        assert_eq!(deserialize_backups(&data, &key(1)).unwrap(), backups);
+
+        let data = serialize_backups(&Vec::<ChannelBackup>::new(), &key(1)).unwrap();
+        assert_eq!(deserialize_backups(&data, &key(1)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn random_nonces() {
+        let backups = vec![backup(1, ChannelType::StaticRemotekey)];
+        let data1 = serialize_backups(&backups, &key(1)).unwrap();
+        let data2 = serialize_backups(&backups, &key(1)).unwrap();
+        assert_ne!(data1, data2);
+    }
+
+    #[test]
+    fn other_node_key() {
+        assert_eq!(key(1), key(1));
+        assert_ne!(key(1), key(2));
+        let data = serialize_backups(&[backup(1, ChannelType::Basic)], &key(1)).unwrap();
+        assert!(matches!(deserialize_backups(&data, &key(2)), Err(BackupError::Decryption)));
+    }
+
+    #[test]
+    fn corrupted_data() {
+        let data = serialize_backups(&[backup(1, ChannelType::Basic)], &key(1)).unwrap();
+
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(deserialize_backups(&tampered, &key(1)), Err(BackupError::Decryption)));
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(deserialize_backups(&wrong_magic, &key(1)), Err(BackupError::WrongMagic)));
+        assert!(matches!(deserialize_backups(&data[..3], &key(1)), Err(BackupError::WrongMagic)));
+
+        let mut newer = data.clone();
+        newer[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            deserialize_backups(&newer, &key(1)),
+            Err(BackupError::UnsupportedVersion(2))
+        ));
+
+        let truncated = &data[..data.len() - 10];
+        assert!(matches!(deserialize_backups(truncated, &key(1)), Err(BackupError::Encoding(_))));
+    }
+}
@@ -0,0 +1,111 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Channel state export format used for migrating channels between nodes without closing them.
+//!
+//! Export data start with [`EXPORT_MAGIC`] bytes and two-byte little-endian format version,
+//! followed by strict-encoded channel id, remote peer and commitment number, and the channel
+//! state as it is persisted by channeld. The data end with SHA256 checksum of all preceding
+//! bytes.
+
+use std::io;
+
+use bitcoin::hashes::{sha256, Hash};
+use internet2::NodeAddr;
+use lnp::p2p::legacy::ChannelId;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Magic bytes starting channel state export data
+pub const EXPORT_MAGIC: [u8; 4] = *b"LNPC";
+
+/// Version of the channel state export format produced by this node
+pub const EXPORT_VERSION: u16 = 1;
+
+/// Length of the header preceding the export payload: magic bytes and format version
+const HEADER_LEN: usize = EXPORT_MAGIC.len() + 2;
+
+/// Errors parsing channel state export data
+#[derive(Clone, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum ExportError {
+    /// data are not a channel state export
+    WrongMagic,
+
+    /// channel state export version {0} is not supported; the supported version is 1
+    UnsupportedVersion(u16),
+
+    /// channel state export is corrupted: checksum does not match the data
+    ChecksumMismatch,
+
+    /// channel state export is malformed. Details: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+/// Channel state exported for the migration to another node
+#[derive(Clone, Debug)]
+pub struct ChannelExport {
+    /// Permanent id of the exported channel
+    pub channel_id: ChannelId,
+
+    /// Remote peer which is a counterparty of the exported channel
+    pub remote_peer: NodeAddr,
+
+    /// Number of the latest commitment transaction in the exported state
+    pub commitment_number: u64,
+
+    /// Channel state in the same versioned container as it is persisted by channeld. The state
+    /// references channel keys, which are derived by signd from the node seed, so the node
+    /// importing the state must use the same seed.
+    pub state: Vec<u8>,
+}
+
+impl ChannelExport {
+    /// Serializes channel export with the versioned header and checksum
+    pub fn serialize(&self) -> Result<Vec<u8>, ExportError> {
+        let mut data = EXPORT_MAGIC.to_vec();
+        data.extend_from_slice(&EXPORT_VERSION.to_le_bytes());
+        self.channel_id.strict_encode(&mut data)?;
+        self.remote_peer.strict_encode(&mut data)?;
+        self.commitment_number.strict_encode(&mut data)?;
+        data.extend_from_slice(&self.state);
+        let checksum = sha256::Hash::hash(&data);
+        data.extend_from_slice(&checksum.into_inner());
+        Ok(data)
+    }
+
+    /// Deserializes channel export, verifying its header and checksum
+    pub fn deserialize(data: impl AsRef<[u8]>) -> Result<Self, ExportError> {
+        let data = data.as_ref();
+        if data.len() < HEADER_LEN + sha256::Hash::LEN || data[..EXPORT_MAGIC.len()] != EXPORT_MAGIC
+        {
+            return Err(ExportError::WrongMagic);
+        }
+        let (payload, checksum) = data.split_at(data.len() - sha256::Hash::LEN);
+        if sha256::Hash::hash(payload)[..] != *checksum {
+            return Err(ExportError::ChecksumMismatch);
+        }
+        let version = u16::from_le_bytes([payload[4], payload[5]]);
+        if version != EXPORT_VERSION {
+            return Err(ExportError::UnsupportedVersion(version));
+        }
+
+        let mut cursor = io::Cursor::new(&payload[HEADER_LEN..]);
+        let channel_id = ChannelId::strict_decode(&mut cursor)?;
+        let remote_peer = NodeAddr::strict_decode(&mut cursor)?;
+        let commitment_number = u64::strict_decode(&mut cursor)?;
+        let state = payload[HEADER_LEN + cursor.position() as usize..].to_vec();
+        Ok(ChannelExport { channel_id, remote_peer, commitment_number, state })
+    }
+}
@@ -0,0 +1,58 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Append-only channel history log, recording each message processed by the channel state
+//! machine or sent to the remote peer.
+//!
+//! The history is persisted in a separate file next to the channel state file as a sequence of
+//! strict-encoded [`ChannelEvent`] records, and survives the termination of the channel daemon.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use lnp_rpc::ChannelEvent;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::bus::BusMsg;
+
+/// Returns message type name for the history records, omitting the message details
+pub fn message_type(message: &BusMsg) -> String {
+    let repr = message.to_string();
+    repr.split('(').next().unwrap_or_default().to_owned()
+}
+
+/// Appends event record to the end of the channel history file, creating the file if needed
+pub fn append_event(path: impl AsRef<Path>, event: &ChannelEvent) -> Result<(), io::Error> {
+    let mut data = vec![];
+    event.strict_encode(&mut data).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    // Writing the whole record at once, such that it is not interleaved with other records
+    file.write_all(&data)
+}
+
+/// Reads all event records from the channel history file. Returns empty history if the file
+/// does not exist.
+pub fn read_history(path: impl AsRef<Path>) -> Result<Vec<ChannelEvent>, strict_encoding::Error> {
+    let data = match fs::read(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        result => result?,
+    };
+    let mut cursor = io::Cursor::new(&data);
+    let mut events = vec![];
+    while (cursor.position() as usize) < data.len() {
+        events.push(ChannelEvent::strict_decode(&mut cursor)?);
+    }
+    Ok(events)
+}
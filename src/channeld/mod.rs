@@ -13,14 +13,27 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 pub(self) mod automata;
+mod backup;
+mod export;
+mod history;
 #[cfg(feature = "server")]
 mod opts;
+mod persistence;
 mod runtime;
+mod shachain;
 mod state;
 pub(self) mod storage;
 
-pub use automata::Error;
+pub use automata::{bolt3, Error};
+pub use backup::{
+    backup_key, deserialize_backups, serialize_backups, BackupError, ChannelBackup, BACKUP_MAGIC,
+    BACKUP_VERSION,
+};
+pub use export::{ChannelExport, ExportError, EXPORT_MAGIC, EXPORT_VERSION};
+pub use history::{append_event, message_type, read_history};
 #[cfg(feature = "server")]
 pub use opts::Opts;
+pub use persistence::{unwrap_state, wrap_state, StateError, STATE_MAGIC, STATE_VERSION};
 pub use runtime::run;
-pub(self) use state::ChannelState;
+pub use shachain::{commitment_index, generate_from_seed, Shachain, ShachainError};
+pub(self) use state::{ChannelState, RevokedCommitment};
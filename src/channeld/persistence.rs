@@ -0,0 +1,268 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Versioned container for the channel state persisted by channeld.
+//!
+//! Persisted data start with [`STATE_MAGIC`] bytes and two-byte little-endian format version,
+//! followed by the strict-encoded channel state of that version. States persisted by the older
+//! node versions are upgraded to [`STATE_VERSION`] once they are loaded. Data persisted before
+//! the container was introduced have no header and are read as the state of version 1.
+//!
+//! Persisted enums are strict-encoded by the order of their variants, so new variants of the
+//! channel state machines are appended after the existing ones.
+
+/// Magic bytes starting persisted channel state
+pub const STATE_MAGIC: [u8; 4] = *b"LNPS";
+
+/// Version of the channel state encoding used by this node
+pub const STATE_VERSION: u16 = 2;
+
+/// Length of the header preceding the channel state: magic bytes and format version
+const HEADER_LEN: usize = STATE_MAGIC.len() + 2;
+
+/// Upgrades strict-encoded channel state of some version to the next version
+pub type Migration = fn(&[u8]) -> Result<Vec<u8>, strict_encoding::Error>;
+
+/// Migrations of the channel state, where migration at index `N` upgrades the state of version
+/// `N + 1` to version `N + 2`. Each change in the channel state encoding must increase
+/// [`STATE_VERSION`] and register a migration from the previous version here.
+const MIGRATIONS: &[Migration] = &[super::state::upgrade_v1];
+
+/// Errors reading persisted channel state
+#[derive(Clone, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum StateError {
+    /// channel state has version {0}, which is newer than the version 2 supported by this node;
+    /// please upgrade the node
+    UnsupportedVersion(u16),
+
+    /// channel state of version {0} can't be upgraded to the current version
+    NoMigration(u16),
+
+    /// channel state can't be upgraded from version {0}. Details: {1}
+    Migration(u16, strict_encoding::Error),
+}
+
+/// Wraps strict-encoded channel state into the container of the current version
+pub fn wrap_state(state: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_LEN + state.len());
+    data.extend_from_slice(&STATE_MAGIC);
+    data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+    data.extend_from_slice(state);
+    data
+}
+
+/// Extracts strict-encoded channel state from the container, upgrading it to the current version
+pub fn unwrap_state(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    debug_assert_eq!(MIGRATIONS.len() + 1, STATE_VERSION as usize);
+
+    let (mut version, mut state) =
+        if data.len() < HEADER_LEN || data[..STATE_MAGIC.len()] != STATE_MAGIC {
+            (1, data.to_vec())
+        } else {
+            (u16::from_le_bytes([data[4], data[5]]), data[HEADER_LEN..].to_vec())
+        };
+    if version > STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+
+    while version < STATE_VERSION {
+        let migration = (version as usize)
+            .checked_sub(1)
+            .and_then(|index| MIGRATIONS.get(index))
+            .ok_or(StateError::NoMigration(version))?;
+        state = migration(&state).map_err(|err| StateError::Migration(version, err))?;
+        info!("Channel state is upgraded from version {} to {}", version, version + 1);
+        version += 1;
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use internet2::NodeAddr;
+    use lnp::channel::bolt::BoltExt;
+    use lnp::p2p::legacy::TempChannelId;
+    use lnp::Channel;
+    use lnpbp::chain::Chain;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::super::automata::accept::ChannelAccept;
+    use super::super::automata::propose::ChannelPropose;
+    use super::super::automata::ChannelStateMachine;
+    use super::super::state::ChannelState;
+    use super::*;
+
+    // Channel state types as they were persisted by the node before the container header was
+    // introduced
+
+    #[derive(Clone, Copy, StrictEncode)]
+    enum BaselineStateMachine {
+        Launch,
+        Propose(BaselinePropose),
+        Accept(BaselineAccept),
+        Active,
+        Reestablishing,
+        Closing,
+        Abort,
+        Penalize,
+    }
+
+    #[derive(Clone, Copy, StrictEncode)]
+    enum BaselinePropose {
+        Proposed,
+        Accepted,
+        Signing,
+        Funding,
+        Published,
+        Locked,
+    }
+
+    #[derive(Clone, Copy, StrictEncode)]
+    enum BaselineAccept {
+        Accepted,
+        Signed,
+        Funded,
+        Locked,
+    }
+
+    #[derive(StrictEncode)]
+    struct BaselineState {
+        state_machine: BaselineStateMachine,
+        channel: Channel<BoltExt>,
+        remote_peer: Option<NodeAddr>,
+    }
+
+    fn state() -> ChannelState { ChannelState::with(TempChannelId::random(), &Chain::Testnet3) }
+
+    /// Encodes the channel state with the given state machine as it was persisted by the node
+    /// before the container header was introduced, returning it together with the encoded
+    /// channel
+    fn fixture(state_machine: BaselineStateMachine) -> (Vec<u8>, Vec<u8>) {
+        let channel = state().channel;
+        let encoded_channel = channel.strict_serialize().unwrap();
+        let state = BaselineState { state_machine, channel, remote_peer: None };
+        (state.strict_serialize().unwrap(), encoded_channel)
+    }
+
+    /// Wraps the state into the container header of the given version
+    fn wrap_version(state: &[u8], version: u16) -> Vec<u8> {
+        let mut data = STATE_MAGIC.to_vec();
+        data.extend_from_slice(&version.to_le_bytes());
+        data.extend_from_slice(state);
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = state().strict_serialize().unwrap();
+        let data = wrap_state(&state);
+        assert_eq!(&data[..4], b"LNPS");
+        assert_eq!(&data[4..6], &STATE_VERSION.to_le_bytes());
+        assert_eq!(unwrap_state(&data).unwrap(), state);
+        ChannelState::strict_deserialize(unwrap_state(&data).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn baseline_discriminants() {
+        let propose = [
+            (BaselinePropose::Proposed, ChannelPropose::Proposed),
+            (BaselinePropose::Accepted, ChannelPropose::Accepted),
+            (BaselinePropose::Signing, ChannelPropose::Signing),
+            (BaselinePropose::Funding, ChannelPropose::Funding),
+            (BaselinePropose::Published, ChannelPropose::Published),
+            (BaselinePropose::Locked, ChannelPropose::Locked),
+        ];
+        let accept = [
+            (BaselineAccept::Accepted, ChannelAccept::Accepted),
+            (BaselineAccept::Signed, ChannelAccept::Signed),
+            (BaselineAccept::Funded, ChannelAccept::Funded),
+            (BaselineAccept::Locked, ChannelAccept::Locked),
+        ];
+        let mut pairs = vec![
+            (BaselineStateMachine::Launch, ChannelStateMachine::Launch),
+            (BaselineStateMachine::Active, ChannelStateMachine::Active),
+        ];
+        pairs.extend(propose.iter().map(|(baseline, current)| {
+            (BaselineStateMachine::Propose(*baseline), ChannelStateMachine::Propose(*current))
+        }));
+        pairs.extend(accept.iter().map(|(baseline, current)| {
+            (BaselineStateMachine::Accept(*baseline), ChannelStateMachine::Accept(*current))
+        }));
+        for (baseline, current) in pairs {
+            assert_eq!(baseline.strict_serialize().unwrap(), current.strict_serialize().unwrap());
+        }
+    }
+
+    #[test]
+    fn baseline_propose() {
+        let (data, channel) = fixture(BaselineStateMachine::Propose(BaselinePropose::Published));
+        let state = ChannelState::strict_deserialize(unwrap_state(&data).unwrap()).unwrap();
+        assert!(matches!(
+            state.state_machine,
+            ChannelStateMachine::Propose(ChannelPropose::Published)
+        ));
+        assert_eq!(state.channel.strict_serialize().unwrap(), channel);
+        assert!(state.remote_peer.is_none());
+        assert!(state.is_funder);
+        assert_eq!(state.minimum_depth, 1);
+    }
+
+    #[test]
+    fn baseline_accept() {
+        let (data, channel) = fixture(BaselineStateMachine::Accept(BaselineAccept::Funded));
+        let state = ChannelState::strict_deserialize(unwrap_state(&data).unwrap()).unwrap();
+        assert!(matches!(state.state_machine, ChannelStateMachine::Accept(ChannelAccept::Funded)));
+        assert_eq!(state.channel.strict_serialize().unwrap(), channel);
+        assert!(!state.is_funder);
+        assert_eq!(state.minimum_depth, 1);
+    }
+
+    #[test]
+    fn baseline_active() {
+        let (data, _) = fixture(BaselineStateMachine::Active);
+        let state = ChannelState::strict_deserialize(unwrap_state(&data).unwrap()).unwrap();
+        assert!(matches!(state.state_machine, ChannelStateMachine::Active));
+        assert!(state.is_funder);
+    }
+
+    #[test]
+    fn baseline_unsupported() {
+        for state_machine in [
+            BaselineStateMachine::Reestablishing,
+            BaselineStateMachine::Closing,
+            BaselineStateMachine::Abort,
+            BaselineStateMachine::Penalize,
+        ]
+        .iter()
+        {
+            let (data, _) = fixture(*state_machine);
+            assert!(matches!(unwrap_state(&data), Err(StateError::Migration(1, _))));
+        }
+    }
+
+    #[test]
+    fn newer_version() {
+        let state = state().strict_serialize().unwrap();
+        let data = wrap_version(&state, STATE_VERSION + 1);
+        assert!(matches!(unwrap_state(&data), Err(StateError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let state = state().strict_serialize().unwrap();
+        let data = wrap_version(&state, 0);
+        assert!(matches!(unwrap_state(&data), Err(StateError::NoMigration(0))));
+    }
+}
@@ -12,25 +12,47 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::io::Seek;
-use std::time::SystemTime;
-use std::{fs, io};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{Read, Seek, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io, process, thread};
 
 use amplify::{DumbDefault, Wrapper};
-use internet2::NodeAddr;
-use lnp::channel::bolt;
+use bitcoin::util::bip32::ChildNumber;
+use bitcoin::{OutPoint, Txid};
+use internet2::{zmqsocket, NodeAddr, ZmqType, ZMQ_CONTEXT};
+use lnp::channel::bolt::{self, Lifecycle};
 use lnp::p2p::legacy::{ActiveChannelId, ChannelId, Messages as LnMsg};
 use lnp::Extension;
-use lnp_rpc::{ChannelInfo, RpcMsg};
+use lnp_rpc::{
+    AuthError, ChannelEvent, ChannelInfo, ChannelSummary, EventDirection, FeePolicy, NodeEvent,
+    RpcMsg,
+};
 use microservices::esb::{self, Handler};
 use strict_encoding::{StrictDecode, StrictEncode};
+use wallet::hlc::HashLock;
 
+use super::automata::{announce, claim, commitment, dump, secrets, ChannelStateMachine};
 use super::storage::{self, Driver};
-use super::ChannelState;
+use super::{ChannelBackup, ChannelExport, ChannelState};
 use crate::bus::{self, BusMsg, CtlMsg, ServiceBus};
 use crate::routed::PaymentError;
-use crate::rpc::{ClientId, ServiceId};
-use crate::{channeld, Config, Endpoints, Error, Responder, Service};
+use crate::rpc::{ClientId, RpcError, ServiceId};
+use crate::service::BridgeHandler;
+use crate::{channeld, Config, Endpoints, Error, Responder, RpcAuth, Service};
+
+/// Period between timer events checking whether the current workflow stage has timed out
+const TIMER_PERIOD: Duration = Duration::from_secs(1);
+
+/// Period between heartbeats reporting channel activity to lnpd
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(30);
+
+/// Period between requests for the fee estimation made to watchd
+const FEE_ESTIMATE_PERIOD: Duration = Duration::from_secs(600);
+
+/// Deviation of the fee estimation from the commitment feerate, in percents, starting from which
+/// the channel funder updates the commitment feerate
+const FEERATE_UPDATE_THRESHOLD: u64 = 20;
 
 pub fn run(config: Config, channel_id: ActiveChannelId) -> Result<(), Error> {
     // TODO: use node configuration to provide custom policy & parameters
@@ -38,9 +60,12 @@ pub fn run(config: Config, channel_id: ActiveChannelId) -> Result<(), Error> {
     // check and read channel file
     let channel_file = config.channel_file(channel_id);
     let (state, file) =
-        if let Ok(file) = fs::OpenOptions::new().read(true).write(true).open(&channel_file) {
+        if let Ok(mut file) = fs::OpenOptions::new().read(true).write(true).open(&channel_file) {
             debug!("Restoring channel state from {}", channel_file.display());
-            let state = ChannelState::strict_decode(&file).map_err(Error::Persistence)?;
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            let data = channeld::unwrap_state(&data).map_err(channeld::Error::from)?;
+            let state = ChannelState::strict_deserialize(data).map_err(Error::Persistence)?;
             info!("Channel state is restored from persistent storage");
             let mut inner_state = bolt::ChannelState::dumb_default();
             state.channel.store_state(&mut inner_state);
@@ -61,6 +86,28 @@ pub fn run(config: Config, channel_id: ActiveChannelId) -> Result<(), Error> {
             return Err(Error::Channel(channeld::Error::NoPersistantData));
         };
 
+    debug!("Opening bridge between runtime and timer threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    // Channel daemons may run as threads of the same process, so the bridge must be unique
+    let bridge_endpoint = format!("inproc://channeld-timer-{}", channel_id);
+    tx.connect(&bridge_endpoint)?;
+    rx.bind(&bridge_endpoint)?;
+
+    debug!("Starting timer thread");
+    let timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    thread::spawn(move || run_timer(timer));
+
     let channel_id = ChannelId::from_inner(channel_id.as_slice32());
     let runtime = Runtime {
         identity: ServiceId::Channel(channel_id),
@@ -69,13 +116,49 @@ pub fn run(config: Config, channel_id: ActiveChannelId) -> Result<(), Error> {
         file,
         started: SystemTime::now(),
         enquirer: None,
+        deadline: None,
+        last_activity: SystemTime::now(),
+        last_heartbeat: None,
+        fee_requested: None,
+        fee_estimate: None,
+        stopping: false,
+        tower_pending: empty!(),
+        tower_queue: empty!(),
+        tower_error: None,
+        dump: None,
+        commitment_signing: None,
+        claim: None,
+        closing_rounds: 0,
+        secrets_export: None,
+        funding_depth: None,
+        force_close_txid: None,
+        htlc_deadline_tracked: None,
+        funding_spending_watched: false,
+        peer_disconnected: false,
+        peer_busy: false,
+        fee_policy: None,
+        announcement: none!(),
+        rpc_auth: RpcAuth::load(&config.data_dir)?,
         storage: Box::new(storage::DiskDriver::init(
             channel_id,
             Box::new(storage::DiskConfig { path: Default::default() }),
         )?),
     };
 
-    Service::run(config, runtime, false)
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+fn run_timer(mut timer: esb::Controller<ServiceBus, BusMsg, BridgeHandler>) {
+    loop {
+        thread::sleep(TIMER_PERIOD);
+        let message = BusMsg::Ctl(CtlMsg::Timeout);
+        if let Err(err) = timer.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+            error!("Channel timer thread is unable to reach the runtime: {}", err);
+        }
+    }
 }
 
 pub struct Runtime {
@@ -87,6 +170,69 @@ pub struct Runtime {
     /// Client which is made an equiry starting the current workflow run by the active state
     /// machine. It is not a part of the state of the machine since it should not persist.
     enquirer: Option<ClientId>,
+    /// Time by which the current workflow stage must complete; otherwise the workflow is
+    /// abandoned. Does not persist: the timers restart with the daemon.
+    pub(super) deadline: Option<SystemTime>,
+    /// Time when the daemon has received the last message other than a timer event
+    last_activity: SystemTime,
+    /// Time when the last heartbeat was sent to lnpd
+    last_heartbeat: Option<SystemTime>,
+    /// Time when the fee estimation was last requested from watchd
+    fee_requested: Option<SystemTime>,
+    /// Feerate estimation for the node confirmation target last reported by watchd, in satoshi
+    /// per kilo-weight unit
+    pub(super) fee_estimate: Option<u32>,
+    /// Indicates that the node is shutting down and the channel state is parked, such that no
+    /// further messages are processed
+    stopping: bool,
+    /// Revoked remote commitment transactions which justice data are not yet accepted by all
+    /// watchtowers. Does not persist: watchd does not keep pending uploads over restarts either.
+    pub(super) tower_pending: BTreeSet<Txid>,
+    /// Revoked remote commitment transactions awaiting address from the funding wallet, to which
+    /// justice transactions uploaded to watchtowers will pay. Does not persist, like the pending
+    /// uploads.
+    pub(super) tower_queue: VecDeque<Txid>,
+    /// The last error uploading justice data to a watchtower, reported until all pending
+    /// uploads succeed
+    tower_error: Option<String>,
+    /// Commitment dump requested by the client, which transactions are being signed
+    pub(super) dump: Option<dump::DumpSession>,
+    /// Remote commitment transaction and its HTLC transactions being signed by signd. Does not
+    /// persist: the remote peer retransmits the updates once it reconnects.
+    pub(super) commitment_signing: Option<commitment::SigningSession>,
+    /// Our output of the latest remote commitment transaction published by the remote peer,
+    /// which is being claimed to the funding wallet
+    pub(super) claim: Option<claim::ClaimSession>,
+    /// Number of `closing_signed` messages received from the remote peer during the current
+    /// closing fee negotiation. Does not persist: the negotiation restarts once the remote peer
+    /// reconnects.
+    pub(super) closing_rounds: u16,
+    /// Client which has requested export of the channel secrets, while signd confirms the
+    /// channel basepoints
+    pub(super) secrets_export: Option<ClientId>,
+    /// Number of funding transaction confirmations reported by watchd since the daemon start
+    pub(super) funding_depth: Option<u32>,
+    /// Remote commitment transaction published by the remote peer, which is already reported to
+    /// the node event subscribers
+    pub(super) force_close_txid: Option<Txid>,
+    /// Deadline of the pending HTLCs registered with watchd. Does not persist: watchd forgets
+    /// the registered heights over restarts.
+    pub(super) htlc_deadline_tracked: Option<u32>,
+    /// Indicates that watchd is asked to report spending of the funding output. Does not
+    /// persist: watchd forgets the watched outpoints over restarts.
+    pub(super) funding_spending_watched: bool,
+    /// Indicates that the remote peer was disconnected on the client request, such that no new
+    /// HTLCs are offered until it reconnects. Does not persist.
+    pub(super) peer_disconnected: bool,
+    /// Indicates that the peer daemon has too many messages queued for the remote peer, such
+    /// that no new channel updates are started until the queue drains. Does not persist.
+    pub(super) peer_busy: bool,
+    /// Routing fee policy announced for the channel, as reported by lnpd
+    fee_policy: Option<FeePolicy>,
+    /// Announcement of the public channel to the network
+    pub(super) announcement: announce::AnnounceSession,
+    /// Root key authenticating client requests
+    rpc_auth: RpcAuth,
     storage: Box<dyn storage::Driver>,
 }
 
@@ -101,6 +247,11 @@ impl esb::Handler<ServiceBus> for Runtime {
 
     fn identity(&self) -> ServiceId { self.identity.clone() }
 
+    fn on_ready(&mut self, endpoints: &mut Endpoints) -> Result<(), Self::Error> {
+        self.restore(endpoints)?;
+        Ok(())
+    }
+
     fn handle(
         &mut self,
         endpoints: &mut Endpoints,
@@ -108,7 +259,22 @@ impl esb::Handler<ServiceBus> for Runtime {
         source: ServiceId,
         message: BusMsg,
     ) -> Result<(), Self::Error> {
+        if bus != ServiceBus::Bridge {
+            self.last_activity = SystemTime::now();
+        }
         match (bus, message, source) {
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) if self.stopping => {
+                // Give ESB time to deliver shutdown acknowledgement before exiting
+                if !self.config.threaded {
+                    info!("Channel daemon {} is stopped", self.identity);
+                    process::exit(0);
+                }
+                Ok(())
+            }
+            (bus, message, source) if self.stopping => {
+                debug!("Ignoring {} message {} from {} during shutdown", bus, message, source);
+                Ok(())
+            }
             (ServiceBus::Msg, BusMsg::Ln(msg), ServiceId::Peer(remote_peer)) => {
                 self.handle_p2p(endpoints, remote_peer, msg)
             }
@@ -116,12 +282,19 @@ impl esb::Handler<ServiceBus> for Runtime {
                 unreachable!("channeld received peer message not from a peerd but from {}", service)
             }
             (ServiceBus::Ctl, BusMsg::Ctl(msg), source) => self.handle_ctl(endpoints, source, msg),
-            (ServiceBus::Rpc, BusMsg::Rpc(msg), ServiceId::Client(client_id)) => {
-                self.handle_rpc(endpoints, client_id, msg)
+            (ServiceBus::Rpc, BusMsg::Request(request), ServiceId::Client(client_id)) => {
+                match self.rpc_auth.authorize(request) {
+                    Ok(msg) => self.handle_rpc(endpoints, client_id, msg),
+                    Err(err) => Ok(self.reject_rpc(endpoints, client_id, err)?),
+                }
+            }
+            (ServiceBus::Rpc, BusMsg::Rpc(_), ServiceId::Client(client_id)) => {
+                Ok(self.reject_rpc(endpoints, client_id, AuthError::Unauthenticated)?)
             }
             (ServiceBus::Rpc, BusMsg::Rpc(_), service) => {
                 unreachable!("lnpd received RPC message not from a client but from {}", service)
             }
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) => self.handle_timer(endpoints),
             (bus, msg, _) => Err(Error::wrong_esb_msg(bus, &msg)),
         }
     }
@@ -139,6 +312,9 @@ impl esb::Handler<ServiceBus> for Runtime {
 }
 
 impl Runtime {
+    #[inline]
+    pub(super) fn config(&self) -> &Config { &self.config }
+
     pub(super) fn set_identity(
         &mut self,
         endpoints: &mut Endpoints,
@@ -159,21 +335,196 @@ impl Runtime {
 
         fs::remove_file(self.config.channel_file(prev_id))?;
 
+        // Channel history is kept under the new channel id, but the daemon may have not yet
+        // recorded anything into it
+        let prev_history =
+            self.config.channel_history_file(ChannelId::from_inner(prev_id.as_slice32()));
+        match fs::rename(prev_history, self.config.channel_history_file(channel_id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+
         Ok(())
     }
 
+    /// Sends message to the remote peer through the peer daemon. Messages starting new channel
+    /// updates are refused with [`channeld::Error::PeerBusy`] while the remote peer is busy;
+    /// messages completing the updates in progress are always sent.
     pub fn send_p2p(
-        &self,
+        &mut self,
         endpoints: &mut Endpoints,
         message: LnMsg,
-    ) -> Result<(), esb::Error<ServiceId>> {
+    ) -> Result<(), channeld::Error> {
         let remote_peer = self.state.remote_peer.clone().expect("unset remote peer in channeld");
-        endpoints.send_to(
-            ServiceBus::Msg,
-            self.identity(),
-            ServiceId::Peer(remote_peer),
-            BusMsg::Ln(message),
-        )
+        let starts_update = matches!(message, LnMsg::UpdateAddHtlc(_) | LnMsg::UpdateFee(_));
+        let refused = self.peer_busy && starts_update;
+        if !refused {
+            // Kept even if the sending fails, since the message is retransmitted once the remote
+            // peer reconnects
+            self.state.last_p2p_message = Some(message.clone());
+        }
+        let message = BusMsg::Ln(message);
+        let message_type = channeld::message_type(&message);
+        let destination = ServiceId::Peer(remote_peer);
+        let result = if refused {
+            Err(channeld::Error::PeerBusy)
+        } else {
+            endpoints
+                .send_to(ServiceBus::Msg, self.identity(), destination.clone(), message)
+                .map_err(channeld::Error::from)
+        };
+        let outcome = match result {
+            Ok(_) => s!("sent"),
+            Err(ref err) => err.to_string(),
+        };
+        let lifecycle = self.state.state_machine.lifecycle();
+        self.record_event(lifecycle, EventDirection::Outbound, message_type, destination, outcome);
+        result
+    }
+
+    /// Appends event to the channel history. Failures to persist the history are logged and do
+    /// not affect channel operations.
+    pub(super) fn record_event(
+        &self,
+        lifecycle: Lifecycle,
+        direction: EventDirection,
+        message: String,
+        service: ServiceId,
+        outcome: String,
+    ) {
+        let event = ChannelEvent {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::from_secs(0))
+                .as_secs(),
+            lifecycle: lifecycle.to_string(),
+            message,
+            direction,
+            service,
+            outcome,
+        };
+        let channel_id = self.state.channel.active_channel_id();
+        let history_file =
+            self.config.channel_history_file(ChannelId::from_inner(channel_id.as_slice32()));
+        if let Err(err) = channeld::append_event(&history_file, &event) {
+            warn!("Unable to append event to {}: {}", history_file.display(), err);
+        }
+    }
+
+    /// Records to the channel history that lnpd was unable to publish a channel transaction
+    fn publish_failed(&self, source: ServiceId, txid: Txid, reason: String) {
+        warn!("Channel transaction {} is not published: {}", txid, reason);
+        let lifecycle = self.state.state_machine.lifecycle();
+        let outcome = format!("transaction {} is not published: {}", txid, reason);
+        self.record_event(lifecycle, EventDirection::Inbound, s!("publish_tx"), source, outcome);
+    }
+
+    /// Reports channel event to lnpd, which publishes it to the node event subscribers.
+    /// Failures to report are logged and do not affect channel operations.
+    pub(super) fn publish_event(&mut self, endpoints: &mut Endpoints, event: NodeEvent) {
+        let message = CtlMsg::NodeEvent(event);
+        if let Err(err) = self.send_ctl(endpoints, ServiceId::LnpBroker, message) {
+            warn!("Unable to report node event to lnpd: {}", err);
+        }
+    }
+
+    /// Reports settlement or failure of the HTLC offered to the remote peer, once the peer's
+    /// update is accepted by the channel
+    pub(super) fn publish_htlc_resolution(&mut self, endpoints: &mut Endpoints, message: &LnMsg) {
+        let channel_id = self.event_channel_id();
+        let event = match message {
+            LnMsg::UpdateFulfillHtlc(update) => NodeEvent::HtlcSettled {
+                channel_id,
+                htlc_id: update.htlc_id,
+                payment_hash: *HashLock::from(update.payment_preimage).as_inner(),
+            },
+            LnMsg::UpdateFailHtlc(update) => {
+                NodeEvent::HtlcFailed { channel_id, htlc_id: update.htlc_id }
+            }
+            LnMsg::UpdateFailMalformedHtlc(update) => {
+                NodeEvent::HtlcFailed { channel_id, htlc_id: update.htlc_id }
+            }
+            _ => return,
+        };
+        self.publish_event(endpoints, event);
+    }
+
+    /// Channel id used in the node events: temporary id until the permanent one is known
+    pub(super) fn event_channel_id(&self) -> ChannelId {
+        ChannelId::from_inner(self.state.channel.active_channel_id().as_slice32())
+    }
+
+    /// Notifies lnpd that the channel is operational, such that lnpd announces its routing fee
+    /// policy to the network, and starts announcing the public channel itself
+    pub(super) fn announce_active(&mut self, endpoints: &mut Endpoints) {
+        let channel_info = self.state.channel.channel_info(self.state.remote_id());
+        let public = self.state.channel_snapshot().common_params.announce_channel;
+        // We swallow error since we do not want to fail the channel if its policy can't be
+        // announced
+        let _ = self.send_ctl(
+            endpoints,
+            ServiceId::LnpBroker,
+            CtlMsg::ChannelActive { channel_info, public },
+        );
+        self.backup_channel(endpoints);
+        if let Err(err) = announce::start(self, endpoints) {
+            warn!("Unable to announce the channel: {}", err);
+        }
+    }
+
+    /// Sends static backup of the channel to lnpd, which adds it to the channel backup file.
+    /// Called once the funding outpoint is known and each time the channel gets active; failures
+    /// are logged and do not affect channel operations.
+    pub(super) fn backup_channel(&mut self, endpoints: &mut Endpoints) {
+        let channel_id = match self.state.channel.active_channel_id().channel_id() {
+            Some(channel_id) => channel_id,
+            None => return,
+        };
+        let key_index = match self.key_index() {
+            Some(key_index) => key_index,
+            None => {
+                warn!(
+                    "Channel {} keyset has no key index; the channel is not backed up",
+                    channel_id
+                );
+                return;
+            }
+        };
+        let constructor = self.state.channel.constructor();
+        let funding = self.state.channel.funding();
+        let backup = ChannelBackup {
+            channel_id,
+            remote_peer: self.state.remote_peer.clone().expect("channel must have remote peer"),
+            funding_outpoint: OutPoint::new(funding.txid(), funding.output() as u32),
+            key_index,
+            remote_payment_point: constructor.remote_keys().payment_basepoint,
+            channel_type: self.state.channel_snapshot().common_params.channel_type,
+        };
+        let message = CtlMsg::BackupChannel(backup);
+        if let Err(err) = self.send_ctl(endpoints, ServiceId::LnpBroker, message) {
+            warn!("Unable to send channel backup to lnpd: {}", err);
+        }
+    }
+
+    /// Index from which signd derives the channel keyset, if the keyset has one
+    pub(super) fn key_index(&self) -> Option<u32> {
+        let payment_basepoint = &self.state.channel.constructor().local_keys().payment_basepoint;
+        // Keysets are derived at `<chain>h/1h/0h/<index>h` path relative to the account key
+        match payment_basepoint.source.1.as_ref().get(3) {
+            Some(ChildNumber::Hardened { index }) => Some(*index),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the remote node is the counterparty of the channel. Remote nodes are
+    /// identified by their node ids, since they may be reachable at different socket addresses.
+    fn is_counterparty(&self, remote_peer: &NodeAddr) -> bool {
+        match (remote_peer, &self.state.remote_peer) {
+            (NodeAddr::Remote(remote_addr), Some(NodeAddr::Remote(counterparty))) => {
+                remote_addr.node_id == counterparty.node_id
+            }
+            _ => false,
+        }
     }
 
     fn handle_p2p(
@@ -198,11 +549,34 @@ impl Runtime {
 
             LnMsg::ChannelReestablish(_)
             | LnMsg::AcceptChannel(_)
+            | LnMsg::FundingCreated(_)
             | LnMsg::FundingSigned(_)
-            | LnMsg::FundingLocked(_) => {
+            | LnMsg::FundingLocked(_)
+            | LnMsg::Shutdown(_)
+            | LnMsg::ClosingSigned(_)
+            | LnMsg::UpdateFee(_)
+            | LnMsg::CommitmentSigned(_)
+            | LnMsg::RevokeAndAck(_)
+            | LnMsg::UpdateAddHtlc(_)
+            | LnMsg::UpdateFulfillHtlc(_)
+            | LnMsg::UpdateFailHtlc(_)
+            | LnMsg::UpdateFailMalformedHtlc(_)
+            | LnMsg::AnnouncementSignatures(_)
+            | LnMsg::Error(_) => {
                 self.process(endpoints, ServiceId::Peer(remote_peer), BusMsg::Ln(message))?;
             }
 
+            // Warnings do not affect the channel state, so they are only logged and recorded to
+            // the channel history
+            LnMsg::Warning(ref warning) => {
+                let text = String::from_utf8_lossy(&warning.data).into_owned();
+                warn!("Remote peer {} has warned about the channel: {}", remote_peer, text);
+                let lifecycle = self.state.state_machine.lifecycle();
+                let message_type = channeld::message_type(&BusMsg::Ln(message));
+                let source = ServiceId::Peer(remote_peer);
+                self.record_event(lifecycle, EventDirection::Inbound, message_type, source, text);
+            }
+
             _ => {
                 // Ignore the rest of LN peer messages
             }
@@ -226,30 +600,230 @@ impl Runtime {
                 // Updating state only if the request was processed
                 self.state.remote_peer = Some(remote_peer);
                 self.process(endpoints, source, BusMsg::Ctl(request))?;
+                self.request_next_point(endpoints)?;
             }
 
             // Processing remote request to open a channel
             CtlMsg::AcceptChannelFrom(bus::AcceptChannelFrom { ref remote_peer, .. }) => {
                 self.enquirer = None;
-                let remote_peer = remote_peer.clone();
-                if self.process(endpoints, source, BusMsg::Ctl(request))? {
-                    // Updating state only if the request was processed
-                    self.state.remote_peer = Some(remote_peer);
+                // Remote peer must be known before processing, since we reply to it with either
+                // `accept_channel` or `error` message
+                self.state.remote_peer = Some(remote_peer.clone());
+                self.process(endpoints, source, BusMsg::Ctl(request))?;
+                self.request_next_point(endpoints)?;
+            }
+
+            CtlMsg::PeerReconnected(ref remote_peer, _) => {
+                // lnpd notifies all channels, so we have to filter out other peers
+                let is_counterparty = self.is_counterparty(remote_peer);
+                if is_counterparty {
+                    self.peer_disconnected = false;
+                    self.peer_busy = false;
+                }
+                if is_counterparty && self.state.state_machine.is_reconnectable() {
+                    self.process(endpoints, source, BusMsg::Ctl(request))?;
+                }
+            }
+
+            CtlMsg::PeerDisconnected(ref remote_peer, _) if self.is_counterparty(remote_peer) => {
+                info!("Remote peer {} is disconnected; new HTLCs are not offered", remote_peer);
+                self.peer_disconnected = true;
+                self.peer_busy = false;
+                self.process(endpoints, source, BusMsg::Ctl(request))?;
+            }
+
+            CtlMsg::PeerBusy(ref remote_peer) if self.is_counterparty(remote_peer) => {
+                warn!("Remote peer {} is busy; new channel updates are held back", remote_peer);
+                self.peer_busy = true;
+            }
+
+            CtlMsg::PeerWritable(ref remote_peer) if self.is_counterparty(remote_peer) => {
+                info!("Remote peer {} is writable again", remote_peer);
+                self.peer_busy = false;
+            }
+
+            // Notifications from the peer daemons of the previous connections
+            CtlMsg::PeerBusy(_) | CtlMsg::PeerWritable(_) => {}
+
+            // lnpd notifies all channels about lost connections
+            CtlMsg::PeerDisconnected(..) => {}
+
+            CtlMsg::AbortChannel { enquirer, .. } => {
+                self.enquirer = Some(enquirer);
+                self.process(endpoints, source, BusMsg::Ctl(request))?;
+            }
+
+            CtlMsg::ExportChannelState { enquirer, .. } => {
+                let reply = match self.export_state() {
+                    Ok(data) => RpcMsg::ChannelExport(data),
+                    Err(err) => RpcMsg::Failure(RpcError::from(&err)),
+                };
+                self.send_rpc(endpoints, enquirer, reply)?;
+            }
+
+            CtlMsg::ImportChannelState { data, enquirer, .. } => {
+                self.enquirer = Some(enquirer);
+                match self.import_state(&data) {
+                    Ok(number) => {
+                        self.restore(endpoints)?;
+                        let msg = format!("Channel state at commitment {} is imported", number);
+                        self.complete_workflow(endpoints, msg);
+                    }
+                    Err(err) => {
+                        warn!("Refusing to import channel state: {}", err);
+                        let failure = RpcError::from(&err);
+                        self.fail_workflow(endpoints, failure);
+                    }
                 }
             }
 
+            CtlMsg::DumpCommitment { enquirer, .. } => {
+                let lifecycle = self.state.state_machine.lifecycle();
+                let outcome = match dump::start(self, endpoints, enquirer) {
+                    Ok(()) => s!("signing"),
+                    Err(err) => {
+                        warn!("Refusing to dump commitment transaction: {}", err);
+                        let failure = RpcError::from(&err);
+                        self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+                        err.to_string()
+                    }
+                };
+                let message = s!("dump_commitment");
+                self.record_event(lifecycle, EventDirection::Inbound, message, source, outcome);
+            }
+
+            CtlMsg::ExportChannelSecrets { enquirer, .. } => {
+                let lifecycle = self.state.state_machine.lifecycle();
+                let outcome = match secrets::start(self, endpoints, enquirer) {
+                    Ok(()) => s!("verifying keys"),
+                    Err(err) => {
+                        warn!("Refusing to export channel secrets: {}", err);
+                        let failure = RpcError::from(&err);
+                        self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+                        err.to_string()
+                    }
+                };
+                let message = s!("export_channel_secrets");
+                self.record_event(lifecycle, EventDirection::Inbound, message, source, outcome);
+            }
+
+            CtlMsg::Keyset(_, keyset) => secrets::complete(self, endpoints, keyset)?,
+
+            CtlMsg::CommitmentPoint { commitment_number, point } => {
+                if commitment_number == self.state.local_commitment_number + 1 {
+                    self.state.next_local_point = Some(point);
+                }
+            }
+
+            // Signer failure to derive the keyset fails the export only
+            CtlMsg::Error { ref request, ref error, .. }
+                if self.secrets_export.is_some() && request.starts_with("derive_channel_keys") =>
+            {
+                secrets::signer_failed(self, endpoints, error)?;
+            }
+
+            CtlMsg::GetInfo => {
+                let summary = self.channel_summary();
+                self.send_ctl(endpoints, source, CtlMsg::ChannelSummary(summary))?;
+            }
+
+            CtlMsg::Shutdown => {
+                self.park(endpoints)?;
+                self.stopping = true;
+                self.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::ShutdownAck)?;
+            }
+
+            CtlMsg::CloseChannel { enquirer, .. } => {
+                self.enquirer = Some(enquirer);
+                self.process(endpoints, source, BusMsg::Ctl(request))?;
+            }
+
+            CtlMsg::ForceClose(_) => {
+                self.process(endpoints, source, BusMsg::Ctl(request))?;
+            }
+
             CtlMsg::FundingConstructed(_)
-            | CtlMsg::TxFound(_)
+            | CtlMsg::FundingProvided(_)
+            | CtlMsg::BatchFailed(_)
+            | CtlMsg::SetChannelFeerate { .. }
+            | CtlMsg::BumpCommitment(..)
+            | CtlMsg::BumpFunding { .. }
+            | CtlMsg::CpfpConstructed(_)
+            | CtlMsg::HtlcTxFunded(_)
+            | CtlMsg::TxConfirmed(_)
+            | CtlMsg::TxReorged(_)
+            | CtlMsg::OutpointSpent { .. }
+            | CtlMsg::FundingConflict(_)
+            | CtlMsg::HeightReached(_)
+            | CtlMsg::NewBlock { .. }
+            | CtlMsg::SweepAddress(_)
             | CtlMsg::Signed(_)
+            | CtlMsg::CommitmentSecret { .. }
+            | CtlMsg::SignFailed { .. }
+            | CtlMsg::AnnouncementSigned(_)
             | CtlMsg::Error { .. }
             | CtlMsg::EsbError { .. } => {
                 self.process(endpoints, source, BusMsg::Ctl(request))?;
             }
 
+            CtlMsg::SignRefused { txid, error } => {
+                error!("Signer has refused to sign transaction {}: {}", txid, error);
+                self.report_failure(endpoints, &error);
+            }
+
+            CtlMsg::TxConflict(txid) => {
+                let reason = s!("conflicts with another transaction in the mempool");
+                self.publish_failed(source, txid, reason);
+            }
+
+            CtlMsg::TxFeeTooLow(txid) => {
+                let reason = s!("fee is too low for the mempool acceptance");
+                self.publish_failed(source, txid, reason);
+            }
+
+            CtlMsg::PublishFailed { txid, error } => self.publish_failed(source, txid, error),
+
+            CtlMsg::TxEvicted(txid) => {
+                warn!("Channel transaction {} is evicted from the mempool", txid);
+                let lifecycle = self.state.state_machine.lifecycle();
+                let outcome =
+                    format!("transaction {} is evicted from the mempool and rebroadcast", txid);
+                let message = s!("tx_evicted");
+                self.record_event(lifecycle, EventDirection::Inbound, message, source, outcome);
+            }
+
+            CtlMsg::TowerRegistered(breach_txid) => {
+                debug!("Revoked commitment {} is registered with watchtowers", breach_txid);
+                self.tower_pending.remove(&breach_txid);
+                if self.tower_pending.is_empty() {
+                    self.tower_error = None;
+                }
+            }
+
+            CtlMsg::TowerFailed { breach_txid, error } => {
+                warn!("Revoked commitment {} is not registered with a watchtower yet", breach_txid);
+                self.tower_error = Some(error);
+            }
+
+            CtlMsg::SetFeePolicy(fee_policy) => {
+                debug!("Channel routing fee policy is set to {}", fee_policy);
+                self.fee_policy = Some(fee_policy);
+            }
+
+            CtlMsg::FeeEstimate { feerate_per_kw, .. } => {
+                self.apply_fee_estimate(endpoints, feerate_per_kw)?;
+            }
+
             CtlMsg::Payment { route, hash_lock, enquirer } => {
                 // TODO: Move into a state machine
                 self.enquirer = Some(enquirer);
                 let payment = &route.get(0).ok_or(PaymentError::RouteNotFound)?.payload;
+                if let Err(err) = self.validate_htlc(payment.amt_to_forward, true) {
+                    warn!("Refusing to add HTLC to the channel: {}", err);
+                    let failure = RpcError::from(&err);
+                    self.fail_workflow(endpoints, failure);
+                    return Ok(());
+                }
                 let message = self.state.channel.compose_add_update_htlc(
                     payment.amt_to_forward,
                     hash_lock,
@@ -257,6 +831,7 @@ impl Runtime {
                     route,
                 )?;
                 self.send_p2p(endpoints, message)?;
+                self.track_htlc_deadline(endpoints)?;
                 // TODO: Report progress here, wait for new commitment to be signed before reporting
                 //       success. Do not clear enquirer
                 let _ = self.report_success(endpoints, Some("HTLC added to the channel"));
@@ -271,6 +846,75 @@ impl Runtime {
         Ok(())
     }
 
+    fn handle_timer(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let now = SystemTime::now();
+        if !matches!(self.last_heartbeat, Some(time) if time + HEARTBEAT_PERIOD > now) {
+            self.last_heartbeat = Some(now);
+            self.send_heartbeat(endpoints)?;
+        }
+        if !matches!(self.fee_requested, Some(time) if time + FEE_ESTIMATE_PERIOD > now) {
+            self.fee_requested = Some(now);
+            let message = CtlMsg::EstimateFee { confirmation_target: None };
+            self.send_ctl(endpoints, ServiceId::Watch, message)?;
+        }
+        match self.deadline {
+            Some(deadline) if deadline <= SystemTime::now() => {
+                self.deadline = None;
+                self.process(endpoints, ServiceId::Loopback, BusMsg::Ctl(CtlMsg::Timeout))?;
+            }
+            _ if self.zero_conf_expired() => {
+                self.process(endpoints, ServiceId::Loopback, BusMsg::Ctl(CtlMsg::Timeout))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Registers fee estimation reported by watchd. The channel funder updates the commitment
+    /// feerate once it deviates from the estimation by more than [`FEERATE_UPDATE_THRESHOLD`]
+    /// percents.
+    fn apply_fee_estimate(
+        &mut self,
+        endpoints: &mut Endpoints,
+        feerate_per_kw: u32,
+    ) -> Result<(), Error> {
+        self.fee_estimate = Some(feerate_per_kw);
+        let current = self.state.channel_snapshot().common_params.feerate_per_kw;
+        let difference = current.max(feerate_per_kw) - current.min(feerate_per_kw);
+        let deviation = difference as u64 * 100 / current.max(1) as u64;
+        let channel_id = match self.state.channel.active_channel_id().channel_id() {
+            Some(channel_id) => channel_id,
+            None => return Ok(()),
+        };
+        if !self.state.is_funder
+            || self.peer_busy
+            || self.peer_disconnected
+            || deviation < FEERATE_UPDATE_THRESHOLD
+            || !matches!(self.state.state_machine, ChannelStateMachine::Active)
+        {
+            return Ok(());
+        }
+        debug!("Commitment feerate {} sat/kw deviates from the fee estimation", current);
+        let message = CtlMsg::SetChannelFeerate { channel_id, feerate_per_kw };
+        self.process(endpoints, ServiceId::Watch, BusMsg::Ctl(message))?;
+        Ok(())
+    }
+
+    /// Reports channel activity to lnpd, which reaps daemons of the stale channel negotiations
+    /// not reached the funding stage, i.e. those still lacking permanent channel id
+    fn send_heartbeat(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let channel_id = self.state.channel.active_channel_id();
+        let last_activity = self
+            .last_activity
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+            .as_secs();
+        let funding_started = channel_id.channel_id().is_some();
+        let message = CtlMsg::ChannelHeartbeat { channel_id, last_activity, funding_started };
+        self.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+        Ok(())
+    }
+
     fn handle_rpc(
         &mut self,
         endpoints: &mut Endpoints,
@@ -279,10 +923,15 @@ impl Runtime {
     ) -> Result<(), Error> {
         match request {
             RpcMsg::GetInfo => {
-                let mut state = bolt::ChannelState::dumb_default();
-                self.state.channel.store_state(&mut state);
-                let channel_info =
-                    ChannelInfo { state, remote_peer: self.state.remote_peer.clone() };
+                let channel_info = ChannelInfo {
+                    state: self.state.channel_snapshot(),
+                    remote_peer: self.state.remote_peer.clone(),
+                    closing: matches!(self.state.state_machine, ChannelStateMachine::Closing(_)),
+                    minimum_depth: self.state.minimum_depth,
+                    tower_pending: self.tower_pending.len() as u32,
+                    tower_error: self.tower_error.clone(),
+                    fee_policy: self.fee_policy,
+                };
                 self.send_rpc(endpoints, client_id, channel_info)?;
             }
             RpcMsg::Send(_) => todo!("payments are not yet implemented"),
@@ -295,10 +944,92 @@ impl Runtime {
         Ok(())
     }
 
+    /// Reports successful completion of the current workflow to the client which has initiated
+    /// it and releases the client, such that further reports from other workflows do not reach
+    /// it.
+    pub(super) fn complete_workflow(&mut self, endpoints: &mut Endpoints, msg: impl ToString) {
+        // Ignoring possible reporting errors: do not want to halt the channel just because the
+        // client disconnected
+        let _ = self.report_success(endpoints, Some(msg));
+        self.enquirer = None;
+    }
+
+    /// Reports failure of the current workflow to the client which has initiated it and releases
+    /// the client, such that further reports from other workflows do not reach it.
+    pub(super) fn fail_workflow(&mut self, endpoints: &mut Endpoints, failure: RpcError) {
+        // The returned error is used only for terminating daemons, which is not the case here
+        let _ = self.report_failure(endpoints, failure);
+        self.enquirer = None;
+    }
+
+    /// Composes brief channel information for the channel listing
+    fn channel_summary(&self) -> ChannelSummary {
+        let snapshot = self.state.channel_snapshot();
+        let active_channel_id = self.state.channel.active_channel_id();
+        let funding = self.state.channel.funding();
+        let funding_outpoint = active_channel_id
+            .channel_id()
+            .map(|_| OutPoint::new(funding.txid(), funding.output() as u32));
+        let remote_node = match self.state.remote_peer {
+            Some(NodeAddr::Remote(ref remote_addr)) => Some(remote_addr.node_id),
+            _ => None,
+        };
+        ChannelSummary {
+            channel_id: ChannelId::from_inner(active_channel_id.as_slice32()),
+            remote_node,
+            lifecycle: self.state.state_machine.lifecycle().to_string(),
+            capacity_sat: funding.amount(),
+            local_balance_msat: snapshot.local_amount_msat,
+            remote_balance_msat: snapshot.remote_amount_msat,
+            pending_htlcs: (snapshot.offered_htlcs.len() + snapshot.received_htlcs.len()) as u32,
+            funding_outpoint,
+            confirmations: self.funding_depth,
+            minimum_depth: self.state.minimum_depth,
+        }
+    }
+
+    /// Exports channel state for the migration to another node
+    fn export_state(&self) -> Result<Vec<u8>, channeld::Error> {
+        let channel_id = self.state.channel.active_channel_id().channel_id().ok_or(
+            channeld::Error::InvalidState {
+                operation: "export channel without permanent channel id",
+                current_state: self.state.state_machine.lifecycle(),
+            },
+        )?;
+        let export = ChannelExport {
+            channel_id,
+            remote_peer: self.state.remote_peer.clone().expect("channel must have remote peer"),
+            commitment_number: self.state.channel_snapshot().commitment_number,
+            state: channeld::wrap_state(&self.state.strict_serialize()?),
+        };
+        Ok(export.serialize()?)
+    }
+
+    /// Replaces channel state with the one exported from another node. Returns the commitment
+    /// number of the imported state.
+    ///
+    /// Refuses to import a state older than the current one, since publishing an outdated
+    /// commitment transaction allows the remote peer to claim all channel funds.
+    fn import_state(&mut self, data: &[u8]) -> Result<u64, channeld::Error> {
+        let export = ChannelExport::deserialize(data)?;
+        let state = ChannelState::strict_deserialize(channeld::unwrap_state(&export.state)?)?;
+        let current = self.state.channel_snapshot().commitment_number;
+        let imported = state.channel_snapshot().commitment_number;
+        if imported < current {
+            return Err(channeld::Error::StaleImport { imported, current });
+        }
+        self.state = state;
+        self.save_state()?;
+        info!("Channel state at commitment {} is imported", imported);
+        Ok(imported)
+    }
+
     // TODO: Use storage drivers
     pub fn save_state(&mut self) -> Result<(), strict_encoding::Error> {
+        let data = channeld::wrap_state(&self.state.strict_serialize()?);
         self.file.seek(io::SeekFrom::Start(0))?;
-        self.state.strict_encode(&self.file)?;
+        self.file.set_len(0)?;
+        self.file.write_all(&data)?;
         self.file.sync_all().map_err(strict_encoding::Error::from)?;
         Ok(())
     }
@@ -0,0 +1,270 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Compact storage of the per-commitment secrets received from the remote peer, as defined by
+//! BOLT-3 "Efficient Per-commitment Secret Storage".
+//!
+//! Per-commitment secrets are generated from a seed such that the secret with 48-bit index `I`
+//! allows to derive all secrets with indexes sharing the bits of `I` above its lowest set bit and
+//! having any bits below. Secrets are revealed starting from index 2^48 - 1 downwards, so at most
+//! 49 secrets must be kept to derive all the secrets revealed so far: the secret is stored in the
+//! bucket numbered by the count of trailing zero bits of its index, replacing the secret which
+//! it can derive.
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::SecretKey;
+
+/// Index of the per-commitment secret for the first commitment transaction
+pub const MAX_INDEX: u64 = (1 << 48) - 1;
+
+/// Number of the buckets used by the storage
+const BUCKET_COUNT: usize = 49;
+
+/// Errors inserting per-commitment secrets into the storage, which indicate misbehavior of the
+/// remote peer
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ShachainError {
+    /// per-commitment secret has index {index}, while the next expected index is {expected}
+    UnexpectedIndex { index: u64, expected: u64 },
+
+    /// per-commitment secret #{index} does not derive the previously received secret #{known}
+    Inconsistent { index: u64, known: u64 },
+}
+
+/// Per-commitment secret kept in the storage, with its index
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+struct Bucket {
+    index: u64,
+    secret: Slice32,
+}
+
+/// Compact storage of the per-commitment secrets received from the remote peer
+#[derive(Clone, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode)]
+pub struct Shachain {
+    /// Number of the secrets received so far
+    received: u64,
+
+    /// Secrets able to derive all received secrets, where the secret at position `N` has index
+    /// with `N` trailing zero bits
+    buckets: Vec<Bucket>,
+}
+
+impl Shachain {
+    /// Number of the per-commitment secrets received so far
+    pub fn received(&self) -> u64 { self.received }
+
+    /// Index of the next per-commitment secret which may be inserted
+    pub fn next_index(&self) -> u64 { MAX_INDEX.saturating_sub(self.received) }
+
+    /// Inserts the next per-commitment secret, checking that it derives all previously received
+    /// secrets it replaces in the storage
+    pub fn insert(&mut self, index: u64, secret: [u8; 32]) -> Result<(), ShachainError> {
+        let expected = self.next_index();
+        if index != expected || self.received > MAX_INDEX {
+            return Err(ShachainError::UnexpectedIndex { index, expected });
+        }
+        let position = bucket_position(index);
+        for bucket in &self.buckets[..position.min(self.buckets.len())] {
+            if derive(secret, position, bucket.index) != bucket.secret.to_inner() {
+                return Err(ShachainError::Inconsistent { index, known: bucket.index });
+            }
+        }
+        let bucket = Bucket { index, secret: Slice32::from_inner(secret) };
+        if position < self.buckets.len() {
+            self.buckets[position] = bucket;
+        } else {
+            self.buckets.push(bucket);
+        }
+        self.received += 1;
+        Ok(())
+    }
+
+    /// Derives previously received per-commitment secret with the given index
+    pub fn secret(&self, index: u64) -> Option<[u8; 32]> {
+        if index > MAX_INDEX || MAX_INDEX - index >= self.received {
+            return None;
+        }
+        self.buckets.iter().enumerate().find_map(|(position, bucket)| {
+            let mask = !((1u64 << position) - 1);
+            if index & mask == bucket.index {
+                Some(derive(bucket.secret.to_inner(), position, index))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Inserts per-commitment secret for the given commitment number
+    pub fn insert_commitment_secret(
+        &mut self,
+        commitment_number: u64,
+        secret: &SecretKey,
+    ) -> Result<(), ShachainError> {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&secret[..]);
+        self.insert(commitment_index(commitment_number), bytes)
+    }
+
+    /// Derives per-commitment secret for the given commitment number, if it was received
+    pub fn commitment_secret(&self, commitment_number: u64) -> Option<SecretKey> {
+        let secret = self.secret(commitment_index(commitment_number))?;
+        SecretKey::from_slice(&secret).ok()
+    }
+}
+
+/// Generates per-commitment secret with the given index from the seed, according to BOLT-3
+/// `generate_from_seed`
+pub fn generate_from_seed(seed: [u8; 32], index: u64) -> [u8; 32] { derive(seed, 48, index) }
+
+/// Index of the per-commitment secret for the commitment with the given number
+pub fn commitment_index(commitment_number: u64) -> u64 { MAX_INDEX - commitment_number }
+
+/// Position of the bucket keeping the secret with the given index, which is the number of
+/// trailing zero bits of the index
+fn bucket_position(index: u64) -> usize { (index.trailing_zeros() as usize).min(BUCKET_COUNT - 1) }
+
+/// Derives the secret with the given index from the secret which has `bits` lowest bits of its
+/// index cleared, according to BOLT-3 `generate_from_seed`
+fn derive(base: [u8; 32], bits: usize, index: u64) -> [u8; 32] {
+    let mut secret = base;
+    for bit in (0..bits).rev() {
+        if index & (1 << bit) != 0 {
+            secret[bit / 8] ^= 1 << (bit % 8);
+            secret = sha256::Hash::hash(&secret).into_inner();
+        }
+    }
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    fn secret(hex: &str) -> [u8; 32] {
+        let mut secret = [0u8; 32];
+        secret.copy_from_slice(&Vec::<u8>::from_hex(hex).unwrap());
+        secret
+    }
+
+    /// Correct sequence of the secrets from BOLT-3 `insert_secret` tests, generated from the seed
+    /// of all 0xFF bytes
+    const CORRECT: [&str; 8] = [
+        "7cc854b54e3e0dcdb010d7a3fee464a9687be6e8db3be6854c475621e007a5dc",
+        "c7518c8ae4660ed02894df8976fa1a3659c1a8b4b5bec0c4b872abeba4cb8964",
+        "2273e227a5b7449b6e70f1fb4652864038b1cbf9cd7c043a7d6456b7fc275ad8",
+        "27cddaa5624534cb6cb9d7da077cf2b22ab21e9b506fd4998a51d54502e99116",
+        "c65716add7aa98ba7acb236352d665cab17345fe45b55fb879ff80e6bd0c41dd",
+        "969660042a28f32d9be17344e09374b379962d03db1574df5a8a5a47e19ce3f2",
+        "a5a64476122ca0925fb344bdc1854c1c0a59fc614298e50a33e331980a220f32",
+        "05cde6323d949933f7f7b78776bcc1ea6d9b31447732e3802e1f7ac44b650e17",
+    ];
+
+    /// Inserts the secrets starting from [`MAX_INDEX`], returning the index of the first secret
+    /// which was rejected
+    fn insert_all(secrets: &[&str]) -> Option<u64> {
+        let mut shachain = Shachain::default();
+        for (no, hex) in secrets.iter().enumerate() {
+            let index = MAX_INDEX - no as u64;
+            match shachain.insert(index, secret(hex)) {
+                Ok(()) => {}
+                Err(ShachainError::Inconsistent { index: failed, .. }) if failed == index => {
+                    return Some(index)
+                }
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn generate_from_seed_vectors() {
+        // generate_from_seed 0 final node
+        let expected = "02a40c85b6f28da08dfdbe0926c53fab2de6d28c10301f8f7c4073d5e42e3148";
+        assert_eq!(generate_from_seed([0x00; 32], MAX_INDEX), secret(expected));
+        // generate_from_seed FF final node
+        let expected = "7cc854b54e3e0dcdb010d7a3fee464a9687be6e8db3be6854c475621e007a5dc";
+        assert_eq!(generate_from_seed([0xFF; 32], MAX_INDEX), secret(expected));
+        // generate_from_seed FF alternate bits 1
+        let expected = "56f4008fb007ca9acf0e15b054d5c9fd12ee06cea347914ddbaed70d1c13a528";
+        assert_eq!(generate_from_seed([0xFF; 32], 0xaaaaaaaaaaa), secret(expected));
+        // generate_from_seed FF alternate bits 2
+        let expected = "9015daaeb06dba4ccc05b91b2f73bd54405f2be9f217fbacd3c5ac2e62327d31";
+        assert_eq!(generate_from_seed([0xFF; 32], 0x555555555555), secret(expected));
+        // generate_from_seed 01 last nontrivial node
+        let expected = "915c75942a26bb3a433a8ce2cb0427c29ec6c1775cfc78328b57f6ba7bfeaa9c";
+        assert_eq!(generate_from_seed([0x01; 32], 1), secret(expected));
+    }
+
+    #[test]
+    fn insert_secret_correct_sequence() {
+        let mut shachain = Shachain::default();
+        for (no, hex) in CORRECT.iter().enumerate() {
+            shachain.insert(MAX_INDEX - no as u64, secret(hex)).unwrap();
+        }
+        assert_eq!(shachain.received(), 8);
+        assert_eq!(shachain.next_index(), MAX_INDEX - 8);
+        for (no, hex) in CORRECT.iter().enumerate() {
+            assert_eq!(shachain.secret(MAX_INDEX - no as u64), Some(secret(hex)));
+        }
+        assert_eq!(shachain.secret(MAX_INDEX - 8), None);
+    }
+
+    #[test]
+    fn insert_secret_incorrect() {
+        // Secrets generated from the zero seed, which can't derive the correct ones
+        let wrong = [
+            "02a40c85b6f28da08dfdbe0926c53fab2de6d28c10301f8f7c4073d5e42e3148",
+            "dddc3a8d14fddf2b68fa8c7fbad2748274937479dd0f8930d5ebb4ab6bd866a3",
+            "c51a18b13e8527e579ec56365482c62f180b7d5760b46e9477dae59e87ed423a",
+            "ba65d7b0ef55a3ba300d4e87af29868f394f8f138d78a7011669c79b37b936f4",
+            "631373ad5f9ef654bb3dade742d09504c567edd24320d2fcd68e3cc47e2ff6a6",
+            "b7e76a83668bde38b373970155c868a653304308f9896692f904a23731224bb1",
+            "e7971de736e01da8ed58b94c2fc216cb1dca9e326f3a96e7194fe8ea8af6c0a3",
+            "a7efbc61aac46d34f77778bac22c8a20c6a46ca460addc49009bda875ec88fa4",
+        ];
+        let c = CORRECT;
+        // insert_secret #1 incorrect
+        assert_eq!(insert_all(&[wrong[0], c[1]]), Some(MAX_INDEX - 1));
+        // insert_secret #2 incorrect (#1 derived from incorrect)
+        assert_eq!(insert_all(&[wrong[0], wrong[1], wrong[2], c[3]]), Some(MAX_INDEX - 3));
+        // insert_secret #3 incorrect
+        assert_eq!(insert_all(&[c[0], c[1], wrong[2], c[3]]), Some(MAX_INDEX - 3));
+        // insert_secret #4 incorrect (1,2,3 derived from incorrect)
+        let secrets = [wrong[0], wrong[1], wrong[2], wrong[3], c[4], c[5], c[6], c[7]];
+        assert_eq!(insert_all(&secrets), Some(MAX_INDEX - 7));
+        // insert_secret #5 incorrect
+        assert_eq!(insert_all(&[c[0], c[1], c[2], c[3], wrong[4], c[5]]), Some(MAX_INDEX - 5));
+        // insert_secret #6 incorrect (5 derived from incorrect)
+        let secrets = [c[0], c[1], c[2], c[3], wrong[4], wrong[5], c[6], c[7]];
+        assert_eq!(insert_all(&secrets), Some(MAX_INDEX - 7));
+        // insert_secret #7 incorrect
+        let secrets = [c[0], c[1], c[2], c[3], c[4], c[5], wrong[6], c[7]];
+        assert_eq!(insert_all(&secrets), Some(MAX_INDEX - 7));
+        // insert_secret #8 incorrect
+        let secrets = [c[0], c[1], c[2], c[3], c[4], c[5], c[6], wrong[7]];
+        assert_eq!(insert_all(&secrets), Some(MAX_INDEX - 7));
+    }
+
+    #[test]
+    fn insert_secret_unexpected_index() {
+        let mut shachain = Shachain::default();
+        let err = shachain.insert(MAX_INDEX - 1, secret(CORRECT[1])).unwrap_err();
+        let expected = ShachainError::UnexpectedIndex { index: MAX_INDEX - 1, expected: MAX_INDEX };
+        assert_eq!(err, expected);
+    }
+}
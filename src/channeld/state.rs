@@ -12,16 +12,32 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::BTreeMap;
+
 use amplify::{DumbDefault, Slice32};
 use bitcoin::hashes::Hash;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Signature};
+use bitcoin::Txid;
 use internet2::NodeAddr;
-use lnp::channel::bolt::{BoltExt, CommonParams, LocalKeyset, PeerParams, Policy};
-use lnp::p2p::legacy::TempChannelId;
-use lnp::Channel;
+use lnp::channel::bolt::{self, BoltExt, CommonParams, LocalKeyset, PeerParams, Policy};
+use lnp::p2p::legacy::{
+    CommitmentSigned, FundingLocked, Messages as LnMsg, RevokeAndAck, TempChannelId,
+};
+use lnp::{Channel, Extension};
 use lnpbp::chain::Chain;
+use psbt::Psbt;
+use strict_encoding::{StrictDecode, StrictEncode};
+use wallet::hlc::{HashLock, HashPreimage};
+use wallet::scripts::PubkeyScript;
 
+use super::automata::abort::SweepSession;
+use super::automata::accept::ChannelAccept;
+use super::automata::close::ClosingSession;
+use super::automata::htlc::HtlcResolution;
+use super::automata::propose::ChannelPropose;
 use super::automata::ChannelStateMachine;
+use super::shachain::Shachain;
+use crate::bus::FundingSource;
 
 /// State of the channel runtime which can persists and which evolution is automated with
 /// different state machines.
@@ -36,20 +52,200 @@ pub(super) struct ChannelState {
     /// Runtime-specific (but persistable) part of the channel state: remote peer which is a
     /// counterparty of this channel.
     pub remote_peer: Option<NodeAddr>,
+
+    /// Number of confirmations for the funding transaction required by the channel fundee before
+    /// the channel can be used, as it was negotiated with `accept_channel` message. Zero for
+    /// zero-conf channels, which are used before the funding transaction gets mined.
+    pub minimum_depth: u32,
+
+    /// Indicates that the channel is used without waiting for the funding transaction
+    /// confirmation, as it was requested by the user (for the funder) or allowed for the trusted
+    /// remote peer (for the fundee)
+    pub zero_conf: bool,
+
+    /// Time (as UNIX timestamp in seconds) by which the funding transaction of a zero-conf
+    /// channel must get confirmed; `None` if the channel is not zero-conf or the funding is
+    /// already confirmed.
+    pub zero_conf_deadline: Option<u64>,
+
+    /// Height of the block containing funding transaction, as it was reported by the on-chain
+    /// tracking service the last time
+    pub funding_height: Option<u32>,
+
+    /// Blockchain height by which the funding transaction reorged out of the blockchain must
+    /// get mined again; `None` if there was no reorg or the funding is mined again.
+    pub reorg_deadline: Option<u32>,
+
+    /// `funding_locked` message which was received from the remote peer before the funding
+    /// transaction confirmation was reported by the on-chain tracking service. It is buffered
+    /// here until we get our own confirmation.
+    pub remote_funding_locked: Option<FundingLocked>,
+
+    /// Indicates whether the channel was proposed and funded by the local node
+    pub is_funder: bool,
+
+    /// Data for the cooperative channel closing negotiations, if they are in progress
+    pub closing: Option<ClosingSession>,
+
+    /// Remote peer signature for our latest commitment transaction, required to close the
+    /// channel unilaterally
+    pub remote_commitment_sig: Option<Signature>,
+
+    /// Remote peer signatures for the second-stage HTLC transactions of our latest commitment
+    /// transaction, ordered by the HTLC output index in the commitment transaction
+    pub remote_htlc_sigs: Vec<Signature>,
+
+    /// Payment preimages known for the HTLCs received by us, which allow us to claim these HTLCs
+    /// on-chain
+    pub htlc_preimages: BTreeMap<HashLock, HashPreimage>,
+
+    /// Id of our commitment transaction published during unilateral channel close
+    pub commitment_txid: Option<Txid>,
+
+    /// Data for sweeping to-local output of our commitment transaction published during
+    /// unilateral channel close
+    pub sweep: Option<SweepSession>,
+
+    /// Data on the on-chain resolution of HTLCs pending in our commitment transaction published
+    /// during unilateral channel close
+    pub htlcs: Option<HtlcResolution>,
+
+    /// The last `commitment_signed` message sent to the remote peer, kept for retransmission
+    /// during channel reestablishment
+    pub last_commitment_signed: Option<CommitmentSigned>,
+
+    /// The last `revoke_and_ack` message sent to the remote peer, kept for retransmission during
+    /// channel reestablishment
+    pub last_revoke_and_ack: Option<RevokeAndAck>,
+
+    /// Remote commitment transactions signed by us which were not revoked by the remote peer
+    /// yet, ordered from the oldest to the latest one
+    pub remote_commitments: Vec<Psbt>,
+
+    /// Remote commitment transactions revoked by the remote peer, together with their commitment
+    /// numbers
+    pub revoked_commitments: BTreeMap<Txid, RevokedCommitment>,
+
+    /// Per-commitment secrets received from the remote peer in compact storage, which allow us to
+    /// claim all funds of the revoked remote commitment transactions if the remote peer
+    /// publishes any of them
+    pub revocation_secrets: Shachain,
+
+    /// Id of the revoked remote commitment transaction published by the remote peer
+    pub breach_txid: Option<Txid>,
+
+    /// Id of the penalty transaction published in response to the revoked remote commitment
+    pub penalty_txid: Option<Txid>,
+
+    /// Script which will receive our funds during cooperative channel closing, as it was
+    /// committed to the remote peer upfront with `option_upfront_shutdown_script`
+    pub local_shutdown_script: Option<PubkeyScript>,
+
+    /// Script which the remote peer has committed upfront to receive its funds during
+    /// cooperative channel closing
+    pub remote_shutdown_script: Option<PubkeyScript>,
+
+    /// Limit for `to_self_delay` the remote peer may require from us, overriding the node
+    /// configuration for this channel
+    pub max_to_self_delay: Option<u16>,
+
+    /// The last message sent to the remote peer, kept for retransmission when the remote peer
+    /// reconnects in the middle of the channel funding
+    pub last_p2p_message: Option<LnMsg>,
+
+    /// Wallet providing funds for the channel funding transaction, if we are the funder
+    pub funding_source: FundingSource,
+
+    /// Number of our current commitment transaction, which equals to the number of our
+    /// commitment transactions revoked with `revoke_and_ack` message
+    pub local_commitment_number: u64,
+
+    /// Per-commitment point of our commitment transaction following the current one, as it was
+    /// derived by signd, which is sent to the remote peer with `funding_locked` message
+    pub next_local_point: Option<PublicKey>,
+}
+
+/// Remote commitment transaction revoked by the remote peer
+#[derive(Clone, Debug, StrictEncode, StrictDecode)]
+pub(super) struct RevokedCommitment {
+    /// Revoked commitment transaction
+    pub psbt: Psbt,
+
+    /// Number of the revoked commitment transaction, for which the per-commitment secret is
+    /// derived from [`ChannelState::revocation_secrets`]
+    pub commitment_number: u64,
 }
 
 impl ChannelState {
     pub fn with(temp_channel_id: TempChannelId, chain: &Chain) -> ChannelState {
+        ChannelState {
+            state_machine: Default::default(),
+            channel: ChannelState::channel_with(
+                temp_channel_id,
+                chain,
+                Policy::default(),
+                CommonParams::default(),
+                PeerParams::default(),
+                LocalKeyset::dumb_default(), // we do not have keyset derived at this stage
+            ),
+            remote_peer: None,
+            minimum_depth: 0,
+            zero_conf: false,
+            zero_conf_deadline: None,
+            funding_height: None,
+            reorg_deadline: None,
+            remote_funding_locked: None,
+            is_funder: false,
+            closing: None,
+            remote_commitment_sig: None,
+            remote_htlc_sigs: empty!(),
+            htlc_preimages: empty!(),
+            commitment_txid: None,
+            sweep: None,
+            htlcs: None,
+            last_commitment_signed: None,
+            last_revoke_and_ack: None,
+            remote_commitments: empty!(),
+            revoked_commitments: empty!(),
+            revocation_secrets: empty!(),
+            breach_txid: None,
+            penalty_txid: None,
+            local_shutdown_script: None,
+            remote_shutdown_script: None,
+            max_to_self_delay: None,
+            last_p2p_message: None,
+            funding_source: FundingSource::Internal,
+            local_commitment_number: 0,
+            next_local_point: None,
+        }
+    }
+
+    /// Constructs channel with a given policy, parameters and local keyset, which is used when
+    /// these data become known only after the channel daemon was launched.
+    pub fn channel_with(
+        temp_channel_id: TempChannelId,
+        chain: &Chain,
+        policy: Policy,
+        common_params: CommonParams,
+        local_params: PeerParams,
+        local_keys: LocalKeyset,
+    ) -> Channel<BoltExt> {
         let chain_hash = chain.as_genesis_hash().as_inner();
-        let channel = Channel::with(
+        Channel::with(
             temp_channel_id,
             Slice32::from(chain_hash),
-            Policy::default(),
-            CommonParams::default(),
-            PeerParams::default(),
-            LocalKeyset::dumb_default(), // we do not have keyset derived at this stage
-        );
-        ChannelState { state_machine: Default::default(), channel, remote_peer: None }
+            policy,
+            common_params,
+            local_params,
+            local_keys,
+        )
+    }
+
+    /// Returns snapshot of the standard part of the channel state
+    pub fn channel_snapshot(&self) -> bolt::ChannelState {
+        let mut state = bolt::ChannelState::dumb_default();
+        self.channel.store_state(&mut state);
+        state
     }
 
     pub fn remote_id(&self) -> PublicKey {
@@ -60,3 +256,58 @@ impl ChannelState {
         }
     }
 }
+
+/// Channel state machine as it was persisted in version 1. Channel proposal and acceptance
+/// workflows keep the encoding of their variants, since new variants were appended to them.
+#[derive(Debug, StrictDecode)]
+enum StateMachineV1 {
+    Launch,
+    Propose(ChannelPropose),
+    Accept(ChannelAccept),
+    Active,
+    Reestablishing,
+    Closing,
+    Abort,
+    Penalize,
+}
+
+/// Channel state as it was persisted in version 1, before the versioned container was
+/// introduced
+#[derive(StrictDecode)]
+struct ChannelStateV1 {
+    state_machine: StateMachineV1,
+    channel: Channel<BoltExt>,
+    remote_peer: Option<NodeAddr>,
+}
+
+/// Upgrades channel state of version 1 to version 2. Version 1 had workflows only for the channel
+/// proposal and acceptance, so only these and the active channels are upgraded; the other states
+/// were never persisted.
+pub(super) fn upgrade_v1(data: &[u8]) -> Result<Vec<u8>, strict_encoding::Error> {
+    let ChannelStateV1 { state_machine, channel, remote_peer } =
+        ChannelStateV1::strict_deserialize(data)?;
+    let state_machine = match state_machine {
+        StateMachineV1::Launch => ChannelStateMachine::Launch,
+        StateMachineV1::Propose(propose) => ChannelStateMachine::Propose(propose),
+        StateMachineV1::Accept(accept) => ChannelStateMachine::Accept(accept),
+        StateMachineV1::Active => ChannelStateMachine::Active,
+        state_machine => {
+            let msg = format!("channel in {:?} state can't be upgraded", state_machine);
+            return Err(strict_encoding::Error::DataIntegrityError(msg));
+        }
+    };
+    let state = ChannelState {
+        // Only the channel proposal workflow of version 1 was able to activate the channel
+        is_funder: matches!(
+            state_machine,
+            ChannelStateMachine::Propose(_) | ChannelStateMachine::Active
+        ),
+        // Version 1 locked the channel once the funding transaction got mined
+        minimum_depth: 1,
+        state_machine,
+        channel,
+        remote_peer,
+        ..none!()
+    };
+    state.strict_serialize()
+}
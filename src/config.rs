@@ -14,17 +14,23 @@
 
 #![allow(clippy::needless_borrow)] // due to a bug in `display(Debug)`
 
+use std::fmt::{self, Debug, Formatter};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
-use internet2::ZmqSocketAddr;
-use lnp::p2p::legacy::ActiveChannelId;
+use bitcoin::secp256k1::PublicKey;
+use internet2::{RemoteNodeAddr, ZmqSocketAddr};
+use lnp::p2p::legacy::{ActiveChannelId, ChannelId};
 use lnpbp::chain::Chain;
 
 #[cfg(feature = "server")]
 use crate::opts::Opts;
-use crate::opts::{LNP_NODE_CTL_SOCKET, LNP_NODE_MSG_SOCKET};
+use crate::opts::{
+    LNP_NODE_CHANNEL_BACKUP, LNP_NODE_CTL_SOCKET, LNP_NODE_HWI, LNP_NODE_MSG_SOCKET,
+    LNP_NODE_TOR_PROXY,
+};
 
 /// Final configuration resulting from data contained in config file environment
 /// variables and command-line options. For security reasons node key is kept
@@ -47,11 +53,408 @@ pub struct Config {
     /// ZMQ socket for daemon RCP interface
     pub rpc_endpoint: ZmqSocketAddr,
 
+    /// ZMQ socket publishing node events to the subscribed clients
+    pub events_endpoint: ZmqSocketAddr,
+
     /// URL for the electrum server connection
     pub electrum_url: String,
 
+    /// URLs of the fallback electrum servers, tried in order when the main server is unreachable
+    pub electrum_fallback_urls: Vec<String>,
+
+    /// Blockchain backend used for tracking transactions, broadcasting them and fee estimation
+    pub chain_backend: ChainBackend,
+
+    /// Limits and confirmation target of the fee estimation
+    pub fees: FeeSettings,
+
     /// Indicates whether deamons should be spawned as threads (true) or as child processes (false)
     pub threaded: bool,
+
+    /// Timeouts for the channel proposal workflow stages
+    pub propose_timeouts: ProposeTimeouts,
+
+    /// Bounds for channel parameters requested by remote peers
+    pub peer_bounds: PeerBounds,
+
+    /// Indicates whether the node supports channels with funding above 2^24-1 satoshis
+    /// (`option_support_large_channel`)
+    pub wumbo: bool,
+
+    /// Trusted remote peers, which channels are accepted without waiting for the funding
+    /// transaction confirmation
+    pub zero_conf_peers: Vec<PublicKey>,
+
+    /// Time within which funding transaction of a zero-conf channel must get confirmed
+    pub zero_conf_timeout: Duration,
+
+    /// Number of blocks after the original funding transaction height within which funding
+    /// transaction reorged out of the blockchain must get mined again
+    pub funding_reorg_timeout: u32,
+
+    /// Number of blocks before the expiry of the earliest pending HTLC at which the channel is
+    /// force-closed
+    pub htlc_deadline_margin: u32,
+
+    /// Time without any activity after which a channel negotiation which has not reached the
+    /// funding stage is considered stale and gets reaped
+    pub channel_idle_timeout: Duration,
+
+    /// Maximal interval between attempts to reconnect the remote peers which have channels with
+    /// the node or are pinned
+    pub reconnect_max_interval: Duration,
+
+    /// Detection of dead connections with the remote peers
+    pub keepalive: Keepalive,
+
+    /// Time limits for establishing connections with the remote peers
+    pub handshake_timeouts: HandshakeTimeouts,
+
+    /// Limits of the incoming connections accepted by the peer listeners
+    pub inbound_limits: InboundLimits,
+
+    /// Time for which the remote peers violating the protocol are banned
+    pub ban_duration: Duration,
+
+    /// SOCKS5 proxy of Tor used for connecting remote peers
+    pub tor_proxy: Option<TorProxy>,
+
+    /// Policy for accepting channels proposed by remote peers
+    pub accept_policy: AcceptPolicy,
+
+    /// Watchtowers which justice data for the revoked remote commitment transactions are
+    /// uploaded to
+    pub towers: Vec<RemoteNodeAddr>,
+
+    /// Remote peers the network graph is synced from with gossip queries
+    pub gossip_sync_peers: Vec<PublicKey>,
+
+    /// Signer holding the channel keys
+    pub signer: SignerMode,
+
+    /// Hardware wallet signing the funding wallet inputs, if the funding wallet keys are kept on
+    /// a hardware device
+    pub hardware_wallet: Option<HardwareWallet>,
+
+    /// Funding wallet is watch-only, and its transactions are signed by the operator outside of
+    /// the node
+    pub watch_only: bool,
+
+    /// File with the passphrase unlocking the encrypted master key on signd start
+    pub unlock_file: Option<PathBuf>,
+
+    /// File with the static backup of all node channels maintained by lnpd
+    pub backup_file: PathBuf,
+
+    /// Amount of satoshis above which withdrawals are signed by signd only with a confirmation
+    /// token
+    pub withdrawal_limit: Option<u64>,
+
+    /// Channel basepoints and revocation secrets may be exported on the admin client request
+    pub unsafe_export: bool,
+}
+
+/// Time limits for the stages of the channel proposal workflow during which channeld awaits
+/// a response from some other party. Once a limit is reached, the channel proposal is abandoned.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ProposeTimeouts {
+    /// Time to wait for the remote peer to reply with `accept_channel` to our `open_channel`
+    pub proposed: Duration,
+
+    /// Time to wait for the funding transaction to be constructed by the funding wallet
+    pub accepted: Duration,
+
+    /// Time to wait for the refund transaction to be signed
+    pub signing: Duration,
+}
+
+/// Parameters of pinging the remote peers, which detects connections silently dropped by the
+/// network (for instance, by NATs)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Keepalive {
+    /// Time without any messages from the remote peer after which the peer is pinged
+    pub ping_interval: Duration,
+
+    /// Time to wait for the remote peer to reply to a ping with `pong`
+    pub pong_timeout: Duration,
+
+    /// Number of consecutive missed pings after which the connection is closed
+    pub max_missed_pongs: u8,
+}
+
+/// Time limits for establishing connections with the remote peers. Connections exceeding them
+/// are closed, such that stalled peers do not occupy the node resources.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HandshakeTimeouts {
+    /// Time to complete BOLT-8 handshake after the TCP connection is established
+    pub handshake: Duration,
+
+    /// Time to receive `init` message from the remote peer after the handshake is completed
+    pub init: Duration,
+}
+
+/// Limits protecting the node from being flooded with incoming connections
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InboundLimits {
+    /// Maximal number of concurrent incoming connections at each listening socket
+    pub max_peers: u16,
+
+    /// Number of connections per minute accepted from a single IP address (or IPv6 /64 network)
+    pub rate: u16,
+
+    /// Number of connections a single IP address may open in a quick succession before being
+    /// limited by the rate
+    pub burst: u16,
+}
+
+/// Tor proxy used for the outbound connections with the remote peers
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TorProxy {
+    /// SOCKS5 proxy address
+    pub address: SocketAddr,
+
+    /// Whether all remote peers are connected through the proxy, and not only those at onion
+    /// addresses
+    pub always: bool,
+}
+
+/// Signer holding the channel keys, which signd uses for signing
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum SignerMode {
+    /// Keys are derived from the master key kept in the node data directory
+    #[display("local")]
+    Local,
+
+    /// Keys are kept by the remote signer at the given address, which signd relays the signing
+    /// requests to
+    #[display("remote:{0}")]
+    Remote(RemoteNodeAddr),
+}
+
+/// Blockchain backend used by watchd for tracking transactions and by lnpd for broadcasting
+/// transactions and fee estimation
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum ChainBackend {
+    /// Electrum server at [`Config::electrum_url`]
+    #[display("electrum")]
+    Electrum,
+
+    /// Bitcoin Core node accessed through its JSON-RPC interface
+    #[display("bitcoind")]
+    Bitcoind(BitcoindConfig),
+
+    /// Bitcoin peers serving BIP 157/158 compact block filters
+    #[display("neutrino")]
+    Neutrino(NeutrinoConfig),
+}
+
+/// Connection to the Bitcoin Core node
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BitcoindConfig {
+    /// JSON-RPC address
+    pub rpc_url: String,
+
+    /// Authentication of the JSON-RPC requests
+    pub rpc_auth: BitcoindAuth,
+
+    /// ZMQ socket publishing `rawblock` and `rawtx` notifications, if configured
+    pub zmq_endpoint: Option<String>,
+}
+
+/// Bitcoin peers serving compact block filters
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NeutrinoConfig {
+    /// Addresses of the peers in the order of preference
+    pub peers: Vec<String>,
+
+    /// Height below which the compact block filters are not matched, since the node has no
+    /// transactions there
+    pub birthday: u32,
+}
+
+/// Authentication of the Bitcoin Core JSON-RPC requests
+#[derive(Clone, PartialEq, Eq)]
+pub enum BitcoindAuth {
+    /// Requests are not authenticated
+    None,
+
+    /// Cookie file created by Bitcoin Core in its data directory
+    Cookie(PathBuf),
+
+    /// User and password from `rpcauth` or `rpcuser` and `rpcpassword` Bitcoin Core options
+    UserPass(String, String),
+}
+
+// Password is not printed, since the configuration gets logged
+impl Debug for BitcoindAuth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BitcoindAuth::None => f.write_str("None"),
+            BitcoindAuth::Cookie(path) => f.debug_tuple("Cookie").field(path).finish(),
+            BitcoindAuth::UserPass(user, _) => {
+                f.debug_tuple("UserPass").field(user).field(&"***").finish()
+            }
+        }
+    }
+}
+
+/// Hardware wallet accessed through HWI, which signs the funding wallet inputs
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HardwareWallet {
+    /// Path to `hwi` executable
+    pub hwi_path: PathBuf,
+
+    /// Time given to the user to confirm a transaction on the device, after which signing is
+    /// cancelled
+    pub timeout: Duration,
+}
+
+impl FromStr for SignerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "local" {
+            return Ok(SignerMode::Local);
+        }
+        match s.strip_prefix("remote:") {
+            Some(addr) => RemoteNodeAddr::from_str(addr)
+                .map(SignerMode::Remote)
+                .map_err(|err| format!("invalid remote signer address '{}': {}", addr, err)),
+            None => Err(format!(
+                "signer '{}' must be either `local` or `remote:<node_id>@<host>:<port>`",
+                s
+            )),
+        }
+    }
+}
+
+/// Bounds for the channel parameters which remote peer may require from us. Channel proposals
+/// and acceptances with parameters outside of these bounds are rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PeerBounds {
+    /// Maximal number of funding transaction confirmations before the channel may be used
+    pub max_minimum_depth: u32,
+
+    /// Maximal number of blocks we agree our to-local outputs to be timelocked for
+    pub max_to_self_delay: u16,
+
+    /// Maximal channel reserve we agree to keep, as a percentage of the channel funding amount
+    pub max_channel_reserve_percent: u8,
+
+    /// Minimal dust limit for the remote peer outputs, in satoshis
+    pub min_dust_limit_satoshis: u64,
+
+    /// Maximal dust limit for the remote peer outputs, in satoshis
+    pub max_dust_limit_satoshis: u64,
+
+    /// Maximal value for the smallest HTLC the remote peer agrees to accept, in millisatoshis
+    pub max_htlc_minimum_msat: u64,
+
+    /// Minimal number of HTLCs the remote peer must agree to accept
+    pub min_max_accepted_htlcs: u16,
+
+    /// Minimal commitment transaction feerate the remote peer may set, per kilo-weight unit
+    pub min_feerate_per_kw: u32,
+
+    /// Maximal commitment transaction feerate the remote peer may set, per kilo-weight unit
+    pub max_feerate_per_kw: u32,
+}
+
+impl Default for PeerBounds {
+    fn default() -> Self {
+        PeerBounds {
+            max_minimum_depth: 144,
+            max_to_self_delay: 2016,
+            max_channel_reserve_percent: 10,
+            // BOLT-3 dust limit for the most expensive standard output type
+            min_dust_limit_satoshis: 354,
+            max_dust_limit_satoshis: 10_000,
+            max_htlc_minimum_msat: 1_000_000,
+            min_max_accepted_htlcs: 5,
+            // Minimal relay fee of 1 sat/vbyte
+            min_feerate_per_kw: 253,
+            max_feerate_per_kw: 100_000,
+        }
+    }
+}
+
+/// Settings of the fee estimation made by watchd with the blockchain backend. The settings are
+/// taken from the node configuration and may be updated at runtime.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{floor_per_kw}..={ceiling_per_kw} sat/kw, {target_blocks} blocks")]
+pub struct FeeSettings {
+    /// Minimal estimated feerate, per kilo-weight unit
+    pub floor_per_kw: u32,
+
+    /// Maximal estimated feerate, per kilo-weight unit
+    pub ceiling_per_kw: u32,
+
+    /// Number of blocks within which the node transactions should get mined
+    pub target_blocks: u16,
+}
+
+/// Policy for accepting channels proposed by remote peers. Channel proposals violating the policy
+/// are rejected. The policy is set from the node configuration and may be updated at runtime.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, NetworkEncode, NetworkDecode)]
+pub struct AcceptPolicy {
+    /// Minimal channel funding amount, in satoshis
+    pub min_funding_sat: u64,
+
+    /// Maximal channel funding amount, in satoshis, if limited
+    pub max_funding_sat: Option<u64>,
+
+    /// Maximal amount the remote peer may push to us when opening the channel, in
+    /// millisatoshis, if limited
+    pub max_push_msat: Option<u64>,
+
+    /// Maximal share of the channel funding the remote peer may push to us when opening the
+    /// channel, in percents, if limited
+    pub max_push_percent: Option<u8>,
+
+    /// Number of funding transaction confirmations we require depending on the channel funding
+    /// amount. Applies also to the channels proposed by us, for which we wait for the funding
+    /// transaction to reach this depth even if the remote peer requires less confirmations.
+    pub depth_tiers: Vec<DepthTier>,
+
+    /// Maximal number of channels with a single remote peer, if limited
+    pub max_channels_per_peer: Option<u16>,
+
+    /// Remote peers which may open channels with us. If empty, all remote peers not listed in
+    /// the denylist may open channels.
+    pub allowlist: Vec<PublicKey>,
+
+    /// Remote peers which may not open channels with us
+    pub denylist: Vec<PublicKey>,
+}
+
+impl AcceptPolicy {
+    /// Checks whether the remote peer with the given node id may open channels with us
+    pub fn is_peer_allowed(&self, node_id: &PublicKey) -> bool {
+        !self.denylist.contains(node_id)
+            && (self.allowlist.is_empty() || self.allowlist.contains(node_id))
+    }
+
+    /// Returns number of funding transaction confirmations required for a channel with the given
+    /// funding amount, unless there is no depth tier applicable to the amount
+    pub fn minimum_depth(&self, funding_sat: u64) -> Option<u32> {
+        self.depth_tiers
+            .iter()
+            .filter(|tier| tier.min_funding_sat <= funding_sat)
+            .map(|tier| tier.minimum_depth)
+            .max()
+    }
+}
+
+/// Number of funding transaction confirmations required for the channels funded with at least
+/// the given amount
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, NetworkEncode, NetworkDecode)]
+#[display("{min_funding_sat}:{minimum_depth}")]
+pub struct DepthTier {
+    /// Minimal channel funding amount the tier applies to, in satoshis
+    pub min_funding_sat: u64,
+
+    /// Number of funding transaction confirmations required
+    pub minimum_depth: u32,
 }
 
 fn default_electrum_port(chain: &Chain) -> u16 {
@@ -65,6 +468,45 @@ fn default_electrum_port(chain: &Chain) -> u16 {
     }
 }
 
+fn default_bitcoind_port(chain: &Chain) -> u16 {
+    match chain {
+        Chain::Mainnet => 8332,
+        Chain::Testnet3 => 18332,
+        Chain::Regtest(_) => 18443,
+        Chain::Signet | Chain::SignetCustom(_) => 38332,
+        _ => 18332,
+    }
+}
+
+fn default_p2p_port(chain: &Chain) -> u16 {
+    match chain {
+        Chain::Mainnet => 8333,
+        Chain::Testnet3 => 18333,
+        Chain::Regtest(_) => 18444,
+        Chain::Signet | Chain::SignetCustom(_) => 38333,
+        _ => 18333,
+    }
+}
+
+/// Cookie file in the default Bitcoin Core data directory for the given network
+#[cfg(feature = "server")]
+fn default_bitcoind_cookie(chain: &Chain) -> PathBuf {
+    #[cfg(target_os = "macos")]
+    const BITCOIND_DATA_DIR: &str = "~/Library/Application Support/Bitcoin";
+    #[cfg(not(target_os = "macos"))]
+    const BITCOIND_DATA_DIR: &str = "~/.bitcoin";
+
+    let mut cookie_file = PathBuf::from(shellexpand::tilde(BITCOIND_DATA_DIR).to_string());
+    match chain {
+        Chain::Mainnet => {}
+        Chain::Testnet3 => cookie_file.push("testnet3"),
+        Chain::Regtest(_) => cookie_file.push("regtest"),
+        _ => cookie_file.push("signet"),
+    }
+    cookie_file.push(".cookie");
+    cookie_file
+}
+
 impl Config {
     pub fn channel_dir(&self) -> PathBuf {
         let mut channel_dir = self.data_dir.clone();
@@ -78,6 +520,16 @@ impl Config {
         channel_file.set_extension("channel");
         channel_file
     }
+
+    /// Path to the channel history file. Unlike the channel state file, it is named after the
+    /// channel id bytes, such that both temporary and permanent channel ids map onto the same
+    /// file name as the one used by the channel daemon identity.
+    pub fn channel_history_file(&self, channel_id: ChannelId) -> PathBuf {
+        let mut history_file = self.channel_dir();
+        history_file.push(channel_id.to_string());
+        history_file.set_extension("history");
+        history_file
+    }
 }
 
 #[cfg(feature = "server")]
@@ -88,6 +540,53 @@ impl From<Opts> for Config {
             opts.electrum_server,
             opts.electrum_port.unwrap_or_else(|| default_electrum_port(&opts.chain))
         );
+        let electrum_fallback_urls = opts
+            .electrum_fallbacks
+            .iter()
+            .map(|server| {
+                if server.contains(':') {
+                    server.clone()
+                } else {
+                    format!("{}:{}", server, default_electrum_port(&opts.chain))
+                }
+            })
+            .collect();
+
+        let chain_backend = match opts.chain_backend.as_str() {
+            "bitcoind" => ChainBackend::Bitcoind(BitcoindConfig {
+                rpc_url: opts.bitcoind_rpc.clone().unwrap_or_else(|| {
+                    format!("http://127.0.0.1:{}", default_bitcoind_port(&opts.chain))
+                }),
+                rpc_auth: match (opts.bitcoind_auth.clone(), opts.bitcoind_cookie.clone()) {
+                    (Some((user, password)), _) => BitcoindAuth::UserPass(user, password),
+                    (None, Some(cookie_file)) => BitcoindAuth::Cookie(cookie_file),
+                    (None, None) => BitcoindAuth::Cookie(default_bitcoind_cookie(&opts.chain)),
+                },
+                zmq_endpoint: opts.bitcoind_zmq.clone(),
+            }),
+            "neutrino" => {
+                let port = default_p2p_port(&opts.chain);
+                let mut peers = opts
+                    .neutrino_peers
+                    .iter()
+                    .map(|peer| {
+                        if peer.contains(':') {
+                            peer.clone()
+                        } else {
+                            format!("{}:{}", peer, port)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if peers.is_empty() {
+                    peers.push(format!("127.0.0.1:{}", port));
+                }
+                ChainBackend::Neutrino(NeutrinoConfig {
+                    peers,
+                    birthday: opts.wallet_birthday.unwrap_or_default(),
+                })
+            }
+            _ => ChainBackend::Electrum,
+        };
 
         let (msg_default, ctl_default) = match opts.threaded_daemons {
             true => (s!("inproc://msg"), s!("inproc://ctl")),
@@ -115,6 +614,14 @@ impl From<Opts> for Config {
             Err(_) => format!("ipc://{}", opts.rpc_socket),
         };
 
+        let events_endpoint = match SocketAddr::from_str(&opts.events_socket) {
+            Ok(_) => format!("tcp://{}", opts.events_socket),
+            Err(_) => format!("ipc://{}", opts.events_socket),
+        };
+
+        let backup_file =
+            opts.backup_file.unwrap_or_else(|| opts.data_dir.join(LNP_NODE_CHANNEL_BACKUP));
+
         Config {
             chain: opts.chain,
             data_dir: opts.data_dir,
@@ -129,8 +636,85 @@ impl From<Opts> for Config {
             rpc_endpoint: rpc_endpoint
                 .parse()
                 .expect("ZMQ sockets should be either TCP addresses or files"),
+            events_endpoint: events_endpoint
+                .parse()
+                .expect("ZMQ sockets should be either TCP addresses or files"),
             electrum_url,
+            electrum_fallback_urls,
+            chain_backend,
+            fees: FeeSettings {
+                floor_per_kw: opts.fee_floor,
+                ceiling_per_kw: opts.fee_ceiling,
+                target_blocks: opts.fee_target,
+            },
             threaded: opts.threaded_daemons,
+            propose_timeouts: ProposeTimeouts {
+                proposed: Duration::from_secs(opts.timeout_proposed),
+                accepted: Duration::from_secs(opts.timeout_accepted),
+                signing: Duration::from_secs(opts.timeout_signing),
+            },
+            // TODO: Read the rest of the bounds from the configuration file
+            peer_bounds: PeerBounds {
+                max_to_self_delay: opts.max_to_self_delay,
+                ..PeerBounds::default()
+            },
+            wumbo: opts.wumbo,
+            zero_conf_peers: opts.zero_conf_peers,
+            zero_conf_timeout: Duration::from_secs(opts.timeout_zero_conf),
+            funding_reorg_timeout: opts.timeout_funding_reorg,
+            htlc_deadline_margin: opts.htlc_deadline_margin,
+            channel_idle_timeout: Duration::from_secs(opts.timeout_channel_idle),
+            reconnect_max_interval: Duration::from_secs(opts.reconnect_max_interval),
+            keepalive: Keepalive {
+                ping_interval: Duration::from_secs(opts.ping_interval),
+                pong_timeout: Duration::from_secs(opts.timeout_pong),
+                max_missed_pongs: opts.max_missed_pongs,
+            },
+            handshake_timeouts: HandshakeTimeouts {
+                handshake: Duration::from_secs(opts.timeout_handshake),
+                init: Duration::from_secs(opts.timeout_init),
+            },
+            inbound_limits: InboundLimits {
+                max_peers: opts.max_inbound_peers,
+                rate: opts.inbound_rate,
+                burst: opts.inbound_burst,
+            },
+            ban_duration: Duration::from_secs(opts.ban_duration),
+            tor_proxy: opts.tor_proxy.map(|proxy| TorProxy {
+                address: proxy.unwrap_or_else(|| {
+                    SocketAddr::from_str(LNP_NODE_TOR_PROXY).expect("default Tor proxy address")
+                }),
+                always: opts.tor_always,
+            }),
+            accept_policy: AcceptPolicy {
+                min_funding_sat: opts.min_funding_sat,
+                max_funding_sat: opts.max_funding_sat,
+                max_push_msat: opts.max_push_msat,
+                max_push_percent: opts.max_push_percent,
+                depth_tiers: opts
+                    .depth_tiers
+                    .into_iter()
+                    .map(|(min_funding_sat, minimum_depth)| DepthTier {
+                        min_funding_sat,
+                        minimum_depth,
+                    })
+                    .collect(),
+                max_channels_per_peer: opts.max_channels_per_peer,
+                allowlist: opts.allow_peers,
+                denylist: opts.deny_peers,
+            },
+            towers: opts.towers,
+            gossip_sync_peers: opts.gossip_sync_peers,
+            signer: opts.signer,
+            hardware_wallet: opts.hwi.map(|hwi_path| HardwareWallet {
+                hwi_path: hwi_path.unwrap_or_else(|| PathBuf::from(LNP_NODE_HWI)),
+                timeout: Duration::from_secs(opts.hwi_timeout),
+            }),
+            watch_only: opts.watch_only,
+            unlock_file: opts.unlock_file,
+            backup_file,
+            withdrawal_limit: opts.withdrawal_limit,
+            unsafe_export: opts.unsafe_export,
         }
     }
 }
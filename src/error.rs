@@ -13,20 +13,27 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::io;
+use std::time::Duration;
 
 use amplify::IoError;
-use bitcoin::util::bip32;
+use bitcoin::secp256k1;
+use bitcoin::util::bip32::{self, Fingerprint};
 use internet2::{presentation, transport};
 use lnp::router;
 use microservices::esb;
 use psbt::sign::SignError;
 
 use crate::bus::ServiceBus;
+use crate::chain::ChainError;
 use crate::channeld;
 use crate::lnpd::automata::launch;
-use crate::lnpd::{funding, Daemon, DaemonError};
+use crate::lnpd::{
+    address_book, ban_list, channel_backups, fee_policy, funding, Daemon, DaemonError,
+};
+use crate::peerd::socks5;
 use crate::routed::PaymentError;
-use crate::rpc::{self, ServiceId};
+use crate::rpc::{self, ErrorCode, RpcError, ServiceId, ToRpcError};
+use crate::signd::{keystore, remote};
 
 #[derive(Debug, Display, From, Error)]
 #[display(doc_comments)]
@@ -52,6 +59,38 @@ pub enum Error {
     #[from]
     Peer(presentation::Error),
 
+    /// unable to connect remote peer through Tor proxy: {0}
+    #[from]
+    TorProxy(socks5::Error),
+
+    /// remote peer at onion address {0} can't be connected without Tor proxy (see --tor-proxy)
+    NoTorProxy(String),
+
+    /// peer address {0} uses unsupported transport; only TCP and WebSocket connections are
+    /// supported for the legacy lightning network
+    UnsupportedTransport(String),
+
+    /// BOLT-8 handshake with the remote peer has failed: {0}
+    Handshake(String),
+
+    /// remote peer has not completed BOLT-8 handshake within {0:?}
+    HandshakeTimeout(Duration),
+
+    /// WebSocket connection with the remote peer has failed: {0}
+    WebSocket(String),
+
+    /// remote peer has not sent `init` message within {0:?} after the handshake
+    InitTimeout(Duration),
+
+    /// remote peer has sent {0}-byte message exceeding the maximal BOLT-8 message length
+    OversizedMessage(usize),
+
+    /// remote peer does not read the messages sent to it; {0} messages are pending
+    PeerStalled(usize),
+
+    /// remote peer {0} is banned for violating the protocol
+    PeerBanned(secp256k1::PublicKey),
+
     /// channel operations failure: {0}
     #[from]
     #[from(lnp::channel::bolt::Error)]
@@ -83,6 +122,26 @@ pub enum Error {
     #[display(inner)]
     FundingWallet(funding::Error),
 
+    /// Error accessing peer address book
+    #[from]
+    #[display(inner)]
+    AddressBook(address_book::Error),
+
+    /// Error accessing ban list of the remote peers
+    #[from]
+    #[display(inner)]
+    BanList(ban_list::Error),
+
+    /// Error accessing routing fee policies
+    #[from]
+    #[display(inner)]
+    FeePolicy(fee_policy::Error),
+
+    /// Error accessing static channel backup
+    #[from]
+    #[display(inner)]
+    ChannelBackups(channel_backups::Error),
+
     /// unable to deriving keys: {0}
     #[from]
     Derivation(bip32::Error),
@@ -95,13 +154,37 @@ pub enum Error {
     #[from]
     Signing(SignError),
 
+    /// unable to derive signing key: {0}
+    #[from]
+    Secp256k1(secp256k1::Error),
+
+    /// signing account with fingerprint {0} is not known
+    UnknownAccount(Fingerprint),
+
+    /// key {0} does not match the key derived by the signer for the signed data
+    KeyMismatch(secp256k1::PublicKey),
+
+    /// Error of the remote signer
+    #[from]
+    #[display(inner)]
+    RemoteSigner(remote::Error),
+
+    /// signer is locked; please unlock it with `lnp-cli unlock`
+    SignerLocked,
+
+    /// Error decrypting the master key
+    #[from]
+    #[display(inner)]
+    Keystore(keystore::Error),
+
     /// bridge interface failure: {0}
     #[from(zmq::Error)]
     #[from]
     Bridge(transport::Error),
 
-    /// unable to connect Electrum server
-    ElectrumConnectivity,
+    /// blockchain backend failure: {0}
+    #[from]
+    Chain(ChainError),
 
     /// message `{1}` is not supported on {0} message bus
     NotSupported(ServiceBus, String),
@@ -115,6 +198,11 @@ pub enum Error {
     /// unrecoverable error "{0}"
     Terminate(String),
 
+    /// failure reported to the client with a specific error code
+    #[display(inner)]
+    #[from]
+    Failure(RpcError),
+
     /// other error type with string explanation
     #[display(inner)]
     #[from(internet2::addr::NoOnionSupportError)]
@@ -136,10 +224,66 @@ impl From<Error> for microservices::rpc::Error {
     fn from(err: Error) -> Self {
         match err {
             Error::Rpc(err) => err.into(),
-            err => microservices::rpc::Error::ServerFailure(microservices::rpc::Failure {
-                code: 2000,
-                info: err.to_string(),
-            }),
+            err => microservices::rpc::Error::ServerFailure(
+                err.to_rpc_error().into_microservice_failure(),
+            ),
+        }
+    }
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_)
+            | Error::Persistence(_)
+            | Error::BitcoinEncoding(_)
+            | Error::AddressBook(_)
+            | Error::BanList(_)
+            | Error::ChannelBackups(_) => ErrorCode::Storage,
+            Error::Esb(_) | Error::Bridge(_) => ErrorCode::Bus,
+            Error::Rpc(err) => err.error_code(),
+            Error::DaemonLaunch(_)
+            | Error::GossipRouter(_)
+            | Error::Chain(_)
+            | Error::Terminate(_)
+            | Error::Other(_) => ErrorCode::Internal,
+            Error::Peer(_)
+            | Error::TorProxy(_)
+            | Error::NoTorProxy(_)
+            | Error::UnsupportedTransport(_)
+            | Error::Handshake(_)
+            | Error::HandshakeTimeout(_)
+            | Error::WebSocket(_)
+            | Error::InitTimeout(_)
+            | Error::PeerStalled(_) => ErrorCode::PeerUnreachable,
+            Error::Misbehaving | Error::OversizedMessage(_) | Error::PeerBanned(_) => {
+                ErrorCode::PeerRejected
+            }
+            Error::Channel(err) => err.error_code(),
+            Error::ChannelLaunch(err) => err.error_code(),
+            Error::Payment(err) => err.error_code(),
+            Error::FundingWallet(err) => err.error_code(),
+            Error::FeePolicy(err) => err.error_code(),
+            Error::Derivation(_)
+            | Error::Miniscript(_)
+            | Error::Signing(_)
+            | Error::Secp256k1(_)
+            | Error::UnknownAccount(_)
+            | Error::KeyMismatch(_)
+            | Error::RemoteSigner(_) => ErrorCode::SignerUnavailable,
+            Error::SignerLocked | Error::Keystore(_) => ErrorCode::SignerLocked,
+            Error::NotSupported(..) | Error::SourceNotSupported(..) => ErrorCode::NotSupported,
+            Error::Failure(failure) => failure.error_code().unwrap_or(ErrorCode::Internal),
+        }
+    }
+
+    fn to_rpc_error(&self) -> RpcError {
+        match self {
+            // Keeping failures reported by other daemons and channel error numbers
+            Error::Rpc(err) => err.to_rpc_error(),
+            Error::Channel(err) => err.to_rpc_error(),
+            Error::Failure(failure) => failure.clone(),
+            err => RpcError::new(err.error_code(), err),
         }
     }
 }
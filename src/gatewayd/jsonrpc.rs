@@ -0,0 +1,142 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! JSON-RPC 2.0 error objects and request parameters.
+//!
+//! Errors use the codes defined by JSON-RPC 2.0 specification for malformed requests. Failures
+//! reported by the node keep their node failure codes (including authentication errors with
+//! codes 8001-8003) and provide the failure details as the error data, and errors of the gateway
+//! itself use codes from -32001 to -32099 range reserved for the implementation-defined server
+//! errors.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde_json::{json, Map, Value};
+
+use crate::rpc::{self, Error, ToRpcError};
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal gateway error
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Node RPC interface can't be reached or has replied with an unexpected message
+pub const NODE_UNAVAILABLE: i64 = -32001;
+/// Polling handle does not match any operation
+pub const UNKNOWN_HANDLE: i64 = -32002;
+/// Method is defined by the gateway, but the node does not support the operation yet
+pub const NOT_SUPPORTED: i64 = -32003;
+
+/// JSON-RPC error object
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display("{message}")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn with(code: i64, message: impl ToString) -> RpcError {
+        RpcError { code, message: message.to_string(), data: None }
+    }
+
+    pub fn invalid_params(message: impl ToString) -> RpcError {
+        RpcError::with(INVALID_PARAMS, message)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut json = json!({ "code": self.code, "message": self.message });
+        if let Some(ref data) = self.data {
+            json["data"] = data.clone();
+        }
+        json
+    }
+}
+
+impl From<rpc::RpcError> for RpcError {
+    fn from(failure: rpc::RpcError) -> Self {
+        RpcError { code: failure.code as i64, message: failure.message, data: failure.details }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Failure(_) | Error::Rpc(microservices::rpc::Error::ServerFailure(_)) => {
+                err.to_rpc_error().into()
+            }
+            err => RpcError::with(NODE_UNAVAILABLE, err),
+        }
+    }
+}
+
+/// Named parameters of the JSON-RPC request
+pub struct Params(Map<String, Value>);
+
+impl Params {
+    /// Extracts parameters from the request; only named parameters are supported
+    pub fn with(params: Option<Value>) -> Result<Params, RpcError> {
+        match params {
+            None | Some(Value::Null) => Ok(Params(Map::new())),
+            Some(Value::Object(map)) => Ok(Params(map)),
+            Some(_) => Err(RpcError::invalid_params("parameters must be given by name")),
+        }
+    }
+
+    /// Parses optional parameter. Strings, numbers and booleans are parsed from their textual
+    /// representation, such that all parameters are read with the same parsers as the
+    /// command-line arguments.
+    pub fn opt<T>(&self, name: &str) -> Result<Option<T>, RpcError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let text = match self.0.get(name) {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::String(s)) => s.clone(),
+            Some(value @ Value::Number(_)) | Some(value @ Value::Bool(_)) => value.to_string(),
+            Some(_) => {
+                return Err(RpcError::invalid_params(format!(
+                    "parameter `{}` must be a string, number or boolean",
+                    name
+                )))
+            }
+        };
+        T::from_str(&text).map(Some).map_err(|err| {
+            RpcError::invalid_params(format!("invalid `{}` parameter: {}", name, err))
+        })
+    }
+
+    /// Parses required parameter
+    pub fn req<T>(&self, name: &str) -> Result<T, RpcError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.opt(name)?
+            .ok_or_else(|| RpcError::invalid_params(format!("missing `{}` parameter", name)))
+    }
+
+    /// Parses optional boolean flag, which defaults to `false`
+    pub fn flag(&self, name: &str) -> Result<bool, RpcError> {
+        Ok(self.opt(name)?.unwrap_or_default())
+    }
+}
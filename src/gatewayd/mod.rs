@@ -0,0 +1,32 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! JSON-RPC 2.0 over HTTP gateway to the node RPC interface.
+//!
+//! The gateway allows integrators to control the node without linking LNP RPC library and
+//! speaking strict-encoded ZMQ protocol. Requests are sent as HTTP POST with JSON-RPC 2.0 body
+//! (single or batch) and are authenticated with the same RPC tokens as the native RPC interface,
+//! provided in `Authorization: Bearer <token>` header.
+//!
+//! Long-running operations (`openchannel`, `closechannel` and `pay`) return a polling handle
+//! instead of the final result; the progress and the outcome of the operation are reported by
+//! `getoperation` method.
+
+mod jsonrpc;
+mod opts;
+mod runtime;
+
+pub use jsonrpc::RpcError;
+pub use opts::{Opts, LNP_NODE_GATEWAY_SOCKET};
+pub use runtime::run;
@@ -0,0 +1,47 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::net::SocketAddr;
+
+/// Default address the gateway serves JSON-RPC requests on
+pub const LNP_NODE_GATEWAY_SOCKET: &str = "127.0.0.1:62964";
+
+/// JSON-RPC over HTTP gateway to the RPC interface of LNP Node.
+///
+/// The gateway is a client of the node RPC socket (see `rpc`), so it may run on a different
+/// host than the node itself.
+#[derive(Parser, Clone, PartialEq, Eq, Debug)]
+#[clap(name = "gatewayd", bin_name = "gatewayd", author, version)]
+pub struct Opts {
+    /// Address to serve JSON-RPC HTTP requests on.
+    ///
+    /// The gateway does not encrypt the connections, so binding to non-local addresses should be
+    /// done only behind a TLS-terminating proxy.
+    #[clap(
+        short = 'L',
+        long = "http",
+        default_value = LNP_NODE_GATEWAY_SOCKET,
+        env = "LNP_NODE_GATEWAY_SOCKET"
+    )]
+    pub http_socket: SocketAddr,
+
+    /// These params can be read also from the configuration file, not just
+    /// command-line args or environment variables
+    #[clap(flatten)]
+    pub shared: crate::opts::Opts,
+}
+
+impl Opts {
+    pub fn process(&mut self) { self.shared.process() }
+}
@@ -0,0 +1,528 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::Address;
+use internet2::{NodeAddr, PartialNodeAddr, ToNodeAddr};
+use lightning_invoice::Invoice;
+use lnp::p2p::legacy::{ChannelId, LNP2P_LEGACY_PORT};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use super::jsonrpc::{
+    Params, RpcError, INTERNAL_ERROR, INVALID_REQUEST, METHOD_NOT_FOUND, NOT_SUPPORTED,
+    PARSE_ERROR, UNKNOWN_HANDLE,
+};
+use crate::rpc::{
+    AddressType, AuthError, AuthToken, ChannelFilter, Client, ClientId, CloseChannel,
+    ClosingFeeRange, CreateChannel, Pagination, PayInvoice, PeerFilter, RpcMsg, ServiceId,
+    ToRpcError,
+};
+use crate::{Error, LogStyle};
+
+pub fn run(http_socket: SocketAddr, rpc_socket: String) -> Result<(), Error> {
+    let server = Server::http(http_socket).map_err(|err| Error::Other(err.to_string()))?;
+    let mut client = Client::with(&rpc_socket)?;
+    client.set_json_output(true);
+
+    let mut runtime = Runtime { rpc_socket, client, operations: empty!() };
+    info!("{} JSON-RPC requests on http://{}", "Serving".ended(), http_socket);
+    for request in server.incoming_requests() {
+        runtime.serve(request);
+    }
+    Err(Error::Terminate(s!("HTTP server has stopped")))
+}
+
+/// Maximal size of the JSON-RPC request body, in bytes
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Time after which finished operations which were never polled by the client are forgotten
+const OPERATION_EXPIRY: Duration = Duration::from_secs(3600);
+
+/// Stage of a long-running operation
+enum Status {
+    Pending,
+    Succeeded(Value),
+    Failed(RpcError),
+}
+
+/// Long-running operation tracked by the gateway until its outcome is polled by the client
+struct Operation {
+    /// JSON-RPC method which has started the operation
+    method: &'static str,
+
+    /// Token of the client which has started the operation, which is required to poll it
+    token: Option<AuthToken>,
+
+    /// Progress reports received from the node
+    progress: Vec<String>,
+
+    status: Status,
+
+    /// Time when the node has reported the outcome of the operation
+    finished: Option<Instant>,
+}
+
+type Operations = Arc<Mutex<BTreeMap<u64, Operation>>>;
+
+struct Runtime {
+    /// Node RPC socket, which is connected by a separate client for each long-running operation
+    rpc_socket: String,
+
+    /// Client performing requests which are replied immediately
+    client: Client,
+
+    /// Long-running operations indexed by their random polling handles. Operations are removed
+    /// once the client polls their outcome or after [`OPERATION_EXPIRY`] since they finish.
+    operations: Operations,
+}
+
+impl Runtime {
+    fn serve(&mut self, mut request: Request) {
+        if *request.method() != Method::Post {
+            let response = Response::from_string("JSON-RPC requests must use POST method");
+            if let Err(err) = request.respond(response.with_status_code(405)) {
+                warn!("Unable to reply HTTP request: {}", err);
+            }
+            return;
+        }
+
+        let body_length = request.body_length();
+        let body = match read_body(request.as_reader(), body_length) {
+            Err(BodyError::TooLarge) => {
+                let response = Response::from_string(format!(
+                    "JSON-RPC request body must not exceed {} bytes",
+                    MAX_BODY
+                ));
+                if let Err(err) = request.respond(response.with_status_code(413)) {
+                    warn!("Unable to reply HTTP request: {}", err);
+                }
+                return;
+            }
+            Err(BodyError::Io(err)) => Err(err),
+            Ok(body) => Ok(body),
+        };
+        let reply = match (bearer_token(&request), body) {
+            (Err(err), _) => Some(error_reply(Value::Null, err)),
+            (_, Err(err)) => Some(error_reply(Value::Null, RpcError::with(PARSE_ERROR, err))),
+            (Ok(token), Ok(body)) => self.process_body(&body, token),
+        };
+
+        // Requests made only of notifications are replied with empty body
+        let response = match reply {
+            Some(reply) => Response::from_string(reply.to_string()).with_header(
+                Header::from_str("Content-Type: application/json").expect("valid HTTP header"),
+            ),
+            None => Response::from_string("").with_status_code(204),
+        };
+        if let Err(err) = request.respond(response) {
+            warn!("Unable to reply HTTP request: {}", err);
+        }
+    }
+
+    fn process_body(&mut self, body: &str, token: Option<AuthToken>) -> Option<Value> {
+        let value = match serde_json::from_str::<Value>(body) {
+            Ok(value) => value,
+            Err(err) => return Some(error_reply(Value::Null, RpcError::with(PARSE_ERROR, err))),
+        };
+        match value {
+            Value::Array(calls) if calls.is_empty() => {
+                Some(error_reply(Value::Null, RpcError::with(INVALID_REQUEST, "empty batch")))
+            }
+            Value::Array(calls) => {
+                let replies = calls
+                    .into_iter()
+                    .filter_map(|call| self.process_call(call, &token))
+                    .collect::<Vec<_>>();
+                if replies.is_empty() {
+                    None
+                } else {
+                    Some(Value::Array(replies))
+                }
+            }
+            call => self.process_call(call, &token),
+        }
+    }
+
+    /// Performs a single JSON-RPC call, returning the reply unless the call is a notification
+    fn process_call(&mut self, call: Value, token: &Option<AuthToken>) -> Option<Value> {
+        let mut call = match call {
+            Value::Object(call) => call,
+            _ => {
+                let err = RpcError::with(INVALID_REQUEST, "request must be a JSON object");
+                return Some(error_reply(Value::Null, err));
+            }
+        };
+        let id = call.remove("id");
+        let notification = id.is_none();
+        let id = id.unwrap_or(Value::Null);
+        if call.get("jsonrpc") != Some(&json!("2.0")) {
+            let err = RpcError::with(INVALID_REQUEST, "only JSON-RPC 2.0 requests are supported");
+            return Some(error_reply(id, err));
+        }
+        let method = match call.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => {
+                let err = RpcError::with(INVALID_REQUEST, "method name must be a string");
+                return Some(error_reply(id, err));
+            }
+        };
+
+        debug!("Performing JSON-RPC call {}", method);
+        let result = Params::with(call.remove("params"))
+            .and_then(|params| self.call(&method, params, token.clone()));
+        if let Err(ref err) = result {
+            debug!("JSON-RPC call {} has failed: {}", method, err);
+        }
+        if notification {
+            return None;
+        }
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(err) => error_reply(id, err),
+        })
+    }
+
+    fn call(
+        &mut self,
+        method: &str,
+        params: Params,
+        token: Option<AuthToken>,
+    ) -> Result<Value, RpcError> {
+        self.client.set_token(token.clone());
+        match method {
+            "getinfo" => {
+                let service = if let Some(node_addr) = params.opt::<NodeAddr>("node")? {
+                    ServiceId::Peer(node_addr)
+                } else if let Some(channel_id) = params.opt::<ChannelId>("channel_id")? {
+                    ServiceId::Channel(channel_id)
+                } else {
+                    ServiceId::LnpBroker
+                };
+                self.query(service, RpcMsg::GetInfo)
+            }
+
+            "listpeers" => {
+                let filter = PeerFilter {
+                    remote_node: params.opt("node")?,
+                    since: params.opt("since")?,
+                    until: params.opt("until")?,
+                    listener: params.opt("listener")?,
+                };
+                let page = pagination(&params)?;
+                let reset = params.opt("reset")?.unwrap_or_default();
+                self.query(ServiceId::LnpBroker, RpcMsg::ListPeers(filter, page, reset))
+            }
+
+            "listchannels" => {
+                let filter = ChannelFilter {
+                    remote_node: params.opt("peer")?,
+                    lifecycle: params.opt("stage")?,
+                };
+                let page = pagination(&params)?;
+                self.query(ServiceId::LnpBroker, RpcMsg::ListChannels(filter, page))
+            }
+
+            "listfunds" => self.query(ServiceId::LnpBroker, RpcMsg::ListFunds),
+
+            "newaddress" => {
+                let address_type = params.opt("type")?.unwrap_or(AddressType::Bech32);
+                self.query(ServiceId::LnpBroker, RpcMsg::GetNewAddress(address_type))
+            }
+
+            "openchannel" => {
+                let peer = params.req::<PartialNodeAddr>("peer")?;
+                let remote_peer = peer.to_node_addr(LNP2P_LEGACY_PORT).ok_or_else(|| {
+                    RpcError::invalid_params("`peer` parameter must contain the node address")
+                })?;
+                let mut request = CreateChannel {
+                    remote_peer,
+                    report_to: None,
+                    funding_sat: params.req("funding_sat")?,
+                    push_msat: params.opt("push_msat")?.unwrap_or_default(),
+                    fee_rate: params.opt("fee_rate")?,
+                    announce_channel: params.opt("announce_channel")?,
+                    channel_type: params.opt("channel_type")?,
+                    dust_limit: params.opt("dust_limit")?,
+                    to_self_delay: params.opt("to_self_delay")?,
+                    htlc_max_count: params.opt("htlc_max_count")?,
+                    htlc_min_value: params.opt("htlc_min_value")?,
+                    htlc_max_total_value: params.opt("htlc_max_total_value")?,
+                    channel_reserve: params.opt("channel_reserve")?,
+                    shutdown_script: params
+                        .opt::<Address>("shutdown_address")?
+                        .map(|address| address.script_pubkey().into()),
+                    zero_conf: params.flag("zero_conf")?,
+                    max_to_self_delay: params.opt("max_to_self_delay")?,
+                    external_funding: params.flag("external_funding")?,
+                    dry_run: params.flag("dry_run")?,
+                };
+                self.launch("openchannel", ServiceId::LnpBroker, token, move |client_id| {
+                    request.report_to = Some(client_id);
+                    RpcMsg::CreateChannel(request)
+                })
+            }
+
+            "closechannel" => {
+                let request = CloseChannel {
+                    channel_id: params.req("channel_id")?,
+                    force: params.flag("force")?,
+                    fee_range: params.opt("fee_rate")?.map(ClosingFeeRange::with_feerate),
+                    dest_address: params.opt("address")?,
+                };
+                self.launch("closechannel", ServiceId::LnpBroker, token, move |_| {
+                    RpcMsg::CloseChannel(request)
+                })
+            }
+
+            "pay" => {
+                let request = PayInvoice {
+                    invoice: params.req::<Invoice>("invoice")?,
+                    channel_id: params.req("channel_id")?,
+                    amount_msat: params.opt("amount_msat")?,
+                };
+                self.launch("pay", ServiceId::Router, token, move |_| RpcMsg::PayInvoice(request))
+            }
+
+            "invoice" => Err(RpcError::with(
+                NOT_SUPPORTED,
+                "invoice generation is not supported by the node yet",
+            )),
+
+            "getoperation" => self.poll(params.req("handle")?, &token),
+
+            method => {
+                Err(RpcError::with(METHOD_NOT_FOUND, format!("unknown method `{}`", method)))
+            }
+        }
+    }
+
+    /// Performs request which is replied by the node immediately
+    fn query(&mut self, service: ServiceId, request: RpcMsg) -> Result<Value, RpcError> {
+        self.client.request(service, request)?;
+        match self.client.response()? {
+            RpcMsg::Failure(failure) => Err(failure.into()),
+            reply => reply_to_json(&reply),
+        }
+    }
+
+    /// Starts long-running operation, returning its polling handle. The operation is performed
+    /// by a separate client, such that its progress reports do not interleave with replies to
+    /// other requests.
+    fn launch(
+        &mut self,
+        method: &'static str,
+        service: ServiceId,
+        token: Option<AuthToken>,
+        request: impl FnOnce(ClientId) -> RpcMsg,
+    ) -> Result<Value, RpcError> {
+        let mut client = Client::with(&self.rpc_socket)?;
+        client.set_json_output(true);
+        client.set_token(token.clone());
+        let request = request(client.identity());
+        client.request(service, request)?;
+
+        let mut operations = self.operations.lock().expect("operation lock is poisoned");
+        expire(&mut operations);
+        // Handles are random, such that other clients can't guess them, and fit into 53 bits
+        // which JavaScript clients represent precisely
+        let handle = loop {
+            let handle = rand::thread_rng().next_u64() >> 11;
+            if !operations.contains_key(&handle) {
+                break handle;
+            }
+        };
+        let operation = Operation {
+            method,
+            token,
+            progress: empty!(),
+            status: Status::Pending,
+            finished: None,
+        };
+        operations.insert(handle, operation);
+        drop(operations);
+        let operations = self.operations.clone();
+        thread::spawn(move || track(client, handle, operations));
+
+        info!("{} operation {} with handle {}", "Launched".promo(), method, handle);
+        Ok(json!({ "handle": handle }))
+    }
+
+    /// Reports progress and outcome of a long-running operation to the client which has started
+    /// it. Finished operations are forgotten once their outcome is reported.
+    fn poll(&mut self, handle: u64, token: &Option<AuthToken>) -> Result<Value, RpcError> {
+        let mut operations = self.operations.lock().expect("operation lock is poisoned");
+        expire(&mut operations);
+        // Operations started by other clients are reported as unknown, such that their handles
+        // are not disclosed
+        let operation = operations.get(&handle).filter(|operation| operation.token == *token);
+        let operation = operation.ok_or_else(|| {
+            RpcError::with(UNKNOWN_HANDLE, format!("unknown operation handle {}", handle))
+        })?;
+        let mut reply = json!({ "method": operation.method, "progress": operation.progress });
+        match operation.status {
+            Status::Pending => {
+                reply["status"] = json!("pending");
+                return Ok(reply);
+            }
+            Status::Succeeded(ref result) => {
+                reply["status"] = json!("succeeded");
+                reply["result"] = result.clone();
+            }
+            Status::Failed(ref err) => {
+                reply["status"] = json!("failed");
+                reply["error"] = err.to_json();
+            }
+        }
+        operations.remove(&handle);
+        Ok(reply)
+    }
+}
+
+/// Collects replies to a long-running operation until the node reports its outcome
+fn track(mut client: Client, handle: u64, operations: Operations) {
+    loop {
+        let reply = client.response().map_err(RpcError::from);
+        let mut operations = operations.lock().expect("operation lock is poisoned");
+        let operation = match operations.get_mut(&handle) {
+            Some(operation) => operation,
+            None => return,
+        };
+        operation.status = match reply {
+            Ok(RpcMsg::Progress(info)) => {
+                operation.progress.push(info);
+                continue;
+            }
+            Ok(RpcMsg::Failure(failure)) => Status::Failed(failure.into()),
+            Ok(reply) => match reply_to_json(&reply) {
+                Ok(result) => Status::Succeeded(result),
+                Err(err) => Status::Failed(err),
+            },
+            Err(err) => Status::Failed(err),
+        };
+        operation.finished = Some(Instant::now());
+        debug!("Operation {} with handle {} has finished", operation.method, handle);
+        return;
+    }
+}
+
+/// Forgets finished operations which outcome was not polled within [`OPERATION_EXPIRY`]
+fn expire(operations: &mut BTreeMap<u64, Operation>) {
+    operations.retain(|handle, operation| match operation.finished {
+        Some(finished) if finished.elapsed() > OPERATION_EXPIRY => {
+            debug!("Operation {} with handle {} has expired", operation.method, handle);
+            false
+        }
+        _ => true,
+    });
+}
+
+/// Extracts reply data. Replies are serialized as JSON objects with a single key naming the
+/// reply type, which is dropped since the method already defines the type of its result.
+fn reply_to_json(reply: &RpcMsg) -> Result<Value, RpcError> {
+    match serde_json::to_value(reply) {
+        Ok(Value::Object(map)) if map.len() == 1 => {
+            Ok(map.into_iter().next().map(|(_, data)| data).unwrap_or_default())
+        }
+        Ok(value) => Ok(value),
+        Err(err) => Err(RpcError::with(INTERNAL_ERROR, err)),
+    }
+}
+
+fn error_reply(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": err.to_json(), "id": id })
+}
+
+/// Reads RPC token from `Authorization: Bearer <token>` header
+/// Errors reading HTTP request body
+#[derive(Debug)]
+enum BodyError {
+    /// Body is larger than [`MAX_BODY`] or declares such length in its headers
+    TooLarge,
+
+    /// Body can't be read or is not a valid UTF-8 string
+    Io(io::Error),
+}
+
+/// Reads HTTP request body of the declared length, reading no more than [`MAX_BODY`] bytes
+fn read_body(reader: impl Read, body_length: Option<usize>) -> Result<String, BodyError> {
+    if matches!(body_length, Some(len) if len > MAX_BODY) {
+        return Err(BodyError::TooLarge);
+    }
+    let mut body = Vec::new();
+    reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body).map_err(BodyError::Io)?;
+    if body.len() > MAX_BODY {
+        return Err(BodyError::TooLarge);
+    }
+    String::from_utf8(body)
+        .map_err(|err| BodyError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+fn bearer_token(request: &Request) -> Result<Option<AuthToken>, RpcError> {
+    let header = request.headers().iter().find(|header| header.field.equiv("Authorization"));
+    let value = match header {
+        Some(header) => header.value.as_str(),
+        None => return Ok(None),
+    };
+    value
+        .strip_prefix("Bearer ")
+        .and_then(|token| AuthToken::from_str(token.trim()).ok())
+        .map(Some)
+        .ok_or_else(|| AuthError::InvalidToken.to_rpc_error().into())
+}
+
+/// Parses listing page from `offset` and `limit` parameters
+fn pagination(params: &Params) -> Result<Pagination, RpcError> {
+    let offset = params.opt("offset")?.unwrap_or_default();
+    Ok(Pagination { offset, limit: params.opt("limit")? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_within_limit() {
+        let body = vec![b'x'; MAX_BODY];
+        assert_eq!(read_body(&body[..], Some(MAX_BODY)).unwrap().len(), MAX_BODY);
+        assert_eq!(read_body(&body[..], None).unwrap().len(), MAX_BODY);
+    }
+
+    #[test]
+    fn body_length_too_large() {
+        assert!(matches!(read_body(&b"{}"[..], Some(MAX_BODY + 1)), Err(BodyError::TooLarge)));
+    }
+
+    #[test]
+    fn body_too_large() {
+        // Bodies without declared length are read only up to the limit
+        let body = io::repeat(b'x');
+        assert!(matches!(read_body(body, None), Err(BodyError::TooLarge)));
+        let body = vec![b'x'; MAX_BODY + 1];
+        assert!(matches!(read_body(&body[..], Some(2)), Err(BodyError::TooLarge)));
+    }
+
+    #[test]
+    fn body_invalid_utf8() {
+        assert!(matches!(read_body(&[0xff, 0xfe][..], None), Err(BodyError::Io(_))));
+    }
+}
@@ -41,14 +41,19 @@ extern crate log;
 
 pub use lnp_rpc as rpc;
 
+mod auth;
 pub mod automata;
 pub mod bus;
+pub mod chain;
 mod config;
 mod error;
+mod message_signing;
 #[cfg(feature = "server")]
 pub mod opts;
 
 pub mod channeld;
+#[cfg(feature = "gateway")]
+pub mod gatewayd;
 pub mod lnpd;
 pub mod peerd;
 pub mod routed;
@@ -56,7 +61,12 @@ mod service;
 pub mod signd;
 pub mod watchd;
 
-pub use config::Config;
+pub use auth::RpcAuth;
+pub use config::{
+    AcceptPolicy, BitcoindAuth, BitcoindConfig, ChainBackend, Config, DepthTier, FeeSettings,
+    HandshakeTimeouts, HardwareWallet, InboundLimits, Keepalive, NeutrinoConfig, PeerBounds,
+    ProposeTimeouts, SignerMode, TorProxy,
+};
 pub use error::Error;
 pub use service::{Endpoints, LogStyle, Responder, Service, TryToServiceId};
 
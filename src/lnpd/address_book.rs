@@ -0,0 +1,220 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Address book of the remote nodes persisted by lnpd.
+//!
+//! The address book is updated on each connection with a remote node and from the node
+//! announcements received from the gossip. lnpd uses it to connect the nodes given only by their
+//! ids and to reconnect the nodes which have channels with the local node or are pinned by the
+//! user.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use amplify::IoError;
+use bitcoin::secp256k1::PublicKey;
+use internet2::addr::InetSocketAddr;
+use internet2::RemoteSocketAddr;
+use lnp::p2p::legacy::{AnnouncedNodeAddr, ChannelId};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Maximal number of addresses kept for a single node
+const MAX_NODE_ADDRESSES: usize = 8;
+
+/// Errors accessing the address book
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error accessing the address book: {0}
+    #[from(std::io::Error)]
+    Io(IoError),
+
+    /// address book file is corrupted: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+/// Information about a remote node kept in the address book
+#[derive(Clone, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode)]
+pub struct NodeEntry {
+    /// Known addresses of the node, the most recently connected first
+    pub addresses: Vec<RemoteSocketAddr>,
+
+    /// UNIX timestamp of the last successful connection with the node
+    pub last_connected: Option<u64>,
+
+    /// Whether the node is reconnected even if it has no channels with the local node
+    pub pinned: bool,
+
+    /// Whether the node was disconnected permanently by the user, such that it is not
+    /// reconnected until the user connects it again
+    pub suspended: bool,
+
+    /// Channels of the local node with the remote node
+    pub channels: BTreeSet<ChannelId>,
+}
+
+impl NodeEntry {
+    /// Checks whether lnpd has to reconnect the node once the connection is lost
+    pub fn is_reconnected(&self) -> bool {
+        !self.suspended && (self.pinned || !self.channels.is_empty())
+    }
+}
+
+/// Address book of the remote nodes, saved to the file on each update
+#[derive(Debug)]
+pub struct AddressBook {
+    path: PathBuf,
+    nodes: BTreeMap<PublicKey, NodeEntry>,
+}
+
+impl AddressBook {
+    /// Reads the address book from the file, starting an empty address book if the file does not
+    /// exist yet
+    pub fn load(path: PathBuf) -> Result<AddressBook, Error> {
+        let nodes = if path.exists() {
+            let file = fs::File::open(&path)?;
+            BTreeMap::strict_decode(&file)?
+        } else {
+            bmap! {}
+        };
+        info!("Address book at '{}' contains {} nodes", path.display(), nodes.len());
+        Ok(AddressBook { path, nodes })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        trace!("Saving address book on disk");
+        let file = fs::File::create(&self.path)?;
+        self.nodes.strict_encode(&file)?;
+        Ok(())
+    }
+
+    pub fn node(&self, node_id: &PublicKey) -> Option<&NodeEntry> { self.nodes.get(node_id) }
+
+    /// Checks whether the book knows no nodes, which is the case for a fresh node
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    /// Address used for connecting the node, which is the one of the last successful connection
+    /// if any
+    pub fn address(&self, node_id: &PublicKey) -> Option<RemoteSocketAddr> {
+        self.nodes.get(node_id).and_then(|node| node.addresses.first()).cloned()
+    }
+
+    /// Nodes which lnpd has to reconnect once the connection is lost
+    pub fn reconnected_nodes(&self) -> Vec<PublicKey> {
+        self.nodes.iter().filter(|(_, node)| node.is_reconnected()).map(|(id, _)| *id).collect()
+    }
+
+    /// Registers successful connection with the node. The address is known only for the
+    /// outbound connections.
+    pub fn register_connection(
+        &mut self,
+        node_id: PublicKey,
+        remote_addr: Option<RemoteSocketAddr>,
+    ) -> Result<(), Error> {
+        let node = self.nodes.entry(node_id).or_default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        node.last_connected = Some(now.as_secs());
+        if let Some(remote_addr) = remote_addr {
+            node.addresses.retain(|addr| *addr != remote_addr);
+            node.addresses.insert(0, remote_addr);
+            node.addresses.truncate(MAX_NODE_ADDRESSES);
+        }
+        self.save()
+    }
+
+    /// Registers addresses announced by the node in the gossip, keeping the addresses of the
+    /// past connections preferred
+    pub fn register_announcement(
+        &mut self,
+        node_id: PublicKey,
+        addresses: impl IntoIterator<Item = RemoteSocketAddr>,
+    ) -> Result<(), Error> {
+        let node = self.nodes.entry(node_id).or_default();
+        let known = node.addresses.len();
+        for remote_addr in addresses {
+            if !node.addresses.contains(&remote_addr) {
+                node.addresses.push(remote_addr);
+            }
+        }
+        node.addresses.truncate(MAX_NODE_ADDRESSES);
+        if node.addresses.len() == known {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Registers channel with the node, which makes lnpd reconnect the node
+    pub fn register_channel(
+        &mut self,
+        node_id: PublicKey,
+        channel_id: ChannelId,
+    ) -> Result<(), Error> {
+        if self.nodes.entry(node_id).or_default().channels.insert(channel_id) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Removes closed channel from the node it was opened with
+    pub fn forget_channel(&mut self, channel_id: ChannelId) -> Result<(), Error> {
+        let mut removed = false;
+        for node in self.nodes.values_mut() {
+            removed |= node.channels.remove(&channel_id);
+        }
+        if removed {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether the node is reconnected even if it has no channels with the local node.
+    /// Returns `false` if the node is not known to the address book and can't be unpinned.
+    pub fn set_pinned(&mut self, node_id: PublicKey, pinned: bool) -> Result<bool, Error> {
+        if !pinned && !self.nodes.contains_key(&node_id) {
+            return Ok(false);
+        }
+        self.nodes.entry(node_id).or_default().pinned = pinned;
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Sets whether the node is excluded from the reconnection after it was disconnected
+    /// permanently by the user
+    pub fn set_suspended(&mut self, node_id: PublicKey, suspended: bool) -> Result<(), Error> {
+        match self.nodes.get_mut(&node_id) {
+            Some(node) if node.suspended == suspended => return Ok(()),
+            Some(node) => node.suspended = suspended,
+            None if !suspended => return Ok(()),
+            None => {
+                self.nodes.insert(node_id, NodeEntry { suspended, ..NodeEntry::default() });
+            }
+        }
+        self.save()
+    }
+}
+
+/// Converts address announced in the gossip into the peer socket address. Tor addresses are not
+/// supported.
+pub fn announced_socket_addr(addr: &AnnouncedNodeAddr) -> Option<RemoteSocketAddr> {
+    let (address, port) = match *addr {
+        AnnouncedNodeAddr::IpV4 { addr, port } => (IpAddr::from(addr), port),
+        AnnouncedNodeAddr::IpV6 { addr, port } => (IpAddr::from(addr), port),
+        _ => return None,
+    };
+    Some(RemoteSocketAddr::Ftcp(InetSocketAddr { address: address.into(), port }))
+}
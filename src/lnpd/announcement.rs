@@ -0,0 +1,235 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Announcements of the local node and of its public channels to the network.
+//!
+//! lnpd holds the node key, so it signs `node_announcement` messages and adds the node signature
+//! to `channel_announcement` messages composed for the channel daemons, as it does for the
+//! `channel_update` messages. The announcements are broadcast by routed.
+//!
+//! Network nodes ignore announcements of the nodes without public channels, so the node is
+//! announced once its first channel is. The announcement is repeated whenever the announced
+//! addresses change and at least each [`REFRESH_INTERVAL`], such that the network does not
+//! forget the node; announcements are spaced by at least [`UPDATE_INTERVAL`].
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Signature};
+use internet2::{LocalNode, RemoteSocketAddr};
+use lightning_encoding::LightningEncode;
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::{
+    AddressList, Alias, AnnouncedNodeAddr, ChannelAnnouncement, NodeAnnouncement, NodeColor,
+};
+use lnp::router::gossip::LocalChannelInfo;
+
+use crate::lnpd::onion_service::OnionService;
+
+/// Maximal time between announcements of the node
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// Minimal interval between announcements of the node
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Node properties announced to the network
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AnnouncementConfig {
+    /// Node alias, up to 32 bytes long
+    pub alias: String,
+
+    /// Node color as red, green and blue components
+    pub color: [u8; 3],
+
+    /// Addresses the node is reachable at, announced in addition to the listening sockets bound
+    /// to public IP addresses
+    pub addresses: Vec<SocketAddr>,
+}
+
+/// Schedules announcements of the local node
+#[derive(Debug)]
+pub struct NodeAnnouncer {
+    config: AnnouncementConfig,
+
+    /// Whether the node has a channel announced to the network
+    has_channels: bool,
+
+    /// Timestamp of the last announcement
+    announced: Option<u32>,
+
+    /// Addresses given in the last announcement
+    addresses: Vec<AnnouncedNodeAddr>,
+}
+
+impl NodeAnnouncer {
+    pub fn with(config: AnnouncementConfig) -> NodeAnnouncer {
+        NodeAnnouncer { config, has_channels: false, announced: None, addresses: vec![] }
+    }
+
+    /// Registers announcement of a local channel, after which the node can be announced
+    pub fn channel_announced(&mut self) { self.has_channels = true; }
+
+    /// Addresses the node is reachable at: the configured ones, the listening sockets bound to
+    /// public IP addresses and the onion service
+    pub fn addresses<'a>(
+        &self,
+        listens: impl IntoIterator<Item = &'a RemoteSocketAddr>,
+        onion_service: Option<&OnionService>,
+    ) -> Vec<AnnouncedNodeAddr> {
+        let listens = listens.into_iter().filter_map(|addr| match addr {
+            RemoteSocketAddr::Ftcp(inet_addr) => SocketAddr::try_from(*inet_addr).ok(),
+            _ => None,
+        });
+        let mut sockets = self.config.addresses.clone();
+        for addr in listens.filter(|addr| is_public(addr.ip())) {
+            if !sockets.contains(&addr) {
+                sockets.push(addr);
+            }
+        }
+        let mut addresses = sockets
+            .into_iter()
+            .map(|addr| match addr {
+                SocketAddr::V4(addr) => {
+                    AnnouncedNodeAddr::IpV4 { addr: addr.ip().octets(), port: addr.port() }
+                }
+                SocketAddr::V6(addr) => {
+                    AnnouncedNodeAddr::IpV6 { addr: addr.ip().octets(), port: addr.port() }
+                }
+            })
+            .collect::<Vec<_>>();
+        addresses.extend(onion_service.and_then(OnionService::announced_addr));
+        addresses
+    }
+
+    /// Constructs `node_announcement` message if the node has to be announced: it was not
+    /// announced yet, its addresses have changed or the last announcement gets outdated. Returns
+    /// `None` if the node has no announced channels or was announced during the last
+    /// [`UPDATE_INTERVAL`].
+    pub fn due_announcement(
+        &mut self,
+        local_node: &LocalNode,
+        features: InitFeatures,
+        addresses: Vec<AnnouncedNodeAddr>,
+    ) -> Option<NodeAnnouncement> {
+        if !self.has_channels {
+            return None;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let refresh = REFRESH_INTERVAL.as_secs() as u32;
+        let interval = UPDATE_INTERVAL.as_secs() as u32;
+        let due = match self.announced {
+            None => true,
+            Some(announced) if announced + interval > now => false,
+            Some(announced) => addresses != self.addresses || announced + refresh <= now,
+        };
+        if !due {
+            return None;
+        }
+
+        // Timestamps of the announcements of the same node must increase
+        let timestamp = self.announced.map(|announced| announced + 1).unwrap_or_default();
+        let timestamp = timestamp.max(now);
+        self.announced = Some(timestamp);
+        self.addresses = addresses.clone();
+
+        let mut alias = [0u8; 32];
+        let len = self.config.alias.len().min(32);
+        alias[..len].copy_from_slice(&self.config.alias.as_bytes()[..len]);
+        let [red, green, blue] = self.config.color;
+        let mut announcement = NodeAnnouncement {
+            signature: Signature::from_compact(&[0u8; 64]).expect("zero signature is well-formed"),
+            features,
+            timestamp,
+            node_id: local_node.node_id(),
+            rgb_color: NodeColor { red, green, blue },
+            alias: Alias::from_inner(Slice32::from_inner(alias)),
+            addresses: AddressList::from_inner(addresses),
+        };
+        // The signature commits to the double SHA256 hash of the message data following it
+        let data = announcement.lightning_serialize().expect("in-memory encoding");
+        announcement.signature = sign(&data[64..], local_node);
+        Some(announcement)
+    }
+}
+
+/// Constructs `channel_announcement` message for the public channel, signed with the node key.
+/// Bitcoin signatures and the remote node signature are added by the channel daemon.
+pub fn channel_announcement(
+    local_node: &LocalNode,
+    info: &LocalChannelInfo,
+    local_funding_key: PublicKey,
+    remote_funding_key: PublicKey,
+) -> ChannelAnnouncement {
+    let zero_signature =
+        Signature::from_compact(&[0u8; 64]).expect("zero signature is well-formed");
+    let node_id = local_node.node_id();
+    // Channel nodes are ordered by their ids
+    let local_first = node_id.serialize() < info.remote_node.serialize();
+    let local = (node_id, local_funding_key);
+    let remote = (info.remote_node, remote_funding_key);
+    let ((node_id_1, bitcoin_key_1), (node_id_2, bitcoin_key_2)) =
+        if local_first { (local, remote) } else { (remote, local) };
+    let mut announcement = ChannelAnnouncement {
+        node_signature_1: zero_signature,
+        node_signature_2: zero_signature,
+        bitcoin_signature_1: zero_signature,
+        bitcoin_signature_2: zero_signature,
+        features: none!(),
+        chain_hash: info.chain_hash,
+        short_channel_id: info.short_channel_id,
+        node_id_1,
+        node_id_2,
+        bitcoin_key_1,
+        bitcoin_key_2,
+    };
+    // All four signatures commit to the double SHA256 hash of the message data following them
+    let data = announcement.lightning_serialize().expect("in-memory encoding");
+    let signature = sign(&data[256..], local_node);
+    if local_first {
+        announcement.node_signature_1 = signature;
+    } else {
+        announcement.node_signature_2 = signature;
+    }
+    announcement
+}
+
+fn sign(data: &[u8], local_node: &LocalNode) -> Signature {
+    let digest = sha256d::Hash::hash(data);
+    let message = Message::from_slice(&digest[..]).expect("hash is 32 bytes");
+    Secp256k1::signing_only().sign(&message, &local_node.private_key())
+}
+
+/// Checks whether the IP address is reachable from the internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => {
+            let segment = ip.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses are not routed
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || segment & 0xfe00 == 0xfc00
+                || segment & 0xffc0 == 0xfe80)
+        }
+    }
+}
@@ -19,7 +19,7 @@
 //! request coming from a remote peer, since this is one-stage process and does not require
 //! dedicated state machine.
 
-use amplify::{Slice32, Wrapper};
+use amplify::{IoError, Slice32, Wrapper};
 use bitcoin::Txid;
 use lnp::channel::bolt::LocalKeyset;
 use lnp::channel::{FundingError, PsbtLnpFunding};
@@ -28,10 +28,13 @@ use microservices::esb;
 use microservices::esb::Handler;
 
 use crate::automata::{Event, StateMachine};
-use crate::bus::{BusMsg, CtlMsg, FundChannel, OpenChannelWith, ServiceBus};
+use crate::bus::{BusMsg, CtlMsg, FundChannel, FundingSource, OpenChannelWith, ServiceBus};
 use crate::lnpd::runtime::Runtime;
-use crate::lnpd::{funding, Daemon, DaemonError};
-use crate::rpc::{ClientId, CreateChannel, Failure, OptionDetails, RpcMsg, ServiceId};
+use crate::lnpd::{channel_type, funding, Daemon, DaemonError};
+use crate::rpc::{
+    ClientId, CreateChannel, ErrorCode, FundingPreview, OptionDetails, RpcError, RpcMsg, ServiceId,
+    ToRpcError,
+};
 use crate::{Endpoints, Responder};
 
 /// Errors for channel launching workflow
@@ -61,10 +64,23 @@ pub enum Error {
     #[from]
     #[display(inner)]
     Funding(funding::Error),
+
+    /// unable to allocate index for the channel keyset: {0}
+    #[from(std::io::Error)]
+    KeyIndex(IoError),
 }
 
-impl From<Error> for Failure {
-    fn from(err: Error) -> Self { Failure { code: 6000, info: err.to_string() } }
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::UnexpectedMessage(..) | Error::DaemonLaunch(_) => ErrorCode::Internal,
+            Error::Esb(_) => ErrorCode::Bus,
+            Error::SignedTxidChanged { .. } => ErrorCode::InvalidSignature,
+            Error::FundingStructure(_) => ErrorCode::Funding,
+            Error::Funding(err) => err.error_code(),
+            Error::KeyIndex(_) => ErrorCode::Storage,
+        }
+    }
 }
 
 /// State machine for launching new channeld by lnpd in response to user channel opening requests.
@@ -135,8 +151,17 @@ impl StateMachine<CtlMsg, Runtime> for ChannelLauncher {
     ) -> Result<Option<Self>, Self::Error> {
         debug!("ChannelLauncher {:#} received {} event", self.channel_id(), event.message);
         let channel_id = self.channel_id();
-        if let CtlMsg::Error { error, .. } = &event.message {
-            let failure = Failure { code: 10000, info: error.clone() };
+        if let CtlMsg::Error { error, .. } | CtlMsg::SignFailed { error, .. } = &event.message {
+            let code = match event.source {
+                ServiceId::Signer => ErrorCode::SignerUnavailable,
+                _ => ErrorCode::Internal,
+            };
+            let failure = RpcError::new(code, error).with_source(&event.source);
+            runtime.send_rpc(event.endpoints, self.enquirer(), RpcMsg::Failure(failure))?;
+            return Ok(None);
+        }
+        if let CtlMsg::SignRefused { error, .. } = &event.message {
+            let failure = RpcError::from(error).with_source(&event.source);
             runtime.send_rpc(event.endpoints, self.enquirer(), RpcMsg::Failure(failure))?;
             return Ok(None);
         }
@@ -160,6 +185,12 @@ impl StateMachine<CtlMsg, Runtime> for ChannelLauncher {
                 start_negotiation2(event, runtime, temp_channel_id, keyset, request, enquirer)
             }
             ChannelLauncher::Negotiating(temp_channel_id, enquirer) => {
+                if let CtlMsg::ConstructFundingDryRun(ref fund_channel) = event.message {
+                    let fund_channel = fund_channel.clone();
+                    complete_dry_run(event, runtime, fund_channel, enquirer)?;
+                    info!("ChannelLauncher {:#} has completed its dry run", channel_id);
+                    return Ok(None);
+                }
                 complete_negotiation(event, runtime, temp_channel_id, enquirer)
             }
             ChannelLauncher::Committing(_, ref txid, ref enquirer) => {
@@ -171,7 +202,7 @@ impl StateMachine<CtlMsg, Runtime> for ChannelLauncher {
                 }
             }
             ChannelLauncher::Signing(channel_id, txid, enquirer) => {
-                complete_signatures(event, runtime, txid, enquirer)?;
+                complete_signatures(event, runtime, channel_id, txid, enquirer)?;
                 info!("ChannelLauncher {:#} has completed its work", channel_id);
                 return Ok(None);
             }
@@ -230,6 +261,7 @@ impl ChannelLauncher {
     ) -> Result<ChannelLauncher, Error> {
         let temp_channel_id = TempChannelId::random();
         debug!("Generated {} as a temporary channel id", temp_channel_id);
+        let index = runtime.key_index.allocate()?;
         debug!("ChannelLauncher {:#} is instantiated", temp_channel_id);
 
         let report = runtime
@@ -238,13 +270,16 @@ impl ChannelLauncher {
             .map_err(Error::from);
         report_progress_or_failure(enquirer, endpoints, report)?;
 
-        debug!("Asking signd to derive keyset for the channel {}", temp_channel_id);
+        debug!("Asking signd to derive keyset #{} for the channel {}", index, temp_channel_id);
         let report = endpoints
             .send_to(
                 ServiceBus::Ctl,
                 runtime.identity(),
                 ServiceId::Signer,
-                BusMsg::Ctl(CtlMsg::DeriveKeyset(temp_channel_id.into_inner())),
+                BusMsg::Ctl(CtlMsg::DeriveChannelKeys {
+                    channel_id: temp_channel_id.into_inner(),
+                    index,
+                }),
             )
             .map(|_| s!("Deriving basepoint keys for the channel"))
             .map_err(Error::from);
@@ -357,7 +392,12 @@ fn start_negotiation(
 ) -> Result<ChannelLauncher, Error> {
     let mut common = runtime.channel_params.1;
     let mut local = runtime.channel_params.2;
+    if let Some(feerate_per_kw) = runtime.fee_estimate {
+        common.feerate_per_kw = feerate_per_kw;
+    }
     create_channel.apply_params(&mut common, &mut local);
+    let features = runtime.peer_features.get(&create_channel.remote_peer);
+    common.channel_type = channel_type::negotiate(common.channel_type, features);
     let request = OpenChannelWith {
         remote_peer: create_channel.remote_peer,
         report_to: create_channel.report_to,
@@ -367,6 +407,17 @@ fn start_negotiation(
         common_params: common,
         local_params: local,
         local_keys: keyset,
+        shutdown_script: create_channel.shutdown_script,
+        features: features.cloned().unwrap_or_default(),
+        zero_conf: create_channel.zero_conf,
+        announce_channel: common.announce_channel,
+        max_to_self_delay: create_channel.max_to_self_delay,
+        funding: if create_channel.external_funding {
+            FundingSource::External
+        } else {
+            FundingSource::Internal
+        },
+        dry_run: create_channel.dry_run,
     };
     event
         .send_ctl(CtlMsg::OpenChannelWith(request))
@@ -380,42 +431,49 @@ fn complete_negotiation(
     temp_channel_id: TempChannelId,
     enquirer: ClientId,
 ) -> Result<ChannelLauncher, Error> {
-    let (amount, script_pubkey, feerate_per_kw) = match event.message {
+    debug_assert_eq!(
+        event.source,
+        ServiceId::Channel(temp_channel_id.into()),
+        "channel_launcher workflow inconsistency: funding CTL message for other than the \
+         launched channel daemon"
+    );
+    let psbt = match event.message {
         CtlMsg::ConstructFunding(FundChannel { amount, ref script_pubkey, feerate_per_kw }) => {
-            (amount, script_pubkey, feerate_per_kw)
+            report_progress(enquirer, event.endpoints, "Remote peer accepted the channel");
+            runtime
+                .funding_wallet
+                .construct_funding_psbt(
+                    temp_channel_id,
+                    script_pubkey.clone(),
+                    amount,
+                    feerate_per_kw,
+                )
+                .map_err(|err| {
+                    report_failure(enquirer, event.endpoints, Error::from(err)).unwrap_err()
+                })?
         }
+        // Channels opened in a batch are funded with a single transaction, which lnpd constructs
+        // once all channels of the batch are accepted
+        CtlMsg::FundingConstructed(ref psbt) => psbt.clone(),
         _ => {
             let err = Error::UnexpectedMessage(event.message.clone(), "SIGNING");
             report_failure(enquirer, event.endpoints, err)?;
             unreachable!()
         }
     };
-    debug_assert_eq!(
-        event.source,
-        ServiceId::Channel(temp_channel_id.into()),
-        "channel_launcher workflow inconsistency: `ConstructFunding` RPC CTL message originating \
-         not from a channel daemon"
-    );
-    report_progress(enquirer, event.endpoints, "Remote peer accepted the channel");
-    let funding_outpoint = runtime
-        .funding_wallet
-        .construct_funding_psbt(temp_channel_id, script_pubkey.clone(), amount, feerate_per_kw)
+    let funding_outpoint = psbt
+        .channel_funding_outpoint()
         .map_err(Error::from)
-        .and_then(|psbt| {
-            let funding_outpoint = psbt.channel_funding_outpoint()?;
-            event.send_ctl(CtlMsg::FundingConstructed(psbt)).map(|_| {
-                report_progress(
-                    enquirer,
-                    event.endpoints,
-                    format!(
-                        "Constructed funding transaction with funding outpoint {}",
-                        funding_outpoint
-                    ),
-                );
-            })?;
+        .and_then(|funding_outpoint| {
+            event.send_ctl(CtlMsg::FundingConstructed(psbt))?;
             Ok(funding_outpoint)
         })
         .map_err(|err| report_failure(enquirer, event.endpoints, err).unwrap_err())?;
+    report_progress(
+        enquirer,
+        event.endpoints,
+        format!("Constructed funding transaction with funding outpoint {}", funding_outpoint),
+    );
 
     let channel_id = ChannelId::with(funding_outpoint.txid, funding_outpoint.vout as u16);
     runtime.update_chanel_id(temp_channel_id, channel_id);
@@ -431,6 +489,37 @@ fn complete_negotiation(
     Ok(ChannelLauncher::Committing(channel_id, funding_outpoint.txid, enquirer))
 }
 
+fn complete_dry_run(
+    mut event: Event<CtlMsg>,
+    runtime: &mut Runtime,
+    fund_channel: FundChannel,
+    enquirer: ClientId,
+) -> Result<(), Error> {
+    report_progress(enquirer, event.endpoints, "Channel proposal is valid");
+    let FundChannel { script_pubkey, amount, feerate_per_kw } = fund_channel;
+    let draft = runtime.funding_wallet.preview_funding_psbt(script_pubkey, amount, feerate_per_kw);
+    // The remote peer is never contacted during the dry run, so the channel daemon is not needed
+    // anymore
+    if let Err(err) = event.send_ctl(CtlMsg::Shutdown) {
+        warn!("Unable to stop {}: {}", event.source, err);
+    }
+    let draft = draft
+        .map_err(|err| report_failure(enquirer, event.endpoints, Error::from(err)).unwrap_err())?;
+
+    let preview = FundingPreview {
+        psbt: (*draft.psbt).to_string(),
+        funding_sat: amount,
+        inputs: draft.inputs.iter().map(|(outpoint, _)| *outpoint).collect(),
+        input_sat: draft.inputs.iter().map(|(_, amount)| amount).sum(),
+        // Change output, if any, follows the funding output
+        change_sat: draft.psbt.global.unsigned_tx.output.get(1).map(|txout| txout.value),
+        fee_sat: draft.fee,
+        weight: draft.weight,
+    };
+    report_reply(enquirer, event.endpoints, RpcMsg::FundingPreview(preview));
+    Ok(())
+}
+
 fn complete_commitment(
     mut event: Event<CtlMsg>,
     runtime: &Runtime,
@@ -451,22 +540,37 @@ fn complete_commitment(
              originating not from a channel daemon"
         )
     };
+    if runtime.config.watch_only {
+        // Funding transaction is exported to the operator by the runtime
+        let report = format!(
+            "Funding transaction {} awaits signature; sign it with your wallet and submit it with \
+             `lnp-cli wallet submit-psbt`",
+            txid
+        );
+        report_progress(enquirer, event.endpoints, report);
+        return Ok(ChannelLauncher::Signing(channel_id, txid, enquirer));
+    }
     let psbt = runtime
         .funding_wallet
         .get_funding_psbt(txid)
         .expect("funding construction is broken")
         .clone();
+    let hint = match runtime.config.hardware_wallet {
+        Some(_) => "; please confirm it on your hardware wallet",
+        None => "",
+    };
     let report = event
         .send_ctl_service(ServiceId::Signer, CtlMsg::Sign(psbt))
-        .map(|_| format!("Signing funding transaction {}", txid))
+        .map(|_| format!("Signing funding transaction {}{}", txid, hint))
         .map_err(Error::from);
     report_progress_or_failure(enquirer, event.endpoints, report)?;
     Ok(ChannelLauncher::Signing(channel_id, txid, enquirer))
 }
 
 fn complete_signatures(
-    event: Event<CtlMsg>,
+    mut event: Event<CtlMsg>,
     runtime: &Runtime,
+    channel_id: ChannelId,
     txid: Txid,
     enquirer: ClientId,
 ) -> Result<(), Error> {
@@ -496,17 +600,31 @@ fn complete_signatures(
         event.endpoints,
         "Funding transaction is signed, publishing to bitcoin network",
     );
-    runtime.funding_wallet.publish(funding_psbt)?;
+    let outpoints = funding_psbt
+        .global
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect();
+    let (tx, feerate_per_kw) = runtime.funding_wallet.publish(funding_psbt)?;
+    // Funding transaction is rebroadcast by watchd until it gets mined; if it gets evicted from
+    // the mempool, the channel daemon is notified
+    let owner = ServiceId::Channel(channel_id);
+    event.send_ctl_service(ServiceId::Watch, CtlMsg::Rebroadcast { tx, owner, feerate_per_kw })?;
+    // Funding inputs may be double-spent until the funding transaction is mined, in which case
+    // watchd notifies the channel daemon
+    event.send_ctl_service(ServiceId::Watch, CtlMsg::TrackOutpoints(outpoints))?;
     report_success(enquirer, event.endpoints, "Channel created and active");
     Ok(())
 }
 
 fn report_failure<E>(client_id: ClientId, endpoints: &mut Endpoints, err: E) -> Result<(), Error>
 where
-    E: Into<Failure> + Into<Error> + std::error::Error,
+    E: ToRpcError + Into<Error>,
 {
     let enquirer = ServiceId::Client(client_id);
-    let report = RpcMsg::Failure(Failure::from(&err));
+    let report = RpcMsg::Failure(RpcError::from(&err).with_source(&ServiceId::LnpBroker));
     // Swallowing error since we do not want to break channel creation workflow just because of
     // not able to report back to the client
     let _ = endpoints
@@ -541,6 +659,15 @@ where
         .map_err(|err| error!("Can't report back to client #{}: {}", client_id, err));
 }
 
+fn report_reply(client_id: ClientId, endpoints: &mut Endpoints, report: RpcMsg) {
+    let enquirer = ServiceId::Client(client_id);
+    // Swallowing error since we do not want to break channel creation workflow just because of
+    // not able to report back to the client
+    let _ = endpoints
+        .send_to(ServiceBus::Rpc, ServiceId::LnpBroker, enquirer, BusMsg::Rpc(report))
+        .map_err(|err| error!("Can't report back to client #{}: {}", client_id, err));
+}
+
 fn report_progress_or_failure<T, E>(
     client_id: ClientId,
     endpoints: &mut Endpoints,
@@ -548,12 +675,12 @@ fn report_progress_or_failure<T, E>(
 ) -> Result<(), Error>
 where
     T: ToString,
-    E: Into<Failure> + Into<Error> + std::error::Error,
+    E: ToRpcError + Into<Error>,
 {
     let enquirer = ServiceId::Client(client_id);
     let report = match &result {
         Ok(val) => RpcMsg::Progress(val.to_string()),
-        Err(err) => RpcMsg::Failure(err.into()),
+        Err(err) => RpcMsg::Failure(RpcError::from(err).with_source(&ServiceId::LnpBroker)),
     };
     // Swallowing error since we do not want to break channel creation workflow just because of
     // not able to report back to the client
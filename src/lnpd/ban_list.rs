@@ -0,0 +1,199 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Ban list of the remote peers persisted by lnpd.
+//!
+//! Remote peers are banned by lnpd once their misbehaviour [`Score`] crosses [`BAN_THRESHOLD`],
+//! or by the node operator. Each ban expires after its duration. lnpd refuses to connect the banned
+//! nodes and disconnects them once they connect us. Peer listeners read the ban list from the
+//! data directory, refusing incoming connections from the addresses of the banned peers before
+//! the handshake and from the banned node ids right after it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use amplify::IoError;
+use bitcoin::secp256k1::PublicKey;
+use internet2::addr::InetAddr;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::peerd;
+
+/// Misbehaviour score after which a remote peer without channels is banned
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Number of points the misbehaviour score loses per hour
+const SCORE_DECAY: f64 = 10.0;
+
+/// Errors accessing the ban list
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error accessing the ban list: {0}
+    #[from(std::io::Error)]
+    Io(IoError),
+
+    /// ban list file is corrupted: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+/// Misbehaviour score of a remote peer, which sums the penalties for its protocol violations.
+/// The score decays by [`SCORE_DECAY`] points per hour, so occasional violations by honest peers
+/// never lead to a ban.
+#[derive(Clone, Copy, Debug)]
+pub struct Score {
+    points: f64,
+    updated: Instant,
+}
+
+impl Default for Score {
+    fn default() -> Self { Score { points: 0.0, updated: Instant::now() } }
+}
+
+impl Score {
+    /// Adds penalty to the score; returns whether the score has crossed [`BAN_THRESHOLD`]
+    pub fn penalize(&mut self, penalty: u32) -> bool {
+        self.decay();
+        self.points += penalty as f64;
+        self.points >= BAN_THRESHOLD as f64
+    }
+
+    /// Checks whether the score has decayed to zero, so it can be forgotten
+    pub fn is_cleared(&mut self) -> bool {
+        self.decay();
+        self.points <= 0.0
+    }
+
+    fn decay(&mut self) {
+        let now = Instant::now();
+        let hours = now.duration_since(self.updated).as_secs_f64() / 3600.0;
+        self.points = (self.points - hours * SCORE_DECAY).max(0.0);
+        self.updated = now;
+    }
+}
+
+/// Ban of a remote node
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct BanEntry {
+    /// Address of the connection on which the node was banned. Incoming connections from this
+    /// address (or its IPv6 /64 network) are refused as well.
+    pub address: Option<InetAddr>,
+
+    /// UNIX timestamp after which the ban is lifted
+    pub until: u64,
+
+    /// Reason of the ban
+    pub reason: String,
+}
+
+impl BanEntry {
+    fn is_active(&self, now: u64) -> bool { self.until > now }
+}
+
+/// Ban list of the remote nodes, saved to the file on each update
+#[derive(Debug)]
+pub struct BanList {
+    path: PathBuf,
+    bans: BTreeMap<PublicKey, BanEntry>,
+}
+
+impl BanList {
+    /// Reads the ban list from the file, starting an empty ban list if the file does not exist
+    /// yet. Expired bans are skipped.
+    pub fn load(path: PathBuf) -> Result<BanList, Error> {
+        let mut bans = if path.exists() {
+            let file = fs::File::open(&path)?;
+            BTreeMap::strict_decode(&file)?
+        } else {
+            bmap! {}
+        };
+        let now = now();
+        bans.retain(|_, ban: &mut BanEntry| ban.is_active(now));
+        debug!("Ban list at '{}' contains {} nodes", path.display(), bans.len());
+        Ok(BanList { path, bans })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        trace!("Saving ban list on disk");
+        let data = self.bans.strict_serialize()?;
+        // Peer listeners must never read a partially written file
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Active bans, ordered by the node id
+    pub fn bans(&self) -> impl Iterator<Item = (&PublicKey, &BanEntry)> {
+        let now = now();
+        self.bans.iter().filter(move |(_, ban)| ban.is_active(now))
+    }
+
+    /// Checks whether the node is banned
+    pub fn is_banned(&self, node_id: &PublicKey) -> bool {
+        self.bans.get(node_id).map(|ban| ban.is_active(now())).unwrap_or_default()
+    }
+
+    /// Checks whether connections from the address are refused since a node was banned on a
+    /// connection from the same host
+    pub fn is_address_banned(&self, ip: IpAddr) -> bool {
+        let address = InetAddr::from(peerd::network(ip));
+        self.bans().any(|(_, ban)| ban.address == Some(address))
+    }
+
+    /// Bans the node for the given duration, replacing its previous ban
+    pub fn ban(
+        &mut self,
+        node_id: PublicKey,
+        ip: Option<IpAddr>,
+        duration: Duration,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let ban = BanEntry {
+            address: ip.map(peerd::network).map(InetAddr::from),
+            until: now().saturating_add(duration.as_secs()),
+            reason: reason.to_owned(),
+        };
+        self.bans.insert(node_id, ban);
+        self.save()
+    }
+
+    /// Lifts the ban of the node. Returns `false` if the node is not banned.
+    pub fn unban(&mut self, node_id: PublicKey) -> Result<bool, Error> {
+        match self.bans.remove(&node_id) {
+            Some(ban) => {
+                self.save()?;
+                Ok(ban.is_active(now()))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Forgets expired bans
+    pub fn expire(&mut self) -> Result<(), Error> {
+        let now = now();
+        let count = self.bans.len();
+        self.bans.retain(|_, ban| ban.is_active(now));
+        if self.bans.len() == count {
+            return Ok(());
+        }
+        self.save()
+    }
+}
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() }
@@ -0,0 +1,209 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Opening of multiple channels funded by a single transaction.
+//!
+//! Each channel of the batch is launched by its own [`ChannelLauncher`], but instead of the
+//! launcher constructing the funding transaction, lnpd collects funding outputs requested by all
+//! channel daemons and constructs a single transaction paying to all of them. Each channel daemon
+//! receives a copy of the transaction with its own output marked as the channel funding output.
+//! The transaction is signed and published once all remote peers have signed the refund
+//! transactions.
+//!
+//! Until any channel daemon sends `funding_created` message to its remote peer, a failure of a
+//! single channel abandons the whole batch. Afterwards the batch is committed: the remaining
+//! channels are failed one by one, since the funding transaction will never be published.
+//!
+//! [`ChannelLauncher`]: super::automata::ChannelLauncher
+
+use amplify::Wrapper;
+use bitcoin::Txid;
+use internet2::NodeAddr;
+use lnp::p2p::legacy::{ChannelId, TempChannelId};
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use crate::bus::FundChannel;
+use crate::rpc::{ClientId, ErrorCode, ServiceId, ToRpcError};
+
+/// Errors opening batch of channels
+#[derive(Clone, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// batch contains no channels to open
+    Empty,
+
+    /// channel with {0} can't be funded from an external wallet, since all channels of the batch
+    /// are funded by the node funding wallet
+    ExternalFunding(NodeAddr),
+
+    /// channel with {0} can't be opened as a dry run, since the batch funding transaction is
+    /// constructed only once all channels of the batch are accepted by the remote peers
+    DryRun(NodeAddr),
+
+    /// opening of all channels of the batch is abandoned, since {0}
+    Abandoned(String),
+
+    /// all channels of the batch are failed, since {0}; the batch funding transaction was
+    /// already committed to the remote peers and will never be published
+    Failed(String),
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Empty | Error::ExternalFunding(_) | Error::DryRun(_) => {
+                ErrorCode::InvalidRequest
+            }
+            Error::Abandoned(_) | Error::Failed(_) => ErrorCode::Funding,
+        }
+    }
+}
+
+/// Channel opened as a part of the funding batch
+#[derive(Clone, Debug)]
+pub struct BatchChannel {
+    pub temp_channel_id: TempChannelId,
+
+    /// Permanent channel id, once the funding transaction is constructed
+    pub channel_id: Option<ChannelId>,
+
+    /// Funding output requested by the channel daemon
+    pub funding: Option<FundChannel>,
+
+    /// Whether the remote peer has signed the refund transaction, such that the funding
+    /// transaction may be published
+    pub funding_signed: bool,
+}
+
+impl BatchChannel {
+    /// Current id of the channel, which is either permanent or temporary one
+    pub fn active_channel_id(&self) -> ChannelId {
+        self.channel_id.unwrap_or_else(|| self.temp_channel_id.into())
+    }
+
+    /// Checks whether the service is the channel daemon, connected under either temporary or
+    /// permanent channel id
+    pub fn is_daemon(&self, service: &ServiceId) -> bool {
+        *service == ServiceId::Channel(self.temp_channel_id.into())
+            || self.channel_id.map(ServiceId::Channel).as_ref() == Some(service)
+    }
+
+    /// Index of the batch funding transaction output paying to the channel
+    pub fn funding_output(&self, psbt: &Psbt) -> Option<u16> {
+        let funding = self.funding.as_ref()?;
+        psbt.global
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| {
+                txout.script_pubkey == *funding.script_pubkey.as_inner()
+                    && txout.value == funding.amount
+            })
+            .map(|vout| vout as u16)
+    }
+}
+
+/// Batch of channels opened by a client and funded with a single transaction
+#[derive(Clone, Debug)]
+pub struct FundingBatch {
+    /// Client which has requested opening of the channels
+    pub enquirer: ClientId,
+
+    pub channels: Vec<BatchChannel>,
+
+    /// Funding transaction, once constructed
+    pub funding_txid: Option<Txid>,
+
+    /// Whether any of the channel daemons has sent `funding_created` message to its remote peer
+    pub committed: bool,
+}
+
+impl FundingBatch {
+    pub fn with(enquirer: ClientId) -> FundingBatch {
+        FundingBatch { enquirer, channels: vec![], funding_txid: None, committed: false }
+    }
+
+    pub fn add_channel(&mut self, temp_channel_id: TempChannelId) {
+        self.channels.push(BatchChannel {
+            temp_channel_id,
+            channel_id: None,
+            funding: None,
+            funding_signed: false,
+        });
+    }
+
+    pub fn contains(&self, channeld: &ServiceId) -> bool {
+        self.channels.iter().any(|channel| channel.is_daemon(channeld))
+    }
+
+    pub fn channel(&self, channeld: &ServiceId) -> Option<&BatchChannel> {
+        self.channels.iter().find(|channel| channel.is_daemon(channeld))
+    }
+
+    pub fn channel_mut(&mut self, channeld: &ServiceId) -> Option<&mut BatchChannel> {
+        self.channels.iter_mut().find(|channel| channel.is_daemon(channeld))
+    }
+
+    /// Temporary channel id under which the batch funding is registered in the funding wallet
+    pub fn funding_channel_id(&self) -> TempChannelId {
+        self.channels.first().expect("funding batch is never empty").temp_channel_id
+    }
+
+    /// Registers funding output requested by the channel daemon. Returns `true` once all
+    /// channels of the batch have requested their funding outputs.
+    pub fn register_funding(&mut self, channeld: &ServiceId, funding: FundChannel) -> bool {
+        if let Some(channel) = self.channel_mut(channeld) {
+            channel.funding = Some(funding);
+        }
+        self.channels.iter().all(|channel| channel.funding.is_some())
+    }
+
+    /// Number of the channels which remote peers have not yet accepted the channel
+    pub fn unfunded_count(&self) -> usize {
+        self.channels.iter().filter(|channel| channel.funding.is_none()).count()
+    }
+
+    /// Funding outputs of all channels in the batch order
+    pub fn funding_outputs(&self) -> Vec<(PubkeyScript, u64)> {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.funding.as_ref())
+            .map(|funding| (funding.script_pubkey.clone(), funding.amount))
+            .collect()
+    }
+
+    /// Feerate of the funding transaction, which is the highest one requested by the channels
+    pub fn feerate_per_kw(&self) -> Option<u32> {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.funding.as_ref())
+            .filter_map(|funding| funding.feerate_per_kw)
+            .max()
+    }
+
+    /// Registers signature of the refund transaction by the remote peer of the channel. Returns
+    /// `true` once the refund transactions of all channels are signed.
+    pub fn register_signed(&mut self, channeld: &ServiceId) -> bool {
+        if let Some(channel) = self.channel_mut(channeld) {
+            channel.funding_signed = true;
+        }
+        self.channels.iter().all(|channel| channel.funding_signed)
+    }
+
+    /// Number of the channels which remote peers have not yet signed the refund transaction
+    pub fn unsigned_count(&self) -> usize {
+        self.channels.iter().filter(|channel| !channel.funding_signed).count()
+    }
+}
@@ -0,0 +1,377 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-10 DNS bootstrap, finding the first peers of a fresh node.
+//!
+//! DNS seeds reply to SRV queries with the records pointing to `<node_id>.<seed>` virtual
+//! hosts, where the node id is bech32-encoded with `ln` prefix, and the port the node listens
+//! at. Addresses of the hosts are taken from A/AAAA records of the additional section or, if the
+//! seed does not provide them, are queried separately. Queries are sent directly to the name
+//! server over UDP, retrying over TCP for the truncated replies, so the resolution is bounded by
+//! its own timeout and does not depend on the system resolver.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use amplify::IoError;
+use bitcoin::bech32::{self, FromBase32};
+use bitcoin::secp256k1::rand::{self, Rng};
+use bitcoin::secp256k1::PublicKey;
+use internet2::addr::InetSocketAddr;
+use internet2::{RemoteNodeAddr, RemoteSocketAddr};
+use lnpbp::chain::Chain;
+
+/// Number of the peers connected after the bootstrap
+pub const BOOTSTRAP_PEERS: usize = 3;
+
+/// Time given to the resolution of a single DNS seed
+const SEED_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// System resolver configuration, providing the default name server
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+const DNS_PORT: u16 = 53;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// Errors resolving the DNS seeds
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error communicating with the name server: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// name server has not replied in time
+    Timeout,
+
+    /// name server has failed the query with code {0}
+    Failed(u16),
+
+    /// malformed reply of the name server
+    Malformed,
+
+    /// domain name '{0}' can't be queried
+    InvalidName(String),
+}
+
+/// Configuration of the DNS bootstrap
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BootstrapConfig {
+    /// Domains of the DNS seeds
+    pub seeds: Vec<String>,
+
+    /// Name server the seeds are resolved with. If absent, the first name server of the system
+    /// resolver configuration is used.
+    pub nameserver: Option<SocketAddr>,
+}
+
+/// DNS seeds maintained by the community for the chain
+pub fn default_seeds(chain: &Chain) -> Vec<String> {
+    match chain {
+        Chain::Mainnet => vec![s!("nodes.lightning.directory"), s!("lseed.bitcoinstats.com")],
+        Chain::Testnet3 => vec![s!("test.nodes.lightning.directory")],
+        _ => vec![],
+    }
+}
+
+/// Starts resolution of the DNS seeds in a separate thread, returning the receiver of the found
+/// peers
+pub fn spawn(config: BootstrapConfig) -> mpsc::Receiver<Vec<RemoteNodeAddr>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let peers = resolve_seeds(&config);
+        // Runtime may have already dropped the receiver
+        let _ = tx.send(peers);
+    });
+    rx
+}
+
+fn resolve_seeds(config: &BootstrapConfig) -> Vec<RemoteNodeAddr> {
+    let nameserver = match config.nameserver.or_else(system_nameserver) {
+        Some(nameserver) => nameserver,
+        None => {
+            warn!("DNS bootstrap is skipped since no name server is configured");
+            return vec![];
+        }
+    };
+    let mut peers = vec![];
+    for seed in &config.seeds {
+        debug!("Querying DNS seed {} through {}", seed, nameserver);
+        match query_seed(nameserver, seed) {
+            Ok(found) => {
+                info!("DNS seed {} has provided {} peers", seed, found.len());
+                peers.extend(found);
+            }
+            Err(err) => warn!("DNS seed {} is unavailable: {}", seed, err),
+        }
+    }
+    peers
+}
+
+/// Reads the first name server from the system resolver configuration
+fn system_nameserver() -> Option<SocketAddr> {
+    let resolv_conf = fs::read_to_string(RESOLV_CONF).ok()?;
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .next()
+}
+
+fn query_seed(nameserver: SocketAddr, seed: &str) -> Result<Vec<RemoteNodeAddr>, Error> {
+    let deadline = Instant::now() + SEED_TIMEOUT;
+    let records = query(nameserver, seed, TYPE_SRV, deadline)?;
+    let mut peers = vec![];
+    for record in &records {
+        let (port, target) = match record.data {
+            RecordData::Srv(port, ref target) => (port, target),
+            _ => continue,
+        };
+        let node_id = match parse_node_id(target) {
+            Some(node_id) => node_id,
+            None => {
+                debug!("DNS seed {} has returned invalid node id in {}", seed, target);
+                continue;
+            }
+        };
+        let mut ip = records
+            .iter()
+            .filter(|record| record.name.eq_ignore_ascii_case(target))
+            .find_map(|record| record.data.ip());
+        for record_type in &[TYPE_A, TYPE_AAAA] {
+            if ip.is_some() {
+                break;
+            }
+            ip = match query(nameserver, target, *record_type, deadline) {
+                Ok(records) => records.iter().find_map(|record| record.data.ip()),
+                Err(Error::Timeout) => return Ok(peers),
+                Err(err) => {
+                    debug!("Unable to resolve {}: {}", target, err);
+                    None
+                }
+            };
+        }
+        if let Some(ip) = ip {
+            let socket_addr = InetSocketAddr::from(SocketAddr::new(ip, port));
+            let remote_addr = RemoteSocketAddr::Ftcp(socket_addr);
+            peers.push(RemoteNodeAddr { node_id, remote_addr });
+        }
+    }
+    Ok(peers)
+}
+
+/// Decodes node id from the first label of the virtual host name returned by the seed
+fn parse_node_id(host: &str) -> Option<PublicKey> {
+    let label = host.split('.').next()?.to_lowercase();
+    let (hrp, data, _) = bech32::decode(&label).ok()?;
+    if hrp != "ln" {
+        return None;
+    }
+    let key = Vec::<u8>::from_base32(&data).ok()?;
+    PublicKey::from_slice(&key).ok()
+}
+
+/// Resource record from a reply of the name server
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Record {
+    name: String,
+    data: RecordData,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum RecordData {
+    /// Port and the target host of SRV record
+    Srv(u16, String),
+    Ip(IpAddr),
+    Other,
+}
+
+impl RecordData {
+    fn ip(&self) -> Option<IpAddr> {
+        match self {
+            RecordData::Ip(ip) => Some(*ip),
+            _ => None,
+        }
+    }
+}
+
+/// Queries name server for the records of the given type, returning the records from all
+/// sections of the reply
+fn query(
+    nameserver: SocketAddr,
+    name: &str,
+    record_type: u16,
+    deadline: Instant,
+) -> Result<Vec<Record>, Error> {
+    let id = rand::thread_rng().gen::<u16>();
+    let request = encode_query(id, name, record_type)?;
+
+    let local = match nameserver {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(nameserver)?;
+    socket.set_read_timeout(Some(remaining(deadline)?))?;
+    socket.send(&request)?;
+    let mut buf = [0u8; 4096];
+    let reply = loop {
+        let len = socket.recv(&mut buf).map_err(timeout_err)?;
+        // Replies to the queries which have already timed out are skipped
+        if len >= 2 && buf[..2] == id.to_be_bytes() {
+            break buf[..len].to_vec();
+        }
+        socket.set_read_timeout(Some(remaining(deadline)?))?;
+    };
+
+    let truncated = reply.len() >= 4 && reply[2] & 0x02 != 0;
+    let reply = if truncated {
+        trace!("DNS reply for {} is truncated; retrying over TCP", name);
+        query_tcp(nameserver, &request, deadline)?
+    } else {
+        reply
+    };
+    decode_reply(&reply, id)
+}
+
+fn query_tcp(nameserver: SocketAddr, request: &[u8], deadline: Instant) -> Result<Vec<u8>, Error> {
+    let mut stream =
+        TcpStream::connect_timeout(&nameserver, remaining(deadline)?).map_err(timeout_err)?;
+    stream.set_read_timeout(Some(remaining(deadline)?))?;
+    stream.set_write_timeout(Some(remaining(deadline)?))?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(request)?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).map_err(timeout_err)?;
+    let mut reply = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply).map_err(timeout_err)?;
+    Ok(reply)
+}
+
+fn remaining(deadline: Instant) -> Result<Duration, Error> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(remaining) if remaining > Duration::from_millis(1) => Ok(remaining),
+        _ => Err(Error::Timeout),
+    }
+}
+
+fn timeout_err(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+        _ => err.into(),
+    }
+}
+
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, Error> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query with recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no answers, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::InvalidName(name.to_owned()));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn decode_reply(reply: &[u8], id: u16) -> Result<Vec<Record>, Error> {
+    let u16_at = |pos: usize| -> Result<u16, Error> {
+        let bytes = reply.get(pos..pos + 2).ok_or(Error::Malformed)?;
+        Ok(u16::from_be_bytes(bytes.try_into().expect("slice of two bytes")))
+    };
+    if u16_at(0)? != id {
+        return Err(Error::Malformed);
+    }
+    match u16_at(2)? & 0x000F {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(vec![]),
+        rcode => return Err(Error::Failed(rcode)),
+    }
+    let questions = u16_at(4)?;
+    let records = u16_at(6)? as usize + u16_at(8)? as usize + u16_at(10)? as usize;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(reply, pos)?.1 + 4;
+    }
+    let mut decoded = Vec::with_capacity(records);
+    for _ in 0..records {
+        let (name, next) = read_name(reply, pos)?;
+        let record_type = u16_at(next)?;
+        let len = u16_at(next + 8)? as usize;
+        let start = next + 10;
+        let rdata = reply.get(start..start + len).ok_or(Error::Malformed)?;
+        let data = match (record_type, len) {
+            (TYPE_A, 4) => {
+                let octets: [u8; 4] = rdata.try_into().expect("four bytes");
+                RecordData::Ip(IpAddr::from(octets))
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = rdata.try_into().expect("sixteen bytes");
+                RecordData::Ip(IpAddr::from(octets))
+            }
+            // Priority and weight are not used, since all the targets are equally good
+            (TYPE_SRV, len) if len > 6 => {
+                RecordData::Srv(u16_at(start + 4)?, read_name(reply, start + 6)?.0)
+            }
+            _ => RecordData::Other,
+        };
+        decoded.push(Record { name, data });
+        pos = start + len;
+    }
+    Ok(decoded)
+}
+
+/// Reads possibly compressed domain name, returning it together with the position following the
+/// name
+fn read_name(reply: &[u8], mut pos: usize) -> Result<(String, usize), Error> {
+    let mut labels = Vec::<String>::new();
+    let mut next = None;
+    // Bounds the number of compression pointers followed, protecting from the pointer loops
+    for _ in 0..128 {
+        let len = *reply.get(pos).ok_or(Error::Malformed)? as usize;
+        match len {
+            0 => return Ok((labels.join("."), next.unwrap_or(pos + 1))),
+            len if len & 0xC0 == 0xC0 => {
+                let low = *reply.get(pos + 1).ok_or(Error::Malformed)? as usize;
+                next.get_or_insert(pos + 2);
+                pos = (len & 0x3F) << 8 | low;
+            }
+            len if len <= 63 => {
+                let label = reply.get(pos + 1..pos + 1 + len).ok_or(Error::Malformed)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            _ => return Err(Error::Malformed),
+        }
+    }
+    Err(Error::Malformed)
+}
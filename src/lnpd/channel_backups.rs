@@ -0,0 +1,106 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Static channel backup file maintained by lnpd.
+//!
+//! Channel daemons send backup records of their channels once the funding outpoint is known and
+//! each time the channel gets active. lnpd rewrites the whole backup file when a channel is added
+//! or removed. The data are written into a temporary file, which then replaces the backup file,
+//! so the backup file is never left partially written.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use amplify::IoError;
+use lnp::p2p::legacy::ChannelId;
+
+use crate::channeld::{self, BackupError, ChannelBackup};
+
+/// Errors accessing static channel backup file
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error accessing static channel backup file: {0}
+    #[from(std::io::Error)]
+    Io(IoError),
+
+    /// unable to read static channel backup file: {0}
+    #[from]
+    Backup(BackupError),
+}
+
+/// Static backups of the node channels, saved to the backup file on each update
+pub struct ChannelBackups {
+    path: PathBuf,
+    key: [u8; 32],
+    channels: BTreeMap<ChannelId, ChannelBackup>,
+}
+
+impl ChannelBackups {
+    /// Reads channel backups from the file, starting with no backups if the file does not exist
+    /// yet
+    pub fn load(path: PathBuf, key: [u8; 32]) -> Result<ChannelBackups, Error> {
+        let channels = if path.exists() {
+            channeld::deserialize_backups(fs::read(&path)?, &key)?
+                .into_iter()
+                .map(|backup| (backup.channel_id, backup))
+                .collect()
+        } else {
+            bmap! {}
+        };
+        info!("Static channel backup at '{}' contains {} channels", path.display(), channels.len());
+        Ok(ChannelBackups { path, key, channels })
+    }
+
+    /// Writes the backup file, returning its content
+    fn save(&self) -> Result<Vec<u8>, Error> {
+        trace!("Saving static channel backup on disk");
+        let data = channeld::serialize_backups(self.channels.values(), &self.key)?;
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, &data)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(data)
+    }
+
+    /// Decrypts channel backups from the backup data created by this node, which may differ from
+    /// the current backup file
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<ChannelBackup>, BackupError> {
+        channeld::deserialize_backups(data, &self.key)
+    }
+
+    /// Number of the backed up channels
+    pub fn len(&self) -> usize { self.channels.len() }
+
+    /// Checks whether no channels are backed up
+    pub fn is_empty(&self) -> bool { self.channels.is_empty() }
+
+    /// Adds or updates channel backup. Returns the content of the backup file if it has changed.
+    pub fn register(&mut self, backup: ChannelBackup) -> Result<Option<Vec<u8>>, Error> {
+        if self.channels.get(&backup.channel_id) == Some(&backup) {
+            return Ok(None);
+        }
+        self.channels.insert(backup.channel_id, backup);
+        self.save().map(Some)
+    }
+
+    /// Removes backup of the closed channel. Returns the content of the backup file if it has
+    /// changed.
+    pub fn forget(&mut self, channel_id: ChannelId) -> Result<Option<Vec<u8>>, Error> {
+        if self.channels.remove(&channel_id).is_none() {
+            return Ok(None);
+        }
+        self.save().map(Some)
+    }
+}
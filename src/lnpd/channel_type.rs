@@ -0,0 +1,63 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Negotiation of the channel type with the remote peer basing on the features negotiated in
+//! `init` messages
+
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::ChannelType;
+
+/// Checks whether the features required by a given channel type are negotiated
+fn is_supported(channel_type: ChannelType, features: &InitFeatures) -> bool {
+    match channel_type {
+        ChannelType::Basic => true,
+        ChannelType::StaticRemotekey => features.option_static_remotekey,
+        ChannelType::AnchoredOutputsStaticRemotekey => features.option_anchor_outputs,
+        ChannelType::AnchoredZeroFeeHtlc => features.option_anchors_zero_fee_htlc_tx,
+    }
+}
+
+/// Selects channel type for the channel proposed to the remote peer. If the peer does not
+/// advertise support for the requested channel type, falls back to the best channel type
+/// supported by both nodes.
+pub fn negotiate(requested: ChannelType, features: Option<&InitFeatures>) -> ChannelType {
+    let features = match features {
+        Some(features) => features,
+        None => {
+            warn!("Features of the remote peer are unknown; proposing {:?} channel", requested);
+            return requested;
+        }
+    };
+    if is_supported(requested, features) {
+        return requested;
+    }
+    let channel_type = implicit(Some(features));
+    info!(
+        "Remote peer does not support {:?} channels; falling back to {:?} channel",
+        requested, channel_type
+    );
+    channel_type
+}
+
+/// Detects channel type of the channel proposed by the remote peer without explicit
+/// `channel_type` field, which is defined by the features supported by both nodes
+pub fn implicit(features: Option<&InitFeatures>) -> ChannelType {
+    match features {
+        Some(features) if features.option_anchors_zero_fee_htlc_tx => {
+            ChannelType::AnchoredZeroFeeHtlc
+        }
+        Some(features) if features.option_static_remotekey => ChannelType::StaticRemotekey,
+        _ => ChannelType::Basic,
+    }
+}
@@ -22,6 +22,7 @@ use std::{process, thread};
 
 use amplify::hex::ToHex;
 use amplify::IoError;
+use internet2::addr::InetSocketAddr;
 use internet2::RemoteSocketAddr;
 use lnp::p2p::legacy::ActiveChannelId;
 
@@ -109,7 +110,7 @@ impl<DaemonName: Debug + Display + Clone> DaemonHandle<DaemonName> {
 #[derive(Clone, Eq, PartialEq, Debug, Display)]
 pub enum Daemon {
     #[display("signd")]
-    Signd,
+    Signd(PathBuf),
 
     #[display("peerd")]
     Peerd(PeerSocket, PathBuf),
@@ -121,17 +122,17 @@ pub enum Daemon {
     Routed,
 
     #[display("watchd")]
-    Watchd,
+    Watchd(PathBuf),
 }
 
 impl Daemon {
     pub fn bin_name(&self) -> &'static str {
         match self {
-            Daemon::Signd => "signd",
+            Daemon::Signd(_) => "signd",
             Daemon::Peerd(..) => "peerd",
             Daemon::Channeld(..) => "channeld",
             Daemon::Routed => "routed",
-            Daemon::Watchd => "watchd",
+            Daemon::Watchd(_) => "watchd",
         }
     }
 }
@@ -149,13 +150,13 @@ impl Runtime {
             .name(d.to_string())
             .spawn(move || {
                 let res = match d.clone() {
-                    Daemon::Signd => signd::run(config),
+                    Daemon::Signd(key_file) => signd::run(config, &key_file),
                     Daemon::Peerd(socket, key_file) => {
                         peerd::supervisor::run(config, &key_file, socket)
                     }
                     Daemon::Channeld(channel_id) => channeld::run(config, channel_id),
                     Daemon::Routed => routed::run(config),
-                    Daemon::Watchd => watchd::run(config),
+                    Daemon::Watchd(key_file) => watchd::run(config, &key_file),
                 };
                 match res {
                     Ok(_) => unreachable!("daemons should never terminate by themselves"),
@@ -188,17 +189,32 @@ impl Runtime {
         cmd.args(std::env::args().skip(1).filter(|arg| !arg.starts_with("--listen")));
 
         match &daemon {
-            Daemon::Peerd(PeerSocket::Listen(RemoteSocketAddr::Ftcp(inet)), _) => {
-                let socket_addr = SocketAddr::try_from(*inet).expect("invalid connection address");
+            Daemon::Peerd(PeerSocket::Listen(addr), _)
+                if matches!(addr, RemoteSocketAddr::Ftcp(_) | RemoteSocketAddr::Websocket(_)) =>
+            {
+                let socket_addr = SocketAddr::try_from(InetSocketAddr::from(*addr))
+                    .expect("invalid connection address");
                 let ip = socket_addr.ip();
                 let port = socket_addr.port();
                 cmd.args(&["--listen", &ip.to_string(), "--port", &port.to_string()]);
+                if let RemoteSocketAddr::Websocket(_) = addr {
+                    cmd.args(&["--overlay", "websocket"]);
+                }
             }
-            Daemon::Peerd(PeerSocket::Connect(node_addr), _) => {
+            Daemon::Peerd(PeerSocket::Connect(node_addr, timeout), _) => {
+                // WebSocket transport is passed separately from the address of the peer
+                let mut node_addr = node_addr.clone();
+                if let RemoteSocketAddr::Websocket(inet) = node_addr.remote_addr {
+                    node_addr.remote_addr = RemoteSocketAddr::Ftcp(inet);
+                    cmd.args(&["--overlay", "websocket"]);
+                }
                 cmd.args(&["--connect", &node_addr.to_string()]);
+                if let Some(timeout) = timeout {
+                    cmd.args(&["--connect-timeout", &timeout.as_secs().to_string()]);
+                }
             }
             Daemon::Peerd(PeerSocket::Listen(_), _) => {
-                // Lightning do not support non-TCP sockets
+                // Lightning peers are connected only over TCP or WebSocket
                 return Err(DaemonError::ProcessAborted(daemon.clone(), ExitStatus::from_raw(101)));
             }
             Daemon::Channeld(channel_id, ..) => {
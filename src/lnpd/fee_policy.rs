@@ -0,0 +1,334 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Routing fee policies of the local channels persisted by lnpd.
+//!
+//! Policy may be set for all channels, for the channels with a remote peer or for a single
+//! channel, the more specific policy taking precedence. lnpd announces the policy of each active
+//! channel with `channel_update` gossip message, which is regenerated whenever the policy in
+//! effect for the channel changes. Updates of the public channels are broadcast by routed once
+//! the channel itself is announced to the network; until then, as for the private channels, they
+//! are sent only to the channel remote peer.
+//!
+//! Network nodes do not relay channel updates coming too often, so announcements of the same
+//! channel are spaced by at least [`UPDATE_INTERVAL`]; the latest policy set in between is
+//! announced once the interval passes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::IoError;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Signature};
+use internet2::LocalNode;
+use lightning_encoding::LightningEncode;
+use lnp::p2p::legacy::{ChannelId, ChannelUpdate};
+use lnp::router::gossip::LocalChannelInfo;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::rpc::{
+    ChannelFeePolicy, ErrorCode, FeePolicy, FeePolicyList, PolicyScope, ToRpcError,
+};
+
+/// Minimal interval between `channel_update` messages announcing the policy of the same channel
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Errors accessing routing fee policies
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error accessing routing fee policies: {0}
+    #[from(std::io::Error)]
+    Io(IoError),
+
+    /// routing fee policy file is corrupted: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// maximal HTLC amount {max} msat is less than the minimal HTLC amount {min} msat
+    HtlcRange { min: u64, max: u64 },
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) | Error::Encoding(_) => ErrorCode::Storage,
+            Error::HtlcRange { .. } => ErrorCode::InvalidRequest,
+        }
+    }
+}
+
+/// Policies set by the user
+#[derive(Clone, PartialEq, Eq, Debug, Default, StrictEncode, StrictDecode)]
+struct PolicySet {
+    default: FeePolicy,
+    peers: BTreeMap<PublicKey, FeePolicy>,
+    channels: BTreeMap<ChannelId, FeePolicy>,
+}
+
+/// Active channel which policy is announced to the network
+#[derive(Clone, Debug)]
+struct ActiveChannel {
+    info: LocalChannelInfo,
+    /// Whether the channel is to be announced to the whole network
+    public: bool,
+    /// Whether `channel_announcement` of the public channel is already broadcast
+    broadcast: bool,
+    /// Timestamp of the last `channel_update` message announcing the channel
+    announced: Option<u32>,
+    /// Whether the policy has to be announced once the rate limit allows
+    pending: bool,
+}
+
+/// Signed `channel_update` message which has to be sent to the remote peers
+#[derive(Clone, Debug)]
+pub struct PolicyUpdate {
+    /// Remote peer of the channel
+    pub remote_node: PublicKey,
+
+    /// Whether the channel is announced, so the update is broadcast to the whole network and not
+    /// only sent to the channel remote peer
+    pub public: bool,
+
+    pub message: ChannelUpdate,
+}
+
+/// Routing fee policies, saved to the file on each update
+#[derive(Debug)]
+pub struct FeePolicyBook {
+    path: PathBuf,
+    policies: PolicySet,
+    active: BTreeMap<ChannelId, ActiveChannel>,
+}
+
+impl FeePolicyBook {
+    /// Reads the policies from the file, starting with the default policy for all channels if
+    /// the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<FeePolicyBook, Error> {
+        let policies = if path.exists() {
+            let file = fs::File::open(&path)?;
+            PolicySet::strict_decode(&file)?
+        } else {
+            PolicySet::default()
+        };
+        info!("Default routing fee policy is {}", policies.default);
+        Ok(FeePolicyBook { path, policies, active: none!() })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        trace!("Saving routing fee policies on disk");
+        let file = fs::File::create(&self.path)?;
+        self.policies.strict_encode(&file)?;
+        Ok(())
+    }
+
+    /// Policy in effect for the channel, with the scope it was set for
+    pub fn policy(
+        &self,
+        channel_id: ChannelId,
+        remote_node: PublicKey,
+    ) -> (PolicyScope, FeePolicy) {
+        if let Some(policy) = self.policies.channels.get(&channel_id) {
+            (PolicyScope::Channel(channel_id), *policy)
+        } else if let Some(policy) = self.policies.peers.get(&remote_node) {
+            (PolicyScope::Peer(remote_node), *policy)
+        } else {
+            (PolicyScope::All, self.policies.default)
+        }
+    }
+
+    fn active_policies(&self) -> BTreeMap<ChannelId, FeePolicy> {
+        self.active
+            .iter()
+            .map(|(channel_id, channel)| {
+                (*channel_id, self.policy(*channel_id, channel.info.remote_node).1)
+            })
+            .collect()
+    }
+
+    /// Sets policy for the given scope. Policy set for all channels or for a remote peer
+    /// replaces the policies set for the narrower scopes. Returns active channels which policy
+    /// has changed; their announcements are scheduled.
+    pub fn set_policy(
+        &mut self,
+        scope: PolicyScope,
+        policy: FeePolicy,
+    ) -> Result<Vec<(ChannelId, FeePolicy)>, Error> {
+        if let Some(max) = policy.htlc_max_msat {
+            if max < policy.htlc_min_msat {
+                return Err(Error::HtlcRange { min: policy.htlc_min_msat, max });
+            }
+        }
+
+        let before = self.active_policies();
+        match scope {
+            PolicyScope::Channel(channel_id) => {
+                self.policies.channels.insert(channel_id, policy);
+            }
+            PolicyScope::Peer(node_id) => {
+                let active = &self.active;
+                self.policies.channels.retain(|channel_id, _| {
+                    active.get(channel_id).map(|channel| channel.info.remote_node) != Some(node_id)
+                });
+                self.policies.peers.insert(node_id, policy);
+            }
+            PolicyScope::All => {
+                self.policies.channels.clear();
+                self.policies.peers.clear();
+                self.policies.default = policy;
+            }
+        }
+        self.save()?;
+
+        let changed = self
+            .active_policies()
+            .into_iter()
+            .filter(|(channel_id, policy)| before.get(channel_id) != Some(policy))
+            .collect::<Vec<_>>();
+        for (channel_id, _) in &changed {
+            if let Some(channel) = self.active.get_mut(channel_id) {
+                channel.pending = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Registers channel which became operational and schedules announcement of its policy.
+    /// Returns the policy in effect for the channel.
+    pub fn register_channel(&mut self, info: LocalChannelInfo, public: bool) -> FeePolicy {
+        let channel_id = info.channel_id;
+        let remote_node = info.remote_node;
+        let known = self.active.get(&channel_id);
+        let announced = known.and_then(|channel| channel.announced);
+        let broadcast = known.map(|channel| channel.broadcast).unwrap_or_default();
+        let channel = ActiveChannel { info, public, broadcast, announced, pending: true };
+        self.active.insert(channel_id, channel);
+        self.policy(channel_id, remote_node).1
+    }
+
+    /// Registers broadcast of the channel announcement and schedules announcement of the channel
+    /// policy to the whole network
+    pub fn channel_announced(&mut self, channel_id: ChannelId) {
+        if let Some(channel) = self.active.get_mut(&channel_id) {
+            channel.broadcast = true;
+            channel.pending = true;
+        }
+    }
+
+    /// Removes closed channel together with the policy set for it
+    pub fn forget_channel(&mut self, channel_id: ChannelId) -> Result<(), Error> {
+        self.active.remove(&channel_id);
+        if self.policies.channels.remove(&channel_id).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Number of the channels which policy announcements are postponed by the rate limit
+    pub fn pending_count(&self) -> usize {
+        self.active.values().filter(|channel| channel.pending).count()
+    }
+
+    /// Constructs `channel_update` messages for the channels which policy has to be announced
+    /// and which were not announced during the last [`UPDATE_INTERVAL`]
+    pub fn due_updates(&mut self, local_node: &LocalNode) -> Vec<PolicyUpdate> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let interval = UPDATE_INTERVAL.as_secs() as u32;
+        let due = self
+            .active
+            .iter()
+            .filter(|(_, channel)| channel.pending)
+            .filter(|(_, channel)| {
+                !matches!(channel.announced, Some(announced) if announced + interval > now)
+            })
+            .map(|(channel_id, _)| *channel_id)
+            .collect::<Vec<_>>();
+
+        let mut updates = vec![];
+        for channel_id in due {
+            let remote_node = self.active[&channel_id].info.remote_node;
+            let (_, policy) = self.policy(channel_id, remote_node);
+            let channel = self.active.get_mut(&channel_id).expect("channel is active");
+            // Timestamps of the updates of the same channel must increase
+            let timestamp = channel.announced.map(|announced| announced + 1).unwrap_or_default();
+            let timestamp = timestamp.max(now);
+            channel.announced = Some(timestamp);
+            channel.pending = false;
+            updates.push(PolicyUpdate {
+                remote_node,
+                public: channel.public && channel.broadcast,
+                message: channel_update(&channel.info, &policy, timestamp, local_node),
+            });
+        }
+        updates
+    }
+
+    /// Lists the policies set by the user and the policies in effect for the active channels
+    pub fn list(&self) -> FeePolicyList {
+        let channels = self
+            .active
+            .iter()
+            .map(|(channel_id, channel)| {
+                let (scope, policy) = self.policy(*channel_id, channel.info.remote_node);
+                ChannelFeePolicy {
+                    channel_id: *channel_id,
+                    remote_node: channel.info.remote_node,
+                    scope,
+                    policy,
+                    announced: channel.announced.map(u64::from),
+                    pending: channel.pending,
+                }
+            })
+            .collect();
+        FeePolicyList {
+            default: self.policies.default,
+            peers: self.policies.peers.clone(),
+            channels,
+        }
+    }
+}
+
+/// Constructs `channel_update` message announcing the channel policy, signed with the node key
+fn channel_update(
+    info: &LocalChannelInfo,
+    policy: &FeePolicy,
+    timestamp: u32,
+    local_node: &LocalNode,
+) -> ChannelUpdate {
+    // The direction bit is set when the update originates from the channel node with the greater
+    // node id
+    let direction = (local_node.node_id().serialize() > info.remote_node.serialize()) as u8;
+    let mut update = ChannelUpdate {
+        signature: Signature::from_compact(&[0u8; 64]).expect("zero signature is well-formed"),
+        chain_hash: info.chain_hash,
+        short_channel_id: info.short_channel_id,
+        timestamp,
+        // The update always carries `htlc_maximum_msat` field
+        message_flags: 1,
+        channel_flags: direction,
+        cltv_expiry_delta: policy.cltv_delta,
+        htlc_minimum_msat: policy.htlc_min_msat,
+        fee_base_msat: policy.base_msat,
+        fee_proportional_millionths: policy.proportional_millionths,
+        htlc_maximum_msat: policy.htlc_max_msat.unwrap_or(info.htlc_maximum_msat),
+    };
+    // The signature commits to the double SHA256 hash of the message data following it
+    let data = update.lightning_serialize().expect("in-memory encoding");
+    let digest = sha256d::Hash::hash(&data[64..]);
+    let message = Message::from_slice(&digest[..]).expect("hash is 32 bytes");
+    update.signature = Secp256k1::signing_only().sign(&message, &local_node.private_key());
+    update
+}
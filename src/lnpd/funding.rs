@@ -21,11 +21,13 @@ use std::{fs, io};
 use amplify::{IoError, Slice32, Wrapper};
 use bitcoin::secp256k1::{self, Secp256k1};
 use bitcoin::util::bip32::ChildNumber;
-use bitcoin::{Address, Network, OutPoint, SigHashType, Txid};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Network, OutPoint, Script, SigHashType, Transaction, TxIn, Txid};
 use bitcoin_hd::{
     DerivationSubpath, DeriveError, DescriptorDerive, SegmentIndexes, TrackingAccount,
     UnhardenedIndex,
 };
+use bitcoin_onchain::blockchain::MiningStatus;
 use bitcoin_onchain::{ResolveUtxo, UtxoResolverError};
 use descriptors::locks::{LockTime, SeqNo};
 use descriptors::InputDescriptor;
@@ -39,9 +41,20 @@ use psbt::Psbt;
 use strict_encoding::{StrictDecode, StrictEncode};
 use wallet::scripts::PubkeyScript;
 
+use crate::chain::{BroadcastError, ChainApi, ChainError};
+use crate::rpc::{ErrorCode, ToRpcError};
+
 // The default fee rate is 2 sats per kilo-vbyte
 const DEFAULT_FEERATE_PER_KW: u32 = 2u32 * 1000 * 4;
 
+/// Number of consecutive addresses without transactions after which wallets stop scanning the
+/// descriptor for funds
+pub const GAP_LIMIT: u32 = 20;
+
+/// Weight of the witness spending commitment transaction anchor output with a signature,
+/// including segwit marker and flag, in weight units
+const ANCHOR_WITNESS_WEIGHT: u64 = 118;
+
 /// Errors working with funding wallet
 #[derive(Debug, Display, Error, From)]
 #[display(doc_comments)]
@@ -64,6 +77,14 @@ pub enum Error {
     #[from]
     Resolver(UtxoResolverError),
 
+    /// error requesting blockchain backend. Details: {0}
+    #[from]
+    Chain(ChainError),
+
+    /// unable to publish transaction: {0}
+    #[from]
+    Broadcast(BroadcastError),
+
     /// funding wallet uses custom descriptor which can't be represented as a
     /// valid bitcoin addresses, making channel funding impossible
     NoAddressRepresentation,
@@ -89,6 +110,38 @@ pub enum Error {
     /// error finalizing transaction, probably not all signatures are present. Details: {0}
     #[from]
     Finalizing(miniscript::psbt::Error),
+
+    /// funding transaction {0} is not known to the funding wallet
+    UnknownFunding(Txid),
+
+    /// {0} issued addresses have not received funds yet; issuing more addresses would exceed
+    /// the gap limit of the wallets restored from the same descriptor, which would not find
+    /// funds sent to them
+    GapLimitReached(u32),
+
+    /// funding transaction {0} has no change output, which is required to bump its fee
+    NoFundingChange(Txid),
+}
+
+impl ToRpcError for Error {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) | Error::StrictEncoding(_) => ErrorCode::Storage,
+            Error::Electrum(_) | Error::Resolver(_) | Error::Chain(_) => ErrorCode::Internal,
+            Error::Broadcast(BroadcastError::Unreachable(_)) => ErrorCode::Internal,
+            Error::Broadcast(_) => ErrorCode::Funding,
+            Error::InsufficientFunds => ErrorCode::InsufficientFunds,
+            Error::UnknownFunding(_) => ErrorCode::NotFound,
+            Error::NoAddressRepresentation
+            | Error::ChainNotSupported
+            | Error::ChainMismatch
+            | Error::Derivation(_)
+            | Error::OutOfIndexes
+            | Error::GapLimitReached(_)
+            | Error::Finalizing(_)
+            | Error::NoFundingChange(_) => ErrorCode::Funding,
+        }
+    }
 }
 
 /// Information about funding which is already used in channels pending
@@ -105,12 +158,29 @@ pub struct PendingFunding {
     pub psbt: Psbt,
 }
 
+/// Funding transaction composed by the wallet, which does not reserve the spent outputs
+#[derive(Clone, Debug)]
+pub struct FundingDraft {
+    /// Unsigned transaction with the channel funding output at index 0
+    pub psbt: Psbt,
+    /// Spent outputs and their amounts
+    pub inputs: Vec<(OutPoint, u64)>,
+    /// Transaction fee, in satoshis
+    pub fee: u64,
+    /// Expected weight of the signed transaction
+    pub weight: u64,
+}
+
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, StrictEncode, StrictDecode)]
 pub struct Funds {
     pub outpoint: OutPoint,
     pub terminal: Vec<UnhardenedIndex>,
     pub script_pubkey: PubkeyScript,
     pub amount: u64,
+    /// Height of the block mining the output, or `None` if the output is not mined yet
+    pub height: Option<u32>,
+    /// Channel which funding transaction spends the output
+    pub reserved_for: Option<TempChannelId>,
 }
 
 #[derive(Clone, Debug, StrictEncode, StrictDecode)]
@@ -126,9 +196,19 @@ pub struct FundingWallet {
     secp: Secp256k1<secp256k1::All>,
     network: bitcoin::Network,
     resolver: ElectrumClient,
+    /// Blockchain backend publishing transactions
+    backend: Box<dyn ChainApi>,
     feerate_per_kw: u32,
     wallet_file: fs::File,
     wallet_data: WalletData,
+    /// Outputs spent by the withdrawal transactions which are being signed. They are not
+    /// persisted, since the signing does not survive the node restart.
+    withdrawals: BTreeMap<Txid, Vec<OutPoint>>,
+    /// Outputs spent by the child transactions paying fees for the channel transactions, with
+    /// the parent outputs the children are spending. The outputs are reserved until the child
+    /// is mined or replaced by a new child for the same parent output. They are not persisted,
+    /// since the children published before the node restart are no longer listed as unspent.
+    children: BTreeMap<Txid, (OutPoint, Vec<OutPoint>)>,
 }
 
 impl FundingWallet {
@@ -137,6 +217,7 @@ impl FundingWallet {
         wallet_path: impl AsRef<Path>,
         descriptor: Descriptor<TrackingAccount>,
         electrum_url: &str,
+        backend: Box<dyn ChainApi>,
     ) -> Result<FundingWallet, Error> {
         info!("Creating funding wallet at '{}'", wallet_path.as_ref().display());
         let wallet_file = fs::File::create(wallet_path)?;
@@ -150,13 +231,14 @@ impl FundingWallet {
         wallet_data.strict_encode(&wallet_file)?;
 
         let network = chain.try_into()?;
-        FundingWallet::init(network, wallet_data, wallet_file, electrum_url)
+        FundingWallet::init(network, wallet_data, wallet_file, electrum_url, backend)
     }
 
     pub fn with(
         chain: &Chain,
         wallet_path: impl AsRef<Path>,
         electrum_url: &str,
+        backend: Box<dyn ChainApi>,
     ) -> Result<FundingWallet, Error> {
         info!("Opening funding wallet at '{}'", wallet_path.as_ref().display());
         let wallet_file =
@@ -168,7 +250,7 @@ impl FundingWallet {
             return Err(Error::ChainMismatch);
         }
 
-        FundingWallet::init(network, wallet_data, wallet_file, electrum_url)
+        FundingWallet::init(network, wallet_data, wallet_file, electrum_url, backend)
     }
 
     fn init(
@@ -176,19 +258,22 @@ impl FundingWallet {
         wallet_data: WalletData,
         wallet_file: fs::File,
         electrum_url: &str,
+        backend: Box<dyn ChainApi>,
     ) -> Result<FundingWallet, Error> {
         info!("Connecting Electrum server at {}", electrum_url);
         let resolver = ElectrumClient::new(electrum_url)?;
 
-        let mut wallet = FundingWallet {
+        let wallet = FundingWallet {
             secp: Secp256k1::new(),
             network,
             resolver,
+            backend,
             wallet_data,
             wallet_file,
             feerate_per_kw: DEFAULT_FEERATE_PER_KW,
+            withdrawals: bmap! {},
+            children: bmap! {},
         };
-        wallet.update_fees()?;
         Ok(wallet)
     }
 
@@ -200,21 +285,11 @@ impl FundingWallet {
         Ok(())
     }
 
-    // TODO: Call update fees from a LNPd on a regular basis
-    pub fn update_fees(&mut self) -> Result<u32, Error> {
-        trace!("Getting fee estimate from the electrum server");
-        let fee_estimate = self.resolver.estimate_fee(1)?;
-        if fee_estimate == -1.0 {
-            debug!(
-                "Electrum server was unable to provide fee estimation, keeping current rate of {} \
-                 per kilo-weight unit",
-                self.feerate_per_kw
-            );
-        } else {
-            self.feerate_per_kw = (fee_estimate * 100_000_000.0 / 4.0) as u32;
-            debug!("Updated fee rate is {} per kilo-weight unit", self.feerate_per_kw);
-        }
-        Ok(self.feerate_per_kw)
+    /// Sets fee rate of the transactions constructed without explicit fee rate, as estimated by
+    /// watchd for the node confirmation target
+    pub fn set_feerate(&mut self, feerate_per_kw: u32) {
+        debug!("Funding wallet fee rate is {} per kilo-weight unit", feerate_per_kw);
+        self.feerate_per_kw = feerate_per_kw;
     }
 
     #[inline]
@@ -226,15 +301,34 @@ impl FundingWallet {
     #[inline]
     pub fn feerate_per_kw(&self) -> u32 { self.feerate_per_kw }
 
-    /// Scans blockchain for available funds.
-    /// Updates last derivation index basing on the scanned information.
+    /// Scans blockchain for available funds, skipping outputs reserved for the pending fundings,
+    /// withdrawals and child transactions. Updates last derivation index basing on the scanned
+    /// information.
     pub fn list_funds(&mut self) -> Result<Vec<Funds>, Error> {
-        let used_outputs: Vec<_> = self
+        let mut funds = self.list_utxos()?;
+        let withdrawals = &self.withdrawals;
+        let children = &self.children;
+        funds.retain(|funds| {
+            funds.reserved_for.is_none()
+                && !withdrawals.values().any(|outpoints| outpoints.contains(&funds.outpoint))
+                && !children.values().any(|(_, outpoints)| outpoints.contains(&funds.outpoint))
+        });
+        Ok(funds)
+    }
+
+    /// Scans blockchain for all funds of the wallet, including outputs reserved for the pending
+    /// fundings. Updates last derivation index basing on the scanned information.
+    pub fn list_utxos(&mut self) -> Result<Vec<Funds>, Error> {
+        let reservations: BTreeMap<OutPoint, TempChannelId> = self
             .wallet_data
             .pending_fundings
             .values()
-            .flat_map(|funding| &funding.prev_outpoints)
+            .flat_map(|funding| {
+                let temp_channel_id = funding.temp_channel_id;
+                funding.prev_outpoints.iter().map(move |outpoint| (*outpoint, temp_channel_id))
+            })
             .collect();
+        let reservations = &reservations;
 
         let lookup =
             |case: UnhardenedIndex, last_index: &mut UnhardenedIndex| -> Result<Vec<_>, Error> {
@@ -245,18 +339,9 @@ impl FundingWallet {
                         &self.wallet_data.descriptor,
                         &[case],
                         UnhardenedIndex::zero(),
-                        last_index.last_index().saturating_add(20),
+                        last_index.last_index().saturating_add(GAP_LIMIT),
                     )?
                     .into_iter()
-                    .map(|mut data| {
-                        let utxo_set = &mut data.1 .1;
-                        *utxo_set = utxo_set
-                            .iter()
-                            .filter(|utxo| !used_outputs.contains(&utxo.outpoint()))
-                            .cloned()
-                            .collect();
-                        data
-                    })
                     .filter(|(_, (_, set))| !set.is_empty())
                     .flat_map(|(index, (script, utxo))| {
                         // Updating last used indexes
@@ -269,6 +354,11 @@ impl FundingWallet {
                             terminal: vec![case, index],
                             script_pubkey: script_pubkey.clone(),
                             amount: utxo.amount().as_sat(),
+                            height: match utxo.mined() {
+                                MiningStatus::Blockchain(height) => Some(height),
+                                _ => None,
+                            },
+                            reserved_for: reservations.get(utxo.outpoint()).copied(),
                         })
                     })
                     .collect())
@@ -287,14 +377,57 @@ impl FundingWallet {
         Ok(funds)
     }
 
+    /// Requests the current blockchain height from Electrum server
+    pub fn tip_height(&self) -> Result<u32, Error> {
+        Ok(self.resolver.block_headers_subscribe()?.height as u32)
+    }
+
     pub fn next_funding_address(&self) -> Result<Address, Error> {
+        self.funding_address(self.wallet_data.last_normal_index)
+    }
+
+    fn funding_address(&self, index: UnhardenedIndex) -> Result<Address, Error> {
         let address = DescriptorDerive::address(&self.wallet_data.descriptor, &self.secp, &[
             UnhardenedIndex::zero(),
-            self.wallet_data.last_normal_index,
+            index,
         ])?;
         Ok(address)
     }
 
+    fn is_address_used(&self, address: &Address) -> Result<bool, Error> {
+        Ok(!self.resolver.script_get_history(&address.script_pubkey())?.is_empty())
+    }
+
+    /// Issues the next funding address which has no transactions, such that it is not issued
+    /// again. Returns the address, its derivation index and the number of issued addresses
+    /// without transactions, including the new one.
+    pub fn issue_address(&mut self) -> Result<(Address, UnhardenedIndex, u32), Error> {
+        // Next funding address is also given out in the funds info, so it may be already used
+        let mut index = self.wallet_data.last_normal_index;
+        let mut address = self.funding_address(index)?;
+        while self.is_address_used(&address)? {
+            index = index.checked_inc().ok_or(Error::OutOfIndexes)?;
+            address = self.funding_address(index)?;
+        }
+
+        let mut unused = 1;
+        let mut prev_index = index;
+        while let Some(index) = prev_index.checked_dec() {
+            if unused > GAP_LIMIT || self.is_address_used(&self.funding_address(index)?)? {
+                break;
+            }
+            unused += 1;
+            prev_index = index;
+        }
+        if unused > GAP_LIMIT {
+            return Err(Error::GapLimitReached(unused - 1));
+        }
+
+        self.wallet_data.last_normal_index = index.checked_inc().ok_or(Error::OutOfIndexes)?;
+        self.save()?;
+        Ok((address, index, unused))
+    }
+
     pub fn construct_funding_psbt(
         &mut self,
         temp_channel_id: TempChannelId,
@@ -302,13 +435,365 @@ impl FundingWallet {
         amount: u64,
         feerate_per_kw: Option<u32>,
     ) -> Result<Psbt, Error> {
+        self.construct_batch_funding_psbt(
+            temp_channel_id,
+            &[(script_pubkey, amount)],
+            feerate_per_kw,
+        )
+    }
+
+    /// Constructs a single transaction funding multiple channels, with the funding outputs
+    /// following in the order of `outputs`. The first output is marked as the channel funding
+    /// output, so each of the other channels has to mark its own output in a copy of the PSBT.
+    ///
+    /// The funding is registered under the temporary id of the first channel of the batch.
+    pub fn construct_batch_funding_psbt(
+        &mut self,
+        temp_channel_id: TempChannelId,
+        outputs: &[(PubkeyScript, u64)],
+        feerate_per_kw: Option<u32>,
+    ) -> Result<Psbt, Error> {
+        let draft = self.compose_funding_psbt(outputs, feerate_per_kw)?;
+        let change_index = self.wallet_data.last_change_index;
+        self.wallet_data.last_change_index =
+            change_index.checked_inc().unwrap_or_else(UnhardenedIndex::zero);
+
+        let psbt = draft.psbt;
+        let txid = psbt.global.unsigned_tx.txid();
+        self.wallet_data.pending_fundings.insert(txid, PendingFunding {
+            temp_channel_id,
+            funding_txid: txid,
+            prev_outpoints: draft.inputs.iter().map(|(outpoint, _)| *outpoint).collect(),
+            psbt: psbt.clone(),
+        });
+
+        Ok(psbt)
+    }
+
+    /// Constructs funding transaction for a channel opened as a dry run. Unlike
+    /// [`FundingWallet::construct_funding_psbt`], the spent outputs are not reserved and the
+    /// change address is not consumed, so the wallet state remains intact.
+    pub fn preview_funding_psbt(
+        &mut self,
+        script_pubkey: PubkeyScript,
+        amount: u64,
+        feerate_per_kw: Option<u32>,
+    ) -> Result<FundingDraft, Error> {
+        self.compose_funding_psbt(&[(script_pubkey, amount)], feerate_per_kw)
+    }
+
+    fn compose_funding_psbt(
+        &mut self,
+        outputs: &[(PubkeyScript, u64)],
+        feerate_per_kw: Option<u32>,
+    ) -> Result<FundingDraft, Error> {
+        let feerate_per_kw = feerate_per_kw.unwrap_or(self.feerate_per_kw);
+        let amount = outputs.iter().map(|(_, amount)| amount).sum::<u64>();
+        // We start with the assumption that we will have four-five inputs and two outputs,
+        // i.e. it is a 2-kw transaction; each additional funding output adds 172 weight units
+        let mut fee_upper_est =
+            (2000 + 172 * (outputs.len() as u64 - 1)) * feerate_per_kw as u64 / 1000;
+        let amount_and_fee = amount + fee_upper_est;
+        // Do coin selection:
+        let mut funds = self.list_funds()?;
+        funds.sort_by_key(|f| f.amount);
+
+        let mut acc = 0u64;
+        let selected = funds
+            .iter()
+            .rev()
+            .take_while(|funding| {
+                if acc >= amount_and_fee {
+                    return false;
+                }
+                acc += funding.amount;
+                true
+            })
+            .collect::<Vec<_>>();
+        if acc < amount_and_fee {
+            return Err(Error::InsufficientFunds);
+        }
+        let inputs = selected
+            .iter()
+            .map(|funds| InputDescriptor {
+                outpoint: funds.outpoint,
+                terminal: DerivationSubpath::from(funds.terminal.clone()),
+                seq_no: SeqNo::with_rbf(0),
+                tweak: None,
+                sighash_type: SigHashType::All,
+            })
+            .collect::<Vec<_>>();
+
+        let change_index = self.wallet_data.last_change_index;
+        let descriptor = &self.wallet_data.descriptor;
+
+        let outputs = outputs
+            .iter()
+            .map(|(script_pubkey, amount)| (script_pubkey.as_inner().clone().into(), *amount))
+            .collect::<Vec<_>>();
+        let (psbt, weight) = loop {
+            trace!("Constructing PSBT with fee {}", fee_upper_est);
+            let mut psbt: Psbt = Psbt::construct(
+                &self.secp,
+                descriptor,
+                LockTime::default(),
+                &inputs,
+                &outputs[..],
+                change_index,
+                fee_upper_est,
+                &self.resolver,
+            )
+            .expect("funding PSBT construction is broken");
+            self.add_root_derivations(&mut psbt);
+            psbt.set_channel_funding_output(0).expect("hardcoded funding output number");
+            let transaction = &psbt.global.unsigned_tx;
+            // If we use non-standard descriptor we assume its witness will weight 256 bytes per
+            // input
+            let tx_weight = transaction.get_weight() as u64;
+            let witness_weight = descriptor.max_satisfaction_weight().unwrap_or(256) * inputs.len();
+            let weight = tx_weight + witness_weight as u64;
+            let precise_fee = weight * feerate_per_kw as u64 / 1000;
+            if precise_fee == fee_upper_est {
+                trace!("Resulting fee matched estimate; exiting PSBT construction cycle");
+                break (psbt, weight);
+            }
+            trace!(
+                "Resulting fee {} didn't match the target {} reconstructing PSBT",
+                precise_fee,
+                fee_upper_est,
+            );
+            fee_upper_est = precise_fee;
+        };
+
+        Ok(FundingDraft {
+            psbt,
+            inputs: selected.iter().map(|funds| (funds.outpoint, funds.amount)).collect(),
+            fee: fee_upper_est,
+            weight,
+        })
+    }
+
+    /// Constructs transaction sending funds to an external address. If `amount` is absent, all
+    /// available funds are sent, with the fee deducted from the sent amount. Returns the PSBT
+    /// together with the sent amount and the fee.
+    ///
+    /// Unless it is a dry run, the outputs spent by the transaction are reserved until the
+    /// transaction is either published or abandoned with [`FundingWallet::release_withdrawal`].
+    pub fn construct_withdrawal_psbt(
+        &mut self,
+        script_pubkey: PubkeyScript,
+        amount: Option<u64>,
+        feerate_per_kw: Option<u32>,
+        dry_run: bool,
+    ) -> Result<(Psbt, u64, u64), Error> {
         let feerate_per_kw = feerate_per_kw.unwrap_or(self.feerate_per_kw);
         // We start with the assumption that we will have four-five inputs and two outputs,
         // i.e. it is a 2-kw transaction
         let mut fee_upper_est = 2u64 * feerate_per_kw as u64;
-        let amount_and_fee = amount + fee_upper_est;
+        // Do coin selection; all funds are spent if no amount is given
+        let mut funds = self.list_funds()?;
+        funds.sort_by_key(|f| f.amount);
+
+        let mut acc = 0u64;
+        let inputs = funds
+            .iter()
+            .rev()
+            .take_while(|funding| {
+                if matches!(amount, Some(amount) if acc >= amount + fee_upper_est) {
+                    return false;
+                }
+                acc += funding.amount;
+                true
+            })
+            .map(|funds| InputDescriptor {
+                outpoint: funds.outpoint,
+                terminal: DerivationSubpath::from(funds.terminal.clone()),
+                seq_no: SeqNo::with_rbf(0),
+                tweak: None,
+                sighash_type: SigHashType::All,
+            })
+            .collect::<Vec<_>>();
+
+        let change_index = self.wallet_data.last_change_index;
+        let descriptor = &self.wallet_data.descriptor;
+        let script_pubkey = script_pubkey.into_inner();
+        let (psbt, sent) = loop {
+            let sent = match amount {
+                Some(amount) if acc >= amount + fee_upper_est => amount,
+                None if acc > fee_upper_est => acc - fee_upper_est,
+                _ => return Err(Error::InsufficientFunds),
+            };
+            trace!("Constructing withdrawal PSBT with fee {}", fee_upper_est);
+            let mut psbt: Psbt = Psbt::construct(
+                &self.secp,
+                descriptor,
+                LockTime::default(),
+                &inputs,
+                &[(script_pubkey.clone().into(), sent)],
+                change_index,
+                fee_upper_est,
+                &self.resolver,
+            )
+            .expect("withdrawal PSBT construction is broken");
+            self.add_root_derivations(&mut psbt);
+            let transaction = &psbt.global.unsigned_tx;
+            let tx_weight = transaction.get_weight() as u64;
+            let witness_weight = descriptor.max_satisfaction_weight().unwrap_or(256) * inputs.len();
+            let precise_fee = (tx_weight + witness_weight as u64) * feerate_per_kw as u64 / 1000;
+            if precise_fee == fee_upper_est {
+                trace!("Resulting fee matched estimate; exiting PSBT construction cycle");
+                break (psbt, sent);
+            }
+            trace!(
+                "Resulting fee {} didn't match the target {} reconstructing PSBT",
+                precise_fee,
+                fee_upper_est,
+            );
+            fee_upper_est = precise_fee;
+        };
+
+        if !dry_run {
+            // Sweeping transaction has no change output
+            if amount.is_some() {
+                self.wallet_data.last_change_index =
+                    change_index.checked_inc().unwrap_or_else(UnhardenedIndex::zero);
+                self.save()?;
+            }
+            let txid = psbt.global.unsigned_tx.txid();
+            self.withdrawals.insert(txid, inputs.iter().map(|inp| inp.outpoint).collect());
+        }
+
+        Ok((psbt, sent, fee_upper_est))
+    }
+
+    /// Releases outputs reserved for the withdrawal transaction, which is either published or
+    /// abandoned. Returns `false` if the transaction is not a withdrawal known to the wallet.
+    pub fn release_withdrawal(&mut self, txid: Txid) -> bool {
+        self.withdrawals.remove(&txid).is_some()
+    }
+
+    /// Checks whether the transaction is a child transaction constructed by the wallet, which
+    /// spent outputs are reserved until it is mined
+    #[inline]
+    pub fn is_child(&self, txid: Txid) -> bool { self.children.contains_key(&txid) }
+
+    /// Releases outputs reserved for the child transaction once it is mined. Returns `false` if
+    /// the transaction is not a child known to the wallet.
+    pub fn release_child(&mut self, txid: Txid) -> bool { self.children.remove(&txid).is_some() }
+
+    /// Constructs child transaction spending the anchor output of a commitment transaction
+    /// together with the funding wallet UTXOs, such that the commitment transaction and its
+    /// child together pay the given feerate. The anchor output must be the only input of the
+    /// provided `anchor_psbt`; its value is returned to the change output.
+    pub fn construct_cpfp_psbt(
+        &mut self,
+        anchor_psbt: Psbt,
+        feerate_per_kw: u32,
+        parent_weight: u64,
+        parent_fee: u64,
+    ) -> Result<Psbt, Error> {
+        self.construct_child_psbt(
+            anchor_psbt,
+            ANCHOR_WITNESS_WEIGHT,
+            feerate_per_kw,
+            parent_weight,
+            parent_fee,
+        )
+    }
+
+    /// Adds the funding wallet UTXOs and change output to the zero-fee second-stage HTLC
+    /// transaction, such that it pays the given feerate. HTLC input and output, signed by the
+    /// remote peer with `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY`, are kept first in the transaction.
+    pub fn fund_htlc_psbt(
+        &mut self,
+        htlc_psbt: Psbt,
+        feerate_per_kw: u32,
+        witness_weight: u64,
+    ) -> Result<Psbt, Error> {
+        self.construct_child_psbt(htlc_psbt, witness_weight, feerate_per_kw, 0, 0)
+    }
+
+    /// Constructs child transaction spending the change output of a published, but not yet
+    /// mined funding transaction, such that the funding transaction and its child together pay
+    /// the given feerate.
+    ///
+    /// Funding transaction can't be replaced with a higher-fee version (RBF): any change to it
+    /// changes its txid and thus the funding outpoint, which is fixed once `funding_created` is
+    /// sent and which both commitment transactions signed by the peers are spending. So the
+    /// funding transaction is kept intact and its fee is bumped by the child instead.
+    pub fn construct_funding_cpfp_psbt(
+        &mut self,
+        funding_txid: Txid,
+        feerate_per_kw: u32,
+    ) -> Result<Psbt, Error> {
+        let funding_psbt = self
+            .get_funding_psbt(funding_txid)
+            .ok_or(Error::UnknownFunding(funding_txid))?
+            .clone();
+        let funding_tx = &funding_psbt.global.unsigned_tx;
+
+        let witness_weight = self.descriptor().max_satisfaction_weight().unwrap_or(256) as u64;
+        let parent_weight =
+            funding_tx.get_weight() as u64 + witness_weight * funding_tx.input.len() as u64;
+        let input_value: u64 = funding_psbt
+            .inputs
+            .iter()
+            .filter_map(|input| input.witness_utxo.as_ref())
+            .map(|prevout| prevout.value)
+            .sum();
+        let output_value: u64 = funding_tx.output.iter().map(|txout| txout.value).sum();
+        let parent_fee = input_value.saturating_sub(output_value);
+
+        let mut change_psbt =
+            funding_change_psbt(&funding_psbt).ok_or(Error::NoFundingChange(funding_txid))?;
+        self.add_root_derivations(&mut change_psbt);
+
+        self.construct_child_psbt(
+            change_psbt,
+            witness_weight,
+            feerate_per_kw,
+            parent_weight,
+            parent_fee,
+        )
+    }
+
+    /// Constructs child transaction spending the parent output, which must be the only input of
+    /// the provided `parent_psbt`, together with the funding wallet UTXOs, such that the parent
+    /// and child transactions together pay the given feerate. Outputs and lock time of the
+    /// `parent_psbt` are preserved, and the rest of the parent output value is returned to the
+    /// change output.
+    fn construct_child_psbt(
+        &mut self,
+        parent_psbt: Psbt,
+        parent_input_weight: u64,
+        feerate_per_kw: u32,
+        parent_weight: u64,
+        parent_fee: u64,
+    ) -> Result<Psbt, Error> {
+        let parent_txin = parent_psbt.global.unsigned_tx.input[0].clone();
+        let parent_input = parent_psbt.inputs[0].clone();
+        let parent_txouts = parent_psbt.global.unsigned_tx.output.clone();
+        let parent_outputs = parent_psbt.outputs.clone();
+        let lock_time = parent_psbt.global.unsigned_tx.lock_time;
+        let parent_value = parent_input
+            .witness_utxo
+            .as_ref()
+            .map(|prevout| prevout.value)
+            .unwrap_or_default()
+            .saturating_sub(parent_txouts.iter().map(|txout| txout.value).sum());
+        // Previous child of the same parent is replaced, so its inputs are reused
+        self.children.retain(|_, (parent, _)| *parent != parent_txin.previous_output);
+
+        // Child pays for the whole package, excluding what was already paid by the parent
+        let child_fee = |child_weight: u64| {
+            package_fee(feerate_per_kw, parent_weight, parent_fee, child_weight)
+        };
+        // We start with the assumption that the child is a 1-kw transaction
+        let mut fee_upper_est = child_fee(1000);
         // Do coin selection:
         let mut funds = self.list_funds()?;
+        // Unconfirmed parent output may already be known to the wallet as one of its UTXOs
+        funds.retain(|f| f.outpoint != parent_txin.previous_output);
         funds.sort_by_key(|f| f.amount);
 
         let mut acc = 0u64;
@@ -316,7 +801,7 @@ impl FundingWallet {
             .iter()
             .rev()
             .take_while(|funding| {
-                if acc >= amount_and_fee {
+                if acc >= fee_upper_est {
                     return false;
                 }
                 acc += funding.amount;
@@ -330,7 +815,7 @@ impl FundingWallet {
                 sighash_type: SigHashType::All,
             })
             .collect::<Vec<_>>();
-        if acc < amount_and_fee {
+        if acc < fee_upper_est {
             return Err(Error::InsufficientFunds);
         }
 
@@ -339,51 +824,35 @@ impl FundingWallet {
             change_index.checked_inc().unwrap_or_else(UnhardenedIndex::zero);
 
         let descriptor = &self.wallet_data.descriptor;
-
-        let mut root_derivations = map![];
-        descriptor.for_each_key(|account| {
-            let account = account.as_key();
-            if let Some(fingerprint) = account.master_fingerprint() {
-                root_derivations
-                    .insert(account.account_fingerprint(), (fingerprint, &account.account_path));
-            }
-            true
-        });
-
-        let script_pubkey = script_pubkey.into_inner();
         let psbt = loop {
-            trace!("Constructing PSBT with fee {}", fee_upper_est);
+            trace!("Constructing CPFP PSBT with fee {}", fee_upper_est);
             let mut psbt: Psbt = Psbt::construct(
                 &self.secp,
                 descriptor,
                 LockTime::default(),
                 &inputs,
-                &[(script_pubkey.clone().into(), amount)],
+                &[],
                 change_index,
                 fee_upper_est,
                 &self.resolver,
             )
-            .expect("funding PSBT construction is broken");
-            // Adding full derivation information to each of the inputs
-            for input in &mut psbt.inputs {
-                for source in input.bip32_derivation.values_mut() {
-                    if let Some((fingerprint, path)) = root_derivations.get(&source.0) {
-                        source.0 = *fingerprint;
-                        source.1 = path
-                            .iter()
-                            .map(ChildNumber::from)
-                            .chain(source.1.into_iter().copied())
-                            .collect();
-                    }
-                }
+            .expect("CPFP PSBT construction is broken");
+            self.add_root_derivations(&mut psbt);
+            if let Some(change) = psbt.global.unsigned_tx.output.first_mut() {
+                change.value += parent_value;
+            }
+            psbt.global.unsigned_tx.lock_time = lock_time;
+            psbt.global.unsigned_tx.input.insert(0, parent_txin.clone());
+            psbt.inputs.insert(0, parent_input.clone());
+            for (index, (txout, output)) in parent_txouts.iter().zip(&parent_outputs).enumerate() {
+                psbt.global.unsigned_tx.output.insert(index, txout.clone());
+                psbt.outputs.insert(index, output.clone());
             }
-            psbt.set_channel_funding_output(0).expect("hardcoded funding output number");
             let transaction = &psbt.global.unsigned_tx;
-            // If we use non-standard descriptor we assume its witness will weight 256 bytes per
-            // input
             let tx_weight = transaction.get_weight() as u64;
             let witness_weight = descriptor.max_satisfaction_weight().unwrap_or(256) * inputs.len();
-            let precise_fee = (tx_weight + witness_weight as u64) * feerate_per_kw as u64 / 1000;
+            let child_weight = tx_weight + witness_weight as u64 + parent_input_weight;
+            let precise_fee = child_fee(child_weight);
             if precise_fee == fee_upper_est {
                 trace!("Resulting fee matched estimate; exiting PSBT construction cycle");
                 break psbt;
@@ -395,28 +864,190 @@ impl FundingWallet {
             );
             fee_upper_est = precise_fee;
         };
-
-        let txid = psbt.global.unsigned_tx.txid();
-        self.wallet_data.pending_fundings.insert(txid, PendingFunding {
-            temp_channel_id,
-            funding_txid: txid,
-            prev_outpoints: inputs.iter().map(|inp| inp.outpoint).collect(),
-            psbt: psbt.clone(),
-        });
+        let outpoints = inputs.iter().map(|input| input.outpoint).collect();
+        self.children
+            .insert(psbt.global.unsigned_tx.txid(), (parent_txin.previous_output, outpoints));
+        self.save()?;
 
         Ok(psbt)
     }
 
+    /// Adds full derivation information, starting from the master key, to each of the inputs
+    fn add_root_derivations(&self, psbt: &mut Psbt) {
+        let mut root_derivations = map![];
+        self.wallet_data.descriptor.for_each_key(|account| {
+            let account = account.as_key();
+            if let Some(fingerprint) = account.master_fingerprint() {
+                root_derivations
+                    .insert(account.account_fingerprint(), (fingerprint, &account.account_path));
+            }
+            true
+        });
+        for input in &mut psbt.inputs {
+            for source in input.bip32_derivation.values_mut() {
+                if let Some((fingerprint, path)) = root_derivations.get(&source.0) {
+                    source.0 = *fingerprint;
+                    source.1 = path
+                        .iter()
+                        .map(ChildNumber::from)
+                        .chain(source.1.into_iter().copied())
+                        .collect();
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn get_funding_psbt(&self, txid: Txid) -> Option<&Psbt> {
         self.wallet_data.pending_fundings.get(&txid).map(|funding| &funding.psbt)
     }
 
-    #[inline]
-    pub fn publish(&self, mut psbt: Psbt) -> Result<(), Error> {
-        miniscript::psbt::finalize(&mut psbt, &self.secp)?;
+    /// Removes information about the funding of a channel which will never be opened, making the
+    /// funding UTXOs it has spent available for other channels.
+    pub fn release_funding(
+        &mut self,
+        temp_channel_id: TempChannelId,
+    ) -> Result<Option<PendingFunding>, Error> {
+        let txid = self
+            .wallet_data
+            .pending_fundings
+            .values()
+            .find(|funding| funding.temp_channel_id == temp_channel_id)
+            .map(|funding| funding.funding_txid);
+        let funding = match txid {
+            Some(txid) => self.wallet_data.pending_fundings.remove(&txid),
+            None => return Ok(None),
+        };
+        self.save()?;
+        Ok(funding)
+    }
+
+    /// Finalizes and broadcasts the transaction, returning it together with its feerate in
+    /// satoshi per kilo-weight unit, which is known only if the PSBT provides values of all the
+    /// spent outputs
+    pub fn publish(&self, mut psbt: Psbt) -> Result<(Transaction, Option<u32>), Error> {
+        // Transactions with custom scripts (like penalty transactions) come already finalized
+        if psbt.inputs.iter().any(|input| input.final_script_witness.is_none()) {
+            miniscript::psbt::finalize(&mut psbt, &self.secp)?;
+        }
+        let input_value = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.as_ref().map(|prevout| prevout.value))
+            .sum::<Option<u64>>();
         let tx = psbt.extract_tx();
-        self.resolver.transaction_broadcast(&tx)?;
-        Ok(())
+        self.backend.broadcast(&tx)?;
+        let output_value: u64 = tx.output.iter().map(|txout| txout.value).sum();
+        let feerate_per_kw = input_value.map(|input_value| {
+            let fee = input_value.saturating_sub(output_value);
+            (fee * 1000 / tx.get_weight() as u64).min(u32::MAX as u64) as u32
+        });
+        Ok((tx, feerate_per_kw))
+    }
+}
+
+/// Computes fee which has to be paid by the child transaction, such that together with its
+/// parent it pays the given feerate, excluding the fee already paid by the parent
+fn package_fee(feerate_per_kw: u32, parent_weight: u64, parent_fee: u64, child_weight: u64) -> u64 {
+    let package_weight = parent_weight + child_weight;
+    (package_weight * feerate_per_kw as u64 / 1000).saturating_sub(parent_fee)
+}
+
+/// Constructs PSBT spending the change output of the funding transaction, which is the only
+/// output belonging to the funding wallet and thus having derivation information. The funding
+/// transaction itself is left intact, so the funding outpoint does not change.
+fn funding_change_psbt(funding_psbt: &Psbt) -> Option<Psbt> {
+    let funding_tx = &funding_psbt.global.unsigned_tx;
+    let vout =
+        funding_psbt.outputs.iter().position(|output| !output.bip32_derivation.is_empty())?;
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(funding_tx.txid(), vout as u32),
+            script_sig: Script::new(),
+            // Signals RBF, such that the child transaction can be replaced by a bigger bump
+            sequence: 0xFFFFFFFD,
+            witness: vec![],
+        }],
+        output: vec![],
+    };
+    let mut change_psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+        .expect("change spending transaction is constructed unsigned");
+    let input = &mut change_psbt.inputs[0];
+    input.witness_utxo = Some(funding_tx.output[vout].clone());
+    input.bip32_derivation = funding_psbt.outputs[vout].bip32_derivation.clone();
+    Some(Psbt::from(change_psbt))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::{PublicKey, TxOut};
+
+    use super::*;
+
+    fn funding_psbt(change_vout: Option<usize>) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::default(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut { value: 100_000, script_pubkey: Script::new() },
+                TxOut { value: 50_000, script_pubkey: Script::new() },
+            ],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        if let Some(vout) = change_vout {
+            let pubkey = PublicKey::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap();
+            let path = DerivationPath::from_str("m/84'/1'/0'/1/0").unwrap();
+            psbt.outputs[vout].bip32_derivation.insert(pubkey, (Fingerprint::default(), path));
+        }
+        Psbt::from(psbt)
+    }
+
+    #[test]
+    fn funding_cpfp_spends_change() {
+        for vout in 0..2 {
+            let funding_psbt = funding_psbt(Some(vout));
+            let funding_tx = &funding_psbt.global.unsigned_tx;
+            let funding_txid = funding_tx.txid();
+
+            let change_psbt = funding_change_psbt(&funding_psbt).unwrap();
+            let child_tx = &change_psbt.global.unsigned_tx;
+            assert_eq!(child_tx.input.len(), 1);
+            assert_eq!(child_tx.input[0].previous_output, OutPoint::new(funding_txid, vout as u32));
+            assert_eq!(change_psbt.inputs[0].witness_utxo.as_ref(), Some(&funding_tx.output[vout]));
+            assert_eq!(
+                change_psbt.inputs[0].bip32_derivation,
+                funding_psbt.outputs[vout].bip32_derivation
+            );
+            // Funding transaction, and thus the funding outpoint, is kept intact
+            assert_eq!(funding_psbt.global.unsigned_tx.txid(), funding_txid);
+        }
+    }
+
+    #[test]
+    fn funding_cpfp_no_change() {
+        assert!(funding_change_psbt(&funding_psbt(None)).is_none());
+    }
+
+    #[test]
+    fn package_fee_excludes_parent_fee() {
+        assert_eq!(package_fee(2500, 600, 0, 400), 2500);
+        assert_eq!(package_fee(2500, 600, 300, 400), 2200);
+        // Parent already pays more than the target package feerate
+        assert_eq!(package_fee(2500, 600, 5000, 400), 0);
     }
 }
@@ -0,0 +1,78 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Indexes of the channel keysets allocated by lnpd.
+//!
+//! Each channel gets the next index, from which signd derives the channel keyset at
+//! `m/9735h/<chain>h/1h/0h/<index>h` path of the master key. Since indexes are sequential, the
+//! keysets of all channels can be derived again from the master key by iterating the indexes,
+//! which makes possible recovering the funds from the master key backup. The next index is
+//! persisted before it is used, so an index is never allocated twice, even after a crash.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use bitcoin::util::bip32::ChildNumber;
+
+/// Allocator of the channel keyset indexes, persisting the next index in the data directory
+#[derive(Debug)]
+pub struct KeyIndex {
+    path: PathBuf,
+    next: u32,
+}
+
+impl KeyIndex {
+    /// Loads the next index from the file, starting with zero if the file does not exist
+    pub fn load(path: PathBuf) -> Result<KeyIndex, io::Error> {
+        let next = match fs::read(&path) {
+            Ok(data) if data.len() == 4 => u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("channel key index file {} is corrupted", path.display()),
+                ))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        Ok(KeyIndex { path, next })
+    }
+
+    /// Allocates the index for a new channel
+    pub fn allocate(&mut self) -> Result<u32, io::Error> {
+        let index = self.next;
+        // Keysets are derived with hardened indexes
+        if ChildNumber::from_hardened_idx(index).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "all channel key indexes are allocated",
+            ));
+        }
+        fs::write(&self.path, (index + 1).to_be_bytes())?;
+        self.next = index + 1;
+        Ok(index)
+    }
+
+    /// Marks the index of a channel recovered from the static backup as used, such that it is
+    /// not allocated to new channels
+    pub fn reserve(&mut self, index: u32) -> Result<(), io::Error> {
+        if index < self.next {
+            return Ok(());
+        }
+        fs::write(&self.path, (index + 1).to_be_bytes())?;
+        self.next = index + 1;
+        Ok(())
+    }
+}
@@ -12,11 +12,23 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+pub mod address_book;
+pub mod announcement;
 pub mod automata;
+pub mod ban_list;
+pub mod bootstrap;
+pub(self) mod batch;
+pub mod channel_backups;
+pub(self) mod channel_type;
 pub(self) mod daemons;
+pub mod fee_policy;
 pub mod funding;
+pub(self) mod key_index;
+pub mod onion_service;
 #[cfg(feature = "server")]
 mod opts;
+pub(self) mod recovery;
+pub mod remote_rpc;
 mod runtime;
 
 pub use daemons::{Daemon, DaemonError};
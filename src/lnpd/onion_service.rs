@@ -0,0 +1,257 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Onion service exposing the peer listener of the node to the Tor network.
+//!
+//! lnpd creates the service with `ADD_ONION` command of the Tor control protocol. The service is
+//! ephemeral: Tor removes it once the control connection is closed, so the connection is kept
+//! open for the whole lifetime of the daemon. The service key generated by Tor is saved to the
+//! data directory, such that the onion address stays the same across the node restarts.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use amplify::hex::ToHex;
+use amplify::IoError;
+use lnp::p2p::legacy::AnnouncedNodeAddr;
+
+use crate::auth::write_secret;
+
+/// Time given to Tor for replying to a control port command
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors setting up the onion service
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error communicating with Tor control port: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// Tor has rejected `{0}` command with code {1}: {2}
+    Rejected(&'static str, String, String),
+
+    /// malformed reply of Tor control port: "{0}"
+    Malformed(String),
+
+    /// Tor control port requires authentication method not supported by the node ({0}); use
+    /// cookie or password authentication
+    UnsupportedAuth(String),
+
+    /// Tor control port requires password; please provide it with `--tor-control-password`
+    PasswordRequired,
+
+    /// unable to read Tor authentication cookie '{0}': {1:?}
+    Cookie(String, IoError),
+
+    /// unable to read onion service key '{0}': {1:?}
+    KeyRead(String, IoError),
+
+    /// unable to save onion service key: {0}
+    KeyWrite(String),
+}
+
+/// Configuration of the onion service
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OnionServiceConfig {
+    /// Address of the Tor control port
+    pub control: SocketAddr,
+
+    /// Password authenticating the node to the Tor control port. If absent, cookie
+    /// authentication is used.
+    pub password: Option<String>,
+
+    /// File keeping the onion service key
+    pub key_file: PathBuf,
+
+    /// Address of the peer listener, to which Tor forwards incoming connections. The onion
+    /// service uses the same port.
+    pub listen: SocketAddr,
+}
+
+/// Running onion service
+#[derive(Debug)]
+pub struct OnionService {
+    /// Onion address of the service, including the port
+    address: String,
+
+    /// Tor control connection, keeping the service alive
+    _control: TcpStream,
+}
+
+impl OnionService {
+    /// Onion address of the service, in `<service_id>.onion:<port>` form
+    pub fn address(&self) -> &str { &self.address }
+
+    /// Onion address of the service in the form used by `node_announcement` message, decoded
+    /// from the base32 service id
+    pub fn announced_addr(&self) -> Option<AnnouncedNodeAddr> {
+        let (service_id, port) = self.address.split_once(".onion:")?;
+        let port = port.parse().ok()?;
+        // Version 3 service id encodes 32-byte public key, 2-byte checksum and 1-byte version
+        if service_id.len() != 56 {
+            return None;
+        }
+        let mut data = Vec::with_capacity(35);
+        let (mut buffer, mut bits) = (0u64, 0u32);
+        for c in service_id.bytes() {
+            let value = match c {
+                b'a'..=b'z' => c - b'a',
+                b'2'..=b'7' => c - b'2' + 26,
+                _ => return None,
+            };
+            buffer = (buffer << 5) | value as u64;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                data.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        let mut ed25519_pubkey = [0u8; 32];
+        ed25519_pubkey.copy_from_slice(&data[..32]);
+        Some(AnnouncedNodeAddr::OnionV3 {
+            ed25519_pubkey,
+            checksum: Some(u16::from_be_bytes([data[32], data[33]])),
+            version: Some(data[34]),
+            port,
+        })
+    }
+}
+
+/// Creates the onion service forwarding to the peer listener
+pub fn start(config: &OnionServiceConfig) -> Result<OnionService, Error> {
+    let stream = TcpStream::connect_timeout(&config.control, CONTROL_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+    let mut control = Control { reader: BufReader::new(stream.try_clone()?), stream };
+
+    control.authenticate(config.password.as_deref())?;
+
+    let key = read_key(&config.key_file)?;
+    let port = config.listen.port();
+    // Listener bound to all interfaces is reached by Tor at the loopback one
+    let target = match config.listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port),
+        _ => config.listen,
+    };
+    let command = format!(
+        "ADD_ONION {} Port={},{}",
+        key.as_deref().unwrap_or("NEW:ED25519-V3"),
+        port,
+        target
+    );
+    let reply = control.command(&command, "ADD_ONION")?;
+
+    let service_id = reply
+        .iter()
+        .find_map(|line| line.strip_prefix("ServiceID="))
+        .ok_or_else(|| Error::Malformed(reply.join("; ")))?;
+    let address = format!("{}.onion:{}", service_id, port);
+    if key.is_none() {
+        let private_key = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("PrivateKey="))
+            .ok_or_else(|| Error::Malformed(reply.join("; ")))?;
+        info!("Saving onion service key to '{}'", config.key_file.display());
+        write_secret(&config.key_file, private_key.as_bytes())
+            .map_err(|err| Error::KeyWrite(err.to_string()))?;
+    }
+
+    Ok(OnionService { address, _control: control.stream })
+}
+
+/// Reads the onion service key, saved in the form accepted by `ADD_ONION` command
+fn read_key(key_file: &Path) -> Result<Option<String>, Error> {
+    if !key_file.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(key_file)
+        .map(|key| Some(key.trim().to_owned()))
+        .map_err(|err| Error::KeyRead(key_file.display().to_string(), err.into()))
+}
+
+struct Control {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Control {
+    /// Sends the command and reads the reply lines, stripped of the status code. The name of the
+    /// command is used for error reporting instead of the command itself, which may contain
+    /// secrets.
+    fn command(&mut self, command: &str, name: &'static str) -> Result<Vec<String>, Error> {
+        self.stream.write_all(format!("{}\r\n", command).as_bytes())?;
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            let line = line.trim_end();
+            // Each reply line starts with three-digit status code followed by a separator, which
+            // is a space for the last line of the reply
+            if line.len() < 4 || !line.is_char_boundary(3) || !line.is_char_boundary(4) {
+                return Err(Error::Malformed(line.to_owned()));
+            }
+            let (code, separator, text) = (&line[..3], &line[3..4], &line[4..]);
+            if code != "250" {
+                return Err(Error::Rejected(name, code.to_owned(), text.to_owned()));
+            }
+            lines.push(text.to_owned());
+            if separator == " " {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Authenticates with one of the methods supported by the control port
+    fn authenticate(&mut self, password: Option<&str>) -> Result<(), Error> {
+        let reply = self.command("PROTOCOLINFO 1", "PROTOCOLINFO")?;
+        let auth = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .ok_or_else(|| Error::Malformed(reply.join("; ")))?;
+        let methods = auth
+            .split(' ')
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .unwrap_or_default()
+            .split(',')
+            .collect::<Vec<_>>();
+        let cookie_file = auth
+            .split_once("COOKIEFILE=\"")
+            .and_then(|(_, file)| file.split('"').next())
+            .map(|file| file.replace("\\\\", "\\"));
+
+        let command = if methods.contains(&"NULL") {
+            s!("AUTHENTICATE")
+        } else if let (Some(password), true) = (password, methods.contains(&"HASHEDPASSWORD")) {
+            format!("AUTHENTICATE \"{}\"", password.replace('\\', "\\\\").replace('"', "\\\""))
+        } else if let (Some(cookie_file), true) = (cookie_file, methods.contains(&"COOKIE")) {
+            let cookie =
+                fs::read(&cookie_file).map_err(|err| Error::Cookie(cookie_file, err.into()))?;
+            format!("AUTHENTICATE {}", cookie.to_hex())
+        } else if methods.contains(&"HASHEDPASSWORD") {
+            return Err(Error::PasswordRequired);
+        } else {
+            return Err(Error::UnsupportedAuth(methods.join(",")));
+        };
+        self.command(&command, "AUTHENTICATE")?;
+        Ok(())
+    }
+}
@@ -12,7 +12,7 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use clap::ValueHint;
 
@@ -52,6 +52,113 @@ pub struct Opts {
     #[clap(short, long, default_value = "9735")]
     pub port: u16,
 
+    /// Listen for incoming peer connections also at the provided socket address.
+    ///
+    /// The argument may be repeated to listen at several interfaces and ports, for instance
+    /// at a LAN interface and at the loopback one used by a reverse proxy. Each socket is
+    /// served by its own peer connection daemon. Sockets may be also added at runtime with
+    /// `lnp-cli listen`.
+    #[clap(long = "listen-addr", value_hint = ValueHint::Hostname)]
+    pub listen_addrs: Vec<SocketAddr>,
+
+    /// Accept incoming peer connections over WebSocket at the provided socket address.
+    ///
+    /// Allows running the node behind infrastructure passing only WebSocket traffic. BOLT-8
+    /// stream is carried in binary WebSocket frames, so the connections are encrypted and
+    /// authenticated as the TCP ones; TLS must be terminated by a reverse proxy. The argument may
+    /// be repeated. Remote peers are connected over WebSocket with `ws://` prefix of the peer
+    /// address.
+    #[clap(long = "listen-ws", value_hint = ValueHint::Hostname)]
+    pub listen_ws: Vec<SocketAddr>,
+
+    /// Start remote RPC listener on the given port, accepting encrypted connections of the
+    /// clients from other machines.
+    ///
+    /// Clients connect the listener with `--connect lnpr://<node_id>@<host>:<port>`, where
+    /// `node_id` is the id of the key authenticating the node to the clients (see
+    /// `--remote-rpc-key`). Connections are encrypted and authenticated with Noise_XK
+    /// handshake, as BOLT-8 peer connections; requests are authorized with RPC tokens.
+    ///
+    /// If the argument is provided in form of flag, without value, uses port `62964`.
+    #[clap(long)]
+    pub remote_rpc: Option<Option<u16>>,
+
+    /// Interface to bind the remote RPC listener to.
+    ///
+    /// Binding to an interface other than the loopback one exposes the node management to the
+    /// network, so the daemon does it only when the interface is given explicitly with this
+    /// argument. Defaults to `127.0.0.1`.
+    #[clap(long, requires = "remote_rpc", value_hint = ValueHint::Hostname)]
+    pub rpc_bind: Option<IpAddr>,
+
+    /// Path to the file with a dedicated key authenticating the node to the remote RPC
+    /// clients, which is generated if the file does not exist.
+    ///
+    /// If the argument is not given, the node key is used.
+    #[clap(long, requires = "remote_rpc", value_hint = ValueHint::FilePath)]
+    pub remote_rpc_key: Option<String>,
+
+    /// Expose the peer listener as Tor onion service, created through the Tor control port
+    /// at the given address.
+    ///
+    /// The onion service forwards to the socket given with `--listen` or, if absent, to the
+    /// first one given with `--listen-addr`; the argument is ignored if the node does not
+    /// listen for incoming connections.
+    ///
+    /// The onion service key is saved to `onion.key` file in the data directory, such that
+    /// the onion address stays the same across the node restarts. If the control port is not
+    /// reachable, the node runs without the onion service.
+    #[clap(long, value_hint = ValueHint::Hostname)]
+    pub tor_control: Option<SocketAddr>,
+
+    /// Password for the Tor control port.
+    ///
+    /// If the argument is not given, cookie authentication is used.
+    #[clap(long, requires = "tor_control", env = "LNP_NODE_TOR_CONTROL_PASSWORD")]
+    pub tor_control_password: Option<String>,
+
+    /// Node alias announced to the network, up to 32 bytes long.
+    #[clap(long, default_value = "", parse(try_from_str = parse_alias))]
+    pub alias: String,
+
+    /// Node color announced to the network, in `RRGGBB` hex form.
+    #[clap(long, default_value = "000000", parse(try_from_str = parse_rgb_color))]
+    pub rgb_color: [u8; 3],
+
+    /// Address the node is reachable at, which is announced to the network. May be repeated.
+    ///
+    /// Listening sockets bound to public IP addresses and the onion service address are
+    /// announced automatically; the argument is needed for the addresses the node is reached at
+    /// through NAT or a reverse proxy.
+    #[clap(long = "announce-addr", value_hint = ValueHint::Hostname)]
+    pub announce_addrs: Vec<SocketAddr>,
+
+    /// Do not look for the first peers of the node in DNS seeds.
+    ///
+    /// Unless the argument is given, a node with an empty address book queries BOLT-10 DNS seeds
+    /// at the start and connects a few random peers from their replies, which provide the node
+    /// with the network gossip. The bootstrap is skipped when all the peers are connected
+    /// through Tor (`--tor-always`), since DNS queries would leak the node IP address.
+    #[clap(long)]
+    pub no_bootstrap: bool,
+
+    /// Domain of the DNS seed queried for the first peers of the node. May be repeated.
+    ///
+    /// Defaults to the well-known seeds for the mainnet and the testnet; there are no default
+    /// seeds for other chains.
+    #[clap(
+        long = "bootstrap-seed",
+        conflicts_with = "no_bootstrap",
+        value_hint = ValueHint::Hostname
+    )]
+    pub bootstrap_seeds: Vec<String>,
+
+    /// Name server resolving the DNS seeds.
+    ///
+    /// Defaults to the first name server from `/etc/resolv.conf`.
+    #[clap(long, conflicts_with = "no_bootstrap", value_hint = ValueHint::Hostname)]
+    pub bootstrap_dns: Option<SocketAddr>,
+
     /// Optional command to execute and exit
     #[clap(subcommand)]
     pub command: Option<Command>,
@@ -60,12 +167,45 @@ pub struct Opts {
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug)]
 pub enum Command {
     /// Initialize data directory
-    Init,
+    Init {
+        /// Descriptor of the funding wallet to use instead of the one derived from the master
+        /// key.
+        ///
+        /// Allows funding channels from a hardware wallet account, which inputs are signed by
+        /// the device configured with `--hwi`. Keys must be given as tracking accounts, in the
+        /// same format as the funding wallet descriptor printed by `lnpd init`.
+        #[clap(long)]
+        funding_descriptor: Option<String>,
+    },
 }
 
 impl Opts {
     pub fn process(&mut self) {
         self.shared.process();
         self.key_opts.process(&self.shared);
+        if let Some(ref mut key_file) = self.remote_rpc_key {
+            self.shared.process_dir(key_file);
+        }
+    }
+}
+
+/// Checks that the node alias fits 32 bytes of `node_announcement` message
+fn parse_alias(s: &str) -> Result<String, String> {
+    if s.len() > 32 {
+        return Err(format!("node alias '{}' is longer than 32 bytes", s));
+    }
+    Ok(s.to_owned())
+}
+
+/// Parses node color given in `RRGGBB` hex form
+fn parse_rgb_color(s: &str) -> Result<[u8; 3], String> {
+    let err = || format!("node color '{}' must be in `RRGGBB` hex form", s);
+    if s.len() != 6 || !s.is_ascii() {
+        return Err(err());
+    }
+    let mut color = [0u8; 3];
+    for (index, component) in color.iter_mut().enumerate() {
+        *component = u8::from_str_radix(&s[index * 2..index * 2 + 2], 16).map_err(|_| err())?;
     }
+    Ok(color)
 }
@@ -0,0 +1,386 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Recovery of channel funds from the static channel backup.
+//!
+//! For each channel of the backup lnpd reconnects the remote peer and sends it
+//! `channel_reestablish` message telling that the local node has lost the channel state
+//! (`option_data_loss_protect`). The remote peer replies with its own `channel_reestablish`
+//! containing its current per-commitment point, after which it is asked with `error` message to
+//! close the channel unilaterally. Once the remote commitment transaction is mined, our
+//! to-remote output is swept to the funding wallet. Funds locked in the pending HTLCs are not
+//! recovered.
+//!
+//! Recoveries are kept in memory only; after the node restart the recovery has to be requested
+//! again.
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use lnp::channel::bolt::LocalPubkey;
+use psbt::Psbt;
+
+use crate::channeld::bolt3::{
+    derive_pubkey, has_anchors, has_static_remotekey, to_remote_script, to_remote_script_pubkey,
+};
+use crate::channeld::{self, ChannelBackup};
+
+/// Weight of the transaction sweeping to-remote output of the remote commitment transaction into
+/// a single P2WPKH output, in weight units. Uses the larger weight of spending P2WSH to-remote
+/// output of the channels with anchor outputs.
+const SWEEP_TX_WEIGHT: u64 = 500;
+
+/// Minimal value of the sweep transaction output
+const SWEEP_DUST_LIMIT: u64 = 354;
+
+/// Channel which funds are recovered from the static channel backup
+pub struct Recovery {
+    pub backup: ChannelBackup,
+
+    /// Our payment basepoint derived by signd for the channel, once it is derived
+    pub payment_basepoint: Option<LocalPubkey>,
+
+    /// Current per-commitment point of the remote peer, once it has replied with its own
+    /// `channel_reestablish`
+    pub remote_per_commitment_point: Option<PublicKey>,
+
+    /// Mined transaction spending the channel funding output, until it is swept
+    pub closing_tx: Option<Transaction>,
+
+    /// Transaction sweeping our output of the closing transaction, while it is signed by signd
+    pub sweep_txid: Option<Txid>,
+}
+
+impl Recovery {
+    pub fn with(backup: ChannelBackup) -> Recovery {
+        Recovery {
+            backup,
+            payment_basepoint: None,
+            remote_per_commitment_point: None,
+            closing_tx: None,
+            sweep_txid: None,
+        }
+    }
+
+    /// Per-commitment point to be used by signd for deriving the key spending our output of the
+    /// remote commitment transaction, or `None` if the key is the payment basepoint itself
+    pub fn signing_point(&self) -> Option<PublicKey> {
+        if has_static_remotekey(self.backup.channel_type) {
+            return None;
+        }
+        let point = self.remote_per_commitment_point;
+        Some(point.expect("funding is watched only once the remote per-commitment point is known"))
+    }
+
+    /// Constructs transaction sweeping our to-remote output of the remote commitment transaction
+    /// to the given script. Requires the payment basepoint and, unless the channel uses static
+    /// remote key, the remote per-commitment point to be known.
+    pub fn compose_sweep(
+        &self,
+        sweep_script: Script,
+        feerate_per_kw: u32,
+    ) -> Result<Psbt, channeld::Error> {
+        let closing_tx = self.closing_tx.as_ref().expect("sweep requires known closing tx");
+        let payment_basepoint =
+            self.payment_basepoint.as_ref().expect("sweep requires derived payment basepoint");
+        let closing_txid = closing_tx.txid();
+        let channel_type = self.backup.channel_type;
+        let anchors = has_anchors(channel_type);
+
+        let payment_pubkey = match self.signing_point() {
+            None => payment_basepoint.key,
+            Some(per_commitment_point) => {
+                let secp = Secp256k1::verification_only();
+                derive_pubkey(&secp, payment_basepoint.key, per_commitment_point)?
+            }
+        };
+        let script_pubkey = to_remote_script_pubkey(payment_pubkey, anchors);
+        let (vout, prevout) = closing_tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, txout)| txout.script_pubkey == script_pubkey)
+            .ok_or(channeld::Error::ToRemoteMismatch(closing_txid))?;
+
+        let fee = feerate_per_kw as u64 * SWEEP_TX_WEIGHT / 1000;
+        let value = prevout.value;
+        if value <= fee + SWEEP_DUST_LIMIT {
+            return Err(channeld::Error::SweepOutputDust { value, fee });
+        }
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(closing_txid, vout as u32),
+                script_sig: Script::new(),
+                // With anchor outputs the to-remote output is delayed by a single block
+                sequence: if anchors { 1 } else { 0xFFFF_FFFD },
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: value - fee, script_pubkey: sweep_script }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+            .expect("sweep transaction is constructed unsigned");
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(prevout.clone());
+        // Signature of P2WPKH output commits to the P2PKH script of the key
+        input.witness_script = Some(if anchors {
+            to_remote_script(payment_pubkey)
+        } else {
+            Script::new_p2pkh(&bitcoin::PublicKey::new(payment_pubkey).pubkey_hash())
+        });
+        // signd uses payment basepoint derivation to produce the spending key
+        input.bip32_derivation.insert(
+            bitcoin::PublicKey::new(payment_basepoint.key),
+            payment_basepoint.source.clone(),
+        );
+        Ok(Psbt::from(psbt))
+    }
+
+    /// Constructs witness of the sweep transaction signed by signd. Returns `None` if signd has
+    /// not signed the transaction.
+    pub fn finalize_sweep(&self, mut psbt: Psbt) -> Option<Psbt> {
+        let input = psbt.inputs.get_mut(0)?;
+        let (pubkey, sig) = input.partial_sigs.iter().next()?;
+        let witness = if has_anchors(self.backup.channel_type) {
+            vec![sig.clone(), input.witness_script.as_ref()?.to_bytes()]
+        } else {
+            vec![sig.clone(), pubkey.to_bytes()]
+        };
+        input.final_script_witness = Some(witness);
+        input.partial_sigs.clear();
+        input.witness_script = None;
+        Some(psbt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::{env, fs, process};
+
+    use amplify::{Slice32, Wrapper};
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+    use internet2::addr::InetSocketAddr;
+    use internet2::{NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
+    use lnp::p2p::legacy::{ChannelId, ChannelType};
+
+    use super::*;
+    use crate::lnpd::channel_backups::ChannelBackups;
+
+    const NODE_SECRET: [u8; 32] = [0x41; 32];
+    /// Remote node id and per-commitment point
+    const POINT_1: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    /// Our payment basepoint
+    const POINT_2: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn backup(no: u8, channel_type: ChannelType) -> ChannelBackup {
+        let node_id = PublicKey::from_str(POINT_1).unwrap();
+        let remote_addr =
+            RemoteSocketAddr::Ftcp(InetSocketAddr::from_str("127.0.0.1:9735").unwrap());
+        ChannelBackup {
+            channel_id: ChannelId::from_inner(Slice32::from_inner([no; 32])),
+            remote_peer: NodeAddr::Remote(RemoteNodeAddr { node_id, remote_addr }),
+            funding_outpoint: OutPoint::new(Txid::from_inner([no; 32]), 0),
+            key_index: no as u32,
+            remote_payment_point: node_id,
+            channel_type,
+        }
+    }
+
+    fn payment_basepoint() -> LocalPubkey {
+        LocalPubkey {
+            key: PublicKey::from_str(POINT_2).unwrap(),
+            source: (Fingerprint::default(), DerivationPath::from_str("m/1'/1'/0'/1'/3'").unwrap()),
+        }
+    }
+
+    /// Remote commitment transaction paying to our to-remote output at index 1
+    fn closing_tx(backup: &ChannelBackup, payment_pubkey: PublicKey, value: u64) -> Transaction {
+        let anchors = has_anchors(backup.channel_type);
+        Transaction {
+            version: 2,
+            lock_time: 0x2000_0000,
+            input: vec![TxIn {
+                previous_output: backup.funding_outpoint,
+                script_sig: Script::new(),
+                sequence: 0x8000_0000,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut { value: 100_000, script_pubkey: Script::new() },
+                TxOut { value, script_pubkey: to_remote_script_pubkey(payment_pubkey, anchors) },
+            ],
+        }
+    }
+
+    fn recovery(backup: ChannelBackup) -> Recovery {
+        let mut recovery = Recovery::with(backup);
+        recovery.payment_basepoint = Some(payment_basepoint());
+        recovery.remote_per_commitment_point = Some(PublicKey::from_str(POINT_1).unwrap());
+        recovery
+    }
+
+    #[test]
+    fn datadir_loss() {
+        let dir = env::temp_dir().join(format!("lnp-node-recovery-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("channel.backup");
+        let key = channeld::backup_key(&SecretKey::from_slice(&NODE_SECRET).unwrap());
+
+        let mut backups = ChannelBackups::load(path.clone(), key).unwrap();
+        assert!(backups.is_empty());
+        let data = backups.register(backup(1, ChannelType::StaticRemotekey)).unwrap().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(backups.register(backup(1, ChannelType::StaticRemotekey)).unwrap().is_none());
+        backups.register(backup(2, ChannelType::Basic)).unwrap().unwrap();
+        let data = backups.register(backup(3, ChannelType::AnchoredZeroFeeHtlc)).unwrap().unwrap();
+        backups.forget(ChannelId::from_inner(Slice32::from_inner([2; 32]))).unwrap().unwrap();
+        assert_eq!(backups.len(), 2);
+        let backup_file = fs::read(&path).unwrap();
+        assert_ne!(backup_file, data);
+
+        // Data directory is lost; the node is restored from the same master key, and thus with
+        // the same node key, on an empty data directory
+        drop(backups);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let key = channeld::backup_key(&SecretKey::from_slice(&NODE_SECRET).unwrap());
+        let restored = ChannelBackups::load(path, key).unwrap();
+        assert!(restored.is_empty());
+        let channels = restored.decrypt(&backup_file).unwrap();
+        assert_eq!(channels, vec![
+            backup(1, ChannelType::StaticRemotekey),
+            backup(3, ChannelType::AnchoredZeroFeeHtlc)
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        for backup in channels {
+            let mut recovery = recovery(backup.clone());
+            let payment_pubkey = payment_basepoint().key;
+            let closing_tx = closing_tx(&backup, payment_pubkey, 50_000);
+            recovery.closing_tx = Some(closing_tx.clone());
+            let psbt = recovery.compose_sweep(Script::new(), 1000).unwrap();
+            let tx = &psbt.global.unsigned_tx;
+            assert_eq!(tx.input[0].previous_output, OutPoint::new(closing_tx.txid(), 1));
+            assert_eq!(tx.output[0].value, 49_500);
+        }
+    }
+
+    #[test]
+    fn sweep_static_remotekey() {
+        let mut recovery = recovery(backup(1, ChannelType::StaticRemotekey));
+        assert_eq!(recovery.signing_point(), None);
+        let payment_pubkey = payment_basepoint().key;
+        let closing_tx = closing_tx(&recovery.backup, payment_pubkey, 50_000);
+        recovery.closing_tx = Some(closing_tx.clone());
+
+        let sweep_script =
+            Script::new_v0_wpkh(&bitcoin::PublicKey::new(payment_pubkey).wpubkey_hash().unwrap());
+        let psbt = recovery.compose_sweep(sweep_script.clone(), 2000).unwrap();
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, OutPoint::new(closing_tx.txid(), 1));
+        assert_eq!(tx.input[0].sequence, 0xFFFF_FFFD);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value, 50_000 - 1000);
+        assert_eq!(tx.output[0].script_pubkey, sweep_script);
+        let input = &psbt.inputs[0];
+        assert_eq!(input.witness_utxo.as_ref(), Some(&closing_tx.output[1]));
+        assert_eq!(
+            input.witness_script,
+            Some(Script::new_p2pkh(&bitcoin::PublicKey::new(payment_pubkey).pubkey_hash()))
+        );
+        let basepoint = payment_basepoint();
+        assert_eq!(
+            input.bip32_derivation.get(&bitcoin::PublicKey::new(basepoint.key)),
+            Some(&basepoint.source)
+        );
+
+        let mut psbt = psbt;
+        let sig = vec![0x30, 0x44, 0x01];
+        psbt.inputs[0].partial_sigs.insert(bitcoin::PublicKey::new(payment_pubkey), sig.clone());
+        let psbt = recovery.finalize_sweep(psbt).unwrap();
+        let input = &psbt.inputs[0];
+        let pubkey = bitcoin::PublicKey::new(payment_pubkey).to_bytes();
+        assert_eq!(input.final_script_witness, Some(vec![sig, pubkey]));
+        assert!(input.partial_sigs.is_empty());
+        assert_eq!(input.witness_script, None);
+    }
+
+    #[test]
+    fn sweep_anchors() {
+        let mut recovery = recovery(backup(1, ChannelType::AnchoredOutputsStaticRemotekey));
+        let payment_pubkey = payment_basepoint().key;
+        let closing_tx = closing_tx(&recovery.backup, payment_pubkey, 50_000);
+        recovery.closing_tx = Some(closing_tx);
+
+        let psbt = recovery.compose_sweep(Script::new(), 1000).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 1);
+        assert_eq!(psbt.inputs[0].witness_script, Some(to_remote_script(payment_pubkey)));
+
+        let mut psbt = psbt;
+        let sig = vec![0x30, 0x44, 0x01];
+        psbt.inputs[0].partial_sigs.insert(bitcoin::PublicKey::new(payment_pubkey), sig.clone());
+        let psbt = recovery.finalize_sweep(psbt).unwrap();
+        let witness_script = to_remote_script(payment_pubkey).to_bytes();
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![sig, witness_script]));
+    }
+
+    #[test]
+    fn sweep_rotating_remotekey() {
+        let mut recovery = recovery(backup(1, ChannelType::Basic));
+        let point = PublicKey::from_str(POINT_1).unwrap();
+        assert_eq!(recovery.signing_point(), Some(point));
+        let secp = Secp256k1::verification_only();
+        let payment_pubkey = derive_pubkey(&secp, payment_basepoint().key, point).unwrap();
+
+        // Output paying to the basepoint itself is not ours for a channel without static remote
+        // key
+        let foreign_tx = closing_tx(&recovery.backup, payment_basepoint().key, 50_000);
+        recovery.closing_tx = Some(foreign_tx.clone());
+        assert!(matches!(
+            recovery.compose_sweep(Script::new(), 1000),
+            Err(channeld::Error::ToRemoteMismatch(txid)) if txid == foreign_tx.txid()
+        ));
+
+        let remote_tx = closing_tx(&recovery.backup, payment_pubkey, 50_000);
+        recovery.closing_tx = Some(remote_tx.clone());
+        let psbt = recovery.compose_sweep(Script::new(), 1000).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input[0].previous_output.txid, remote_tx.txid());
+        assert_eq!(
+            psbt.inputs[0].witness_script,
+            Some(Script::new_p2pkh(&bitcoin::PublicKey::new(payment_pubkey).pubkey_hash()))
+        );
+    }
+
+    #[test]
+    fn sweep_dust() {
+        let mut recovery = recovery(backup(1, ChannelType::StaticRemotekey));
+        let payment_pubkey = payment_basepoint().key;
+        // Fee of 500 sat plus the dust limit of 354 sat
+        recovery.closing_tx = Some(closing_tx(&recovery.backup, payment_pubkey, 854));
+        assert!(matches!(
+            recovery.compose_sweep(Script::new(), 1000),
+            Err(channeld::Error::SweepOutputDust { value: 854, fee: 500 })
+        ));
+        recovery.closing_tx = Some(closing_tx(&recovery.backup, payment_pubkey, 855));
+        let psbt = recovery.compose_sweep(Script::new(), 1000).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 355);
+    }
+}
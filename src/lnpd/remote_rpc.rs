@@ -0,0 +1,159 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Remote RPC listener of lnpd.
+//!
+//! The listener accepts encrypted TCP connections of the remote clients (see
+//! [`crate::rpc::RemoteSession`]) and relays their requests to the node RPC bus, each connection
+//! being served by its own thread connected to the bus as a separate client. Requests are
+//! authenticated with RPC tokens by the daemons serving them, exactly as the requests of the
+//! local clients.
+//!
+//! The listener binds to the loopback interface unless another one is given explicitly, such
+//! that the node is never exposed to the network by a configuration default.
+
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use bitcoin::secp256k1::{self, rand, PublicKey};
+use internet2::LocalNode;
+use strict_encoding::StrictEncode;
+
+use crate::auth::write_secret;
+use crate::peerd::supervisor::read_node_key_file;
+use crate::rpc::{self, Client, RemoteListener, RemoteRequest, RemoteSession, RpcMsg};
+use crate::{Error, LogStyle};
+
+/// Configuration of the remote RPC listener
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RemoteRpcConfig {
+    /// Address the listener is bound to
+    pub bind: SocketAddr,
+
+    /// Whether the bind address was given explicitly by the user, which is required for binding
+    /// to an interface other than loopback one
+    pub explicit_bind: bool,
+
+    /// File with a dedicated key authenticating the node to the remote clients. If absent, the
+    /// node key is used.
+    pub key_file: Option<PathBuf>,
+
+    /// ZMQ socket of the node RPC bus, to which the requests are relayed
+    pub rpc_socket: String,
+}
+
+/// Binds the remote RPC listener and starts a thread accepting the connections
+pub fn spawn(config: RemoteRpcConfig, node_key: &LocalNode) -> Result<(), Error> {
+    if !config.bind.ip().is_loopback() && !config.explicit_bind {
+        return Err(Error::Other(format!(
+            "remote RPC listener is not bound to non-loopback address {} unless it is given \
+             with --rpc-bind argument",
+            config.bind
+        )));
+    }
+
+    let local_node = match config.key_file {
+        Some(ref key_file) => read_or_create_key(key_file)?,
+        None => node_key.clone(),
+    };
+
+    let listener = RemoteListener::bind(config.bind, local_node)?;
+    if !config.bind.ip().is_loopback() {
+        warn!("Remote RPC listener is exposed to the network at {}", config.bind);
+    }
+    info!(
+        "{} at {}",
+        "Remote RPC listener is running".ended(),
+        listener.endpoint()?.promo()
+    );
+
+    let rpc_socket = config.rpc_socket;
+    thread::Builder::new().name(s!("lnpd-remote-rpc")).spawn(move || loop {
+        match listener.accept() {
+            Ok((stream, remote_addr)) => {
+                let rpc_socket = rpc_socket.clone();
+                let local_node = listener.local_node().clone();
+                let spawned = thread::Builder::new()
+                    .name(format!("lnpd-remote-rpc<{}>", remote_addr))
+                    .spawn(move || serve(stream, remote_addr, local_node, rpc_socket));
+                if let Err(err) = spawned {
+                    error!("Unable to serve remote RPC client {}: {}", remote_addr, err);
+                }
+            }
+            Err(err) => error!("Error accepting remote RPC connection: {}", err),
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Serves a single remote client until the connection is closed
+fn serve(stream: TcpStream, remote_addr: SocketAddr, local_node: LocalNode, rpc_socket: String) {
+    debug!("Establishing encrypted session with remote RPC client {}", remote_addr);
+    match relay(stream, remote_addr, &local_node, &rpc_socket) {
+        Ok(()) => {}
+        Err(rpc::Error::Io(_)) | Err(rpc::Error::Remote(_)) => {
+            debug!("Remote RPC client {} has disconnected", remote_addr)
+        }
+        Err(err) => error!("Remote RPC session with {} has failed: {}", remote_addr, err),
+    }
+}
+
+fn relay(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    local_node: &LocalNode,
+    rpc_socket: &str,
+) -> Result<(), rpc::Error> {
+    let mut session = RemoteSession::accept(stream, remote_addr, local_node)?;
+    let mut client = Client::with(rpc_socket)?;
+    info!(
+        "Remote RPC client {} is connected with key {}, relaying requests as client {}",
+        remote_addr,
+        session.remote_key(),
+        client.identity()
+    );
+    session.send_identity(client.identity())?;
+
+    loop {
+        let RemoteRequest { daemon, request } = session.recv_request()?;
+        trace!("Relaying remote request {} to {}", request, daemon);
+        client.set_token(request.token);
+        client.request(daemon, request.msg)?;
+        // Progress reports are followed by the final reply to the request
+        loop {
+            let reply = client.response()?;
+            session.send_reply(&reply)?;
+            if !matches!(reply, RpcMsg::Progress(_)) {
+                break;
+            }
+        }
+    }
+}
+
+/// Reads dedicated remote RPC key, generating it if the file does not exist yet
+fn read_or_create_key(key_file: &Path) -> Result<LocalNode, Error> {
+    if key_file.exists() {
+        return Ok(read_node_key_file(key_file));
+    }
+
+    info!("Generating remote RPC key '{}'", key_file.display());
+    let secp = secp256k1::Secp256k1::signing_only();
+    let seckey = secp256k1::SecretKey::new(&mut rand::thread_rng());
+    let local_node = LocalNode::with(seckey, PublicKey::from_secret_key(&secp, &seckey));
+    let data = local_node.strict_serialize().map_err(Error::Persistence)?;
+    write_secret(key_file, &data)?;
+    Ok(local_node)
+}
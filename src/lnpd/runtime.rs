@@ -13,62 +13,287 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::time::{Duration, SystemTime};
+use std::{fs, iter, mem, process, thread};
 
-use amplify::{DumbDefault, Wrapper};
+use amplify::hex::ToHex;
+use amplify::{DumbDefault, Slice32, Wrapper};
+use bitcoin::consensus;
+use bitcoin::secp256k1::rand::{self, seq::SliceRandom};
+use bitcoin::util::psbt::PartiallySignedTransaction;
 use bitcoin::{secp256k1, Txid};
 use internet2::addr::InetSocketAddr;
-use internet2::{NodeAddr, RemoteSocketAddr};
-use lnp::channel::bolt::{CommonParams, LocalKeyset, PeerParams, Policy};
+use internet2::{
+    zmqsocket, LocalNode, NodeAddr, RemoteNodeAddr, RemoteSocketAddr, ZmqType, ZMQ_CONTEXT,
+};
+use lnp::channel::bolt::{CommonParams, Lifecycle, LocalKeyset, PeerParams, Policy};
+use lnp::features::InitFeatures;
 use lnp::p2p::legacy::{
-    ActiveChannelId, ChannelId, ChannelReestablish, Messages as LnMsg, TempChannelId,
+    ActiveChannelId, ChannelId, ChannelReestablish, ChannelType, Error as PeerError,
+    Messages as LnMsg, NodeAnnouncement, TempChannelId,
 };
 use microservices::esb::{self, Handler};
+use nix::libc;
+use nix::sys::signal::{self, SigHandler, Signal};
+use psbt::Psbt;
 use wallet::address::AddressCompat;
 
 use crate::automata::{Event, StateMachine};
+use crate::channeld::{self, ChannelBackup, ChannelExport};
 use crate::bus::{
-    AcceptChannelFrom, BusMsg, CtlMsg, IntoSuccessOrFalure, ServiceBus, Status, ToProgressOrFalure,
+    AcceptChannelFrom, BusMsg, CtlMsg, IntoSuccessOrFalure, Misbehaviour, ServiceBus, Status,
+    ToProgressOrFalure,
 };
+use crate::chain::{self, BroadcastError};
+use crate::lnpd::address_book::{announced_socket_addr, AddressBook, NodeEntry};
+use crate::lnpd::announcement::{self, AnnouncementConfig, NodeAnnouncer};
 use crate::lnpd::automata::ChannelLauncher;
+use crate::lnpd::ban_list::{BanList, Score};
+use crate::lnpd::batch::{self, FundingBatch};
+use crate::lnpd::bootstrap::{self, BootstrapConfig, BOOTSTRAP_PEERS};
+use crate::lnpd::channel_backups::ChannelBackups;
+use crate::lnpd::channel_type;
 use crate::lnpd::daemons::{Daemon, DaemonHandle};
+use crate::lnpd::fee_policy::{self, FeePolicyBook};
 use crate::lnpd::funding::{self, FundingWallet};
-use crate::opts::LNP_NODE_FUNDING_WALLET;
+use crate::lnpd::key_index::KeyIndex;
+use crate::lnpd::onion_service::{self, OnionService, OnionServiceConfig};
+use crate::lnpd::recovery::Recovery;
+use crate::lnpd::remote_rpc::{self, RemoteRpcConfig};
+use crate::opts::{
+    LNP_NODE_ADDRESS_BOOK, LNP_NODE_BAN_LIST, LNP_NODE_CHANNEL_KEY_INDEX, LNP_NODE_FEE_POLICIES,
+    LNP_NODE_FUNDING_WALLET,
+};
 use crate::peerd::supervisor::read_node_key_file;
-use crate::peerd::PeerSocket;
-use crate::rpc::{ClientId, Failure, FundsInfo, NodeInfo, OptionDetails, RpcMsg, ServiceId};
-use crate::{Config, Endpoints, Error, LogStyle, Responder, Service};
+use crate::peerd::{self, InboundStats, PeerSocket};
+use crate::rpc::{
+    AddressType, AuthError, BanInfo, BanPeer, ChannelBalance, ChannelFilter, ChannelList,
+    ChannelSummary, ClientId, CloseChannel, ConnectPeer, CreateChannel, DisconnectPeer, ErrorCode,
+    EventEncoding, FundsInfo, List, NewAddress, NodeEvent, NodeInfo, OptionDetails, Pagination,
+    PeerFilter, PeerInfo, PeerList, PendingPsbt, PolicyScope, ProvideFunding, ReconnectInfo,
+    RpcError, RpcMsg, ServiceId, SetFeePolicy, SetFees, SyncProgress, ToRpcError, TxDepth,
+    UtxoInfo, Withdraw, Withdrawal,
+};
+use crate::service::BridgeHandler;
+use crate::{ChainBackend, Config, Endpoints, Error, LogStyle, Responder, RpcAuth, Service};
+
+/// Period between checks whether the node has received a termination signal
+const SIGNAL_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Time given to the channel daemons for parking their state during the node shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Period between checks for the stale channel negotiations, channel listings and node info
+/// requests
+const REAPER_PERIOD: Duration = Duration::from_secs(5);
+
+/// Time given to the channel and peer daemons for reporting to the channel and peer listings
+const LISTING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time given to the daemons for reporting their status to the node info request
+const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval before the first attempt of reconnecting a remote peer, doubled with each failed
+/// attempt up to the configured maximum
+const RECONNECT_MIN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time given to the peer daemon for reconnecting a remote peer
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Time given to the peer daemon for reconnecting a remote peer through the Tor proxy, which
+/// has to build a Tor circuit first
+const TOR_RECONNECT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Number of issued funding addresses without transactions, starting from which the client is
+/// warned that the addresses approach the gap limit
+const UNUSED_ADDRESS_WARNING: u32 = 10;
+
+/// Minimal relay feerate, below which the fee estimation floor may not be set, in satoshi per
+/// kilo-weight unit
+const FEERATE_FLOOR: u32 = 253;
+
+/// Set by the signal handler once the node is requested to terminate
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+pub fn run(
+    config: Config,
+    key_file: PathBuf,
+    listen: Vec<RemoteSocketAddr>,
+    remote_rpc: Option<RemoteRpcConfig>,
+    onion_service: Option<OnionServiceConfig>,
+    announcement: AnnouncementConfig,
+    bootstrap: Option<BootstrapConfig>,
+) -> Result<(), Error> {
+    let listens = listen.into_iter().collect::<HashSet<_>>();
+
+    let local_node = read_node_key_file(&key_file);
+    let node_id = local_node.node_id();
 
-pub fn run(config: Config, key_file: PathBuf, listen: Option<SocketAddr>) -> Result<(), Error> {
-    let mut listens = HashSet::with_capacity(1);
-    if let Some(addr) = listen {
-        listens.insert(RemoteSocketAddr::Ftcp(InetSocketAddr::from(addr)));
+    debug!("Binding node event socket {}", config.events_endpoint);
+    let events = ZMQ_CONTEXT.socket(zmq::PUB)?;
+    events.bind(&config.events_endpoint.to_string())?;
+
+    let rpc_auth = RpcAuth::init(&config.data_dir)?;
+
+    if let Some(remote_rpc) = remote_rpc {
+        remote_rpc::spawn(remote_rpc, &local_node)?;
     }
 
-    let node_id = read_node_key_file(&key_file).node_id();
+    // Failure to create the onion service leaves the node reachable at clearnet addresses only
+    let onion_service = onion_service.and_then(|onion_config| {
+        match onion_service::start(&onion_config) {
+            Ok(onion_service) => {
+                let address = onion_service.address();
+                info!("{} at {}", "Onion service is running".ended(), address.promo());
+                Some(onion_service)
+            }
+            Err(err) => {
+                warn!(
+                    "Unable to create onion service through Tor control port {}: {}; the node is \
+                     reachable at clearnet addresses only",
+                    onion_config.control, err
+                );
+                None
+            }
+        }
+    });
+
+    let address_book = AddressBook::load(config.data_dir.join(LNP_NODE_ADDRESS_BOOK))?;
+    let fee_policies = FeePolicyBook::load(config.data_dir.join(LNP_NODE_FEE_POLICIES))?;
+    let ban_list = BanList::load(config.data_dir.join(LNP_NODE_BAN_LIST))?;
+    let key_index = KeyIndex::load(config.data_dir.join(LNP_NODE_CHANNEL_KEY_INDEX))?;
+    let backup_key = channeld::backup_key(&local_node.private_key());
+    let channel_backups = ChannelBackups::load(config.backup_file.clone(), backup_key)?;
+
+    // DNS queries can't be routed through the Tor proxy, so they would leak the node IP address
+    let tor_always = config.tor_proxy.map(|proxy| proxy.always).unwrap_or_default();
+    let bootstrap = bootstrap.filter(|bootstrap| {
+        if !address_book.is_empty() || bootstrap.seeds.is_empty() {
+            return false;
+        }
+        if tor_always {
+            warn!("DNS bootstrap is skipped since all peers are connected through Tor");
+            return false;
+        }
+        true
+    });
 
     let runtime = Runtime {
         identity: ServiceId::LnpBroker,
         config: config.clone(),
         node_key_path: key_file,
         node_id,
+        local_node,
         listens,
+        onion_service,
         started: SystemTime::now(),
         handles: vec![],
         funding_wallet: config.funding_wallet()?,
         channel_params: config.channel_params()?,
         connections: none!(),
+        peer_features: none!(),
         channels: none!(),
+        channel_routes: none!(),
         spawning_peers: none!(),
+        address_book,
+        reconnects: none!(),
+        peer_stats: none!(),
+        ban_list,
+        misbehaviour_scores: none!(),
+        bootstrap: bootstrap.map(bootstrap::spawn),
+        fee_policies,
+        key_index,
+        channel_backups,
+        node_announcer: NodeAnnouncer::with(announcement),
         creating_channels: none!(),
         funding_channels: none!(),
+        funding_batches: none!(),
+        withdrawals: none!(),
+        pending_psbts: none!(),
+        recoveries: none!(),
         accepting_channels: none!(),
         reestablishing_channels: none!(),
+        importing_channels: none!(),
+        channel_activity: none!(),
+        reaped_channels: none!(),
+        channel_peers: none!(),
+        rejected_channels: 0,
+        stopping: None,
+        channel_listings: none!(),
+        peer_listings: none!(),
+        info_requests: none!(),
+        tx_depth_requests: none!(),
+        chain_status_requests: none!(),
+        pending_tx_requests: none!(),
+        chain_sync: none!(),
+        fee_estimate: none!(),
+        events,
+        rpc_auth,
     };
 
-    Service::run(config, runtime, true)
+    debug!("Opening bridge between runtime and signal watcher threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://lnpd-signals")?;
+    rx.bind("inproc://lnpd-signals")?;
+
+    debug!("Starting signal watcher thread");
+    let watcher = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    for sig in &[Signal::SIGTERM, Signal::SIGINT] {
+        // The handler only sets an atomic flag, which is safe to do from a signal handler
+        unsafe { signal::signal(*sig, SigHandler::Handler(handle_terminate)) }
+            .map_err(|err| Error::Other(format!("unable to set signal handler: {}", err)))?;
+    }
+    thread::spawn(move || watch_signals(watcher));
+
+    let mut service = Service::broker(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+extern "C" fn handle_terminate(_: libc::c_int) { TERMINATE.store(true, Ordering::SeqCst); }
+
+/// Notifies the runtime about termination signals. If the channel daemons do not confirm the
+/// shutdown in time, the notification is repeated, which makes the runtime exit anyway. Also
+/// periodically wakes up the runtime for reaping stale channel negotiations.
+fn watch_signals(mut watcher: esb::Controller<ServiceBus, BusMsg, BridgeHandler>) {
+    let mut deadline = None;
+    let mut next_reaping = SystemTime::now() + REAPER_PERIOD;
+    loop {
+        thread::sleep(SIGNAL_CHECK_PERIOD);
+        if next_reaping <= SystemTime::now() {
+            next_reaping = SystemTime::now() + REAPER_PERIOD;
+            let message = BusMsg::Ctl(CtlMsg::Timeout);
+            if let Err(err) = watcher.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+                error!("Signal watcher thread is unable to reach the runtime: {}", err);
+            }
+        }
+        let expired = matches!(deadline, Some(deadline) if deadline <= SystemTime::now());
+        if !TERMINATE.swap(false, Ordering::SeqCst) && !expired {
+            continue;
+        }
+        deadline = Some(SystemTime::now() + SHUTDOWN_TIMEOUT);
+        let message = BusMsg::Ctl(CtlMsg::Shutdown);
+        if let Err(err) = watcher.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+            error!("Signal watcher thread is unable to reach the runtime: {}", err);
+        }
+    }
 }
 
 impl Config {
@@ -76,14 +301,20 @@ impl Config {
         let mut wallet_path = self.data_dir.clone();
         wallet_path.push(LNP_NODE_FUNDING_WALLET);
         debug!("Loading funding wallet from '{}'", wallet_path.display());
-        let funding_wallet = FundingWallet::with(&self.chain, wallet_path, &self.electrum_url)?;
+        let backend = chain::connect(self)?;
+        let funding_wallet =
+            FundingWallet::with(&self.chain, wallet_path, &self.electrum_url, backend)?;
         info!("Funding wallet: {}", funding_wallet.descriptor());
         Ok(funding_wallet)
     }
 
     fn channel_params(&self) -> Result<(Policy, CommonParams, PeerParams), Error> {
         // TODO: Read params from config
-        Ok((Policy::default(), CommonParams::default(), PeerParams::default()))
+        let common_params = CommonParams {
+            channel_type: ChannelType::AnchoredZeroFeeHtlc,
+            ..CommonParams::default()
+        };
+        Ok((Policy::default(), common_params, PeerParams::default()))
     }
 }
 
@@ -92,20 +323,184 @@ pub struct Runtime {
     pub(super) config: Config,
     node_key_path: PathBuf,
     node_id: secp256k1::PublicKey,
+    /// Node keys signing the gossip messages
+    local_node: LocalNode,
     listens: HashSet<RemoteSocketAddr>,
+    /// Onion service exposing the peer listener to the Tor network
+    onion_service: Option<OnionService>,
     started: SystemTime,
     handles: Vec<DaemonHandle<Daemon>>,
     pub(super) funding_wallet: FundingWallet,
     pub(super) channel_params: (Policy, CommonParams, PeerParams),
     connections: HashSet<NodeAddr>,
+    /// Features negotiated with the connected remote peers
+    pub(super) peer_features: HashMap<NodeAddr, InitFeatures>,
     channels: HashSet<ChannelId>,
-    spawning_peers: HashMap<ServiceId, ClientId>,
+    /// Routing table resolving both temporary and permanent channel ids into the identity of the
+    /// channel daemon. A channel daemon keeps its temporary id until it connects under the
+    /// permanent one, so in between both ids are routed to the temporary identity.
+    channel_routes: HashMap<ChannelId, ServiceId>,
+    /// Peer daemons connecting to the remote nodes, with the clients which have requested the
+    /// connections and the deadlines for establishing them
+    spawning_peers: HashMap<ServiceId, (ClientId, Option<SystemTime>)>,
+    /// Persistent address book of the remote nodes, learned from the node announcements and the
+    /// connections
+    address_book: AddressBook,
+    /// Remote peers which have to be reconnected after the connection loss
+    reconnects: HashMap<secp256k1::PublicKey, Reconnect>,
+    /// Connections with the remote peers since lnpd was started, which are not persisted
+    peer_stats: HashMap<secp256k1::PublicKey, ConnectionStats>,
+    /// Persistent ban list of the remote peers violating the protocol
+    ban_list: BanList,
+    /// Misbehaviour scores of the remote peers which have violated the protocol, which are not
+    /// persisted
+    misbehaviour_scores: HashMap<secp256k1::PublicKey, Score>,
+    /// Receiver of the peers found by the DNS bootstrap, until the resolution completes
+    bootstrap: Option<mpsc::Receiver<Vec<RemoteNodeAddr>>>,
+    /// Persistent routing fee policies of the channels
+    fee_policies: FeePolicyBook,
+    /// Allocator of the indexes deriving channel keysets
+    pub(super) key_index: KeyIndex,
+    /// Persistent static backups of the channels
+    channel_backups: ChannelBackups,
+    /// Schedules announcements of the local node
+    node_announcer: NodeAnnouncer,
     creating_channels: HashMap<ServiceId, ChannelLauncher>,
     funding_channels: HashMap<Txid, ChannelLauncher>,
+    /// Batches of channels funded with a single transaction, until the transaction is signed
+    funding_batches: Vec<FundingBatch>,
+    /// Withdrawal transactions being signed by signd, with the clients which have requested them
+    withdrawals: HashMap<Txid, (ClientId, Withdrawal)>,
+    /// Transactions of the watch-only funding wallet awaiting signature by the operator
+    pending_psbts: BTreeMap<Txid, PendingPsbt>,
+    /// Channels which funds are recovered from the static channel backup
+    recoveries: HashMap<ChannelId, Recovery>,
     accepting_channels: HashMap<ServiceId, AcceptChannelFrom>,
     reestablishing_channels: HashMap<ServiceId, (NodeAddr, ChannelReestablish)>,
+    importing_channels: HashMap<ServiceId, (ClientId, Vec<u8>)>,
+    /// Time of the last activity reported by the daemons of the channels which have not yet
+    /// reached the funding stage
+    channel_activity: HashMap<ServiceId, (ActiveChannelId, SystemTime)>,
+    /// Channels which negotiations were abandoned due to inactivity
+    reaped_channels: Vec<ChannelId>,
+    /// Remote peers of the channels known to the node
+    channel_peers: HashMap<ChannelId, NodeAddr>,
+    /// Number of channels proposed by remote peers which were rejected
+    rejected_channels: u64,
+    /// Channel daemons which have not yet confirmed parking their state during the node
+    /// shutdown; `None` unless the node is shutting down
+    stopping: Option<HashSet<ServiceId>>,
+    /// Channel listings requested by the clients which are awaiting for the channel daemons to
+    /// report their channels
+    channel_listings: Vec<Listing<ChannelSummary, ChannelQuery>>,
+    /// Peer listings requested by the clients which are awaiting for the peer daemons to report
+    /// their connections, with the connection statistics taken when the listing was requested
+    peer_listings: Vec<Listing<PeerInfo, PeerListing>>,
+    /// Node info requested by the clients which is awaiting for the daemons to report their
+    /// status
+    info_requests: Vec<InfoRequest>,
+    /// Clients awaiting for watchd to report the number of confirmations of the transactions
+    tx_depth_requests: Vec<(ClientId, Txid)>,
+    /// Clients awaiting for watchd to report the status of its blockchain backend
+    chain_status_requests: Vec<ClientId>,
+    /// Clients awaiting for watchd to report the transactions it rebroadcasts
+    pending_tx_requests: Vec<ClientId>,
+    /// Progress of the blockchain backend synchronization last reported by watchd, if its
+    /// backend gets synchronized before use
+    chain_sync: Option<SyncProgress>,
+    /// Feerate estimation for the node confirmation target last reported by watchd, used for
+    /// the commitment transactions of the new channels
+    pub(super) fee_estimate: Option<u32>,
+    /// Socket publishing node events to the subscribed clients
+    events: zmq::Socket,
+    /// Root key authenticating client requests and minting RPC tokens
+    rpc_auth: RpcAuth,
+}
+
+/// Purpose of the channel listing requested by a client
+enum ChannelQuery {
+    /// Page of the channels passing the filter is sent to the client
+    Listing(ChannelFilter, Pagination),
+
+    /// Funds info is sent to the client with the balances of all channels
+    Funds(FundsInfo),
+
+    /// Summary of a single channel is sent to the client
+    Channel(ChannelId),
+}
+
+/// Listing of channels or peer connections requested by a client
+struct Listing<T, C = ()> {
+    enquirer: ClientId,
+    /// Daemons which have not yet reported their channel or connection
+    pending: HashSet<ServiceId>,
+    /// Items reported so far
+    items: Vec<T>,
+    started: SystemTime,
+    /// Request parameters applied once the listing is completed
+    context: C,
+}
+
+impl<T: Clone, C> Listing<T, C> {
+    fn with(enquirer: ClientId, pending: HashSet<ServiceId>, context: C) -> Listing<T, C> {
+        Listing { enquirer, pending, items: vec![], started: SystemTime::now(), context }
+    }
+
+    /// Registers item reported by a daemon in the listings awaiting for it and takes out the
+    /// listings which got reports from all daemons or have been awaiting for the reports longer
+    /// than [`LISTING_TIMEOUT`]
+    fn take_completed(
+        listings: &mut Vec<Listing<T, C>>,
+        reported: Option<(&ServiceId, T)>,
+    ) -> Vec<Listing<T, C>> {
+        if let Some((daemon, item)) = reported {
+            for listing in listings.iter_mut() {
+                if listing.pending.remove(daemon) {
+                    listing.items.push(item.clone());
+                }
+            }
+        }
+
+        let now = SystemTime::now();
+        let (completed, pending) = mem::take(listings).into_iter().partition(|listing| {
+            listing.pending.is_empty() || listing.started + LISTING_TIMEOUT <= now
+        });
+        *listings = pending;
+        completed
+    }
+}
+
+/// Node info requested by a client
+struct InfoRequest {
+    enquirer: ClientId,
+    /// Peer, channel and chain watch daemons which have not yet reported their status
+    pending: HashSet<ServiceId>,
+    /// Information gathered so far
+    info: NodeInfo,
+    started: SystemTime,
+}
+
+/// Reconnection of a remote peer after the connection loss
+struct Reconnect {
+    /// Number of the reconnection attempts made so far
+    attempts: u32,
+    next_attempt: SystemTime,
 }
 
+/// Connections with a remote peer. Each connection is served by a separate peer daemon, so the
+/// connections are counted by lnpd.
+#[derive(Clone, Debug, Default)]
+struct ConnectionStats {
+    /// Number of the connections which have completed the handshake and the exchange of `init`
+    /// messages
+    handshakes: u32,
+    /// Number of the lost connections by the short name of the disconnection cause
+    disconnects: BTreeMap<String, u64>,
+}
+
+/// Parameters of the peer listing and the connection statistics taken for it
+type PeerListing = (PeerFilter, Pagination, HashMap<secp256k1::PublicKey, ConnectionStats>);
+
 impl Responder for Runtime {}
 
 impl esb::Handler<ServiceBus> for Runtime {
@@ -116,14 +511,17 @@ impl esb::Handler<ServiceBus> for Runtime {
 
     fn on_ready(&mut self, _senders: &mut Endpoints) -> Result<(), Self::Error> {
         info!("Starting signer daemon...");
-        self.launch_daemon(Daemon::Signd, self.config.clone())?;
+        self.launch_daemon(Daemon::Signd(self.node_key_path.clone()), self.config.clone())?;
         info!("Starting routing daemon...");
         self.launch_daemon(Daemon::Routed, self.config.clone())?;
         info!("Starting chain watch daemon...");
-        self.launch_daemon(Daemon::Watchd, self.config.clone())?;
+        self.launch_daemon(Daemon::Watchd(self.node_key_path.clone()), self.config.clone())?;
         for addr in self.listens.clone() {
             self.listen(addr)?;
         }
+        for node_id in self.address_book.reconnected_nodes() {
+            self.schedule_reconnect(node_id);
+        }
         Ok(())
     }
 
@@ -138,12 +536,45 @@ impl esb::Handler<ServiceBus> for Runtime {
             (ServiceBus::Msg, BusMsg::Ln(msg), ServiceId::Peer(remote_peer)) => {
                 self.handle_p2p(endpoints, remote_peer, msg)
             }
+            // Node announcements validated by routed populate the address book used to connect
+            // nodes by their ids
+            (
+                ServiceBus::Msg,
+                BusMsg::Ln(LnMsg::NodeAnnouncement(announcement)),
+                ServiceId::Router,
+            ) => {
+                // Own announcements are relayed back by routed
+                if announcement.node_id != self.node_id {
+                    self.register_node_addresses(&announcement);
+                }
+                Ok(())
+            }
             (ServiceBus::Msg, BusMsg::Ln(_), service) => {
                 unreachable!("lnpd received peer message not from a peerd but from {}", service)
             }
             (ServiceBus::Ctl, BusMsg::Ctl(msg), source) => self.handle_ctl(endpoints, source, msg),
-            (ServiceBus::Rpc, BusMsg::Rpc(msg), ServiceId::Client(client_id)) => {
-                self.handle_rpc(endpoints, client_id, msg)
+            (ServiceBus::Rpc, BusMsg::Request(request), ServiceId::Client(client_id)) => {
+                match self.rpc_auth.authorize(request) {
+                    Ok(msg) => self.handle_rpc(endpoints, client_id, msg),
+                    Err(err) => Ok(self.reject_rpc(endpoints, client_id, err)?),
+                }
+            }
+            (ServiceBus::Rpc, BusMsg::Rpc(_), ServiceId::Client(client_id)) => {
+                Ok(self.reject_rpc(endpoints, client_id, AuthError::Unauthenticated)?)
+            }
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Shutdown), _) => self.shutdown(endpoints),
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) => {
+                self.complete_listings(endpoints, None);
+                self.complete_peer_listings(endpoints, None);
+                self.complete_info_requests(endpoints, None);
+                self.reap_stale_connections(endpoints);
+                self.ping_peers(endpoints);
+                self.expire_bans();
+                self.reconnect_peers();
+                self.bootstrap_peers();
+                self.announce_fee_policies(endpoints);
+                self.announce_node(endpoints);
+                self.reap_stale_channels(endpoints)
             }
             (ServiceBus::Rpc, BusMsg::Rpc(_), service) => {
                 unreachable!("lnpd received RPC message not from a client but from {}", service)
@@ -177,6 +608,24 @@ impl esb::Handler<ServiceBus> for Runtime {
 }
 
 impl Runtime {
+    /// Publishes node event to the subscribed clients in both strict and JSON encodings
+    fn publish_event(&self, event: NodeEvent) {
+        debug!("Publishing node event {}", event);
+        for &encoding in &[EventEncoding::Strict, EventEncoding::Json] {
+            let topic = event.topic(encoding);
+            let payload = match event.serialize(encoding) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    warn!("Unable to encode node event {}: {}", event, err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.events.send_multipart(vec![topic.as_bytes(), &payload[..]], 0) {
+                warn!("Unable to publish node event {} under {}: {}", event, topic, err);
+            }
+        }
+    }
+
     fn handle_p2p(
         &mut self,
         endpoints: &mut Endpoints,
@@ -188,33 +637,120 @@ impl Runtime {
             // Lisnening peerd forwards this request to lnpd so it can launch a new channeld
             // instance.
             LnMsg::OpenChannel(open_channel) => {
+                if let Err(failure) = self.ensure_chain_synced() {
+                    self.rejected_channels += 1;
+                    info!("Rejecting channel proposed by {}: {}", remote_peer, failure.message);
+                    let error = PeerError {
+                        channel_id: open_channel.temporary_channel_id.into(),
+                        data: failure.message.into_bytes(),
+                    };
+                    endpoints.send_to(
+                        ServiceBus::Msg,
+                        self.identity(),
+                        ServiceId::Peer(remote_peer),
+                        BusMsg::Ln(LnMsg::Error(error)),
+                    )?;
+                    return Ok(());
+                }
                 // TODO: Replace with state machine-based workflow
                 info!("Creating channel by peer request from {}", remote_peer);
-                self.launch_daemon(
-                    Daemon::Channeld(open_channel.temporary_channel_id.into()),
-                    self.config.clone(),
-                )?;
-                let channeld_id = ServiceId::Channel(open_channel.temporary_channel_id.into());
+                let temp_channel_id = open_channel.temporary_channel_id;
+                let channeld_id = ServiceId::Channel(temp_channel_id.into());
+                let features = self.peer_features.get(&remote_peer).cloned().unwrap_or_default();
+                let zero_conf = match remote_peer {
+                    NodeAddr::Remote(ref remote_addr) => {
+                        self.config.zero_conf_peers.contains(&remote_addr.node_id)
+                    }
+                    NodeAddr::Local(_) => false,
+                };
+                let mut common_params = self.channel_params.1;
+                common_params.channel_type = open_channel
+                    .channel_type
+                    .unwrap_or_else(|| channel_type::implicit(Some(&features)));
+                // Whether the channel is announced is up to the funder
+                common_params.announce_channel = open_channel.channel_flags & 1 != 0;
+                let peer_channels = self
+                    .channel_peers
+                    .values()
+                    .filter(|peer| is_same_node(peer, &remote_peer))
+                    .count() as u16;
+                let index = self.key_index.allocate()?;
+                self.channel_peers.insert(temp_channel_id.into(), remote_peer.clone());
                 let accept_channel = AcceptChannelFrom {
                     remote_peer,
                     report_to: None,
                     channel_req: open_channel,
                     policy: self.channel_params.0.clone(),
-                    common_params: self.channel_params.1,
+                    common_params,
                     local_params: self.channel_params.2,
-                    // TODO: Remove this field, channeld will derive keyset itself
+                    // Will be replaced with the keyset derived by signd
                     local_keys: LocalKeyset::dumb_default(),
+                    features,
+                    zero_conf,
+                    accept_policy: self.config.accept_policy.clone(),
+                    peer_channels,
                 };
+                self.channel_routes.insert(temp_channel_id.into(), channeld_id.clone());
                 self.accepting_channels.insert(channeld_id, accept_channel);
+                // We launch channeld only once signd has derived the keyset for the channel
+                debug!(
+                    "Asking signd to derive keyset #{} for the channel {}",
+                    index, temp_channel_id
+                );
+                self.send_ctl(
+                    endpoints,
+                    ServiceId::Signer,
+                    CtlMsg::DeriveChannelKeys { channel_id: temp_channel_id.into_inner(), index },
+                )?;
+            }
+
+            // The channel is recovered from the static backup and has no state to reestablish
+            LnMsg::ChannelReestablish(channel_reestablish)
+                if self.recoveries.contains_key(&channel_reestablish.channel_id) =>
+            {
+                let channel_id = channel_reestablish.channel_id;
+                let recovery = self
+                    .recoveries
+                    .get_mut(&channel_id)
+                    .expect("recovery presence is checked above");
+                if recovery.remote_per_commitment_point.is_none() {
+                    info!(
+                        "Remote peer {} has reestablished recovered channel {}; asking it to \
+                         close the channel",
+                        remote_peer, channel_id
+                    );
+                    recovery.remote_per_commitment_point =
+                        Some(channel_reestablish.my_current_per_commitment_point);
+                    let outpoint = recovery.backup.funding_outpoint;
+                    self.send_ctl(
+                        endpoints,
+                        ServiceId::Watch,
+                        CtlMsg::WatchSpending { outpoint, mempool: false },
+                    )?;
+                }
+                // Asking the remote peer to fail the channel by publishing its commitment
+                // transaction; repeated on each reconnection until the funding is spent
+                let error = PeerError {
+                    channel_id,
+                    data: b"channel state is lost; please close the channel unilaterally".to_vec(),
+                };
+                endpoints.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    ServiceId::Peer(remote_peer),
+                    BusMsg::Ln(LnMsg::Error(error)),
+                )?;
             }
 
             LnMsg::ChannelReestablish(channel_reestablish) => {
                 let channel_id = channel_reestablish.channel_id;
-                if let Some(channeld) = self.channels.get(&channel_id) {
+                self.channel_peers.insert(channel_id, remote_peer.clone());
+                self.register_peer_channel(channel_id);
+                if self.channels.contains(&channel_id) {
                     endpoints.send_to(
                         ServiceBus::Msg,
                         ServiceId::Peer(remote_peer),
-                        ServiceId::Channel(*channeld),
+                        self.channel_route(channel_id),
                         BusMsg::Ln(LnMsg::ChannelReestablish(channel_reestablish)),
                     )?;
                 } else {
@@ -232,6 +768,14 @@ impl Runtime {
         Ok(())
     }
 
+    fn register_node_addresses(&mut self, announcement: &NodeAnnouncement) {
+        let node_id = announcement.node_id;
+        let addresses = announcement.addresses.as_inner().iter().filter_map(announced_socket_addr);
+        if let Err(err) = self.address_book.register_announcement(node_id, addresses) {
+            warn!("Unable to register addresses of the node {}: {}", node_id, err);
+        }
+    }
+
     fn handle_rpc(
         &mut self,
         endpoints: &mut Endpoints,
@@ -239,93 +783,351 @@ impl Runtime {
         message: RpcMsg,
     ) -> Result<(), Error> {
         match message {
-            RpcMsg::GetInfo => {
-                let node_info = RpcMsg::NodeInfo(NodeInfo {
-                    node_id: self.node_id,
-                    listens: self.listens.iter().cloned().collect(),
-                    uptime: SystemTime::now()
-                        .duration_since(self.started)
-                        .unwrap_or_else(|_| Duration::from_secs(0)),
-                    since: self
-                        .started
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap_or_else(|_| Duration::from_secs(0))
-                        .as_secs(),
-                    peers: self.connections.iter().cloned().collect(),
-                    channels: self.channels.iter().cloned().collect(),
-                });
-                self.send_rpc(endpoints, client_id, node_info)?;
-            }
-
-            RpcMsg::ListPeers => {
-                let peer_list = self.connections.iter().cloned().collect();
-                self.send_rpc(endpoints, client_id, RpcMsg::PeerList(peer_list))?;
-            }
-
-            RpcMsg::ListChannels => {
-                let channel_list = self.channels.iter().cloned().collect();
-                self.send_rpc(endpoints, client_id, RpcMsg::ChannelList(channel_list))?;
+            RpcMsg::GetInfo => self.request_info(endpoints, client_id),
+
+            RpcMsg::ListPeers(filter, page, reset) => {
+                if let Err(failure) = self.list_peers(endpoints, client_id, filter, page, reset) {
+                    self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                }
+            }
+
+            RpcMsg::ListChannels(filter, page) => {
+                self.list_channels(endpoints, client_id, ChannelQuery::Listing(filter, page))
+            }
+
+            RpcMsg::GetChannel(channel_id) if !self.channel_routes.contains_key(&channel_id) => {
+                let failure = RpcError::new(
+                    ErrorCode::ChannelNotFound,
+                    format!("Channel {} is unknown or its daemon is not running", channel_id),
+                );
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::GetChannel(channel_id) => {
+                self.list_channels(endpoints, client_id, ChannelQuery::Channel(channel_id))
+            }
+
+            RpcMsg::GetTxDepth(txid) => {
+                let message = BusMsg::Ctl(CtlMsg::GetTxDepth(txid));
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), ServiceId::Watch, message)?;
+                self.tx_depth_requests.push((client_id, txid));
+            }
+
+            RpcMsg::GetChainStatus => {
+                let message = BusMsg::Ctl(CtlMsg::GetChainStatus);
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), ServiceId::Watch, message)?;
+                self.chain_status_requests.push(client_id);
+            }
+
+            RpcMsg::ListPendingTxs => {
+                let message = BusMsg::Ctl(CtlMsg::GetPendingTxs);
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), ServiceId::Watch, message)?;
+                self.pending_tx_requests.push(client_id);
             }
 
             RpcMsg::ListFunds => {
-                let bitcoin_funds = self.available_funding()?;
-                let next_address = self.funding_wallet.next_funding_address()?;
-                let funds_info = FundsInfo { bitcoin_funds, asset_funds: none!(), next_address };
-                self.send_rpc(endpoints, client_id, RpcMsg::FundsInfo(funds_info))?;
+                // Funds info is sent once the channel daemons report their balances
+                let funds_info = self.funds_info()?;
+                self.list_channels(endpoints, client_id, ChannelQuery::Funds(funds_info));
             }
 
-            RpcMsg::Listen(addr) if self.listens.contains(&addr) => {
-                let failure = Failure {
-                    code: 1, /* TODO: Update code */
-                    info: format!("Listener on {} already exists, ignoring request", addr),
+            RpcMsg::ListFeePolicies => {
+                let fee_policies = self.fee_policies.list();
+                self.send_rpc(endpoints, client_id, RpcMsg::FeePolicies(fee_policies))?;
+            }
+
+            RpcMsg::SetFeePolicy(set_fee_policy) => {
+                let resp = self.set_fee_policy(endpoints, set_fee_policy);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::SetFees(set_fees) => {
+                let resp = self.set_fees(endpoints, set_fees);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::GetNewAddress(address_type) => {
+                let reply = match self.issue_address(address_type) {
+                    Ok(new_address) => RpcMsg::NewAddress(new_address),
+                    Err(failure) => {
+                        warn!("{}", failure.message.err());
+                        RpcMsg::Failure(failure)
+                    }
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            RpcMsg::BakeToken(permissions) => {
+                info!("{} RPC token with {} permissions", "Baking".promo(), permissions);
+                let token = self.rpc_auth.bake(permissions);
+                self.send_rpc(endpoints, client_id, RpcMsg::Token(token))?;
+            }
+
+            RpcMsg::Withdraw(withdraw) => {
+                if let Err(failure) = self.withdraw(endpoints, client_id, withdraw) {
+                    warn!("{}", failure.message.err());
+                    self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                }
+            }
+
+            RpcMsg::ListPendingPsbts => {
+                let pending = self.pending_psbts.values().cloned().collect();
+                self.send_rpc(endpoints, client_id, RpcMsg::PendingPsbts(pending))?;
+            }
+
+            RpcMsg::SubmitPsbt(psbt) => {
+                let reply = match self.submit_psbt(endpoints, &psbt) {
+                    Ok(txid) => RpcMsg::Success(OptionDetails::with(format!(
+                        "Signed transaction {} is accepted",
+                        txid
+                    ))),
+                    Err(failure) => {
+                        warn!("{}", failure.message.err());
+                        RpcMsg::Failure(failure)
+                    }
                 };
-                warn!("{}", failure.info.err());
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            RpcMsg::Listen(addr) if self.listens.contains(&addr) => {
+                let failure = RpcError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("Listener on {} already exists, ignoring request", addr),
+                );
+                warn!("{}", failure.message.err());
                 self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
             }
             RpcMsg::Listen(addr) => {
                 let addr_str = addr.addr();
-                self.listens.insert(addr);
                 info!(
                     "{} for incoming LN peer connections on {}",
                     "Starting listener".promo(),
                     addr_str
                 );
+                // Each listener is run by its own peerd instance, so the already running
+                // listeners and the connections accepted by them are not affected
                 let resp = self.listen(addr);
                 match resp {
-                    Ok(_) => info!(
-                        "Connection daemon is {} for incoming LN peer connections on {}",
-                        "listening".ended(),
-                        addr_str
-                    ),
+                    Ok(_) => {
+                        self.listens.insert(addr);
+                        info!(
+                            "Connection daemon is {} for incoming LN peer connections on {}",
+                            "listening".ended(),
+                            addr_str
+                        )
+                    }
                     Err(ref err) => error!("{}", err.err()),
                 }
                 self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
             }
 
-            RpcMsg::ConnectPeer(addr) => {
-                info!("{} to remote peer {}", "Connecting".promo(), addr.promoter());
-                let peerd =
-                    Daemon::Peerd(PeerSocket::Connect(addr.clone()), self.node_key_path.clone());
-                let resp = match self.launch_daemon(peerd, self.config.clone()) {
-                    Ok(handle) => {
-                        self.spawning_peers.insert(ServiceId::Peer(addr.into()), client_id);
-                        Ok(format!("Launched new instance of {}", handle))
-                    }
-                    Err(err) => {
-                        error!("{}", err.err());
-                        Err(Error::from(err))
-                    }
-                };
-                self.send_rpc(endpoints, client_id, resp.to_progress_or_failure())?;
+            RpcMsg::ConnectPeer(connect_peer) => {
+                self.connect_peer(endpoints, client_id, connect_peer)?;
+            }
+
+            RpcMsg::DisconnectPeer(disconnect_peer) => {
+                let resp = self.disconnect_peer(endpoints, disconnect_peer);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::PinPeer(node_id) => {
+                let resp = self.pin_peer(node_id, true);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::UnpinPeer(node_id) => {
+                let resp = self.pin_peer(node_id, false);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::ListBans => {
+                let bans = self.list_bans();
+                self.send_rpc(endpoints, client_id, RpcMsg::Bans(bans))?;
+            }
+
+            RpcMsg::BanPeer(ban_peer) => {
+                let resp = self.ban_peer(endpoints, ban_peer);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
+            }
+
+            RpcMsg::UnbanPeer(node_id) => {
+                let resp = self.unban_peer(node_id);
+                self.send_rpc(endpoints, client_id, resp.into_success_or_failure())?;
             }
 
             RpcMsg::CreateChannel(create_channel) => {
+                if let Err(failure) = self.ensure_chain_synced() {
+                    warn!("{}", failure.message.err());
+                    self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                    return Ok(());
+                }
                 info!("Creating channel with {}", create_channel.remote_peer);
+                let remote_peer = create_channel.remote_peer.clone();
                 let launcher = ChannelLauncher::with(endpoints, client_id, create_channel, self)?;
                 let channeld_id = ServiceId::Channel(launcher.channel_id().into());
+                self.channel_routes.insert(launcher.channel_id().into(), channeld_id.clone());
+                self.channel_peers.insert(launcher.channel_id().into(), remote_peer);
                 self.creating_channels.insert(channeld_id, launcher);
             }
 
+            RpcMsg::OpenChannels(requests) => self.open_channels(endpoints, client_id, requests)?,
+
+            RpcMsg::AbortChannel(channel_id) if !self.channel_routes.contains_key(&channel_id) => {
+                let failure = RpcError::new(
+                    ErrorCode::ChannelNotFound,
+                    format!("Channel {} is unknown or its daemon is not running", channel_id),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::AbortChannel(channel_id) => {
+                info!("{} opening of channel {}", "Aborting".promo(), channel_id.promoter());
+                // Channel launcher keeps temporary channel id only until the funding transaction
+                // is constructed
+                let channeld_id = self.channel_route(channel_id);
+                let active_channel_id = match self.creating_channels.get(&channeld_id) {
+                    Some(launcher) if launcher.funding_txid().is_none() => {
+                        let temp_channel_id = TempChannelId::from_inner(channel_id.into_inner());
+                        ActiveChannelId::Temporary(temp_channel_id)
+                    }
+                    _ => ActiveChannelId::Static(channel_id),
+                };
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    channeld_id,
+                    BusMsg::Ctl(CtlMsg::AbortChannel {
+                        channel_id: active_channel_id,
+                        enquirer: client_id,
+                    }),
+                )?;
+            }
+
+            RpcMsg::CloseChannel(close_channel) => {
+                if let Err(failure) = self.close_channel(endpoints, client_id, close_channel) {
+                    warn!("{}", failure.message.err());
+                    self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                }
+            }
+
+            RpcMsg::ProvideFunding(ProvideFunding { channel_id, psbt }) => {
+                let reply = match consensus::deserialize::<PartiallySignedTransaction>(&psbt) {
+                    Ok(psbt) => {
+                        info!(
+                            "{} funding transaction for channel {}",
+                            "Forwarding".promo(),
+                            channel_id.promoter()
+                        );
+                        endpoints.send_to(
+                            ServiceBus::Ctl,
+                            self.identity(),
+                            self.channel_route(channel_id),
+                            BusMsg::Ctl(CtlMsg::FundingProvided(Psbt::from(psbt))),
+                        )?;
+                        // Channel daemon reports further progress to the client which has
+                        // requested the channel opening
+                        RpcMsg::Success(OptionDetails::with(format!(
+                            "Funding transaction is provided to channel {}",
+                            channel_id
+                        )))
+                    }
+                    Err(err) => {
+                        let failure = RpcError::new(
+                            ErrorCode::InvalidRequest,
+                            format!("Funding transaction is not a valid PSBT: {}", err),
+                        );
+                        warn!("{}", failure.message.err());
+                        RpcMsg::Failure(failure)
+                    }
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            RpcMsg::ExportChannel(channel_id) if !self.channels.contains(&channel_id) => {
+                let failure = RpcError::new(
+                    ErrorCode::ChannelNotFound,
+                    format!("Channel {} is unknown or its daemon is not running", channel_id),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::ExportChannel(channel_id) => {
+                info!("{} state of channel {}", "Exporting".promo(), channel_id.promoter());
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    self.channel_route(channel_id),
+                    BusMsg::Ctl(CtlMsg::ExportChannelState { channel_id, enquirer: client_id }),
+                )?;
+            }
+
+            RpcMsg::ImportChannel(data) => self.import_channel(endpoints, client_id, data)?,
+
+            RpcMsg::RecoverChannels(data) => self.recover_channels(endpoints, client_id, data)?,
+
+            RpcMsg::DumpCommitment(channel_id) if !self.channels.contains(&channel_id) => {
+                let failure = RpcError::new(
+                    ErrorCode::ChannelNotFound,
+                    format!("Channel {} is unknown or its daemon is not running", channel_id),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::DumpCommitment(channel_id) => {
+                warn!("{} commitment transaction of channel {}", "Dumping".err(), channel_id);
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    self.channel_route(channel_id),
+                    BusMsg::Ctl(CtlMsg::DumpCommitment { channel_id, enquirer: client_id }),
+                )?;
+            }
+
+            RpcMsg::ExportChannelSecrets(channel_id) if !self.config.unsafe_export => {
+                let failure = RpcError::new(
+                    ErrorCode::PolicyViolation,
+                    s!("Export of channel secrets is disabled; start the node with \
+                        `--unsafe-export` to allow it"),
+                );
+                warn!("Refusing to export secrets of channel {}: {}", channel_id, failure.message);
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::ExportChannelSecrets(channel_id) if !self.channels.contains(&channel_id) => {
+                let failure = RpcError::new(
+                    ErrorCode::ChannelNotFound,
+                    format!("Channel {} is unknown or its daemon is not running", channel_id),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            }
+            RpcMsg::ExportChannelSecrets(channel_id) => {
+                warn!("{} secrets of channel {}", "Exporting".err(), channel_id);
+                let message = CtlMsg::ExportChannelSecrets { channel_id, enquirer: client_id };
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    self.channel_route(channel_id),
+                    BusMsg::Ctl(message),
+                )?;
+            }
+
+            RpcMsg::ChannelHistory(channel_id) => {
+                // History is read from disk, such that it is available for channels which daemons
+                // have already terminated, including failed channel negotiations
+                let history_file = self.config.channel_history_file(channel_id);
+                let reply = match channeld::read_history(&history_file) {
+                    Ok(events) => RpcMsg::ChannelEvents(events.into_iter().collect()),
+                    Err(err) => {
+                        let failure = RpcError::new(
+                            ErrorCode::Storage,
+                            format!("Channel {} history is corrupted: {}", channel_id, err),
+                        );
+                        warn!("{}", failure.message.err());
+                        RpcMsg::Failure(failure)
+                    }
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
             wrong_msg => {
                 error!("Request is not supported by the RPC interface");
                 return Err(Error::wrong_esb_msg(ServiceBus::Rpc, &wrong_msg));
@@ -344,6 +1146,29 @@ impl Runtime {
         match &message {
             CtlMsg::Hello => self.handle_hello(endpoints, source)?,
 
+            CtlMsg::Keyset(ServiceId::Channel(channel_id), keyset)
+                if self.recoveries.contains_key(channel_id) =>
+            {
+                let recovery = self
+                    .recoveries
+                    .get_mut(channel_id)
+                    .expect("recovery presence is checked above");
+                recovery.payment_basepoint = Some(keyset.payment_basepoint.clone());
+                self.sweep_recovered_channel(endpoints, *channel_id)?;
+            }
+
+            CtlMsg::Keyset(service_id, keyset)
+                if self.accepting_channels.contains_key(service_id) =>
+            {
+                let accept_channel = self
+                    .accepting_channels
+                    .get_mut(service_id)
+                    .expect("accepting channel presence is checked above");
+                accept_channel.local_keys = keyset.clone();
+                let temp_channel_id = accept_channel.channel_req.temporary_channel_id;
+                self.launch_daemon(Daemon::Channeld(temp_channel_id.into()), self.config.clone())?;
+            }
+
             CtlMsg::Keyset(service_id, _) => {
                 let service_id = service_id.clone();
                 let launcher = self
@@ -356,6 +1181,22 @@ impl Runtime {
                 self.creating_channels.insert(service_id, launcher);
             }
 
+            CtlMsg::ConstructFunding(fund_channel) if self.batch_index(&source).is_some() => {
+                let index = self.batch_index(&source).expect("batch presence is checked above");
+                let batch = &mut self.funding_batches[index];
+                if batch.register_funding(&source, fund_channel.clone()) {
+                    self.construct_batch_funding(endpoints, index)?;
+                } else {
+                    let enquirer = batch.enquirer;
+                    let msg = format!(
+                        "Remote peer accepted {}; awaiting for {} more channels of the batch",
+                        source,
+                        batch.unfunded_count()
+                    );
+                    self.report_batch_progress(endpoints, enquirer, msg);
+                }
+            }
+
             CtlMsg::ConstructFunding(_) => {
                 let launcher = self
                     .creating_channels
@@ -368,6 +1209,27 @@ impl Runtime {
                     .insert(ChannelId::from_inner(launcher.channel_id()).into(), launcher);
             }
 
+            CtlMsg::ConstructFundingDryRun(_) => {
+                let launcher = self
+                    .creating_channels
+                    .remove(&source)
+                    .unwrap_or_else(|| panic!("unregistered channel launcher for {}", source));
+                let channel_id = ChannelId::from_inner(launcher.channel_id());
+                let result = launcher
+                    .next(Event::with(endpoints, self.identity(), source.clone(), message), self);
+                // Channel daemon of the dry run is stopped by the launcher, whether the funding
+                // transaction was composed or not
+                self.channels.remove(&channel_id);
+                self.channel_peers.remove(&channel_id);
+                self.channel_routes.retain(|_, route| *route != source);
+                self.channel_activity.remove(&source);
+                result?;
+            }
+
+            CtlMsg::PublishFunding if self.batch_index(&source).is_some() => {
+                self.publish_batch_funding(endpoints, source)?;
+            }
+
             CtlMsg::PublishFunding => {
                 let launcher = self
                     .creating_channels
@@ -376,42 +1238,543 @@ impl Runtime {
                 let launcher = launcher
                     .next(Event::with(endpoints, self.identity(), source.clone(), message), self)?
                     .expect("channel launcher should not be complete");
-                let txid =
-                    launcher.funding_txid().expect("funding txid must be known at this stage");
-                self.funding_channels.insert(txid, launcher);
+                self.register_funding(launcher);
             }
 
-            CtlMsg::Signed(psbt) => {
+            CtlMsg::PublishTx(psbt) => {
                 let txid = psbt.global.unsigned_tx.txid();
-                let launcher = self
-                    .funding_channels
-                    .remove(&txid)
-                    .unwrap_or_else(|| panic!("unregistered channel launcher for {}", source));
-                let none = launcher
-                    .next(Event::with(endpoints, self.identity(), source.clone(), message), self)?;
-                debug_assert!(
-                    matches!(none, None),
-                    "Channel launcher must complete upon publishing funding transaction"
+                info!("{} transaction {} for {}", "Publishing".promo(), txid.promoter(), source);
+                match self.funding_wallet.publish(psbt.clone()) {
+                    Ok((tx, feerate_per_kw)) => {
+                        let message = CtlMsg::Rebroadcast { tx, owner: source, feerate_per_kw };
+                        self.send_ctl(endpoints, ServiceId::Watch, message)?;
+                        // Wallet UTXOs spent by the child stay reserved until it is mined
+                        if self.funding_wallet.is_child(txid) {
+                            let message = CtlMsg::Track { txid, depth: 1 };
+                            self.send_ctl(endpoints, ServiceId::Watch, message)?;
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Transaction {} for {} is not published: {}", txid, source, err);
+                        // Rejections are reported to the requesting service, which may act upon
+                        // them
+                        let failure = match err {
+                            funding::Error::Broadcast(BroadcastError::MempoolConflict) => {
+                                CtlMsg::TxConflict(txid)
+                            }
+                            funding::Error::Broadcast(BroadcastError::InsufficientFee(_)) => {
+                                CtlMsg::TxFeeTooLow(txid)
+                            }
+                            err => CtlMsg::PublishFailed { txid, error: err.to_string() },
+                        };
+                        let message = BusMsg::Ctl(failure);
+                        endpoints.send_to(ServiceBus::Ctl, self.identity(), source, message)?;
+                    }
+                }
+            }
+
+            CtlMsg::GetSweepAddress => {
+                let address = self.funding_wallet.next_funding_address()?;
+                debug!("Providing address {} for {} to sweep funds", address, source);
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    BusMsg::Ctl(CtlMsg::SweepAddress(address.script_pubkey().into())),
+                )?;
+            }
+
+            CtlMsg::ConstructCpfp { psbt, feerate_per_kw, parent_weight, parent_fee } => {
+                let psbt = self.funding_wallet.construct_cpfp_psbt(
+                    psbt.clone(),
+                    *feerate_per_kw,
+                    *parent_weight,
+                    *parent_fee,
+                )?;
+                debug!(
+                    "Constructed CPFP transaction {} for {}",
+                    psbt.global.unsigned_tx.txid(),
+                    source
                 );
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    BusMsg::Ctl(CtlMsg::CpfpConstructed(psbt)),
+                )?;
             }
 
-            CtlMsg::Error { destination, .. } | CtlMsg::EsbError { destination, .. } => {
-                let launcher = self
-                    .creating_channels
-                    .remove(destination)
-                    .unwrap_or_else(|| panic!("unregistered channel launcher for {}", destination));
-                // We swallow `None` here
-                let _ = launcher.next(
-                    Event::with(endpoints, self.identity(), destination.clone(), message),
-                    self,
+            CtlMsg::ConstructFundingCpfp { txid, feerate_per_kw } => {
+                let psbt =
+                    self.funding_wallet.construct_funding_cpfp_psbt(*txid, *feerate_per_kw)?;
+                debug!(
+                    "Constructed CPFP transaction {} for funding transaction {}",
+                    psbt.global.unsigned_tx.txid(),
+                    txid
                 );
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    BusMsg::Ctl(CtlMsg::CpfpConstructed(psbt)),
+                )?;
             }
 
-            CtlMsg::Report(report) => {
-                let msg = match &report.status {
-                    Status::Progress(msg) => RpcMsg::Progress(msg.clone()),
-                    Status::Success(msg) => RpcMsg::Success(msg.clone()),
-                    Status::Failure(msg) => RpcMsg::Failure(msg.clone()),
+            CtlMsg::FundHtlcTx { psbt, feerate_per_kw, witness_weight } => {
+                let psbt = self.funding_wallet.fund_htlc_psbt(
+                    psbt.clone(),
+                    *feerate_per_kw,
+                    *witness_weight,
+                )?;
+                debug!(
+                    "Funded HTLC transaction {} for {}",
+                    psbt.global.unsigned_tx.txid(),
+                    source
+                );
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    BusMsg::Ctl(CtlMsg::HtlcTxFunded(psbt)),
+                )?;
+            }
+
+            CtlMsg::TxConfirmed(status) if self.funding_wallet.release_child(status.txid) => {
+                debug!("Child transaction {} is mined, releasing its inputs", status.txid);
+                self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::Untrack(status.txid))?;
+            }
+
+            CtlMsg::PeerReconnected(NodeAddr::Remote(remote_addr), _)
+                if self.ban_list.is_banned(&remote_addr.node_id) =>
+            {
+                warn!("Disconnecting banned remote peer {}", remote_addr.node_id);
+                self.send_ctl(endpoints, source, CtlMsg::Banned(s!("the node is banned")))?;
+            }
+
+            CtlMsg::PeerMisbehaved(misbehaviour) => {
+                self.score_misbehaviour(endpoints, source, misbehaviour)?;
+            }
+
+            CtlMsg::PeerReconnected(remote_peer, features) => {
+                debug!("Remote peer {} has negotiated features {:?}", remote_peer, features);
+                self.peer_features.insert(remote_peer.clone(), features.clone());
+                if let NodeAddr::Remote(remote_addr) = remote_peer {
+                    self.peer_stats.entry(remote_addr.node_id).or_default().handshakes += 1;
+                }
+                // We do not know which of the channels are with this peer, so we notify all of
+                // them. Router relays the gossip to the connected peers.
+                let channels = self.channels.iter().copied().map(ServiceId::Channel);
+                for service in channels.chain(iter::once(ServiceId::Router)) {
+                    endpoints.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        service,
+                        BusMsg::Ctl(message.clone()),
+                    )?;
+                }
+                self.reestablish_recoveries(endpoints, remote_peer)?;
+                self.publish_event(NodeEvent::PeerConnected { remote_peer: remote_peer.clone() });
+            }
+
+            CtlMsg::PeerDisconnected(remote_peer, cause) => {
+                if let ServiceId::Peer(connection_id) = &source {
+                    self.connections.remove(connection_id);
+                }
+                self.peer_features.remove(remote_peer);
+                info!(
+                    "Connection {} is lost ({}); total {} connections are known",
+                    remote_peer,
+                    cause,
+                    self.connections.len()
+                );
+                if let NodeAddr::Remote(remote_addr) = remote_peer {
+                    self.count_disconnect(remote_addr.node_id, cause);
+                }
+                // Channel daemons filter out notifications about other peers
+                let channels = self.channels.iter().copied().map(ServiceId::Channel);
+                for service in channels.chain(iter::once(ServiceId::Router)) {
+                    endpoints.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        service,
+                        BusMsg::Ctl(message.clone()),
+                    )?;
+                }
+                if let NodeAddr::Remote(remote_addr) = remote_peer {
+                    let node_id = remote_addr.node_id;
+                    let node = self.address_book.node(&node_id);
+                    if node.map(NodeEntry::is_reconnected).unwrap_or_default() {
+                        self.schedule_reconnect(node_id);
+                    }
+                }
+                let remote_peer = remote_peer.clone();
+                self.publish_event(NodeEvent::PeerDisconnected { remote_peer });
+            }
+
+            CtlMsg::NodeEvent(event) => {
+                if let NodeEvent::ChannelLifecycle { channel_id, lifecycle, .. } = event {
+                    if *lifecycle == Lifecycle::Closed.to_string() {
+                        self.forget_peer_channel(*channel_id);
+                        self.forget_channel_backup(*channel_id);
+                        if let Err(err) = self.fee_policies.forget_channel(*channel_id) {
+                            warn!("Unable to remove fee policy of channel {}: {}", channel_id, err);
+                        }
+                    }
+                }
+                self.publish_event(event.clone())
+            }
+
+            CtlMsg::BackupChannel(backup) => self.backup_channel(backup.clone()),
+
+            CtlMsg::ShutdownAck => self.complete_shutdown(Some(source.clone())),
+
+            CtlMsg::ChannelActive { channel_info, public } => {
+                let fee_policy = self.fee_policies.register_channel(channel_info.clone(), *public);
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    source,
+                    BusMsg::Ctl(CtlMsg::SetFeePolicy(fee_policy)),
+                )?;
+                self.announce_fee_policies(endpoints);
+            }
+
+            CtlMsg::ComposeAnnouncement { channel_info, local_funding_key, remote_funding_key } => {
+                let announcement = announcement::channel_announcement(
+                    &self.local_node,
+                    channel_info,
+                    *local_funding_key,
+                    *remote_funding_key,
+                );
+                self.send_ctl(endpoints, source, CtlMsg::AnnouncementSigned(announcement))?;
+            }
+
+            CtlMsg::ChannelAnnounced(announcement) => {
+                endpoints.send_to(
+                    ServiceBus::Msg,
+                    self.identity(),
+                    ServiceId::Router,
+                    BusMsg::Ln(LnMsg::ChannelAnnouncement(announcement.clone())),
+                )?;
+                // Channel updates are broadcast by routed from now on
+                if let ServiceId::Channel(channel_id) = source {
+                    self.fee_policies.channel_announced(channel_id);
+                }
+                self.node_announcer.channel_announced();
+                self.announce_fee_policies(endpoints);
+                self.announce_node(endpoints);
+            }
+
+            CtlMsg::ChannelRenamed(temp_channel_id) => match &source {
+                ServiceId::Channel(channel_id) => {
+                    // Renamed channel has reached the funding stage and is not reaped anymore
+                    let temp_channeld = ServiceId::Channel((*temp_channel_id).into());
+                    self.channel_activity.remove(&temp_channeld);
+                    // Launcher of the externally funded channel is not needed anymore since the
+                    // funding transaction is not constructed by the node
+                    self.creating_channels.remove(&temp_channeld);
+                    self.update_chanel_id(*temp_channel_id, *channel_id);
+                }
+                _ => warn!("Channel rename notification from non-channel daemon {}", source),
+            },
+
+            CtlMsg::ChannelHeartbeat { channel_id, last_activity, funding_started } => {
+                if *funding_started {
+                    self.channel_activity.remove(&source);
+                } else {
+                    let since_epoch = Duration::from_secs(*last_activity);
+                    let last_activity = SystemTime::UNIX_EPOCH + since_epoch;
+                    self.channel_activity.insert(source.clone(), (*channel_id, last_activity));
+                }
+            }
+
+            CtlMsg::ChannelRejected { channel_id, reason } => {
+                self.rejected_channels += 1;
+                self.channel_peers.remove(&ChannelId::from(*channel_id));
+                info!("Channel {} proposed by remote peer is rejected: {}", channel_id, reason);
+            }
+
+            CtlMsg::SetChannelPolicy(accept_policy) => {
+                info!("{} policy for accepting channels", "Updating".promo());
+                debug!("New channel accept policy: {:?}", accept_policy);
+                self.config.accept_policy = accept_policy.clone();
+            }
+
+            CtlMsg::FundingReleased(_) if self.batch_index(&source).is_some() => {
+                self.creating_channels.remove(&source);
+                let index = self.batch_index(&source).expect("batch presence is checked above");
+                let batch = self.funding_batches.remove(index);
+                let reason = format!("{} has abandoned the channel", source);
+                self.fail_batch(endpoints, batch, Some(&source), reason)?;
+            }
+
+            CtlMsg::FundingReleased(temp_channel_id) => {
+                self.creating_channels.remove(&source);
+                match self.funding_wallet.release_funding(*temp_channel_id)? {
+                    Some(funding) => info!(
+                        "{} {} funding UTXOs reserved for abandoned channel {}",
+                        "Released".ended(),
+                        funding.prev_outpoints.len(),
+                        temp_channel_id.ender()
+                    ),
+                    None => debug!(
+                        "No funding was constructed for abandoned channel {}",
+                        temp_channel_id
+                    ),
+                }
+            }
+
+            CtlMsg::OutpointSpent { outpoint, spending_txid, tx, .. } => {
+                let channel_id = match self
+                    .recoveries
+                    .values()
+                    .find(|recovery| recovery.backup.funding_outpoint == *outpoint)
+                {
+                    Some(recovery) => recovery.backup.channel_id,
+                    None => {
+                        warn!("Spending of unknown outpoint {} is reported", outpoint);
+                        return Ok(());
+                    }
+                };
+                info!(
+                    "Funding of recovered channel {} is {} by transaction {}",
+                    channel_id,
+                    "spent".ended(),
+                    spending_txid
+                );
+                let recovery =
+                    self.recoveries.get_mut(&channel_id).expect("recovery presence is checked");
+                recovery.closing_tx = Some(tx.clone());
+                self.sweep_recovered_channel(endpoints, channel_id)?;
+            }
+
+            CtlMsg::Signed(psbt)
+                if self.recovery_by_sweep(psbt.global.unsigned_tx.txid()).is_some() =>
+            {
+                let txid = psbt.global.unsigned_tx.txid();
+                let channel_id =
+                    self.recovery_by_sweep(txid).expect("recovery presence is checked above");
+                let recovery =
+                    self.recoveries.remove(&channel_id).expect("recovery presence is checked");
+                match recovery.finalize_sweep(psbt.clone()) {
+                    None => warn!("Signer has not signed sweep transaction {}", txid),
+                    Some(psbt) => match self.funding_wallet.publish(psbt) {
+                        Ok((tx, feerate_per_kw)) => {
+                            info!(
+                                "{} funds of recovered channel {} with transaction {}",
+                                "Swept".ended(),
+                                channel_id.ender(),
+                                txid
+                            );
+                            let owner = self.identity();
+                            let message = CtlMsg::Rebroadcast { tx, owner, feerate_per_kw };
+                            self.send_ctl(endpoints, ServiceId::Watch, message)?;
+                        }
+                        Err(err) => warn!("Unable to publish sweep transaction {}: {}", txid, err),
+                    },
+                }
+                self.forget_peer_channel(channel_id);
+                self.forget_channel_backup(channel_id);
+            }
+
+            CtlMsg::SignFailed { txid, error } if self.recovery_by_sweep(*txid).is_some() => {
+                let channel_id =
+                    self.recovery_by_sweep(*txid).expect("recovery presence is checked above");
+                self.recoveries.remove(&channel_id);
+                warn!(
+                    "Sweep transaction {} of recovered channel {} is not signed: {}; the recovery \
+                     has to be requested again",
+                    txid, channel_id, error
+                );
+            }
+
+            CtlMsg::Signed(psbt)
+                if self.withdrawals.contains_key(&psbt.global.unsigned_tx.txid()) =>
+            {
+                let txid = psbt.global.unsigned_tx.txid();
+                let (enquirer, withdrawal) =
+                    self.withdrawals.remove(&txid).expect("withdrawal presence is checked");
+                self.funding_wallet.release_withdrawal(txid);
+                info!("{} withdrawal transaction {}", "Publishing".promo(), txid.promoter());
+                let reply = match self.funding_wallet.publish(psbt.clone()) {
+                    Ok((tx, feerate_per_kw)) => {
+                        let owner = self.identity();
+                        let message = CtlMsg::Rebroadcast { tx, owner, feerate_per_kw };
+                        self.send_ctl(endpoints, ServiceId::Watch, message)?;
+                        RpcMsg::Withdrawal(withdrawal)
+                    }
+                    Err(err) => {
+                        let failure = RpcError::new(
+                            ErrorCode::Funding,
+                            format!("Unable to publish withdrawal transaction: {}", err),
+                        );
+                        warn!("{}", failure.message.err());
+                        RpcMsg::Failure(failure)
+                    }
+                };
+                self.send_rpc(endpoints, enquirer, reply)?;
+            }
+
+            CtlMsg::SignFailed { txid, error } if self.withdrawals.contains_key(txid) => {
+                let (enquirer, _) =
+                    self.withdrawals.remove(txid).expect("withdrawal presence is checked");
+                self.funding_wallet.release_withdrawal(*txid);
+                let failure = RpcError::new(
+                    ErrorCode::SignerUnavailable,
+                    format!("Withdrawal transaction {} is not signed: {}", txid, error),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+            }
+
+            CtlMsg::SignRefused { txid, error } if self.withdrawals.contains_key(txid) => {
+                let (enquirer, _) =
+                    self.withdrawals.remove(txid).expect("withdrawal presence is checked");
+                self.funding_wallet.release_withdrawal(*txid);
+                warn!("Withdrawal transaction {} is refused by signer: {}", txid, error.err());
+                let failure = RpcError::from(error).with_source(&source);
+                self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+            }
+
+            CtlMsg::SignFailed { txid, .. } | CtlMsg::SignRefused { txid, .. } => {
+                match self.funding_channels.remove(txid) {
+                    Some(launcher) => {
+                        let none = launcher.next(
+                            Event::with(endpoints, self.identity(), source.clone(), message),
+                            self,
+                        )?;
+                        debug_assert!(
+                            matches!(none, None),
+                            "Channel launcher must complete upon signing failure"
+                        );
+                    }
+                    None => warn!("Signing of unknown transaction {} has failed", txid),
+                }
+            }
+
+            CtlMsg::Signed(psbt) => {
+                let txid = psbt.global.unsigned_tx.txid();
+                let launcher = self
+                    .funding_channels
+                    .remove(&txid)
+                    .unwrap_or_else(|| panic!("unregistered channel launcher for {}", source));
+                let none = launcher
+                    .next(Event::with(endpoints, self.identity(), source.clone(), message), self)?;
+                debug_assert!(
+                    matches!(none, None),
+                    "Channel launcher must complete upon publishing funding transaction"
+                );
+            }
+
+            CtlMsg::ChannelSummary(summary) => {
+                self.complete_listings(endpoints, Some((&source, summary.clone())));
+                self.complete_info_requests(endpoints, Some((&source, &message)));
+            }
+
+            CtlMsg::PeerInfo(peer_info) => {
+                self.complete_peer_listings(endpoints, Some((&source, peer_info.clone())));
+                self.complete_info_requests(endpoints, Some((&source, &message)));
+            }
+
+            CtlMsg::ChainInfo { .. } | CtlMsg::SignerInfo { .. } => {
+                self.complete_info_requests(endpoints, Some((&source, &message)));
+            }
+
+            CtlMsg::TxDepth(tx_depth) => self.complete_tx_depth_requests(endpoints, *tx_depth),
+
+            CtlMsg::ChainSync(sync) => {
+                if !sync.synced {
+                    debug!("Blockchain backend is syncing: {}", sync);
+                } else if !matches!(self.chain_sync, Some(ref prev) if prev.synced) {
+                    info!("Blockchain backend is {} ({})", "synced".ended(), sync);
+                }
+                self.chain_sync = Some(sync.clone());
+            }
+
+            CtlMsg::FeeEstimate { confirmation_target, feerate_per_kw } => {
+                debug!(
+                    "Fee estimation for {} blocks is {} sat/kw",
+                    confirmation_target, feerate_per_kw
+                );
+                self.fee_estimate = Some(*feerate_per_kw);
+                self.funding_wallet.set_feerate(*feerate_per_kw);
+            }
+
+            CtlMsg::PendingTxs(txs) => {
+                for enquirer in mem::take(&mut self.pending_tx_requests) {
+                    let reply = RpcMsg::PendingTxs(txs.iter().cloned().collect());
+                    // If the client is disconnected, just swallow the error
+                    if self.send_rpc(endpoints, enquirer, reply).is_err() {
+                        error!("Client #{} got disconnected", enquirer);
+                    }
+                }
+            }
+
+            CtlMsg::TxEvicted(txid) => {
+                warn!(
+                    "Transaction {} is evicted from the mempool; it is rebroadcast until it gets \
+                     mined",
+                    txid
+                );
+            }
+
+            CtlMsg::ChainStatus(status) => {
+                for enquirer in mem::take(&mut self.chain_status_requests) {
+                    let reply = RpcMsg::ChainStatus(status.clone());
+                    // If the client is disconnected, just swallow the error
+                    if self.send_rpc(endpoints, enquirer, reply).is_err() {
+                        error!("Client #{} got disconnected", enquirer);
+                    }
+                }
+            }
+
+            CtlMsg::Error { destination, .. } | CtlMsg::EsbError { destination, .. } => {
+                // Offline daemon will not report its status to the listings and info requests
+                for listing in &mut self.channel_listings {
+                    listing.pending.remove(destination);
+                }
+                for listing in &mut self.peer_listings {
+                    listing.pending.remove(destination);
+                }
+                for request in &mut self.info_requests {
+                    if request.pending.remove(destination) {
+                        request.info.warnings.push(format!("{} is unreachable", destination));
+                    }
+                }
+                self.complete_listings(endpoints, None);
+                self.complete_peer_listings(endpoints, None);
+                self.complete_info_requests(endpoints, None);
+                if *destination == ServiceId::Watch {
+                    self.fail_tx_depth_requests(endpoints);
+                    self.fail_chain_status_requests(endpoints);
+                    self.fail_pending_tx_requests(endpoints);
+                }
+                if let Some(index) = self.batch_index(destination) {
+                    let batch = self.funding_batches.remove(index);
+                    let reason = format!("{} is unreachable", destination);
+                    self.fail_batch(endpoints, batch, Some(destination), reason)?;
+                }
+                // The failed daemon may be a channel daemon renamed while the message was in
+                // flight, in which case there is no launcher to notify
+                let launcher = match self.creating_channels.remove(destination) {
+                    Some(launcher) => launcher,
+                    None => {
+                        warn!("Message to {} failed, but it has no channel launcher", destination);
+                        return Ok(());
+                    }
+                };
+                // We swallow `None` here
+                let _ = launcher.next(
+                    Event::with(endpoints, self.identity(), destination.clone(), message),
+                    self,
+                );
+            }
+
+            CtlMsg::Report(report) => {
+                let msg = match &report.status {
+                    Status::Progress(msg) => RpcMsg::Progress(msg.clone()),
+                    Status::Success(msg) => RpcMsg::Success(msg.clone()),
+                    Status::Failure(msg) => RpcMsg::Failure(msg.clone()),
                 };
                 // If the client is disconnected, just swallow the error - there is no reason to
                 // propagate it anywhere
@@ -433,6 +1796,18 @@ impl Runtime {
         info!("{} daemon is {}", source.ended(), "connected".ended());
 
         self.register_daemon(source.clone());
+        if let ServiceId::Peer(NodeAddr::Remote(ref remote_addr)) = source {
+            self.register_connection(remote_addr);
+        }
+
+        // Channel daemon connects under the permanent channel id right before sending
+        // `funding_created` to the remote peer, which commits the funding batch of the channel
+        if let Some(index) = self.batch_index(&source) {
+            let batch = &mut self.funding_batches[index];
+            if batch.channel(&source).and_then(|channel| channel.channel_id).is_some() {
+                batch.committed = true;
+            }
+        }
 
         if let Some(channel_launcher) = self.creating_channels.remove(&source) {
             // Tell channeld channel options and link it with the peer daemon
@@ -471,9 +1846,20 @@ impl Runtime {
                 source.clone(),
                 BusMsg::Ln(LnMsg::ChannelReestablish(channel_reestablish)),
             )?;
-        } else if let Some(enquirer) = self.spawning_peers.get(&source).copied() {
+        } else if let Some((enquirer, data)) = self.importing_channels.remove(&source) {
+            debug!("Ordering {} to import the channel state", source);
+            let channel_id = match source {
+                ServiceId::Channel(channel_id) => channel_id,
+                _ => unreachable!("channel state import is registered for a non-channel daemon"),
+            };
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                source.clone(),
+                BusMsg::Ctl(CtlMsg::ImportChannelState { channel_id, data, enquirer }),
+            )?;
+        } else if let Some((enquirer, _)) = self.spawning_peers.remove(&source) {
             debug!("Daemon {} reported back", source);
-            self.spawning_peers.remove(&source);
             let success =
                 RpcMsg::Success(OptionDetails::with(format!("Peer connected to {}", source)));
             self.send_rpc(endpoints, enquirer, success)?;
@@ -483,6 +1869,9 @@ impl Runtime {
     }
 
     fn register_daemon(&mut self, source: ServiceId) {
+        if let ServiceId::Channel(channel_id) = source {
+            self.register_channel_route(channel_id);
+        }
         match source {
             ServiceId::LnpBroker => {
                 error!("{}", "Unexpected another lnpd instance connection".err());
@@ -519,40 +1908,1978 @@ impl Runtime {
         }
     }
 
-    fn listen(&mut self, addr: RemoteSocketAddr) -> Result<String, Error> {
-        info!("Starting peer connection listening daemon on {}...", addr);
-        let handle = self.launch_daemon(
-            Daemon::Peerd(PeerSocket::Listen(addr), self.node_key_path.clone()),
-            self.config.clone(),
-        )?;
-        Ok(format!("Launched new instance of {}", handle))
+    /// Imports channel state exported from another node. The state is handed over to the
+    /// channel daemon, which refuses to replace its state with an older one; if there is no
+    /// persisted state for the channel, the imported state is just saved to the disk.
+    fn import_channel(
+        &mut self,
+        endpoints: &mut Endpoints,
+        client_id: ClientId,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let export = match ChannelExport::deserialize(&data) {
+            Ok(export) => export,
+            Err(err) => {
+                let failure = RpcError::from(&channeld::Error::from(err));
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                return Ok(());
+            }
+        };
+        let channel_id = export.channel_id;
+        info!("{} state of channel {}", "Importing".promo(), channel_id.promoter());
+
+        // The remote peer must not interact with the channel while its state is replaced
+        let connected =
+            self.connections.iter().any(|connection| is_same_node(connection, &export.remote_peer));
+        if connected {
+            let failure = RpcError::new(
+                ErrorCode::ChannelState,
+                format!(
+                    "Channel {} can't be imported while the node is connected to its remote peer \
+                     {}",
+                    channel_id, export.remote_peer
+                ),
+            );
+            warn!("{}", failure.message.err());
+            self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            return Ok(());
+        }
+
+        let channel_file = self.config.channel_file(ActiveChannelId::Static(channel_id));
+        if self.channels.contains(&channel_id) {
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                self.channel_route(channel_id),
+                BusMsg::Ctl(CtlMsg::ImportChannelState { channel_id, data, enquirer: client_id }),
+            )?;
+        } else if channel_file.exists() {
+            // Channel daemon has to check the imported state against the persisted one
+            self.launch_daemon(
+                Daemon::Channeld(ActiveChannelId::Static(channel_id)),
+                self.config.clone(),
+            )?;
+            self.importing_channels.insert(ServiceId::Channel(channel_id), (client_id, data));
+        } else {
+            fs::create_dir_all(self.config.channel_dir())?;
+            fs::write(channel_file, &export.state)?;
+            let success = RpcMsg::Success(OptionDetails::with(format!(
+                "Channel {} is imported and will be reestablished once the remote peer connects",
+                channel_id
+            )));
+            self.send_rpc(endpoints, client_id, success)?;
+        }
+        Ok(())
     }
 
-    fn available_funding(&mut self) -> Result<BTreeMap<AddressCompat, u64>, Error> {
-        self.funding_wallet.list_funds()?.into_iter().try_fold(
-            bmap! {},
-            |mut acc, f| -> Result<_, Error> {
-                *acc.entry(
-                    AddressCompat::from_script(
-                        f.script_pubkey.as_inner(),
-                        self.funding_wallet.network(),
-                    )
-                    .ok_or(funding::Error::NoAddressRepresentation)?,
-                )
-                .or_insert(0) += f.amount;
-                Ok(acc)
-            },
-        )
+    /// Starts recovery of the channels from the static channel backup created by this node.
+    /// Channels which are known to the node are skipped.
+    fn recover_channels(
+        &mut self,
+        endpoints: &mut Endpoints,
+        client_id: ClientId,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let backups = match self.channel_backups.decrypt(&data) {
+            Ok(backups) => backups,
+            Err(err) => {
+                let failure = RpcError::from(&channeld::Error::from(err));
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                return Ok(());
+            }
+        };
+
+        let mut skipped = 0usize;
+        let mut recovering = 0usize;
+        for backup in backups {
+            let channel_id = backup.channel_id;
+            let channel_file = self.config.channel_file(ActiveChannelId::Static(channel_id));
+            if self.channels.contains(&channel_id)
+                || channel_file.exists()
+                || self.recoveries.contains_key(&channel_id)
+            {
+                debug!("Channel {} is known to the node; skipping its recovery", channel_id);
+                skipped += 1;
+                continue;
+            }
+            info!("{} funds of channel {}", "Recovering".promo(), channel_id.promoter());
+
+            // Keysets of the new channels must not reuse the index of the recovered one
+            self.key_index.reserve(backup.key_index)?;
+            if let NodeAddr::Remote(ref remote_addr) = backup.remote_peer {
+                let node_id = remote_addr.node_id;
+                self.address_book
+                    .register_announcement(node_id, iter::once(remote_addr.remote_addr.clone()))?;
+                self.address_book.register_channel(node_id, channel_id)?;
+                if !self.is_connected(&node_id) {
+                    self.schedule_reconnect(node_id);
+                }
+            }
+            self.backup_channel(backup.clone());
+            self.channel_peers.insert(channel_id, backup.remote_peer.clone());
+            self.send_ctl(
+                endpoints,
+                ServiceId::Signer,
+                CtlMsg::DeriveChannelKeys {
+                    channel_id: channel_id.into_inner(),
+                    index: backup.key_index,
+                },
+            )?;
+            self.recoveries.insert(channel_id, Recovery::with(backup));
+            recovering += 1;
+        }
+
+        // Remote peers which are already connected will not report reconnection
+        let connections = self.connections.iter().cloned().collect::<Vec<_>>();
+        for remote_peer in &connections {
+            self.reestablish_recoveries(endpoints, remote_peer)?;
+        }
+
+        let success = RpcMsg::Success(OptionDetails::with(format!(
+            "Recovering funds of {} channels, {} channels are skipped as known to the node; funds \
+             are swept to the funding wallet once the remote peers close the channels",
+            recovering, skipped
+        )));
+        self.send_rpc(endpoints, client_id, success)?;
+        Ok(())
     }
 
-    pub fn update_chanel_id(&mut self, old_id: TempChannelId, new_id: ChannelId) -> bool {
-        let mut known = true;
-        if !self.channels.remove(&ChannelId::from(old_id)) {
-            known = false;
-            warn!("Temporary channel id {} was unknown", old_id);
+    /// Starts the node shutdown by asking all channel daemons to park their state. The node
+    /// exits once all of them confirm this; repeated shutdown request makes it exit immediately.
+    fn shutdown(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        if let Some(pending) = &self.stopping {
+            warn!(
+                "{} channel daemons have not confirmed the shutdown; exiting anyway",
+                pending.len()
+            );
+            self.exit();
         }
-        self.channels.insert(new_id);
-        info!("Channel daemon id registered to change from {} to {}", old_id, new_id);
-        known
+
+        info!("{} the node", "Shutting down".promo());
+        let mut pending = HashSet::with_capacity(self.channels.len());
+        for channel_id in &self.channels {
+            let channeld = self.channel_route(*channel_id);
+            let message = BusMsg::Ctl(CtlMsg::Shutdown);
+            match endpoints.send_to(ServiceBus::Ctl, self.identity(), channeld.clone(), message) {
+                Ok(_) => {
+                    pending.insert(channeld);
+                }
+                Err(err) => warn!("Unable to ask {} to shut down: {}", channeld, err),
+            }
+        }
+        self.stopping = Some(pending);
+        self.complete_shutdown(None);
+        Ok(())
+    }
+
+    /// Registers shutdown confirmation from a channel daemon and exits once all channel daemons
+    /// have confirmed the shutdown
+    fn complete_shutdown(&mut self, confirmed_by: Option<ServiceId>) {
+        let pending = match &mut self.stopping {
+            Some(pending) => pending,
+            None => return,
+        };
+        if let Some(channeld) = confirmed_by {
+            debug!("{} has parked its state", channeld);
+            pending.remove(&channeld);
+        }
+        if pending.is_empty() {
+            self.exit();
+        }
+    }
+
+    /// Validates client request to close a channel and forwards it to the channel daemon, which
+    /// reports the closing progress directly to the client
+    fn close_channel(
+        &self,
+        endpoints: &mut Endpoints,
+        enquirer: ClientId,
+        close_channel: CloseChannel,
+    ) -> Result<(), RpcError> {
+        let CloseChannel { channel_id, force, fee_range, dest_address } = close_channel;
+        if !self.channels.contains(&channel_id) {
+            return Err(RpcError::new(
+                ErrorCode::ChannelNotFound,
+                format!("Channel {} is unknown or its daemon is not running", channel_id),
+            ));
+        }
+        if force && (fee_range.is_some() || dest_address.is_some()) {
+            return Err(RpcError::new(
+                ErrorCode::InvalidRequest,
+                "Fee range and destination address are not applicable to force-closing, which \
+                 publishes already signed commitment transaction",
+            ));
+        }
+        match fee_range {
+            Some(range) if range.min_fee_sat > range.max_fee_sat => {
+                let message = format!("Closing fee range {} is empty", range);
+                return Err(RpcError::new(ErrorCode::InvalidRequest, message));
+            }
+            _ => {}
+        }
+
+        info!(
+            "{} {} closing of channel {}",
+            "Starting".promo(),
+            if force { "force" } else { "cooperative" },
+            channel_id.promoter()
+        );
+        let message = CtlMsg::CloseChannel {
+            channel_id,
+            force,
+            fee_range,
+            shutdown_script: dest_address.map(|address| address.script_pubkey().into()),
+            enquirer,
+        };
+        let channeld = self.channel_route(channel_id);
+        endpoints
+            .send_to(ServiceBus::Ctl, self.identity(), channeld, BusMsg::Ctl(message))
+            .map_err(|err| {
+                let message = format!("Unable to reach channel {}: {}", channel_id, err);
+                RpcError::new(ErrorCode::Bus, message)
+            })
+    }
+
+    /// Constructs transaction sending funds from the funding wallet to an external address. On a
+    /// dry run the unsigned transaction is returned to the client; otherwise the transaction is
+    /// sent to signd and published once signed.
+    fn withdraw(
+        &mut self,
+        endpoints: &mut Endpoints,
+        enquirer: ClientId,
+        withdraw: Withdraw,
+    ) -> Result<(), RpcError> {
+        let Withdraw { address, amount_sat, feerate_per_kw, dry_run, confirmation } = withdraw;
+        let mainnet = self.funding_wallet.network() == bitcoin::Network::Bitcoin;
+        if (address.network == bitcoin::Network::Bitcoin) != mainnet {
+            return Err(RpcError::new(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "Address {} belongs to a network different from the funding wallet network",
+                    address
+                ),
+            ));
+        }
+
+        let (psbt, amount_sat, fee_sat) = self
+            .funding_wallet
+            .construct_withdrawal_psbt(
+                address.script_pubkey().into(),
+                amount_sat,
+                feerate_per_kw,
+                dry_run,
+            )
+            .map_err(|err| {
+                RpcError::new(err.error_code(), format!("Unable to construct withdrawal: {}", err))
+            })?;
+        let txid = psbt.global.unsigned_tx.txid();
+        let mut withdrawal = Withdrawal { txid, amount_sat, fee_sat, psbt: None };
+        if dry_run {
+            withdrawal.psbt = Some(consensus::encode::serialize_hex(&*psbt));
+            return self
+                .send_rpc(endpoints, enquirer, RpcMsg::Withdrawal(withdrawal))
+                .map_err(|err| RpcError::new(ErrorCode::Bus, err));
+        }
+
+        info!(
+            "{} withdrawal of {} sat to {} with transaction {}",
+            "Signing".promo(),
+            amount_sat,
+            address,
+            txid.promoter()
+        );
+        self.withdrawals.insert(txid, (enquirer, withdrawal));
+        if self.config.watch_only {
+            self.export_psbt("withdrawal", psbt);
+            let progress = format!(
+                "Withdrawal transaction {} awaits signature; sign it with your wallet and submit \
+                 it with `lnp-cli wallet submit-psbt`",
+                txid
+            );
+            return self
+                .send_rpc(endpoints, enquirer, RpcMsg::Progress(progress))
+                .map_err(|err| RpcError::new(ErrorCode::Bus, err));
+        }
+        let message = BusMsg::Ctl(CtlMsg::SignWithdrawal { psbt, confirmation });
+        let signer = ServiceId::Signer;
+        if let Err(err) = endpoints.send_to(ServiceBus::Ctl, self.identity(), signer, message) {
+            self.withdrawals.remove(&txid);
+            self.funding_wallet.release_withdrawal(txid);
+            let message = format!("Unable to reach signing daemon: {}", err);
+            return Err(RpcError::new(ErrorCode::SignerUnavailable, message));
+        }
+        if self.config.hardware_wallet.is_some() {
+            let progress = format!(
+                "Signing withdrawal transaction {}; please confirm it on your hardware wallet",
+                txid
+            );
+            self.send_rpc(endpoints, enquirer, RpcMsg::Progress(progress))
+                .map_err(|err| RpcError::new(ErrorCode::Bus, err))?;
+        }
+        Ok(())
+    }
+
+    /// Funding batch the channel daemon belongs to
+    fn batch_index(&self, channeld: &ServiceId) -> Option<usize> {
+        self.funding_batches.iter().position(|batch| batch.contains(channeld))
+    }
+
+    /// Launches channel daemons for the batch of channels funded with a single transaction
+    fn open_channels(
+        &mut self,
+        endpoints: &mut Endpoints,
+        enquirer: ClientId,
+        requests: Vec<CreateChannel>,
+    ) -> Result<(), Error> {
+        let unbatchable =
+            requests.iter().find(|request| request.external_funding || request.dry_run);
+        let err = match unbatchable {
+            _ if requests.is_empty() => Some(batch::Error::Empty),
+            Some(request) if request.external_funding => {
+                Some(batch::Error::ExternalFunding(request.remote_peer.clone()))
+            }
+            Some(request) => Some(batch::Error::DryRun(request.remote_peer.clone())),
+            None => None,
+        };
+        let failure = match err {
+            Some(err) => Some(RpcError::from(&err)),
+            None => self.ensure_chain_synced().err(),
+        };
+        if let Some(failure) = failure {
+            warn!("{}", failure.message.err());
+            self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure))?;
+            return Ok(());
+        }
+
+        info!("{} batch of {} channels", "Opening".promo(), requests.len());
+        let mut batch = FundingBatch::with(enquirer);
+        for create_channel in requests {
+            info!("Creating channel with {}", create_channel.remote_peer);
+            let remote_peer = create_channel.remote_peer.clone();
+            let launcher = match ChannelLauncher::with(endpoints, enquirer, create_channel, self) {
+                Ok(launcher) => launcher,
+                Err(err) => {
+                    let reason = format!("channel with {} can't be launched: {}", remote_peer, err);
+                    return self.fail_batch(endpoints, batch, None, reason);
+                }
+            };
+            let temp_channel_id = TempChannelId::from_inner(launcher.channel_id());
+            let channeld_id = ServiceId::from(temp_channel_id);
+            self.channel_routes.insert(temp_channel_id.into(), channeld_id.clone());
+            self.channel_peers.insert(temp_channel_id.into(), remote_peer);
+            self.creating_channels.insert(channeld_id, launcher);
+            batch.add_channel(temp_channel_id);
+        }
+        self.funding_batches.push(batch);
+        Ok(())
+    }
+
+    /// Constructs a single funding transaction for all channels of the batch once all remote
+    /// peers have accepted their channels, and provides each channel daemon with it
+    fn construct_batch_funding(
+        &mut self,
+        endpoints: &mut Endpoints,
+        index: usize,
+    ) -> Result<(), Error> {
+        let batch = &self.funding_batches[index];
+        let outputs = batch.funding_outputs();
+        let feerate_per_kw = batch.feerate_per_kw();
+        let psbt = match self.funding_wallet.construct_batch_funding_psbt(
+            batch.funding_channel_id(),
+            &outputs,
+            feerate_per_kw,
+        ) {
+            Ok(psbt) => psbt,
+            Err(err) => {
+                let batch = self.funding_batches.remove(index);
+                let reason = format!("funding transaction can't be constructed: {}", err);
+                return self.fail_batch(endpoints, batch, None, reason);
+            }
+        };
+        let txid = psbt.global.unsigned_tx.txid();
+        info!(
+            "{} funding transaction {} for batch of {} channels",
+            "Constructed".ended(),
+            txid.ender(),
+            outputs.len()
+        );
+        self.funding_batches[index].funding_txid = Some(txid);
+
+        for channel in self.funding_batches[index].channels.clone() {
+            let channeld = ServiceId::from(channel.temp_channel_id);
+            let vout =
+                channel.funding_output(&psbt).expect("batch funding pays to all the channels");
+            let mut channel_psbt = psbt.clone();
+            channel_psbt
+                .set_channel_funding_output(vout)
+                .expect("funding output presence is checked above");
+            let launcher = self
+                .creating_channels
+                .remove(&channeld)
+                .unwrap_or_else(|| panic!("unregistered channel launcher for {}", channeld));
+            let message = CtlMsg::FundingConstructed(channel_psbt);
+            let event = Event::with(endpoints, self.identity(), channeld.clone(), message);
+            let launcher = match launcher.next(event, self) {
+                Ok(launcher) => launcher.expect("channel launcher should not be complete"),
+                // Launcher has already reported the failure to the client
+                Err(err) => {
+                    let batch = self.funding_batches.remove(index);
+                    return self.fail_batch(endpoints, batch, Some(&channeld), err.to_string());
+                }
+            };
+            let channel_id = ChannelId::from_inner(launcher.channel_id());
+            self.creating_channels.insert(channel_id.into(), launcher);
+            if let Some(channel) = self.funding_batches[index].channel_mut(&channeld) {
+                channel.channel_id = Some(channel_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the refund transaction signed by the remote peer of a channel from the batch.
+    /// Once the refund transactions of all channels are signed, the batch funding transaction is
+    /// signed and published by the launcher of the last channel.
+    fn publish_batch_funding(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+    ) -> Result<(), Error> {
+        let index = self.batch_index(&source).expect("batch presence is checked by the caller");
+        let batch = &mut self.funding_batches[index];
+        if !batch.register_signed(&source) {
+            let enquirer = batch.enquirer;
+            let msg = format!(
+                "Remote peer of {} signed the refund transaction; awaiting for {} more channels of \
+                 the batch",
+                source,
+                batch.unsigned_count()
+            );
+            self.report_batch_progress(endpoints, enquirer, msg);
+            return Ok(());
+        }
+
+        let batch = self.funding_batches.remove(index);
+        for channel in &batch.channels {
+            if !channel.is_daemon(&source) {
+                self.creating_channels.remove(&ServiceId::Channel(channel.active_channel_id()));
+            }
+        }
+        let msg =
+            format!("Refund transactions of all {} channels are signed", batch.channels.len());
+        self.report_batch_progress(endpoints, batch.enquirer, msg);
+        let launcher = self
+            .creating_channels
+            .remove(&source)
+            .unwrap_or_else(|| panic!("unregistered channel launcher for {}", source));
+        let event = Event::with(endpoints, self.identity(), source, CtlMsg::PublishFunding);
+        let launcher =
+            launcher.next(event, self)?.expect("channel launcher should not be complete");
+        self.register_funding(launcher);
+        Ok(())
+    }
+
+    /// Registers launcher of the channel which funding transaction is being signed. With the
+    /// watch-only funding wallet the transaction is exported to the operator instead of signd.
+    fn register_funding(&mut self, launcher: ChannelLauncher) {
+        let txid = launcher.funding_txid().expect("funding txid must be known at this stage");
+        if self.config.watch_only {
+            let psbt = self
+                .funding_wallet
+                .get_funding_psbt(txid)
+                .expect("funding construction is broken")
+                .clone();
+            self.export_psbt("funding", psbt);
+        }
+        self.funding_channels.insert(txid, launcher);
+    }
+
+    /// Exports transaction of the watch-only funding wallet to the operator, who signs it with
+    /// the wallet holding the keys and submits it back with `SubmitPsbt` request
+    fn export_psbt(&mut self, purpose: &str, psbt: Psbt) {
+        let txid = psbt.global.unsigned_tx.txid();
+        info!("{} {} transaction {} for signing", "Exporting".promo(), purpose, txid.promoter());
+        let pending = PendingPsbt { txid, purpose: purpose.to_owned(), psbt: (*psbt).to_string() };
+        self.publish_event(NodeEvent::PsbtPending {
+            txid,
+            purpose: pending.purpose.clone(),
+            psbt: pending.psbt.clone(),
+        });
+        self.pending_psbts.insert(txid, pending);
+    }
+
+    /// Accepts transaction of the watch-only funding wallet signed by the operator, completing
+    /// the channel funding or withdrawal as if the transaction was signed by signd
+    fn submit_psbt(&mut self, endpoints: &mut Endpoints, data: &[u8]) -> Result<Txid, RpcError> {
+        let psbt = consensus::deserialize::<PartiallySignedTransaction>(data).map_err(|err| {
+            RpcError::new(
+                ErrorCode::InvalidRequest,
+                format!("Signed transaction is not a valid PSBT: {}", err),
+            )
+        })?;
+        let txid = psbt.global.unsigned_tx.txid();
+        if !self.pending_psbts.contains_key(&txid) {
+            return Err(RpcError::new(
+                ErrorCode::NotFound,
+                format!("Transaction {} does not await signature", txid),
+            ));
+        }
+        let unsigned = psbt.inputs.iter().position(|input| {
+            input.partial_sigs.is_empty()
+                && input.final_script_sig.is_none()
+                && input.final_script_witness.is_none()
+        });
+        if let Some(vin) = unsigned {
+            return Err(RpcError::new(
+                ErrorCode::InvalidRequest,
+                format!("Input #{} of transaction {} is not signed", vin, txid),
+            ));
+        }
+
+        info!("{} transaction {} signed by the operator", "Accepting".promo(), txid.promoter());
+        self.pending_psbts.remove(&txid);
+        // Signed transaction completes the workflow which has been waiting for signd
+        self.handle_ctl(endpoints, ServiceId::Signer, CtlMsg::Signed(Psbt::from(psbt)))
+            .map_err(|err| RpcError::from(&err))?;
+        Ok(txid)
+    }
+
+    /// Fails the batch after one of its channels has failed: reports the failure to the client,
+    /// fails the remaining channels and releases funding UTXOs reserved for the batch
+    fn fail_batch(
+        &mut self,
+        endpoints: &mut Endpoints,
+        batch: FundingBatch,
+        failed: Option<&ServiceId>,
+        reason: String,
+    ) -> Result<(), Error> {
+        let err = if batch.committed {
+            batch::Error::Failed(reason)
+        } else {
+            batch::Error::Abandoned(reason)
+        };
+        warn!("{}", err.err());
+        let failure = RpcError::from(&err);
+        if self.send_rpc(endpoints, batch.enquirer, RpcMsg::Failure(failure)).is_err() {
+            error!("Client #{} got disconnected", batch.enquirer);
+        }
+
+        for channel in &batch.channels {
+            if matches!(failed, Some(failed) if channel.is_daemon(failed)) {
+                continue;
+            }
+            // Launcher is registered under the permanent channel id once the funding
+            // transaction is constructed
+            self.creating_channels.remove(&ServiceId::from(channel.temp_channel_id));
+            self.creating_channels.remove(&ServiceId::Channel(channel.active_channel_id()));
+            let channeld = self.channel_route(channel.active_channel_id());
+            let message = BusMsg::Ctl(CtlMsg::BatchFailed(err.to_string()));
+            if let Err(err) =
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), channeld.clone(), message)
+            {
+                warn!("Unable to fail {}: {}", channeld, err);
+            }
+        }
+
+        let funding = match batch.funding_txid {
+            Some(_) => self.funding_wallet.release_funding(batch.funding_channel_id())?,
+            None => None,
+        };
+        if let Some(funding) = funding {
+            info!(
+                "{} {} funding UTXOs reserved for the failed channel batch",
+                "Released".ended(),
+                funding.prev_outpoints.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports progress of the batch channel opening to the client, which may be disconnected
+    /// already, in which case the report is dropped
+    fn report_batch_progress(&self, endpoints: &mut Endpoints, enquirer: ClientId, msg: String) {
+        if self.send_rpc(endpoints, enquirer, RpcMsg::Progress(msg)).is_err() {
+            error!("Client #{} got disconnected", enquirer);
+        }
+    }
+
+    /// Asks all channel daemons to report their channels for the channel listing requested by
+    /// the client. The listing is sent once all of the daemons reply; if it was requested for the
+    /// funds info, the funds info is sent instead with the channel balances.
+    fn list_channels(
+        &mut self,
+        endpoints: &mut Endpoints,
+        enquirer: ClientId,
+        query: ChannelQuery,
+    ) {
+        let daemons = match query {
+            ChannelQuery::Channel(channel_id) => vec![self.channel_route(channel_id)],
+            _ => self.channels.iter().map(|channel_id| self.channel_route(*channel_id)).collect(),
+        };
+        let pending = self.request_status(endpoints, daemons, CtlMsg::GetInfo);
+        self.channel_listings.push(Listing::with(enquirer, pending, query));
+        self.complete_listings(endpoints, None);
+    }
+
+    /// Registers channel reported by a channel daemon and sends to the clients the listings
+    /// which got reports from all channel daemons. Listings awaiting for the reports longer than
+    /// [`LISTING_TIMEOUT`] are sent with the channels reported so far.
+    fn complete_listings(
+        &mut self,
+        endpoints: &mut Endpoints,
+        reported: Option<(&ServiceId, ChannelSummary)>,
+    ) {
+        for listing in Listing::take_completed(&mut self.channel_listings, reported) {
+            if !listing.pending.is_empty() {
+                warn!(
+                    "{} channel daemons have not reported their channels in time; they are \
+                     omitted from the channel listing",
+                    listing.pending.len()
+                );
+            }
+            let reply = match listing.context {
+                ChannelQuery::Listing(filter, page) => {
+                    let mut channels = listing.items;
+                    channels.retain(|channel| filter.matches(channel));
+                    channels.sort_by_key(|channel| channel.channel_id.to_string());
+                    let total_count = channels.len() as u32;
+                    let channels = page.apply(channels);
+                    RpcMsg::ChannelList(ChannelList { channels, total_count })
+                }
+                ChannelQuery::Funds(mut funds_info) => {
+                    funds_info.channel_balances = listing
+                        .items
+                        .into_iter()
+                        .map(|summary| ChannelBalance {
+                            channel_id: summary.channel_id,
+                            lifecycle: summary.lifecycle,
+                            local_balance_msat: summary.local_balance_msat,
+                            remote_balance_msat: summary.remote_balance_msat,
+                        })
+                        .collect();
+                    RpcMsg::FundsInfo(funds_info)
+                }
+                ChannelQuery::Channel(channel_id) => match listing.items.into_iter().next() {
+                    Some(summary) => RpcMsg::ChannelSummary(summary),
+                    None => RpcMsg::Failure(RpcError::new(
+                        ErrorCode::ChannelNotFound,
+                        format!("Channel {} has not reported its state in time", channel_id),
+                    )),
+                },
+            };
+            // If the client is disconnected, just swallow the error
+            if self.send_rpc(endpoints, listing.enquirer, reply).is_err() {
+                error!("Client #{} got disconnected", listing.enquirer);
+            }
+        }
+    }
+
+    /// Asks peer daemons to report their connections for the peer listing requested by the
+    /// client. The listing is sent once all of the daemons reply. If `reset` is set, the
+    /// statistics of the connections with the listed node, or with all nodes unless the listing
+    /// is filtered by the node, are reset once they are reported.
+    fn list_peers(
+        &mut self,
+        endpoints: &mut Endpoints,
+        enquirer: ClientId,
+        filter: PeerFilter,
+        page: Pagination,
+        reset: bool,
+    ) -> Result<(), RpcError> {
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since > until {
+                return Err(RpcError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("listing time range starts at {} after its end at {}", since, until),
+                ));
+            }
+        }
+        let is_listed = |node_id: &secp256k1::PublicKey| {
+            filter.remote_node.map_or(true, |remote_node| remote_node == *node_id)
+        };
+        let daemons = self
+            .connections
+            .iter()
+            .filter(|addr| match addr {
+                NodeAddr::Remote(remote_addr) => is_listed(&remote_addr.node_id),
+                NodeAddr::Local(_) => filter.remote_node.is_none(),
+            })
+            .cloned()
+            .map(ServiceId::Peer)
+            .collect();
+        let (request, stats) = if reset {
+            let (taken, kept) =
+                mem::take(&mut self.peer_stats).into_iter().partition(|(id, _)| is_listed(id));
+            self.peer_stats = kept;
+            (CtlMsg::GetInfoAndReset, taken)
+        } else {
+            (CtlMsg::GetInfo, self.peer_stats.clone())
+        };
+        let pending = self.request_status(endpoints, daemons, request);
+        self.peer_listings.push(Listing::with(enquirer, pending, (filter, page, stats)));
+        self.complete_peer_listings(endpoints, None);
+        Ok(())
+    }
+
+    /// Registers connection reported by a peer daemon and sends to the clients the listings
+    /// which got reports from all peer daemons. Listings awaiting for the reports longer than
+    /// [`LISTING_TIMEOUT`] are sent with the connections reported so far.
+    fn complete_peer_listings(
+        &mut self,
+        endpoints: &mut Endpoints,
+        reported: Option<(&ServiceId, PeerInfo)>,
+    ) {
+        let mut reconnecting = self.reconnect_info();
+        for listing in Listing::take_completed(&mut self.peer_listings, reported) {
+            if !listing.pending.is_empty() {
+                warn!(
+                    "{} peer daemons have not reported their connections in time; they are \
+                     omitted from the peer listing",
+                    listing.pending.len()
+                );
+            }
+            let (filter, page, stats) = listing.context;
+            let mut connected = listing.items;
+            connected.retain(|peer| filter.matches(peer));
+            for peer in &mut connected {
+                let peer_stats = peer.remote_id.first().and_then(|node_id| stats.get(node_id));
+                let peer_stats = peer_stats.cloned().unwrap_or_default();
+                peer.handshakes = peer_stats.handshakes;
+                peer.disconnects = peer_stats.disconnects;
+            }
+            for peer in &mut reconnecting {
+                let peer_stats = stats.get(&peer.node_id).cloned().unwrap_or_default();
+                peer.handshakes = peer_stats.handshakes;
+                peer.disconnects = peer_stats.disconnects;
+            }
+            connected.sort_by_key(|peer| peer.remote_socket.first().map(ToString::to_string));
+            let total_count = connected.len() as u32;
+            let reconnecting = reconnecting
+                .iter()
+                .filter(|peer| filter.matches_reconnecting(peer))
+                .cloned()
+                .collect();
+            let connected = page.apply(connected);
+            let peer_list = PeerList { connected, reconnecting, total_count };
+            // If the client is disconnected, just swallow the error
+            if self.send_rpc(endpoints, listing.enquirer, RpcMsg::PeerList(peer_list)).is_err() {
+                error!("Client #{} got disconnected", listing.enquirer);
+            }
+        }
+    }
+
+    /// Sends [`CtlMsg::GetInfo`] or [`CtlMsg::GetInfoAndReset`] request to the daemons,
+    /// returning the daemons which were reached
+    fn request_status(
+        &self,
+        endpoints: &mut Endpoints,
+        daemons: Vec<ServiceId>,
+        request: CtlMsg,
+    ) -> HashSet<ServiceId> {
+        let mut pending = HashSet::with_capacity(daemons.len());
+        for daemon in daemons {
+            let message = BusMsg::Ctl(request.clone());
+            match endpoints.send_to(ServiceBus::Ctl, self.identity(), daemon.clone(), message) {
+                Ok(_) => {
+                    pending.insert(daemon);
+                }
+                Err(err) => warn!("Unable to request status from {}: {}", daemon, err),
+            }
+        }
+        pending
+    }
+
+    /// Gathers node info known to lnpd and asks peer, channel, chain watch and signing daemons to
+    /// report their status. The info is sent once all of the daemons reply.
+    fn request_info(&mut self, endpoints: &mut Endpoints, enquirer: ClientId) {
+        let mut warnings = vec![];
+        let (confirmed_balance_sat, unconfirmed_balance_sat) =
+            match self.funding_wallet.list_funds() {
+                Ok(funds) => {
+                    let (confirmed, unconfirmed): (Vec<_>, Vec<_>) =
+                        funds.into_iter().partition(|funds| funds.height.is_some());
+                    (
+                        Some(confirmed.iter().map(|funds| funds.amount).sum()),
+                        Some(unconfirmed.iter().map(|funds| funds.amount).sum()),
+                    )
+                }
+                Err(err) => {
+                    warn!("Unable to get funding wallet balance: {}", err);
+                    warnings.push(format!("funding wallet balance is unknown: {}", err));
+                    (None, None)
+                }
+            };
+
+        let mut daemons = self.connections.iter().cloned().map(ServiceId::Peer).collect::<Vec<_>>();
+        daemons.extend(self.channels.iter().map(|channel_id| self.channel_route(*channel_id)));
+        daemons.push(ServiceId::Watch);
+        daemons.push(ServiceId::Signer);
+        let pending = self.request_status(endpoints, daemons.clone(), CtlMsg::GetInfo);
+        warnings.extend(
+            daemons
+                .iter()
+                .filter(|daemon| !pending.contains(daemon))
+                .map(|daemon| format!("{} is unreachable", daemon)),
+        );
+
+        let inbound = self.inbound_stats();
+        let info = NodeInfo {
+            node_id: self.node_id,
+            version: s!(env!("CARGO_PKG_VERSION")),
+            features: peerd::feature_names(&peerd::local_features(&self.config)),
+            listens: self.listens.iter().cloned().collect(),
+            onion_address: self.onion_service.as_ref().map(|service| service.address().to_owned()),
+            uptime: SystemTime::now()
+                .duration_since(self.started)
+                .unwrap_or_else(|_| Duration::from_secs(0)),
+            since: self
+                .started
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::from_secs(0))
+                .as_secs(),
+            peers: self.connections.iter().cloned().collect(),
+            channels: self.channels.iter().cloned().collect(),
+            reaped_channels: self.reaped_channels.clone(),
+            rejected_channels: self.rejected_channels,
+            inbound_peers: inbound.peers,
+            rejected_connections: inbound.rate_limited + inbound.slots_exhausted + inbound.banned,
+            evicted_peers: inbound.evicted,
+            handshake_timeouts: inbound.handshake_timeouts,
+            init_timeouts: inbound.init_timeouts,
+            oversized_messages: inbound.oversized_messages,
+            connected_peers: 0,
+            channel_stages: none!(),
+            chain_backend: None,
+            sync_height: None,
+            chain_error: None,
+            chain_sync: None,
+            confirmed_balance_sat,
+            unconfirmed_balance_sat,
+            signer_locked: None,
+            warnings,
+        };
+        let started = SystemTime::now();
+        self.info_requests.push(InfoRequest { enquirer, pending, info, started });
+        self.complete_info_requests(endpoints, None);
+    }
+
+    /// Registers status reported by a daemon and sends to the clients the node info which got
+    /// reports from all daemons. Info awaiting for the reports longer than [`INFO_TIMEOUT`] is
+    /// sent partial, with warnings naming the daemons which have not replied.
+    fn complete_info_requests(
+        &mut self,
+        endpoints: &mut Endpoints,
+        reported: Option<(&ServiceId, &CtlMsg)>,
+    ) {
+        if let Some((daemon, message)) = reported {
+            for request in &mut self.info_requests {
+                if !request.pending.remove(daemon) {
+                    continue;
+                }
+                let info = &mut request.info;
+                match message {
+                    CtlMsg::PeerInfo(peer_info) if peer_info.connected => info.connected_peers += 1,
+                    CtlMsg::ChannelSummary(summary) => {
+                        *info.channel_stages.entry(summary.lifecycle.clone()).or_insert(0) += 1
+                    }
+                    CtlMsg::ChainInfo { backend, height, error, sync } => {
+                        info.chain_backend = Some(backend.clone());
+                        info.sync_height = *height;
+                        info.chain_error = error.clone();
+                        info.chain_sync = sync.clone();
+                        if let Some(error) = error {
+                            info.warnings.push(format!("blockchain backend failure: {}", error));
+                        }
+                    }
+                    CtlMsg::SignerInfo { locked } => info.signer_locked = Some(*locked),
+                    _ => {}
+                }
+            }
+        }
+
+        let now = SystemTime::now();
+        let (completed, pending): (Vec<_>, _) = mem::take(&mut self.info_requests)
+            .into_iter()
+            .partition(|request| {
+                request.pending.is_empty() || request.started + INFO_TIMEOUT <= now
+            });
+        self.info_requests = pending;
+        for mut request in completed {
+            if !request.pending.is_empty() {
+                warn!(
+                    "{} daemons have not reported their status in time; node info is partial",
+                    request.pending.len()
+                );
+            }
+            for daemon in &request.pending {
+                request.info.warnings.push(format!("{} has not replied in time", daemon));
+            }
+            // If the client is disconnected, just swallow the error
+            if self.send_rpc(endpoints, request.enquirer, RpcMsg::NodeInfo(request.info)).is_err() {
+                error!("Client #{} got disconnected", request.enquirer);
+            }
+        }
+    }
+
+    /// Sends the number of transaction confirmations reported by watchd to the clients which
+    /// have requested it
+    fn complete_tx_depth_requests(&mut self, endpoints: &mut Endpoints, tx_depth: TxDepth) {
+        let (completed, pending) = mem::take(&mut self.tx_depth_requests)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, txid)| *txid == tx_depth.txid);
+        self.tx_depth_requests = pending;
+        for (enquirer, _) in completed {
+            // If the client is disconnected, just swallow the error
+            if self.send_rpc(endpoints, enquirer, RpcMsg::TxDepth(tx_depth)).is_err() {
+                error!("Client #{} got disconnected", enquirer);
+            }
+        }
+    }
+
+    /// Checks that the blockchain backend has synced past the wallet birthday, which is required
+    /// for opening channels when the node uses compact block filters
+    fn ensure_chain_synced(&self) -> Result<(), RpcError> {
+        if !matches!(self.config.chain_backend, ChainBackend::Neutrino(_)) {
+            return Ok(());
+        }
+        let message = match &self.chain_sync {
+            Some(sync) if sync.synced => return Ok(()),
+            Some(sync) => format!("blockchain backend has not synced yet: {}", sync),
+            None => s!("blockchain backend has not reported its synchronization progress yet"),
+        };
+        Err(RpcError::new(ErrorCode::ChainSyncing, message))
+    }
+
+    /// Fails all requests for the blockchain backend status once watchd is unreachable
+    fn fail_chain_status_requests(&mut self, endpoints: &mut Endpoints) {
+        for enquirer in mem::take(&mut self.chain_status_requests) {
+            let failure =
+                RpcError::new(ErrorCode::Bus, s!("On-chain tracking service is unreachable"));
+            if self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure)).is_err() {
+                error!("Client #{} got disconnected", enquirer);
+            }
+        }
+    }
+
+    /// Fails all requests for the rebroadcast transactions once watchd is unreachable
+    fn fail_pending_tx_requests(&mut self, endpoints: &mut Endpoints) {
+        for enquirer in mem::take(&mut self.pending_tx_requests) {
+            let failure =
+                RpcError::new(ErrorCode::Bus, s!("On-chain tracking service is unreachable"));
+            if self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure)).is_err() {
+                error!("Client #{} got disconnected", enquirer);
+            }
+        }
+    }
+
+    /// Fails all requests for the number of transaction confirmations once watchd is unreachable
+    fn fail_tx_depth_requests(&mut self, endpoints: &mut Endpoints) {
+        for (enquirer, txid) in mem::take(&mut self.tx_depth_requests) {
+            let failure = RpcError::new(
+                ErrorCode::Bus,
+                format!("On-chain tracking service is unreachable; status of {} is unknown", txid),
+            );
+            if self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure)).is_err() {
+                error!("Client #{} got disconnected", enquirer);
+            }
+        }
+    }
+
+    fn exit(&self) -> ! {
+        info!("Node is {}", "stopped".ended());
+        process::exit(0)
+    }
+
+    /// Launches peer daemon connecting to the remote node. If the request provides no node
+    /// address, the address is taken from the address book.
+    fn connect_peer(
+        &mut self,
+        endpoints: &mut Endpoints,
+        client_id: ClientId,
+        connect_peer: ConnectPeer,
+    ) -> Result<(), Error> {
+        let ConnectPeer { node_id, remote_addr, timeout } = connect_peer;
+        if self.ban_list.is_banned(&node_id) {
+            let failure = RpcError::new(
+                ErrorCode::PeerRejected,
+                format!("node {} is banned; please unban it before connecting", node_id),
+            );
+            warn!("{}", failure.message.err());
+            self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+            return Ok(());
+        }
+        let known_addr = self.address_book.address(&node_id);
+        let remote_addr = match remote_addr.or(known_addr) {
+            Some(remote_addr) => remote_addr,
+            None => {
+                let failure = RpcError::new(
+                    ErrorCode::PeerUnreachable,
+                    format!(
+                        "address of the node {} is unknown; please provide it in \
+                         '<node_id>@<host>[:<port>]' form",
+                        node_id
+                    ),
+                );
+                warn!("{}", failure.message.err());
+                self.send_rpc(endpoints, client_id, RpcMsg::Failure(failure))?;
+                return Ok(());
+            }
+        };
+        // Manual connection lifts the suspension set by the permanent disconnection
+        if let Err(err) = self.address_book.set_suspended(node_id, false) {
+            warn!("Unable to resume reconnection of the node {}: {}", node_id, err);
+        }
+        let addr = RemoteNodeAddr { node_id, remote_addr };
+        let timeout = timeout.map(|secs| Duration::from_secs(secs as u64));
+
+        info!("{} to remote peer {}", "Connecting".promo(), addr.promoter());
+        let peer_socket = PeerSocket::Connect(addr.clone(), timeout);
+        let peerd = Daemon::Peerd(peer_socket, self.node_key_path.clone());
+        let resp = match self.launch_daemon(peerd, self.config.clone()) {
+            Ok(handle) => {
+                let deadline = timeout.map(|timeout| SystemTime::now() + timeout);
+                self.spawning_peers.insert(ServiceId::Peer(addr.into()), (client_id, deadline));
+                Ok(format!("Launched new instance of {}", handle))
+            }
+            Err(err) => {
+                error!("{}", err.err());
+                Err(Error::from(err))
+            }
+        };
+        self.send_rpc(endpoints, client_id, resp.to_progress_or_failure())?;
+        Ok(())
+    }
+
+    /// Closes connections with the remote peer. Channel daemons of the peer are notified before
+    /// the connections are closed, such that they freeze outgoing HTLCs and may still report
+    /// abandoned channel negotiations to the peer. Permanently disconnected peer is not
+    /// reconnected until the user connects it again.
+    fn disconnect_peer(
+        &mut self,
+        endpoints: &mut Endpoints,
+        disconnect_peer: DisconnectPeer,
+    ) -> Result<String, Error> {
+        let DisconnectPeer { node_id, permanent } = disconnect_peer;
+        let is_peer =
+            |addr: &NodeAddr| matches!(addr, NodeAddr::Remote(remote) if remote.node_id == node_id);
+        let connections =
+            self.connections.iter().filter(|addr| is_peer(addr)).cloned().collect::<Vec<_>>();
+        if connections.is_empty() && !permanent {
+            let message = format!("peer {} is not connected", node_id);
+            return Err(RpcError::new(ErrorCode::PeerUnreachable, message).into());
+        }
+
+        if permanent {
+            self.address_book.set_suspended(node_id, true)?;
+            self.reconnects.remove(&node_id);
+        }
+
+        let channel_daemons = self
+            .channel_peers
+            .iter()
+            .filter(|(_, peer)| is_peer(peer))
+            .map(|(channel_id, _)| self.channel_route(*channel_id))
+            .collect::<HashSet<_>>();
+        for remote_peer in &connections {
+            for service in channel_daemons.iter().cloned().chain(iter::once(ServiceId::Router)) {
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    service,
+                    BusMsg::Ctl(CtlMsg::PeerDisconnected(remote_peer.clone(), s!("operator"))),
+                )?;
+            }
+        }
+
+        for remote_peer in connections {
+            info!("{} remote peer {}", "Disconnecting".promo(), remote_peer.promoter());
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                ServiceId::Peer(remote_peer.clone()),
+                BusMsg::Ctl(CtlMsg::Disconnect(s!("disconnected by the node operator"))),
+            )?;
+            self.connections.remove(&remote_peer);
+            self.peer_features.remove(&remote_peer);
+            self.count_disconnect(node_id, "operator");
+            self.publish_event(NodeEvent::PeerDisconnected { remote_peer });
+        }
+
+        let node = self.address_book.node(&node_id);
+        if permanent {
+            Ok(format!(
+                "Peer {} is disconnected and will not be reconnected until connected manually",
+                node_id
+            ))
+        } else if node.map(NodeEntry::is_reconnected).unwrap_or_default() {
+            let next_attempt = SystemTime::now() + RECONNECT_MIN_INTERVAL;
+            self.reconnects.insert(node_id, Reconnect { attempts: 0, next_attempt });
+            Ok(format!("Peer {} is disconnected; it will be reconnected later", node_id))
+        } else {
+            Ok(format!("Peer {} is disconnected", node_id))
+        }
+    }
+
+    /// Stores routing fee policy and informs the channel daemons which policy has changed. The
+    /// changed policies are announced to the network as long as the gossip rate limit allows.
+    fn set_fee_policy(
+        &mut self,
+        endpoints: &mut Endpoints,
+        set_fee_policy: SetFeePolicy,
+    ) -> Result<String, Error> {
+        let SetFeePolicy { scope, policy } = set_fee_policy;
+        if let PolicyScope::Channel(channel_id) = scope {
+            if !self.channels.contains(&channel_id) {
+                let message = format!("channel {} is not known to the node", channel_id);
+                return Err(RpcError::new(ErrorCode::ChannelNotFound, message).into());
+            }
+        }
+
+        info!("{} routing fee policy for {} to {}", "Setting".promo(), scope, policy);
+        let changed = self.fee_policies.set_policy(scope, policy)?;
+        for (channel_id, fee_policy) in &changed {
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                self.channel_route(*channel_id),
+                BusMsg::Ctl(CtlMsg::SetFeePolicy(*fee_policy)),
+            )?;
+        }
+        self.announce_fee_policies(endpoints);
+
+        let mut msg = format!(
+            "Fee policy for {} is set; policy of {} active channels has changed",
+            scope,
+            changed.len()
+        );
+        let postponed = self.fee_policies.pending_count();
+        if postponed > 0 {
+            msg += &format!(
+                "; {} channel updates will be announced once the gossip rate limit of {} seconds \
+                 passes",
+                postponed,
+                fee_policy::UPDATE_INTERVAL.as_secs()
+            );
+        }
+        Ok(msg)
+    }
+
+    /// Updates the fee estimation settings, which watchd applies to the feerates it reports
+    fn set_fees(&mut self, endpoints: &mut Endpoints, set_fees: SetFees) -> Result<String, Error> {
+        let mut settings = self.config.fees;
+        if let Some(floor_per_kw) = set_fees.floor_per_kw {
+            settings.floor_per_kw = floor_per_kw;
+        }
+        if let Some(ceiling_per_kw) = set_fees.ceiling_per_kw {
+            settings.ceiling_per_kw = ceiling_per_kw;
+        }
+        if let Some(target_blocks) = set_fees.target_blocks {
+            settings.target_blocks = target_blocks;
+        }
+        let err = if settings.floor_per_kw < FEERATE_FLOOR {
+            Some(format!("fee floor must not be below {} sat/kw", FEERATE_FLOOR))
+        } else if settings.ceiling_per_kw < settings.floor_per_kw {
+            Some(format!("fee ceiling must not be below the floor of {}", settings.floor_per_kw))
+        } else if settings.target_blocks == 0 {
+            Some(s!("confirmation target must be at least one block"))
+        } else {
+            None
+        };
+        if let Some(message) = err {
+            return Err(RpcError::new(ErrorCode::InvalidRequest, message).into());
+        }
+
+        info!("{} fee estimation settings to {}", "Setting".promo(), settings);
+        self.config.fees = settings;
+        self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::SetFees(settings))?;
+        Ok(format!("fee estimation settings are set to {}", settings))
+    }
+
+    /// Sends `channel_update` messages which are due to routed. Updates of the announced channels
+    /// are broadcast by routed, updates of the other ones are sent also to the channel remote
+    /// peer.
+    fn announce_fee_policies(&mut self, endpoints: &mut Endpoints) {
+        for update in self.fee_policies.due_updates(&self.local_node) {
+            let node_id = update.remote_node;
+            let is_receiver = |connection: &&NodeAddr| {
+                !update.public
+                    && matches!(connection, NodeAddr::Remote(remote) if remote.node_id == node_id)
+            };
+            let receivers = self
+                .connections
+                .iter()
+                .filter(is_receiver)
+                .cloned()
+                .map(ServiceId::Peer)
+                .chain(iter::once(ServiceId::Router))
+                .collect::<Vec<_>>();
+            debug!("Announcing policy of channel {}", update.message.short_channel_id);
+            let message = BusMsg::Ln(LnMsg::ChannelUpdate(update.message));
+            for receiver in receivers {
+                let identity = self.identity();
+                if let Err(err) =
+                    endpoints.send_to(ServiceBus::Msg, identity, receiver.clone(), message.clone())
+                {
+                    warn!("Unable to send channel update to {}: {}", receiver, err);
+                }
+            }
+        }
+    }
+
+    /// Sends `node_announcement` message to routed for the broadcast, if the node has to be
+    /// (re)announced
+    fn announce_node(&mut self, endpoints: &mut Endpoints) {
+        let addresses = self.node_announcer.addresses(&self.listens, self.onion_service.as_ref());
+        let features = peerd::local_features(&self.config);
+        let announcement =
+            match self.node_announcer.due_announcement(&self.local_node, features, addresses) {
+                Some(announcement) => announcement,
+                None => return,
+            };
+        debug!("Announcing the node with {} address(es)", announcement.addresses.as_inner().len());
+        let message = BusMsg::Ln(LnMsg::NodeAnnouncement(announcement));
+        if let Err(err) =
+            endpoints.send_to(ServiceBus::Msg, self.identity(), ServiceId::Router, message)
+        {
+            warn!("Unable to send node announcement to {}: {}", ServiceId::Router, err);
+        }
+    }
+
+    /// Fails connection requests which peer daemons have not connected the remote node before
+    /// the deadline
+    fn reap_stale_connections(&mut self, endpoints: &mut Endpoints) {
+        let now = SystemTime::now();
+        let stale = self
+            .spawning_peers
+            .iter()
+            .filter(|(_, (_, deadline))| matches!(deadline, Some(deadline) if *deadline <= now))
+            .map(|(peerd, (enquirer, _))| (peerd.clone(), *enquirer))
+            .collect::<Vec<_>>();
+
+        for (peerd, enquirer) in stale {
+            self.spawning_peers.remove(&peerd);
+            let failure = RpcError::new(
+                ErrorCode::Timeout,
+                format!("{} has not connected the remote node in time", peerd),
+            );
+            warn!("{}", failure.message.err());
+            if self.send_rpc(endpoints, enquirer, RpcMsg::Failure(failure)).is_err() {
+                error!("Client #{} got disconnected", enquirer);
+            }
+        }
+    }
+
+    /// Asks peer daemons to check whether their connections are still alive. Each daemon pings
+    /// its remote peer after the configured period of inactivity.
+    fn ping_peers(&self, endpoints: &mut Endpoints) {
+        for peerd in &self.connections {
+            let message = BusMsg::Ctl(CtlMsg::PingPeer);
+            let peerd = ServiceId::Peer(peerd.clone());
+            if let Err(err) = endpoints.send_to(ServiceBus::Ctl, self.identity(), peerd, message) {
+                warn!("Unable to reach peer daemon: {}", err);
+            }
+        }
+    }
+
+    /// Checks whether any of the peer daemons is connected to the remote node
+    fn is_connected(&self, node_id: &secp256k1::PublicKey) -> bool {
+        self.connections.iter().any(|connection| {
+            matches!(connection, NodeAddr::Remote(remote) if remote.node_id == *node_id)
+        })
+    }
+
+    /// Records connection with the remote node in the address book, completing its
+    /// reconnection. Incoming connections are identified by the local node id, so they are not
+    /// attributed to the remote nodes.
+    fn register_connection(&mut self, remote_addr: &RemoteNodeAddr) {
+        let node_id = remote_addr.node_id;
+        if node_id == self.node_id {
+            return;
+        }
+        if let Some(reconnect) = self.reconnects.remove(&node_id) {
+            info!(
+                "Remote peer {} is {} after {} attempts",
+                node_id,
+                "reconnected".ended(),
+                reconnect.attempts
+            );
+        }
+        let addr = Some(remote_addr.remote_addr.clone());
+        if let Err(err) = self.address_book.register_connection(node_id, addr) {
+            warn!("Unable to register connection with the node {}: {}", node_id, err);
+        }
+    }
+
+    /// Records channel in the address book entry of its remote peer, such that the peer gets
+    /// reconnected after the connection loss
+    fn register_peer_channel(&mut self, channel_id: ChannelId) {
+        let node_id = match self.channel_peers.get(&channel_id) {
+            Some(NodeAddr::Remote(remote_addr)) if remote_addr.node_id != self.node_id => {
+                remote_addr.node_id
+            }
+            _ => return,
+        };
+        if let Err(err) = self.address_book.register_channel(node_id, channel_id) {
+            warn!("Unable to register channel {} with the node {}: {}", channel_id, node_id, err);
+        }
+    }
+
+    /// Removes closed channel from the address book, such that its remote peer is not
+    /// reconnected anymore unless it has other channels or is pinned
+    fn forget_peer_channel(&mut self, channel_id: ChannelId) {
+        if let Err(err) = self.address_book.forget_channel(channel_id) {
+            warn!("Unable to remove channel {} from the address book: {}", channel_id, err);
+        }
+    }
+
+    /// Adds the channel to the static channel backup, publishing the updated backup to the node
+    /// event subscribers
+    fn backup_channel(&mut self, backup: ChannelBackup) {
+        let channel_id = backup.channel_id;
+        match self.channel_backups.register(backup) {
+            Ok(Some(data)) => {
+                info!("Channel {} is added to the static channel backup", channel_id);
+                self.publish_channel_backup(data);
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Unable to back up channel {}: {}", channel_id, err),
+        }
+    }
+
+    /// Removes closed channel from the static channel backup, publishing the updated backup to
+    /// the node event subscribers
+    fn forget_channel_backup(&mut self, channel_id: ChannelId) {
+        match self.channel_backups.forget(channel_id) {
+            Ok(Some(data)) => {
+                info!("Channel {} is removed from the static channel backup", channel_id);
+                self.publish_channel_backup(data);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!("Unable to remove channel {} from the static backup: {}", channel_id, err)
+            }
+        }
+    }
+
+    fn publish_channel_backup(&self, data: Vec<u8>) {
+        let channels = self.channel_backups.len() as u32;
+        self.publish_event(NodeEvent::ChannelBackup { channels, data: data.to_hex() });
+    }
+
+    /// Tells the remote peer of the recovered channels that the local node has lost their state,
+    /// which makes the peer reply with its current per-commitment point
+    fn reestablish_recoveries(
+        &self,
+        endpoints: &mut Endpoints,
+        remote_peer: &NodeAddr,
+    ) -> Result<(), Error> {
+        let recoveries = self.recoveries.values().filter(|recovery| {
+            recovery.remote_per_commitment_point.is_none()
+                && is_same_node(&recovery.backup.remote_peer, remote_peer)
+        });
+        for recovery in recoveries {
+            let channel_id = recovery.backup.channel_id;
+            debug!("Reestablishing recovered channel {} with {}", channel_id, remote_peer);
+            // Zero commitment numbers with an empty secret signal the loss of the channel data
+            // (`option_data_loss_protect`); any valid point may be provided as our own
+            let channel_reestablish = ChannelReestablish {
+                channel_id,
+                next_commitment_number: 1,
+                next_revocation_number: 0,
+                your_last_per_commitment_secret: Slice32::default(),
+                my_current_per_commitment_point: self.node_id,
+            };
+            endpoints.send_to(
+                ServiceBus::Msg,
+                self.identity(),
+                ServiceId::Peer(remote_peer.clone()),
+                BusMsg::Ln(LnMsg::ChannelReestablish(channel_reestablish)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Asks signd to sign the transaction sweeping our funds from the recovered channel, once
+    /// the channel is closed and the payment basepoint is derived
+    fn sweep_recovered_channel(
+        &mut self,
+        endpoints: &mut Endpoints,
+        channel_id: ChannelId,
+    ) -> Result<(), Error> {
+        let recovery = self.recoveries.get(&channel_id).expect("recovery must be registered");
+        if recovery.closing_tx.is_none() || recovery.payment_basepoint.is_none() {
+            return Ok(());
+        }
+        let sweep_script = self.funding_wallet.next_funding_address()?.script_pubkey();
+        let feerate_per_kw = self.funding_wallet.feerate_per_kw();
+        let psbt = match recovery.compose_sweep(sweep_script, feerate_per_kw) {
+            Ok(psbt) => psbt,
+            Err(err) => {
+                warn!("Unable to sweep funds of recovered channel {}: {}", channel_id, err);
+                self.recoveries.remove(&channel_id);
+                self.forget_peer_channel(channel_id);
+                self.forget_channel_backup(channel_id);
+                return Ok(());
+            }
+        };
+        let per_commitment_point = recovery.signing_point();
+        let txid = psbt.global.unsigned_tx.txid();
+        debug!("Signing sweep transaction {} of recovered channel {}", txid, channel_id);
+        self.recoveries.get_mut(&channel_id).expect("recovery is present").sweep_txid = Some(txid);
+        self.send_ctl(
+            endpoints,
+            ServiceId::Signer,
+            CtlMsg::SignToRemote { psbt, per_commitment_point },
+        )?;
+        Ok(())
+    }
+
+    /// Finds recovered channel which sweep transaction is being signed
+    fn recovery_by_sweep(&self, txid: Txid) -> Option<ChannelId> {
+        self.recoveries
+            .values()
+            .find(|recovery| recovery.sweep_txid == Some(txid))
+            .map(|recovery| recovery.backup.channel_id)
+    }
+
+    /// Sets whether the remote peer is reconnected even if it has no channels with the node
+    fn pin_peer(&mut self, node_id: secp256k1::PublicKey, pinned: bool) -> Result<String, Error> {
+        if !self.address_book.set_pinned(node_id, pinned)? {
+            let message = format!("node {} is not known to the address book", node_id);
+            return Err(RpcError::new(ErrorCode::NotFound, message).into());
+        }
+        let node = self.address_book.node(&node_id);
+        if !node.map(NodeEntry::is_reconnected).unwrap_or_default() {
+            let suspended = node.map(|node| node.suspended).unwrap_or_default();
+            self.reconnects.remove(&node_id);
+            if pinned && suspended {
+                return Ok(format!(
+                    "Peer {} is pinned, but it is not reconnected since it was disconnected \
+                     permanently; please connect it manually",
+                    node_id
+                ));
+            }
+            return Ok(format!("Peer {} is unpinned", node_id));
+        }
+        if !self.is_connected(&node_id) {
+            self.schedule_reconnect(node_id);
+        }
+        Ok(match (pinned, self.address_book.address(&node_id)) {
+            (true, Some(_)) => format!("Peer {} is pinned", node_id),
+            (true, None) => format!(
+                "Peer {} is pinned; it will be connected once its address is learned from the \
+                 gossip",
+                node_id
+            ),
+            (false, _) => format!(
+                "Peer {} is unpinned, but it is still reconnected since it has channels with the \
+                 node",
+                node_id
+            ),
+        })
+    }
+
+    /// Active bans of the remote peers
+    fn list_bans(&self) -> List<BanInfo> {
+        let now =
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.ban_list
+            .bans()
+            .map(|(node_id, ban)| BanInfo {
+                node_id: *node_id,
+                address: ban.address,
+                until: ban.until,
+                remaining: Duration::from_secs(ban.until.saturating_sub(now)),
+                reason: ban.reason.clone(),
+            })
+            .collect()
+    }
+
+    /// Bans the remote peer on the node operator request. Unlike the bans for the protocol
+    /// violations, the operator may ban peers having channels with the node.
+    fn ban_peer(&mut self, endpoints: &mut Endpoints, ban_peer: BanPeer) -> Result<String, Error> {
+        let BanPeer { node_id, duration } = ban_peer;
+        let duration = duration.map(Duration::from_secs).unwrap_or(self.config.ban_duration);
+        let ip = self
+            .address_book
+            .address(&node_id)
+            .map(InetSocketAddr::from)
+            .and_then(|addr| SocketAddr::try_from(addr).ok())
+            .map(|addr| addr.ip());
+        info!("{} remote peer {} for {}s", "Banning".promo(), node_id, duration.as_secs());
+        let reason = "banned by the node operator";
+        self.ban_list.ban(node_id, ip, duration, reason)?;
+        self.misbehaviour_scores.remove(&node_id);
+        let closed = self.expel_peer(endpoints, node_id, None, reason)?;
+        Ok(format!(
+            "Peer {} is banned for {} seconds; {} connections with it are closed",
+            node_id,
+            duration.as_secs(),
+            closed
+        ))
+    }
+
+    /// Lifts the ban of the remote peer, resuming its reconnection if the peer is pinned or has
+    /// channels
+    fn unban_peer(&mut self, node_id: secp256k1::PublicKey) -> Result<String, Error> {
+        if !self.ban_list.unban(node_id)? {
+            let message = format!("node {} is not banned", node_id);
+            return Err(RpcError::new(ErrorCode::NotFound, message).into());
+        }
+        info!("{} remote peer {}", "Unbanning".promo(), node_id);
+        let node = self.address_book.node(&node_id);
+        if node.map(NodeEntry::is_reconnected).unwrap_or_default() && !self.is_connected(&node_id) {
+            self.schedule_reconnect(node_id);
+        }
+        Ok(format!("Peer {} is unbanned", node_id))
+    }
+
+    /// Adds the penalty for the protocol violation to the misbehaviour score of the remote peer,
+    /// banning the peer once the score crosses the ban threshold. Peers with channels
+    /// are never banned automatically, since the ban would leave their channels unattended.
+    fn score_misbehaviour(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        misbehaviour: &Misbehaviour,
+    ) -> Result<(), Error> {
+        let node_id = misbehaviour.remote_id;
+        debug!("Remote peer {} has violated the protocol: {}", node_id, misbehaviour.offence);
+        let score = self.misbehaviour_scores.entry(node_id).or_default();
+        if !score.penalize(misbehaviour.penalty) {
+            return Ok(());
+        }
+        let has_channels = misbehaviour.has_channels
+            || self
+                .address_book
+                .node(&node_id)
+                .map(|node| !node.channels.is_empty())
+                .unwrap_or_default();
+        if has_channels {
+            warn!(
+                "Remote peer {} repeatedly violates the protocol, but it is not banned since it \
+                 has channels with the node",
+                node_id
+            );
+            return Ok(());
+        }
+        self.misbehaviour_scores.remove(&node_id);
+        let ip = SocketAddr::try_from(misbehaviour.remote_socket).ok().map(|addr| addr.ip());
+        let duration = self.config.ban_duration;
+        let reason = format!("repeated protocol violations, last being {}", misbehaviour.offence);
+        warn!(
+            "{} remote peer {} for {}s: {}",
+            "Banning".err(),
+            node_id,
+            duration.as_secs(),
+            reason
+        );
+        self.ban_list.ban(node_id, ip, duration, &reason)?;
+        self.expel_peer(endpoints, node_id, Some(source), &reason)?;
+        Ok(())
+    }
+
+    /// Orders the peer daemons connected to the banned node to close their connections, which
+    /// includes the inbound connection reported by `source`, since inbound connections are not
+    /// identified by the remote node id. Returns the number of the closed connections.
+    fn expel_peer(
+        &mut self,
+        endpoints: &mut Endpoints,
+        node_id: secp256k1::PublicKey,
+        source: Option<ServiceId>,
+        reason: &str,
+    ) -> Result<usize, Error> {
+        self.reconnects.remove(&node_id);
+        let mut peers = self
+            .connections
+            .iter()
+            .filter(|addr| matches!(addr, NodeAddr::Remote(remote) if remote.node_id == node_id))
+            .cloned()
+            .map(ServiceId::Peer)
+            .collect::<HashSet<_>>();
+        peers.extend(source.filter(|service| matches!(service, ServiceId::Peer(_))));
+        for peer in &peers {
+            self.send_ctl(endpoints, peer.clone(), CtlMsg::Banned(reason.to_owned()))?;
+        }
+        Ok(peers.len())
+    }
+
+    /// Forgets expired bans and the misbehaviour scores which have decayed
+    fn expire_bans(&mut self) {
+        if let Err(err) = self.ban_list.expire() {
+            warn!("Unable to remove expired bans: {}", err);
+        }
+        self.misbehaviour_scores.retain(|_, score| !score.is_cleared());
+    }
+
+    /// Schedules reconnection of the remote peer, unless it is already scheduled
+    fn schedule_reconnect(&mut self, node_id: secp256k1::PublicKey) {
+        debug!("Scheduling reconnection of the remote peer {}", node_id);
+        self.reconnects
+            .entry(node_id)
+            .or_insert(Reconnect { attempts: 0, next_attempt: SystemTime::now() });
+    }
+
+    /// Launches peer daemons for the remote peers which reconnection attempt is due. Interval
+    /// between the attempts starts from [`RECONNECT_MIN_INTERVAL`] and is doubled with each
+    /// attempt up to the configured maximum.
+    fn reconnect_peers(&mut self) {
+        let now = SystemTime::now();
+        let due = self
+            .reconnects
+            .iter()
+            .filter(|(_, reconnect)| reconnect.next_attempt <= now)
+            .map(|(node_id, _)| *node_id)
+            .collect::<Vec<_>>();
+
+        let max_interval = self.config.reconnect_max_interval;
+        for node_id in due {
+            let node = self.address_book.node(&node_id);
+            let reconnected = node.map(NodeEntry::is_reconnected).unwrap_or_default();
+            if !reconnected || self.is_connected(&node_id) {
+                self.reconnects.remove(&node_id);
+                continue;
+            }
+            // Peers banned by the node operator are reconnected once the ban expires
+            if self.ban_list.is_banned(&node_id) {
+                trace!("Remote peer {} is banned; postponing reconnection", node_id);
+                continue;
+            }
+            let remote_addr = self.address_book.address(&node_id);
+            let reconnect = self.reconnects.get_mut(&node_id).expect("reconnect is due");
+            let interval = RECONNECT_MIN_INTERVAL
+                .checked_mul(2u32.saturating_pow(reconnect.attempts))
+                .unwrap_or(max_interval)
+                .min(max_interval);
+            reconnect.next_attempt = now + interval;
+            let remote_addr = match remote_addr {
+                Some(remote_addr) => remote_addr,
+                None => {
+                    debug!(
+                        "Address of the remote peer {} is unknown; postponing reconnection",
+                        node_id
+                    );
+                    continue;
+                }
+            };
+            reconnect.attempts += 1;
+            let tor_always = self.config.tor_proxy.map(|proxy| proxy.always).unwrap_or_default();
+            let timeout = if tor_always || is_onion(remote_addr) {
+                TOR_RECONNECT_TIMEOUT
+            } else {
+                RECONNECT_TIMEOUT
+            };
+            let addr = RemoteNodeAddr { node_id, remote_addr };
+            info!(
+                "{} to remote peer {} (attempt {})",
+                "Reconnecting".promo(),
+                addr.promoter(),
+                reconnect.attempts
+            );
+            let peer_socket = PeerSocket::Connect(addr, Some(timeout));
+            let peerd = Daemon::Peerd(peer_socket, self.node_key_path.clone());
+            if let Err(err) = self.launch_daemon(peerd, self.config.clone()) {
+                error!("{}", err.err());
+            }
+        }
+    }
+
+    /// Connects random peers found by the DNS bootstrap once the resolution completes
+    fn bootstrap_peers(&mut self) {
+        let mut peers = match self.bootstrap.as_ref().map(mpsc::Receiver::try_recv) {
+            None | Some(Err(mpsc::TryRecvError::Empty)) => return,
+            Some(Err(mpsc::TryRecvError::Disconnected)) => vec![],
+            Some(Ok(peers)) => peers,
+        };
+        self.bootstrap = None;
+        peers.retain(|peer| !self.ban_list.is_banned(&peer.node_id));
+        peers.shuffle(&mut rand::thread_rng());
+        let mut node_ids = HashSet::new();
+        peers.retain(|peer| node_ids.insert(peer.node_id));
+        if peers.is_empty() {
+            warn!("DNS bootstrap has found no peers; please connect a peer manually");
+            return;
+        }
+        for addr in peers.into_iter().take(BOOTSTRAP_PEERS) {
+            info!("{} to bootstrap peer {}", "Connecting".promo(), addr.promoter());
+            let peer_socket = PeerSocket::Connect(addr, Some(RECONNECT_TIMEOUT));
+            let peerd = Daemon::Peerd(peer_socket, self.node_key_path.clone());
+            if let Err(err) = self.launch_daemon(peerd, self.config.clone()) {
+                error!("{}", err.err());
+            }
+        }
+    }
+
+    /// Counts lost connection with the remote peer under the short name of its cause
+    fn count_disconnect(&mut self, node_id: secp256k1::PublicKey, cause: &str) {
+        let stats = self.peer_stats.entry(node_id).or_default();
+        *stats.disconnects.entry(cause.to_owned()).or_default() += 1;
+    }
+
+    /// Information about the remote peers being reconnected for the peer listing
+    fn reconnect_info(&self) -> Vec<ReconnectInfo> {
+        let now = SystemTime::now();
+        self.reconnects
+            .iter()
+            .map(|(node_id, reconnect)| {
+                let node = self.address_book.node(node_id);
+                ReconnectInfo {
+                    node_id: *node_id,
+                    remote_socket: self.address_book.address(node_id).map(InetSocketAddr::from),
+                    pinned: node.map(|node| node.pinned).unwrap_or_default(),
+                    attempts: reconnect.attempts,
+                    next_attempt: reconnect.next_attempt.duration_since(now).unwrap_or_default(),
+                    last_connected: node.and_then(|node| node.last_connected),
+                    // Connection statistics are filled in by the peer listing
+                    handshakes: 0,
+                    disconnects: none!(),
+                }
+            })
+            .collect()
+    }
+
+    /// Stops daemons of the channel negotiations which have not reached the funding stage and
+    /// have had no activity for longer than the configured idle timeout, releasing funds reserved
+    /// for them and removing them from the routing table
+    fn reap_stale_channels(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let idle_timeout = self.config.channel_idle_timeout;
+        let stale = self
+            .channel_activity
+            .iter()
+            .filter(|(_, (_, last_activity))| *last_activity + idle_timeout <= now)
+            .map(|(channeld, (channel_id, _))| (channeld.clone(), *channel_id))
+            .collect::<Vec<_>>();
+
+        for (channeld, active_channel_id) in stale {
+            warn!("{} stale channel negotiation {}", "Reaping".promo(), active_channel_id);
+            self.channel_activity.remove(&channeld);
+            // Channel daemon parks its state, abandoning the negotiations, and exits
+            let message = BusMsg::Ctl(CtlMsg::Shutdown);
+            if let Err(err) =
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), channeld.clone(), message)
+            {
+                warn!("Unable to stop {}: {}", channeld, err);
+            }
+
+            let channel_id = ChannelId::from_inner(active_channel_id.as_slice32());
+            self.channels.remove(&channel_id);
+            self.channel_peers.remove(&channel_id);
+            self.channel_routes.retain(|_, route| *route != channeld);
+            self.creating_channels.remove(&channeld);
+            self.accepting_channels.remove(&channeld);
+            if let Some(index) = self.batch_index(&channeld) {
+                let batch = self.funding_batches.remove(index);
+                let reason = format!("negotiation of {} is stale", active_channel_id);
+                self.fail_batch(endpoints, batch, Some(&channeld), reason)?;
+            }
+            if let Some(temp_channel_id) = active_channel_id.temp_channel_id() {
+                if let Some(funding) = self.funding_wallet.release_funding(temp_channel_id)? {
+                    info!(
+                        "{} {} funding UTXOs reserved for stale channel {}",
+                        "Released".ended(),
+                        funding.prev_outpoints.len(),
+                        temp_channel_id.ender()
+                    );
+                }
+            }
+            self.reaped_channels.push(channel_id);
+        }
+        Ok(())
+    }
+
+    /// Statistics of the incoming connections summed over all the listeners
+    fn inbound_stats(&self) -> InboundStats {
+        self.listens
+            .iter()
+            .filter_map(|addr| match addr {
+                RemoteSocketAddr::Ftcp(inet_addr) | RemoteSocketAddr::Websocket(inet_addr) => {
+                    InboundStats::load(&InboundStats::file(&self.config.data_dir, *inet_addr))
+                }
+                _ => None,
+            })
+            .fold(InboundStats::default(), |sum, stats| InboundStats {
+                peers: sum.peers + stats.peers,
+                rate_limited: sum.rate_limited + stats.rate_limited,
+                slots_exhausted: sum.slots_exhausted + stats.slots_exhausted,
+                evicted: sum.evicted + stats.evicted,
+                handshake_timeouts: sum.handshake_timeouts + stats.handshake_timeouts,
+                init_timeouts: sum.init_timeouts + stats.init_timeouts,
+                oversized_messages: sum.oversized_messages + stats.oversized_messages,
+                banned: sum.banned + stats.banned,
+            })
+    }
+
+    fn listen(&mut self, addr: RemoteSocketAddr) -> Result<String, Error> {
+        info!("Starting peer connection listening daemon on {}...", addr);
+        let handle = self.launch_daemon(
+            Daemon::Peerd(PeerSocket::Listen(addr), self.node_key_path.clone()),
+            self.config.clone(),
+        )?;
+        Ok(format!("Launched new instance of {}", handle))
+    }
+
+    /// Collects on-chain funds of the funding wallet. Channel balances are left empty, since
+    /// they are reported by the channel daemons.
+    fn funds_info(&mut self) -> Result<FundsInfo, Error> {
+        let utxos = self.funding_wallet.list_utxos()?;
+        let tip = self.funding_wallet.tip_height()?;
+        let network = self.funding_wallet.network();
+        let mut funds_info = FundsInfo {
+            bitcoin_funds: bmap! {},
+            asset_funds: none!(),
+            next_address: self.funding_wallet.next_funding_address()?,
+            utxos: vec![],
+            confirmed_sat: 0,
+            unconfirmed_sat: 0,
+            reserved_sat: 0,
+            channel_balances: vec![],
+        };
+        for funds in utxos {
+            // Funding is reserved under the temporary channel id, while the channel may have
+            // already got its permanent id
+            let reserved_for = funds.reserved_for.map(|temp_channel_id| {
+                let channel_id = ChannelId::from_inner(temp_channel_id.into_inner());
+                match self.channel_route(channel_id) {
+                    ServiceId::Channel(channel_id) => channel_id,
+                    _ => channel_id,
+                }
+            });
+            match (reserved_for, funds.height) {
+                (Some(_), _) => funds_info.reserved_sat += funds.amount,
+                (None, Some(_)) => funds_info.confirmed_sat += funds.amount,
+                (None, None) => funds_info.unconfirmed_sat += funds.amount,
+            }
+            if reserved_for.is_none() {
+                let address = AddressCompat::from_script(funds.script_pubkey.as_inner(), network)
+                    .ok_or(funding::Error::NoAddressRepresentation)?;
+                *funds_info.bitcoin_funds.entry(address).or_insert(0) += funds.amount;
+            }
+            funds_info.utxos.push(UtxoInfo {
+                outpoint: funds.outpoint,
+                amount_sat: funds.amount,
+                confirmations: funds.height.map_or(0, |height| tip.saturating_sub(height) + 1),
+                derivation: funds
+                    .terminal
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                reserved_for,
+            });
+        }
+        Ok(funds_info)
+    }
+
+    /// Issues a new funding wallet address for deposits, warning the client if there are too many
+    /// issued addresses which have not received funds
+    fn issue_address(&mut self, address_type: AddressType) -> Result<NewAddress, RpcError> {
+        let failure = |err: funding::Error, context: &str| {
+            RpcError::new(err.error_code(), format!("{}: {}", context, err))
+        };
+        let expected = match address_type {
+            AddressType::Bech32 => [bitcoin::AddressType::P2wpkh, bitcoin::AddressType::P2wsh],
+            AddressType::Taproot => {
+                return Err(RpcError::new(
+                    ErrorCode::NotSupported,
+                    "Taproot addresses are not supported by the funding wallet",
+                ))
+            }
+        };
+        // Address type is checked before issuing, such that the address index is not wasted
+        let next_address = self
+            .funding_wallet
+            .next_funding_address()
+            .map_err(|err| failure(err, "Unable to derive funding address"))?;
+        if !matches!(next_address.address_type(), Some(ty) if expected.contains(&ty)) {
+            return Err(RpcError::new(
+                ErrorCode::NotSupported,
+                format!("Funding wallet descriptor does not produce {} addresses", address_type),
+            ));
+        }
+
+        let (address, index, unused_issued) = self
+            .funding_wallet
+            .issue_address()
+            .map_err(|err| failure(err, "Unable to issue funding address"))?;
+        info!("{} funding address {} with index {}", "Issued".ended(), address, index);
+        let warning = if unused_issued >= UNUSED_ADDRESS_WARNING {
+            let warning = format!(
+                "{} issued addresses have not received funds yet; the funding wallet refuses to \
+                 issue more than {} of them",
+                unused_issued,
+                funding::GAP_LIMIT
+            );
+            warn!("{}", warning);
+            Some(warning)
+        } else {
+            None
+        };
+        Ok(NewAddress { address, derivation: format!("0/{}", index), unused_issued, warning })
+    }
+
+    /// Resolves channel id, which may be a temporary id of an already renamed channel, into the
+    /// identity of the channel daemon
+    pub(super) fn channel_route(&self, channel_id: ChannelId) -> ServiceId {
+        self.channel_routes.get(&channel_id).cloned().unwrap_or(ServiceId::Channel(channel_id))
+    }
+
+    /// Updates the routing table once a channel daemon connects under the given channel id:
+    /// channel ids routed to the previous identity of the daemon are re-routed to the new one
+    fn register_channel_route(&mut self, channel_id: ChannelId) {
+        let channeld = ServiceId::Channel(channel_id);
+        match self.channel_routes.insert(channel_id, channeld.clone()) {
+            Some(prev) if prev != channeld => {
+                for route in self.channel_routes.values_mut().filter(|route| **route == prev) {
+                    *route = channeld.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn update_chanel_id(&mut self, old_id: TempChannelId, new_id: ChannelId) -> bool {
+        let mut known = true;
+        if !self.channels.remove(&ChannelId::from(old_id)) {
+            known = false;
+            warn!("Temporary channel id {} was unknown", old_id);
+        }
+        self.channels.insert(new_id);
+        // Until the channel daemon connects under the new id we keep routing both ids to the
+        // temporary identity
+        let channeld = match self.channel_routes.get(&new_id) {
+            Some(channeld) => channeld.clone(),
+            None => self.channel_route(old_id.into()),
+        };
+        self.channel_routes.insert(old_id.into(), channeld.clone());
+        self.channel_routes.insert(new_id, channeld);
+        if let Some(remote_peer) = self.channel_peers.remove(&ChannelId::from(old_id)) {
+            self.channel_peers.insert(new_id, remote_peer);
+        }
+        self.register_peer_channel(new_id);
+        info!("Channel daemon id registered to change from {} to {}", old_id, new_id);
+        known
+    }
+}
+
+/// Checks whether the remote peer is reachable only through the Tor proxy
+fn is_onion(remote_addr: RemoteSocketAddr) -> bool {
+    match remote_addr {
+        RemoteSocketAddr::Ftcp(inet_addr) | RemoteSocketAddr::Websocket(inet_addr) => {
+            SocketAddr::try_from(inet_addr).is_err()
+        }
+        _ => false,
+    }
+}
+
+/// Checks whether both addresses belong to the same node. Remote nodes are identified by their
+/// node ids, since they may be reachable at different socket addresses.
+fn is_same_node(addr1: &NodeAddr, addr2: &NodeAddr) -> bool {
+    match (addr1, addr2) {
+        (NodeAddr::Remote(remote1), NodeAddr::Remote(remote2)) => {
+            remote1.node_id == remote2.node_id
+        }
+        _ => addr1 == addr2,
     }
 }
@@ -0,0 +1,200 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Signatures of arbitrary messages made with the node key.
+//!
+//! Signatures are compatible with `signmessage` and `verifymessage` commands of LND and
+//! c-lightning: the node key signs double SHA256 hash of the message prefixed with
+//! [`MESSAGE_PREFIX`], and the recoverable signature is encoded with zbase32. The node id of the
+//! signer is recovered from the signature and the message, so the signature does not need to be
+//! accompanied by the public key.
+
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+
+/// Prefix of the signed messages, preventing signing data which is meaningful in other contexts
+pub const MESSAGE_PREFIX: &str = "Lightning Signed Message:";
+
+/// Alphabet of the human-oriented base32 encoding
+const ZBASE32_ALPHABET: &[u8; 32] = b"ybndrfg8ejkmcpqxot1uwisza345h769";
+
+/// Errors recovering the signer of a message
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// signature is not a valid zbase32 string
+    Encoding,
+
+    /// signature must be 65 bytes long, while it has {0} bytes
+    Length(usize),
+
+    /// signature has invalid header byte {0}
+    Header(u8),
+
+    /// signature does not match any public key
+    Invalid,
+}
+
+/// Signs the message with the node key, returning zbase32-encoded recoverable signature
+pub fn sign<C: Signing>(secp: &Secp256k1<C>, message: &str, node_key: &SecretKey) -> String {
+    let signature = secp.sign_recoverable(&digest(message), node_key);
+    let (recovery_id, compact) = signature.serialize_compact();
+    let mut data = Vec::with_capacity(65);
+    // Header of the signatures made with a compressed public key
+    data.push(31 + recovery_id.to_i32() as u8);
+    data.extend_from_slice(&compact);
+    zbase32_encode(&data)
+}
+
+/// Recovers node id of the signer from zbase32-encoded signature of the message
+pub fn recover<C: Verification>(
+    secp: &Secp256k1<C>,
+    message: &str,
+    signature: &str,
+) -> Result<PublicKey, Error> {
+    let data = zbase32_decode(signature).ok_or(Error::Encoding)?;
+    if data.len() != 65 {
+        return Err(Error::Length(data.len()));
+    }
+    // Headers 27 to 30 are used by the signatures made with uncompressed public keys
+    let recovery_id = match data[0] {
+        header @ 27..=34 => (header - 27) % 4,
+        header => return Err(Error::Header(header)),
+    };
+    let recovery_id = RecoveryId::from_i32(recovery_id as i32).expect("recovery id is below 4");
+    let signature =
+        RecoverableSignature::from_compact(&data[1..], recovery_id).map_err(|_| Error::Invalid)?;
+    secp.recover(&digest(message), &signature).map_err(|_| Error::Invalid)
+}
+
+fn digest(message: &str) -> Message {
+    let data = format!("{}{}", MESSAGE_PREFIX, message);
+    let hash = sha256d::Hash::hash(data.as_bytes());
+    Message::from_slice(&hash[..]).expect("hash is 32 bytes")
+}
+
+fn zbase32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0u8;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ZBASE32_ALPHABET[(buffer >> bits) as usize & 0x1F] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ZBASE32_ALPHABET[(buffer << (5 - bits)) as usize & 0x1F] as char);
+    }
+    encoded
+}
+
+fn zbase32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0u8;
+    for ch in encoded.bytes() {
+        let value = ZBASE32_ALPHABET.iter().position(|c| *c == ch)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+        }
+    }
+    // Padding bits of the last character must be zero
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    /// Signature of "test message" made with the secret key `1`, as it is produced by
+    /// rust-lightning implementation compatible with LND `signmessage`
+    const ONE_KEY_SIG: &str = concat!(
+        "d9tibmnic9t5y41hg7hkakdcra94akas9ku3rmmj4ag9mritc8ok",
+        "4p5qzefs78c9pqfhpuftqqzhydbdwfg7u6w6wdxcqpqn4sj4e73e"
+    );
+
+    /// Signature of "Hello, lightning!" made with the secret key of 32 `0x41` bytes
+    const NODE_SIG: &str = concat!(
+        "dhu798t81b7akjdwbm67ej4ksa7p7fuu8qfi87tign1z3sfzsip9",
+        "yeojqg3f48aqzscxofknythx7e4gu39n1z4j571xjpootgnwn3ms"
+    );
+
+    const NODE_ID: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+    fn one_key() -> SecretKey {
+        let mut key = [0u8; 32];
+        key[31] = 1;
+        SecretKey::from_slice(&key).unwrap()
+    }
+
+    #[test]
+    fn sign_fixtures() {
+        let secp = Secp256k1::new();
+        assert_eq!(sign(&secp, "test message", &one_key()), ONE_KEY_SIG);
+        let node_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        assert_eq!(sign(&secp, "Hello, lightning!", &node_key), NODE_SIG);
+    }
+
+    #[test]
+    fn recover_node_id() {
+        let secp = Secp256k1::new();
+        let one_pubkey = PublicKey::from_secret_key(&secp, &one_key());
+        assert_eq!(recover(&secp, "test message", ONE_KEY_SIG), Ok(one_pubkey));
+        let node_id = PublicKey::from_str(NODE_ID).unwrap();
+        assert_eq!(recover(&secp, "Hello, lightning!", NODE_SIG), Ok(node_id));
+        // Signature of another message recovers a different key
+        assert_ne!(recover(&secp, "Hello, lightning", NODE_SIG), Ok(node_id));
+    }
+
+    #[test]
+    fn recover_uncompressed_header() {
+        let secp = Secp256k1::new();
+        // Same signature with header 27 + recovery id, used for uncompressed public keys
+        let sig = NODE_SIG.replacen('h', "c", 1);
+        let node_id = PublicKey::from_str(NODE_ID).unwrap();
+        assert_eq!(recover(&secp, "Hello, lightning!", &sig), Ok(node_id));
+    }
+
+    #[test]
+    fn recover_malformed() {
+        let secp = Secp256k1::new();
+        assert_eq!(recover(&secp, "test message", "d9tibmnic0"), Err(Error::Encoding));
+        assert_eq!(recover(&secp, "test message", &ONE_KEY_SIG[..96]), Err(Error::Length(60)));
+        let data = zbase32_decode(ONE_KEY_SIG).unwrap();
+        let mut bad_header = data;
+        bad_header[0] = 35;
+        let sig = zbase32_encode(&bad_header);
+        assert_eq!(recover(&secp, "test message", &sig), Err(Error::Header(35)));
+    }
+
+    #[test]
+    fn zbase32_round_trip() {
+        for len in 0..70 {
+            let data = (0..len).map(|byte| byte as u8 ^ 0xA5).collect::<Vec<_>>();
+            assert_eq!(zbase32_decode(&zbase32_encode(&data)), Some(data));
+        }
+    }
+}
@@ -16,11 +16,15 @@ use std::fs;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
+use bitcoin::secp256k1::PublicKey;
 use clap::ValueHint;
-use lnp_rpc::LNP_NODE_RPC_SOCKET;
+use internet2::RemoteNodeAddr;
+use lnp_rpc::{LNP_NODE_EVENTS_SOCKET, LNP_NODE_RPC_SOCKET};
 use lnpbp::chain::Chain;
 use microservices::shell::LogLevel;
 
+use crate::SignerMode;
+
 #[cfg(any(target_os = "linux"))]
 pub const LNP_NODE_DATA_DIR: &'static str = "~/.lnp_node/{chain}";
 #[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
@@ -39,10 +43,22 @@ pub const LNP_NODE_CTL_SOCKET: &str = "{data_dir}/ctl";
 
 pub const LNP_NODE_CONFIG: &str = "{data_dir}/lnp_node.toml";
 pub const LNP_NODE_TOR_PROXY: &str = "127.0.0.1:9050";
+pub const LNP_NODE_HWI: &str = "hwi";
 pub const LNP_NODE_KEY_FILE: &str = "{data_dir}/node.key";
 
 pub const LNP_NODE_MASTER_KEY_FILE: &str = "master.key";
 pub const LNP_NODE_FUNDING_WALLET: &str = "funding.wallet";
+pub const LNP_NODE_RPC_KEY_FILE: &str = "rpc.key";
+pub const LNP_NODE_ADMIN_TOKEN_FILE: &str = "admin.token";
+pub const LNP_NODE_WITHDRAWAL_TOKEN_FILE: &str = "withdrawal.token";
+pub const LNP_NODE_ADDRESS_BOOK: &str = "address.book";
+pub const LNP_NODE_FEE_POLICIES: &str = "fee_policies.dat";
+pub const LNP_NODE_BAN_LIST: &str = "ban.list";
+pub const LNP_NODE_CHANNEL_KEY_INDEX: &str = "channel_keys.index";
+pub const LNP_NODE_CHANNEL_BACKUP: &str = "channel.backup";
+pub const LNP_NODE_ONION_KEY: &str = "onion.key";
+pub const LNP_NODE_GOSSIP_STORE: &str = "gossip.store";
+pub const LNP_NODE_REBROADCAST_TXS: &str = "rebroadcast.txs";
 
 /// Shared options used by different binaries
 #[derive(Parser, Clone, PartialEq, Eq, Debug)]
@@ -79,9 +95,9 @@ pub struct Opts {
 
     /// Use Tor.
     ///
-    /// If set, specifies SOCKS5 proxy used for Tor connectivity and directs all network
-    /// traffic through Tor network. If the argument is provided in form of flag, without
-    /// value, uses `127.0.0.1:9050` as default Tor proxy address.
+    /// If set, specifies SOCKS5 proxy used for connecting remote peers at onion addresses.
+    /// If the argument is provided in form of flag, without value, uses `127.0.0.1:9050` as
+    /// default Tor proxy address.
     #[clap(
         short = 'T',
         long,
@@ -92,6 +108,12 @@ pub struct Opts {
     )]
     pub tor_proxy: Option<Option<SocketAddr>>,
 
+    /// Connect all remote peers through the Tor proxy, not only those at onion addresses.
+    ///
+    /// Hides IP address of the node from the remote peers it connects to.
+    #[clap(long, global = true, requires = "tor_proxy", env = "LNP_NODE_TOR_ALWAYS")]
+    pub tor_always: bool,
+
     /// ZMQ socket for internal message bus.
     ///
     /// A user needs to specify this socket usually if it likes to distribute daemons
@@ -135,6 +157,20 @@ pub struct Opts {
     )]
     pub rpc_socket: String,
 
+    /// ZMQ socket publishing node events to the subscribed clients.
+    ///
+    /// Socket can be either TCP address in form of `<ipv4 | ipv6>:<port>` – or a path
+    /// to an IPC file.
+    ///
+    /// Defaults to `127.0.0.1:62963`.
+    #[clap(
+        long = "events",
+        global = true,
+        default_value = LNP_NODE_EVENTS_SOCKET,
+        env = "LNP_NODE_EVENTS_SOCKET"
+    )]
+    pub events_socket: String,
+
     /// Blockchain to use
     #[clap(
         short = 'n',
@@ -161,9 +197,327 @@ pub struct Opts {
     #[clap(long, global = true, env = "LNP_NODE_ELECTRUM_PORT")]
     pub electrum_port: Option<u16>,
 
+    /// Fallback Electrum servers in `<host>[:<port>]` format, tried in the given order when the
+    /// connection with the main server is lost. May be repeated.
+    #[clap(long = "electrum-fallback", global = true, value_hint = ValueHint::Hostname)]
+    pub electrum_fallbacks: Vec<String>,
+
+    /// Blockchain backend used for tracking transactions, broadcasting them and fee estimation.
+    ///
+    /// With `bitcoind` backend the node connects Bitcoin Core JSON-RPC interface set by
+    /// `--bitcoind-rpc`; with `neutrino` backend it connects bitcoin peers set by
+    /// `--neutrino-peer` and matches compact block filters against the scripts of the tracked
+    /// transactions. Funding wallet scans its funds with Electrum server regardless of the
+    /// backend.
+    #[clap(
+        long,
+        global = true,
+        default_value = "electrum",
+        possible_values = &["electrum", "bitcoind", "neutrino"],
+        env = "LNP_NODE_CHAIN_BACKEND"
+    )]
+    pub chain_backend: String,
+
+    /// Bitcoin Core JSON-RPC address. By default uses localhost with the port matching the
+    /// selected network.
+    #[clap(long, global = true, env = "LNP_NODE_BITCOIND_RPC", value_hint = ValueHint::Url)]
+    pub bitcoind_rpc: Option<String>,
+
+    /// Cookie file authenticating Bitcoin Core JSON-RPC requests. By default uses `.cookie` file
+    /// from the Bitcoin Core data directory for the selected network.
+    #[clap(
+        long,
+        global = true,
+        env = "LNP_NODE_BITCOIND_COOKIE",
+        value_hint = ValueHint::FilePath
+    )]
+    pub bitcoind_cookie: Option<PathBuf>,
+
+    /// User and password authenticating Bitcoin Core JSON-RPC requests, in `<user>:<password>`
+    /// form.
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "bitcoind_cookie",
+        parse(try_from_str = parse_rpc_auth),
+        env = "LNP_NODE_BITCOIND_AUTH"
+    )]
+    pub bitcoind_auth: Option<(String, String)>,
+
+    /// Bitcoin Core ZMQ socket publishing `rawblock` and `rawtx` notifications.
+    ///
+    /// Without notifications mined transactions with spent first output can be found only if
+    /// Bitcoin Core maintains transaction index (`txindex=1`).
+    #[clap(long, global = true, env = "LNP_NODE_BITCOIND_ZMQ", value_hint = ValueHint::Url)]
+    pub bitcoind_zmq: Option<String>,
+
+    /// Bitcoin peers serving compact block filters for `neutrino` backend, in
+    /// `<host>[:<port>]` format, tried in the given order. May be repeated; by default uses
+    /// localhost with the port matching the selected network.
+    #[clap(long = "neutrino-peer", global = true, value_hint = ValueHint::Hostname)]
+    pub neutrino_peers: Vec<String>,
+
+    /// Height of the blockchain below which `neutrino` backend does not look for the node
+    /// transactions. Must not exceed the height at which the node has been created.
+    #[clap(
+        long,
+        global = true,
+        required_if_eq("chain_backend", "neutrino"),
+        env = "LNP_NODE_WALLET_BIRTHDAY"
+    )]
+    pub wallet_birthday: Option<u32>,
+
+    /// Minimal feerate applied to the fee estimations of the blockchain backend, in satoshis per
+    /// kilo-weight unit. Used as is by the backends unable to estimate fees.
+    #[clap(long, global = true, default_value = "253", env = "LNP_NODE_FEE_FLOOR")]
+    pub fee_floor: u32,
+
+    /// Maximal feerate applied to the fee estimations of the blockchain backend, in satoshis per
+    /// kilo-weight unit.
+    #[clap(long, global = true, default_value = "50000", env = "LNP_NODE_FEE_CEILING")]
+    pub fee_ceiling: u32,
+
+    /// Number of blocks within which the node transactions should get mined, used for the fee
+    /// estimation.
+    #[clap(long, global = true, default_value = "6", env = "LNP_NODE_FEE_TARGET")]
+    pub fee_target: u16,
+
     /// Spawn daemons as threads and not processes
     #[clap(long)]
     pub threaded_daemons: bool,
+
+    /// Number of seconds to wait for the remote peer to accept our channel proposal before
+    /// abandoning it.
+    #[clap(long, global = true, default_value = "60", env = "LNP_NODE_TIMEOUT_PROPOSED")]
+    pub timeout_proposed: u64,
+
+    /// Number of seconds to wait for the funding wallet to construct funding transaction for a
+    /// channel accepted by the remote peer before abandoning the channel.
+    #[clap(long, global = true, default_value = "60", env = "LNP_NODE_TIMEOUT_ACCEPTED")]
+    pub timeout_accepted: u64,
+
+    /// Number of seconds to wait for the refund transaction of a new channel to be signed before
+    /// abandoning the channel.
+    #[clap(long, global = true, default_value = "60", env = "LNP_NODE_TIMEOUT_SIGNING")]
+    pub timeout_signing: u64,
+
+    /// Allow channels with funding above 16777215 satoshis with the remote peers supporting them
+    /// (`option_support_large_channel`).
+    #[clap(long, global = true, env = "LNP_NODE_WUMBO")]
+    pub wumbo: bool,
+
+    /// Node id of a trusted remote peer, which channels are accepted without waiting for the
+    /// funding transaction confirmation (zero-conf channels). May be repeated.
+    #[clap(long = "zero-conf-peer", global = true)]
+    pub zero_conf_peers: Vec<PublicKey>,
+
+    /// Number of seconds within which funding transaction of a zero-conf channel must get
+    /// confirmed; otherwise the channel is failed.
+    #[clap(long, global = true, default_value = "86400", env = "LNP_NODE_TIMEOUT_ZERO_CONF")]
+    pub timeout_zero_conf: u64,
+
+    /// Number of blocks after the original funding transaction height within which funding
+    /// transaction reorged out of the blockchain must get mined again; otherwise the channel is
+    /// failed.
+    #[clap(long, global = true, default_value = "144", env = "LNP_NODE_TIMEOUT_FUNDING_REORG")]
+    pub timeout_funding_reorg: u32,
+
+    /// Number of blocks before the expiry of the earliest HTLC pending in a channel at which the
+    /// channel is force-closed, such that the HTLC gets resolved on-chain in time.
+    #[clap(long, global = true, default_value = "12", env = "LNP_NODE_HTLC_DEADLINE_MARGIN")]
+    pub htlc_deadline_margin: u32,
+
+    /// Number of seconds after which a channel negotiation without any activity is considered
+    /// stale, such that its daemon is stopped and the funds reserved for it are released. Applies
+    /// only to the channels which have not reached the funding stage.
+    #[clap(long, global = true, default_value = "3600", env = "LNP_NODE_TIMEOUT_CHANNEL_IDLE")]
+    pub timeout_channel_idle: u64,
+
+    /// Maximal number of seconds between attempts to reconnect the remote peers which have
+    /// channels with the node or are pinned. Intervals between the attempts grow exponentially
+    /// up to this value.
+    #[clap(long, global = true, default_value = "3600", env = "LNP_NODE_RECONNECT_MAX_INTERVAL")]
+    pub reconnect_max_interval: u64,
+
+    /// Number of seconds without any messages from a remote peer after which the peer is pinged
+    /// to check that the connection is still alive.
+    #[clap(long, global = true, default_value = "30", env = "LNP_NODE_PING_INTERVAL")]
+    pub ping_interval: u64,
+
+    /// Number of seconds within which a remote peer must reply to our ping; otherwise the ping
+    /// is considered missed.
+    #[clap(long, global = true, default_value = "10", env = "LNP_NODE_TIMEOUT_PONG")]
+    pub timeout_pong: u64,
+
+    /// Number of consecutive pings a remote peer may miss before the connection with it is
+    /// considered dead and gets closed.
+    #[clap(long, global = true, default_value = "3", env = "LNP_NODE_MAX_MISSED_PONGS")]
+    pub max_missed_pongs: u8,
+
+    /// Number of seconds within which a remote peer must complete BOLT-8 handshake after the TCP
+    /// connection is established; otherwise the connection is closed.
+    #[clap(long, global = true, default_value = "30", env = "LNP_NODE_TIMEOUT_HANDSHAKE")]
+    pub timeout_handshake: u64,
+
+    /// Number of seconds within which a remote peer must send its `init` message after the
+    /// handshake is completed; otherwise the connection is closed.
+    #[clap(long, global = true, default_value = "30", env = "LNP_NODE_TIMEOUT_INIT")]
+    pub timeout_init: u64,
+
+    /// Maximal number of concurrent incoming peer connections at each listening socket.
+    ///
+    /// Once the limit is reached, a remote peer without channels is disconnected to make room
+    /// for a new connection. Peers with channels are never disconnected; if all the connections
+    /// are with such peers, new connections are refused.
+    #[clap(long, global = true, default_value = "125", env = "LNP_NODE_MAX_INBOUND_PEERS")]
+    pub max_inbound_peers: u16,
+
+    /// Number of incoming connections per minute accepted from a single IP address; IPv6
+    /// addresses are limited by their /64 network. Connections over the rate are closed before
+    /// the handshake.
+    #[clap(long, global = true, default_value = "6", env = "LNP_NODE_INBOUND_RATE")]
+    pub inbound_rate: u16,
+
+    /// Number of incoming connections a single IP address may open in a quick succession before
+    /// being limited by `--inbound-rate`.
+    #[clap(long, global = true, default_value = "3", env = "LNP_NODE_INBOUND_BURST")]
+    pub inbound_burst: u16,
+
+    /// Number of seconds a remote peer is banned for once it has repeatedly violated the
+    /// protocol. Banned peers are disconnected and their connections are refused. Peers with
+    /// channels are banned only by the node operator.
+    #[clap(long, global = true, default_value = "86400", env = "LNP_NODE_BAN_DURATION")]
+    pub ban_duration: u64,
+
+    /// Maximal number of blocks remote peers may require our funds to be timelocked for after a
+    /// unilateral channel close (`to_self_delay`).
+    #[clap(long, global = true, default_value = "2016", env = "LNP_NODE_MAX_TO_SELF_DELAY")]
+    pub max_to_self_delay: u16,
+
+    /// Minimal funding amount of the channels proposed by remote peers, in satoshis.
+    #[clap(long, global = true, default_value = "0", env = "LNP_NODE_MIN_FUNDING_SAT")]
+    pub min_funding_sat: u64,
+
+    /// Maximal funding amount of the channels proposed by remote peers, in satoshis.
+    #[clap(long, global = true, env = "LNP_NODE_MAX_FUNDING_SAT")]
+    pub max_funding_sat: Option<u64>,
+
+    /// Maximal amount remote peers may push to us when opening a channel, in millisatoshis.
+    #[clap(long, global = true, env = "LNP_NODE_MAX_PUSH_MSAT")]
+    pub max_push_msat: Option<u64>,
+
+    /// Maximal share of the channel funding remote peers may push to us when opening a channel,
+    /// in percents.
+    ///
+    /// Channels pushing most of their funds to us are often used for probing or dust attacks.
+    #[clap(long, global = true, env = "LNP_NODE_MAX_PUSH_PERCENT")]
+    pub max_push_percent: Option<u8>,
+
+    /// Number of funding transaction confirmations required for the channels with at least the
+    /// given funding amount, in `<min_funding_sat>:<minimum_depth>` form. May be repeated; the
+    /// largest number of confirmations applicable to the channel amount is used.
+    ///
+    /// For the channels proposed by remote peers this is the depth we request; for the channels
+    /// proposed by us this is the minimal depth we wait for before using the channel.
+    #[clap(
+        long = "depth-tier",
+        global = true,
+        default_values = &["0:1", "100000:3", "1000000:6"],
+        parse(try_from_str = parse_depth_tier)
+    )]
+    pub depth_tiers: Vec<(u64, u32)>,
+
+    /// Maximal number of channels a single remote peer may open with us.
+    #[clap(long, global = true, env = "LNP_NODE_MAX_CHANNELS_PER_PEER")]
+    pub max_channels_per_peer: Option<u16>,
+
+    /// Node id of a remote peer allowed to open channels with us. May be repeated; if given,
+    /// channels proposed by other remote peers are rejected.
+    #[clap(long = "allow-peer", global = true)]
+    pub allow_peers: Vec<PublicKey>,
+
+    /// Node id of a remote peer not allowed to open channels with us. May be repeated.
+    #[clap(long = "deny-peer", global = true)]
+    pub deny_peers: Vec<PublicKey>,
+
+    /// Watchtower which justice data for the revoked channel states are uploaded to, in
+    /// `<node_id>@<host>:<port>` form. May be repeated.
+    #[clap(long = "tower", global = true)]
+    pub towers: Vec<RemoteNodeAddr>,
+
+    /// Node id of a remote peer the network graph is synced from with gossip queries whenever
+    /// the peer gets connected. May be repeated; if absent, the graph is synced from the first
+    /// connected peer supporting gossip queries while the graph is empty.
+    #[clap(long = "gossip-sync-peer", global = true)]
+    pub gossip_sync_peers: Vec<PublicKey>,
+
+    /// Signer holding the channel keys: `local` for the keys derived from the master key in the
+    /// data directory, or `remote:<node_id>@<host>:<port>` for a remote signer run with
+    /// `signd --serve` on a separate host.
+    #[clap(long, global = true, default_value = "local", env = "LNP_NODE_SIGNER")]
+    pub signer: SignerMode,
+
+    /// Sign funding wallet inputs with a hardware wallet through HWI.
+    ///
+    /// The funding wallet must be created from the hardware wallet account with
+    /// `lnpd init --funding-descriptor`. If the argument is provided in form of flag, without
+    /// value, uses `hwi` executable from the PATH.
+    #[clap(long, global = true, env = "LNP_NODE_HWI", value_hint = ValueHint::ExecutablePath)]
+    pub hwi: Option<Option<PathBuf>>,
+
+    /// Number of seconds to wait for the user to confirm a transaction on the hardware wallet
+    /// before cancelling the signing.
+    #[clap(
+        long,
+        global = true,
+        default_value = "120",
+        requires = "hwi",
+        env = "LNP_NODE_HWI_TIMEOUT"
+    )]
+    pub hwi_timeout: u64,
+
+    /// Funding wallet is watch-only, and its transactions are signed by the operator outside of
+    /// the node.
+    ///
+    /// The funding wallet must be imported from the external wallet descriptor with
+    /// `lnpd init --funding-descriptor`. Funding and withdrawal transactions are published as
+    /// `wallet.psbt_pending` node events and listed by `lnp-cli wallet pending`; once signed,
+    /// they are submitted back with `lnp-cli wallet submit-psbt`.
+    #[clap(long, global = true, conflicts_with = "hwi", env = "LNP_NODE_WATCH_ONLY")]
+    pub watch_only: bool,
+
+    /// File with the passphrase unlocking the encrypted master key on the node start.
+    ///
+    /// Allows unattended node restarts; otherwise the signer stays locked until it is unlocked
+    /// with `lnp-cli unlock`. The file must be readable only by the node user.
+    #[clap(long, global = true, env = "LNP_NODE_UNLOCK_FILE", value_hint = ValueHint::FilePath)]
+    pub unlock_file: Option<PathBuf>,
+
+    /// File with the static backup of all node channels, which allows to recover the channel
+    /// funds with `lnp-cli recover` after the loss of the data directory.
+    ///
+    /// The file is updated each time a channel is opened or closed and should be placed on a
+    /// storage separate from the data directory. Defaults to `channel.backup` file inside
+    /// `--data-dir` directory.
+    #[clap(long, global = true, env = "LNP_NODE_BACKUP_FILE", value_hint = ValueHint::FilePath)]
+    pub backup_file: Option<PathBuf>,
+
+    /// Maximal amount of satoshis signd signs for withdrawal without a confirmation token.
+    ///
+    /// Withdrawals above the limit must be confirmed with the token from `withdrawal.token` file
+    /// of the data directory, which signd creates on its first start with the limit set. If
+    /// absent, withdrawals are not limited.
+    #[clap(long, global = true, env = "LNP_NODE_WITHDRAWAL_LIMIT")]
+    pub withdrawal_limit: Option<u64>,
+
+    /// Allows export of the channel basepoints and the revocation secrets received from the
+    /// remote peers with `lnp-cli channel export-secrets`.
+    ///
+    /// The export requires the admin RPC token. Revocation secrets allow to claim all channel
+    /// funds if the remote peer publishes a revoked commitment transaction, so the exported data
+    /// must be protected like the node keys.
+    #[clap(long, global = true, env = "LNP_NODE_UNSAFE_EXPORT")]
+    pub unsafe_export: bool,
 }
 
 impl Opts {
@@ -188,6 +542,27 @@ impl Opts {
     }
 }
 
+/// Parses depth tier given in `<min_funding_sat>:<minimum_depth>` form
+fn parse_depth_tier(s: &str) -> Result<(u64, u32), String> {
+    let err = || format!("depth tier '{}' must be in `<min_funding_sat>:<minimum_depth>` form", s);
+    let mut split = s.split(':');
+    match (split.next(), split.next(), split.next()) {
+        (Some(amount), Some(depth), None) => Ok((
+            amount.parse().map_err(|_| err())?,
+            depth.parse().map_err(|_| err())?,
+        )),
+        _ => Err(err()),
+    }
+}
+
+/// Parses Bitcoin Core RPC credentials given in `<user>:<password>` form
+fn parse_rpc_auth(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((user, password)) if !user.is_empty() => Ok((user.to_owned(), password.to_owned())),
+        _ => Err(s!("Bitcoin Core RPC credentials must be in `<user>:<password>` form")),
+    }
+}
+
 pub fn process_dir(path: &mut String, data_dir: &str) {
     *path = path.replace("{data_dir}", data_dir);
     *path = shellexpand::tilde(path).to_string();
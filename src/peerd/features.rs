@@ -0,0 +1,97 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-9 features advertised by the node in its `init` message and their negotiation with the
+//! remote peers.
+//!
+//! A feature is negotiated once both the node and the remote peer advertise it. Features are
+//! advertised only if they are implemented by the node: for instance, `payment_secret` and
+//! `basic_mpp` are recognized in the remote peer features, but the node does not advertise
+//! them, since it neither issues invoices nor accepts multi-part payments.
+
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::Init;
+
+use crate::Config;
+
+/// Features supported by the node, which are advertised to the remote peers
+pub fn local_features(config: &Config) -> InitFeatures {
+    InitFeatures {
+        option_data_loss_protect: true,
+        var_onion_optin: true,
+        option_static_remotekey: true,
+        option_anchor_outputs: true,
+        option_anchors_zero_fee_htlc_tx: true,
+        option_channel_type: true,
+        gossip_queries: true,
+        option_support_large_channel: config.wumbo,
+        ..none!()
+    }
+}
+
+/// Our `init` message advertising the local features
+pub fn init_message(local_features: InitFeatures) -> Init {
+    Init { global_features: none!(), local_features, assets: none!(), unknown_tlvs: none!() }
+}
+
+/// Features which the remote peer must support for the node to keep the connection. Payments
+/// are routed with TLV onion payloads, which can't be forwarded through the peers not supporting
+/// `var_onion_optin`.
+pub fn required_features() -> InitFeatures { InitFeatures { var_onion_optin: true, ..none!() } }
+
+/// Features supported both by the local node and the remote peer
+pub fn negotiate(local: &InitFeatures, remote: &InitFeatures) -> InitFeatures {
+    InitFeatures {
+        option_data_loss_protect: local.option_data_loss_protect
+            && remote.option_data_loss_protect,
+        var_onion_optin: local.var_onion_optin && remote.var_onion_optin,
+        option_static_remotekey: local.option_static_remotekey && remote.option_static_remotekey,
+        payment_secret: local.payment_secret && remote.payment_secret,
+        basic_mpp: local.basic_mpp && remote.basic_mpp,
+        option_support_large_channel: local.option_support_large_channel
+            && remote.option_support_large_channel,
+        option_anchor_outputs: local.option_anchor_outputs && remote.option_anchor_outputs,
+        option_anchors_zero_fee_htlc_tx: local.option_anchors_zero_fee_htlc_tx
+            && remote.option_anchors_zero_fee_htlc_tx,
+        option_channel_type: local.option_channel_type && remote.option_channel_type,
+        gossip_queries: local.gossip_queries && remote.gossip_queries,
+        ..none!()
+    }
+}
+
+/// Names of the required features which the remote peer does not support
+pub fn missing_features(required: &InitFeatures, remote: &InitFeatures) -> Vec<String> {
+    let remote = feature_names(remote);
+    feature_names(required).into_iter().filter(|name| !remote.contains(name)).collect()
+}
+
+/// Names of the features known to the node which are set in the node features
+pub fn feature_names(features: &InitFeatures) -> Vec<String> {
+    [
+        ("option_data_loss_protect", features.option_data_loss_protect),
+        ("var_onion_optin", features.var_onion_optin),
+        ("option_static_remotekey", features.option_static_remotekey),
+        ("payment_secret", features.payment_secret),
+        ("basic_mpp", features.basic_mpp),
+        ("option_support_large_channel", features.option_support_large_channel),
+        ("option_anchor_outputs", features.option_anchor_outputs),
+        ("option_anchors_zero_fee_htlc_tx", features.option_anchors_zero_fee_htlc_tx),
+        ("option_channel_type", features.option_channel_type),
+        ("gossip_queries", features.gossip_queries),
+    ]
+    .iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| name.to_string())
+    .collect()
+}
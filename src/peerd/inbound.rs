@@ -0,0 +1,512 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Admission of the incoming peer connections by the peer listener.
+//!
+//! Each incoming connection is served by a dedicated peer daemon, so opening thousands of TCP
+//! connections would exhaust the node. The listener admits connections before spawning the
+//! daemon and before reading any handshake data: connections from an IP address exceeding its
+//! rate are closed right away, as well as the connections arriving once all the inbound slots
+//! are taken. In the latter case the listener first tries to evict a peer which has no channels
+//! with the node; peers with channels are never evicted.
+//!
+//! Peer daemons forked by the listener notify it through a pipe once they get a channel. Daemons
+//! running as threads can't be stopped by the listener, so they are not evicted.
+//!
+//! Connections which do not complete the handshake or do not send `init` message in time are
+//! closed by their daemons. Forked daemons report the reason with their exit code and daemons
+//! running as threads with their result, so the listener counts such connections. Such failures
+//! add to the misbehaviour score of the IP address, which is banned by the listener for the
+//! configured ban duration once the score crosses the threshold. Connections from the addresses
+//! of the peers banned by lnpd are refused as well, with the ban list re-read once lnpd updates
+//! it.
+//!
+//! The listener process has no connection to the node buses, so it saves its statistics to the
+//! data directory, where lnpd reads them for the node info.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use internet2::addr::InetSocketAddr;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, Pid};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::misbehaviour::Offence;
+use super::supervisor::Handler;
+use crate::lnpd::ban_list::{BanList, Score};
+use crate::{Error, InboundLimits};
+
+/// Minimal interval between saving the listener statistics
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Statistics of the incoming connections at a listening socket
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, StrictEncode, StrictDecode)]
+pub struct InboundStats {
+    /// Number of the connections being served
+    pub peers: u32,
+
+    /// Number of the connections refused since their IP address has exceeded the rate limit
+    pub rate_limited: u64,
+
+    /// Number of the connections refused since all the inbound slots were taken by the peers
+    /// which can't be evicted
+    pub slots_exhausted: u64,
+
+    /// Number of the peers disconnected to free a slot for a new connection
+    pub evicted: u64,
+
+    /// Number of the connections closed since the remote peer has not completed BOLT-8
+    /// handshake in time
+    pub handshake_timeouts: u64,
+
+    /// Number of the connections closed since the remote peer has not sent `init` message in time
+    pub init_timeouts: u64,
+
+    /// Number of the connections closed since the remote peer has sent a message exceeding the
+    /// maximal BOLT-8 message length
+    pub oversized_messages: u64,
+
+    /// Number of the connections refused since their IP address is banned
+    pub banned: u64,
+}
+
+impl InboundStats {
+    /// File keeping the statistics of the listener at the given socket
+    pub fn file(data_dir: &Path, listener: InetSocketAddr) -> PathBuf {
+        data_dir.join(format!("inbound-{}.stats", listener))
+    }
+
+    /// Reads the statistics saved by a listener; returns `None` if the listener has not saved
+    /// them yet
+    pub fn load(path: &Path) -> Option<InboundStats> {
+        let file = fs::File::open(path).ok()?;
+        InboundStats::strict_decode(file).ok()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let data = self.strict_serialize().map_err(Error::Persistence)?;
+        // lnpd must never read a partially written file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Reason of closing an incoming connection which is counted by the listener
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Abort {
+    HandshakeTimeout = 10,
+    InitTimeout = 11,
+    OversizedMessage = 12,
+    HandshakeFailure = 13,
+}
+
+impl Abort {
+    /// Detects the reason of closing the connection from the daemon error
+    pub fn with(err: &Error) -> Option<Abort> {
+        match err {
+            Error::HandshakeTimeout(_) => Some(Abort::HandshakeTimeout),
+            Error::InitTimeout(_) => Some(Abort::InitTimeout),
+            Error::OversizedMessage(_) => Some(Abort::OversizedMessage),
+            Error::Handshake(_) => Some(Abort::HandshakeFailure),
+            _ => None,
+        }
+    }
+
+    /// Exit code of the forked daemon telling the listener the reason of closing the connection
+    pub fn exit_code(self) -> i32 { self as i32 }
+
+    /// Protocol violation the reason of closing the connection is scored as
+    fn offence(self) -> Offence {
+        match self {
+            Abort::HandshakeTimeout | Abort::InitTimeout | Abort::HandshakeFailure => {
+                Offence::HandshakeFailure
+            }
+            Abort::OversizedMessage => Offence::OversizedMessage,
+        }
+    }
+
+    fn from_exit_code(code: i32) -> Option<Abort> {
+        [
+            Abort::HandshakeTimeout,
+            Abort::InitTimeout,
+            Abort::OversizedMessage,
+            Abort::HandshakeFailure,
+        ]
+        .iter()
+        .copied()
+        .find(|abort| abort.exit_code() == code)
+    }
+}
+
+/// Notification sent by a forked peer daemon to its listener once the daemon gets a channel
+/// with the remote peer, which protects the connection from eviction
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ChannelSignal {
+    fd: RawFd,
+    slot: u32,
+}
+
+impl ChannelSignal {
+    pub fn send(self) {
+        if let Err(err) = unistd::write(self.fd, &self.slot.to_le_bytes()) {
+            warn!("Unable to notify the peer listener about the channel: {}", err);
+        }
+    }
+}
+
+/// Connection rate allowance of an IP address
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, limits: InboundLimits) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limits.rate as f64 / 60.0).min(limits.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Incoming connection served by a peer daemon
+struct Slot {
+    id: u32,
+    ip: IpAddr,
+    handler: Handler,
+    channels: bool,
+}
+
+/// Admission control of the incoming connections at a listening socket
+pub(super) struct Admission {
+    limits: InboundLimits,
+    buckets: HashMap<IpAddr, Bucket>,
+    ban_duration: Duration,
+    /// Misbehaviour scores of the addresses which connections have failed the handshake
+    scores: HashMap<IpAddr, Score>,
+    /// Addresses banned by the listener, with the time their bans expire
+    banned: HashMap<IpAddr, Instant>,
+    /// Ban list of lnpd, with the modification time of its file when it was read
+    ban_list: Option<(BanList, SystemTime)>,
+    ban_list_file: PathBuf,
+    slots: Vec<Slot>,
+    next_slot: u32,
+    /// Read and write ends of the pipe receiving channel signals from the forked daemons
+    signals: Option<(RawFd, RawFd)>,
+    stats: InboundStats,
+    stats_file: PathBuf,
+    stats_changed: bool,
+    stats_saved: Option<Instant>,
+}
+
+impl Admission {
+    pub fn with(
+        limits: InboundLimits,
+        ban_duration: Duration,
+        stats_file: PathBuf,
+        ban_list_file: PathBuf,
+        forked: bool,
+    ) -> Admission {
+        let signals = if forked {
+            let (read, write) = unistd::pipe().expect("Unable to create channel signal pipe");
+            fcntl(read, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+                .expect("Unable to set up channel signal pipe");
+            Some((read, write))
+        } else {
+            None
+        };
+        Admission {
+            limits,
+            buckets: empty!(),
+            ban_duration,
+            scores: empty!(),
+            banned: empty!(),
+            ban_list: None,
+            ban_list_file,
+            slots: vec![],
+            next_slot: 0,
+            signals,
+            stats: InboundStats::default(),
+            stats_file,
+            stats_changed: true,
+            stats_saved: None,
+        }
+    }
+
+    /// Number of the connections being served
+    pub fn peers(&self) -> usize { self.slots.len() }
+
+    /// Forgets finished daemons, registers channel signals, expires bans and saves the
+    /// statistics
+    pub fn maintain(&mut self) {
+        self.reap();
+        self.receive_signals();
+        self.reload_ban_list();
+
+        let now = Instant::now();
+        let limits = self.limits;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now, limits);
+            bucket.tokens < limits.burst as f64
+        });
+        self.banned.retain(|_, until| *until > now);
+        self.scores.retain(|_, score| !score.is_cleared());
+
+        if self.stats.peers != self.slots.len() as u32 {
+            self.stats.peers = self.slots.len() as u32;
+            self.stats_changed = true;
+        }
+        let save_due =
+            self.stats_saved.map_or(true, |saved| now.duration_since(saved) >= STATS_SAVE_INTERVAL);
+        if self.stats_changed && save_due {
+            if let Err(err) = self.stats.save(&self.stats_file) {
+                warn!("Unable to save incoming connection statistics: {}", err);
+            }
+            self.stats_changed = false;
+            self.stats_saved = Some(now);
+        }
+    }
+
+    /// Decides whether the connection from the given address is served, evicting another peer
+    /// if necessary
+    pub fn admit(&mut self, ip: IpAddr) -> bool {
+        self.maintain();
+
+        if self.is_banned(ip) {
+            debug!("Refusing connection from {}, which is banned", ip);
+            self.stats.banned += 1;
+            self.stats_changed = true;
+            return false;
+        }
+        if !self.take_token(ip) {
+            debug!("Refusing connection from {}, which exceeds the connection rate limit", ip);
+            self.stats.rate_limited += 1;
+            self.stats_changed = true;
+            return false;
+        }
+        if self.slots.len() >= self.limits.max_peers as usize && !self.evict() {
+            warn!(
+                "Refusing connection from {}: all {} inbound slots are taken by the peers which \
+                 can't be evicted",
+                ip,
+                self.slots.len()
+            );
+            self.stats.slots_exhausted += 1;
+            self.stats_changed = true;
+            return false;
+        }
+        true
+    }
+
+    /// Reserves identifier for the slot of the connection which is being admitted
+    pub fn next_slot(&mut self) -> u32 {
+        self.next_slot = self.next_slot.wrapping_add(1);
+        self.next_slot
+    }
+
+    /// Channel signal for the daemon serving the slot, if the daemon is forked
+    pub fn channel_signal(&self, slot: u32) -> Option<ChannelSignal> {
+        self.signals.map(|(_, fd)| ChannelSignal { fd, slot })
+    }
+
+    /// Registers the daemon serving the connection admitted from the given address
+    pub fn register(&mut self, slot: u32, ip: IpAddr, handler: Handler) {
+        self.slots.push(Slot { id: slot, ip, handler, channels: false });
+    }
+
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        self.banned.contains_key(&network(ip))
+            || matches!(self.ban_list, Some((ref ban_list, _)) if ban_list.is_address_banned(ip))
+    }
+
+    /// Re-reads the ban list once lnpd has updated its file
+    fn reload_ban_list(&mut self) {
+        let modified = match fs::metadata(&self.ban_list_file).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            // lnpd has not banned anybody yet
+            Err(_) => return,
+        };
+        if matches!(self.ban_list, Some((_, read)) if read == modified) {
+            return;
+        }
+        match BanList::load(self.ban_list_file.clone()) {
+            Ok(ban_list) => self.ban_list = Some((ban_list, modified)),
+            Err(err) => warn!("Unable to read ban list: {}", err),
+        }
+    }
+
+    /// Adds the penalty for the failed connection to the score of its address, banning the
+    /// address once the score crosses the threshold
+    fn penalize(&mut self, ip: IpAddr, offence: Offence) {
+        let network = network(ip);
+        if !self.scores.entry(network).or_default().penalize(offence.penalty()) {
+            return;
+        }
+        warn!(
+            "Banning {} for {:?} after repeated protocol violations, the last one being {}",
+            network, self.ban_duration, offence
+        );
+        self.scores.remove(&network);
+        self.banned.insert(network, Instant::now() + self.ban_duration);
+    }
+
+    fn take_token(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let limits = self.limits;
+        let bucket = self
+            .buckets
+            .entry(network(ip))
+            .or_insert(Bucket { tokens: limits.burst as f64, updated: now });
+        bucket.refill(now, limits);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Stops the daemon of the longest connection without channels
+    fn evict(&mut self) -> bool {
+        let index = match self
+            .slots
+            .iter()
+            .position(|slot| !slot.channels && matches!(slot.handler, Handler::Process(_)))
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        let slot = self.slots.remove(index);
+        if let Handler::Process(pid) = slot.handler {
+            info!("Evicting peer connection served by process {} to free inbound slot", pid);
+            if let Err(err) = kill(pid, Signal::SIGTERM) {
+                warn!("Unable to stop peer daemon process {}: {}", pid, err);
+            }
+        }
+        self.stats.evicted += 1;
+        self.stats_changed = true;
+        true
+    }
+
+    fn reap(&mut self) {
+        if self.signals.is_some() {
+            // Forked daemons are the only children of the listener process
+            while let Ok(status) = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                let pid = match status {
+                    WaitStatus::StillAlive => break,
+                    WaitStatus::Exited(pid, code) => {
+                        let is_child = |slot: &&Slot| {
+                            matches!(slot.handler, Handler::Process(child) if child == pid)
+                        };
+                        let ip = self.slots.iter().find(is_child).map(|slot| slot.ip);
+                        self.count(ip, Abort::from_exit_code(code));
+                        status.pid()
+                    }
+                    status => status.pid(),
+                };
+                self.slots.retain(|slot| match slot.handler {
+                    Handler::Process(child) => Some(child) != pid,
+                    Handler::Thread(..) => true,
+                });
+            }
+        }
+        let (finished, running) =
+            self.slots.drain(..).partition::<Vec<_>, _>(|slot| match slot.handler {
+                Handler::Thread(_, ref running) => Arc::strong_count(running) == 1,
+                Handler::Process(_) => false,
+            });
+        self.slots = running;
+        for Slot { ip, handler, .. } in finished {
+            if let Handler::Thread(handle, _) = handler {
+                if let Ok(Err(err)) = handle.join() {
+                    self.count(Some(ip), Abort::with(&err));
+                }
+            }
+        }
+    }
+
+    /// Counts the connection closed for the remote peer misbehaviour, penalizing its address
+    fn count(&mut self, ip: Option<IpAddr>, abort: Option<Abort>) {
+        let abort = match abort {
+            Some(abort) => abort,
+            None => return,
+        };
+        let counter = match abort {
+            Abort::HandshakeTimeout => Some(&mut self.stats.handshake_timeouts),
+            Abort::InitTimeout => Some(&mut self.stats.init_timeouts),
+            Abort::OversizedMessage => Some(&mut self.stats.oversized_messages),
+            Abort::HandshakeFailure => None,
+        };
+        if let Some(counter) = counter {
+            *counter += 1;
+            self.stats_changed = true;
+        }
+        if let Some(ip) = ip {
+            self.penalize(ip, abort.offence());
+        }
+    }
+
+    fn receive_signals(&mut self) {
+        let fd = match self.signals {
+            Some((fd, _)) => fd,
+            None => return,
+        };
+        let mut buf = [0u8; 256];
+        // Pipe writes of the signals are atomic, so the reads never split a signal
+        while let Ok(len) = unistd::read(fd, &mut buf) {
+            if len == 0 {
+                break;
+            }
+            for chunk in buf[..len].chunks_exact(4) {
+                let id = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                if let Some(slot) = self.slots.iter_mut().find(|slot| slot.id == id) {
+                    slot.channels = true;
+                }
+            }
+        }
+    }
+}
+
+/// Address rate limits apply to. A single host usually gets the whole IPv6 /64 network, so all
+/// of its addresses share the limit.
+pub(crate) fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            match ip.to_ipv4() {
+                // IPv4 connections accepted by a dual-stack listener
+                Some(ipv4) if segments[5] == 0xffff => IpAddr::V4(ipv4),
+                _ => IpAddr::V6(Ipv6Addr::new(
+                    segments[0],
+                    segments[1],
+                    segments[2],
+                    segments[3],
+                    0,
+                    0,
+                    0,
+                    0,
+                )),
+            }
+        }
+        ip => ip,
+    }
+}
@@ -0,0 +1,99 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Protocol violations of the remote peers.
+//!
+//! Peer daemons report the violations to lnpd, which adds their penalties to the misbehaviour
+//! score of the remote node and bans the node once the score crosses the threshold. Peer
+//! listeners score the incoming connections failing the handshake by their IP address, since the
+//! remote node is not known until the handshake completes.
+
+use std::time::{Duration, Instant};
+
+/// Interval within which the messages are counted by [`RateWindow`]
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Protocol violation by a remote peer
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Offence {
+    /// Message which can't be decoded
+    #[display("malformed_message")]
+    MalformedMessage,
+
+    /// Message exceeding the maximal BOLT-8 message length
+    #[display("oversized_message")]
+    OversizedMessage,
+
+    /// Too many messages which the remote peer must not send at the current connection state,
+    /// like repeated `init` messages or pongs for no pings
+    #[display("unexpected_messages")]
+    UnexpectedMessages,
+
+    /// Too many gossip queries
+    #[display("gossip_spam")]
+    GossipSpam,
+
+    /// Too many pings arriving too frequently
+    #[display("ping_flood")]
+    PingFlood,
+
+    /// Failure to complete BOLT-8 handshake or to send `init` message in time
+    #[display("handshake_failure")]
+    HandshakeFailure,
+}
+
+impl Offence {
+    /// Score added to the misbehaviour score of the remote peer for the violation
+    pub fn penalty(self) -> u32 {
+        match self {
+            Offence::MalformedMessage => 20,
+            Offence::OversizedMessage => 50,
+            Offence::UnexpectedMessages => 25,
+            Offence::GossipSpam => 25,
+            Offence::PingFlood => 25,
+            Offence::HandshakeFailure => 20,
+        }
+    }
+}
+
+/// Counter of the messages of some kind received within a minute
+#[derive(Copy, Clone, Debug)]
+pub struct RateWindow {
+    limit: u32,
+    count: u32,
+    started: Instant,
+}
+
+impl RateWindow {
+    pub fn with(limit: u32) -> RateWindow {
+        RateWindow { limit, count: 0, started: Instant::now() }
+    }
+
+    /// Counts a message; returns whether the number of messages has exceeded the limit, in which
+    /// case the counting starts over
+    pub fn exceeded(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.started) >= RATE_WINDOW {
+            self.count = 0;
+            self.started = now;
+        }
+        self.count += 1;
+        if self.count <= self.limit {
+            return false;
+        }
+        self.count = 0;
+        self.started = now;
+        true
+    }
+}
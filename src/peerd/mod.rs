@@ -12,13 +12,24 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+pub mod features;
+mod inbound;
+mod misbehaviour;
 #[cfg(feature = "server")]
 mod opts;
+mod outbound;
 mod peer_socket;
+mod renaming;
 pub(self) mod runtime;
+pub mod socks5;
+mod stats;
 pub mod supervisor;
+mod transport;
 
 #[cfg(feature = "server")]
 pub use opts::{KeyOpts, Opts};
 pub use peer_socket::PeerSocket;
+pub use features::{feature_names, local_features};
+pub use inbound::InboundStats;
+pub(crate) use inbound::network;
 pub(self) use supervisor::RuntimeParams;
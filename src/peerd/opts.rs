@@ -13,6 +13,7 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
 use clap::{ArgGroup, ValueHint};
 use internet2::addr::InetSocketAddr;
@@ -55,11 +56,19 @@ pub struct Opts {
     /// Connect to a remote peer with the provided address after start.
     ///
     /// Connects to the specified remote peer. Peer address should be given as either
-    /// IPv4, IPv6 or Onion address (v2 or v3); in the former case you will be also
-    /// required to provide `--tor` argument.
+    /// IPv4, IPv6 or Onion v3 address; in the latter case you will be also required to
+    /// provide `--tor-proxy` argument.
     #[clap(short = 'C', long, group = "action")]
     pub connect: Option<RemoteNodeAddr>,
 
+    /// Timeout for connecting the remote peer, in seconds.
+    ///
+    /// If the remote peer given to `--connect` argument is not reachable within the timeout,
+    /// the daemon exits with an error. If absent, the operating system TCP connection timeout
+    /// applies, or one minute for the connections through the Tor proxy.
+    #[clap(long, requires = "connect")]
+    pub connect_timeout: Option<u64>,
+
     /// Customize port used by lightning peer network.
     ///
     /// Optional argument specifying local or remote TCP port to use with the address
@@ -68,6 +77,9 @@ pub struct Opts {
     pub port: u16,
 
     /// Overlay peer communications through different transport protocol.
+    ///
+    /// Lightning network peers are connected either over raw TCP (`tcp`) or over WebSocket
+    /// (`websocket`), which applies both to the listened socket and to the connected peer.
     #[clap(
         short,
         long,
@@ -117,14 +129,21 @@ impl KeyOpts {
 
 impl From<Opts> for crate::peerd::PeerSocket {
     fn from(opts: Opts) -> Self {
-        if let Some(peer_addr) = opts.connect {
-            Self::Connect(peer_addr)
+        if let Some(mut peer_addr) = opts.connect {
+            if let (FramingProtocol::Websocket, RemoteSocketAddr::Ftcp(inet_addr)) =
+                (opts.overlay, peer_addr.remote_addr)
+            {
+                peer_addr.remote_addr = RemoteSocketAddr::Websocket(inet_addr);
+            }
+            Self::Connect(peer_addr, opts.connect_timeout.map(Duration::from_secs))
         } else if let Some(bind_addr) = opts.listen {
+            let inet_addr = InetSocketAddr {
+                address: bind_addr.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).into(),
+                port: opts.port,
+            };
             Self::Listen(match opts.overlay {
-                FramingProtocol::FramedRaw => RemoteSocketAddr::Ftcp(InetSocketAddr {
-                    address: bind_addr.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).into(),
-                    port: opts.port,
-                }),
+                FramingProtocol::FramedRaw => RemoteSocketAddr::Ftcp(inet_addr),
+                FramingProtocol::Websocket => RemoteSocketAddr::Websocket(inet_addr),
                 // TODO: (v2) implement overlay protocols
                 _ => unimplemented!(),
             })
@@ -0,0 +1,215 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Bounded queue of the messages sent to the remote peer.
+//!
+//! Messages are written to the connection by a dedicated writer thread, so a remote peer which
+//! does not read its socket does not block the daemon runtime. Once the queue holds
+//! [`OUTBOUND_QUEUE_LEN`] messages, the peer is considered busy: gossip is dropped, making room
+//! for the channel messages, and the daemons sending channel messages are notified to hold back
+//! new channel updates. The queue reports the peer writable again once it drains to a half of
+//! its length. A peer leaving [`OUTBOUND_QUEUE_MAX`] messages unread is considered stalled.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use internet2::TypedEnum;
+use lnp::p2p::legacy::Messages as LnMsg;
+use microservices::esb;
+use microservices::peer::{PeerSender, SendMessage};
+
+use super::stats::TrafficCounters;
+use crate::bus::{BusMsg, CtlMsg, ServiceBus};
+use crate::rpc::ServiceId;
+use crate::service::BridgeHandler;
+
+/// Number of the queued messages after which the remote peer is considered busy
+pub const OUTBOUND_QUEUE_LEN: usize = 256;
+
+/// Number of the queued messages after which the remote peer is considered stalled and the
+/// connection is dropped
+pub const OUTBOUND_QUEUE_MAX: usize = 1024;
+
+/// Controller sending messages from the daemon threads to the runtime over the bridge, shared by
+/// the threads reading from and writing to the remote peer
+pub type Bridge = Arc<Mutex<esb::Controller<ServiceBus, BusMsg, BridgeHandler>>>;
+
+/// Result of queueing a message for the remote peer
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Push {
+    /// Message is queued
+    Queued,
+
+    /// Message is queued, but the remote peer is busy
+    Busy,
+
+    /// Gossip message is dropped since the remote peer is busy, or the connection is closed
+    Dropped,
+
+    /// Message is not queued since the remote peer has stalled
+    Stalled,
+}
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<LnMsg>,
+    /// Whether the writer is sending a message taken from the queue
+    sending: bool,
+    busy: bool,
+    closed: bool,
+    dropped_gossip: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signals the writer that there are messages to send
+    ready: Condvar,
+    /// Signals that all the queued messages are sent
+    flushed: Condvar,
+    /// Messages taken from the queue by the writer, counted without locking the queue
+    sent: TrafficCounters,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<State> { self.state.lock().expect("outbound queue is poisoned") }
+}
+
+/// Queue of the messages for the remote peer, written to the connection by the writer thread
+pub struct OutboundQueue {
+    shared: Arc<Shared>,
+}
+
+impl OutboundQueue {
+    /// Starts the writer thread sending queued messages with the sender. The writer reports
+    /// draining of the queue and the connection failures to the runtime over the bridge.
+    pub fn spawn(sender: PeerSender, bridge: Bridge, identity: ServiceId) -> OutboundQueue {
+        let shared = Arc::new(Shared::default());
+        let writer = shared.clone();
+        thread::spawn(move || run_writer(writer, sender, bridge, identity));
+        OutboundQueue { shared }
+    }
+
+    /// Queues message for the remote peer
+    pub fn push(&self, message: LnMsg) -> Push {
+        let mut state = self.shared.lock();
+        if state.closed {
+            return Push::Dropped;
+        }
+        if state.messages.len() >= OUTBOUND_QUEUE_LEN {
+            state.busy = true;
+            if is_gossip(&message) {
+                state.dropped_gossip += 1;
+                return Push::Dropped;
+            }
+            if let Some(pos) = state.messages.iter().position(is_gossip) {
+                state.messages.remove(pos);
+                state.dropped_gossip += 1;
+            } else if state.messages.len() >= OUTBOUND_QUEUE_MAX {
+                return Push::Stalled;
+            }
+        }
+        state.messages.push_back(message);
+        self.shared.ready.notify_one();
+        if state.busy {
+            Push::Busy
+        } else {
+            Push::Queued
+        }
+    }
+
+    /// Waits until all the queued messages are sent, for at most the timeout
+    pub fn flush(&self, timeout: Duration) {
+        let state = self.shared.lock();
+        let _ = self.shared.flushed.wait_timeout_while(state, timeout, |state| {
+            !state.closed && (state.sending || !state.messages.is_empty())
+        });
+    }
+
+    /// Number of the messages awaiting to be sent
+    pub fn pending(&self) -> usize { self.shared.lock().messages.len() }
+
+    /// Number of the gossip messages dropped since the remote peer was busy
+    pub fn dropped_gossip(&self) -> u64 { self.shared.lock().dropped_gossip }
+
+    /// Counters of the messages written to the remote peer
+    pub fn sent(&self) -> &TrafficCounters { &self.shared.sent }
+}
+
+/// Gossip is relayed on a best-effort basis, so it can be dropped without breaking the protocol
+fn is_gossip(message: &LnMsg) -> bool {
+    matches!(
+        message,
+        LnMsg::ChannelAnnouncement(_)
+            | LnMsg::ChannelUpdate(_)
+            | LnMsg::NodeAnnouncement(_)
+            | LnMsg::QueryShortChannelIds(_)
+            | LnMsg::ReplyShortChannelIdsEnd(_)
+            | LnMsg::QueryChannelRange(_)
+            | LnMsg::ReplyChannelRange(_)
+            | LnMsg::GossipTimestampFilter(_)
+    )
+}
+
+fn run_writer(shared: Arc<Shared>, mut sender: PeerSender, bridge: Bridge, identity: ServiceId) {
+    let remote_peer = match identity {
+        ServiceId::Peer(ref remote_peer) => remote_peer.clone(),
+        _ => unreachable!("peer daemon identity is always a peer"),
+    };
+    let notify = |message: CtlMsg| {
+        let mut bridge = bridge.lock().expect("bridge is poisoned");
+        if let Err(err) = bridge.send_to(ServiceBus::Bridge, identity.clone(), BusMsg::Ctl(message))
+        {
+            error!("Writer thread is unable to reach the runtime: {}", err);
+        }
+    };
+    loop {
+        let (message, writable) = {
+            let mut state = shared.lock();
+            while state.messages.is_empty() {
+                state = shared.ready.wait(state).expect("outbound queue is poisoned");
+            }
+            let message = state.messages.pop_front().expect("outbound queue is not empty");
+            state.sending = true;
+            let writable = state.busy && state.messages.len() <= OUTBOUND_QUEUE_LEN / 2;
+            if writable {
+                state.busy = false;
+            }
+            (message, writable)
+        };
+        if writable {
+            debug!("Outbound queue has drained, remote peer is writable");
+            notify(CtlMsg::PeerWritable(remote_peer.clone()));
+        }
+        shared.sent.record(&message, message.serialize().len());
+        let res = sender.send_message(message);
+        let mut state = shared.lock();
+        state.sending = false;
+        if let Err(ref err) = res {
+            error!("Unable to send message to the remote peer: {}", err);
+            state.closed = true;
+            state.messages.clear();
+        }
+        if state.messages.is_empty() {
+            shared.flushed.notify_all();
+        }
+        drop(state);
+        if res.is_err() {
+            notify(CtlMsg::PeerDisconnected(remote_peer, s!("write_failed")));
+            return;
+        }
+    }
+}
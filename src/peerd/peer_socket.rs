@@ -18,16 +18,19 @@
 pub enum PeerSocket {
     /// The service should listen for incoming connections on a certain
     /// TCP socket, which may be IPv4- or IPv6-based. For Tor hidden services
-    /// use IPv4 TCP port proxied as a Tor hidden service in `torrc`.
+    /// use IPv4 TCP port proxied as a Tor hidden service in `torrc`. Sockets
+    /// given as WebSocket addresses accept WebSocket connections.
     #[display("--listen={0}")]
     Listen(internet2::RemoteSocketAddr),
 
     /// The service should connect to the remote peer residing on the provided
-    /// address, which may be either IPv4/v6 or Onion V2/v3 address (using
-    /// onion hidden services will require
+    /// address, which may be either IPv4/v6 or Onion v3 address (using
+    /// onion hidden services requires Tor proxy, see `--tor-proxy`).
     /// DNS names, due to a censorship vulnerability issues and for avoiding
     /// leaking any information about th elocal node to DNS resolvers, are not
     /// supported.
+    ///
+    /// The connection is abandoned if it is not established within the optional timeout.
     #[display("--connect={0}")]
-    Connect(internet2::RemoteNodeAddr),
+    Connect(internet2::RemoteNodeAddr, Option<std::time::Duration>),
 }
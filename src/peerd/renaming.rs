@@ -0,0 +1,100 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Channel daemons switching from the temporary to the permanent channel id.
+//!
+//! Messages sent to a channel daemon in the middle of its identity switch are lost, so the remote
+//! peer messages addressed to the permanent id are held until the channel daemon sends its first
+//! message under the permanent id, and then delivered in the order of their arrival.
+
+use std::collections::HashMap;
+
+use lnp::p2p::legacy::ChannelId;
+
+/// Messages held for the channels switching their identity
+#[derive(Clone, Debug)]
+pub struct RenameQueue<M> {
+    queues: HashMap<ChannelId, Vec<M>>,
+}
+
+impl<M> Default for RenameQueue<M> {
+    fn default() -> Self { RenameQueue { queues: empty!() } }
+}
+
+impl<M> RenameQueue<M> {
+    /// Starts holding messages for the channel which daemon is switching to the permanent id
+    pub fn start(&mut self, channel_id: ChannelId) { self.queues.entry(channel_id).or_default(); }
+
+    /// Holds the message if the channel daemon is switching its identity; otherwise returns the
+    /// message back for the immediate delivery
+    pub fn hold(&mut self, channel_id: ChannelId, message: M) -> Option<M> {
+        match self.queues.get_mut(&channel_id) {
+            Some(queue) => {
+                queue.push(message);
+                None
+            }
+            None => Some(message),
+        }
+    }
+
+    /// Completes identity switch of the channel daemon, returning the held messages in the order
+    /// of their arrival, or `None` if the channel daemon was not switching its identity
+    pub fn complete(&mut self, channel_id: ChannelId) -> Option<Vec<M>> {
+        self.queues.remove(&channel_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amplify::{Slice32, Wrapper};
+
+    use super::*;
+
+    fn channel_id(no: u8) -> ChannelId { ChannelId::from_inner(Slice32::from_inner([no; 32])) }
+
+    #[test]
+    fn not_renaming() {
+        let mut queue = RenameQueue::default();
+        assert_eq!(queue.hold(channel_id(1), 1u32), Some(1));
+        assert_eq!(queue.complete(channel_id(1)), None);
+    }
+
+    #[test]
+    fn flood_during_rename() {
+        const MESSAGES: u32 = 10_000;
+
+        let mut queue = RenameQueue::default();
+        let mut delivered = vec![];
+
+        queue.start(channel_id(1));
+        for no in 0..MESSAGES {
+            // Messages for the other channels are not delayed
+            assert_eq!(queue.hold(channel_id(2), no), Some(no));
+            if let Some(message) = queue.hold(channel_id(1), no) {
+                delivered.push(message);
+            }
+        }
+        assert!(delivered.is_empty());
+
+        // Repeated start must not drop the messages held so far
+        queue.start(channel_id(1));
+        delivered.extend(queue.complete(channel_id(1)).unwrap());
+        for no in MESSAGES..MESSAGES * 2 {
+            delivered.extend(queue.hold(channel_id(1), no));
+        }
+
+        assert_eq!(delivered, (0..MESSAGES * 2).collect::<Vec<_>>());
+        assert_eq!(queue.complete(channel_id(1)), None);
+    }
+}
@@ -12,63 +12,121 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::spawn;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-use amplify::Bipolar;
-use bitcoin::secp256k1::rand::{self, Rng, RngCore};
+use amplify::{Bipolar, Slice32, Wrapper};
+use bitcoin::secp256k1::rand::{self, Rng};
 use bitcoin::secp256k1::PublicKey;
 use internet2::addr::InetSocketAddr;
-use internet2::{presentation, transport, zmqsocket, CreateUnmarshaller, ZmqType, ZMQ_CONTEXT};
+use internet2::{
+    presentation, transport, zmqsocket, CreateUnmarshaller, TypedEnum, ZmqType, ZMQ_CONTEXT,
+};
+use lnp::features::InitFeatures;
 use lnp::p2p::legacy::{
-    ActiveChannelId, ChannelId, FundingCreated, FundingLocked, FundingSigned, Init,
-    Messages as LnMsg, Ping, UpdateAddHtlc, UpdateFailHtlc, UpdateFailMalformedHtlc,
-    UpdateFulfillHtlc,
+    ActiveChannelId, AnnouncementSignatures, ChannelId, CommitmentSigned, Error as PeerError,
+    FundingCreated, FundingLocked, FundingSigned, Init, Messages as LnMsg, Ping, RevokeAndAck,
+    UpdateAddHtlc, UpdateFailHtlc, UpdateFailMalformedHtlc, UpdateFee, UpdateFulfillHtlc,
 };
-use lnp_rpc::{ClientId, RpcMsg};
+use lnp_rpc::{AuthError, ClientId, NodeEvent, RpcMsg};
 use microservices::esb::{self, Handler};
 use microservices::node::TryService;
-use microservices::peer::{self, PeerConnection, PeerSender, SendMessage};
-
-use super::RuntimeParams;
-use crate::bus::{BusMsg, CtlMsg, ServiceBus};
-use crate::rpc::{PeerInfo, ServiceId};
-use crate::{Endpoints, Error, LogStyle, Responder, Service};
-
-pub(super) fn run(connection: PeerConnection, params: RuntimeParams) -> Result<(), Error> {
+use microservices::peer::{self, PeerConnection};
+
+use super::inbound::{Abort, ChannelSignal};
+use super::misbehaviour::{Offence, RateWindow};
+use super::outbound::{Bridge, OutboundQueue, Push};
+use super::renaming::RenameQueue;
+use super::stats::{self, TrafficCounters};
+use super::supervisor::MAX_MESSAGE_LEN;
+use super::{features, RuntimeParams};
+use crate::bus::{BusMsg, CtlMsg, Misbehaviour, ServiceBus};
+use crate::rpc::{ConnectionDirection, PeerInfo, ServiceId};
+use crate::service::BridgeHandler;
+use crate::{Endpoints, Error, Keepalive, LogStyle, Responder, RpcAuth, Service};
+
+/// Pings from the remote peer arriving within this interval after the last answered one are
+/// not answered
+const PING_MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of consecutive pings arriving too frequently, after which the remote peer is
+/// disconnected for flooding us with pings
+const PING_FLOOD_LIMIT: u8 = 10;
+
+/// Number of unexpected messages per minute after which the remote peer is reported for sending
+/// them
+const UNEXPECTED_MESSAGE_LIMIT: u32 = 10;
+
+/// Number of gossip queries per minute after which the remote peer is reported for gossip spam
+const GOSSIP_QUERY_LIMIT: u32 = 60;
+
+/// Pings requesting this or larger number of bytes in reply must be ignored according to BOLT-1
+const PONG_SIZE_IGNORED: u16 = 65532;
+
+/// Maximal time the stopping daemon waits for the queued messages to be sent to the remote peer
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of the bridges opened by the process, which makes bridge endpoints of the daemons
+/// running as threads of the same process unique
+static BRIDGE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs the daemon for the connection, once `init` messages are exchanged with the remote peer
+pub(super) fn run(
+    connection: PeerConnection,
+    remote_init: Init,
+    params: RuntimeParams,
+) -> Result<(), Error> {
     debug!("Splitting connection into receiver and sender parts");
     let (receiver, sender) = connection.split();
 
     debug!("Opening bridge between runtime and peer listener threads");
+    let endpoint = format!("inproc://bridge-{}", BRIDGE_COUNT.fetch_add(1, Ordering::SeqCst));
     let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
     let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
-    tx.connect("inproc://bridge")?;
-    rx.bind("inproc://bridge")?;
+    rx.bind(&endpoint)?;
+    tx.connect(&endpoint)?;
 
     let identity = ServiceId::Peer(params.id);
+    // Bridge is shared by the threads reading from and writing to the remote peer
+    let bridge = Arc::new(Mutex::new(esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?));
 
     debug!("Starting thread listening for messages from the remote peer");
+    let received = Arc::new(TrafficCounters::default());
     let bridge_handler = ListenerRuntime {
         identity: identity.clone(),
-        bridge: esb::Controller::with(
-            map! {
-                ServiceBus::Bridge => esb::BusConfig {
-                    carrier: zmqsocket::Carrier::Socket(tx),
-                    router: None,
-                    queued: true,
-                }
-            },
-            BridgeHandler,
-            ZmqType::Rep,
-        )?,
+        bridge: bridge.clone(),
+        received: received.clone(),
+        remote_id: params.remote_id,
+        remote_socket: params.remote_socket,
     };
     let listener = peer::Listener::with(receiver, bridge_handler, LnMsg::create_unmarshaller());
     spawn(move || listener.run_or_panic("peerd-listener"));
     // TODO: Use the handle returned by spawn to track the child process
 
+    debug!("Starting thread writing messages to the remote peer");
+    let outbound = OutboundQueue::spawn(sender, bridge, identity.clone());
+
     debug!("Staring main service runtime");
+    let local_features = features::local_features(&params.config);
+    // `init` messages are exchanged before the runtime is started
+    let local_init = LnMsg::Init(features::init_message(local_features.clone()));
+    outbound.sent().record(&local_init, local_init.serialize().len());
+    let remote_init_msg = LnMsg::Init(remote_init.clone());
+    received.record(&remote_init_msg, remote_init_msg.serialize().len());
     let runtime = Runtime {
         identity,
         local_id: params.local_id,
@@ -76,12 +134,33 @@ pub(super) fn run(connection: PeerConnection, params: RuntimeParams) -> Result<(
         local_socket: params.local_socket,
         remote_socket: params.remote_socket,
         channels: empty!(),
-        sender,
+        renamed_channels: empty!(),
+        renaming_channels: empty!(),
+        outbound,
+        busy_services: empty!(),
         connect: params.connect,
+        direction: if params.connect {
+            ConnectionDirection::Outbound
+        } else {
+            ConnectionDirection::Inbound
+        },
+        local_features,
+        threaded: params.config.threaded,
+        remote_init: Some(remote_init),
+        features: None,
         started: SystemTime::now(),
-        messages_sent: 0,
-        messages_received: 0,
+        received,
         awaited_pong: None,
+        last_ping_rtt: None,
+        keepalive: params.config.keepalive,
+        last_received: Instant::now(),
+        missed_pongs: 0,
+        last_remote_ping: None,
+        flooded_pings: 0,
+        unexpected_messages: RateWindow::with(UNEXPECTED_MESSAGE_LIMIT),
+        gossip_queries: RateWindow::with(GOSSIP_QUERY_LIMIT),
+        rpc_auth: RpcAuth::load(&params.config.data_dir)?,
+        channel_signal: params.channel_signal,
     };
     let mut service = Service::service(params.config, runtime)?;
     service.add_loopback(rx)?;
@@ -89,46 +168,42 @@ pub(super) fn run(connection: PeerConnection, params: RuntimeParams) -> Result<(
     unreachable!()
 }
 
-pub struct BridgeHandler;
-
-impl esb::Handler<ServiceBus> for BridgeHandler {
-    type Request = BusMsg;
-    type Error = Error;
-
-    fn identity(&self) -> ServiceId { ServiceId::Loopback }
-
-    fn handle(
-        &mut self,
-        _: &mut Endpoints,
-        _: ServiceBus,
-        _: ServiceId,
-        _: BusMsg,
-    ) -> Result<(), Error> {
-        // Bridge does not receive replies for now
-        Ok(())
-    }
-
-    fn handle_err(
-        &mut self,
-        _: &mut Endpoints,
-        err: esb::Error<ServiceId>,
-    ) -> Result<(), Self::Error> {
-        // We simply propagate the error since it's already being reported
-        Err(err.into())
-    }
-}
-
 pub struct ListenerRuntime {
     identity: ServiceId,
-    bridge: esb::Controller<ServiceBus, BusMsg, BridgeHandler>,
+    bridge: Bridge,
+    /// Messages received from the remote peer, counted by the listener thread without locking
+    received: Arc<TrafficCounters>,
+    remote_id: Option<PublicKey>,
+    remote_socket: InetSocketAddr,
 }
 
 impl ListenerRuntime {
     fn send_over_bridge(&mut self, req: BusMsg) -> Result<(), Error> {
         debug!("Forwarding LN P2P message over BRIDGE interface to the runtime");
-        self.bridge.send_to(ServiceBus::Bridge, self.identity.clone(), req)?;
+        let mut bridge = self.bridge.lock().expect("bridge is poisoned");
+        bridge.send_to(ServiceBus::Bridge, self.identity.clone(), req)?;
         Ok(())
     }
+
+    /// Reports protocol violation by the remote peer to the runtime, which forwards the report
+    /// to lnpd
+    fn report_misbehaviour(&mut self, offence: Offence) {
+        let remote_id = match self.remote_id {
+            Some(remote_id) => remote_id,
+            None => return,
+        };
+        let misbehaviour = Misbehaviour {
+            remote_id,
+            remote_socket: self.remote_socket,
+            offence: offence.to_string(),
+            penalty: offence.penalty(),
+            has_channels: false,
+        };
+        let message = BusMsg::Ctl(CtlMsg::PeerMisbehaved(misbehaviour));
+        if let Err(err) = self.send_over_bridge(message) {
+            error!("Unable to report misbehaviour of the remote peer to the runtime: {}", err);
+        }
+    }
 }
 
 impl peer::Handler<LnMsg> for ListenerRuntime {
@@ -138,6 +213,7 @@ impl peer::Handler<LnMsg> for ListenerRuntime {
         // Forwarding all received messages to the runtime
         debug!("New message from remote peer: {}", message);
         trace!("{:#?}", message);
+        self.received.record(&message, message.serialize().len());
         self.send_over_bridge(BusMsg::Ln((*message).clone()))
     }
 
@@ -153,6 +229,25 @@ impl peer::Handler<LnMsg> for ListenerRuntime {
             // propagate error to the upper level
             _ => {
                 error!("Unrecoverable {}, halting", err);
+                // Messages which can't be decoded violate the protocol, unlike the failures of
+                // the connection itself
+                let malformed = matches!(
+                    err,
+                    Error::Peer(ref err) if !matches!(err, presentation::Error::Transport(_))
+                );
+                let cause = if malformed {
+                    self.report_misbehaviour(Offence::MalformedMessage);
+                    Offence::MalformedMessage.to_string()
+                } else {
+                    s!("connection_lost")
+                };
+                // Runtime has to notify lnpd that the connection is lost
+                if let ServiceId::Peer(remote_peer) = self.identity.clone() {
+                    let message = BusMsg::Ctl(CtlMsg::PeerDisconnected(remote_peer, cause));
+                    if let Err(err) = self.send_over_bridge(message) {
+                        error!("Unable to report lost connection to the runtime: {}", err);
+                    }
+                }
                 Err(err)
             }
         }
@@ -166,14 +261,59 @@ pub struct Runtime {
     local_socket: Option<InetSocketAddr>,
     remote_socket: InetSocketAddr,
 
-    sender: PeerSender,
+    /// Messages awaiting to be written to the remote peer
+    outbound: OutboundQueue,
+    /// Daemons notified that the remote peer is busy, which have to be notified once the peer
+    /// gets writable
+    busy_services: HashSet<ServiceId>,
     connect: bool,
+    direction: ConnectionDirection,
+    /// Features advertised to the remote peer in our `init` message
+    local_features: InitFeatures,
+    /// Whether the daemon runs in a thread of lnpd process, which can't exit without stopping
+    /// the whole node
+    threaded: bool,
+    /// `init` message received from the remote peer before the runtime was started, which is
+    /// processed once the runtime is ready
+    remote_init: Option<Init>,
+    /// Features negotiated with the remote peer, known once its `init` message is received
+    features: Option<InitFeatures>,
 
     channels: HashSet<ActiveChannelId>,
+    /// Permanent ids of the channels which have changed their temporary ids, indexed by the
+    /// temporary id. The remote peer may still refer to the channel by its temporary id (for
+    /// instance when failing `funding_created`), while the channel daemon is already renamed.
+    renamed_channels: HashMap<ChannelId, ChannelId>,
+    /// Remote peer messages for the channels which daemons are switching from the temporary to
+    /// the permanent id, indexed by the permanent id. Messages sent to a daemon in the middle of
+    /// its identity switch are lost, so they are queued until the channel daemon sends its first
+    /// message under the permanent id.
+    renaming_channels: RenameQueue<BusMsg>,
     started: SystemTime,
-    messages_sent: usize,
-    messages_received: usize,
-    awaited_pong: Option<u16>,
+    /// Messages received from the remote peer, counted by the listener thread; messages sent
+    /// are counted by the writer thread of the outbound queue
+    received: Arc<TrafficCounters>,
+    /// Pong size and the time of sending the ping which is not yet answered
+    awaited_pong: Option<(u16, Instant)>,
+    last_ping_rtt: Option<Duration>,
+    keepalive: Keepalive,
+    /// Time of receiving the last message from the remote peer
+    last_received: Instant,
+    /// Number of our consecutive pings the remote peer has not replied to
+    missed_pongs: u8,
+    /// Time of receiving the last answered ping from the remote peer
+    last_remote_ping: Option<Instant>,
+    /// Number of consecutive pings from the remote peer which arrived too frequently
+    flooded_pings: u8,
+    /// Messages the remote peer must not send at the current connection state
+    unexpected_messages: RateWindow,
+    /// Gossip queries of the remote peer, answered by the router
+    gossip_queries: RateWindow,
+    /// Root key authenticating client requests
+    rpc_auth: RpcAuth,
+    /// Notification for the listener which has forked the daemon, sent once the daemon gets a
+    /// channel
+    channel_signal: Option<ChannelSignal>,
 }
 
 impl Responder for Runtime {}
@@ -185,20 +325,13 @@ impl esb::Handler<ServiceBus> for Runtime {
 
     fn identity(&self) -> ServiceId { self.identity.clone() }
 
-    fn on_ready(&mut self, _: &mut Endpoints) -> Result<(), Error> {
-        if self.connect {
-            info!("{} with the remote peer", "Initializing connection".promo());
-
-            self.sender.send_message(LnMsg::Init(Init {
-                global_features: none!(),
-                local_features: none!(),
-                assets: none!(),
-                unknown_tlvs: none!(),
-            }))?;
-
-            self.connect = false;
+    fn on_ready(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        info!("{} with the remote peer", "Connection is initialized".promo());
+        self.connect = false;
+        match self.remote_init.take() {
+            Some(init) => self.receive_init(endpoints, init),
+            None => Ok(()),
         }
-        Ok(())
     }
 
     fn handle(
@@ -208,18 +341,35 @@ impl esb::Handler<ServiceBus> for Runtime {
         source: ServiceId,
         message: BusMsg,
     ) -> Result<(), Self::Error> {
-        match (bus, message, source) {
+        let res = match (bus, message, source) {
             (ServiceBus::Msg, BusMsg::Ln(msg), source) => self.handle_p2p(endpoints, source, msg),
             (ServiceBus::Ctl, BusMsg::Ctl(msg), source) => self.handle_ctl(endpoints, source, msg),
             (ServiceBus::Bridge, msg, _) => self.handle_bridge(endpoints, msg),
-            (ServiceBus::Rpc, BusMsg::Rpc(msg), ServiceId::Client(client_id)) => {
-                self.handle_rpc(endpoints, client_id, msg)
+            (ServiceBus::Rpc, BusMsg::Request(request), ServiceId::Client(client_id)) => {
+                match self.rpc_auth.authorize(request) {
+                    Ok(msg) => self.handle_rpc(endpoints, client_id, msg),
+                    Err(err) => Ok(self.reject_rpc(endpoints, client_id, err)?),
+                }
+            }
+            (ServiceBus::Rpc, BusMsg::Rpc(_), ServiceId::Client(client_id)) => {
+                Ok(self.reject_rpc(endpoints, client_id, AuthError::Unauthenticated)?)
             }
             (ServiceBus::Rpc, BusMsg::Rpc(_), service) => {
                 unreachable!("lnpd received RPC message not from a client but from {}", service)
             }
             (bus, msg, _) => Err(Error::wrong_esb_msg(bus, &msg)),
+        };
+        if let Err(Error::PeerStalled(pending)) = res {
+            let reason = format!("remote peer has left {} messages unread", pending);
+            return self.drop_connection(endpoints, "stalled", &reason);
         }
+        // Listener must not evict the connection once we have a channel with the peer
+        if !self.channels.is_empty() {
+            if let Some(signal) = self.channel_signal.take() {
+                signal.send();
+            }
+        }
+        res
     }
 
     fn handle_err(
@@ -237,14 +387,31 @@ impl esb::Handler<ServiceBus> for Runtime {
 impl Runtime {
     fn handle_p2p(
         &mut self,
-        _: &mut Endpoints,
-        _source: ServiceId,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
         message: LnMsg,
     ) -> Result<(), Error> {
         debug!("Sending remote peer {}", message);
         trace!("{:#?}", message);
-        self.messages_sent += 1;
-        self.sender.send_message(message.clone())?;
+        let push = self.send_to_peer(message.clone())?;
+        if push == Push::Busy && matches!(source, ServiceId::Channel(_)) {
+            self.notify_busy(endpoints, source.clone())?;
+        }
+
+        // A message from the channel daemon under its permanent id acknowledges that the daemon
+        // has completed its identity switch
+        if let ServiceId::Channel(channel_id) = source {
+            if let Some(queue) = self.renaming_channels.complete(channel_id) {
+                debug!(
+                    "Channel daemon {} has switched its identity; delivering {} queued messages",
+                    channel_id,
+                    queue.len()
+                );
+                for request in queue {
+                    endpoints.send_to(ServiceBus::Msg, self.identity(), source.clone(), request)?;
+                }
+            }
+        }
 
         match message {
             LnMsg::OpenChannel(open_channel) => {
@@ -255,12 +422,14 @@ impl Runtime {
                     .insert(ActiveChannelId::Temporary(accept_channel.temporary_channel_id));
             }
             LnMsg::FundingCreated(funding_created) => {
-                self.channels
-                    .remove(&ActiveChannelId::Temporary(funding_created.temporary_channel_id));
-                self.channels.insert(ActiveChannelId::Static(ChannelId::with(
+                let temp_channel_id = funding_created.temporary_channel_id;
+                let channel_id = ChannelId::with(
                     funding_created.funding_txid,
                     funding_created.funding_output_index,
-                )));
+                );
+                self.channels.remove(&ActiveChannelId::Temporary(temp_channel_id));
+                self.channels.insert(ActiveChannelId::Static(channel_id));
+                self.renamed_channels.insert(temp_channel_id.into(), channel_id);
             }
             LnMsg::FundingSigned(_) => {
                 // We ingore this message since we rename the channel upon receiving of
@@ -277,12 +446,43 @@ impl Runtime {
 
     fn handle_ctl(
         &mut self,
-        _endpoints: &mut Endpoints,
-        _source: ServiceId,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
         request: CtlMsg,
     ) -> Result<(), Error> {
-        #[allow(clippy::match_single_binding)]
         match request {
+            CtlMsg::GetInfo => {
+                let peer_info = self.peer_info(false);
+                self.send_ctl(endpoints, source, CtlMsg::PeerInfo(peer_info))?;
+                Ok(())
+            }
+
+            CtlMsg::GetInfoAndReset => {
+                let peer_info = self.peer_info(true);
+                self.send_ctl(endpoints, source, CtlMsg::PeerInfo(peer_info))?;
+                Ok(())
+            }
+
+            CtlMsg::PingPeer => self.keepalive(endpoints),
+
+            CtlMsg::Disconnect(reason) if source == ServiceId::LnpBroker => {
+                info!("{} the remote peer: {}", "Disconnecting".promo(), reason);
+                self.send_warning(&reason)?;
+                self.stop(0);
+                Ok(())
+            }
+
+            CtlMsg::Banned(reason) if source == ServiceId::LnpBroker => {
+                self.send_warning(&reason)?;
+                self.drop_connection(endpoints, "banned", &reason)
+            }
+
+            // Channel daemon has already warned the remote peer about the channel; the peer is
+            // reconnected by lnpd
+            CtlMsg::Disconnect(reason) => {
+                self.drop_connection(endpoints, "channel_warning", &reason)
+            }
+
             _ => {
                 error!("Request is not supported by the CTL interface");
                 Err(Error::wrong_esb_msg(ServiceBus::Ctl, &request))
@@ -293,30 +493,71 @@ impl Runtime {
     fn handle_bridge(&mut self, endpoints: &mut Endpoints, request: BusMsg) -> Result<(), Error> {
         debug!("BRIDGE RPC request: {}", request);
 
-        if let BusMsg::Ln(_) = request {
-            self.messages_received += 1;
+        if let BusMsg::Ln(ref message) = request {
+            let len = message.serialize().len();
+            if len > MAX_MESSAGE_LEN {
+                warn!("Remote peer has sent {}-byte message", len);
+                self.report_misbehaviour(endpoints, Offence::OversizedMessage)?;
+                self.abort(endpoints, "oversized_message", Error::OversizedMessage(len))?;
+                return Ok(());
+            }
+            self.last_received = Instant::now();
         }
 
         match &request {
             BusMsg::Ctl(CtlMsg::PingPeer) => {
-                self.ping()?;
+                self.keepalive(endpoints)?;
+            }
+
+            BusMsg::Ctl(CtlMsg::PeerDisconnected(..)) => {
+                // Listener thread has already stopped, so the daemon has nothing to serve
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), ServiceId::LnpBroker, request)?;
+                self.stop(0);
+            }
+
+            BusMsg::Ctl(CtlMsg::PeerMisbehaved(misbehaviour)) => {
+                // Listener thread does not know whether the connection has channels
+                let has_channels = !self.channels.is_empty();
+                let misbehaviour = Misbehaviour { has_channels, ..misbehaviour.clone() };
+                let message = CtlMsg::PeerMisbehaved(misbehaviour);
+                self.send_ctl(endpoints, ServiceId::LnpBroker, message)?;
+            }
+
+            BusMsg::Ctl(CtlMsg::PeerWritable(remote_peer)) => {
+                for service in self.busy_services.drain().collect::<Vec<_>>() {
+                    debug!("Notifying {} that the remote peer is writable", service);
+                    let message = CtlMsg::PeerWritable(remote_peer.clone());
+                    self.send_ctl(endpoints, service, message)?;
+                }
             }
 
             BusMsg::Ln(LnMsg::Ping(Ping { pong_size, .. })) => {
-                self.pong(*pong_size)?;
+                self.answer_ping(endpoints, *pong_size)?;
             }
 
             BusMsg::Ln(LnMsg::Pong(noise)) => {
                 match self.awaited_pong {
-                    None => warn!("Unexpected pong from the remote peer"),
-                    Some(len) if len as usize != noise.len() => {
+                    None => {
+                        warn!("Unexpected pong from the remote peer");
+                        self.count_unexpected(endpoints)?;
+                    }
+                    Some((len, _)) if len as usize != noise.len() => {
                         warn!("Pong data size does not match requested with ping")
                     }
-                    _ => trace!("Got pong reply, exiting pong await mode"),
+                    Some((_, sent)) => {
+                        trace!("Got pong reply, exiting pong await mode");
+                        self.last_ping_rtt = Some(sent.elapsed());
+                        self.missed_pongs = 0;
+                    }
                 }
                 self.awaited_pong = None;
             }
 
+            BusMsg::Ln(LnMsg::Init(_)) => {
+                warn!("Ignoring repeated init message from the remote peer");
+                self.count_unexpected(endpoints)?;
+            }
+
             BusMsg::Ln(LnMsg::ChannelReestablish(_)) | BusMsg::Ln(LnMsg::OpenChannel(_)) => {
                 endpoints.send_to(
                     ServiceBus::Msg,
@@ -326,6 +567,28 @@ impl Runtime {
                 )?;
             }
 
+            // Gossip is validated, stored and relayed to other peers by the router, which also
+            // answers gossip queries
+            BusMsg::Ln(LnMsg::ChannelAnnouncement(_))
+            | BusMsg::Ln(LnMsg::ChannelUpdate(_))
+            | BusMsg::Ln(LnMsg::NodeAnnouncement(_))
+            | BusMsg::Ln(LnMsg::QueryShortChannelIds(_))
+            | BusMsg::Ln(LnMsg::ReplyShortChannelIdsEnd(_))
+            | BusMsg::Ln(LnMsg::QueryChannelRange(_))
+            | BusMsg::Ln(LnMsg::ReplyChannelRange(_))
+            | BusMsg::Ln(LnMsg::GossipTimestampFilter(_)) => {
+                let query = matches!(
+                    request,
+                    BusMsg::Ln(LnMsg::QueryShortChannelIds(_))
+                        | BusMsg::Ln(LnMsg::QueryChannelRange(_))
+                        | BusMsg::Ln(LnMsg::GossipTimestampFilter(_))
+                );
+                if query && self.gossip_queries.exceeded() {
+                    self.report_misbehaviour(endpoints, Offence::GossipSpam)?;
+                }
+                endpoints.send_to(ServiceBus::Msg, self.identity(), ServiceId::Router, request)?;
+            }
+
             BusMsg::Ln(LnMsg::AcceptChannel(accept_channel)) => {
                 let channeld: ServiceId = accept_channel.temporary_channel_id.into();
                 endpoints.send_to(ServiceBus::Msg, self.identity(), channeld, request)?;
@@ -337,30 +600,78 @@ impl Runtime {
                 funding_output_index,
                 ..
             })) => {
-                let temp_channel_id = ActiveChannelId::Temporary(*temporary_channel_id);
-                let channel_id =
-                    ActiveChannelId::Static(ChannelId::with(*funding_txid, *funding_output_index));
+                let channel_id = ChannelId::with(*funding_txid, *funding_output_index);
                 endpoints.send_to(
                     ServiceBus::Msg,
                     self.identity(),
                     (*temporary_channel_id).into(),
-                    request,
+                    request.clone(),
                 )?;
-                self.channels.remove(&temp_channel_id);
-                self.channels.insert(channel_id);
+                self.channels.remove(&ActiveChannelId::Temporary(*temporary_channel_id));
+                self.channels.insert(ActiveChannelId::Static(channel_id));
+                self.renamed_channels.insert((*temporary_channel_id).into(), channel_id);
+                // The channel daemon switches to the permanent id only after signing the
+                // commitment, while the remote peer may start using the permanent id right away
+                self.renaming_channels.start(channel_id);
             }
 
             BusMsg::Ln(LnMsg::FundingSigned(FundingSigned { channel_id, .. }))
             | BusMsg::Ln(LnMsg::FundingLocked(FundingLocked { channel_id, .. }))
+            | BusMsg::Ln(LnMsg::UpdateFee(UpdateFee { channel_id, .. }))
+            | BusMsg::Ln(LnMsg::CommitmentSigned(CommitmentSigned { channel_id, .. }))
+            | BusMsg::Ln(LnMsg::RevokeAndAck(RevokeAndAck { channel_id, .. }))
             | BusMsg::Ln(LnMsg::UpdateAddHtlc(UpdateAddHtlc { channel_id, .. }))
             | BusMsg::Ln(LnMsg::UpdateFulfillHtlc(UpdateFulfillHtlc { channel_id, .. }))
             | BusMsg::Ln(LnMsg::UpdateFailHtlc(UpdateFailHtlc { channel_id, .. }))
             | BusMsg::Ln(LnMsg::UpdateFailMalformedHtlc(UpdateFailMalformedHtlc {
                 channel_id,
                 ..
+            }))
+            | BusMsg::Ln(LnMsg::AnnouncementSignatures(AnnouncementSignatures {
+                channel_id,
+                ..
             })) => {
-                let channeld: ServiceId = (*channel_id).into();
-                endpoints.send_to(ServiceBus::Msg, self.identity(), channeld, request)?;
+                self.forward_to_channel(endpoints, *channel_id, request.clone())?;
+            }
+
+            BusMsg::Ln(LnMsg::Error(PeerError { channel_id, .. })) => {
+                // All-zero channel id means that the error refers to all channels with the peer
+                let channels = if channel_id.as_inner() == &Slice32::default() {
+                    self.channels
+                        .iter()
+                        .map(|channel_id| ChannelId::from_inner(channel_id.as_slice32()))
+                        .collect()
+                } else {
+                    // Route errors referring to a temporary id to the renamed channel daemon
+                    vec![self.renamed_channels.get(channel_id).copied().unwrap_or(*channel_id)]
+                };
+                for channel_id in channels {
+                    self.forward_to_channel(endpoints, channel_id, request.clone())?;
+                }
+            }
+
+            BusMsg::Ln(LnMsg::Warning(PeerError { channel_id, data })) => {
+                let message = String::from_utf8_lossy(data).into_owned();
+                // All-zero channel id means that the warning refers to the connection as a whole
+                let channel_id = if channel_id.as_inner() == &Slice32::default() {
+                    warn!("Remote peer has sent a warning: {}", message);
+                    None
+                } else {
+                    warn!("Remote peer has sent a warning for channel {}: {}", channel_id, message);
+                    let channel_id =
+                        self.renamed_channels.get(channel_id).copied().unwrap_or(*channel_id);
+                    self.forward_to_channel(endpoints, channel_id, request.clone())?;
+                    Some(channel_id)
+                };
+                if let ServiceId::Peer(remote_peer) = self.identity() {
+                    let event = NodeEvent::PeerWarning { remote_peer, channel_id, message };
+                    endpoints.send_to(
+                        ServiceBus::Ctl,
+                        self.identity(),
+                        ServiceId::LnpBroker,
+                        BusMsg::Ctl(CtlMsg::NodeEvent(event)),
+                    )?;
+                }
             }
 
             BusMsg::Ln(message) => {
@@ -378,6 +689,25 @@ impl Runtime {
         Ok(())
     }
 
+    /// Forwards remote peer message to the channel daemon, or queues it if the daemon is in the
+    /// middle of switching to its permanent id
+    fn forward_to_channel(
+        &mut self,
+        endpoints: &mut Endpoints,
+        channel_id: ChannelId,
+        request: BusMsg,
+    ) -> Result<(), Error> {
+        let request = match self.renaming_channels.hold(channel_id, request) {
+            Some(request) => request,
+            None => {
+                debug!("Queueing message until channel {} switches its identity", channel_id);
+                return Ok(());
+            }
+        };
+        endpoints.send_to(ServiceBus::Msg, self.identity(), channel_id.into(), request)?;
+        Ok(())
+    }
+
     fn handle_rpc(
         &mut self,
         endpoints: &mut Endpoints,
@@ -386,30 +716,7 @@ impl Runtime {
     ) -> Result<(), Error> {
         match message {
             RpcMsg::GetInfo => {
-                let peer_info = PeerInfo {
-                    local_id: self.local_id,
-                    remote_id: self.remote_id.map(|id| vec![id]).unwrap_or_default(),
-                    local_socket: self.local_socket,
-                    remote_socket: vec![self.remote_socket],
-                    uptime: SystemTime::now()
-                        .duration_since(self.started)
-                        .unwrap_or_else(|_| Duration::from_secs(0)),
-                    since: self
-                        .started
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap_or_else(|_| Duration::from_secs(0))
-                        .as_secs(),
-                    messages_sent: self.messages_sent,
-                    messages_received: self.messages_received,
-                    channels: self
-                        .channels
-                        .iter()
-                        .copied()
-                        .map(ActiveChannelId::as_slice32)
-                        .collect(),
-                    connected: !self.connect,
-                    awaits_pong: self.awaited_pong.is_some(),
-                };
+                let peer_info = self.peer_info(false);
                 self.send_rpc(endpoints, client_id, peer_info)?;
             }
 
@@ -422,35 +729,246 @@ impl Runtime {
         Ok(())
     }
 
+    /// Reports the connection status, resetting the traffic statistics if `reset` is set
+    fn peer_info(&self, reset: bool) -> PeerInfo {
+        let message_stats = stats::aggregate(self.outbound.sent(), &self.received, reset);
+        PeerInfo {
+            local_id: self.local_id,
+            remote_id: self.remote_id.map(|id| vec![id]).unwrap_or_default(),
+            local_socket: self.local_socket,
+            remote_socket: vec![self.remote_socket],
+            direction: self.direction,
+            uptime: SystemTime::now()
+                .duration_since(self.started)
+                .unwrap_or_else(|_| Duration::from_secs(0)),
+            since: self
+                .started
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_else(|_| Duration::from_secs(0))
+                .as_secs(),
+            features: self.features.as_ref().map(features::feature_names).unwrap_or_default(),
+            messages_sent: message_stats.values().map(|stats| stats.sent as usize).sum(),
+            messages_received: message_stats.values().map(|stats| stats.received as usize).sum(),
+            bytes_sent: message_stats.values().map(|stats| stats.bytes_sent).sum(),
+            bytes_received: message_stats.values().map(|stats| stats.bytes_received).sum(),
+            ping_rtt_ms: self.last_ping_rtt.map(|rtt| rtt.as_millis() as u64),
+            channels: self.channels.iter().copied().map(ActiveChannelId::as_slice32).collect(),
+            connected: !self.connect,
+            awaits_pong: self.awaited_pong.is_some(),
+            queued_messages: self.outbound.pending(),
+            dropped_gossip: self.outbound.dropped_gossip(),
+            message_stats,
+            last_sent: self.outbound.sent().last_activity(),
+            last_received: self.received.last_activity(),
+            // Connections with the remote peer are counted by lnpd, since the daemon serves a
+            // single connection
+            handshakes: 1,
+            disconnects: none!(),
+        }
+    }
+
+    /// Negotiates features with the remote peer from its `init` message and notifies lnpd that
+    /// the connection is initialized
+    fn receive_init(&mut self, endpoints: &mut Endpoints, init: Init) -> Result<(), Error> {
+        let required = features::required_features();
+        let missing = features::missing_features(&required, &init.local_features);
+        if !missing.is_empty() {
+            let reason = format!("required features {} are not supported", missing.join(", "));
+            self.send_warning(&reason)?;
+            return self.drop_connection(endpoints, "unsupported_features", &reason);
+        }
+        let features = features::negotiate(&self.local_features, &init.local_features);
+        debug!(
+            "Features negotiated with the remote peer: {}",
+            features::feature_names(&features).join(", ")
+        );
+        self.features = Some(features.clone());
+        // Once the connection is initialized, existing channels with the peer have to be
+        // reestablished
+        if let ServiceId::Peer(remote_peer) = self.identity() {
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                ServiceId::LnpBroker,
+                BusMsg::Ctl(CtlMsg::PeerReconnected(remote_peer, features)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sends the remote peer a warning with zero channel id, which refers to the connection as a
+    /// whole and does not fail the channels
+    fn send_warning(&mut self, reason: &str) -> Result<(), Error> {
+        let warning = PeerError {
+            channel_id: ChannelId::from_inner(Slice32::default()),
+            data: reason.as_bytes().to_vec(),
+        };
+        self.send_to_peer(LnMsg::Warning(warning))?;
+        Ok(())
+    }
+
+    /// Queues message for the remote peer. Fails if the remote peer has stalled, in which case
+    /// the connection has to be dropped.
+    fn send_to_peer(&mut self, message: LnMsg) -> Result<Push, Error> {
+        let push = self.outbound.push(message);
+        match push {
+            Push::Queued | Push::Busy => {}
+            Push::Dropped => trace!("Message to the busy remote peer is dropped"),
+            Push::Stalled => return Err(Error::PeerStalled(self.outbound.pending())),
+        }
+        Ok(push)
+    }
+
+    /// Notifies daemon sending messages to the remote peer that the peer is busy, unless it was
+    /// already notified
+    fn notify_busy(&mut self, endpoints: &mut Endpoints, service: ServiceId) -> Result<(), Error> {
+        if !self.busy_services.insert(service.clone()) {
+            return Ok(());
+        }
+        if let ServiceId::Peer(remote_peer) = self.identity() {
+            warn!("Remote peer is busy; notifying {} to hold back channel updates", service);
+            self.send_ctl(endpoints, service, CtlMsg::PeerBusy(remote_peer))?;
+        }
+        Ok(())
+    }
+
+    /// Pings the remote peer once it is silent for the configured interval and closes the
+    /// connection if the peer misses too many pings
+    fn keepalive(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        // Nothing may be sent to the remote peer before the connection is initialized
+        if self.features.is_none() {
+            return Ok(());
+        }
+        match self.awaited_pong {
+            Some((_, sent)) if sent.elapsed() < self.keepalive.pong_timeout => Ok(()),
+            Some(_) => {
+                self.awaited_pong = None;
+                self.missed_pongs += 1;
+                warn!(
+                    "Remote peer {} has not replied to {} pings",
+                    self.remote_socket, self.missed_pongs
+                );
+                if self.missed_pongs >= self.keepalive.max_missed_pongs {
+                    let reason = "remote peer does not reply to pings";
+                    return self.drop_connection(endpoints, "ping_timeout", reason);
+                }
+                self.ping()
+            }
+            None if self.last_received.elapsed() >= self.keepalive.ping_interval => self.ping(),
+            None => Ok(()),
+        }
+    }
+
     fn ping(&mut self) -> Result<(), Error> {
         trace!("Sending ping to the remote peer");
-        if self.awaited_pong.is_some() {
-            warn!(
-                "Peer {}@{} ignores our ping. Are we banned?",
-                self.remote_id.expect("peer id is known at this stage"),
-                self.remote_socket
-            );
-        }
         let mut rng = rand::thread_rng();
+        // BOLT-1 requires ignored bytes to be zeros, since they must not contain any sensitive
+        // data
         let len: u16 = rng.gen_range(4, 32);
-        let mut noise = vec![0u8; len as usize];
-        rng.fill_bytes(&mut noise);
         let pong_size = rng.gen_range(4, 32);
-        self.messages_sent += 1;
-        self.sender.send_message(LnMsg::Ping(Ping { ignored: noise.into(), pong_size }))?;
-        self.awaited_pong = Some(pong_size);
+        let ping = Ping { ignored: vec![0u8; len as usize].into(), pong_size };
+        self.send_to_peer(LnMsg::Ping(ping))?;
+        self.awaited_pong = Some((pong_size, Instant::now()));
         Ok(())
     }
 
-    fn pong(&mut self, pong_size: u16) -> Result<(), Error> {
+    fn answer_ping(&mut self, endpoints: &mut Endpoints, pong_size: u16) -> Result<(), Error> {
+        let now = Instant::now();
+        let flooded = matches!(self.last_remote_ping, Some(last) if now - last < PING_MIN_INTERVAL);
+        if flooded {
+            self.flooded_pings += 1;
+            debug!("Ignoring ping which arrived too early after the previous one");
+            if self.flooded_pings >= PING_FLOOD_LIMIT {
+                warn!("Remote peer {} floods us with pings", self.remote_socket);
+                self.send_warning("too frequent pings")?;
+                self.report_misbehaviour(endpoints, Offence::PingFlood)?;
+                self.flooded_pings = 0;
+            }
+            return Ok(());
+        }
+        self.last_remote_ping = Some(now);
+        self.flooded_pings = 0;
+        if pong_size >= PONG_SIZE_IGNORED {
+            trace!("Ignoring ping which does not require a reply");
+            return Ok(());
+        }
         trace!("Replying with pong to the remote peer");
-        let mut noise = vec![0u8; pong_size as usize];
-        let mut rng = rand::thread_rng();
-        for byte in &mut noise {
-            *byte = rng.gen();
+        self.send_to_peer(LnMsg::Pong(vec![0u8; pong_size as usize].into()))?;
+        Ok(())
+    }
+
+    /// Counts message which the remote peer must not send at the current connection state,
+    /// reporting the peer once it sends too many of them
+    fn count_unexpected(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        if self.unexpected_messages.exceeded() {
+            self.report_misbehaviour(endpoints, Offence::UnexpectedMessages)?;
         }
-        self.messages_sent += 1;
-        self.sender.send_message(LnMsg::Pong(noise.into()))?;
         Ok(())
     }
+
+    /// Reports protocol violation by the remote peer to lnpd, which bans the peer once it
+    /// violates the protocol repeatedly
+    fn report_misbehaviour(
+        &mut self,
+        endpoints: &mut Endpoints,
+        offence: Offence,
+    ) -> Result<(), Error> {
+        let remote_id = match self.remote_id {
+            Some(remote_id) => remote_id,
+            None => return Ok(()),
+        };
+        warn!("Remote peer {} has violated the protocol: {}", self.remote_socket, offence);
+        let misbehaviour = Misbehaviour {
+            remote_id,
+            remote_socket: self.remote_socket,
+            offence: offence.to_string(),
+            penalty: offence.penalty(),
+            has_channels: !self.channels.is_empty(),
+        };
+        self.send_ctl(endpoints, ServiceId::LnpBroker, CtlMsg::PeerMisbehaved(misbehaviour))?;
+        Ok(())
+    }
+
+    /// Reports the connection as lost to lnpd, which notifies the channel daemons and schedules
+    /// reconnection of the remote peer, and stops the daemon. The cause is a short name under
+    /// which lnpd counts the disconnection, while the reason is logged.
+    fn drop_connection(
+        &mut self,
+        endpoints: &mut Endpoints,
+        cause: &str,
+        reason: &str,
+    ) -> Result<(), Error> {
+        error!("{} the connection: {}", "Dropping".err(), reason);
+        self.report_disconnected(endpoints, cause)?;
+        self.stop(0);
+        Ok(())
+    }
+
+    /// Drops the connection for the remote peer misbehaviour counted by the listener, which
+    /// learns the reason from the exit code of the daemon
+    fn abort(&mut self, endpoints: &mut Endpoints, cause: &str, err: Error) -> Result<(), Error> {
+        error!("{} the connection: {}", "Aborting".err(), err);
+        self.report_disconnected(endpoints, cause)?;
+        self.stop(Abort::with(&err).map_or(1, Abort::exit_code));
+        Ok(())
+    }
+
+    fn report_disconnected(&mut self, endpoints: &mut Endpoints, cause: &str) -> Result<(), Error> {
+        if let ServiceId::Peer(remote_peer) = self.identity() {
+            let message = BusMsg::Ctl(CtlMsg::PeerDisconnected(remote_peer, cause.to_owned()));
+            endpoints.send_to(ServiceBus::Ctl, self.identity(), ServiceId::LnpBroker, message)?;
+        }
+        Ok(())
+    }
+
+    /// Stops the daemon process with the exit code. Daemons running as threads can't be stopped.
+    fn stop(&self, exit_code: i32) {
+        if self.threaded {
+            warn!("Peer daemon running in a thread keeps the connection open");
+            return;
+        }
+        self.outbound.flush(FLUSH_TIMEOUT);
+        info!("Peer daemon {} is stopped", self.identity);
+        process::exit(exit_code);
+    }
 }
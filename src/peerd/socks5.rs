@@ -0,0 +1,185 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Minimal SOCKS5 client (RFC 1928) used for connecting remote peers through the Tor proxy.
+//!
+//! Only the `CONNECT` command without authentication is supported, which is sufficient for the
+//! Tor SOCKS port. Onion addresses are passed to the proxy as domain names, such that they are
+//! resolved by Tor itself.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use amplify::IoError;
+use internet2::addr::InetSocketAddr;
+
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Errors connecting remote peer through SOCKS5 proxy
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// I/O error communicating with the proxy: {0:?}
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// proxy does not speak SOCKS5 protocol
+    NotSocks5,
+
+    /// proxy requires authentication
+    AuthRequired,
+
+    /// destination host name {0} is too long for SOCKS5 protocol
+    HostTooLong(String),
+
+    /// proxy failed to connect the remote peer: {0}
+    Reply(Reply),
+}
+
+impl Error {
+    /// Whether the failure may be caused by a Tor circuit which could not be built, such that
+    /// the connection may succeed once retried
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Reply(Reply::GeneralFailure)
+                | Error::Reply(Reply::HostUnreachable)
+                | Error::Reply(Reply::TtlExpired)
+        )
+    }
+}
+
+/// Failure codes replied by the SOCKS5 proxy
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+#[display(doc_comments)]
+pub enum Reply {
+    /// general SOCKS server failure
+    GeneralFailure,
+
+    /// connection not allowed by ruleset
+    NotAllowed,
+
+    /// network unreachable
+    NetworkUnreachable,
+
+    /// host unreachable
+    HostUnreachable,
+
+    /// connection refused
+    ConnectionRefused,
+
+    /// TTL expired
+    TtlExpired,
+
+    /// command not supported
+    CommandNotSupported,
+
+    /// address type not supported
+    AddressNotSupported,
+
+    /// unknown failure code {0:#04x}
+    Unknown(u8),
+}
+
+impl From<u8> for Reply {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => Reply::GeneralFailure,
+            0x02 => Reply::NotAllowed,
+            0x03 => Reply::NetworkUnreachable,
+            0x04 => Reply::HostUnreachable,
+            0x05 => Reply::ConnectionRefused,
+            0x06 => Reply::TtlExpired,
+            0x07 => Reply::CommandNotSupported,
+            0x08 => Reply::AddressNotSupported,
+            code => Reply::Unknown(code),
+        }
+    }
+}
+
+/// Connects the destination through the SOCKS5 proxy. The timeout applies both to connecting
+/// the proxy and to the proxy connecting the destination.
+pub fn connect(
+    proxy: SocketAddr,
+    destination: InetSocketAddr,
+    timeout: Duration,
+) -> Result<TcpStream, Error> {
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    stream.write_all(&[SOCKS_VERSION, 1, METHOD_NO_AUTH])?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method)?;
+    match method {
+        [SOCKS_VERSION, METHOD_NO_AUTH] => {}
+        [SOCKS_VERSION, _] => return Err(Error::AuthRequired),
+        _ => return Err(Error::NotSocks5),
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match SocketAddr::try_from(destination) {
+        Ok(SocketAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        Ok(SocketAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        // Onion addresses are resolved by Tor
+        Err(_) => {
+            let host = destination.address.to_string();
+            let len = u8::try_from(host.len()).map_err(|_| Error::HostTooLong(host.clone()))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&destination.port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::NotSocks5);
+    }
+    if reply[1] != 0x00 {
+        return Err(Error::Reply(Reply::from(reply[1])));
+    }
+    // Bound address is of no use for us, but it has to be read out of the stream
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(Error::NotSocks5),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
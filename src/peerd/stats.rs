@@ -0,0 +1,155 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Statistics of the traffic with the remote peer.
+//!
+//! Messages are counted by the threads reading from and writing to the connection, each one
+//! owning its [`TrafficCounters`]. Counters are atomics updated with relaxed ordering, so counting
+//! adds no lock to the read and write paths; the runtime aggregates both counters when the peer
+//! info is requested. The statistics live as long as the connection and are not persisted.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lnp::p2p::legacy::Messages as LnMsg;
+
+use crate::rpc::MessageStats;
+
+/// Names of the message types counted separately, in the order of [`type_index`]. Messages of
+/// the types not listed here are counted as `other`.
+const MESSAGE_TYPES: [&str; 30] = [
+    "init",
+    "error",
+    "warning",
+    "ping",
+    "pong",
+    "open_channel",
+    "accept_channel",
+    "funding_created",
+    "funding_signed",
+    "funding_locked",
+    "shutdown",
+    "closing_signed",
+    "update_add_htlc",
+    "update_fulfill_htlc",
+    "update_fail_htlc",
+    "update_fail_malformed_htlc",
+    "commitment_signed",
+    "revoke_and_ack",
+    "update_fee",
+    "channel_reestablish",
+    "announcement_signatures",
+    "channel_announcement",
+    "node_announcement",
+    "channel_update",
+    "query_short_channel_ids",
+    "reply_short_channel_ids_end",
+    "query_channel_range",
+    "reply_channel_range",
+    "gossip_timestamp_filter",
+    "other",
+];
+
+/// Counters of the messages passed in a single direction, updated by a single thread
+#[derive(Default)]
+pub struct TrafficCounters {
+    messages: [AtomicU64; MESSAGE_TYPES.len()],
+    bytes: [AtomicU64; MESSAGE_TYPES.len()],
+    /// UNIX timestamp of the last counted message, or zero if no messages were counted
+    last_activity: AtomicU64,
+}
+
+impl TrafficCounters {
+    /// Counts message of the given serialized length
+    pub fn record(&self, message: &LnMsg, len: usize) {
+        let index = type_index(message);
+        self.messages[index].fetch_add(1, Ordering::Relaxed);
+        self.bytes[index].fetch_add(len as u64, Ordering::Relaxed);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.last_activity.store(now, Ordering::Relaxed);
+    }
+
+    /// Time of the last counted message
+    pub fn last_activity(&self) -> Option<u64> {
+        Some(self.last_activity.load(Ordering::Relaxed)).filter(|timestamp| *timestamp > 0)
+    }
+
+    /// Number and total size of the counted messages by the message type, which are zeroed once
+    /// read if `reset` is set. Messages counted while the counters are read are accounted either
+    /// to this or to the next read, but they are never lost.
+    fn take(&self, reset: bool) -> Vec<(u64, u64)> {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        self.messages.iter().map(read).zip(self.bytes.iter().map(read)).collect()
+    }
+}
+
+/// Aggregates the counters of the messages sent to and received from the remote peer into the
+/// per-type statistics, omitting the types of the messages which were never exchanged
+pub fn aggregate(
+    sent: &TrafficCounters,
+    received: &TrafficCounters,
+    reset: bool,
+) -> BTreeMap<String, MessageStats> {
+    MESSAGE_TYPES
+        .iter()
+        .zip(sent.take(reset).into_iter().zip(received.take(reset)))
+        .filter(|(_, (sent, received))| sent.0 > 0 || received.0 > 0)
+        .map(|(name, ((sent, bytes_sent), (received, bytes_received)))| {
+            (name.to_string(), MessageStats { sent, received, bytes_sent, bytes_received })
+        })
+        .collect()
+}
+
+/// Index of the message type in [`MESSAGE_TYPES`]
+fn type_index(message: &LnMsg) -> usize {
+    match message {
+        LnMsg::Init(_) => 0,
+        LnMsg::Error(_) => 1,
+        LnMsg::Warning(_) => 2,
+        LnMsg::Ping(_) => 3,
+        LnMsg::Pong(_) => 4,
+        LnMsg::OpenChannel(_) => 5,
+        LnMsg::AcceptChannel(_) => 6,
+        LnMsg::FundingCreated(_) => 7,
+        LnMsg::FundingSigned(_) => 8,
+        LnMsg::FundingLocked(_) => 9,
+        LnMsg::Shutdown(_) => 10,
+        LnMsg::ClosingSigned(_) => 11,
+        LnMsg::UpdateAddHtlc(_) => 12,
+        LnMsg::UpdateFulfillHtlc(_) => 13,
+        LnMsg::UpdateFailHtlc(_) => 14,
+        LnMsg::UpdateFailMalformedHtlc(_) => 15,
+        LnMsg::CommitmentSigned(_) => 16,
+        LnMsg::RevokeAndAck(_) => 17,
+        LnMsg::UpdateFee(_) => 18,
+        LnMsg::ChannelReestablish(_) => 19,
+        LnMsg::AnnouncementSignatures(_) => 20,
+        LnMsg::ChannelAnnouncement(_) => 21,
+        LnMsg::NodeAnnouncement(_) => 22,
+        LnMsg::ChannelUpdate(_) => 23,
+        LnMsg::QueryShortChannelIds(_) => 24,
+        LnMsg::ReplyShortChannelIdsEnd(_) => 25,
+        LnMsg::QueryChannelRange(_) => 26,
+        LnMsg::ReplyChannelRange(_) => 27,
+        LnMsg::GossipTimestampFilter(_) => 28,
+        _ => MESSAGE_TYPES.len() - 1,
+    }
+}
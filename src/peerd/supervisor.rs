@@ -12,23 +12,77 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+//! Peer daemon supervisor, which establishes connections with the remote peers and launches the
+//! daemon runtime for them.
+//!
+//! Each connection passes BOLT-8 handshake and the exchange of `init` messages before the
+//! runtime is started, so a remote peer stalling at these stages does not hold any daemon
+//! threads or bridge sockets. Each stage has to complete within its timeout from
+//! [`crate::HandshakeTimeouts`]; otherwise the connection is closed.
+
 use std::convert::TryFrom;
-use std::net::{SocketAddr, TcpListener};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::time::Duration;
-use std::{fs, thread};
+use std::time::{Duration, Instant};
+use std::{fs, process, thread};
 
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{self, rand, PublicKey};
 use internet2::addr::InetSocketAddr;
-use internet2::{session, LocalNode, LocalSocketAddr, NodeAddr, RemoteNodeAddr, RemoteSocketAddr};
-use microservices::peer::PeerConnection;
+use internet2::session::ftcp;
+use internet2::session::noise::HandshakeState;
+use internet2::{
+    session, CreateUnmarshaller, LocalNode, LocalSocketAddr, NodeAddr, RemoteNodeAddr, TypedEnum,
+    Unmarshall,
+};
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::{Init, Messages as LnMsg};
+use microservices::peer::{PeerConnection, RecvMessage, SendMessage};
+use nix::poll::{poll, PollFd, PollFlags};
 use nix::unistd::{fork, ForkResult, Pid};
 use strict_encoding::StrictDecode;
 
-use super::runtime;
+use super::inbound::{Abort, Admission, ChannelSignal, InboundStats};
+use super::transport::{self, Transport};
+use super::{features, runtime, socks5};
+use crate::lnpd::ban_list::BanList;
+use crate::opts::LNP_NODE_BAN_LIST;
 use crate::peerd::PeerSocket;
-use crate::{Config, Error, LogStyle};
+use crate::{Config, Error, LogStyle, TorProxy};
+
+/// Timeout for connecting remote peer through Tor proxy, unless other is requested by lnpd.
+/// Building Tor circuit towards onion service takes way longer than a direct TCP connection.
+const TOR_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximal number of attempts to connect remote peer through Tor proxy
+const TOR_CONNECT_ATTEMPTS: u8 = 3;
+
+/// Delay before the next attempt to connect remote peer through Tor proxy
+const TOR_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Length of the first act of BOLT-8 handshake, sent by the initiator
+const ACT_ONE_LEN: usize = 50;
+
+/// Length of the second act of BOLT-8 handshake, sent by the responder
+const ACT_TWO_LEN: usize = 50;
+
+/// Length of the third act of BOLT-8 handshake, sent by the initiator
+const ACT_THREE_LEN: usize = 66;
+
+/// Maximal length of the BOLT-8 message, which length prefix is two bytes long
+pub(super) const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Timeout for reading from the remote peer, after which the peer daemon checks whether the
+/// remote peer has to be pinged
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximal time the listener awaits for incoming connections before doing its housekeeping
+const LISTENER_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Clone, Debug)]
 pub(super) struct RuntimeParams {
@@ -39,6 +93,7 @@ pub(super) struct RuntimeParams {
     pub local_socket: Option<InetSocketAddr>,
     pub remote_socket: InetSocketAddr,
     pub connect: bool,
+    pub channel_signal: Option<ChannelSignal>,
 }
 
 impl RuntimeParams {
@@ -51,6 +106,7 @@ impl RuntimeParams {
             local_socket: None,
             remote_socket: Default::default(),
             connect: false,
+            channel_signal: None,
         }
     }
 }
@@ -79,21 +135,31 @@ pub fn run(config: Config, key_file: &Path, peer_socket: PeerSocket) -> Result<(
     let local_node = read_node_key_file(key_file);
 
     let threaded = config.threaded;
+    let tor_proxy = config.tor_proxy;
+    let timeouts = config.handshake_timeouts;
+    let local_features = features::local_features(&config);
     let mut params = RuntimeParams::with(config, local_node.node_id());
     match peer_socket {
-        PeerSocket::Listen(RemoteSocketAddr::Ftcp(inet_addr)) => {
+        PeerSocket::Listen(listen_addr) => {
+            let (transport, inet_addr) = match transport::with_addr(&listen_addr) {
+                Some(selected) => selected,
+                None => {
+                    error!("Unable to listen on {}: unsupported transport", listen_addr);
+                    return Err(Error::UnsupportedTransport(listen_addr.to_string()));
+                }
+            };
             info!("Running peer daemon in LISTEN mode");
 
             params.connect = false;
             params.local_socket = Some(inet_addr);
             params.id = NodeAddr::Remote(RemoteNodeAddr {
                 node_id: local_node.node_id(),
-                remote_addr: RemoteSocketAddr::Ftcp(inet_addr),
+                remote_addr: listen_addr,
             });
 
-            spawner(params, inet_addr, threaded)?;
+            spawner(params, inet_addr, transport, local_node, local_features, threaded)?;
         }
-        PeerSocket::Connect(remote_node_addr) => {
+        PeerSocket::Connect(remote_node_addr, timeout) => {
             debug!("Running peer daemon in CONNECT mode");
 
             params.connect = true;
@@ -101,87 +167,586 @@ pub fn run(config: Config, key_file: &Path, peer_socket: PeerSocket) -> Result<(
             params.remote_id = Some(remote_node_addr.node_id);
             params.remote_socket = remote_node_addr.remote_addr.into();
 
+            let (transport, inet_addr) = match transport::with_addr(&remote_node_addr.remote_addr) {
+                Some(selected) => selected,
+                None => {
+                    error!("Unable to connect {}: unsupported transport", remote_node_addr);
+                    let remote_addr = remote_node_addr.remote_addr.to_string();
+                    return Err(Error::UnsupportedTransport(remote_addr));
+                }
+            };
             info!("Connecting to {}", &remote_node_addr);
-            let connection = PeerConnection::connect(remote_node_addr, &local_node)
-                .expect("Unable to connect to the remote peer");
-            runtime::run(connection, params)?;
-        }
-        PeerSocket::Listen(_) => {
-            unimplemented!("we do not support non-TCP connections for the legacy lightning network")
+            let stream = match tor_proxy {
+                Some(proxy) if proxy.always || is_onion(inet_addr) => {
+                    connect_tor(&remote_node_addr, inet_addr, proxy, timeout)?
+                }
+                None if is_onion(inet_addr) => {
+                    error!("Remote peer {} requires Tor proxy", remote_node_addr);
+                    return Err(Error::NoTorProxy(remote_node_addr.to_string()));
+                }
+                _ => connect_tcp(&remote_node_addr, inet_addr, timeout)?,
+            };
+            let name = transport.name();
+            let stream =
+                transport.connect(stream, inet_addr, timeouts.handshake).map_err(|err| {
+                    error!("{} connection with {} has failed: {}", name, remote_node_addr, err);
+                    err
+                })?;
+
+            let (mut connection, stream) = handshake(
+                stream,
+                inet_addr,
+                remote_node_addr.node_id,
+                &local_node,
+                timeouts.handshake,
+            )?;
+            let init = exchange_init(&mut connection, &stream, local_features, timeouts.init)?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            runtime::run(connection, init, params)?;
         }
     }
 
     unreachable!()
 }
 
+/// Connects the remote peer directly. Unless the timeout is given, the connection is established
+/// with the operating system TCP connection timeout, which may take minutes.
+fn connect_tcp(
+    remote_node_addr: &RemoteNodeAddr,
+    inet_addr: InetSocketAddr,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, Error> {
+    let socket_addr = SocketAddr::try_from(inet_addr)
+        .expect("onion addresses are reachable only through Tor proxy");
+    let res = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&socket_addr, timeout),
+        None => TcpStream::connect(socket_addr),
+    };
+    res.map_err(|err| {
+        error!("Remote peer {} is not reachable: {}", remote_node_addr, err.err_details());
+        Error::from(err)
+    })
+}
+
+/// Onion addresses can't be converted into socket addresses and are reachable only through Tor
+fn is_onion(inet_addr: InetSocketAddr) -> bool { SocketAddr::try_from(inet_addr).is_err() }
+
+/// Connects remote peer through the Tor proxy, retrying the failures of building Tor circuit
+/// for as long as the timeout allows
+fn connect_tor(
+    remote_node_addr: &RemoteNodeAddr,
+    inet_addr: InetSocketAddr,
+    proxy: TorProxy,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, Error> {
+    let deadline = Instant::now() + timeout.unwrap_or(TOR_CONNECT_TIMEOUT);
+    let mut attempt = 1;
+    let stream = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match socks5::connect(proxy.address, inet_addr, remaining) {
+            Ok(stream) => break stream,
+            Err(err)
+                if err.is_transient()
+                    && attempt < TOR_CONNECT_ATTEMPTS
+                    && deadline > Instant::now() + TOR_RETRY_DELAY =>
+            {
+                warn!("Unable to reach {} through Tor ({}), retrying", remote_node_addr, err);
+                attempt += 1;
+                thread::sleep(TOR_RETRY_DELAY);
+            }
+            Err(err) => {
+                error!(
+                    "Remote peer {} is not reachable through Tor proxy {}: {}",
+                    remote_node_addr, proxy.address, err
+                );
+                return Err(err.into());
+            }
+        }
+    };
+    debug!("Tor proxy {} has connected {}", proxy.address, remote_node_addr);
+    Ok(stream)
+}
+
+/// Closes the connection once the timeout expires, unless disarmed before. Unlike socket read
+/// timeouts, it bounds the whole stage of the connection and not a single read, so the remote
+/// peer can't stall the stage by sending its data byte by byte.
+struct Watchdog {
+    disarm: mpsc::Sender<()>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn arm(stream: &TcpStream, timeout: Duration) -> Result<Watchdog, Error> {
+        let stream = stream.try_clone()?;
+        let (disarm, disarmed) = mpsc::channel();
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        thread::Builder::new().name(s!("peerd-watchdog")).spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = disarmed.recv_timeout(timeout) {
+                flag.store(true, Ordering::SeqCst);
+                // Pending reads fail once the connection is shut down
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        })?;
+        Ok(Watchdog { disarm, expired })
+    }
+
+    /// Stops the watchdog; returns whether the timeout has already expired and the connection
+    /// is closed
+    fn disarm(self) -> bool {
+        let _ = self.disarm.send(());
+        self.expired.load(Ordering::SeqCst)
+    }
+}
+
+/// Completes BOLT-8 handshake as an initiator within the timeout. Returns the connection together
+/// with the stream handle used to control the connection socket.
+pub(crate) fn handshake(
+    mut stream: TcpStream,
+    inet_addr: InetSocketAddr,
+    remote_key: PublicKey,
+    local_node: &LocalNode,
+    timeout: Duration,
+) -> Result<(PeerConnection, TcpStream), Error> {
+    let control = stream.try_clone()?;
+    // Tor proxy stream may keep the read timeout of the proxy negotiation
+    stream.set_read_timeout(None)?;
+    let watchdog = Watchdog::arm(&stream, timeout)?;
+
+    let ephemeral_key = secp256k1::SecretKey::new(&mut rand::thread_rng());
+    let handshake =
+        HandshakeState::new_initiator(&local_node.private_key(), &remote_key, &ephemeral_key);
+    let res = handshake.next(&[]).map_err(Error::Handshake).and_then(|(act_one, handshake)| {
+        stream.write_all(&act_one.unwrap_or_default())?;
+        let mut act_two = [0u8; ACT_TWO_LEN];
+        stream.read_exact(&mut act_two)?;
+        match handshake.next(&act_two).map_err(Error::Handshake)? {
+            (Some(act_three), HandshakeState::Complete(Some((transcoder, _)))) => {
+                stream.write_all(&act_three)?;
+                Ok(transcoder)
+            }
+            _ => Err(Error::Handshake(s!("unexpected state of Noise handshake"))),
+        }
+    });
+    let transcoder = handshake_result(res, watchdog, inet_addr, timeout)?;
+
+    let connection = ftcp::Connection::with(stream, inet_addr);
+    let session = session::Raw::with(transcoder, connection);
+    Ok((PeerConnection::with(session), control))
+}
+
+/// Completes BOLT-8 handshake as a responder within the timeout. Returns the connection together
+/// with the stream handle used to control the connection socket and the remote node key.
+pub(crate) fn accept_handshake(
+    mut stream: TcpStream,
+    inet_addr: InetSocketAddr,
+    local_node: &LocalNode,
+    timeout: Duration,
+) -> Result<(PeerConnection, TcpStream, PublicKey), Error> {
+    let control = stream.try_clone()?;
+    let watchdog = Watchdog::arm(&stream, timeout)?;
+
+    let ephemeral_key = secp256k1::SecretKey::new(&mut rand::thread_rng());
+    let handshake = HandshakeState::new_responder(&local_node.private_key(), &ephemeral_key);
+    let res = (|| {
+        let mut act_one = [0u8; ACT_ONE_LEN];
+        stream.read_exact(&mut act_one)?;
+        let (act_two, handshake) = handshake.next(&act_one).map_err(Error::Handshake)?;
+        stream.write_all(&act_two.unwrap_or_default())?;
+        let mut act_three = [0u8; ACT_THREE_LEN];
+        stream.read_exact(&mut act_three)?;
+        match handshake.next(&act_three).map_err(Error::Handshake)? {
+            (None, HandshakeState::Complete(Some(complete))) => Ok(complete),
+            _ => Err(Error::Handshake(s!("unexpected state of Noise handshake"))),
+        }
+    })();
+    let (transcoder, remote_key) = handshake_result(res, watchdog, inet_addr, timeout)?;
+
+    let connection = ftcp::Connection::with(stream, inet_addr);
+    let session = session::Raw::with(transcoder, connection);
+    Ok((PeerConnection::with(session), control, remote_key))
+}
+
+fn handshake_result<T>(
+    res: Result<T, Error>,
+    watchdog: Watchdog,
+    inet_addr: InetSocketAddr,
+    timeout: Duration,
+) -> Result<T, Error> {
+    if watchdog.disarm() {
+        warn!("Remote peer {} has not completed BOLT-8 handshake within {:?}", inet_addr, timeout);
+        return Err(Error::HandshakeTimeout(timeout));
+    }
+    res.map_err(|err| {
+        let err = match err {
+            Error::Io(err) => Error::Handshake(err.to_string()),
+            err => err,
+        };
+        error!("BOLT-8 handshake with {} has failed: {}", inet_addr, err);
+        err
+    })
+}
+
+/// Sends our `init` message and awaits `init` message from the remote peer, which must be the
+/// first message the remote peer sends after the handshake
+fn exchange_init(
+    connection: &mut PeerConnection,
+    stream: &TcpStream,
+    local_features: InitFeatures,
+    timeout: Duration,
+) -> Result<Init, Error> {
+    let watchdog = Watchdog::arm(stream, timeout)?;
+    let init = features::init_message(local_features);
+    let res = connection
+        .send_raw_message(&LnMsg::Init(init).serialize())
+        .and_then(|_| connection.recv_raw_message());
+    if watchdog.disarm() {
+        warn!("Remote peer has not sent init message within {:?} after the handshake", timeout);
+        return Err(Error::InitTimeout(timeout));
+    }
+    let frame = res?;
+    if frame.len() > MAX_MESSAGE_LEN {
+        warn!("Remote peer has sent {}-byte message instead of init", frame.len());
+        return Err(Error::OversizedMessage(frame.len()));
+    }
+    match &*LnMsg::create_unmarshaller().unmarshall(&frame)? {
+        LnMsg::Init(init) => {
+            debug!("Remote peer has initialized the connection");
+            Ok(init.clone())
+        }
+        message => {
+            warn!("Remote peer has sent {} before init message", message);
+            Err(Error::Misbehaving)
+        }
+    }
+}
+
+/// Accepts the incoming connection, completing the handshake and the exchange of `init`
+/// messages, and runs the daemon runtime for it
+fn accept(
+    stream: TcpStream,
+    inet_addr: InetSocketAddr,
+    transport: &dyn Transport,
+    local_node: &LocalNode,
+    local_features: InitFeatures,
+    mut params: RuntimeParams,
+) -> Result<(), Error> {
+    let timeouts = params.config.handshake_timeouts;
+    let stream = transport.accept(stream, timeouts.handshake).map_err(|err| {
+        let remote_socket = params.remote_socket;
+        warn!("{} connection from {} has failed: {}", transport.name(), remote_socket, err);
+        err
+    })?;
+    debug!("Establishing session with the remote");
+    let (mut connection, stream, remote_key) =
+        accept_handshake(stream, inet_addr, local_node, timeouts.handshake)?;
+    debug!("Session successfully established with {}", remote_key);
+    // Ban list is read once per connection, since the daemon serves a single connection
+    let banned = BanList::load(params.config.data_dir.join(LNP_NODE_BAN_LIST))
+        .map(|ban_list| ban_list.is_banned(&remote_key))
+        .unwrap_or_else(|err| {
+            warn!("Unable to read ban list: {}", err);
+            false
+        });
+    if banned {
+        warn!("Closing connection from {}, which is banned", remote_key);
+        return Err(Error::PeerBanned(remote_key));
+    }
+    params.remote_id = Some(remote_key);
+
+    let init = exchange_init(&mut connection, &stream, local_features, timeouts.init)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    runtime::run(connection, init, params)
+}
+
 pub enum Handler {
-    Thread(JoinHandle<Result<(), Error>>),
+    /// Thread of the daemon, with the token which the thread holds while it runs
+    Thread(JoinHandle<Result<(), Error>>, Arc<()>),
     Process(Pid),
 }
 
 fn spawner(
     mut params: RuntimeParams,
     inet_addr: InetSocketAddr,
+    transport: Arc<dyn Transport>,
+    local_node: LocalNode,
+    local_features: InitFeatures,
     threaded_daemons: bool,
 ) -> Result<(), Error> {
-    // Handlers for all of our spawned processes and threads
-    let mut handlers = vec![];
+    // Handlers for all of our spawned processes and threads are kept by the admission control
+    let stats_file = InboundStats::file(&params.config.data_dir, inet_addr);
+    let ban_list_file = params.config.data_dir.join(LNP_NODE_BAN_LIST);
+    let mut admission = Admission::with(
+        params.config.inbound_limits,
+        params.config.ban_duration,
+        stats_file,
+        ban_list_file,
+        !threaded_daemons,
+    );
 
-    info!("Binding TCP socket {}", inet_addr);
+    info!("Binding {} socket {}", transport.name(), inet_addr);
     let listener =
         TcpListener::bind(SocketAddr::try_from(inet_addr).expect("Tor is not yet supported"))
             .expect("Unable to bind to Lightning network peer socket");
 
     info!("Running TCP listener event loop");
+    debug!("Awaiting for incoming connections...");
     let stream = loop {
-        debug!("Awaiting for incoming connections...");
+        admission.maintain();
+        if !await_connection(&listener, LISTENER_MAINTENANCE_INTERVAL) {
+            continue;
+        }
         let (stream, remote_socket_addr) =
             listener.accept().expect("Error accepting incpming peer connection");
         info!("New connection from {}", remote_socket_addr);
 
+        if !admission.admit(remote_socket_addr.ip()) {
+            // Dropping the stream closes the connection before any handshake data are read
+            continue;
+        }
+
         params.remote_socket = remote_socket_addr.into();
+        let slot = admission.next_slot();
+        params.channel_signal = admission.channel_signal(slot);
 
         if threaded_daemons {
             debug!("Spawning child thread");
             let child_params = params.clone();
+            let child_node = local_node.clone();
+            let child_features = local_features.clone();
+            let child_transport = transport.clone();
+            let running = Arc::new(());
+            let token = running.clone();
             let handler = thread::Builder::new()
                 .name(format!("peerd-listner<{}>", inet_addr))
                 .spawn(move || {
-                    debug!("Establishing session with the remote");
-                    let session = session::Raw::with_ftcp_unencrypted(stream, inet_addr)
-                        .expect("Unable to establish session with the remote peer");
-                    let connection = PeerConnection::with(session);
-                    runtime::run(connection, child_params)
+                    let _running = token;
+                    let transport = &*child_transport;
+                    accept(stream, inet_addr, transport, &child_node, child_features, child_params)
                 })?;
-            handlers.push(Handler::Thread(handler));
+            admission.register(slot, remote_socket_addr.ip(), Handler::Thread(handler, running));
             // We have started the thread so awaiting for the next incoming connection
         } else {
             debug!("Forking child process");
             if let ForkResult::Parent { child } =
                 unsafe { fork().expect("Unable to fork child process") }
             {
-                handlers.push(Handler::Process(child));
+                admission.register(slot, remote_socket_addr.ip(), Handler::Process(child));
                 debug!("Child forked with pid {}; returning into main listener event loop", child);
             } else {
                 break stream; // We are in the child process and need to proceed with incoming
                               // connection
             }
         }
-        trace!("Total {} peerd are spawned for the incoming connections", handlers.len());
+        trace!("Total {} peerd are spawned for the incoming connections", admission.peers());
     };
 
-    // Here we get only in the child process forked from the parent
-    stream
-        .set_read_timeout(Some(Duration::from_secs(30)))
-        .expect("Unable to set up timeout for TCP connection");
+    // Here we get only in the child process forked from the parent. The listener learns why the
+    // connection was closed from the exit code.
+    if let Err(err) = accept(stream, inet_addr, &*transport, &local_node, local_features, params) {
+        error!("Incoming connection is closed: {}", err);
+        process::exit(Abort::with(&err).map_or(1, Abort::exit_code));
+    }
 
-    debug!("Establishing session with the remote");
-    let session = session::Raw::with_ftcp_unencrypted(stream, inet_addr)
-        .expect("Unable to establish session with the remote peer");
+    unreachable!()
+}
+
+/// Waits for an incoming connection up to the timeout; returns whether there is a connection to
+/// accept
+fn await_connection(listener: &TcpListener, timeout: Duration) -> bool {
+    let mut fds = [PollFd::new(listener.as_raw_fd(), PollFlags::POLLIN)];
+    matches!(poll(&mut fds, timeout.as_millis() as i32), Ok(ready) if ready > 0)
+}
 
-    debug!("Session successfully established");
-    let connection = PeerConnection::with(session);
-    runtime::run(connection, params)?;
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
 
-    unreachable!()
+    use bitcoin::hashes::hex::ToHex;
+    use internet2::session::Encrypt;
+
+    use super::transport::{Tcp, WebSocket};
+    use super::*;
+
+    /// BOLT-8 rotates the keys after each 1000 messages encrypted with them
+    const ROTATION_INTERVAL: usize = 1000;
+
+    // Handshake and message encryption test vectors from BOLT-8 specification
+
+    const ACT_ONE: &str =
+        "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df608655115\
+         1f58b8afe6c195782c6a";
+    const ACT_TWO: &str =
+        "0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac\
+         583c9ef6eafca3f730ae";
+    const ACT_THREE: &str =
+        "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc\
+         28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba";
+
+    /// Encryptions of `hello` message by the handshake initiator, indexed by the message number
+    const MESSAGES: [(usize, &str); 6] = [
+        (0, "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"),
+        (1, "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1"),
+        (500, "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8"),
+        (501, "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd"),
+        (1000, "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09"),
+        (1001, "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36"),
+    ];
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn local_node() -> LocalNode {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let seckey = secp256k1::SecretKey::new(&mut rand::thread_rng());
+        LocalNode::with(seckey, PublicKey::from_secret_key(&secp, &seckey))
+    }
+
+    /// Message with the number and length distinct for each of the consequent messages, such
+    /// that a message decrypted with the stale key or the wrong nonce is never taken as valid
+    fn message(no: usize) -> Vec<u8> {
+        let mut message = (no as u32).to_be_bytes().to_vec();
+        message.extend(vec![no as u8; no % 397]);
+        message
+    }
+
+    /// Connects two in-process peerd transports with BOLT-8 handshake. The accepting side runs
+    /// in a separate thread, which is passed the established connection; the returned receiver
+    /// reports the error of the accepting side, if any.
+    fn connect<F>(
+        transport: Arc<dyn Transport>,
+        remote: F,
+    ) -> (PeerConnection, Receiver<Option<String>>)
+    where
+        F: FnOnce(&mut PeerConnection) -> Result<(), Error> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let inet_addr = InetSocketAddr::from(addr);
+        let responder = local_node();
+        let responder_key = responder.node_id();
+        let responder_transport = transport.clone();
+        let (report, reports) = mpsc::channel();
+        thread::spawn(move || {
+            let res = (|| {
+                let (stream, _) = listener.accept()?;
+                let stream = responder_transport.accept(stream, TIMEOUT)?;
+                let (mut connection, ..) =
+                    accept_handshake(stream, inet_addr, &responder, TIMEOUT)?;
+                remote(&mut connection)
+            })();
+            report.send(res.err().map(|err| err.to_string())).unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let stream = transport.connect(stream, inet_addr, TIMEOUT).unwrap();
+        let (connection, _) = handshake(stream, inet_addr, responder_key, &local_node(), TIMEOUT)
+            .expect("BOLT-8 handshake has failed");
+        (connection, reports)
+    }
+
+    fn echo(transport: Arc<dyn Transport>, count: usize) {
+        let (mut connection, reports) = connect(transport, move |connection| {
+            for _ in 0..count {
+                let message = connection.recv_raw_message()?;
+                connection.send_raw_message(&message)?;
+            }
+            Ok(())
+        });
+        for no in 0..count {
+            connection.send_raw_message(&message(no)).unwrap();
+            let echo = connection
+                .recv_raw_message()
+                .unwrap_or_else(|err| panic!("echo of message #{} has failed: {}", no + 1, err));
+            assert_eq!(echo, message(no), "echo of message #{} is corrupted", no + 1);
+        }
+        assert_eq!(reports.recv_timeout(TIMEOUT).unwrap(), None);
+    }
+
+    /// Each side sends its messages in a row, so the key rotation of the sending and receiving
+    /// directions happens at different moments
+    fn one_way(transport: Arc<dyn Transport>, sent: usize, received: usize) {
+        let (mut connection, reports) = connect(transport, move |connection| {
+            for no in 0..sent {
+                if connection.recv_raw_message()? != message(no) {
+                    return Err(Error::Other(format!("message #{} is corrupted", no + 1)));
+                }
+            }
+            for no in 0..received {
+                connection.send_raw_message(&message(no))?;
+            }
+            Ok(())
+        });
+        for no in 0..sent {
+            connection.send_raw_message(&message(no)).unwrap();
+        }
+        for no in 0..received {
+            let msg = connection
+                .recv_raw_message()
+                .unwrap_or_else(|err| panic!("receiving message #{} has failed: {}", no + 1, err));
+            assert_eq!(msg, message(no), "message #{} is corrupted", no + 1);
+        }
+        assert_eq!(reports.recv_timeout(TIMEOUT).unwrap(), None);
+    }
+
+    #[test]
+    fn spec_vectors() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let key = |byte| secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+        let initiator_key = PublicKey::from_secret_key(&secp, &key(0x11));
+        let responder_key = PublicKey::from_secret_key(&secp, &key(0x21));
+        let initiator = HandshakeState::new_initiator(&key(0x11), &responder_key, &key(0x12));
+        let responder = HandshakeState::new_responder(&key(0x21), &key(0x22));
+
+        let (act_one, initiator) = initiator.next(&[]).unwrap();
+        let act_one = act_one.unwrap();
+        assert_eq!(act_one.to_hex(), ACT_ONE);
+        let (act_two, responder) = responder.next(&act_one).unwrap();
+        let act_two = act_two.unwrap();
+        assert_eq!(act_two.to_hex(), ACT_TWO);
+        let (act_three, mut transcoder) = match initiator.next(&act_two).unwrap() {
+            (Some(act_three), HandshakeState::Complete(Some((transcoder, _)))) => {
+                (act_three, transcoder)
+            }
+            _ => panic!("initiator has not completed the handshake"),
+        };
+        assert_eq!(act_three.to_hex(), ACT_THREE);
+        match responder.next(&act_three).unwrap() {
+            (None, HandshakeState::Complete(Some((_, remote_key)))) => {
+                assert_eq!(remote_key, initiator_key)
+            }
+            _ => panic!("responder has not completed the handshake"),
+        }
+
+        let encrypted = (0..=ROTATION_INTERVAL + 1)
+            .map(|_| transcoder.encrypt(&b"hello"[..]))
+            .collect::<Vec<_>>();
+        for (no, ciphertext) in MESSAGES.iter() {
+            assert_eq!(encrypted[*no].to_hex(), *ciphertext, "message #{} is wrong", no);
+        }
+    }
+
+    #[test]
+    fn rotation_boundary() {
+        // Last message before the rotation, the first one after it and the one after that
+        for count in &[ROTATION_INTERVAL - 1, ROTATION_INTERVAL, ROTATION_INTERVAL + 1] {
+            echo(Arc::new(Tcp), *count);
+        }
+    }
+
+    #[test]
+    fn rotation_sending() { one_way(Arc::new(Tcp), ROTATION_INTERVAL * 2 + 1, 1); }
+
+    #[test]
+    fn rotation_receiving() { one_way(Arc::new(Tcp), 1, ROTATION_INTERVAL * 2 + 1); }
+
+    #[test]
+    fn rotation_interleaved() {
+        one_way(Arc::new(Tcp), ROTATION_INTERVAL / 2 * 3, ROTATION_INTERVAL * 2 + 7);
+    }
+
+    #[test]
+    fn echo_5000_tcp() { echo(Arc::new(Tcp), 5000); }
+
+    #[test]
+    fn echo_5000_websocket() { echo(Arc::new(WebSocket), 5000); }
 }
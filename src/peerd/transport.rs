@@ -0,0 +1,371 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Transports carrying BOLT-8 stream between the peers.
+//!
+//! Raw TCP connections carry the stream as is. WebSocket connections (RFC 6455) wrap it into
+//! binary frames, so the node may run behind infrastructure passing only WebSocket traffic.
+//! The frames are unwrapped by the relay threads, which exchange the raw stream with the daemon
+//! over a loopback TCP connection. Thus, BOLT-8 handshake, timeouts and keepalive work
+//! identically for all transports. WebSocket connections are not encrypted with TLS, which is
+//! expected to be terminated by a reverse proxy; BOLT-8 encryption applies in either case.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::base64;
+use bitcoin::hashes::{sha1, Hash};
+use bitcoin::secp256k1::rand::{self, Rng};
+use internet2::addr::InetSocketAddr;
+use internet2::RemoteSocketAddr;
+
+use crate::Error;
+
+/// GUID appended to the WebSocket key when computing the accept key, as defined by RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximal length of the HTTP request or response upgrading the connection to WebSocket
+const MAX_HEAD_LEN: usize = 8192;
+
+/// Maximal payload length of a WebSocket frame. Peers may pack several BOLT-8 messages into a
+/// single frame, so the limit is well above the maximal BOLT-8 message length.
+const MAX_FRAME_LEN: u64 = 1 << 20;
+
+/// Length of the chunks of BOLT-8 stream sent in a single WebSocket frame
+const RELAY_BUFFER_LEN: usize = 65536;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Transport carrying BOLT-8 stream over the TCP connection with the remote peer
+pub trait Transport: Send + Sync {
+    /// Name of the transport used in the logs
+    fn name(&self) -> &'static str;
+
+    /// Negotiates the transport as the connecting side within the timeout. Returns the stream
+    /// carrying raw BOLT-8 data.
+    fn connect(
+        &self,
+        stream: TcpStream,
+        remote_addr: InetSocketAddr,
+        timeout: Duration,
+    ) -> Result<TcpStream, Error>;
+
+    /// Negotiates the transport as the accepting side within the timeout. Returns the stream
+    /// carrying raw BOLT-8 data.
+    fn accept(&self, stream: TcpStream, timeout: Duration) -> Result<TcpStream, Error>;
+}
+
+/// Selects the transport for the peer socket address. Returns `None` for the overlay protocols
+/// not supported by the lightning network.
+pub fn with_addr(addr: &RemoteSocketAddr) -> Option<(Arc<dyn Transport>, InetSocketAddr)> {
+    match *addr {
+        RemoteSocketAddr::Ftcp(inet_addr) => Some((Arc::new(Tcp), inet_addr)),
+        RemoteSocketAddr::Websocket(inet_addr) => Some((Arc::new(WebSocket), inet_addr)),
+        _ => None,
+    }
+}
+
+/// Raw TCP connection carrying BOLT-8 stream as is
+pub struct Tcp;
+
+impl Transport for Tcp {
+    fn name(&self) -> &'static str { "TCP" }
+
+    fn connect(
+        &self,
+        stream: TcpStream,
+        _remote_addr: InetSocketAddr,
+        _timeout: Duration,
+    ) -> Result<TcpStream, Error> {
+        Ok(stream)
+    }
+
+    fn accept(&self, stream: TcpStream, _timeout: Duration) -> Result<TcpStream, Error> {
+        Ok(stream)
+    }
+}
+
+/// WebSocket connection carrying BOLT-8 stream in binary frames
+pub struct WebSocket;
+
+impl Transport for WebSocket {
+    fn name(&self) -> &'static str { "WebSocket" }
+
+    fn connect(
+        &self,
+        mut stream: TcpStream,
+        remote_addr: InetSocketAddr,
+        timeout: Duration,
+    ) -> Result<TcpStream, Error> {
+        let deadline = Instant::now() + timeout;
+        let key = base64::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            remote_addr, key
+        );
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(request.as_bytes())?;
+        let head = read_head(&mut stream, deadline, timeout)?;
+        let mut lines = head.lines();
+        let status = lines.next().unwrap_or_default();
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(Error::WebSocket(format!("upgrade is refused with `{}`", status)));
+        }
+        if header(&head, "sec-websocket-accept") != Some(accept_key(&key)) {
+            return Err(Error::WebSocket(s!("remote peer has sent invalid accept key")));
+        }
+        debug!("Connection with {} is upgraded to WebSocket", remote_addr);
+        relay(stream, true)
+    }
+
+    fn accept(&self, mut stream: TcpStream, timeout: Duration) -> Result<TcpStream, Error> {
+        let deadline = Instant::now() + timeout;
+        stream.set_write_timeout(Some(timeout))?;
+        let head = read_head(&mut stream, deadline, timeout)?;
+        let is_get = head.starts_with("GET ");
+        let is_upgrade = header(&head, "upgrade")
+            .map(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+            .unwrap_or_default();
+        let key = match header(&head, "sec-websocket-key") {
+            Some(key) if is_get && is_upgrade => key,
+            _ => {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n");
+                return Err(Error::WebSocket(s!("remote peer has not requested the upgrade")));
+            }
+        };
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key)
+        );
+        stream.write_all(response.as_bytes())?;
+        debug!("Connection is upgraded to WebSocket");
+        relay(stream, false)
+    }
+}
+
+/// Reads HTTP request or response head up to the empty line, byte by byte, such that the
+/// frames following it are left in the stream. Fails with [`Error::HandshakeTimeout`] unless
+/// the head is read before the deadline, so the remote peer can't stall the upgrade.
+fn read_head(
+    stream: &mut TcpStream,
+    deadline: Instant,
+    timeout: Duration,
+) -> Result<String, Error> {
+    let mut head = Vec::with_capacity(512);
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(Error::WebSocket(format!("HTTP head exceeds {} bytes", MAX_HEAD_LEN)));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(Error::HandshakeTimeout(timeout));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        match stream.read(&mut byte) {
+            Ok(0) => return Err(Error::WebSocket(s!("connection is closed during the upgrade"))),
+            Ok(_) => head.push(byte[0]),
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(Error::HandshakeTimeout(timeout))
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    String::from_utf8(head).map_err(|_| Error::WebSocket(s!("HTTP head is not UTF-8 text")))
+}
+
+/// Value of the HTTP header with the given lowercase name
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().skip(1).find_map(|line| {
+        let mut split = line.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case(name) => {
+                Some(value.trim().to_owned())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Computes `Sec-WebSocket-Accept` value for the `Sec-WebSocket-Key` of the client
+fn accept_key(key: &str) -> String {
+    let digest = sha1::Hash::hash(format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+    base64::encode(&digest[..])
+}
+
+/// Starts the relay threads exchanging BOLT-8 stream between the WebSocket connection and the
+/// daemon. Returns the daemon end of the loopback connection. Closing either of the connections
+/// closes the other one.
+fn relay(stream: TcpStream, client: bool) -> Result<TcpStream, Error> {
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    let (daemon_end, relay_end) = loopback_pair()?;
+
+    let mut frames_in = stream.try_clone()?;
+    let mut bytes_out = relay_end.try_clone()?;
+    let writer = Arc::new(Mutex::new(stream));
+    let control = writer.clone();
+    thread::Builder::new().name(s!("peerd-ws-reader")).spawn(move || {
+        if let Err(err) = relay_frames(&mut frames_in, &mut bytes_out, &control, client) {
+            debug!("WebSocket connection is closed: {}", err);
+        }
+        let _ = bytes_out.shutdown(Shutdown::Both);
+    })?;
+
+    let mut bytes_in = relay_end;
+    thread::Builder::new().name(s!("peerd-ws-writer")).spawn(move || {
+        let mut buf = vec![0u8; RELAY_BUFFER_LEN];
+        let res = loop {
+            match bytes_in.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(len) => {
+                    if let Err(err) = send_frame(&writer, OPCODE_BINARY, &buf[..len], client) {
+                        break Err(err);
+                    }
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        if let Err(err) = res {
+            debug!("Relaying to WebSocket connection has stopped: {}", err);
+        }
+        let _ = send_frame(&writer, OPCODE_CLOSE, &[], client);
+        let _ = writer.lock().expect("WebSocket writer is poisoned").shutdown(Shutdown::Both);
+    })?;
+
+    Ok(daemon_end)
+}
+
+/// Connects a pair of sockets over the loopback interface
+fn loopback_pair() -> Result<(TcpStream, TcpStream), Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let daemon_end = TcpStream::connect(listener.local_addr()?)?;
+    let daemon_addr = daemon_end.local_addr()?;
+    loop {
+        let (relay_end, addr) = listener.accept()?;
+        // Connections from other local processes are dropped
+        if addr == daemon_addr {
+            daemon_end.set_nodelay(true)?;
+            relay_end.set_nodelay(true)?;
+            return Ok((daemon_end, relay_end));
+        }
+    }
+}
+
+/// Writes payloads of the binary frames received from the remote peer to the daemon, answering
+/// pings, until the connection is closed
+fn relay_frames(
+    frames: &mut TcpStream,
+    bytes: &mut TcpStream,
+    writer: &Mutex<TcpStream>,
+    client: bool,
+) -> Result<(), io::Error> {
+    loop {
+        let (opcode, payload) = recv_frame(frames, !client)?;
+        match opcode {
+            OPCODE_BINARY | OPCODE_CONTINUATION => bytes.write_all(&payload)?,
+            OPCODE_PING => send_frame(writer, OPCODE_PONG, &payload, client)?,
+            OPCODE_PONG => {}
+            OPCODE_CLOSE => {
+                let _ = send_frame(writer, OPCODE_CLOSE, &[], client);
+                return Ok(());
+            }
+            OPCODE_TEXT => return Err(invalid_data("text frames are not supported")),
+            _ => return Err(invalid_data("unknown frame opcode")),
+        }
+    }
+}
+
+/// Reads a single frame, returning its opcode and unmasked payload. Frames sent by the client
+/// must be masked.
+fn recv_frame(stream: &mut TcpStream, masked: bool) -> Result<(u8, Vec<u8>), io::Error> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0F;
+    if masked != (head[1] & 0x80 != 0) {
+        return Err(invalid_data("frame masking does not match the connection side"));
+    }
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("frame exceeds the maximal length"));
+    }
+    let mut mask = [0u8; 4];
+    if masked {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    if masked {
+        payload.iter_mut().enumerate().for_each(|(index, byte)| *byte ^= mask[index % 4]);
+    }
+    Ok((opcode, payload))
+}
+
+/// Sends a single final frame. Frames sent by the client are masked with a random key.
+fn send_frame(
+    writer: &Mutex<TcpStream>,
+    opcode: u8,
+    payload: &[u8],
+    masked: bool,
+) -> Result<(), io::Error> {
+    let mask_bit = if masked { 0x80 } else { 0 };
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        let mask = rand::thread_rng().gen::<[u8; 4]>();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    writer.lock().expect("WebSocket writer is poisoned").write_all(&frame)
+}
+
+fn invalid_data(reason: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, reason) }
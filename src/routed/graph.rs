@@ -0,0 +1,439 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Network graph built from the gossip messages, and validation of the messages.
+//!
+//! A channel enters the graph once its `channel_announcement` is signed by both nodes and both
+//! funding keys, and its funding output, looked up in the blockchain by watchd, pays to the
+//! 2-of-2 multisig of the announced funding keys. Channel updates and node announcements are
+//! accepted for the channels and nodes present in the graph, if they are newer than the known
+//! ones and are signed by the node they originate from. Channels leave the graph once watchd
+//! reports their funding output spent.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use amplify::Slice32;
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2};
+use bitcoin::blockdata::script;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Signature, VerifyOnly};
+use bitcoin::TxOut;
+use lightning_encoding::LightningEncode;
+use lnp::p2p::legacy::{
+    ChannelAnnouncement, ChannelUpdate, Messages as LnMsg, NodeAnnouncement, ShortChannelId,
+};
+use lnp_rpc::{GraphChannel, GraphInfo, GraphNode, GraphPolicy};
+
+use super::store::Record;
+use crate::lnpd::address_book::announced_socket_addr;
+
+/// Reasons to reject a gossip message
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Rejection {
+    /// message belongs to another blockchain
+    WrongChain,
+
+    /// message is not newer than the one already known
+    Outdated,
+
+    /// message signature is invalid
+    InvalidSignature,
+
+    /// channel is not announced
+    UnknownChannel,
+
+    /// node has no announced channels
+    UnknownNode,
+
+    /// funding output does not exist or is already spent
+    NoFunding,
+
+    /// funding output does not pay to the announced funding keys
+    FundingMismatch,
+}
+
+/// Announced channel with its routing policies
+struct Channel {
+    announcement: ChannelAnnouncement,
+
+    capacity_sat: u64,
+
+    /// Updates sent by the first and the second channel nodes
+    updates: [Option<ChannelUpdate>; 2],
+}
+
+/// Node having channels in the graph
+#[derive(Default)]
+struct Node {
+    channels: BTreeSet<ShortChannelId>,
+
+    /// Latest announcement of the node, if it has announced itself
+    announcement: Option<NodeAnnouncement>,
+}
+
+pub struct Graph {
+    /// Genesis hash of the blockchain used by the node
+    chain_hash: Slice32,
+
+    channels: BTreeMap<ShortChannelId, Channel>,
+
+    nodes: BTreeMap<PublicKey, Node>,
+
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl Graph {
+    pub fn with(chain_hash: Slice32) -> Graph {
+        Graph {
+            chain_hash,
+            channels: empty!(),
+            nodes: empty!(),
+            secp: Secp256k1::verification_only(),
+        }
+    }
+
+    /// Restores the graph from the records of the gossip store, which were validated before
+    /// being stored
+    pub fn restore(&mut self, records: Vec<Record>) {
+        for record in records {
+            self.insert(record);
+        }
+    }
+
+    /// Records representing the whole graph. Each channel announcement precedes the updates of
+    /// the channel and the announcements of the channel nodes.
+    pub fn records(&self) -> Vec<Record> {
+        let channels = self.channels.values();
+        let announcements = channels
+            .clone()
+            .map(|channel| Record::Channel(channel.announcement.clone(), channel.capacity_sat));
+        let updates = channels
+            .flat_map(|channel| channel.updates.iter().flatten().cloned())
+            .map(Record::Update);
+        let nodes = self.nodes.values().filter_map(|node| node.announcement.clone());
+        announcements.chain(updates).chain(nodes.map(Record::Node)).collect()
+    }
+
+    /// Number of the records representing the whole graph
+    pub fn record_count(&self) -> usize {
+        let updates = self.channels.values().flat_map(|channel| channel.updates.iter().flatten());
+        let nodes = self.nodes.values().filter(|node| node.announcement.is_some());
+        self.channels.len() + updates.count() + nodes.count()
+    }
+
+    pub fn channel_count(&self) -> usize { self.channels.len() }
+
+    pub fn short_channel_ids(&self) -> Vec<ShortChannelId> {
+        self.channels.keys().copied().collect()
+    }
+
+    pub fn chain_hash(&self) -> Slice32 { self.chain_hash }
+
+    /// Channels funded in the blocks from `first_blocknum` up to, but not including,
+    /// `end_blocknum`, in ascending order
+    pub fn short_channel_ids_within(
+        &self,
+        first_blocknum: u32,
+        end_blocknum: u64,
+    ) -> Vec<ShortChannelId> {
+        self.channels
+            .keys()
+            .filter(|short_channel_id| {
+                let height = u32::from(short_channel_id.block_height);
+                height >= first_blocknum && (height as u64) < end_blocknum
+            })
+            .copied()
+            .collect()
+    }
+
+    pub fn has_channel(&self, short_channel_id: ShortChannelId) -> bool {
+        self.channels.contains_key(&short_channel_id)
+    }
+
+    pub fn has_node(&self, node_id: PublicKey) -> bool { self.nodes.contains_key(&node_id) }
+
+    /// Adds validated record to the graph. Returns `false` if the record does not change the
+    /// graph.
+    pub fn insert(&mut self, record: Record) -> bool {
+        match record {
+            Record::Channel(announcement, capacity_sat) => {
+                if self.has_channel(announcement.short_channel_id) {
+                    return false;
+                }
+                let short_channel_id = announcement.short_channel_id;
+                for node_id in [announcement.node_id_1, announcement.node_id_2] {
+                    self.nodes.entry(node_id).or_default().channels.insert(short_channel_id);
+                }
+                let channel = Channel { announcement, capacity_sat, updates: [None, None] };
+                self.channels.insert(short_channel_id, channel);
+            }
+            Record::Update(update) => {
+                let channel = match self.channels.get_mut(&update.short_channel_id) {
+                    Some(channel) => channel,
+                    None => return false,
+                };
+                let slot = &mut channel.updates[direction(&update)];
+                match slot {
+                    Some(known) if known.timestamp >= update.timestamp => return false,
+                    _ => {}
+                }
+                *slot = Some(update);
+            }
+            Record::Node(announcement) => {
+                let node = match self.nodes.get_mut(&announcement.node_id) {
+                    Some(node) => node,
+                    None => return false,
+                };
+                match node.announcement {
+                    Some(ref known) if known.timestamp >= announcement.timestamp => return false,
+                    _ => {}
+                }
+                node.announcement = Some(announcement);
+            }
+            Record::Pruned(short_channel_id) => return self.remove_channel(short_channel_id),
+        }
+        true
+    }
+
+    /// Removes the channel, together with the nodes left without channels
+    pub fn remove_channel(&mut self, short_channel_id: ShortChannelId) -> bool {
+        let channel = match self.channels.remove(&short_channel_id) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        for node_id in [channel.announcement.node_id_1, channel.announcement.node_id_2] {
+            if let Some(node) = self.nodes.get_mut(&node_id) {
+                node.channels.remove(&short_channel_id);
+                if node.channels.is_empty() {
+                    self.nodes.remove(&node_id);
+                }
+            }
+        }
+        true
+    }
+
+    /// Checks the chain and signatures of the channel announcement. The funding output is
+    /// verified separately with [`verify_funding`], once it is found in the blockchain.
+    pub fn verify_announcement(&self, announcement: &ChannelAnnouncement) -> Result<(), Rejection> {
+        if announcement.chain_hash != self.chain_hash {
+            return Err(Rejection::WrongChain);
+        }
+        if self.has_channel(announcement.short_channel_id) {
+            return Err(Rejection::Outdated);
+        }
+        // All four signatures commit to the message data following them
+        let data = announcement.lightning_serialize().expect("in-memory encoding");
+        let digest = sha256d::Hash::hash(&data[256..]);
+        for (signature, key) in [
+            (&announcement.node_signature_1, &announcement.node_id_1),
+            (&announcement.node_signature_2, &announcement.node_id_2),
+            (&announcement.bitcoin_signature_1, &announcement.bitcoin_key_1),
+            (&announcement.bitcoin_signature_2, &announcement.bitcoin_key_2),
+        ] {
+            self.verify_signature(digest, signature, key)?;
+        }
+        Ok(())
+    }
+
+    pub fn verify_update(&self, update: &ChannelUpdate) -> Result<(), Rejection> {
+        if update.chain_hash != self.chain_hash {
+            return Err(Rejection::WrongChain);
+        }
+        let channel =
+            self.channels.get(&update.short_channel_id).ok_or(Rejection::UnknownChannel)?;
+        let direction = direction(update);
+        match channel.updates[direction] {
+            Some(ref known) if known.timestamp >= update.timestamp => {
+                return Err(Rejection::Outdated)
+            }
+            _ => {}
+        }
+        let node_id = if direction == 0 {
+            &channel.announcement.node_id_1
+        } else {
+            &channel.announcement.node_id_2
+        };
+        let data = update.lightning_serialize().expect("in-memory encoding");
+        self.verify_signature(sha256d::Hash::hash(&data[64..]), &update.signature, node_id)
+    }
+
+    pub fn verify_node(&self, announcement: &NodeAnnouncement) -> Result<(), Rejection> {
+        let node = self.nodes.get(&announcement.node_id).ok_or(Rejection::UnknownNode)?;
+        match node.announcement {
+            Some(ref known) if known.timestamp >= announcement.timestamp => {
+                return Err(Rejection::Outdated)
+            }
+            _ => {}
+        }
+        let data = announcement.lightning_serialize().expect("in-memory encoding");
+        let digest = sha256d::Hash::hash(&data[64..]);
+        self.verify_signature(digest, &announcement.signature, &announcement.node_id)
+    }
+
+    /// Announcement of the channel followed by its updates, as they are sent to the peers
+    /// querying the channel
+    pub fn channel_gossip(&self, short_channel_id: ShortChannelId) -> Vec<LnMsg> {
+        let channel = match self.channels.get(&short_channel_id) {
+            Some(channel) => channel,
+            None => return vec![],
+        };
+        let announcement = LnMsg::ChannelAnnouncement(channel.announcement.clone());
+        let updates = channel.updates.iter().flatten().cloned().map(LnMsg::ChannelUpdate);
+        Some(announcement).into_iter().chain(updates).collect()
+    }
+
+    pub fn node_gossip(&self, node_id: PublicKey) -> Option<LnMsg> {
+        let announcement = self.nodes.get(&node_id)?.announcement.clone()?;
+        Some(LnMsg::NodeAnnouncement(announcement))
+    }
+
+    /// Gossip with timestamps from `first_timestamp` up to, but not including, `end_timestamp`.
+    /// Channel announcements have no timestamp and are sent ahead of the channel updates
+    /// matching the range.
+    pub fn gossip_within(&self, first_timestamp: u32, end_timestamp: u64) -> Vec<LnMsg> {
+        let within = |timestamp: u32| {
+            timestamp >= first_timestamp && (timestamp as u64) < end_timestamp
+        };
+        let mut gossip = vec![];
+        for channel in self.channels.values() {
+            let mut updates = channel
+                .updates
+                .iter()
+                .flatten()
+                .filter(|update| within(update.timestamp))
+                .cloned()
+                .map(LnMsg::ChannelUpdate)
+                .peekable();
+            if updates.peek().is_some() {
+                gossip.push(LnMsg::ChannelAnnouncement(channel.announcement.clone()));
+                gossip.extend(updates);
+            }
+        }
+        let nodes = self.nodes.values().filter_map(|node| node.announcement.as_ref());
+        gossip.extend(
+            nodes
+                .filter(|announcement| within(announcement.timestamp))
+                .cloned()
+                .map(LnMsg::NodeAnnouncement),
+        );
+        gossip
+    }
+
+    pub fn describe(&self) -> GraphInfo {
+        GraphInfo {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(node_id, node)| self.graph_node(*node_id, node))
+                .collect(),
+            channels: self
+                .channels
+                .iter()
+                .map(|(short_channel_id, channel)| graph_channel(*short_channel_id, channel))
+                .collect(),
+        }
+    }
+
+    pub fn node_info(&self, node_id: PublicKey) -> Option<GraphNode> {
+        self.nodes.get(&node_id).map(|node| self.graph_node(node_id, node))
+    }
+
+    pub fn channel_info(&self, short_channel_id: ShortChannelId) -> Option<GraphChannel> {
+        self.channels
+            .get(&short_channel_id)
+            .map(|channel| graph_channel(short_channel_id, channel))
+    }
+
+    fn graph_node(&self, node_id: PublicKey, node: &Node) -> GraphNode {
+        let announcement = node.announcement.as_ref();
+        GraphNode {
+            node_id,
+            announced: announcement.map(|announcement| announcement.timestamp),
+            addresses: announcement
+                .map(|announcement| {
+                    let addresses = announcement.addresses.as_inner();
+                    addresses.iter().filter_map(announced_socket_addr).collect()
+                })
+                .unwrap_or_default(),
+            channels: node.channels.iter().copied().collect(),
+            capacity_sat: node
+                .channels
+                .iter()
+                .filter_map(|short_channel_id| self.channels.get(short_channel_id))
+                .map(|channel| channel.capacity_sat)
+                .sum(),
+        }
+    }
+
+    fn verify_signature(
+        &self,
+        digest: sha256d::Hash,
+        signature: &Signature,
+        key: &PublicKey,
+    ) -> Result<(), Rejection> {
+        let message = Message::from_slice(&digest[..]).expect("hash is 32 bytes");
+        self.secp.verify(&message, signature, key).map_err(|_| Rejection::InvalidSignature)
+    }
+}
+
+/// Checks that the funding output exists and pays to 2-of-2 multisig of the funding keys
+/// announced for the channel; returns the channel capacity
+pub fn verify_funding(
+    announcement: &ChannelAnnouncement,
+    txout: Option<&TxOut>,
+) -> Result<u64, Rejection> {
+    let txout = txout.ok_or(Rejection::NoFunding)?;
+    let mut pubkeys =
+        [announcement.bitcoin_key_1.serialize(), announcement.bitcoin_key_2.serialize()];
+    pubkeys.sort();
+    let witness_script = script::Builder::new()
+        .push_opcode(OP_PUSHNUM_2)
+        .push_slice(&pubkeys[0])
+        .push_slice(&pubkeys[1])
+        .push_opcode(OP_PUSHNUM_2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script();
+    if txout.script_pubkey != witness_script.to_v0_p2wsh() {
+        return Err(Rejection::FundingMismatch);
+    }
+    Ok(txout.value)
+}
+
+/// Index of the channel node which has sent the update
+pub fn direction(update: &ChannelUpdate) -> usize { (update.channel_flags & 1) as usize }
+
+fn graph_channel(short_channel_id: ShortChannelId, channel: &Channel) -> GraphChannel {
+    GraphChannel {
+        short_channel_id,
+        node_1: channel.announcement.node_id_1,
+        node_2: channel.announcement.node_id_2,
+        capacity_sat: channel.capacity_sat,
+        policy_1: channel.updates[0].as_ref().map(graph_policy),
+        policy_2: channel.updates[1].as_ref().map(graph_policy),
+    }
+}
+
+fn graph_policy(update: &ChannelUpdate) -> GraphPolicy {
+    GraphPolicy {
+        timestamp: update.timestamp,
+        disabled: update.channel_flags & 0b10 != 0,
+        cltv_expiry_delta: update.cltv_expiry_delta,
+        htlc_minimum_msat: update.htlc_minimum_msat,
+        // The field is present only if the first bit of the message flags is set
+        htlc_maximum_msat: Some(update.htlc_maximum_msat).filter(|_| update.message_flags & 1 != 0),
+        fee_base_msat: update.fee_base_msat,
+        fee_proportional_millionths: update.fee_proportional_millionths,
+    }
+}
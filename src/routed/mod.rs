@@ -12,14 +12,19 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod graph;
 #[cfg(feature = "server")]
 mod opts;
+mod queries;
 mod runtime;
+mod store;
 
 #[cfg(feature = "server")]
 pub use opts::Opts;
 pub use runtime::run;
 
+use crate::rpc::{ErrorCode, ToRpcError};
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
 #[display(doc_comments)]
 pub enum PaymentError {
@@ -29,3 +34,12 @@ pub enum PaymentError {
     /// there is no known route to the payee
     RouteNotFound,
 }
+
+impl ToRpcError for PaymentError {
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            PaymentError::AmountUnknown => ErrorCode::InvalidRequest,
+            PaymentError::RouteNotFound => ErrorCode::NotFound,
+        }
+    }
+}
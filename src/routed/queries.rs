@@ -0,0 +1,313 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! BOLT-7 gossip queries (`gossip_queries` feature).
+//!
+//! Peers negotiating the feature do not receive any gossip until they set a timestamp filter with
+//! `gossip_timestamp_filter`. Graph sync is done with range queries: `query_channel_range` asks
+//! for the short ids of the channels funded in a block range, and the channels missing from the
+//! local graph are requested with `query_short_channel_ids`, one query at a time.
+
+use std::collections::VecDeque;
+
+use amplify::Slice32;
+use lnp::p2p::legacy::{
+    GossipTimestampFilter, QueryChannelRange, QueryShortChannelIds, ReplyChannelRange,
+    ShortChannelId,
+};
+
+/// Maximal number of short channel ids in a single reply or query, keeping the message under
+/// 65535 bytes with the uncompressed encoding of the ids
+pub const MAX_SHORT_IDS: usize = 8000;
+
+/// Splits channels funded in the queried block range into `reply_channel_range` messages. The
+/// replies cover the whole queried range without gaps and never split channels of a single block
+/// across several replies; only the last reply has `sync_complete` set.
+///
+/// The short channel ids must be sorted and belong to the queried range. If the node does not
+/// follow the queried chain, `complete` is `false` and no channels are given.
+pub fn channel_range_replies(
+    query: &QueryChannelRange,
+    short_ids: &[ShortChannelId],
+    complete: bool,
+) -> Vec<ReplyChannelRange> {
+    let end_blocknum = query.first_blocknum as u64 + query.number_of_blocks as u64;
+    let mut replies = vec![];
+    let mut first_blocknum = query.first_blocknum;
+    let mut chunk = vec![];
+    for (index, short_channel_id) in short_ids.iter().enumerate() {
+        let height = u32::from(short_channel_id.block_height);
+        chunk.push(*short_channel_id);
+        let next_height = short_ids.get(index + 1).map(|next| u32::from(next.block_height));
+        // Reply is sent once it is full, at a block boundary
+        if chunk.len() >= MAX_SHORT_IDS && next_height != Some(height) {
+            replies.push(ReplyChannelRange {
+                chain_hash: query.chain_hash,
+                first_blocknum,
+                number_of_blocks: height + 1 - first_blocknum,
+                sync_complete: 0,
+                short_ids: chunk.split_off(0),
+                unknown_tlvs: none!(),
+            });
+            first_blocknum = height + 1;
+        }
+    }
+    if replies.is_empty() || !chunk.is_empty() || (first_blocknum as u64) < end_blocknum {
+        replies.push(ReplyChannelRange {
+            chain_hash: query.chain_hash,
+            first_blocknum,
+            number_of_blocks: end_blocknum.saturating_sub(first_blocknum as u64) as u32,
+            sync_complete: 0,
+            short_ids: chunk,
+            unknown_tlvs: none!(),
+        });
+    }
+    if let Some(last) = replies.last_mut() {
+        last.sync_complete = complete as u8;
+    }
+    replies
+}
+
+/// Query for all the channels of the chain
+pub fn full_range_query(chain_hash: Slice32) -> QueryChannelRange {
+    QueryChannelRange {
+        chain_hash,
+        first_blocknum: 0,
+        number_of_blocks: u32::MAX,
+        unknown_tlvs: none!(),
+    }
+}
+
+/// Timestamp filter requesting the gossip generated from now on
+pub fn live_gossip_filter(chain_hash: Slice32, now: u32) -> GossipTimestampFilter {
+    GossipTimestampFilter { chain_hash, first_timestamp: now, timestamp_range: u32::MAX }
+}
+
+/// Checks whether the gossip with the given timestamp passes the filter set by the remote peer
+pub fn passes_filter(filter: &GossipTimestampFilter, timestamp: u32) -> bool {
+    let end_timestamp = filter.first_timestamp as u64 + filter.timestamp_range as u64;
+    timestamp >= filter.first_timestamp && (timestamp as u64) < end_timestamp
+}
+
+/// Progress of the graph sync with a remote peer
+#[derive(Default)]
+pub struct GraphSync {
+    /// Channels reported by the peer which are not yet requested
+    queue: VecDeque<ShortChannelId>,
+
+    /// Whether `query_short_channel_ids` was sent and is not yet answered
+    awaits_reply: bool,
+
+    /// Whether the peer has sent the last `reply_channel_range`
+    range_complete: bool,
+}
+
+impl GraphSync {
+    /// Adds channels reported by the peer, which are missing from the local graph, to the
+    /// query queue
+    pub fn enqueue(&mut self, reply: &ReplyChannelRange, missing: impl Fn(ShortChannelId) -> bool) {
+        self.queue.extend(reply.short_ids.iter().copied().filter(|id| missing(*id)));
+        if reply.sync_complete != 0 {
+            self.range_complete = true;
+        }
+    }
+
+    /// Marks the last query answered
+    pub fn reply_received(&mut self) { self.awaits_reply = false; }
+
+    /// Next query to send to the peer, if the previous one is answered and there are channels
+    /// left to request
+    pub fn next_query(&mut self, chain_hash: Slice32) -> Option<QueryShortChannelIds> {
+        if self.awaits_reply || self.queue.is_empty() {
+            return None;
+        }
+        let count = self.queue.len().min(MAX_SHORT_IDS);
+        self.awaits_reply = true;
+        Some(QueryShortChannelIds {
+            chain_hash,
+            short_ids: self.queue.drain(..count).collect(),
+            unknown_tlvs: none!(),
+        })
+    }
+
+    /// Whether all the channels reported by the peer are received
+    pub fn is_complete(&self) -> bool {
+        self.range_complete && !self.awaits_reply && self.queue.is_empty()
+    }
+
+    /// Number of the channels left to request
+    pub fn remaining(&self) -> usize { self.queue.len() }
+}
+
+#[cfg(test)]
+mod tests {
+    use lightning_encoding::LightningDecode;
+
+    use super::*;
+
+    fn short_id(height: u32, tx_index: u32) -> ShortChannelId {
+        let mut data = height.to_be_bytes()[1..].to_vec();
+        data.extend(&tx_index.to_be_bytes()[1..]);
+        data.extend(&[0u8, 0]);
+        ShortChannelId::lightning_deserialize(&data).unwrap()
+    }
+
+    /// Short ids of `per_block` channels in each of the blocks in the given range
+    fn short_ids(blocks: std::ops::Range<u32>, per_block: u32) -> Vec<ShortChannelId> {
+        blocks.flat_map(|height| (0..per_block).map(move |index| short_id(height, index))).collect()
+    }
+
+    fn query(first_blocknum: u32, number_of_blocks: u32) -> QueryChannelRange {
+        QueryChannelRange {
+            chain_hash: Slice32::default(),
+            first_blocknum,
+            number_of_blocks,
+            unknown_tlvs: none!(),
+        }
+    }
+
+    /// Query extended by the given number of blocks
+    fn query_ext(query: QueryChannelRange, blocks: u32) -> QueryChannelRange {
+        QueryChannelRange { number_of_blocks: query.number_of_blocks + blocks, ..query }
+    }
+
+    /// Checks that the replies cover the queried range without gaps, contain all the channels in
+    /// order within their block ranges, and that only the last reply completes the sync
+    fn check_replies(
+        query: &QueryChannelRange,
+        short_ids: &[ShortChannelId],
+        replies: &[ReplyChannelRange],
+        complete: bool,
+    ) {
+        assert!(!replies.is_empty());
+        let mut next_blocknum = query.first_blocknum as u64;
+        for (index, reply) in replies.iter().enumerate() {
+            assert_eq!(reply.chain_hash, query.chain_hash);
+            assert_eq!(reply.first_blocknum as u64, next_blocknum);
+            next_blocknum += reply.number_of_blocks as u64;
+            for short_id in &reply.short_ids {
+                let height = u32::from(short_id.block_height) as u64;
+                assert!(height >= reply.first_blocknum as u64 && height < next_blocknum);
+            }
+            let last = index == replies.len() - 1;
+            assert_eq!(reply.sync_complete, (last && complete) as u8);
+        }
+        assert_eq!(next_blocknum, query.first_blocknum as u64 + query.number_of_blocks as u64);
+        let replied = replies.iter().flat_map(|reply| reply.short_ids.clone()).collect::<Vec<_>>();
+        assert_eq!(replied, short_ids);
+    }
+
+    #[test]
+    fn empty_range() {
+        let query = query(100, 50);
+        let replies = channel_range_replies(&query, &[], true);
+        assert_eq!(replies.len(), 1);
+        check_replies(&query, &[], &replies, true);
+    }
+
+    #[test]
+    fn unknown_chain() {
+        let query = full_range_query(Slice32::default());
+        let replies = channel_range_replies(&query, &[], false);
+        assert_eq!(replies.len(), 1);
+        check_replies(&query, &[], &replies, false);
+    }
+
+    #[test]
+    fn single_reply() {
+        let query = query(100, 50);
+        let short_ids = short_ids(110..120, 5);
+        let replies = channel_range_replies(&query, &short_ids, true);
+        assert_eq!(replies.len(), 1);
+        check_replies(&query, &short_ids, &replies, true);
+    }
+
+    #[test]
+    fn multi_chunk_replies() {
+        let query = query(1000, 10_000);
+        // Reply capacity is not a multiple of the number of channels per block
+        let short_ids = short_ids(1000..8000, 3);
+        let replies = channel_range_replies(&query, &short_ids, true);
+        assert_eq!(replies.len(), 3);
+        check_replies(&query, &short_ids, &replies, true);
+        for reply in &replies[..2] {
+            // Reply is closed at the first block boundary after it gets full
+            assert!(reply.short_ids.len() >= MAX_SHORT_IDS);
+            assert!(reply.short_ids.len() < MAX_SHORT_IDS + 3);
+        }
+        assert_eq!(replies[2].short_ids.len(), 21_000 - replies[0].short_ids.len() * 2);
+    }
+
+    #[test]
+    fn full_reply_at_range_end() {
+        let query = query(0, 8000);
+        let short_ids = short_ids(0..8000, 1);
+        let replies = channel_range_replies(&query, &short_ids, true);
+        assert_eq!(replies.len(), 1);
+        check_replies(&query, &short_ids, &replies, true);
+
+        // Range past the last full reply is covered by an empty reply
+        let query = query_ext(query, 100);
+        let replies = channel_range_replies(&query, &short_ids, true);
+        assert_eq!(replies.len(), 2);
+        assert!(replies[1].short_ids.is_empty());
+        check_replies(&query, &short_ids, &replies, true);
+    }
+
+    #[test]
+    fn graph_sync() {
+        let chain_hash = Slice32::default();
+        let mut sync = GraphSync::default();
+        let query = query(0, 10_000);
+        let short_ids = short_ids(0..10_000, 1);
+        let replies = channel_range_replies(&query, &short_ids, true);
+        assert_eq!(replies.len(), 2);
+
+        // Channels known to the local graph are not requested
+        let missing = |short_id: ShortChannelId| u32::from(short_id.block_height) % 10 != 0;
+        sync.enqueue(&replies[0], missing);
+        assert!(!sync.is_complete());
+        sync.enqueue(&replies[1], missing);
+        assert_eq!(sync.remaining(), 9000);
+
+        let first = sync.next_query(chain_hash).unwrap();
+        assert_eq!(first.short_ids.len(), MAX_SHORT_IDS);
+        // Next query waits for the reply to the previous one
+        assert!(sync.next_query(chain_hash).is_none());
+        sync.reply_received();
+        let second = sync.next_query(chain_hash).unwrap();
+        assert_eq!(second.short_ids.len(), 1000);
+        assert!(!sync.is_complete());
+        sync.reply_received();
+        assert!(sync.next_query(chain_hash).is_none());
+        assert!(sync.is_complete());
+    }
+
+    #[test]
+    fn timestamp_filter() {
+        let filter = GossipTimestampFilter {
+            chain_hash: Slice32::default(),
+            first_timestamp: 100,
+            timestamp_range: 10,
+        };
+        assert!(!passes_filter(&filter, 99));
+        assert!(passes_filter(&filter, 100));
+        assert!(passes_filter(&filter, 109));
+        assert!(!passes_filter(&filter, 110));
+
+        let live = live_gossip_filter(Slice32::default(), u32::MAX - 1);
+        assert!(!passes_filter(&live, u32::MAX - 2));
+        assert!(passes_filter(&live, u32::MAX));
+    }
+}
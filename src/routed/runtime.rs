@@ -12,28 +12,179 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{mem, thread};
+
 use amplify::{Slice32, Wrapper};
 use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::TxOut;
 use internet2::presentation::sphinx::Hop;
+use internet2::{zmqsocket, NodeAddr, ZmqType, ZMQ_CONTEXT};
 use lightning_invoice::Invoice;
-use lnp::p2p::legacy::{Messages as LnMsg, PaymentOnion, PaymentRequest};
+use lnp::features::InitFeatures;
+use lnp::p2p::legacy::{
+    ChannelAnnouncement, ChannelUpdate, GossipTimestampFilter, Messages as LnMsg,
+    NodeAnnouncement, PaymentOnion, PaymentRequest, QueryChannelRange, QueryShortChannelIds,
+    ReplyChannelRange, ReplyShortChannelIdsEnd, ShortChannelId,
+};
 use lnp::router::gossip::{GossipExt, UpdateMsg};
 use lnp::router::Router;
 use lnp::Extension;
-use lnp_rpc::{ClientId, PayInvoice, RpcMsg};
+use lnp_rpc::{
+    AuthError, ClientId, ErrorCode, MessageVerification, PayInvoice, RpcError, RpcMsg,
+    VerifyMessage,
+};
 use microservices::esb;
 use wallet::hlc::HashLock;
 
+use super::graph::{direction, verify_funding, Graph, Rejection};
+use super::queries::{self, GraphSync};
+use super::store::{GossipStore, Record};
 use crate::bus::{BusMsg, CtlMsg, ServiceBus};
+use crate::message_signing;
+use crate::opts::LNP_NODE_GOSSIP_STORE;
 use crate::routed::PaymentError;
 use crate::rpc::ServiceId;
-use crate::{Config, Endpoints, Error, Responder, Service};
+use crate::service::BridgeHandler;
+use crate::{Config, Endpoints, Error, Responder, RpcAuth, Service};
+
+/// Period between relaying the gossip to the connected peers. Messages received during the period
+/// are relayed in a single batch, where only the latest update of a channel direction and the
+/// latest announcement of a node are kept.
+const GOSSIP_FLUSH_PERIOD: Duration = Duration::from_secs(60);
+
+/// Maximal number of the restored channels which funding outputs are re-checked with watchd
+/// during each gossip flush period
+const FUNDING_CHECK_BATCH: usize = 500;
+
+/// Gossip store is compacted once it has that many records more than twice the number of the
+/// records representing the graph
+const STORE_COMPACTION_SLACK: usize = 1000;
 
 pub fn run(config: Config) -> Result<(), Error> {
-    let runtime =
-        Runtime { identity: ServiceId::Router, router: Router::default(), enquirer: None };
+    let chain_hash = config.chain.as_genesis_hash().as_inner();
+    let mut graph = Graph::with(Slice32::from(chain_hash));
+    let store_path = config.data_dir.join(LNP_NODE_GOSSIP_STORE);
+    graph.restore(GossipStore::load(&store_path)?);
+    // Rewriting the store drops the superseded messages
+    let store = GossipStore::create(store_path, graph.records())?;
+    info!("Restored network graph with {} channels from the gossip store", graph.channel_count());
+
+    let mut router = Router::default();
+    for message in graph.records().iter().filter_map(Record::to_message) {
+        if let Err(err) = router.update_from_peer(&message) {
+            debug!("Router has not accepted stored gossip {}: {}", message, err);
+        }
+    }
+
+    debug!("Opening bridge between runtime and timer threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://routed-timer")?;
+    rx.bind("inproc://routed-timer")?;
+
+    debug!("Starting timer thread");
+    let timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    thread::spawn(move || run_timer(timer));
+
+    let runtime = Runtime {
+        identity: ServiceId::Router,
+        router,
+        enquirer: None,
+        rpc_auth: RpcAuth::load(&config.data_dir)?,
+        sync_peers: config.gossip_sync_peers.clone(),
+        // Restored channels may have been closed while the node was offline
+        unchecked_channels: graph.short_channel_ids(),
+        graph,
+        store,
+        pending_channels: empty!(),
+        peers: empty!(),
+        relay_queue: empty!(),
+    };
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+fn run_timer(mut timer: esb::Controller<ServiceBus, BusMsg, BridgeHandler>) {
+    loop {
+        thread::sleep(GOSSIP_FLUSH_PERIOD);
+        let message = BusMsg::Ctl(CtlMsg::Timeout);
+        if let Err(err) = timer.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+            error!("Routed timer thread is unable to reach the runtime: {}", err);
+        }
+    }
+}
+
+/// Channel announcement awaiting for watchd to find the channel funding output
+struct PendingChannel {
+    announcement: ChannelAnnouncement,
+
+    /// Service which has sent the announcement
+    source: ServiceId,
+
+    /// Updates of the channel received before the announcement is validated
+    updates: Vec<(ChannelUpdate, ServiceId)>,
+
+    /// Announcements of the channel nodes received before the announcement is validated, for the
+    /// nodes which have no other channels
+    nodes: Vec<(NodeAnnouncement, ServiceId)>,
 
-    Service::run(config, runtime, false)
+    /// Number of gossip flushes passed since the funding output was requested
+    flushes: u8,
+}
+
+/// Gossip state of a connected remote peer
+struct PeerGossip {
+    /// Whether `gossip_queries` feature is negotiated with the peer
+    queries: bool,
+
+    /// Timestamp filter set by the peer negotiating gossip queries; until it is set, the peer
+    /// gets no gossip
+    filter: Option<GossipTimestampFilter>,
+
+    /// Graph sync with the peer, if it is being synced from
+    sync: Option<GraphSync>,
+}
+
+impl PeerGossip {
+    fn with(features: &InitFeatures) -> PeerGossip {
+        PeerGossip { queries: features.gossip_queries, filter: None, sync: None }
+    }
+
+    /// Checks whether the gossip message is relayed to the peer
+    fn accepts(&self, message: &LnMsg) -> bool {
+        if !self.queries {
+            return true;
+        }
+        let filter = match self.filter {
+            Some(ref filter) => filter,
+            None => return false,
+        };
+        match message {
+            LnMsg::ChannelUpdate(update) => queries::passes_filter(filter, update.timestamp),
+            LnMsg::NodeAnnouncement(announcement) => {
+                queries::passes_filter(filter, announcement.timestamp)
+            }
+            // Channel announcements are relayed together with the updates of the channel
+            _ => true,
+        }
+    }
 }
 
 pub struct Runtime {
@@ -42,6 +193,30 @@ pub struct Runtime {
     router: Router<GossipExt>,
 
     enquirer: Option<ClientId>,
+
+    /// Root key authenticating client requests
+    rpc_auth: RpcAuth,
+
+    /// Remote peers the graph is synced from once they are connected
+    sync_peers: Vec<PublicKey>,
+
+    /// Network graph built from the validated gossip
+    graph: Graph,
+
+    /// Store persisting the validated gossip
+    store: GossipStore,
+
+    /// Channels which funding outputs were not checked since the graph was restored
+    unchecked_channels: Vec<ShortChannelId>,
+
+    /// Announced channels which funding outputs are being looked up by watchd
+    pending_channels: HashMap<ShortChannelId, PendingChannel>,
+
+    /// Connected remote peers receiving the gossip
+    peers: HashMap<NodeAddr, PeerGossip>,
+
+    /// Gossip to relay at the end of the flush period, with the services it was received from
+    relay_queue: Vec<(LnMsg, ServiceId)>,
 }
 
 impl Responder for Runtime {
@@ -66,10 +241,17 @@ where
         message: BusMsg,
     ) -> Result<(), Self::Error> {
         match (bus, message, source) {
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) => self.flush_gossip(endpoints),
             (ServiceBus::Msg, BusMsg::Ln(msg), source) => self.handle_p2p(endpoints, source, msg),
             (ServiceBus::Ctl, BusMsg::Ctl(msg), source) => self.handle_ctl(endpoints, source, msg),
-            (ServiceBus::Rpc, BusMsg::Rpc(msg), ServiceId::Client(client_id)) => {
-                self.handle_rpc(endpoints, client_id, msg)
+            (ServiceBus::Rpc, BusMsg::Request(request), ServiceId::Client(client_id)) => {
+                match self.rpc_auth.authorize(request) {
+                    Ok(msg) => self.handle_rpc(endpoints, client_id, msg),
+                    Err(err) => Ok(self.reject_rpc(endpoints, client_id, err)?),
+                }
+            }
+            (ServiceBus::Rpc, BusMsg::Rpc(_), ServiceId::Client(client_id)) => {
+                Ok(self.reject_rpc(endpoints, client_id, AuthError::Unauthenticated)?)
             }
             (ServiceBus::Rpc, BusMsg::Rpc(_), service) => {
                 unreachable!("lnpd received RPC message not from a client but from {}", service)
@@ -95,11 +277,43 @@ where
 impl Runtime {
     fn handle_p2p(
         &mut self,
-        _endpoints: &mut Endpoints,
-        _source: ServiceId,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
         message: LnMsg,
     ) -> Result<(), Error> {
-        self.router.update_from_peer(&message).map_err(Error::from)
+        // Updates of the local channels which are not announced are used for routing only, since
+        // lnpd sends them to the channel remote peer itself. Announcements of the local node and
+        // channels are validated and broadcast as any other gossip.
+        if let LnMsg::ChannelUpdate(ref update) = message {
+            let short_channel_id = update.short_channel_id;
+            if source == ServiceId::LnpBroker
+                && !self.graph.has_channel(short_channel_id)
+                && !self.pending_channels.contains_key(&short_channel_id)
+            {
+                return self.router.update_from_peer(&message).map_err(Error::from);
+            }
+        }
+
+        match message {
+            LnMsg::ChannelAnnouncement(announcement) => {
+                self.receive_announcement(endpoints, source, announcement)?
+            }
+            LnMsg::ChannelUpdate(update) => self.receive_update(source, update),
+            LnMsg::NodeAnnouncement(announcement) => {
+                self.receive_node(endpoints, source, announcement)?
+            }
+            LnMsg::QueryChannelRange(query) => self.answer_range(endpoints, source, query)?,
+            LnMsg::ReplyChannelRange(reply) => self.receive_range(endpoints, source, reply)?,
+            LnMsg::QueryShortChannelIds(query) => self.answer_ids(endpoints, source, query)?,
+            LnMsg::ReplyShortChannelIdsEnd(reply) => {
+                self.receive_ids_end(endpoints, source, reply)?
+            }
+            LnMsg::GossipTimestampFilter(filter) => {
+                self.receive_filter(endpoints, source, filter)?
+            }
+            message => self.router.update_from_peer(&message)?,
+        }
+        Ok(())
     }
 
     fn handle_rpc(
@@ -121,6 +335,48 @@ impl Runtime {
                 self.send_ctl(endpoints, ServiceId::Channel(channel_id), msg)?;
             }
 
+            RpcMsg::DescribeGraph => self.send_rpc(endpoints, client_id, self.graph.describe())?,
+
+            RpcMsg::GetNodeInfo(node_id) => {
+                let reply = match self.graph.node_info(node_id) {
+                    Some(node) => RpcMsg::GraphNode(node),
+                    None => RpcMsg::Failure(RpcError::new(
+                        ErrorCode::NotFound,
+                        format!("node {} has no channels known from the gossip", node_id),
+                    )),
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            RpcMsg::GetChannelInfo(short_channel_id) => {
+                let reply = match self.graph.channel_info(short_channel_id) {
+                    Some(channel) => RpcMsg::GraphChannel(channel),
+                    None => RpcMsg::Failure(RpcError::new(
+                        ErrorCode::NotFound,
+                        format!("channel {} is not known from the gossip", short_channel_id),
+                    )),
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            RpcMsg::VerifyMessage(VerifyMessage { message, signature, pubkey }) => {
+                let secp = Secp256k1::verification_only();
+                let reply = match message_signing::recover(&secp, &message, &signature) {
+                    Ok(signer) => {
+                        let valid = match pubkey {
+                            Some(pubkey) => signer == pubkey,
+                            None => self.graph.has_node(signer),
+                        };
+                        RpcMsg::MessageVerification(MessageVerification { valid, pubkey: signer })
+                    }
+                    Err(err) => RpcMsg::Failure(RpcError::new(
+                        ErrorCode::InvalidSignature,
+                        format!("malformed message signature: {}", err),
+                    )),
+                };
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
             wrong_msg => {
                 error!("Request is not supported by the RPC interface");
                 return Err(Error::wrong_esb_msg(ServiceBus::Rpc, &wrong_msg));
@@ -132,11 +388,25 @@ impl Runtime {
 
     fn handle_ctl(
         &mut self,
-        _: &mut Endpoints,
+        endpoints: &mut Endpoints,
         _: ServiceId,
         message: CtlMsg,
     ) -> Result<(), Error> {
         match message {
+            CtlMsg::FundingOutput { short_channel_id, txout } => {
+                self.complete_announcement(endpoints, short_channel_id, txout)?
+            }
+
+            CtlMsg::FundingSpent(short_channel_id) => self.prune_channel(short_channel_id),
+
+            CtlMsg::PeerReconnected(remote_peer, features) => {
+                self.peer_connected(endpoints, remote_peer, &features)?
+            }
+
+            CtlMsg::PeerDisconnected(remote_peer, _) => {
+                self.peers.remove(&remote_peer);
+            }
+
             CtlMsg::ChannelCreated(channel_info) => {
                 debug!("Adding local channel {} to the routing table", channel_info.channel_id);
                 self.router.update_from_local(&UpdateMsg::DirectChannelAdd(channel_info))?;
@@ -160,6 +430,401 @@ impl Runtime {
         Ok(())
     }
 
+    fn receive_announcement(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        announcement: ChannelAnnouncement,
+    ) -> Result<(), Error> {
+        let short_channel_id = announcement.short_channel_id;
+        if self.pending_channels.contains_key(&short_channel_id) {
+            return Ok(());
+        }
+        if let Err(err) = self.graph.verify_announcement(&announcement) {
+            log_rejection("channel_announcement", short_channel_id, &source, err);
+            return Ok(());
+        }
+        let pending =
+            PendingChannel { announcement, source, updates: vec![], nodes: vec![], flushes: 0 };
+        self.pending_channels.insert(short_channel_id, pending);
+        self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::GetFundingOutput(short_channel_id))?;
+        Ok(())
+    }
+
+    /// Accepts the announced channel once watchd has found its funding output, or prunes the
+    /// restored channel which funding output is not found anymore
+    fn complete_announcement(
+        &mut self,
+        endpoints: &mut Endpoints,
+        short_channel_id: ShortChannelId,
+        txout: Option<TxOut>,
+    ) -> Result<(), Error> {
+        let pending = match self.pending_channels.remove(&short_channel_id) {
+            Some(pending) => pending,
+            None if txout.is_none() => {
+                self.prune_channel(short_channel_id);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+        match verify_funding(&pending.announcement, txout.as_ref()) {
+            Ok(capacity_sat) => {
+                self.accept(Record::Channel(pending.announcement, capacity_sat), pending.source);
+                for (update, source) in pending.updates {
+                    self.receive_update(source, update);
+                }
+                for (announcement, source) in pending.nodes {
+                    self.receive_node(endpoints, source, announcement)?;
+                }
+            }
+            Err(err) => {
+                log_rejection("channel_announcement", short_channel_id, &pending.source, err)
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_update(&mut self, source: ServiceId, update: ChannelUpdate) {
+        let short_channel_id = update.short_channel_id;
+        if let Some(pending) = self.pending_channels.get_mut(&short_channel_id) {
+            pending.updates.push((update, source));
+            return;
+        }
+        match self.graph.verify_update(&update) {
+            Ok(()) => self.accept(Record::Update(update), source),
+            Err(err) => log_rejection("channel_update", short_channel_id, &source, err),
+        }
+    }
+
+    fn receive_node(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        announcement: NodeAnnouncement,
+    ) -> Result<(), Error> {
+        let node_id = announcement.node_id;
+        match self.graph.verify_node(&announcement) {
+            Ok(()) => {}
+            // Node which has just opened its first channel is announced together with the channel
+            Err(Rejection::UnknownNode) => {
+                let pending = self.pending_channels.values_mut().find(|pending| {
+                    pending.announcement.node_id_1 == node_id
+                        || pending.announcement.node_id_2 == node_id
+                });
+                match pending {
+                    Some(pending) => pending.nodes.push((announcement, source)),
+                    None => {
+                        log_rejection("node_announcement", node_id, &source, Rejection::UnknownNode)
+                    }
+                }
+                return Ok(());
+            }
+            Err(err) => {
+                log_rejection("node_announcement", node_id, &source, err);
+                return Ok(());
+            }
+        }
+        // Node announcements populate the lnpd address book used to connect nodes by their ids
+        endpoints.send_to(
+            ServiceBus::Msg,
+            self.identity(),
+            ServiceId::LnpBroker,
+            BusMsg::Ln(LnMsg::NodeAnnouncement(announcement.clone())),
+        )?;
+        self.accept(Record::Node(announcement), source);
+        Ok(())
+    }
+
+    /// Adds validated gossip to the graph, the gossip store and the relay queue
+    fn accept(&mut self, record: Record, source: ServiceId) {
+        let message = match record.to_message() {
+            Some(message) => message,
+            None => return,
+        };
+        if !self.graph.insert(record.clone()) {
+            return;
+        }
+        if let Err(err) = self.router.update_from_peer(&message) {
+            debug!("Router has not accepted gossip {}: {}", message, err);
+        }
+        if let Err(err) = self.store.append(&record) {
+            warn!("Unable to save gossip to the store: {}", err);
+        }
+        self.compact_store();
+
+        // Only the latest update of a channel direction and announcement of a node are relayed
+        self.relay_queue.retain(|(queued, _)| !supersedes(&message, queued));
+        self.relay_queue.push((message, source));
+    }
+
+    fn prune_channel(&mut self, short_channel_id: ShortChannelId) {
+        if !self.graph.remove_channel(short_channel_id) {
+            return;
+        }
+        info!("Removing channel {} which funding output is spent", short_channel_id);
+        if let Err(err) = self.store.append(&Record::Pruned(short_channel_id)) {
+            warn!("Unable to save gossip to the store: {}", err);
+        }
+        self.compact_store();
+        // Routes are computed by the router which does not support removal of the remote
+        // channels; the channel is absent from the router after the restart only
+    }
+
+    /// Rewrites the gossip store once most of its records are superseded
+    fn compact_store(&mut self) {
+        let actual = self.graph.record_count();
+        if self.store.records() <= actual * 2 + STORE_COMPACTION_SLACK {
+            return;
+        }
+        debug!("Compacting gossip store from {} to {} records", self.store.records(), actual);
+        if let Err(err) = self.store.compact(self.graph.records()) {
+            warn!("Unable to compact the gossip store: {}", err);
+        }
+    }
+
+    /// Relays queued gossip to the connected peers, except the peers it was received from, and
+    /// requests funding outputs of the next batch of the restored channels
+    fn flush_gossip(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        // Announcements which funding output watchd was unable to look up are dropped; they are
+        // received again with the gossip of other peers
+        self.pending_channels.retain(|_, pending| {
+            pending.flushes += 1;
+            pending.flushes <= 1
+        });
+
+        let batch = self.unchecked_channels.len().saturating_sub(FUNDING_CHECK_BATCH);
+        for short_channel_id in self.unchecked_channels.split_off(batch) {
+            self.send_ctl(endpoints, ServiceId::Watch, CtlMsg::GetFundingOutput(short_channel_id))?;
+        }
+
+        let queue = mem::take(&mut self.relay_queue);
+        if queue.is_empty() || self.peers.is_empty() {
+            return Ok(());
+        }
+        debug!("Relaying {} gossip messages to {} peers", queue.len(), self.peers.len());
+        for (message, source) in queue {
+            for (remote_peer, peer) in &self.peers {
+                let peerd = ServiceId::Peer(remote_peer.clone());
+                if peerd == source || !peer.accepts(&message) {
+                    continue;
+                }
+                let message = BusMsg::Ln(message.clone());
+                let identity = self.identity();
+                if let Err(err) = endpoints.send_to(ServiceBus::Msg, identity, peerd, message) {
+                    warn!("Unable to relay gossip to {}: {}", remote_peer, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn peer_connected(
+        &mut self,
+        endpoints: &mut Endpoints,
+        remote_peer: NodeAddr,
+        features: &InitFeatures,
+    ) -> Result<(), Error> {
+        let mut peer = PeerGossip::with(features);
+        if peer.queries {
+            // Peers negotiating gossip queries send no gossip until we set a filter
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let filter = queries::live_gossip_filter(self.graph.chain_hash(), now.as_secs() as u32);
+            self.send_peer(endpoints, &remote_peer, LnMsg::GossipTimestampFilter(filter))?;
+
+            let syncing = self.peers.values().any(|peer| peer.sync.is_some());
+            let sync = if self.sync_peers.is_empty() {
+                self.graph.channel_count() == 0 && !syncing
+            } else {
+                self.sync_peers.contains(&remote_peer.id)
+            };
+            if sync {
+                info!("Syncing network graph from {}", remote_peer);
+                let query = queries::full_range_query(self.graph.chain_hash());
+                self.send_peer(endpoints, &remote_peer, LnMsg::QueryChannelRange(query))?;
+                peer.sync = Some(GraphSync::default());
+            }
+        }
+        self.peers.insert(remote_peer, peer);
+        Ok(())
+    }
+
+    fn answer_range(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        query: QueryChannelRange,
+    ) -> Result<(), Error> {
+        let remote_peer = match source {
+            ServiceId::Peer(remote_peer) => remote_peer,
+            _ => return Ok(()),
+        };
+        let complete = query.chain_hash == self.graph.chain_hash();
+        let short_ids = if complete {
+            let end_blocknum = query.first_blocknum as u64 + query.number_of_blocks as u64;
+            self.graph.short_channel_ids_within(query.first_blocknum, end_blocknum)
+        } else {
+            vec![]
+        };
+        let replies = queries::channel_range_replies(&query, &short_ids, complete);
+        debug!(
+            "Replying {} with {} channels in {} messages",
+            remote_peer,
+            short_ids.len(),
+            replies.len()
+        );
+        for reply in replies {
+            self.send_peer(endpoints, &remote_peer, LnMsg::ReplyChannelRange(reply))?;
+        }
+        Ok(())
+    }
+
+    fn answer_ids(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        query: QueryShortChannelIds,
+    ) -> Result<(), Error> {
+        let remote_peer = match source {
+            ServiceId::Peer(remote_peer) => remote_peer,
+            _ => return Ok(()),
+        };
+        let full_information = query.chain_hash == self.graph.chain_hash();
+        if full_information {
+            // Node announcements are sent once per query
+            let mut nodes = vec![];
+            for short_channel_id in query.short_ids {
+                for message in self.graph.channel_gossip(short_channel_id) {
+                    if let LnMsg::ChannelAnnouncement(ref announcement) = message {
+                        nodes.extend([announcement.node_id_1, announcement.node_id_2]);
+                    }
+                    self.send_peer(endpoints, &remote_peer, message)?;
+                }
+            }
+            nodes.sort_unstable();
+            nodes.dedup();
+            for node_id in nodes {
+                if let Some(message) = self.graph.node_gossip(node_id) {
+                    self.send_peer(endpoints, &remote_peer, message)?;
+                }
+            }
+        }
+        let reply = ReplyShortChannelIdsEnd {
+            chain_hash: query.chain_hash,
+            full_information: full_information as u8,
+        };
+        self.send_peer(endpoints, &remote_peer, LnMsg::ReplyShortChannelIdsEnd(reply))
+    }
+
+    fn receive_range(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        reply: ReplyChannelRange,
+    ) -> Result<(), Error> {
+        let remote_peer = match source {
+            ServiceId::Peer(remote_peer) => remote_peer,
+            _ => return Ok(()),
+        };
+        let graph = &self.graph;
+        let pending = &self.pending_channels;
+        let sync = match self.peers.get_mut(&remote_peer).and_then(|peer| peer.sync.as_mut()) {
+            Some(sync) if reply.chain_hash == graph.chain_hash() => sync,
+            _ => return Ok(()),
+        };
+        sync.enqueue(&reply, |short_channel_id| {
+            !graph.has_channel(short_channel_id) && !pending.contains_key(&short_channel_id)
+        });
+        self.continue_sync(endpoints, remote_peer)
+    }
+
+    fn receive_ids_end(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        reply: ReplyShortChannelIdsEnd,
+    ) -> Result<(), Error> {
+        let remote_peer = match source {
+            ServiceId::Peer(remote_peer) => remote_peer,
+            _ => return Ok(()),
+        };
+        if reply.full_information == 0 {
+            warn!("Remote peer {} has no full information about the channels", remote_peer);
+        }
+        if let Some(sync) = self.peers.get_mut(&remote_peer).and_then(|peer| peer.sync.as_mut()) {
+            sync.reply_received();
+        }
+        self.continue_sync(endpoints, remote_peer)
+    }
+
+    /// Requests the next batch of channels from the peer the graph is synced from
+    fn continue_sync(
+        &mut self,
+        endpoints: &mut Endpoints,
+        remote_peer: NodeAddr,
+    ) -> Result<(), Error> {
+        let chain_hash = self.graph.chain_hash();
+        let peer = match self.peers.get_mut(&remote_peer) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        let sync = match peer.sync {
+            Some(ref mut sync) => sync,
+            None => return Ok(()),
+        };
+        if let Some(query) = sync.next_query(chain_hash) {
+            debug!(
+                "Requesting {} channels from {}; {} channels left",
+                query.short_ids.len(),
+                remote_peer,
+                sync.remaining()
+            );
+            return self.send_peer(endpoints, &remote_peer, LnMsg::QueryShortChannelIds(query));
+        }
+        if sync.is_complete() {
+            info!("Network graph sync from {} is complete", remote_peer);
+            peer.sync = None;
+        }
+        Ok(())
+    }
+
+    fn receive_filter(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        filter: GossipTimestampFilter,
+    ) -> Result<(), Error> {
+        let remote_peer = match source {
+            ServiceId::Peer(remote_peer) => remote_peer,
+            _ => return Ok(()),
+        };
+        if filter.chain_hash != self.graph.chain_hash() {
+            return Ok(());
+        }
+        // Gossip already known is sent to the peer right away; the rest is relayed as usual
+        let end_timestamp = filter.first_timestamp as u64 + filter.timestamp_range as u64;
+        let backlog = self.graph.gossip_within(filter.first_timestamp, end_timestamp);
+        match self.peers.get_mut(&remote_peer) {
+            Some(peer) => peer.filter = Some(filter),
+            None => return Ok(()),
+        }
+        debug!("Sending {} gossip messages matching the filter of {}", backlog.len(), remote_peer);
+        for message in backlog {
+            self.send_peer(endpoints, &remote_peer, message)?;
+        }
+        Ok(())
+    }
+
+    fn send_peer(
+        &self,
+        endpoints: &mut Endpoints,
+        remote_peer: &NodeAddr,
+        message: LnMsg,
+    ) -> Result<(), Error> {
+        let peerd = ServiceId::Peer(remote_peer.clone());
+        endpoints.send_to(ServiceBus::Msg, self.identity(), peerd, BusMsg::Ln(message))?;
+        Ok(())
+    }
+
     fn compute_route(
         &mut self,
         endpoints: &mut Endpoints,
@@ -186,3 +851,25 @@ impl Runtime {
         Ok(route)
     }
 }
+
+/// Checks whether the queued gossip message is superseded by the new one
+fn supersedes(message: &LnMsg, queued: &LnMsg) -> bool {
+    match (message, queued) {
+        (LnMsg::ChannelUpdate(update), LnMsg::ChannelUpdate(queued)) => {
+            update.short_channel_id == queued.short_channel_id
+                && direction(update) == direction(queued)
+        }
+        (LnMsg::NodeAnnouncement(announcement), LnMsg::NodeAnnouncement(queued)) => {
+            announcement.node_id == queued.node_id
+        }
+        _ => false,
+    }
+}
+
+fn log_rejection(message: &str, subject: impl Display, source: &ServiceId, err: Rejection) {
+    match err {
+        // The same gossip is received from many peers
+        Rejection::Outdated => trace!("Ignoring known {} for {} from {}", message, subject, source),
+        err => debug!("Rejecting {} for {} from {}: {}", message, subject, source, err),
+    }
+}
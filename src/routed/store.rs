@@ -0,0 +1,196 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! On-disk store of the gossip accepted by the router.
+//!
+//! The store is a log of `channel_announcement`, `channel_update` and `node_announcement`
+//! messages appended once they are validated, so at start the router restores the network graph
+//! without validating the messages again. Each record is prefixed with its type and length. The
+//! channel announcement record is followed by the channel capacity, which is not a part of the
+//! message but is known from the funding output; closed channels are marked with a record of
+//! their own.
+//!
+//! Superseded updates and announcements, as well as the messages of the closed channels, remain
+//! in the log until the router compacts it, writing the graph to a new file.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use lightning_encoding::{LightningDecode, LightningEncode};
+use lnp::p2p::legacy::{
+    ChannelAnnouncement, ChannelUpdate, Messages as LnMsg, NodeAnnouncement, ShortChannelId,
+};
+
+use crate::Error;
+
+/// Version of the store format, written as the first byte of the store
+const STORE_VERSION: u8 = 1;
+
+// Gossip records use the types of the stored messages
+const CHANNEL_ANNOUNCEMENT: u16 = 256;
+const NODE_ANNOUNCEMENT: u16 = 257;
+const CHANNEL_UPDATE: u16 = 258;
+const CHANNEL_PRUNED: u16 = 4102;
+
+/// Record of the gossip store
+#[derive(Clone, Debug)]
+pub enum Record {
+    /// Announcement of the channel with its capacity in satoshis
+    Channel(ChannelAnnouncement, u64),
+
+    Update(ChannelUpdate),
+
+    Node(NodeAnnouncement),
+
+    /// Marks the channel which funding output is spent
+    Pruned(ShortChannelId),
+}
+
+impl Record {
+    /// Gossip message of the record, if any
+    pub fn to_message(&self) -> Option<LnMsg> {
+        match self {
+            Record::Channel(announcement, _) => {
+                Some(LnMsg::ChannelAnnouncement(announcement.clone()))
+            }
+            Record::Update(update) => Some(LnMsg::ChannelUpdate(update.clone())),
+            Record::Node(announcement) => Some(LnMsg::NodeAnnouncement(announcement.clone())),
+            Record::Pruned(_) => None,
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let (ty, data) = match self {
+            Record::Channel(announcement, capacity_sat) => {
+                let mut data = announcement.lightning_serialize();
+                if let Ok(ref mut data) = data {
+                    data.extend_from_slice(&capacity_sat.to_be_bytes());
+                }
+                (CHANNEL_ANNOUNCEMENT, data)
+            }
+            Record::Update(update) => (CHANNEL_UPDATE, update.lightning_serialize()),
+            Record::Node(announcement) => (NODE_ANNOUNCEMENT, announcement.lightning_serialize()),
+            Record::Pruned(short_channel_id) => {
+                (CHANNEL_PRUNED, short_channel_id.lightning_serialize())
+            }
+        };
+        let data = data.expect("in-memory encoding");
+        let mut record = Vec::with_capacity(data.len() + 4);
+        record.extend_from_slice(&ty.to_be_bytes());
+        // Lightning messages never exceed 65535 bytes
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend(data);
+        record
+    }
+
+    fn deserialize(ty: u16, data: &[u8]) -> Option<Record> {
+        match ty {
+            CHANNEL_ANNOUNCEMENT if data.len() > 8 => {
+                let (data, capacity) = data.split_at(data.len() - 8);
+                let mut capacity_sat = [0u8; 8];
+                capacity_sat.copy_from_slice(capacity);
+                let capacity_sat = u64::from_be_bytes(capacity_sat);
+                ChannelAnnouncement::lightning_deserialize(data)
+                    .ok()
+                    .map(|announcement| Record::Channel(announcement, capacity_sat))
+            }
+            CHANNEL_UPDATE => ChannelUpdate::lightning_deserialize(data).ok().map(Record::Update),
+            NODE_ANNOUNCEMENT => {
+                NodeAnnouncement::lightning_deserialize(data).ok().map(Record::Node)
+            }
+            CHANNEL_PRUNED => ShortChannelId::lightning_deserialize(data).ok().map(Record::Pruned),
+            _ => None,
+        }
+    }
+}
+
+/// Gossip store opened for appending the records
+pub struct GossipStore {
+    path: PathBuf,
+
+    file: fs::File,
+
+    /// Number of the records in the store
+    records: usize,
+}
+
+impl GossipStore {
+    /// Reads the records of the store. Incomplete record at the end of the store, which remains
+    /// if the node was terminated while writing it, is ignored.
+    pub fn load(path: &Path) -> Result<Vec<Record>, Error> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        match data.first() {
+            None => return Ok(vec![]),
+            Some(&STORE_VERSION) => {}
+            Some(version) => {
+                warn!("Gossip store has unknown format version {}; discarding it", version);
+                return Ok(vec![]);
+            }
+        }
+
+        let mut records = vec![];
+        let mut pos = 1;
+        while pos + 4 <= data.len() {
+            let ty = u16::from_be_bytes([data[pos], data[pos + 1]]);
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let end = pos + 4 + len;
+            if end > data.len() {
+                break;
+            }
+            match Record::deserialize(ty, &data[pos + 4..end]) {
+                Some(record) => records.push(record),
+                None => warn!("Skipping malformed gossip store record of type {}", ty),
+            }
+            pos = end;
+        }
+        if pos < data.len() {
+            warn!("Ignoring incomplete record at the end of the gossip store");
+        }
+        Ok(records)
+    }
+
+    /// Writes the records to a new store replacing the existing one
+    pub fn create(path: PathBuf, records: Vec<Record>) -> Result<GossipStore, Error> {
+        let mut data = vec![STORE_VERSION];
+        for record in &records {
+            data.extend(record.serialize());
+        }
+        // Router must never read a partially written store
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+        let file = fs::OpenOptions::new().append(true).open(&path)?;
+        Ok(GossipStore { path, file, records: records.len() })
+    }
+
+    /// Number of the records in the store
+    pub fn records(&self) -> usize { self.records }
+
+    pub fn append(&mut self, record: &Record) -> Result<(), Error> {
+        self.file.write_all(&record.serialize())?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Replaces the store content with the given records
+    pub fn compact(&mut self, records: Vec<Record>) -> Result<(), Error> {
+        *self = GossipStore::create(self.path.clone(), records)?;
+        Ok(())
+    }
+}
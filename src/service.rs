@@ -17,7 +17,7 @@ use microservices::esb;
 use microservices::node::TryService;
 
 use crate::bus::{self, BusMsg, CtlMsg, Report, ServiceBus};
-use crate::rpc::{Failure, ServiceId};
+use crate::rpc::{RpcError, ServiceId};
 use crate::{Config, Error};
 
 pub struct Service<Runtime>
@@ -98,6 +98,37 @@ where
 
 pub type Endpoints = esb::EndpointList<ServiceBus>;
 
+/// Handler for the controller sending messages from a daemon thread to its main runtime over the
+/// in-process loopback bridge
+pub struct BridgeHandler;
+
+impl esb::Handler<ServiceBus> for BridgeHandler {
+    type Request = BusMsg;
+    type Error = Error;
+
+    fn identity(&self) -> ServiceId { ServiceId::Loopback }
+
+    fn handle(
+        &mut self,
+        _: &mut Endpoints,
+        _: ServiceBus,
+        _: ServiceId,
+        _: BusMsg,
+    ) -> Result<(), Error> {
+        // Bridge does not receive replies for now
+        Ok(())
+    }
+
+    fn handle_err(
+        &mut self,
+        _: &mut Endpoints,
+        err: esb::Error<ServiceId>,
+    ) -> Result<(), Self::Error> {
+        // We simply propagate the error since it's already being reported
+        Err(err.into())
+    }
+}
+
 pub trait TryToServiceId {
     fn try_to_service_id(&self) -> Option<ServiceId>;
 }
@@ -164,8 +195,8 @@ where
         Ok(())
     }
 
-    fn report_failure(&mut self, endpoints: &mut Endpoints, failure: impl Into<Failure>) -> Error {
-        let failure = failure.into();
+    fn report_failure(&mut self, endpoints: &mut Endpoints, failure: impl Into<RpcError>) -> Error {
+        let failure = failure.into().with_source(&self.identity());
         if let Some(client) = self.enquirer() {
             let status = bus::Status::Failure(failure.clone());
             let report = CtlMsg::Report(Report { client, status });
@@ -199,18 +230,33 @@ where
         client_id: ClientId,
         message: impl Into<RpcMsg>,
     ) -> Result<(), esb::Error<ServiceId>> {
+        let message = match message.into() {
+            RpcMsg::Failure(failure) => RpcMsg::Failure(failure.with_source(&self.identity())),
+            message => message,
+        };
         endpoints.send_to(
             ServiceBus::Rpc,
             self.identity(),
             ServiceId::Client(client_id),
-            BusMsg::Rpc(message.into()),
+            BusMsg::Rpc(message),
         )
     }
+
+    /// Replies the client with the failure to authorize its request
+    fn reject_rpc(
+        &self,
+        endpoints: &mut Endpoints,
+        client_id: ClientId,
+        err: AuthError,
+    ) -> Result<(), esb::Error<ServiceId>> {
+        warn!("Rejecting request from client {}: {}", client_id, err);
+        self.send_rpc(endpoints, client_id, RpcMsg::Failure(err.into()))
+    }
 }
 
 // TODO: Move to LNP/BP Services library
 use colored::Colorize;
-use lnp_rpc::{ClientId, RpcMsg};
+use lnp_rpc::{AuthError, ClientId, RpcMsg};
 
 pub trait LogStyle: ToString {
     fn promo(&self) -> colored::ColoredString { self.to_string().bold().bright_blue() }
@@ -0,0 +1,257 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Hardware wallets signing the funding wallet inputs.
+//!
+//! Channel keys are always held by the software signer, while the funding wallet may be created
+//! from a hardware wallet account with `lnpd init --funding-descriptor`. Inputs of the PSBTs sent
+//! to signd with [`CtlMsg::Sign`] which remain unsigned by the software signer are signed by the
+//! hardware wallet, and its signatures are merged into the PSBT before replying.
+//!
+//! Since the user has to confirm each transaction on the device, signing runs in a separate
+//! thread, and signd keeps serving the channel daemons meanwhile. Signing which is not confirmed
+//! within the configured timeout is cancelled, and the requesting daemon receives
+//! [`CtlMsg::SignFailed`].
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::Txid;
+use lnpbp::chain::Chain;
+use microservices::esb;
+use psbt::Psbt;
+use serde_json::Value;
+
+use crate::bus::{BusMsg, CtlMsg, ServiceBus};
+use crate::rpc::ServiceId;
+use crate::service::BridgeHandler;
+
+/// Interval between the checks whether HWI has completed
+const HWI_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors signing with a hardware wallet
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum HardwareError {
+    /// unable to run hardware wallet interface: {0}
+    Launch(String),
+
+    /// transaction is not confirmed on the hardware wallet within {0:?}; signing is cancelled
+    Timeout(Duration),
+
+    /// hardware wallet has not signed the transaction: {0}
+    Rejected(String),
+
+    /// hardware wallet has returned invalid reply: {0}
+    InvalidReply(String),
+}
+
+/// Backend signing PSBTs with the keys held by a hardware device
+pub trait HardwareSigner: Send {
+    /// Name of the backend used in the logs
+    fn name(&self) -> &'static str;
+
+    /// Signs PSBT inputs with the device keys, prompting the user to confirm the transaction on
+    /// the device. Must give up and cancel the prompt once the timeout expires.
+    fn sign(&self, psbt: &Psbt, timeout: Duration) -> Result<Psbt, HardwareError>;
+}
+
+/// Hardware wallet accessed through `hwi` command-line tool of HWI project
+pub struct Hwi {
+    /// Path to `hwi` executable
+    path: PathBuf,
+
+    /// Chain name as used by HWI
+    chain: &'static str,
+}
+
+impl Hwi {
+    pub fn with(path: PathBuf, chain: &Chain) -> Hwi {
+        let chain = match chain {
+            Chain::Mainnet => "main",
+            Chain::Regtest(_) => "regtest",
+            Chain::Signet | Chain::SignetCustom(_) => "signet",
+            _ => "test",
+        };
+        Hwi { path, chain }
+    }
+}
+
+impl HardwareSigner for Hwi {
+    fn name(&self) -> &'static str { "HWI" }
+
+    fn sign(&self, psbt: &Psbt, timeout: Duration) -> Result<Psbt, HardwareError> {
+        let mut command = Command::new(&self.path);
+        command.args(&["--chain", self.chain]);
+        // Selects the device holding the keys if several devices are connected
+        if let Some(fingerprint) = device_inputs(psbt)
+            .flat_map(|input| input.bip32_derivation.values())
+            .map(|(fingerprint, _)| fingerprint)
+            .next()
+        {
+            command.args(&["--fingerprint", &fingerprint.to_string()]);
+        }
+        command
+            .args(&["signtx", &(**psbt).to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        let mut child = command.spawn().map_err(|err| HardwareError::Launch(err.to_string()))?;
+        let mut stdout = child.stdout.take().expect("HWI output is piped");
+        // Output is read concurrently, so HWI never blocks on a full pipe
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stdout.read_to_string(&mut output);
+            output
+        });
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break,
+                Ok(None) if Instant::now() >= deadline => {
+                    // Terminating HWI closes its connection to the device, which aborts the
+                    // confirmation prompt
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(HardwareError::Timeout(timeout));
+                }
+                Ok(None) => thread::sleep(HWI_POLL_INTERVAL),
+                Err(err) => return Err(HardwareError::Launch(err.to_string())),
+            }
+        }
+        let output = reader.join().unwrap_or_default();
+
+        let reply: Value = serde_json::from_str(&output)
+            .map_err(|_| HardwareError::InvalidReply(output.trim().to_owned()))?;
+        if let Some(error) = reply.get("error").and_then(Value::as_str) {
+            return Err(HardwareError::Rejected(error.to_owned()));
+        }
+        let signed = reply
+            .get("psbt")
+            .and_then(Value::as_str)
+            .ok_or_else(|| HardwareError::InvalidReply(s!("PSBT is absent")))?;
+        PartiallySignedTransaction::from_str(signed)
+            .map(Psbt::from)
+            .map_err(|err| HardwareError::InvalidReply(err.to_string()))
+    }
+}
+
+/// Inputs of the PSBT which are left for the hardware wallet: the inputs with key derivations
+/// which have neither signatures nor final witness
+fn device_inputs(psbt: &Psbt) -> impl Iterator<Item = &bitcoin::util::psbt::Input> {
+    psbt.inputs.iter().filter(|input| {
+        !input.bip32_derivation.is_empty()
+            && input.partial_sigs.is_empty()
+            && input.final_script_sig.is_none()
+            && input.final_script_witness.is_none()
+    })
+}
+
+/// Checks whether the PSBT signed by the software signer still has inputs to be signed by the
+/// hardware wallet
+pub fn requires_device(psbt: &Psbt) -> bool { device_inputs(psbt).next().is_some() }
+
+/// Client of the thread signing PSBTs with the hardware wallet one by one
+pub struct HardwareClient {
+    jobs: mpsc::Sender<Psbt>,
+
+    completed: mpsc::Receiver<(Txid, Result<Psbt, HardwareError>)>,
+
+    /// PSBTs signed by the software signer which are being signed by the hardware wallet, with
+    /// the services which have requested the signature
+    signing: HashMap<Txid, (ServiceId, Psbt)>,
+}
+
+impl HardwareClient {
+    /// Starts the signing thread, which wakes up the runtime through the bridge with
+    /// [`CtlMsg::Timeout`] each time it completes signing
+    pub fn spawn(
+        signer: Box<dyn HardwareSigner>,
+        timeout: Duration,
+        mut bridge: esb::Controller<ServiceBus, BusMsg, BridgeHandler>,
+    ) -> HardwareClient {
+        let (jobs, job_receiver) = mpsc::channel::<Psbt>();
+        let (completion, completed) = mpsc::channel();
+        thread::spawn(move || {
+            for psbt in job_receiver {
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("Awaiting confirmation of transaction {} on {} device", txid, signer.name());
+                let _ = completion.send((txid, signer.sign(&psbt, timeout)));
+                let message = BusMsg::Ctl(CtlMsg::Timeout);
+                if let Err(err) = bridge.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+                    error!("Hardware signing thread is unable to reach the runtime: {}", err);
+                }
+            }
+        });
+        HardwareClient { jobs, completed, signing: empty!() }
+    }
+
+    /// Sends the PSBT signed by the software signer to the hardware wallet
+    pub fn sign(&mut self, source: ServiceId, psbt: Psbt) {
+        let txid = psbt.global.unsigned_tx.txid();
+        // The thread lives as long as the client, so it always receives the job
+        let _ = self.jobs.send(psbt.clone());
+        self.signing.insert(txid, (source, psbt));
+    }
+
+    /// Merges signatures made by the hardware wallet. Returns replies to the services which
+    /// have requested the signatures.
+    pub fn complete(&mut self) -> Vec<(ServiceId, CtlMsg)> {
+        let mut replies = vec![];
+        for (txid, result) in self.completed.try_iter() {
+            let (source, mut psbt) = match self.signing.remove(&txid) {
+                Some(signing) => signing,
+                None => continue,
+            };
+            let result = result.and_then(|signed| {
+                if signed.global.unsigned_tx.txid() != txid {
+                    return Err(HardwareError::InvalidReply(s!("transaction has changed")));
+                }
+                let mut sig_count = 0usize;
+                for (input, signed) in psbt.inputs.iter_mut().zip(signed.inputs.iter()) {
+                    if !input.partial_sigs.is_empty() || input.final_script_witness.is_some() {
+                        continue;
+                    }
+                    sig_count += signed.partial_sigs.len();
+                    input.partial_sigs.extend(signed.partial_sigs.clone());
+                }
+                Ok(sig_count)
+            });
+            let message = match result {
+                Ok(sig_count) => {
+                    info!(
+                        "Transaction {} is signed by hardware wallet ({} signatures)",
+                        txid, sig_count
+                    );
+                    CtlMsg::Signed(psbt)
+                }
+                Err(err) => {
+                    warn!("Transaction {} is not signed by hardware wallet: {}", txid, err);
+                    CtlMsg::SignFailed { txid, error: err.to_string() }
+                }
+            };
+            replies.push((source, message));
+        }
+        replies
+    }
+}
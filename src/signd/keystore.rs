@@ -0,0 +1,133 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Encryption of the master key file at rest.
+//!
+//! Encrypted file starts with [`KEYSTORE_MAGIC`], followed by the salt and the nonce, and the
+//! strict-encoded signing account encrypted with ChaCha20-Poly1305. The encryption key is derived
+//! from the passphrase with Argon2id using the default parameters of `argon2` crate.
+//!
+//! Files lacking the magic are plaintext master key files created by the previous node versions.
+//! They are still read without a passphrase, and are encrypted by re-running `lnpd init`.
+
+use std::fs;
+use std::path::Path;
+
+use argon2::Argon2;
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use psbt::sign::MemorySigningAccount;
+
+/// Prefix of the encrypted master key file, with the version of the encryption format
+pub const KEYSTORE_MAGIC: &[u8; 8] = b"LNPKEY\x00\x01";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Errors accessing the encrypted master key file
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// encrypted master key file is corrupted
+    Corrupted,
+
+    /// passphrase does not match the one used to encrypt the master key
+    Passphrase,
+
+    /// unable to derive encryption key from the passphrase: {0}
+    KeyDerivation(String),
+}
+
+/// Checks whether the master key file data are encrypted
+pub fn is_encrypted(data: &[u8]) -> bool { data.starts_with(KEYSTORE_MAGIC) }
+
+/// Encrypts the data with the key derived from the passphrase using a random salt
+pub fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = cipher(passphrase, &salt)?;
+    let ciphertext =
+        cipher.encrypt(Nonce::from_slice(&nonce), data).map_err(|_| Error::Corrupted)?;
+
+    let mut encrypted =
+        Vec::with_capacity(KEYSTORE_MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    encrypted.extend_from_slice(KEYSTORE_MAGIC);
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+/// Decrypts the data encrypted with [`encrypt`]
+pub fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if !is_encrypted(encrypted) || encrypted.len() < KEYSTORE_MAGIC.len() + SALT_LEN + NONCE_LEN {
+        return Err(Error::Corrupted);
+    }
+    let (salt, rest) = encrypted[KEYSTORE_MAGIC.len()..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    // Authentication of the ciphertext fails both for the wrong key and the modified data, and
+    // the wrong passphrase is much more likely
+    cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Passphrase)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| Error::KeyDerivation(err.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Reads master key file, decrypting it with the passphrase. Fails with
+/// [`crate::Error::SignerLocked`] if the file is encrypted and no passphrase is given.
+pub fn read_signing_account<C: Signing>(
+    secp: &Secp256k1<C>,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> Result<MemorySigningAccount, crate::Error> {
+    let data = fs::read(path)?;
+    let data = match (is_encrypted(&data), passphrase) {
+        (false, _) => data,
+        (true, None) => return Err(crate::Error::SignerLocked),
+        (true, Some(passphrase)) => decrypt(&data, passphrase)?,
+    };
+    Ok(MemorySigningAccount::read(secp, data.as_slice())?)
+}
+
+/// Reads the passphrase from the file, ignoring the trailing line break
+pub fn read_passphrase_file(path: &Path) -> Result<String, crate::Error> {
+    let passphrase = fs::read_to_string(path)?;
+    Ok(passphrase.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+/// Writes master key file encrypted with the passphrase. The file is replaced atomically, so
+/// the plaintext file being encrypted is never lost.
+pub fn write_signing_account(
+    signing_account: &MemorySigningAccount,
+    path: &Path,
+    passphrase: &str,
+) -> Result<(), crate::Error> {
+    let mut data = vec![];
+    signing_account.write(&mut data)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, encrypt(&data, passphrase)?)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
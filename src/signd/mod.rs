@@ -12,10 +12,17 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+pub mod hardware;
+pub mod keystore;
 #[cfg(feature = "server")]
 mod opts;
+mod policy;
+pub mod remote;
 mod runtime;
+mod signer;
 
 #[cfg(feature = "server")]
 pub use opts::Opts;
+pub use policy::{PolicyError, SigningPolicy};
+pub use remote::serve;
 pub use runtime::run;
@@ -12,12 +12,32 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::net::SocketAddr;
+
+use bitcoin::secp256k1::PublicKey;
+
+use crate::peerd::KeyOpts;
+
 /// Lightning peer network channel daemon; part of LNP Node.
 ///
 /// The daemon is controlled though RPC socket (see `rpc-socket`).
 #[derive(Parser, Clone, PartialEq, Eq, Debug)]
 #[clap(name = "signd", bin_name = "signd", author, version)]
 pub struct Opts {
+    /// Node key configuration
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    /// Run as a remote signer serving the signing requests of the nodes at the given address,
+    /// instead of serving the local node daemons. The node key given with `--key-file`
+    /// authenticates the signer to the nodes.
+    #[clap(long)]
+    pub serve: Option<SocketAddr>,
+
+    /// Node id of a node allowed to use the remote signer. May be repeated.
+    #[clap(long = "allow-client", requires = "serve")]
+    pub allow_clients: Vec<PublicKey>,
+
     /// These params can be read also from the configuration file, not just
     /// command-line args or environment variables
     #[clap(flatten)]
@@ -25,5 +45,8 @@ pub struct Opts {
 }
 
 impl Opts {
-    pub fn process(&mut self) { self.shared.process() }
+    pub fn process(&mut self) {
+        self.shared.process();
+        self.key_opts.process(&self.shared);
+    }
 }
@@ -0,0 +1,281 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Signing policy checked by signd before signing transactions.
+//!
+//! Signer adds signatures to all PSBT inputs it has keys for, so each transaction is checked
+//! according to the kind of the request:
+//! - funding transactions, sent by lnpd with [`CtlMsg::Sign`], may pay only to the funding wallet
+//!   and to the channel funding outputs registered in advance by channeld with
+//!   [`CtlMsg::ExpectFunding`], with exactly the registered amounts;
+//! - channel transactions (refund, commitment, closing and CPFP transactions), sent by channeld
+//!   with [`CtlMsg::Sign`], may spend only the registered channel funding outputs of the
+//!   registered capacity, and may not send outside of the funding wallet more than they spend
+//!   from the channel funding outputs;
+//! - second-stage HTLC transactions, sent by channeld with [`CtlMsg::SignHtlc`], may not send
+//!   outside of the funding wallet more than they spend from the channel HTLC outputs, such that
+//!   the funding wallet inputs added to pay their fee return to the wallet;
+//! - withdrawals, sent by lnpd with [`CtlMsg::SignWithdrawal`], exceeding the withdrawal limit
+//!   must be confirmed with the token from [`LNP_NODE_WITHDRAWAL_TOKEN_FILE`].
+//!
+//! Outputs of the funding wallet are recognized by their BIP32 derivation information, which the
+//! funding wallet provides for its change outputs. Registered channel fundings are not persisted:
+//! channeld registers its funding again each time it is restarted.
+//!
+//! [`CtlMsg::Sign`]: crate::bus::CtlMsg::Sign
+//! [`CtlMsg::ExpectFunding`]: crate::bus::CtlMsg::ExpectFunding
+//! [`CtlMsg::SignHtlc`]: crate::bus::CtlMsg::SignHtlc
+//! [`CtlMsg::SignWithdrawal`]: crate::bus::CtlMsg::SignWithdrawal
+
+use std::collections::HashMap;
+use std::fs;
+
+use amplify::hex::ToHex;
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2};
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::Script;
+use lnp_rpc::{ErrorCode, RpcError, ToRpcError};
+use psbt::Psbt;
+use wallet::scripts::PubkeyScript;
+
+use crate::auth::write_secret;
+use crate::opts::LNP_NODE_WITHDRAWAL_TOKEN_FILE;
+use crate::{Config, Error, LogStyle};
+
+/// Violations of the signing policy
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, NetworkEncode, NetworkDecode)]
+#[display(doc_comments)]
+pub enum PolicyError {
+    /// funding transaction output #{vout} pays to {script_pubkey}, which is not registered as a
+    /// channel funding output
+    UnexpectedOutput { vout: u16, script_pubkey: PubkeyScript },
+
+    /// funding transaction output #{vout} pays {amount} sat to the channel funding output, while
+    /// the channel is registered with {expected} sat
+    FundingAmount { vout: u16, amount: u64, expected: u64 },
+
+    /// transaction input #{vin} spends channel funding output which is not registered
+    UnknownFunding { vin: u16 },
+
+    /// transaction input #{vin} spends {amount} sat from the channel funding output, while the
+    /// channel is registered with {expected} sat
+    ChannelCapacity { vin: u16, amount: u64, expected: u64 },
+
+    /// transaction sends {amount} sat outside of the funding wallet, while it spends only
+    /// {permitted} sat from the channel funding outputs
+    ExternalPayout { amount: u64, permitted: u64 },
+
+    /// withdrawal of {amount} sat exceeds the limit of {limit} sat and must be confirmed with the
+    /// withdrawal token
+    ConfirmationRequired { amount: u64, limit: u64 },
+
+    /// withdrawal confirmation token is invalid
+    InvalidConfirmation,
+}
+
+impl PolicyError {
+    /// Name of the violated policy rule, reported to the clients within the failure details
+    pub fn rule(&self) -> &'static str {
+        match self {
+            PolicyError::UnexpectedOutput { .. } | PolicyError::FundingAmount { .. } => {
+                "expected_funding"
+            }
+            PolicyError::UnknownFunding { .. } | PolicyError::ChannelCapacity { .. } => {
+                "channel_funding"
+            }
+            PolicyError::ExternalPayout { .. } => "external_payout",
+            PolicyError::ConfirmationRequired { .. } | PolicyError::InvalidConfirmation => {
+                "withdrawal_limit"
+            }
+        }
+    }
+}
+
+impl ToRpcError for PolicyError {
+    fn error_code(&self) -> ErrorCode { ErrorCode::SignerPolicy }
+
+    fn to_rpc_error(&self) -> RpcError {
+        RpcError::new(self.error_code(), self).with_detail("rule", self.rule())
+    }
+}
+
+/// Signing policy of signd
+pub struct SigningPolicy {
+    /// Channel funding outputs registered by channeld, with the channel capacity
+    fundings: HashMap<PubkeyScript, u64>,
+
+    /// Amount above which withdrawals require confirmation token
+    withdrawal_limit: Option<u64>,
+
+    /// Token confirming withdrawals above the limit
+    withdrawal_token: Option<String>,
+}
+
+impl SigningPolicy {
+    /// Constructs policy from the node configuration. If the withdrawal limit is set, reads the
+    /// withdrawal confirmation token from the data directory, creating it on the first start.
+    pub fn with(config: &Config) -> Result<SigningPolicy, Error> {
+        let withdrawal_token = match config.withdrawal_limit {
+            None => None,
+            Some(limit) => {
+                let token_path = config.data_dir.join(LNP_NODE_WITHDRAWAL_TOKEN_FILE);
+                if !token_path.exists() {
+                    let mut token = [0u8; 32];
+                    rand::thread_rng().fill_bytes(&mut token);
+                    write_secret(&token_path, token.to_hex().as_bytes())?;
+                    info!(
+                        "{} withdrawal confirmation token to '{}'",
+                        "Saved".ended(),
+                        token_path.display()
+                    );
+                }
+                info!("Withdrawals above {} sat require confirmation token", limit);
+                Some(fs::read_to_string(&token_path)?.trim().to_owned())
+            }
+        };
+        Ok(SigningPolicy {
+            fundings: empty!(),
+            withdrawal_limit: config.withdrawal_limit,
+            withdrawal_token,
+        })
+    }
+
+    /// Registers channel funding output
+    pub fn expect_funding(&mut self, script_pubkey: PubkeyScript, amount: u64) {
+        debug!("Channel funding of {} sat to {} is registered", amount, script_pubkey);
+        self.fundings.insert(script_pubkey, amount);
+    }
+
+    /// Checks that the funding transaction pays only to the funding wallet and to the registered
+    /// channel funding outputs
+    pub fn check_funding(&self, psbt: &Psbt) -> Result<(), PolicyError> {
+        let tx = &psbt.global.unsigned_tx;
+        for (vout, (txout, output)) in tx.output.iter().zip(&psbt.outputs).enumerate() {
+            if is_wallet_output(output) {
+                continue;
+            }
+            let vout = vout as u16;
+            let script_pubkey = PubkeyScript::from(txout.script_pubkey.clone());
+            match self.fundings.get(&script_pubkey) {
+                None => return Err(PolicyError::UnexpectedOutput { vout, script_pubkey }),
+                Some(expected) if *expected != txout.value => {
+                    let amount = txout.value;
+                    return Err(PolicyError::FundingAmount { vout, amount, expected: *expected });
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the channel transaction spends only the registered channel funding outputs
+    /// and does not send the funding wallet funds outside of the wallet
+    pub fn check_channel_tx(&self, psbt: &Psbt) -> Result<(), PolicyError> {
+        let mut permitted = 0u64;
+        for (vin, input) in psbt.inputs.iter().enumerate() {
+            let (witness_script, prevout) = match (&input.witness_script, &input.witness_utxo) {
+                (Some(witness_script), Some(prevout)) if is_funding_script(witness_script) => {
+                    (witness_script, prevout)
+                }
+                _ => continue,
+            };
+            let vin = vin as u16;
+            let script_pubkey = witness_script.to_v0_p2wsh();
+            let expected = match self.fundings.get(&PubkeyScript::from(script_pubkey.clone())) {
+                Some(expected) if prevout.script_pubkey == script_pubkey => *expected,
+                _ => return Err(PolicyError::UnknownFunding { vin }),
+            };
+            if prevout.value != expected {
+                let amount = prevout.value;
+                return Err(PolicyError::ChannelCapacity { vin, amount, expected });
+            }
+            permitted += prevout.value;
+        }
+
+        let amount = external_value(psbt);
+        if amount > permitted {
+            return Err(PolicyError::ExternalPayout { amount, permitted });
+        }
+        Ok(())
+    }
+
+    /// Checks that the second-stage HTLC transaction does not send the funding wallet funds
+    /// outside of the wallet. Channel inputs are recognized by their witness script; signature
+    /// of the channel input commits to its value, so the value can't be inflated.
+    pub fn check_htlc_tx(&self, psbt: &Psbt) -> Result<(), PolicyError> {
+        let permitted = psbt
+            .inputs
+            .iter()
+            .filter(|input| input.witness_script.is_some())
+            .filter_map(|input| input.witness_utxo.as_ref())
+            .map(|prevout| prevout.value)
+            .sum();
+        let amount = external_value(psbt);
+        if amount > permitted {
+            return Err(PolicyError::ExternalPayout { amount, permitted });
+        }
+        Ok(())
+    }
+
+    /// Checks that the withdrawal exceeding the withdrawal limit is confirmed with the token
+    pub fn check_withdrawal(
+        &self,
+        psbt: &Psbt,
+        confirmation: Option<&str>,
+    ) -> Result<(), PolicyError> {
+        let limit = match self.withdrawal_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let amount = external_value(psbt);
+        match confirmation {
+            _ if amount <= limit => Ok(()),
+            None => Err(PolicyError::ConfirmationRequired { amount, limit }),
+            Some(token) if Some(token) == self.withdrawal_token.as_deref() => {
+                info!("Withdrawal of {} sat above the limit is confirmed with the token", amount);
+                Ok(())
+            }
+            Some(_) => Err(PolicyError::InvalidConfirmation),
+        }
+    }
+}
+
+/// Checks whether the output belongs to the funding wallet, which provides derivation
+/// information for its outputs
+fn is_wallet_output(output: &bitcoin::util::psbt::Output) -> bool {
+    !output.bip32_derivation.is_empty()
+}
+
+/// Checks whether the script is 2-of-2 multisig script of the channel funding output
+fn is_funding_script(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    bytes.len() == 71
+        && bytes[0] == OP_PUSHNUM_2.into_u8()
+        && bytes[1] == 33
+        && bytes[35] == 33
+        && bytes[69] == OP_PUSHNUM_2.into_u8()
+        && bytes[70] == OP_CHECKMULTISIG.into_u8()
+}
+
+/// Total amount sent by the transaction to the outputs not belonging to the funding wallet
+fn external_value(psbt: &Psbt) -> u64 {
+    psbt.global
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(&psbt.outputs)
+        .filter(|(_, output)| !is_wallet_output(output))
+        .map(|(txout, _)| txout.value)
+        .sum()
+}
@@ -0,0 +1,306 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Protocol between the node signd and the remote signer keeping the channel keys on a separate
+//! host.
+//!
+//! The remote signer is signd started with `--serve` option. The node signd, configured with
+//! `--signer remote:<node_id>@<host>:<port>`, holds no channel keys and relays each signing
+//! request to the remote signer over a new BOLT-8 connection, which authenticates both sides with
+//! their node keys. The remote signer serves only the nodes which ids are given with
+//! `--allow-client` option.
+//!
+//! Each connection starts with the exchange of [`SignerRequest::Hello`] and [`SignerReply::Hello`]
+//! messages carrying the protocol version of the sides, and the connection is closed unless the
+//! versions match. Requests and replies are strict-encoded and sent as BOLT-8 messages; failed
+//! requests are replied with [`SignerReply::Failure`]. Per-commitment points are not requested
+//! separately: the first one is delivered within the derived channel keyset, from which channeld
+//! computes the subsequent ones.
+
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use internet2::addr::InetSocketAddr;
+use internet2::{LocalNode, RemoteNodeAddr, RemoteSocketAddr};
+use lnp::channel::bolt::{LocalKeyset, LocalPubkey};
+use lnp::p2p::legacy::ChannelAnnouncement;
+use microservices::peer::{PeerConnection, RecvMessage, SendMessage};
+use psbt::Psbt;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::keystore;
+use super::signer::Signer;
+use crate::peerd::supervisor::{accept_handshake, handshake, read_node_key_file};
+use crate::Config;
+
+/// Version of the remote signer protocol. Must be increased with any change to the encoding of
+/// [`SignerRequest`] and [`SignerReply`].
+pub const SIGNER_PROTOCOL_VERSION: u16 = 4;
+
+/// Time within which the remote signer must accept the connection, complete BOLT-8 handshake and
+/// reply to each request. Requests failing to complete in time are reported as failed, so the
+/// channels never wait for an unavailable signer indefinitely.
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors of the requests to the remote signer
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// remote signer {0} is unavailable; the request may be retried once the signer is back
+    /// online. Details: {1}
+    Unavailable(RemoteNodeAddr, String),
+
+    /// remote signer uses protocol version {0}, while version {1} is required
+    Version(u16, u16),
+
+    /// remote signer has failed the request: {0}
+    Failed(String),
+
+    /// remote signer has sent unexpected reply {0}
+    UnexpectedReply(String),
+}
+
+/// Request sent to the remote signer
+#[derive(Clone, Debug, Display, NetworkEncode, NetworkDecode)]
+pub enum SignerRequest {
+    /// Protocol version used by the node; must be the first request sent over the connection
+    #[display("hello({0})")]
+    Hello(u16),
+
+    /// Derives keyset for the channel with the given index
+    #[display("derive_channel_keys({0})")]
+    DeriveChannelKeys(u32),
+
+    /// Derives per-commitment point of the local commitment transaction with the given number
+    /// for the channel with the given index
+    #[display("commitment_point({index}, {commitment_number})")]
+    CommitmentPoint { index: u32, commitment_number: u64 },
+
+    /// Reveals per-commitment secret of the revoked local commitment transaction with the given
+    /// number for the channel with the given index
+    #[display("commitment_secret({index}, {commitment_number})")]
+    CommitmentSecret { index: u32, commitment_number: u64 },
+
+    /// Signs PSBT inputs spending outputs controlled by the signer keys
+    #[display("sign_psbt(...)")]
+    SignPsbt(Psbt),
+
+    /// Signs penalty transaction spending revoked remote commitment transaction
+    #[display("sign_penalty(...)")]
+    SignPenalty { psbt: Psbt, per_commitment_secret: SecretKey },
+
+    /// Signs transaction sweeping delayed outputs of our own commitment transaction
+    #[display("sign_delayed(...)")]
+    SignDelayed { psbt: Psbt, per_commitment_point: PublicKey },
+
+    /// Signs second-stage HTLC transactions of our own commitment transaction
+    #[display("sign_htlc(...)")]
+    SignHtlc { psbt: Psbt, per_commitment_point: PublicKey },
+
+    /// Signs transaction sweeping our output of the remote commitment transaction
+    #[display("sign_to_remote(...)")]
+    SignToRemote { psbt: Psbt, per_commitment_point: Option<PublicKey> },
+
+    /// Signs channel announcement gossip message with the channel funding key
+    #[display("sign_announcement(...)")]
+    SignAnnouncement { announcement: ChannelAnnouncement, funding_key: LocalPubkey },
+}
+
+/// Reply of the remote signer
+#[derive(Clone, Debug, Display, NetworkEncode, NetworkDecode)]
+pub enum SignerReply {
+    /// Protocol version used by the signer
+    #[display("hello({0})")]
+    Hello(u16),
+
+    #[display("keyset(...)")]
+    Keyset(LocalKeyset),
+
+    #[display("commitment_point({commitment_number}, {point})")]
+    CommitmentPoint { commitment_number: u64, point: PublicKey },
+
+    /// Per-commitment secret of the revoked commitment, together with the per-commitment point of
+    /// the commitment following the next one
+    #[display("commitment_secret({commitment_number}, ...)")]
+    CommitmentSecret { commitment_number: u64, secret: SecretKey, next_point: PublicKey },
+
+    #[display("signed(...)")]
+    Signed(Psbt),
+
+    #[display("announcement_signed(...)")]
+    AnnouncementSigned(ChannelAnnouncement),
+
+    /// Request has failed, with the failure description
+    #[display("failure({0})")]
+    Failure(String),
+}
+
+/// Client relaying signing requests to the remote signer
+pub struct RemoteSigner {
+    /// Node key authenticating the node to the remote signer
+    local_node: LocalNode,
+
+    remote: RemoteNodeAddr,
+}
+
+impl RemoteSigner {
+    pub fn with(local_node: LocalNode, remote: RemoteNodeAddr) -> RemoteSigner {
+        RemoteSigner { local_node, remote }
+    }
+
+    /// Sends the request to the remote signer over a new connection and awaits for the reply.
+    /// Fails with [`Error::Unavailable`] if the signer can't be reached or does not reply in time.
+    pub fn request(&self, request: SignerRequest) -> Result<SignerReply, Error> {
+        let unavailable = |err: String| Error::Unavailable(self.remote.clone(), err);
+        let mut connection = self.connect().map_err(|err| unavailable(err.to_string()))?;
+        let hello = SignerRequest::Hello(SIGNER_PROTOCOL_VERSION);
+        match exchange(&mut connection, &hello).map_err(unavailable)? {
+            SignerReply::Hello(SIGNER_PROTOCOL_VERSION) => {}
+            SignerReply::Hello(version) => {
+                return Err(Error::Version(version, SIGNER_PROTOCOL_VERSION))
+            }
+            SignerReply::Failure(err) => return Err(Error::Failed(err)),
+            reply => return Err(Error::UnexpectedReply(reply.to_string())),
+        }
+        match exchange(&mut connection, &request).map_err(unavailable)? {
+            SignerReply::Failure(err) => Err(Error::Failed(err)),
+            reply => Ok(reply),
+        }
+    }
+
+    fn connect(&self) -> Result<PeerConnection, crate::Error> {
+        let inet_addr = match self.remote.remote_addr {
+            RemoteSocketAddr::Ftcp(inet_addr) => inet_addr,
+            _ => return Err(crate::Error::Other(s!("remote signer must use TCP address"))),
+        };
+        let socket_addr = SocketAddr::try_from(inet_addr)
+            .map_err(|_| crate::Error::Other(s!("remote signer must use IP address")))?;
+        let stream = TcpStream::connect_timeout(&socket_addr, SIGNER_TIMEOUT)?;
+        let (connection, stream) =
+            handshake(stream, inet_addr, self.remote.node_id, &self.local_node, SIGNER_TIMEOUT)?;
+        stream.set_read_timeout(Some(SIGNER_TIMEOUT))?;
+        Ok(connection)
+    }
+}
+
+fn exchange(
+    connection: &mut PeerConnection,
+    request: &SignerRequest,
+) -> Result<SignerReply, String> {
+    let data = request.strict_serialize().expect("in-memory encoding");
+    connection.send_raw_message(&data).map_err(|err| err.to_string())?;
+    let reply = connection.recv_raw_message().map_err(|err| err.to_string())?;
+    SignerReply::strict_deserialize(&reply).map_err(|err| err.to_string())
+}
+
+/// Runs the remote signer, serving the signing requests of the nodes with the given ids at the
+/// listening address. Connections are served one by one.
+pub fn serve(
+    config: Config,
+    key_file: &Path,
+    listen: SocketAddr,
+    clients: Vec<PublicKey>,
+) -> Result<(), crate::Error> {
+    let secp = Secp256k1::new();
+    let passphrase = match config.unlock_file {
+        Some(ref path) => Some(keystore::read_passphrase_file(path)?),
+        None => None,
+    };
+    let signer = Signer::with(&secp, &config, passphrase.as_deref()).map_err(|err| match err {
+        crate::Error::SignerLocked => crate::Error::Other(s!(
+            "master key is encrypted; please provide the passphrase with --unlock-file option"
+        )),
+        err => err,
+    })?;
+    let local_node = read_node_key_file(key_file);
+    if clients.is_empty() {
+        warn!("No clients are allowed with --allow-client; all requests will be refused");
+    }
+
+    let listener = TcpListener::bind(listen)?;
+    info!("Remote signer {} is listening on {}", local_node.node_id(), listen);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Unable to accept signer client connection: {}", err);
+                continue;
+            }
+        };
+        let inet_addr = match stream.peer_addr() {
+            Ok(addr) => InetSocketAddr::from(addr),
+            Err(_) => continue,
+        };
+        match serve_client(&signer, &local_node, &clients, stream, inet_addr) {
+            Ok(()) => debug!("Signer client {} has disconnected", inet_addr),
+            Err(err) => warn!("Connection with signer client {} has failed: {}", inet_addr, err),
+        }
+    }
+    Ok(())
+}
+
+fn serve_client(
+    signer: &Signer,
+    local_node: &LocalNode,
+    clients: &[PublicKey],
+    stream: TcpStream,
+    inet_addr: InetSocketAddr,
+) -> Result<(), crate::Error> {
+    let (mut connection, stream, remote_key) =
+        accept_handshake(stream, inet_addr, local_node, SIGNER_TIMEOUT)?;
+    // Connections are not kept open by the clients, so the idle ones are abandoned
+    stream.set_read_timeout(Some(SIGNER_TIMEOUT))?;
+
+    let mut negotiated = false;
+    loop {
+        let data = match connection.recv_raw_message() {
+            Ok(data) => data,
+            // Client has closed the connection or has not sent the next request in time
+            Err(_) => return Ok(()),
+        };
+        let (reply, proceed) = match SignerRequest::strict_deserialize(&data) {
+            Err(err) => (SignerReply::Failure(format!("malformed request: {}", err)), false),
+            Ok(_) if !clients.contains(&remote_key) => {
+                warn!("Signer client {} with node id {} is not allowed", inet_addr, remote_key);
+                (SignerReply::Failure(s!("client is not allowed")), false)
+            }
+            Ok(SignerRequest::Hello(SIGNER_PROTOCOL_VERSION)) => {
+                negotiated = true;
+                (SignerReply::Hello(SIGNER_PROTOCOL_VERSION), true)
+            }
+            Ok(SignerRequest::Hello(version)) => {
+                warn!("Signer client {} uses unsupported protocol version {}", inet_addr, version);
+                (SignerReply::Hello(SIGNER_PROTOCOL_VERSION), false)
+            }
+            Ok(_) if !negotiated => {
+                (SignerReply::Failure(s!("protocol version is not negotiated")), false)
+            }
+            Ok(request) => {
+                debug!("Signer client {} has requested {}", remote_key, request);
+                let reply = signer.process(request).unwrap_or_else(|err| {
+                    warn!("Request of signer client {} has failed: {}", remote_key, err);
+                    SignerReply::Failure(err.to_string())
+                });
+                (reply, true)
+            }
+        };
+        connection.send_raw_message(&reply.strict_serialize().expect("in-memory encoding"))?;
+        if !proceed {
+            return Ok(());
+        }
+    }
+}
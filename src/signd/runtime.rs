@@ -12,60 +12,151 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::fs;
+use std::path::Path;
 
 use amplify::Wrapper;
-use bitcoin::secp256k1::{self, Secp256k1};
-use bitcoin::util::bip32::{ChildNumber, DerivationPath};
-use lnp::channel::bolt::LocalKeyset;
+use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+use bitcoin::Txid;
+use internet2::{zmqsocket, ZmqType, ZMQ_CONTEXT};
 use lnp::p2p::legacy::ChannelId;
-use lnpbp::chain::Chain;
+use lnp_rpc::{AuthError, ClientId, ErrorCode, OptionDetails, RpcError, RpcMsg, ToRpcError};
 use microservices::esb::{self, Handler};
-use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SecretProvider, SignAll};
 
+use super::hardware::{self, HardwareClient, Hwi};
+use super::keystore;
+use super::policy::{PolicyError, SigningPolicy};
+use super::remote::{self, RemoteSigner, SignerReply, SignerRequest};
+use super::signer::Signer;
 use crate::bus::{BusMsg, CtlMsg, ServiceBus};
-use crate::opts::LNP_NODE_MASTER_KEY_FILE;
+use crate::message_signing;
+use crate::peerd::supervisor::read_node_key_file;
 use crate::rpc::ServiceId;
-use crate::{Config, Endpoints, Error, Service};
+use crate::service::BridgeHandler;
+use crate::{Config, Endpoints, Error, Responder, RpcAuth, Service, SignerMode};
 
-pub fn run(config: Config) -> Result<(), Error> {
+pub fn run(config: Config, key_file: &Path) -> Result<(), Error> {
     let secp = Secp256k1::new();
-    let runtime = Runtime::with(&secp, &config)?;
-    Service::run(config, runtime, false)
+    let mut runtime = Runtime::with(&secp, &config, key_file)?;
+
+    let hardware_wallet = match config.hardware_wallet.clone() {
+        Some(hardware_wallet) => hardware_wallet,
+        None => return Service::run(config, runtime, false),
+    };
+
+    debug!("Opening bridge between runtime and hardware signing threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://signd-hardware")?;
+    rx.bind("inproc://signd-hardware")?;
+
+    debug!("Starting hardware signing thread");
+    let bridge = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    info!(
+        "Funding wallet inputs are signed by hardware wallet through {}",
+        hardware_wallet.hwi_path.display()
+    );
+    let hwi = Hwi::with(hardware_wallet.hwi_path, &config.chain);
+    runtime.hardware = Some(HardwareClient::spawn(Box::new(hwi), hardware_wallet.timeout, bridge));
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+/// Signer holding the channel keys
+enum Backend<'secp> {
+    /// Keys are derived from the master key in the node data directory
+    Local(Signer<'secp>),
+
+    /// Master key is encrypted and awaits for the passphrase; signing requests are failed with
+    /// [`Error::SignerLocked`]
+    Locked,
+
+    /// Requests are relayed to the remote signer
+    Remote(RemoteSigner),
 }
 
 pub struct Runtime<'secp>
 where
     Self: 'secp,
 {
-    chain: Chain,
     identity: ServiceId,
-    provider: MemoryKeyProvider<'secp, secp256k1::All>,
+    config: Config,
+    secp: &'secp Secp256k1<secp256k1::All>,
+    backend: Backend<'secp>,
+
+    /// Hardware wallet signing the funding wallet inputs, if configured
+    hardware: Option<HardwareClient>,
+
+    /// Node key signing arbitrary messages on the client requests
+    node_key: SecretKey,
+
+    /// Root key authenticating client requests
+    rpc_auth: RpcAuth,
+
+    /// Policy which transactions are checked against before signing
+    policy: SigningPolicy,
 }
 
 impl<'secp> Runtime<'secp>
 where
     Self: 'secp,
 {
-    pub fn with(secp: &'secp Secp256k1<secp256k1::All>, config: &Config) -> Result<Self, Error> {
+    pub fn with(
+        secp: &'secp Secp256k1<secp256k1::All>,
+        config: &Config,
+        key_file: &Path,
+    ) -> Result<Self, Error> {
+        let local_node = read_node_key_file(key_file);
+        let node_key = local_node.private_key();
+        let backend = match config.signer {
+            SignerMode::Local => {
+                let passphrase = match config.unlock_file {
+                    Some(ref path) => Some(keystore::read_passphrase_file(path)?),
+                    None => None,
+                };
+                match Signer::with(secp, config, passphrase.as_deref()) {
+                    Ok(signer) => Backend::Local(signer),
+                    Err(Error::SignerLocked) => {
+                        warn!("Master key is encrypted; signer is locked until `lnp-cli unlock`");
+                        Backend::Locked
+                    }
+                    // Unattended node keeps serving its peers, while the operator may still
+                    // unlock the signer with the right passphrase
+                    Err(err @ Error::Keystore(_)) => {
+                        error!("Unable to unlock signer with the passphrase file: {}", err);
+                        Backend::Locked
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            SignerMode::Remote(ref remote) => {
+                info!("Channel keys are held by remote signer {}", remote);
+                Backend::Remote(RemoteSigner::with(local_node, remote.clone()))
+            }
+        };
         Ok(Runtime {
-            chain: config.chain.clone(),
             identity: ServiceId::Signer,
-            provider: Runtime::provider(secp, config)?,
+            config: config.clone(),
+            secp,
+            backend,
+            hardware: None,
+            node_key,
+            rpc_auth: RpcAuth::load(&config.data_dir)?,
+            policy: SigningPolicy::with(config)?,
         })
     }
-
-    fn provider(
-        secp: &'secp Secp256k1<secp256k1::All>,
-        config: &Config,
-    ) -> Result<MemoryKeyProvider<'secp, secp256k1::All>, Error> {
-        let mut wallet_path = config.data_dir.clone();
-        wallet_path.push(LNP_NODE_MASTER_KEY_FILE);
-        let signing_account = MemorySigningAccount::read(secp, fs::File::open(wallet_path)?)?;
-        let mut provider = MemoryKeyProvider::with(secp);
-        provider.add_account(signing_account);
-        Ok(provider)
-    }
 }
 
 impl<'secp> esb::Handler<ServiceBus> for Runtime<'secp>
@@ -98,6 +189,18 @@ where
                     Ok(())
                 }
             }
+            (ServiceBus::Rpc, BusMsg::Request(request), ServiceId::Client(client_id)) => {
+                match self.rpc_auth.authorize(request) {
+                    Ok(msg) => self.handle_rpc(endpoints, client_id, msg),
+                    Err(err) => Ok(self.reject_rpc(endpoints, client_id, err)?),
+                }
+            }
+            (ServiceBus::Rpc, BusMsg::Rpc(_), ServiceId::Client(client_id)) => {
+                Ok(self.reject_rpc(endpoints, client_id, AuthError::Unauthenticated)?)
+            }
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) => {
+                self.complete_hardware_signing(endpoints)
+            }
             (bus, msg, _) => Err(Error::wrong_esb_msg(bus, &msg)),
         }
     }
@@ -114,70 +217,201 @@ where
     }
 }
 
+impl<'secp> Responder for Runtime<'secp> where Self: 'secp {}
+
 impl<'secp> Runtime<'secp>
 where
     Self: 'secp,
 {
+    fn handle_rpc(
+        &mut self,
+        endpoints: &mut Endpoints,
+        client_id: ClientId,
+        message: RpcMsg,
+    ) -> Result<(), Error> {
+        match message {
+            RpcMsg::SignMessage(message) => {
+                let signature = message_signing::sign(self.secp, &message, &self.node_key);
+                info!("Message of {} bytes is signed with the node key", message.len());
+                self.send_rpc(endpoints, client_id, RpcMsg::MessageSignature(signature))?;
+            }
+            RpcMsg::Unlock(passphrase) => {
+                let reply = self.unlock(&passphrase).map_or_else(
+                    |err| RpcMsg::Failure(err.to_rpc_error()),
+                    |details| RpcMsg::Success(OptionDetails::with(details)),
+                );
+                self.send_rpc(endpoints, client_id, reply)?;
+            }
+
+            wrong_msg => {
+                error!("Request is not supported by the RPC interface");
+                return Err(Error::wrong_esb_msg(ServiceBus::Rpc, &wrong_msg));
+            }
+        }
+
+        Ok(())
+    }
+
     fn handle_ctl(
         &mut self,
         endpoints: &mut Endpoints,
         source: ServiceId,
         message: CtlMsg,
     ) -> Result<(), Error> {
-        match message {
-            CtlMsg::Sign(mut psbt) => {
-                let sig_count = psbt.sign_all(&self.provider)?;
-                let txid = psbt.global.unsigned_tx.txid();
-                info!("Transaction {} is signed ({} signatures added)", txid, sig_count);
-                trace!("Signed PSBT: {:#?}", psbt);
-                endpoints.send_to(
-                    ServiceBus::Ctl,
-                    self.identity.clone(),
-                    source,
-                    BusMsg::Ctl(CtlMsg::Signed(psbt)),
-                )?;
-            }
-
-            CtlMsg::DeriveKeyset(slice32) => {
-                let mut buf = [0u8; 4];
-                buf.copy_from_slice(&slice32.as_inner()[..4]);
-                let le = u32::from_be_bytes(buf);
-                let channel_index = le & 0x7FFFFFFF;
-                if let Some(account) = self.provider.into_iter().next() {
-                    let account_xpriv = account.account_xpriv();
-                    let chain_index = self.chain.chain_params().is_testnet as u32;
-                    let path = &[chain_index, 1, 0, channel_index]
-                        .iter()
-                        .map(|idx| ChildNumber::from_hardened_idx(*idx).expect("hardcoded index"))
-                        .collect::<Vec<_>>();
-                    let channel_xpriv =
-                        account_xpriv.derive_priv(self.provider.secp_context(), path)?;
-                    let keyset = LocalKeyset::with(
-                        self.provider.secp_context(),
-                        (account.account_fingerprint(), DerivationPath::from(path.as_ref())),
-                        channel_xpriv,
-                        // TODO: Use a key from a funding wallet
-                        None,
-                    );
-
-                    endpoints.send_to(
-                        ServiceBus::Ctl,
-                        self.identity(),
-                        source,
-                        BusMsg::Ctl(CtlMsg::Keyset(
-                            ServiceId::Channel(ChannelId::from_inner(slice32)),
-                            keyset,
-                        )),
-                    )?;
+        let mut channel_id = None;
+        let mut sign_psbt = false;
+        let request = match message {
+            CtlMsg::GetInfo => {
+                let locked = matches!(self.backend, Backend::Locked);
+                let message = BusMsg::Ctl(CtlMsg::SignerInfo { locked });
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, message)?;
+                return Ok(());
+            }
+            CtlMsg::ExpectFunding { script_pubkey, amount } => {
+                self.policy.expect_funding(script_pubkey, amount);
+                return Ok(());
+            }
+            CtlMsg::Sign(psbt) => {
+                let check = match source {
+                    ServiceId::Channel(_) => self.policy.check_channel_tx(&psbt),
+                    _ => self.policy.check_funding(&psbt),
+                };
+                if let Err(error) = check {
+                    let txid = psbt.global.unsigned_tx.txid();
+                    return self.refuse_signing(endpoints, source, txid, error);
+                }
+                sign_psbt = true;
+                SignerRequest::SignPsbt(psbt)
+            }
+            CtlMsg::SignWithdrawal { psbt, confirmation } => {
+                if let Err(error) = self.policy.check_withdrawal(&psbt, confirmation.as_deref()) {
+                    let txid = psbt.global.unsigned_tx.txid();
+                    return self.refuse_signing(endpoints, source, txid, error);
                 }
+                sign_psbt = true;
+                SignerRequest::SignPsbt(psbt)
+            }
+            CtlMsg::SignPenalty { psbt, per_commitment_secret } => {
+                SignerRequest::SignPenalty { psbt, per_commitment_secret }
+            }
+            CtlMsg::SignDelayed { psbt, per_commitment_point } => {
+                SignerRequest::SignDelayed { psbt, per_commitment_point }
+            }
+            CtlMsg::SignHtlc { psbt, per_commitment_point } => {
+                if let Err(error) = self.policy.check_htlc_tx(&psbt) {
+                    let txid = psbt.global.unsigned_tx.txid();
+                    return self.refuse_signing(endpoints, source, txid, error);
+                }
+                SignerRequest::SignHtlc { psbt, per_commitment_point }
+            }
+            CtlMsg::SignToRemote { psbt, per_commitment_point } => {
+                SignerRequest::SignToRemote { psbt, per_commitment_point }
+            }
+            CtlMsg::SignAnnouncement { announcement, funding_key } => {
+                SignerRequest::SignAnnouncement { announcement, funding_key }
+            }
+            CtlMsg::DeriveChannelKeys { channel_id: slice32, index } => {
+                channel_id = Some(ChannelId::from_inner(slice32));
+                SignerRequest::DeriveChannelKeys(index)
+            }
+            CtlMsg::GetCommitmentPoint { index, commitment_number } => {
+                SignerRequest::CommitmentPoint { index, commitment_number }
+            }
+            CtlMsg::RevealCommitmentSecret { index, commitment_number } => {
+                SignerRequest::CommitmentSecret { index, commitment_number }
             }
 
             wrong_msg => {
                 error!("Request {} is not supported by the CTL interface", wrong_msg);
                 return Err(Error::wrong_esb_msg(ServiceBus::Ctl, &wrong_msg));
             }
+        };
+
+        let reply = match self.backend {
+            Backend::Local(ref signer) => signer.process(request)?,
+            Backend::Locked => {
+                warn!("Request {} is refused since the signer is locked", request);
+                return Err(Error::SignerLocked);
+            }
+            Backend::Remote(ref remote) => {
+                debug!("Relaying {} to the remote signer", request);
+                remote.request(request)?
+            }
+        };
+
+        let message = match (reply, channel_id) {
+            (SignerReply::Signed(psbt), None) => match self.hardware {
+                // Inputs left unsigned by the software signer belong to the funding wallet
+                Some(ref mut hardware) if sign_psbt && hardware::requires_device(&psbt) => {
+                    hardware.sign(source, psbt);
+                    return Ok(());
+                }
+                _ => CtlMsg::Signed(psbt),
+            },
+            (SignerReply::AnnouncementSigned(announcement), None) => {
+                CtlMsg::AnnouncementSigned(announcement)
+            }
+            (SignerReply::Keyset(keyset), Some(channel_id)) => {
+                CtlMsg::Keyset(ServiceId::Channel(channel_id), keyset)
+            }
+            (SignerReply::CommitmentPoint { commitment_number, point }, None) => {
+                CtlMsg::CommitmentPoint { commitment_number, point }
+            }
+            (SignerReply::CommitmentSecret { commitment_number, secret, next_point }, None) => {
+                CtlMsg::CommitmentSecret { commitment_number, secret, next_point }
+            }
+            (reply, _) => return Err(remote::Error::UnexpectedReply(reply.to_string()).into()),
+        };
+        endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
+
+        Ok(())
+    }
+
+    /// Replies to the service requesting the signature that the transaction violates the signing
+    /// policy and is not signed
+    fn refuse_signing(
+        &mut self,
+        endpoints: &mut Endpoints,
+        source: ServiceId,
+        txid: Txid,
+        error: PolicyError,
+    ) -> Result<(), Error> {
+        warn!("Signing of transaction {} requested by {} is refused: {}", txid, source, error);
+        let message = BusMsg::Ctl(CtlMsg::SignRefused { txid, error });
+        endpoints.send_to(ServiceBus::Ctl, self.identity(), source, message)?;
+        Ok(())
+    }
+
+    /// Decrypts the master key with the passphrase, returning the description of the result
+    fn unlock(&mut self, passphrase: &str) -> Result<&'static str, Error> {
+        match self.backend {
+            Backend::Locked => {
+                let signer = Signer::with(self.secp, &self.config, Some(passphrase))?;
+                self.backend = Backend::Local(signer);
+                info!("Signer is unlocked");
+                Ok("Signer is unlocked")
+            }
+            Backend::Local(_) => Ok("Signer is already unlocked"),
+            Backend::Remote(_) => Err(Error::Failure(RpcError::new(
+                ErrorCode::NotSupported,
+                s!("Channel keys are held by the remote signer, which is unlocked on its host"),
+            ))),
         }
+    }
 
+    fn complete_hardware_signing(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let replies = match self.hardware {
+            Some(ref mut hardware) => hardware.complete(),
+            None => return Ok(()),
+        };
+        for (destination, message) in replies {
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                destination,
+                BusMsg::Ctl(message),
+            )?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,528 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Signer holding the channel keys derived from the node master key.
+//!
+//! The signer is used by signd directly, unless the node is configured with a remote signer; in
+//! the latter case the signer runs inside signd started with `--serve` option on a separate host,
+//! which serves the requests relayed by the node signd over the [`super::remote`] protocol.
+
+use bitcoin::blockdata::opcodes::all::OP_IF;
+use bitcoin::hashes::{sha256, sha256d, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::SigHashType;
+use lightning_encoding::LightningEncode;
+use lnp::channel::bolt::{LocalKeyset, LocalPubkey};
+use lnp::p2p::legacy::ChannelAnnouncement;
+use lnpbp::chain::Chain;
+use psbt::sign::{MemoryKeyProvider, SecretProvider, SignAll};
+use psbt::Psbt;
+
+use super::keystore;
+use super::remote::{SignerReply, SignerRequest, SIGNER_PROTOCOL_VERSION};
+use crate::channeld::{commitment_index, generate_from_seed};
+use crate::opts::LNP_NODE_MASTER_KEY_FILE;
+use crate::{Config, Error};
+
+pub struct Signer<'secp>
+where
+    Self: 'secp,
+{
+    chain: Chain,
+    provider: MemoryKeyProvider<'secp, secp256k1::All>,
+}
+
+impl<'secp> Signer<'secp>
+where
+    Self: 'secp,
+{
+    /// Loads the master key, decrypting it with the passphrase. Fails with
+    /// [`Error::SignerLocked`] if the master key is encrypted and the passphrase is not given.
+    pub fn with(
+        secp: &'secp Secp256k1<secp256k1::All>,
+        config: &Config,
+        passphrase: Option<&str>,
+    ) -> Result<Self, Error> {
+        let provider = Signer::provider(secp, config, passphrase)?;
+        Ok(Signer { chain: config.chain.clone(), provider })
+    }
+
+    fn provider(
+        secp: &'secp Secp256k1<secp256k1::All>,
+        config: &Config,
+        passphrase: Option<&str>,
+    ) -> Result<MemoryKeyProvider<'secp, secp256k1::All>, Error> {
+        let mut wallet_path = config.data_dir.clone();
+        wallet_path.push(LNP_NODE_MASTER_KEY_FILE);
+        let signing_account = keystore::read_signing_account(secp, &wallet_path, passphrase)?;
+        let mut provider = MemoryKeyProvider::with(secp);
+        provider.add_account(signing_account);
+        Ok(provider)
+    }
+
+    /// Processes signing request, returning the signed data
+    pub fn process(&self, request: SignerRequest) -> Result<SignerReply, Error> {
+        Ok(match request {
+            SignerRequest::Hello(_) => SignerReply::Hello(SIGNER_PROTOCOL_VERSION),
+
+            SignerRequest::SignPsbt(mut psbt) => {
+                let sig_count = psbt.sign_all(&self.provider)?;
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("Transaction {} is signed ({} signatures added)", txid, sig_count);
+                trace!("Signed PSBT: {:#?}", psbt);
+                SignerReply::Signed(psbt)
+            }
+
+            SignerRequest::SignPenalty { mut psbt, per_commitment_secret } => {
+                let sig_count = self.sign_penalty(&mut psbt, per_commitment_secret)?;
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("Penalty transaction {} is signed ({} inputs finalized)", txid, sig_count);
+                trace!("Signed PSBT: {:#?}", psbt);
+                SignerReply::Signed(psbt)
+            }
+
+            SignerRequest::SignDelayed { mut psbt, per_commitment_point } => {
+                let sig_count = self.sign_delayed(&mut psbt, per_commitment_point)?;
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("Sweep transaction {} is signed ({} inputs finalized)", txid, sig_count);
+                trace!("Signed PSBT: {:#?}", psbt);
+                SignerReply::Signed(psbt)
+            }
+
+            SignerRequest::SignHtlc { mut psbt, per_commitment_point } => {
+                let sig_count = self.sign_htlc(&mut psbt, per_commitment_point)?;
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("HTLC transaction {} is signed ({} inputs signed)", txid, sig_count);
+                trace!("Signed PSBT: {:#?}", psbt);
+                SignerReply::Signed(psbt)
+            }
+
+            SignerRequest::SignToRemote { mut psbt, per_commitment_point } => {
+                let sig_count = self.sign_to_remote(&mut psbt, per_commitment_point)?;
+                let txid = psbt.global.unsigned_tx.txid();
+                info!("Sweep transaction {} is signed ({} inputs signed)", txid, sig_count);
+                trace!("Signed PSBT: {:#?}", psbt);
+                SignerReply::Signed(psbt)
+            }
+
+            SignerRequest::SignAnnouncement { mut announcement, funding_key } => {
+                self.sign_announcement(&mut announcement, funding_key)?;
+                info!("Announcement of channel {} is signed", announcement.short_channel_id);
+                SignerReply::AnnouncementSigned(announcement)
+            }
+
+            SignerRequest::DeriveChannelKeys(index) => {
+                SignerReply::Keyset(self.derive_keyset(index)?)
+            }
+
+            SignerRequest::CommitmentPoint { index, commitment_number } => {
+                let point = self.commitment_point(index, commitment_number)?;
+                SignerReply::CommitmentPoint { commitment_number, point }
+            }
+
+            SignerRequest::CommitmentSecret { index, commitment_number } => {
+                let secret = self.commitment_secret(index, commitment_number)?;
+                // `revoke_and_ack` revoking commitment N provides the point for commitment N + 2,
+                // since the point for N + 1 was already sent with the previous message
+                let next_point = self.commitment_point(index, commitment_number + 2)?;
+                info!("Local commitment {} of channel {} is revoked", commitment_number, index);
+                SignerReply::CommitmentSecret { commitment_number, secret, next_point }
+            }
+        })
+    }
+
+    /// Derives channel keyset, including the basepoints and the first per-commitment point, for
+    /// the channel with the given index at `m/9735h/<chain>h/1h/0h/<index>h` path
+    fn derive_keyset(&self, channel_index: u32) -> Result<LocalKeyset, Error> {
+        let (source, channel_xpriv) = self.channel_xpriv(channel_index)?;
+        let mut keyset = LocalKeyset::with(
+            self.provider.secp_context(),
+            source,
+            channel_xpriv,
+            // TODO: Use a key from a funding wallet
+            None,
+        );
+        keyset.first_per_commitment_point.key = self.commitment_point(channel_index, 0)?;
+        Ok(keyset)
+    }
+
+    /// Derives per-commitment secret of the local commitment transaction with the given number
+    /// from the channel per-commitment seed, according to BOLT-3
+    fn commitment_secret(
+        &self,
+        channel_index: u32,
+        commitment_number: u64,
+    ) -> Result<SecretKey, Error> {
+        let (_, channel_xpriv) = self.channel_xpriv(channel_index)?;
+        // Per-commitment seed is bound to the channel key, so it is never stored separately
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"lnp-node:per-commitment-seed");
+        engine.input(&channel_xpriv.private_key.key[..]);
+        let seed = sha256::Hash::from_engine(engine).into_inner();
+        let secret = generate_from_seed(seed, commitment_index(commitment_number));
+        Ok(SecretKey::from_slice(&secret)?)
+    }
+
+    /// Derives per-commitment point of the local commitment transaction with the given number
+    fn commitment_point(
+        &self,
+        channel_index: u32,
+        commitment_number: u64,
+    ) -> Result<PublicKey, Error> {
+        let secret = self.commitment_secret(channel_index, commitment_number)?;
+        Ok(PublicKey::from_secret_key(self.provider.secp_context(), &secret))
+    }
+
+    /// Derives extended private key of the channel with the given index, returning it together
+    /// with its derivation source
+    fn channel_xpriv(
+        &self,
+        channel_index: u32,
+    ) -> Result<((Fingerprint, DerivationPath), ExtendedPrivKey), Error> {
+        if channel_index & 0x80000000 != 0 {
+            return Err(Error::Other(format!("invalid channel key index {}", channel_index)));
+        }
+        let account = self
+            .provider
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Other(s!("no signing account is available")))?;
+        let account_xpriv = account.account_xpriv();
+        let chain_index = self.chain.chain_params().is_testnet as u32;
+        let path = &[chain_index, 1, 0, channel_index]
+            .iter()
+            .map(|idx| ChildNumber::from_hardened_idx(*idx).expect("hardcoded index"))
+            .collect::<Vec<_>>();
+        let channel_xpriv = account_xpriv.derive_priv(self.provider.secp_context(), path)?;
+        let source = (account.account_fingerprint(), DerivationPath::from(path.as_ref()));
+        Ok((source, channel_xpriv))
+    }
+
+    /// Signs and finalizes inputs of the penalty transaction, which spend revocable to-local and
+    /// HTLC outputs of the remote commitment transaction. Returns number of finalized inputs.
+    fn sign_penalty(
+        &self,
+        psbt: &mut Psbt,
+        per_commitment_secret: SecretKey,
+    ) -> Result<usize, Error> {
+        let secp = self.provider.secp_context();
+        let per_commitment_point = PublicKey::from_secret_key(secp, &per_commitment_secret);
+        let sig_count = self.sign_derived(psbt, None, |basepoint, basepoint_secret| {
+            // BOLT-3: revocationprivkey = revocation_basepoint_secret *
+            //     SHA256(revocation_basepoint || per_commitment_point) +
+            //     per_commitment_secret * SHA256(per_commitment_point || revocation_basepoint)
+            let mut revocation_secret = basepoint_secret;
+            revocation_secret.mul_assign(&tweak(&basepoint, &per_commitment_point)[..])?;
+            let mut commitment_secret = per_commitment_secret;
+            commitment_secret.mul_assign(&tweak(&per_commitment_point, &basepoint)[..])?;
+            revocation_secret.add_assign(&commitment_secret[..])?;
+            Ok(revocation_secret)
+        })?;
+
+        for input in &mut psbt.inputs {
+            let witness_script = match input.witness_script {
+                Some(ref witness_script) => witness_script.clone(),
+                None => continue,
+            };
+            let (revocation_pubkey, sig) = match input.partial_sigs.iter().next() {
+                Some((pubkey, sig)) => (pubkey.to_bytes(), sig.clone()),
+                None => continue,
+            };
+            // To-local output script starts with the revocation branch selected by `1` witness
+            // element, while HTLC output scripts take the revocation pubkey instead
+            let branch = if witness_script.as_bytes().first() == Some(&OP_IF.into_u8()) {
+                vec![1]
+            } else {
+                revocation_pubkey
+            };
+            input.final_script_witness = Some(vec![sig, branch, witness_script.to_bytes()]);
+            input.partial_sigs.clear();
+        }
+        Ok(sig_count)
+    }
+
+    /// Signs and finalizes inputs spending time-locked to-local outputs of our own commitment
+    /// transaction. Returns number of finalized inputs.
+    fn sign_delayed(
+        &self,
+        psbt: &mut Psbt,
+        per_commitment_point: PublicKey,
+    ) -> Result<usize, Error> {
+        // Empty witness element selects delayed branch of the to-local output script
+        self.sign_derived(psbt, Some(vec![]), |basepoint, basepoint_secret| {
+            derive_secret(per_commitment_point, basepoint, basepoint_secret)
+        })
+    }
+
+    /// Signs inputs of the second-stage HTLC transactions spending HTLC outputs of our own
+    /// commitment transaction. Inputs are not finalized, since their witness requires remote
+    /// peer signature known to channeld; signatures are added as partial signatures instead.
+    /// Funding wallet inputs, added to zero-fee HTLC transactions to pay their fee, are signed
+    /// with the wallet keys. Returns number of signed inputs.
+    fn sign_htlc(&self, psbt: &mut Psbt, per_commitment_point: PublicKey) -> Result<usize, Error> {
+        let mut sig_count = self.sign_derived(psbt, None, |basepoint, basepoint_secret| {
+            derive_secret(per_commitment_point, basepoint, basepoint_secret)
+        })?;
+        if psbt.inputs.len() == 1 {
+            return Ok(sig_count);
+        }
+
+        // HTLC inputs have derivation of the HTLC basepoint, which must not be used as a key
+        let mut wallet_psbt = psbt.clone();
+        for input in &mut wallet_psbt.inputs {
+            if input.witness_script.is_some() {
+                input.bip32_derivation.clear();
+            }
+        }
+        sig_count += wallet_psbt.sign_all(&self.provider)?;
+        for (input, wallet_input) in psbt.inputs.iter_mut().zip(wallet_psbt.inputs) {
+            if input.witness_script.is_none() {
+                input.partial_sigs = wallet_input.partial_sigs;
+            }
+        }
+        Ok(sig_count)
+    }
+
+    /// Signs inputs spending our output of the remote commitment transaction. The signing key is
+    /// the payment basepoint itself for the channels with static remote key; otherwise it is
+    /// derived from the per-commitment point of that commitment. Signatures are added to the
+    /// input partial signatures, since the witness depends on the output type. Returns number of
+    /// signed inputs.
+    fn sign_to_remote(
+        &self,
+        psbt: &mut Psbt,
+        per_commitment_point: Option<PublicKey>,
+    ) -> Result<usize, Error> {
+        self.sign_derived(psbt, None, |basepoint, basepoint_secret| match per_commitment_point {
+            Some(per_commitment_point) => {
+                derive_secret(per_commitment_point, basepoint, basepoint_secret)
+            }
+            None => Ok(basepoint_secret),
+        })
+    }
+
+    /// Signs inputs with keys derived from the basepoints provided as the input BIP32
+    /// derivations. Each input must provide witness script and a single derivation. If `branch`
+    /// witness element, selecting the branch of the witness script to execute, is given, inputs
+    /// are finalized; otherwise signatures are added to the input partial signatures.
+    fn sign_derived(
+        &self,
+        psbt: &mut Psbt,
+        branch: Option<Vec<u8>>,
+        derive: impl Fn(PublicKey, SecretKey) -> Result<SecretKey, secp256k1::Error>,
+    ) -> Result<usize, Error> {
+        let secp = self.provider.secp_context();
+        let tx = psbt.global.unsigned_tx.clone();
+        let mut sig_hasher = SigHashCache::new(&tx);
+        let mut sig_count = 0usize;
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let (witness_script, value) = match (&input.witness_script, &input.witness_utxo) {
+                (Some(witness_script), Some(prevout)) => (witness_script.clone(), prevout.value),
+                _ => continue,
+            };
+            let (basepoint, (fingerprint, derivation)) = match input.bip32_derivation.iter().next()
+            {
+                Some((basepoint, source)) => (basepoint.key, source.clone()),
+                None => continue,
+            };
+
+            let basepoint_secret = self.secret_key(fingerprint, &derivation)?;
+            if PublicKey::from_secret_key(secp, &basepoint_secret) != basepoint {
+                warn!("Basepoint of input {} does not match its derivation", index);
+                continue;
+            }
+            let secret = derive(basepoint, basepoint_secret)?;
+
+            // Second-stage HTLC transactions of anchor channels are signed with
+            // SIGHASH_SINGLE|SIGHASH_ANYONECANPAY requested by channeld
+            let sighash_type = input.sighash_type.unwrap_or(SigHashType::All);
+            let sighash = sig_hasher.signature_hash(index, &witness_script, value, sighash_type);
+            let message = Message::from_slice(&sighash[..]).expect("sighash is always 32 bytes");
+            let mut sig = secp.sign(&message, &secret).serialize_der().to_vec();
+            sig.push(sighash_type.as_u32() as u8);
+            match branch {
+                Some(ref branch) => {
+                    input.final_script_witness =
+                        Some(vec![sig, branch.clone(), witness_script.to_bytes()]);
+                }
+                None => {
+                    let pubkey = PublicKey::from_secret_key(secp, &secret);
+                    input.partial_sigs.insert(bitcoin::PublicKey::new(pubkey), sig);
+                }
+            }
+            sig_count += 1;
+        }
+        Ok(sig_count)
+    }
+
+    /// Adds bitcoin signature made with the channel funding key to the channel announcement
+    fn sign_announcement(
+        &self,
+        announcement: &mut ChannelAnnouncement,
+        funding_key: LocalPubkey,
+    ) -> Result<(), Error> {
+        let secp = self.provider.secp_context();
+        let (fingerprint, derivation) = funding_key.source;
+        let secret = self.secret_key(fingerprint, &derivation)?;
+        let pubkey = PublicKey::from_secret_key(secp, &secret);
+        if pubkey != funding_key.key {
+            return Err(Error::KeyMismatch(funding_key.key));
+        }
+
+        // All four signatures commit to the double SHA256 hash of the message data following them
+        let data = announcement.lightning_serialize().expect("in-memory encoding");
+        let digest = sha256d::Hash::hash(&data[256..]);
+        let message = Message::from_slice(&digest[..]).expect("hash is 32 bytes");
+        let signature = secp.sign(&message, &secret);
+        if announcement.bitcoin_key_1 == pubkey {
+            announcement.bitcoin_signature_1 = signature;
+        } else if announcement.bitcoin_key_2 == pubkey {
+            announcement.bitcoin_signature_2 = signature;
+        } else {
+            return Err(Error::KeyMismatch(pubkey));
+        }
+        Ok(())
+    }
+
+    fn secret_key(
+        &self,
+        fingerprint: Fingerprint,
+        derivation: &DerivationPath,
+    ) -> Result<SecretKey, Error> {
+        let account = self
+            .provider
+            .into_iter()
+            .find(|account| account.account_fingerprint() == fingerprint)
+            .ok_or(Error::UnknownAccount(fingerprint))?;
+        let xpriv =
+            account.account_xpriv().derive_priv(self.provider.secp_context(), derivation)?;
+        Ok(xpriv.private_key.key)
+    }
+}
+
+fn tweak(first: &PublicKey, second: &PublicKey) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&first.serialize());
+    engine.input(&second.serialize());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Derives private key for the per-commitment public key from the basepoint secret.
+///
+/// BOLT-3: privkey = basepoint_secret + SHA256(per_commitment_point || basepoint)
+fn derive_secret(
+    per_commitment_point: PublicKey,
+    basepoint: PublicKey,
+    basepoint_secret: SecretKey,
+) -> Result<SecretKey, secp256k1::Error> {
+    let mut secret = basepoint_secret;
+    secret.add_assign(&tweak(&per_commitment_point, &basepoint)[..])?;
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::Network;
+    use psbt::sign::MemorySigningAccount;
+
+    use super::*;
+
+    /// Signer with the master key of BIP32 test vector 1 (seed `000102...0f`), as it is
+    /// initialized by `lnpd init` with `m/9735h` account
+    fn test_signer(secp: &Secp256k1<secp256k1::All>) -> Signer {
+        let seed = (0u8..16).collect::<Vec<_>>();
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &seed).unwrap();
+        let derivation = DerivationPath::from_str("m/9735h").unwrap();
+        let account_xpriv = master.derive_priv(secp, &derivation).unwrap();
+        let account =
+            MemorySigningAccount::with(secp, master.identifier(secp), derivation, account_xpriv);
+        let mut provider = MemoryKeyProvider::with(secp);
+        provider.add_account(account);
+        Signer { chain: Chain::Testnet3, provider }
+    }
+
+    #[test]
+    fn channel_key_path() {
+        let secp = Secp256k1::new();
+        let signer = test_signer(&secp);
+        let ((fingerprint, path), xpriv) = signer.channel_xpriv(5).unwrap();
+        assert_eq!(fingerprint, Fingerprint::from_str("438f81c2").unwrap());
+        assert_eq!(path, DerivationPath::from_str("m/1h/1h/0h/5h").unwrap());
+        let expected = "f3d0a158c1c940d5d3810f83ca22098a832510fcc3b21c2e1d75a8e7f57380aa";
+        assert_eq!(xpriv.private_key.key, SecretKey::from_str(expected).unwrap());
+        assert!(signer.channel_xpriv(0x80000000).is_err());
+    }
+
+    #[test]
+    fn commitment_secrets() {
+        let secp = Secp256k1::new();
+        let signer = test_signer(&secp);
+        let fixtures = [
+            (
+                "ad966b47a290ba1d20a655377afe90de82ec93c694ec2e8eef7e571a8e76a58a",
+                "0313485ed74b1dbce08f887d2ae51a6683f24262a8e4e0edc23119e362d94d1015",
+            ),
+            (
+                "b9c3673cd1b5830367dda8bbaea815aaf67548ec864bffa686551d9bfd21fdcc",
+                "032d0f68df68813497a27552785ea3b6a5809be7c6fccfabb77e8da4a1ba1f96f7",
+            ),
+            (
+                "ad39e64eb0a3ebf53ccb9a36d8539ca81fa5802215de9cbd36af1082de6c2563",
+                "037544c49c5122cdf6122de60ca365c6e25ee44d7fdc7a7baace70498d6d19839b",
+            ),
+        ];
+        for (commitment_number, (secret, point)) in fixtures.iter().enumerate() {
+            let commitment_number = commitment_number as u64;
+            let expected = SecretKey::from_str(secret).unwrap();
+            assert_eq!(signer.commitment_secret(5, commitment_number).unwrap(), expected);
+            let expected = PublicKey::from_str(point).unwrap();
+            assert_eq!(signer.commitment_point(5, commitment_number).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn channel_keyset() {
+        let secp = Secp256k1::new();
+        let signer = test_signer(&secp);
+        let keyset = signer.derive_keyset(5).unwrap();
+        let expected = "0313485ed74b1dbce08f887d2ae51a6683f24262a8e4e0edc23119e362d94d1015";
+        assert_eq!(keyset.first_per_commitment_point.key, PublicKey::from_str(expected).unwrap());
+
+        // signd must be able to produce secret keys for all basepoints from their derivation
+        let channel_path = DerivationPath::from_str("m/1h/1h/0h/5h").unwrap();
+        let basepoints = [
+            &keyset.funding_pubkey,
+            &keyset.revocation_basepoint,
+            &keyset.payment_basepoint,
+            &keyset.delayed_payment_basepoint,
+            &keyset.htlc_basepoint,
+        ];
+        for basepoint in basepoints.iter() {
+            let (fingerprint, ref derivation) = basepoint.source;
+            assert!(derivation.as_ref().starts_with(channel_path.as_ref()));
+            let secret = signer.secret_key(fingerprint, derivation).unwrap();
+            assert_eq!(PublicKey::from_secret_key(&secp, &secret), basepoint.key);
+        }
+
+        // The same seed always produces the same keys, distinct for each channel
+        let again = test_signer(&secp).derive_keyset(5).unwrap();
+        assert_eq!(again.payment_basepoint.key, keyset.payment_basepoint.key);
+        let other = signer.derive_keyset(6).unwrap();
+        assert_ne!(other.payment_basepoint.key, keyset.payment_basepoint.key);
+    }
+}
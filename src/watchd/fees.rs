@@ -0,0 +1,104 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Fee estimation for the node transactions. Estimations of the blockchain backend are refreshed
+//! periodically and smoothed with an exponential moving average, such that a single outlier does
+//! not make all channels update their commitment feerate; the result is limited by the floor and
+//! the ceiling from the node fee settings.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::chain::ChainApi;
+use crate::FeeSettings;
+
+/// Period after which the estimation is refreshed from the blockchain backend
+const REFRESH_PERIOD: Duration = Duration::from_secs(300);
+
+/// Weight of a new backend estimation in the smoothed feerate, in percents
+const SMOOTHING_PERCENT: u64 = 30;
+
+pub struct FeeEstimator {
+    settings: FeeSettings,
+
+    /// Smoothed estimations per confirmation target, in satoshi per kilo-weight unit
+    estimations: HashMap<u16, u32>,
+
+    /// Time of the last request to the blockchain backend per confirmation target, including the
+    /// failed ones
+    refreshed: HashMap<u16, Instant>,
+}
+
+impl FeeEstimator {
+    pub fn with(settings: FeeSettings) -> FeeEstimator {
+        FeeEstimator { settings, estimations: empty!(), refreshed: empty!() }
+    }
+
+    #[inline]
+    pub fn settings(&self) -> FeeSettings { self.settings }
+
+    #[inline]
+    pub fn set_settings(&mut self, settings: FeeSettings) { self.settings = settings }
+
+    /// Feerate for the transactions to be mined within the given number of blocks, in satoshi per
+    /// kilo-weight unit. Outdated estimation is refreshed from the blockchain backend; backends
+    /// unable to estimate fees yield the floor feerate.
+    pub fn feerate(&mut self, chain: &dyn ChainApi, target_blocks: u16) -> u32 {
+        let outdated = self
+            .refreshed
+            .get(&target_blocks)
+            .map(|time| time.elapsed() >= REFRESH_PERIOD)
+            .unwrap_or(true);
+        if outdated {
+            self.refresh(chain, target_blocks);
+        }
+        let FeeSettings { floor_per_kw, ceiling_per_kw, .. } = self.settings;
+        self.estimations
+            .get(&target_blocks)
+            .copied()
+            .unwrap_or(floor_per_kw)
+            .max(floor_per_kw)
+            .min(ceiling_per_kw)
+    }
+
+    fn refresh(&mut self, chain: &dyn ChainApi, target_blocks: u16) {
+        self.refreshed.insert(target_blocks, Instant::now());
+        let btc_per_kvb = match chain.estimate_fee(target_blocks) {
+            Ok(Some(btc_per_kvb)) => btc_per_kvb,
+            Ok(None) => {
+                debug!("Blockchain backend is unable to estimate fee for {} blocks", target_blocks);
+                return;
+            }
+            Err(err) => {
+                warn!("Unable to get fee estimation from blockchain backend: {}", err);
+                return;
+            }
+        };
+        let estimation = (btc_per_kvb * 100_000_000.0 / 4.0) as u64;
+        let smoothed = match self.estimations.get(&target_blocks) {
+            Some(prev) => {
+                (*prev as u64 * (100 - SMOOTHING_PERCENT) + estimation * SMOOTHING_PERCENT) / 100
+            }
+            None => estimation,
+        };
+        let smoothed = smoothed.min(u32::MAX as u64) as u32;
+        trace!(
+            "Fee estimation for {} blocks is {} sat/kw, smoothed to {} sat/kw",
+            target_blocks,
+            estimation,
+            smoothed
+        );
+        self.estimations.insert(target_blocks, smoothed);
+    }
+}
@@ -12,10 +12,14 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod fees;
 #[cfg(feature = "server")]
 mod opts;
+mod rebroadcast;
 mod runtime;
+mod tower;
 
 #[cfg(feature = "server")]
 pub use opts::Opts;
 pub use runtime::run;
+pub use tower::{TowerError, HINT_LEN};
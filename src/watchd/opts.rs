@@ -12,12 +12,18 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use crate::peerd::KeyOpts;
+
 /// Lightning peer network channel daemon; part of LNP Node.
 ///
 /// The daemon is controlled though RPC socket (see `rpc-socket`).
 #[derive(Parser, Clone, PartialEq, Eq, Debug)]
 #[clap(name = "watchd", bin_name = "watchd", author, version)]
 pub struct Opts {
+    /// Node key configuration
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
     /// These params can be read also from the configuration file, not just
     /// command-line args or environment variables
     #[clap(flatten)]
@@ -25,5 +31,8 @@ pub struct Opts {
 }
 
 impl Opts {
-    pub fn process(&mut self) { self.shared.process() }
+    pub fn process(&mut self) {
+        self.shared.process();
+        self.key_opts.process(&self.shared);
+    }
 }
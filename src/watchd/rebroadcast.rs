@@ -0,0 +1,239 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Rebroadcasting of the transactions published by the node.
+//!
+//! Transactions may be evicted from the mempools during fee spikes and are never relayed again
+//! afterwards. watchd keeps the set of the published transactions which are not mined yet on
+//! disk and rebroadcasts them on each new block and once the blockchain backend reconnects. The
+//! service on which behalf a transaction was published is notified when the transaction is found
+//! missing from the mempool, such that it may bump the transaction fee.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Transaction, Txid};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::bus::CtlMsg;
+use crate::chain::ChainApi;
+use crate::rpc::{PendingTx, ServiceId};
+use crate::Error;
+
+/// Number of blocks after which a transaction which is still not mined is not rebroadcast
+/// anymore
+const MAX_PENDING_BLOCKS: u32 = 2016;
+
+/// Published transaction which is not mined yet
+#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+struct PendingEntry {
+    tx: Transaction,
+
+    /// Service on which behalf the transaction was published
+    owner: ServiceId,
+
+    /// Feerate of the transaction, if known, in satoshi per kilo-weight unit
+    feerate_per_kw: Option<u32>,
+
+    /// UNIX timestamp of the first publication of the transaction
+    published: u64,
+
+    /// Blockchain height at the first publication of the transaction, if it was known
+    published_height: Option<u32>,
+
+    /// Number of the successful rebroadcasts
+    rebroadcasts: u32,
+
+    /// Whether the transaction was found missing from the mempool, such that the owner was
+    /// notified about its eviction
+    evicted: bool,
+}
+
+/// Set of the published transactions which are not mined yet, saved to the file on each update
+pub struct Rebroadcaster {
+    path: PathBuf,
+
+    txs: BTreeMap<Txid, PendingEntry>,
+
+    /// Number of the blockchain backend reconnections seen during the last poll
+    reconnects: u64,
+}
+
+impl Rebroadcaster {
+    /// Reads the transactions from the file, starting with an empty set if the file does not
+    /// exist yet
+    pub fn load(path: PathBuf) -> Result<Rebroadcaster, Error> {
+        let txs = if path.exists() {
+            let file = fs::File::open(&path)?;
+            BTreeMap::strict_decode(&file).map_err(Error::Persistence)?
+        } else {
+            bmap! {}
+        };
+        info!("Rebroadcasting {} unconfirmed transactions from '{}'", txs.len(), path.display());
+        Ok(Rebroadcaster { path, txs, reconnects: 0 })
+    }
+
+    /// Saves the transactions to the file. Failures are logged, since the transactions are still
+    /// rebroadcast until watchd restarts.
+    fn save(&self) {
+        trace!("Saving rebroadcast transactions on disk");
+        let result = fs::File::create(&self.path)
+            .map_err(Error::from)
+            .and_then(|file| self.txs.strict_encode(&file).map_err(Error::Persistence));
+        if let Err(err) = result {
+            warn!("Unable to save rebroadcast transactions to '{}': {}", self.path.display(), err);
+        }
+    }
+
+    /// Adds just published transaction to the set. Republished transactions keep the time of
+    /// their first publication.
+    pub fn add(
+        &mut self,
+        tx: Transaction,
+        owner: ServiceId,
+        feerate_per_kw: Option<u32>,
+        height: Option<u32>,
+    ) {
+        let txid = tx.txid();
+        debug!("Rebroadcasting tx {} on behalf of {} until it gets mined", txid, owner);
+        let entry = self.txs.entry(txid).or_insert_with(|| PendingEntry {
+            tx,
+            owner: owner.clone(),
+            feerate_per_kw,
+            published: now(),
+            published_height: height,
+            rebroadcasts: 0,
+            evicted: false,
+        });
+        entry.owner = owner;
+        entry.feerate_per_kw = feerate_per_kw.or(entry.feerate_per_kw);
+        entry.evicted = false;
+        self.save();
+    }
+
+    /// Stops rebroadcasting the transaction
+    pub fn remove(&mut self, txid: Txid) {
+        if self.txs.remove(&txid).is_some() {
+            debug!("Stopping rebroadcasting tx {}", txid);
+            self.save();
+        }
+    }
+
+    /// Transactions being rebroadcast, in the order of their publication
+    pub fn pending(&self) -> Vec<PendingTx> {
+        let now = now();
+        let mut pending = self
+            .txs
+            .iter()
+            .map(|(txid, entry)| PendingTx {
+                txid: *txid,
+                owner: entry.owner.clone(),
+                age: now.saturating_sub(entry.published),
+                feerate_per_kw: entry.feerate_per_kw,
+                rebroadcasts: entry.rebroadcasts,
+                evicted: entry.evicted,
+            })
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|tx| std::cmp::Reverse(tx.age));
+        pending
+    }
+
+    /// Rebroadcasts all transactions which are not mined yet if there is a new block or the
+    /// blockchain backend has reconnected since the last poll. Returns notifications to the
+    /// owners of the transactions which were found missing from the mempool.
+    pub fn poll(
+        &mut self,
+        chain: &dyn ChainApi,
+        tip: u32,
+        new_block: bool,
+    ) -> Vec<(ServiceId, CtlMsg)> {
+        let reconnects = chain.status().reconnects;
+        let reconnected = reconnects > self.reconnects;
+        self.reconnects = reconnects;
+        if self.txs.is_empty() || !(new_block || reconnected) {
+            return vec![];
+        }
+
+        // Parents must be rebroadcast before their children (like CPFP transactions), which are
+        // always published later
+        let mut txids =
+            self.txs.iter().map(|(txid, entry)| (entry.published, *txid)).collect::<Vec<_>>();
+        txids.sort_unstable();
+
+        let mut notifications = vec![];
+        for (_, txid) in txids {
+            match chain.tx_status(txid, tip) {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    debug!("Rebroadcast tx {} is mined at height {}", txid, status.height);
+                    self.txs.remove(&txid);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Unable to get status of tx {} from blockchain backend: {}", txid, err);
+                    continue;
+                }
+            }
+
+            let entry = self.txs.get_mut(&txid).expect("txid is taken from the set");
+            let published_height = *entry.published_height.get_or_insert(tip);
+            if tip.saturating_sub(published_height) > MAX_PENDING_BLOCKS {
+                warn!(
+                    "Transaction {} is not mined within {} blocks; it is not rebroadcast anymore",
+                    txid, MAX_PENDING_BLOCKS
+                );
+                self.txs.remove(&txid);
+                continue;
+            }
+
+            let in_mempool = match chain.in_mempool(&entry.tx) {
+                Ok(in_mempool) => Some(in_mempool),
+                Err(err) => {
+                    debug!("Unable to check presence of tx {} in the mempool: {}", txid, err);
+                    None
+                }
+            };
+            match in_mempool {
+                Some(false) if !entry.evicted => {
+                    warn!("Transaction {} is neither mined nor present in the mempool", txid);
+                    entry.evicted = true;
+                    notifications.push((entry.owner.clone(), CtlMsg::TxEvicted(txid)));
+                }
+                Some(true) if entry.evicted => {
+                    info!("Transaction {} is back in the mempool", txid);
+                    entry.evicted = false;
+                }
+                _ => {}
+            }
+
+            match chain.broadcast(&entry.tx) {
+                Ok(()) => entry.rebroadcasts += 1,
+                // Backends may refuse transactions which are already in their mempool
+                Err(err) if in_mempool == Some(true) => {
+                    trace!("Transaction {} is not rebroadcast: {}", txid, err)
+                }
+                Err(err) => warn!("Unable to rebroadcast transaction {}: {}", txid, err),
+            }
+        }
+        self.save();
+        notifications
+    }
+}
+
+/// Current UNIX timestamp, in seconds
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
@@ -12,30 +12,227 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use std::{mem, thread};
 
-use bitcoin::Txid;
-use electrum_client::Client as ElectrumClient;
-use lnp::p2p::legacy::Messages as LnMsg;
-use microservices::esb;
+use bitcoin::{BlockHash, OutPoint, Transaction, TxOut, Txid};
+use internet2::{zmqsocket, ZmqType, ZMQ_CONTEXT};
+use lnp::p2p::legacy::{Messages as LnMsg, ShortChannelId};
+use microservices::esb::{self, Handler};
 
-use crate::bus::{BusMsg, CtlMsg, ServiceBus};
-use crate::rpc::ServiceId;
+use super::fees::FeeEstimator;
+use super::rebroadcast::Rebroadcaster;
+use super::tower::TowerClient;
+use crate::bus::{BusMsg, CtlMsg, ServiceBus, TxStatus};
+use crate::chain::{self, ChainApi, ChainError};
+use crate::opts::LNP_NODE_REBROADCAST_TXS;
+use crate::peerd::supervisor::read_node_key_file;
+use crate::rpc::{ChainStatus, ServiceId, SyncProgress, TxDepth};
+use crate::service::BridgeHandler;
 use crate::{Config, Endpoints, Error, Service};
 
-pub fn run(config: Config) -> Result<(), Error> {
-    let electrum =
-        ElectrumClient::new(&config.electrum_url).map_err(|_| Error::ElectrumConnectivity)?;
+/// Period between polling blockchain backend for the updates on the tracked transactions and
+/// blockchain height
+const POLL_PERIOD: Duration = Duration::from_secs(10);
 
-    let runtime = Runtime { electrum, track_list: empty!() };
+pub fn run(config: Config, key_file: &Path) -> Result<(), Error> {
+    let chain = chain::connect(&config)?;
+    let rebroadcaster = Rebroadcaster::load(config.data_dir.join(LNP_NODE_REBROADCAST_TXS))?;
 
-    Service::run(config, runtime, false)
+    debug!("Opening bridge between runtime and polling threads");
+    let tx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    let rx = ZMQ_CONTEXT.socket(zmq::PAIR)?;
+    tx.connect("inproc://watchd-timer")?;
+    rx.bind("inproc://watchd-timer")?;
+
+    debug!("Starting polling thread");
+    let timer = esb::Controller::with(
+        map! {
+            ServiceBus::Bridge => esb::BusConfig {
+                carrier: zmqsocket::Carrier::Socket(tx),
+                router: None,
+                queued: true,
+            }
+        },
+        BridgeHandler,
+        ZmqType::Rep,
+    )?;
+    thread::spawn(move || run_timer(timer));
+
+    let runtime = Runtime {
+        chain,
+        chain_error: None,
+        reported_sync: None,
+        fees: FeeEstimator::with(config.fees),
+        reported_feerate: None,
+        track_list: empty!(),
+        height_triggers: empty!(),
+        tip: None,
+        tip_hash: None,
+        funding_inputs: empty!(),
+        announced_funding: empty!(),
+        watched_outpoints: empty!(),
+        rebroadcaster,
+        tower: TowerClient::with(read_node_key_file(key_file), config.towers.clone()),
+    };
+
+    let mut service = Service::service(config, runtime)?;
+    service.add_loopback(rx)?;
+    service.run_loop()?;
+    unreachable!()
+}
+
+fn run_timer(mut timer: esb::Controller<ServiceBus, BusMsg, BridgeHandler>) {
+    loop {
+        thread::sleep(POLL_PERIOD);
+        let message = BusMsg::Ctl(CtlMsg::Timeout);
+        if let Err(err) = timer.send_to(ServiceBus::Bridge, ServiceId::Loopback, message) {
+            error!("Watchd polling thread is unable to reach the runtime: {}", err);
+        }
+    }
+}
+
+/// Transaction tracked on behalf of some service
+struct Tracking {
+    /// Depth up to which the service is notified about each new confirmation
+    depth: u32,
+
+    /// Mining status of the transaction reported to the service the last time, or `None` if
+    /// the transaction is not mined
+    status: Option<TxStatus>,
+}
+
+impl Tracking {
+    /// Updates mining status of the transaction, returning notifications for the service. Once
+    /// the transaction is reorged out of the blockchain or into another block, the service is
+    /// notified about its confirmations again until the requested depth is reached.
+    fn update(&mut self, txid: Txid, status: Option<TxStatus>) -> Vec<CtlMsg> {
+        let messages = match (self.status, status) {
+            (None, None) => vec![],
+            (Some(_), None) => {
+                warn!("Transaction {} is reorged out of the blockchain", txid);
+                vec![CtlMsg::TxReorged(txid)]
+            }
+            // Transaction is mined in another block since the last poll, meaning that the
+            // block it was reported in got disconnected
+            (Some(prev), Some(status))
+                if prev.height != status.height || prev.pos != status.pos =>
+            {
+                warn!(
+                    "Transaction {} is reorged from block {} into block {}",
+                    txid, prev.height, status.height
+                );
+                vec![CtlMsg::TxReorged(txid), CtlMsg::TxConfirmed(status)]
+            }
+            // The service is not notified if nothing has changed or the depth it has
+            // requested is already reached
+            (Some(prev), Some(status))
+                if prev.depth == status.depth || u32::from(prev.depth) >= self.depth =>
+            {
+                vec![]
+            }
+            (_, Some(status)) => vec![CtlMsg::TxConfirmed(status)],
+        };
+        self.status = status;
+        messages
+    }
+}
+
+/// Inputs of a funding transaction watched for double-spends until the transaction is mined
+struct FundingInputs {
+    /// Outpoints spent by the funding transaction
+    outpoints: Vec<OutPoint>,
+
+    /// Funding transaction spending the outpoints, once it is matched with one of the
+    /// transactions tracked on behalf of the channel daemons
+    funding_txid: Option<Txid>,
+}
+
+/// Outpoint watched on behalf of some service until it is spent by a mined transaction
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct WatchedOutpoint {
+    outpoint: OutPoint,
+
+    /// Service which requested the spending report
+    service: ServiceId,
+
+    /// Whether the service is notified about the unconfirmed spending transactions
+    mempool: bool,
+
+    /// Unconfirmed transaction spending the outpoint reported to the service the last time
+    unconfirmed_txid: Option<Txid>,
+}
+
+impl WatchedOutpoint {
+    /// Returns notification for the service about the transaction spending the outpoint, unless
+    /// the service has not requested it or is already notified about the transaction
+    fn spent_by(&mut self, tx: Transaction, mined: bool) -> Option<CtlMsg> {
+        let spending_txid = tx.txid();
+        if !mined && (!self.mempool || self.unconfirmed_txid == Some(spending_txid)) {
+            return None;
+        }
+        if !mined {
+            self.unconfirmed_txid = Some(spending_txid);
+        }
+        Some(CtlMsg::OutpointSpent { outpoint: self.outpoint, spending_txid, mined, tx })
+    }
+}
+
+/// Funding output of a channel announced in the gossip, watched until it is spent
+struct AnnouncedFunding {
+    outpoint: OutPoint,
+
+    txout: TxOut,
+
+    /// Service which requested the funding output
+    service: ServiceId,
 }
 
 pub struct Runtime {
-    electrum: ElectrumClient,
+    /// Blockchain backend selected by the node configuration
+    chain: Box<dyn ChainApi>,
+
+    /// Error of the last request to the blockchain backend, if it has failed
+    chain_error: Option<String>,
+
+    /// Progress of the blockchain backend synchronization last reported to lnpd
+    reported_sync: Option<SyncProgress>,
 
-    track_list: HashMap<Txid, (u32, ServiceId)>,
+    /// Fee estimator using the blockchain backend
+    fees: FeeEstimator,
+
+    /// Feerate estimation for the node confirmation target last reported to lnpd
+    reported_feerate: Option<u32>,
+
+    /// Transactions tracked on behalf of the services. A transaction may be tracked by several
+    /// services at once, like the funding transaction shared by a batch of channels.
+    track_list: HashMap<(Txid, ServiceId), Tracking>,
+
+    /// Services awaiting for the blockchain to reach some height
+    height_triggers: Vec<(u32, ServiceId)>,
+
+    /// Blockchain height received from the blockchain backend during the last poll
+    tip: Option<u32>,
+
+    /// Hash of the blockchain tip last announced with [`CtlMsg::NewBlock`]
+    tip_hash: Option<BlockHash>,
+
+    /// Inputs of the published funding transactions which are not mined yet
+    funding_inputs: Vec<FundingInputs>,
+
+    /// Funding outputs of the channels announced in the gossip, which are not spent yet
+    announced_funding: HashMap<ShortChannelId, AnnouncedFunding>,
+
+    /// Outpoints which spending has to be reported to the services
+    watched_outpoints: Vec<WatchedOutpoint>,
+
+    /// Published transactions which are rebroadcast until they get mined
+    rebroadcaster: Rebroadcaster,
+
+    /// Client uploading justice data for the revoked channel states to the watchtowers
+    tower: TowerClient,
 }
 
 impl esb::Handler<ServiceBus> for Runtime {
@@ -52,6 +249,7 @@ impl esb::Handler<ServiceBus> for Runtime {
         message: BusMsg,
     ) -> Result<(), Self::Error> {
         match (bus, message, source) {
+            (ServiceBus::Bridge, BusMsg::Ctl(CtlMsg::Timeout), _) => self.poll(endpoints),
             (ServiceBus::Msg, BusMsg::Ln(msg), source) => self.handle_p2p(endpoints, source, msg),
             (ServiceBus::Ctl, BusMsg::Ctl(msg), source) => self.handle_ctl(endpoints, source, msg),
             (bus, msg, _) => Err(Error::wrong_esb_msg(bus, &msg)),
@@ -88,21 +286,148 @@ impl Runtime {
 
     fn handle_ctl(
         &mut self,
-        _: &mut Endpoints,
+        endpoints: &mut Endpoints,
         source: ServiceId,
         message: CtlMsg,
     ) -> Result<(), Error> {
         match message {
             CtlMsg::Track { txid, depth } => {
-                debug!("Tracking status for tx {}", txid);
-                self.track_list.insert(txid, (depth, source));
+                debug!("Tracking status for tx {} on behalf of {}", txid, source);
+                self.track_list.insert((txid, source), Tracking { depth, status: None });
+            }
+
+            CtlMsg::TrackHeight(height) => {
+                debug!("Tracking blockchain height {} for {}", height, source);
+                self.height_triggers.push((height, source));
+            }
+
+            CtlMsg::TrackOutpoints(outpoints) => {
+                debug!("Watching {} funding transaction inputs for double-spends", outpoints.len());
+                self.funding_inputs.push(FundingInputs { outpoints, funding_txid: None });
+            }
+
+            CtlMsg::WatchSpending { outpoint, mempool } => {
+                let watched = self
+                    .watched_outpoints
+                    .iter_mut()
+                    .find(|watched| watched.outpoint == outpoint && watched.service == source);
+                match watched {
+                    Some(watched) => watched.mempool |= mempool,
+                    None => {
+                        debug!("Watching spending of {} for {}", outpoint, source);
+                        self.watched_outpoints.push(WatchedOutpoint {
+                            outpoint,
+                            service: source,
+                            mempool,
+                            unconfirmed_txid: None,
+                        });
+                    }
+                }
+            }
+
+            CtlMsg::GetFundingOutput(short_channel_id) => {
+                let txout = match self.chain.funding_output(short_channel_id) {
+                    Ok(Some((outpoint, txout))) => {
+                        self.watch_funding(short_channel_id, outpoint, &txout, source.clone());
+                        Some(txout)
+                    }
+                    Ok(None) => None,
+                    // Missing reply makes the requesting service retry the request later instead
+                    // of treating the channel as closed
+                    Err(err) => {
+                        warn!(
+                            "Unable to get funding of channel {} from blockchain backend: {}",
+                            short_channel_id, err
+                        );
+                        return Ok(());
+                    }
+                };
+                let message = CtlMsg::FundingOutput { short_channel_id, txout };
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
+            }
+
+            CtlMsg::RegisterWithTower { breach_txid, penalty_psbt, per_commitment_secret } => {
+                debug!("Signing justice transaction for revoked commitment {}", breach_txid);
+                self.tower.expect_signed(&penalty_psbt, breach_txid, source);
+                let message = CtlMsg::SignPenalty { psbt: penalty_psbt, per_commitment_secret };
+                endpoints.send_to(
+                    ServiceBus::Ctl,
+                    self.identity(),
+                    ServiceId::Signer,
+                    BusMsg::Ctl(message),
+                )?;
+            }
+
+            CtlMsg::Signed(psbt) => {
+                if !self.tower.upload_signed(psbt) {
+                    warn!("Signed transaction is not a justice transaction awaited by watchd");
+                }
+            }
+
+            CtlMsg::GetInfo => {
+                let message = CtlMsg::ChainInfo {
+                    backend: self.chain.name().to_owned(),
+                    height: self.tip,
+                    error: self.chain_error.clone(),
+                    sync: self.chain.status().sync,
+                };
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
+            }
+
+            CtlMsg::GetChainStatus => {
+                let backend = self.chain.status();
+                let status = ChainStatus {
+                    backend: self.chain.name().to_owned(),
+                    server: backend.server,
+                    height: self.tip,
+                    subscriptions: backend.subscriptions as u32,
+                    tracked_txs: self.tracked_txids().len() as u32,
+                    watched_outpoints: self.watched_outpoints.len() as u32,
+                    reconnects: backend.reconnects,
+                    error: self.chain_error.clone(),
+                };
+                let message = CtlMsg::ChainStatus(status);
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
+            }
+
+            CtlMsg::EstimateFee { confirmation_target } => {
+                let confirmation_target =
+                    confirmation_target.unwrap_or(self.fees.settings().target_blocks);
+                let feerate_per_kw = self.fees.feerate(&*self.chain, confirmation_target);
+                let message = CtlMsg::FeeEstimate { confirmation_target, feerate_per_kw };
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
+            }
+
+            CtlMsg::SetFees(settings) => {
+                info!("Fee estimation settings are set to {}", settings);
+                self.fees.set_settings(settings);
+                self.report_feerate(endpoints)?;
+            }
+
+            CtlMsg::GetTxDepth(txid) => {
+                let tx_depth = TxDepth { txid, depth: self.tx_depth(txid) };
+                let message = CtlMsg::TxDepth(tx_depth);
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
             }
 
             CtlMsg::Untrack(txid) => {
-                debug!("Stopping tracking tx {}", txid);
-                if self.track_list.remove(&txid).is_none() {
+                debug!("Stopping tracking tx {} on behalf of {}", txid, source);
+                if self.track_list.remove(&(txid, source)).is_none() {
                     warn!("Transaction {} was not tracked before", txid);
                 }
+                // Other services may still await the transaction confirmation
+                if !self.tracked_txids().contains(&txid) {
+                    self.rebroadcaster.remove(txid);
+                }
+            }
+
+            CtlMsg::Rebroadcast { tx, owner, feerate_per_kw } => {
+                self.rebroadcaster.add(tx, owner, feerate_per_kw, self.tip);
+            }
+
+            CtlMsg::GetPendingTxs => {
+                let message = CtlMsg::PendingTxs(self.rebroadcaster.pending());
+                endpoints.send_to(ServiceBus::Ctl, self.identity(), source, BusMsg::Ctl(message))?;
             }
 
             wrong_msg => {
@@ -113,4 +438,576 @@ impl Runtime {
 
         Ok(())
     }
+
+    /// Number of the transaction confirmations, if the transaction is mined. Transactions which
+    /// status can't be retrieved from the blockchain backend are reported as not mined.
+    fn tx_depth(&self, txid: Txid) -> Option<u32> {
+        let tip = match self.tip {
+            Some(tip) => tip,
+            None => match self.chain.height() {
+                Ok(height) => height,
+                Err(err) => {
+                    warn!("Unable to get blockchain height from blockchain backend: {}", err);
+                    return None;
+                }
+            },
+        };
+        match self.chain.tx_status(txid, tip) {
+            Ok(status) => status.map(|status| u32::from(status.depth)),
+            Err(err) => {
+                debug!("Unable to get status of tx {} from blockchain backend: {}", txid, err);
+                None
+            }
+        }
+    }
+
+    /// Checks mining status of all tracked transactions, notifying services about new
+    /// confirmations and transactions which were mined but got reorged out of the blockchain.
+    /// Unconfirmed transactions published by the node are rebroadcast on each new block.
+    fn poll(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let notifications = self.tower.notifications();
+        self.notify(endpoints, notifications)?;
+
+        let height = self.chain.height();
+        self.report_sync(endpoints)?;
+        self.report_feerate(endpoints)?;
+        let tip = match height {
+            Ok(height) => height,
+            Err(err) => {
+                warn!("Unable to get blockchain height from blockchain backend: {}", err);
+                self.chain_error = Some(err.to_string());
+                return Ok(());
+            }
+        };
+        self.tip = Some(tip);
+        self.chain_error = None;
+
+        let mut notifications = vec![];
+        // Transactions tracked by several services are queried once
+        let mut tx_statuses = HashMap::<Txid, Option<TxStatus>>::new();
+        for ((txid, service), tracking) in &mut self.track_list {
+            let status = match tx_statuses.get(txid) {
+                Some(status) => *status,
+                None => match self.chain.tx_status(*txid, tip) {
+                    Ok(status) => *tx_statuses.entry(*txid).or_insert(status),
+                    Err(err) => {
+                        warn!(
+                            "Unable to get status of tx {} from blockchain backend: {}",
+                            txid, err
+                        );
+                        self.chain_error = Some(err.to_string());
+                        continue;
+                    }
+                },
+            };
+            let messages = tracking.update(*txid, status);
+            notifications.extend(messages.into_iter().map(|message| (service.clone(), message)));
+        }
+
+        for mut inputs in mem::take(&mut self.funding_inputs) {
+            let funding_txid =
+                match inputs.funding_txid.or_else(|| self.find_spending_tx(&inputs.outpoints)) {
+                    Some(txid) => txid,
+                    None => {
+                        // The channel daemon has not requested the funding tracking yet
+                        self.funding_inputs.push(inputs);
+                        continue;
+                    }
+                };
+            inputs.funding_txid = Some(funding_txid);
+            // All channels funded by the transaction are notified
+            let services = self
+                .track_list
+                .iter()
+                .filter(|((txid, _), tracking)| *txid == funding_txid && tracking.status.is_none())
+                .map(|((_, service), _)| service.clone())
+                .collect::<Vec<_>>();
+            if services.is_empty() {
+                // Funding transaction is either mined or not tracked by the channels anymore
+                continue;
+            }
+            match self.find_conflict(&inputs.outpoints, funding_txid) {
+                Ok(None) => self.funding_inputs.push(inputs),
+                Ok(Some(conflict_txid)) => {
+                    warn!(
+                        "Inputs of funding transaction {} are double-spent by transaction {}",
+                        funding_txid, conflict_txid
+                    );
+                    notifications.extend(
+                        services
+                            .into_iter()
+                            .map(|service| (service, CtlMsg::FundingConflict(funding_txid))),
+                    );
+                }
+                Err(err) => {
+                    warn!(
+                        "Unable to check inputs of funding tx {} with blockchain backend: {}",
+                        funding_txid, err
+                    );
+                    self.funding_inputs.push(inputs);
+                }
+            }
+        }
+
+        notifications.extend(self.find_spent_funding());
+        notifications.extend(self.find_spent_outpoints());
+        let prev_hash = self.tip_hash;
+        notifications.extend(self.announce_block(tip));
+        let new_block = self.tip_hash != prev_hash;
+        notifications.extend(self.rebroadcaster.poll(&*self.chain, tip, new_block));
+
+        notifications.extend(take_reached_heights(&mut self.height_triggers, tip));
+
+        self.notify(endpoints, notifications)
+    }
+
+    /// Announces the blockchain tip to the services awaiting some blockchain height, if the tip
+    /// has changed since the last poll
+    fn announce_block(&mut self, tip: u32) -> Vec<(ServiceId, CtlMsg)> {
+        let block_hash = match self.chain.block_hash(tip) {
+            Ok(block_hash) => block_hash,
+            Err(err) => {
+                warn!("Unable to get hash of block {} from blockchain backend: {}", tip, err);
+                return vec![];
+            }
+        };
+        if self.tip_hash == Some(block_hash) {
+            return vec![];
+        }
+        self.tip_hash = Some(block_hash);
+        debug!("New blockchain tip {} at height {}", block_hash, tip);
+
+        block_subscribers(&self.height_triggers)
+            .into_iter()
+            .map(|service| (service, CtlMsg::NewBlock { height: tip, block_hash }))
+            .collect()
+    }
+
+    /// Notifies lnpd about the progress of the blockchain backend synchronization, if it has
+    /// changed since the last report
+    fn report_sync(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let sync = self.chain.status().sync;
+        if sync == self.reported_sync {
+            return Ok(());
+        }
+        self.reported_sync = sync.clone();
+        if let Some(sync) = sync {
+            let message = CtlMsg::ChainSync(sync);
+            endpoints.send_to(
+                ServiceBus::Ctl,
+                self.identity(),
+                ServiceId::LnpBroker,
+                BusMsg::Ctl(message),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Notifies lnpd about the feerate estimation for the node confirmation target, if it has
+    /// changed since the last report
+    fn report_feerate(&mut self, endpoints: &mut Endpoints) -> Result<(), Error> {
+        let confirmation_target = self.fees.settings().target_blocks;
+        let feerate_per_kw = self.fees.feerate(&*self.chain, confirmation_target);
+        if self.reported_feerate == Some(feerate_per_kw) {
+            return Ok(());
+        }
+        debug!("Fee estimation for {} blocks is {} sat/kw", confirmation_target, feerate_per_kw);
+        self.reported_feerate = Some(feerate_per_kw);
+        let message = CtlMsg::FeeEstimate { confirmation_target, feerate_per_kw };
+        endpoints.send_to(
+            ServiceBus::Ctl,
+            self.identity(),
+            ServiceId::LnpBroker,
+            BusMsg::Ctl(message),
+        )?;
+        Ok(())
+    }
+
+    fn notify(
+        &self,
+        endpoints: &mut Endpoints,
+        notifications: Vec<(ServiceId, CtlMsg)>,
+    ) -> Result<(), Error> {
+        for (service, message) in notifications {
+            endpoints.send_to(ServiceBus::Ctl, self.identity(), service, BusMsg::Ctl(message))?;
+        }
+        Ok(())
+    }
+
+    /// Starts watching the funding output, such that its spending is detected during the polling
+    fn watch_funding(
+        &mut self,
+        short_channel_id: ShortChannelId,
+        outpoint: OutPoint,
+        txout: &TxOut,
+        service: ServiceId,
+    ) {
+        if self.announced_funding.contains_key(&short_channel_id) {
+            return;
+        }
+        if let Err(err) = self.chain.watch_output(outpoint, txout) {
+            warn!("Unable to watch funding output of channel {}: {}", short_channel_id, err);
+            return;
+        }
+        let funding = AnnouncedFunding { outpoint, txout: txout.clone(), service };
+        self.announced_funding.insert(short_channel_id, funding);
+    }
+
+    /// Detects spending of the watched funding outputs of the announced channels
+    fn find_spent_funding(&mut self) -> Vec<(ServiceId, CtlMsg)> {
+        let mut spent = vec![];
+        for (short_channel_id, funding) in &self.announced_funding {
+            match self.chain.output_changed(funding.outpoint, &funding.txout) {
+                Ok(false) => continue,
+                Ok(true) => {}
+                Err(err) => {
+                    warn!("Unable to watch funding of channel {}: {}", short_channel_id, err);
+                    continue;
+                }
+            }
+            match self.chain.is_unspent(funding.outpoint, &funding.txout) {
+                Ok(true) => {}
+                Ok(false) => spent.push(*short_channel_id),
+                Err(err) => {
+                    warn!("Unable to check funding of channel {}: {}", short_channel_id, err)
+                }
+            }
+        }
+        spent
+            .into_iter()
+            .filter_map(|short_channel_id| {
+                let funding = self.announced_funding.remove(&short_channel_id)?;
+                debug!("Funding output of channel {} is spent", short_channel_id);
+                if let Err(err) = self.chain.unwatch_output(funding.outpoint, &funding.txout) {
+                    debug!("Unable to stop watching funding output: {}", err);
+                }
+                Some((funding.service, CtlMsg::FundingSpent(short_channel_id)))
+            })
+            .collect()
+    }
+
+    /// Finds transactions spending the watched outpoints. Outpoints spent by the mined
+    /// transactions are not watched anymore.
+    fn find_spent_outpoints(&mut self) -> Vec<(ServiceId, CtlMsg)> {
+        let mut spent = vec![];
+        for mut watched in mem::take(&mut self.watched_outpoints) {
+            let outpoint = watched.outpoint;
+            let spending = match self.chain.find_spending(outpoint) {
+                Ok(Some(tx)) => Ok(Some((tx, true))),
+                Ok(None) if watched.mempool => self
+                    .chain
+                    .find_unconfirmed_spending(outpoint)
+                    .map(|tx| tx.map(|tx| (tx, false))),
+                res => res.map(|_| None),
+            };
+            let mined = match spending {
+                Ok(Some((tx, mined))) => {
+                    let txid = tx.txid();
+                    if let Some(message) = watched.spent_by(tx, mined) {
+                        let status = if mined { "mined" } else { "unconfirmed" };
+                        debug!("Outpoint {} is spent by {} transaction {}", outpoint, status, txid);
+                        spent.push((watched.service.clone(), message));
+                    }
+                    mined
+                }
+                Ok(None) => false,
+                Err(err) => {
+                    warn!(
+                        "Unable to check spending of {} with blockchain backend: {}",
+                        outpoint, err
+                    );
+                    false
+                }
+            };
+            if !mined {
+                self.watched_outpoints.push(watched);
+            }
+        }
+        spent
+    }
+
+    /// Ids of the transactions tracked on behalf of any service
+    fn tracked_txids(&self) -> HashSet<Txid> {
+        self.track_list.keys().map(|(txid, _)| *txid).collect()
+    }
+
+    /// Finds not yet mined tracked transaction spending some of the given outpoints
+    fn find_spending_tx(&self, outpoints: &[OutPoint]) -> Option<Txid> {
+        self.track_list
+            .iter()
+            .filter(|(_, tracking)| tracking.status.is_none())
+            .map(|((txid, _), _)| *txid)
+            .find(|txid| match self.chain.transaction(*txid) {
+                Ok(tx) => tx.input.iter().any(|txin| outpoints.contains(&txin.previous_output)),
+                Err(_) => false,
+            })
+    }
+
+    /// Finds mined transaction other than the funding one spending some of the funding
+    /// transaction inputs
+    fn find_conflict(
+        &self,
+        outpoints: &[OutPoint],
+        funding_txid: Txid,
+    ) -> Result<Option<Txid>, ChainError> {
+        for outpoint in outpoints {
+            match self.chain.find_spending(*outpoint)? {
+                Some(tx) if tx.txid() != funding_txid => return Ok(Some(tx.txid())),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Services awaiting some blockchain height, which are notified about each new blockchain tip
+fn block_subscribers(height_triggers: &[(u32, ServiceId)]) -> Vec<ServiceId> {
+    let mut services = Vec::<ServiceId>::new();
+    for (_, service) in height_triggers {
+        if !services.contains(service) {
+            services.push(service.clone());
+        }
+    }
+    services
+}
+
+/// Removes the triggers for the heights reached by the blockchain tip, returning notifications
+/// for their services
+fn take_reached_heights(
+    height_triggers: &mut Vec<(u32, ServiceId)>,
+    tip: u32,
+) -> Vec<(ServiceId, CtlMsg)> {
+    let (reached, pending) = height_triggers.drain(..).partition(|(height, _)| *height <= tip);
+    *height_triggers = pending;
+    reached.into_iter().map(|(_, service)| (service, CtlMsg::HeightReached(tip))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use amplify::num::u24;
+    use amplify::{Slice32, Wrapper};
+    use bitcoin::hashes::Hash;
+    use lnp::p2p::legacy::ChannelId;
+
+    use super::*;
+
+    fn channeld(no: u8) -> ServiceId {
+        ServiceId::Channel(ChannelId::from_inner(Slice32::from_inner([no; 32])))
+    }
+
+    #[test]
+    fn height_triggers() {
+        let mut triggers = vec![(488, channeld(1)), (500, channeld(2)), (488, channeld(3))];
+        assert_eq!(block_subscribers(&triggers), vec![channeld(1), channeld(2), channeld(3)]);
+
+        // Blocks are mined one by one until all the deadlines are reached
+        let mut reached = vec![];
+        for tip in 480..=500 {
+            for (service, message) in take_reached_heights(&mut triggers, tip) {
+                assert!(matches!(message, CtlMsg::HeightReached(height) if height == tip));
+                reached.push((tip, service));
+            }
+        }
+        assert_eq!(reached, vec![(488, channeld(1)), (488, channeld(3)), (500, channeld(2))]);
+        assert!(triggers.is_empty());
+        assert!(block_subscribers(&triggers).is_empty());
+    }
+
+    #[test]
+    fn height_triggers_past_tip() {
+        // Deadline registered after the blockchain has passed it fires on the next poll
+        let mut triggers = vec![(100, channeld(1)), (200, channeld(1))];
+        assert_eq!(block_subscribers(&triggers), vec![channeld(1)]);
+        let reached = take_reached_heights(&mut triggers, 150);
+        assert_eq!(reached.len(), 1);
+        let (service, message) = &reached[0];
+        assert_eq!(*service, channeld(1));
+        assert!(matches!(message, CtlMsg::HeightReached(150)));
+        assert_eq!(triggers, vec![(200, channeld(1))]);
+    }
+
+    /// Notification about the tracked transaction, as seen by the service
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Event {
+        Confirmed { depth: u32, height: u32 },
+        Reorged,
+    }
+    use Event::*;
+
+    fn status(depth: u32, height: u32) -> Option<TxStatus> {
+        Some(TxStatus {
+            txid: Txid::from_inner([1; 32]),
+            depth: u24::try_from(depth).unwrap(),
+            height: u24::try_from(height).unwrap(),
+            pos: u24::try_from(1).unwrap(),
+        })
+    }
+
+    /// Feeds the tracker with the statuses reported by the blockchain backend on each poll,
+    /// returning the notifications sent to the service after each of the polls
+    fn poll(tracking: &mut Tracking, statuses: &[Option<TxStatus>]) -> Vec<Vec<Event>> {
+        let txid = Txid::from_inner([1; 32]);
+        statuses
+            .iter()
+            .map(|status| {
+                tracking
+                    .update(txid, *status)
+                    .into_iter()
+                    .map(|message| match message {
+                        CtlMsg::TxConfirmed(status) => {
+                            Confirmed { depth: status.depth.into(), height: status.height.into() }
+                        }
+                        CtlMsg::TxReorged(reorged_txid) if reorged_txid == txid => Reorged,
+                        message => panic!("unexpected notification {}", message),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tracking_depth() {
+        let mut tracking = Tracking { depth: 3, status: None };
+        let statuses = [
+            None,
+            status(1, 100),
+            status(1, 100),
+            status(2, 100),
+            status(3, 100),
+            status(4, 100),
+            status(5, 100),
+        ];
+        assert_eq!(poll(&mut tracking, &statuses), vec![
+            vec![],
+            vec![Confirmed { depth: 1, height: 100 }],
+            vec![],
+            vec![Confirmed { depth: 2, height: 100 }],
+            vec![Confirmed { depth: 3, height: 100 }],
+            vec![],
+            vec![],
+        ]);
+        assert_eq!(tracking.status, status(5, 100));
+    }
+
+    #[test]
+    fn tracking_reorg_rearm() {
+        let mut tracking = Tracking { depth: 3, status: None };
+        let statuses = [
+            status(1, 100),
+            status(2, 100),
+            // Block 100 is disconnected and the transaction returns to mempool
+            None,
+            None,
+            // Requirement is re-armed once the transaction is mined again
+            status(1, 102),
+            status(2, 102),
+            status(3, 102),
+            status(4, 102),
+        ];
+        assert_eq!(poll(&mut tracking, &statuses), vec![
+            vec![Confirmed { depth: 1, height: 100 }],
+            vec![Confirmed { depth: 2, height: 100 }],
+            vec![Reorged],
+            vec![],
+            vec![Confirmed { depth: 1, height: 102 }],
+            vec![Confirmed { depth: 2, height: 102 }],
+            vec![Confirmed { depth: 3, height: 102 }],
+            vec![],
+        ]);
+    }
+
+    #[test]
+    fn tracking_reorg_into_other_block() {
+        let mut tracking = Tracking { depth: 3, status: None };
+        let statuses = [
+            status(1, 100),
+            status(2, 100),
+            // Reorg between the polls moves the transaction into a block at another height
+            status(1, 101),
+            status(2, 101),
+            status(3, 101),
+        ];
+        assert_eq!(poll(&mut tracking, &statuses), vec![
+            vec![Confirmed { depth: 1, height: 100 }],
+            vec![Confirmed { depth: 2, height: 100 }],
+            vec![Reorged, Confirmed { depth: 1, height: 101 }],
+            vec![Confirmed { depth: 2, height: 101 }],
+            vec![Confirmed { depth: 3, height: 101 }],
+        ]);
+    }
+
+    #[test]
+    fn tracking_reorg_after_depth() {
+        // Reorg is reported even after the requested depth was reached
+        let mut tracking = Tracking { depth: 1, status: None };
+        let statuses = [status(1, 100), status(2, 100), None, status(1, 101)];
+        assert_eq!(poll(&mut tracking, &statuses), vec![
+            vec![Confirmed { depth: 1, height: 100 }],
+            vec![],
+            vec![Reorged],
+            vec![Confirmed { depth: 1, height: 101 }],
+        ]);
+    }
+
+    fn spending_tx(lock_time: u32) -> Transaction {
+        Transaction { version: 2, lock_time, input: vec![], output: vec![] }
+    }
+
+    /// Reports about the outpoint spending as pairs of the spending transaction id and its
+    /// mining status
+    fn spent_by(
+        watched: &mut WatchedOutpoint,
+        tx: Transaction,
+        mined: bool,
+    ) -> Option<(Txid, bool)> {
+        let outpoint = watched.outpoint;
+        match watched.spent_by(tx, mined)? {
+            CtlMsg::OutpointSpent { outpoint: spent, spending_txid, mined, tx } => {
+                assert_eq!(spent, outpoint);
+                assert_eq!(spending_txid, tx.txid());
+                Some((spending_txid, mined))
+            }
+            message => panic!("unexpected notification {}", message),
+        }
+    }
+
+    #[test]
+    fn outpoint_spent_mined() {
+        let mut watched = WatchedOutpoint {
+            outpoint: OutPoint::new(Txid::from_inner([1; 32]), 0),
+            service: channeld(1),
+            mempool: false,
+            unconfirmed_txid: None,
+        };
+        let tx = spending_tx(0);
+        assert_eq!(spent_by(&mut watched, tx.clone(), false), None);
+        assert_eq!(spent_by(&mut watched, tx.clone(), true), Some((tx.txid(), true)));
+    }
+
+    #[test]
+    fn outpoint_spent_mempool() {
+        let mut watched = WatchedOutpoint {
+            outpoint: OutPoint::new(Txid::from_inner([1; 32]), 0),
+            service: channeld(1),
+            mempool: true,
+            unconfirmed_txid: None,
+        };
+        let tx = spending_tx(0);
+        let replacement = spending_tx(1);
+        assert_eq!(spent_by(&mut watched, tx.clone(), false), Some((tx.txid(), false)));
+        // Each unconfirmed transaction is reported once, while its replacement is reported again
+        assert_eq!(spent_by(&mut watched, tx.clone(), false), None);
+        assert_eq!(
+            spent_by(&mut watched, replacement.clone(), false),
+            Some((replacement.txid(), false))
+        );
+        assert_eq!(spent_by(&mut watched, replacement.clone(), false), None);
+        // Mined transaction is reported even if it was reported as unconfirmed before
+        assert_eq!(
+            spent_by(&mut watched, replacement.clone(), true),
+            Some((replacement.txid(), true))
+        );
+    }
 }
@@ -0,0 +1,299 @@
+// LNP Node: node running lightning network protocol and generalized lightning
+// channels.
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Watchtower client uploading justice data for the revoked remote commitment transactions to
+//! the external watchtowers.
+//!
+//! Justice data for a revoked commitment transaction is the penalty transaction spending it,
+//! signed in advance. The data are encrypted with ChaCha20-Poly1305 under a key derived from the
+//! id of the revoked commitment transaction and uploaded together with a hint made of the first
+//! [`HINT_LEN`] bytes of the same id. Thus, the tower can decrypt the data only once the revoked
+//! commitment transaction gets published, and learns nothing about the channel before that.
+//!
+//! Uploads are made over BOLT-8 connection, which authenticates both the node and the tower.
+//! The node sends a single message containing the hint and the encrypted blob; the tower replies
+//! with a zero byte if the data are accepted, or with a non-zero byte followed by UTF-8 error
+//! description otherwise. Uploads run in a separate thread, such that unreachable towers do not
+//! block watchd, and failed uploads are retried with exponential backoff.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::consensus;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::rand::{self, RngCore};
+use bitcoin::Txid;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use internet2::{LocalNode, RemoteNodeAddr, RemoteSocketAddr};
+use microservices::peer::{PeerConnection, RecvMessage, SendMessage};
+use psbt::Psbt;
+
+use crate::bus::CtlMsg;
+use crate::peerd::supervisor::handshake;
+use crate::rpc::ServiceId;
+
+/// Number of the revoked commitment transaction id bytes used as a hint for the tower
+pub const HINT_LEN: usize = 16;
+
+/// Length of the nonce preceding the encrypted justice data
+const NONCE_LEN: usize = 12;
+
+/// Timeout for connecting a tower, completing the handshake with it and awaiting for its reply
+const TOWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before the first retry of a failed upload
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Maximal delay between the upload retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// Errors uploading justice data to a watchtower
+#[derive(Clone, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TowerError {
+    /// unable to reach watchtower {0}. Details: {1}
+    Connection(RemoteNodeAddr, String),
+
+    /// watchtower {0} has rejected justice data: {1}
+    Rejected(RemoteNodeAddr, String),
+}
+
+/// Justice data upload to a single watchtower
+struct Upload {
+    /// Tower receiving the data
+    tower: RemoteNodeAddr,
+
+    /// Revoked commitment transaction which the data are for
+    breach_txid: Txid,
+
+    /// Channel daemon which has requested the upload
+    service: ServiceId,
+
+    /// Message sent to the tower: hint followed by the encrypted justice data
+    message: Vec<u8>,
+
+    /// Number of failed upload attempts
+    failures: u32,
+
+    /// Time of the next upload attempt
+    retry_at: Instant,
+}
+
+/// Client uploading justice data to the watchtowers configured for the node
+pub struct TowerClient {
+    towers: Vec<RemoteNodeAddr>,
+
+    /// Penalty transactions being signed by signd, with the ids of the revoked commitment
+    /// transactions they spend and the channel daemons which have requested the upload
+    signing: HashMap<Txid, (Txid, ServiceId)>,
+
+    /// Uploads passed to the uploading thread
+    uploads: Sender<Vec<Upload>>,
+
+    /// Notifications on the upload results for the channel daemons produced by the uploading
+    /// thread
+    notifications: Receiver<(ServiceId, CtlMsg)>,
+}
+
+impl TowerClient {
+    /// Constructs the client and starts its uploading thread
+    pub fn with(local_node: LocalNode, towers: Vec<RemoteNodeAddr>) -> TowerClient {
+        let (uploads, queue) = mpsc::channel();
+        let (reporter, notifications) = mpsc::channel();
+        let uploader = Uploader { local_node, queue, reporter, pending: empty!() };
+        thread::Builder::new()
+            .name(s!("watchd-towers"))
+            .spawn(move || uploader.run())
+            .expect("unable to start watchtower uploading thread");
+        TowerClient { towers, signing: empty!(), uploads, notifications }
+    }
+
+    /// Remembers penalty transaction sent for signing, such that it gets uploaded to the towers
+    /// once signed
+    pub fn expect_signed(&mut self, penalty_psbt: &Psbt, breach_txid: Txid, service: ServiceId) {
+        let penalty_txid = penalty_psbt.global.unsigned_tx.txid();
+        self.signing.insert(penalty_txid, (breach_txid, service));
+    }
+
+    /// Schedules upload of the signed penalty transaction to all towers. Returns `false` if the
+    /// transaction is not a penalty transaction awaited by the client.
+    pub fn upload_signed(&mut self, psbt: Psbt) -> bool {
+        let (breach_txid, service) = match self.signing.remove(&psbt.global.unsigned_tx.txid()) {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let justice_tx = psbt.extract_tx();
+        let mut message = breach_txid[..HINT_LEN].to_vec();
+        message.extend(encrypt_blob(breach_txid, &consensus::serialize(&justice_tx)));
+
+        let now = Instant::now();
+        let uploads = self
+            .towers
+            .iter()
+            .map(|tower| Upload {
+                tower: tower.clone(),
+                breach_txid,
+                service: service.clone(),
+                message: message.clone(),
+                failures: 0,
+                retry_at: now,
+            })
+            .collect();
+        if self.uploads.send(uploads).is_err() {
+            error!("Watchtower uploading thread has terminated; justice data are not uploaded");
+        }
+        true
+    }
+
+    /// Collects notifications on the upload results for the channel daemons produced by the
+    /// uploading thread since the last call. Never blocks.
+    pub fn notifications(&mut self) -> Vec<(ServiceId, CtlMsg)> {
+        self.notifications.try_iter().collect()
+    }
+}
+
+/// Uploading thread, which performs the uploads and retries the failed ones
+struct Uploader {
+    /// Node key authenticating the node to the towers
+    local_node: LocalNode,
+
+    /// New uploads received from the client
+    queue: Receiver<Vec<Upload>>,
+
+    /// Notifications on the upload results sent to the client
+    reporter: Sender<(ServiceId, CtlMsg)>,
+
+    /// Failed uploads awaiting for a retry
+    pending: Vec<Upload>,
+}
+
+impl Uploader {
+    /// Runs the uploading thread until the client is dropped
+    fn run(mut self) {
+        loop {
+            let now = Instant::now();
+            let timeout = self
+                .pending
+                .iter()
+                .map(|upload| upload.retry_at.saturating_duration_since(now))
+                .min()
+                .unwrap_or(MAX_RETRY_DELAY);
+            match self.queue.recv_timeout(timeout) {
+                Ok(uploads) => self.pending.extend(uploads),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            let (due, pending) = self.pending.drain(..).partition(|upload| upload.retry_at <= now);
+            self.pending = pending;
+            for notification in self.upload(due) {
+                if self.reporter.send(notification).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn upload(&mut self, uploads: Vec<Upload>) -> Vec<(ServiceId, CtlMsg)> {
+        let mut notifications = vec![];
+        let mut completed = vec![];
+        for mut upload in uploads {
+            match self.send(&upload) {
+                Ok(()) => {
+                    debug!(
+                        "Justice data for revoked commitment {} are uploaded to tower {}",
+                        upload.breach_txid, upload.tower
+                    );
+                    if !completed.contains(&(upload.breach_txid, upload.service.clone())) {
+                        completed.push((upload.breach_txid, upload.service));
+                    }
+                }
+                Err(err) => {
+                    let delay = INITIAL_RETRY_DELAY
+                        .checked_mul(1 << upload.failures.min(16))
+                        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY));
+                    warn!("{}; retrying in {} seconds", err, delay.as_secs());
+                    upload.failures += 1;
+                    upload.retry_at = Instant::now() + delay;
+                    let breach_txid = upload.breach_txid;
+                    let message = CtlMsg::TowerFailed { breach_txid, error: err.to_string() };
+                    notifications.push((upload.service.clone(), message));
+                    self.pending.push(upload);
+                }
+            }
+        }
+
+        // Channel is notified only once all towers have accepted the data
+        for (breach_txid, service) in completed {
+            if !self.pending.iter().any(|upload| upload.breach_txid == breach_txid) {
+                notifications.push((service, CtlMsg::TowerRegistered(breach_txid)));
+            }
+        }
+        notifications
+    }
+
+    fn send(&self, upload: &Upload) -> Result<(), TowerError> {
+        let tower = &upload.tower;
+        let connection_err = |err: String| TowerError::Connection(tower.clone(), err);
+        let mut connection = self.connect(tower).map_err(|err| connection_err(err.to_string()))?;
+        connection
+            .send_raw_message(&upload.message)
+            .map_err(|err| connection_err(err.to_string()))?;
+        let reply = connection.recv_raw_message().map_err(|err| connection_err(err.to_string()))?;
+        match reply.split_first() {
+            Some((0, _)) => Ok(()),
+            Some((_, reason)) => {
+                let reason = String::from_utf8_lossy(reason).to_string();
+                Err(TowerError::Rejected(tower.clone(), reason))
+            }
+            None => Err(TowerError::Rejected(tower.clone(), s!("empty reply"))),
+        }
+    }
+
+    /// Connects the tower, bounding each stage of the connection with [`TOWER_TIMEOUT`]
+    fn connect(&self, tower: &RemoteNodeAddr) -> Result<PeerConnection, crate::Error> {
+        let inet_addr = match tower.remote_addr {
+            RemoteSocketAddr::Ftcp(inet_addr) => inet_addr,
+            _ => return Err(crate::Error::Other(s!("watchtower must use TCP address"))),
+        };
+        let socket_addr = SocketAddr::try_from(inet_addr)
+            .map_err(|_| crate::Error::Other(s!("watchtower must use IP address")))?;
+        let stream = TcpStream::connect_timeout(&socket_addr, TOWER_TIMEOUT)?;
+        let (connection, stream) =
+            handshake(stream, inet_addr, tower.node_id, &self.local_node, TOWER_TIMEOUT)?;
+        stream.set_read_timeout(Some(TOWER_TIMEOUT))?;
+        Ok(connection)
+    }
+}
+
+/// Encrypts justice data with ChaCha20-Poly1305 under the key derived from the revoked commitment
+/// transaction id. The ciphertext is preceded by a random nonce.
+fn encrypt_blob(breach_txid: Txid, data: &[u8]) -> Vec<u8> {
+    let key = sha256::Hash::hash(&breach_txid[..]);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key[..]));
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .expect("justice transaction is much shorter than the cipher limit");
+    let mut blob = nonce.to_vec();
+    blob.extend(ciphertext);
+    blob
+}